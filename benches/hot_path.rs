@@ -0,0 +1,213 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{Address, TransactionRequest, H256, U256},
+};
+use serde_json::value::RawValue;
+
+use tsuki::{
+    constants::{
+        protocol::UniswapV2::{APESWAP, QUICKSWAP, SUSHISWAP},
+        token::ERC20Token::{self, *},
+    },
+    uniswapV2::{amounts_in, amounts_out, UniswapV2Pair},
+    utils::{
+        batch::common::{BatchRequest, Response},
+        quote_cache::QuoteCache,
+        transaction::{decode_raw_transaction, sign_typed_request, EthTransactionRequest},
+    },
+    world::split_amount,
+};
+
+/// requests per batch, about what `get_reserves_many` sends per chunk
+const BATCH_SIZE: usize = 500;
+
+fn pair(
+    protocol: tsuki::constants::protocol::UniswapV2,
+    token0: ERC20Token,
+    token1: ERC20Token,
+    reserve0: u128,
+    reserve1: u128,
+) -> UniswapV2Pair {
+    let mut pair = UniswapV2Pair::default();
+    pair.update_metadata(protocol, token0, token1, U256::zero());
+    pair.update_reserves(reserve0.into(), reserve1.into());
+    pair
+}
+
+/// USDC -> WETH -> USDT -> USDC at mainnet-ish depths
+fn route() -> (Vec<UniswapV2Pair>, Vec<ERC20Token>) {
+    (
+        vec![
+            pair(
+                QUICKSWAP,
+                USDC,
+                WETH,
+                5_000_000_000_000,
+                3_000_000_000_000_000_000_000,
+            ),
+            pair(
+                SUSHISWAP,
+                USDT,
+                WETH,
+                4_000_000_000_000,
+                2_400_000_000_000_000_000_000,
+            ),
+            pair(APESWAP, USDC, USDT, 1_000_000_000_000, 1_001_000_000_000),
+        ],
+        vec![USDC, WETH, USDT, USDC],
+    )
+}
+
+fn bench_batch(c: &mut Criterion) {
+    let pair = Address::random();
+    c.bench_function("batch_serialize", |b| {
+        b.iter(|| {
+            let mut batch = BatchRequest::with_capacity(BATCH_SIZE);
+            for _ in 0..BATCH_SIZE {
+                batch
+                    .add_request(
+                        "eth_call",
+                        (
+                            serde_json::json!({ "to": pair, "data": "0x0902f1ac" }),
+                            "latest",
+                        ),
+                    )
+                    .unwrap();
+            }
+            batch
+        })
+    });
+
+    let reserves = format!("0x{}", "00".repeat(95) + "01");
+    let body = format!(
+        "[{}]",
+        (0..BATCH_SIZE)
+            .map(|id| format!(r#"{{"jsonrpc":"2.0","id":{},"result":"{}"}}"#, id, reserves))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    c.bench_function("batch_deserialize", |b| {
+        b.iter(|| {
+            let responses: Vec<Response> = serde_json::from_str(black_box(&body)).unwrap();
+            responses
+                .into_iter()
+                .map(|response| match response {
+                    Response::Success { result, .. } => {
+                        serde_json::from_str::<&RawValue>(result.get()).is_ok()
+                    }
+                    _ => false,
+                })
+                .count()
+        })
+    });
+}
+
+fn bench_route_math(c: &mut Criterion) {
+    let (pairs, path) = route();
+    let amount = U256::from(10_000_000_000u64);
+    c.bench_function("amounts_out_3_hops", |b| {
+        b.iter(|| amounts_out(black_box(&pairs), black_box(amount), &path))
+    });
+    c.bench_function("amounts_in_3_hops", |b| {
+        b.iter(|| amounts_in(black_box(&pairs), black_box(amount), &path))
+    });
+
+    let venues = vec![
+        pair(
+            QUICKSWAP,
+            USDC,
+            WETH,
+            5_000_000_000_000,
+            3_000_000_000_000_000_000_000,
+        ),
+        pair(
+            SUSHISWAP,
+            USDC,
+            WETH,
+            2_000_000_000_000,
+            1_200_000_000_000_000_000_000,
+        ),
+        pair(
+            APESWAP,
+            USDC,
+            WETH,
+            500_000_000_000,
+            300_000_000_000_000_000_000,
+        ),
+    ];
+    c.bench_function("split_amount_10_parts", |b| {
+        b.iter(|| split_amount(black_box(&venues), USDC, black_box(amount), 10))
+    });
+}
+
+fn bench_mempool_decoding(c: &mut Criterion) {
+    let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse::<LocalWallet>()
+        .unwrap()
+        .with_chain_id(137u64);
+    let request = EthTransactionRequest {
+        to: Some(Address::random()),
+        gas: Some(200_000.into()),
+        max_fee_per_gas: Some(100_000_000_000u64.into()),
+        max_priority_fee_per_gas: Some(30_000_000_000u64.into()),
+        nonce: Some(7.into()),
+        data: Some([0x38, 0xed, 0x17, 0x39].repeat(64).into()),
+        ..Default::default()
+    }
+    .into_typed_request()
+    .unwrap();
+    let raw = sign_typed_request(request, &wallet).encode_raw();
+    c.bench_function("decode_raw_eip1559", |b| {
+        b.iter(|| decode_raw_transaction(black_box(&raw)).unwrap())
+    });
+
+    // what the mempool stream deserializes per pending txn
+    let tx = TransactionRequest::new()
+        .from(wallet.address())
+        .to(Address::random())
+        .data(vec![0u8; 256]);
+    let json = serde_json::to_string(&tx).unwrap();
+    c.bench_function("deserialize_pending_txn", |b| {
+        b.iter(|| serde_json::from_str::<TransactionRequest>(black_box(&json)).unwrap())
+    });
+}
+
+fn bench_quotes(c: &mut Criterion) {
+    let (pairs, _) = route();
+    let amount = U256::from(10_000_000_000u64);
+    c.bench_function("v2_get_amounts_out", |b| {
+        b.iter(|| pairs[0].get_amounts_out(black_box(amount), USDC))
+    });
+
+    let cache: QuoteCache<(Address, Address), (u32, U256)> = QuoteCache::new(32);
+    cache.advance(1);
+    let key = (USDC.get_address(), WETH.get_address());
+    cache.insert(key, amount, 1, (500, amount));
+    c.bench_function("quote_cache_hit", |b| {
+        b.iter(|| cache.get(black_box(key), black_box(amount)))
+    });
+    c.bench_function("quote_cache_miss_insert", |b| {
+        b.iter_batched(
+            || (H256::random(), U256::from(rand_amount())),
+            |(hash, amount)| {
+                let key = (Address::from(hash), Address::zero());
+                cache.get_or_insert_with(key, amount, |bucket| (500, bucket))
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn rand_amount() -> u64 {
+    H256::random().to_low_u64_be() >> 1
+}
+
+criterion_group!(
+    benches,
+    bench_batch,
+    bench_route_math,
+    bench_mempool_decoding,
+    bench_quotes
+);
+criterion_main!(benches);
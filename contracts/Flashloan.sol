@@ -19,18 +19,80 @@ struct ArbParams {
     uint24[] fees;
 }
 
+// what the flashloan hands to receiveFlashLoan
+struct FlashParams {
+    ArbParams params;
+    uint256[] minAmountsOut; // per hop, empty to not check them
+    uint256 minProfit;
+    uint256 maxLastAmountIn; // 0 sells everything on the last hop
+}
+
 contract Flashloan is Ownable, IFlashLoanRecipientBalancer {
     using SafeERC20 for IERC20;
 
+    // read by the bots through executorVersion() to pick the calldata
+    uint256 public constant EXECUTOR_VERSION = 1;
+    uint256 public constant FEATURE_MIN_PROFIT = 1;
+    uint256 public constant FEATURE_HOP_MIN_OUT = 1 << 1;
+    uint256 public constant FEATURE_EXACT_OUTPUT = 1 << 2;
+
     IBalancerVault private immutable vault;
 
+    // accounts besides the owner allowed to execute, e.g. its smart account
+    mapping(address => bool) public executors;
+
+    modifier onlyExecutor() {
+        require(msg.sender == owner() || executors[msg.sender], "o");
+        _;
+    }
+
     constructor(address _vault) {
         vault = IBalancerVault(_vault);
 	}
 
-    function executeArbitrage(ArbParams memory params, uint blockNumber) external onlyOwner{
+    function executorVersion() external pure returns (uint256 version, uint256 features) {
+        return (EXECUTOR_VERSION, FEATURE_MIN_PROFIT | FEATURE_HOP_MIN_OUT | FEATURE_EXACT_OUTPUT);
+    }
+
+    function setExecutor(address account, bool allowed) external onlyOwner {
+        executors[account] = allowed;
+    }
+
+    function executeArbitrage(ArbParams memory params, uint blockNumber) external onlyExecutor {
+        flashLoan(FlashParams(params, new uint256[](0), 0, 0), blockNumber);
+    }
+
+    // reverts unless every hop returns at least its min amount and the loan
+    // is repaid with at least minProfit left
+    function executeArbitrageChecked(
+        ArbParams memory params,
+        uint256[] memory minAmountsOut,
+        uint256 minProfit,
+        uint blockNumber
+    ) external onlyExecutor {
+        require(minAmountsOut.length == params.protocolPath.length, "l");
+        flashLoan(FlashParams(params, minAmountsOut, minProfit, 0), blockNumber);
+    }
+
+    // the last hop buys back exactly the loan for at most maxLastAmountIn,
+    // what's left of the token it sells goes to the owner and has to be at
+    // least minProfit
+    function executeArbitrageExactOutput(
+        ArbParams memory params,
+        uint256[] memory minAmountsOut,
+        uint256 maxLastAmountIn,
+        uint256 minProfit,
+        uint blockNumber
+    ) external onlyExecutor {
+        require(minAmountsOut.length == params.protocolPath.length, "l");
+        require(maxLastAmountIn > 0, "m");
+        flashLoan(FlashParams(params, minAmountsOut, minProfit, maxLastAmountIn), blockNumber);
+    }
+
+    function flashLoan(FlashParams memory flash, uint blockNumber) internal {
         require(block.number <= blockNumber, "b");
-        bytes memory data = abi.encode(params);
+        ArbParams memory params = flash.params;
+        bytes memory data = abi.encode(flash);
 
         // create params to pass into vault flashloan call
         IERC20[] memory tokens = new IERC20[](1);
@@ -52,31 +114,55 @@ contract Flashloan is Ownable, IFlashLoanRecipientBalancer {
         uint256[] memory feeAmounts,
         bytes memory userData
     ) external override {
-        ArbParams memory decoded = abi.decode(userData, (ArbParams));
+        FlashParams memory flash = abi.decode(userData, (FlashParams));
+        ArbParams memory decoded = flash.params;
 
         // ARB LOGIC HERE
         uint256 currentAmount = decoded.amountIn;
         uint len = decoded.protocolPath.length;
+        bool exactOutput = flash.maxLastAmountIn > 0;
         address[] memory path = new address[](2);
-        for (uint i; i < len; ++i) {
+        for (uint i; i < (exactOutput ? len - 1 : len); ++i) {
             path[0] = decoded.tokenPath[i];
             path[1] = decoded.tokenPath[i + 1];
 
+            uint256 minOut = flash.minAmountsOut.length > 0 ? flash.minAmountsOut[i] : 0;
             uint8 protocolType = decoded.protocolTypes[i];
             if (protocolType == 0) {
                 // uniswapv2 gang
-                currentAmount = uniswapV2(currentAmount, decoded.protocolPath[i], path);
+                currentAmount = uniswapV2(currentAmount, minOut, decoded.protocolPath[i], path);
             } else if (protocolType == 1) {
                 // uniswapv3 gang
-                currentAmount = uniswapV3(currentAmount, decoded.protocolPath[i], decoded.fees[i], path);
+                currentAmount = uniswapV3(currentAmount, minOut, decoded.protocolPath[i], decoded.fees[i], path);
             }
         }
 
-        require(currentAmount > decoded.amountIn, "a");
-
         IERC20 loanToken = tokens[0];
         uint256 loanAmount = amounts[0];
 
+        if (exactOutput) {
+            uint last = len - 1;
+            path[0] = decoded.tokenPath[last];
+            path[1] = decoded.tokenPath[last + 1];
+            uint256 spent;
+            if (decoded.protocolTypes[last] == 0) {
+                spent = uniswapV2ExactOutput(loanAmount, flash.maxLastAmountIn, decoded.protocolPath[last], path);
+            } else {
+                spent = uniswapV3ExactOutput(loanAmount, flash.maxLastAmountIn, decoded.protocolPath[last], decoded.fees[last], path);
+            }
+            require(currentAmount - spent >= flash.minProfit, "p");
+
+            // Send what's left of the last hop's input to owner
+            IERC20(path[0]).transfer(owner(), currentAmount - spent);
+
+            // Return funds
+            loanToken.transfer(address(vault), loanAmount);
+            return;
+        }
+
+        require(currentAmount > decoded.amountIn, "a");
+        require(currentAmount - loanAmount >= flash.minProfit, "p");
+
         // Send profits to owner
         loanToken.transfer(owner(), currentAmount - loanAmount);
 
@@ -86,13 +172,14 @@ contract Flashloan is Ownable, IFlashLoanRecipientBalancer {
 
     function uniswapV2(
         uint256 amountIn,
+        uint256 minOut,
         address router,
         address[] memory path
     ) internal returns (uint256 amountOut) {
         approveToken(path[0], router, amountIn);
         return IUniswapV2Router(router).swapExactTokensForTokens(
             amountIn,
-            1,
+            minOut > 0 ? minOut : 1,
             path,
             address(this),
             block.timestamp
@@ -101,6 +188,7 @@ contract Flashloan is Ownable, IFlashLoanRecipientBalancer {
 
     function uniswapV3(
         uint256 amountIn,
+        uint256 minOut,
         address router,
         uint24 fee,
         address[] memory path
@@ -117,7 +205,47 @@ contract Flashloan is Ownable, IFlashLoanRecipientBalancer {
                 recipient: address(this),
                 deadline: block.timestamp,
                 amountIn: amountIn,
-                amountOutMinimum: 0,
+                amountOutMinimum: minOut,
+                sqrtPriceLimitX96: 0
+            })
+        );
+    }
+
+    function uniswapV2ExactOutput(
+        uint256 amountOut,
+        uint256 maxAmountIn,
+        address router,
+        address[] memory path
+    ) internal returns (uint256 amountIn) {
+        approveToken(path[0], router, maxAmountIn);
+        return IUniswapV2Router(router).swapTokensForExactTokens(
+            amountOut,
+            maxAmountIn,
+            path,
+            address(this),
+            block.timestamp
+        )[0];
+    }
+
+    function uniswapV3ExactOutput(
+        uint256 amountOut,
+        uint256 maxAmountIn,
+        address router,
+        uint24 fee,
+        address[] memory path
+    ) internal returns (uint256 amountIn) {
+        ISwapRouter swapRouter = ISwapRouter(router);
+        approveToken(path[0], address(swapRouter), maxAmountIn);
+
+        amountIn = swapRouter.exactOutputSingle(
+            ISwapRouter.ExactOutputSingleParams({
+                tokenIn: path[0],
+                tokenOut: path[1],
+                fee: fee,
+                recipient: address(this),
+                deadline: block.timestamp,
+                amountOut: amountOut,
+                amountInMaximum: maxAmountIn,
                 sqrtPriceLimitX96: 0
             })
         );
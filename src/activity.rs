@@ -0,0 +1,371 @@
+//! Indexes arbitrages and liquidations other searchers executed in past
+//! blocks, to size the opportunity landscape and see how we compare.
+//!
+//! A txn is an arbitrage if it swaps through at least two pools without
+//! going through a router and leaves its sender and contract with no less
+//! of any token than before, and a liquidation if AAVE emitted a
+//! `LiquidationCall` in it. Profit is estimated from the ERC20 transfers in
+//! and out of the sender and the contract it called, so profit unwrapped to
+//! MATIC or sent elsewhere within the txn isn't seen.
+
+use std::collections::{HashMap, HashSet};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, Filter, TransactionReceipt, H256, I256, U256, U64},
+    utils::keccak256,
+};
+use futures_util::future::join_all;
+use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::protocol::{UniswapV2, UNISWAP_V3},
+    export::{int64, utf8, Cell, Column, ColumnType, Record},
+    liquidator::{
+        competitors::{LIQUIDATION_CALL_EVENT, MAX_LOG_RANGE},
+        AAVE_V3_POOL,
+    },
+    storage,
+};
+
+lazy_static! {
+    static ref TRANSFER: H256 = topic("Transfer(address,address,uint256)");
+    pub(crate) static ref V2_SWAP: H256 = topic("Swap(address,uint256,uint256,uint256,uint256,address)");
+    pub(crate) static ref V3_SWAP: H256 = topic("Swap(address,address,int256,int256,uint160,uint128,int24)");
+    static ref LIQUIDATION_CALL: H256 = topic(LIQUIDATION_CALL_EVENT);
+    /// AAVE v3, Balancer and DODO flashloans
+    static ref FLASHLOANS: [H256; 3] = [
+        topic("FlashLoan(address,address,address,uint256,uint8,uint256,uint16)"),
+        topic("FlashLoan(address,address,uint256,uint256)"),
+        topic("DODOFlashLoan(address,address,uint256,uint256)"),
+    ];
+}
+
+fn topic(event: &str) -> H256 {
+    H256::from(keccak256(event))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Arbitrage,
+    Liquidation,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityRecord {
+    pub block: u64,
+    pub tx_hash: H256,
+    pub kind: ActivityKind,
+    /// the contract called, usually the searcher's bot
+    pub searcher: Address,
+    pub sender: Address,
+    pub flashloan: bool,
+    pub swaps: u64,
+    pub gas_used: U256,
+    pub gas_price: U256,
+    /// token the searcher gained the most of, `None` if it lost any token
+    /// (e.g. repaid a liquidation from its own balance) since that needs
+    /// prices to net out
+    pub profit_token: Option<Address>,
+    pub profit: U256,
+}
+
+impl ActivityRecord {
+    pub fn gas_cost(&self) -> U256 {
+        self.gas_used * self.gas_price
+    }
+}
+
+impl Record for ActivityRecord {
+    const LOG: &'static str = storage::ACTIVITY;
+
+    fn columns() -> &'static [Column] {
+        const COLUMNS: &[Column] = &[
+            int64("block"),
+            utf8("tx_hash"),
+            utf8("kind"),
+            utf8("searcher"),
+            utf8("sender"),
+            int64("flashloan"),
+            int64("swaps"),
+            utf8("gas_used"),
+            utf8("gas_price"),
+            Column {
+                name: "profit_token",
+                ty: ColumnType::Utf8,
+                nullable: true,
+            },
+            utf8("profit"),
+        ];
+        COLUMNS
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let kind = match self.kind {
+            ActivityKind::Arbitrage => "arbitrage",
+            ActivityKind::Liquidation => "liquidation",
+        };
+        vec![
+            self.block.into(),
+            self.tx_hash.into(),
+            kind.to_string().into(),
+            self.searcher.into(),
+            self.sender.into(),
+            (self.flashloan as u64).into(),
+            self.swaps.into(),
+            self.gas_used.into(),
+            self.gas_price.into(),
+            self.profit_token.into(),
+            self.profit.into(),
+        ]
+    }
+}
+
+/// Classifies a mined txn, `None` if it's neither an arbitrage nor a
+/// liquidation. `routers` are contracts retail swaps go through.
+pub fn classify(
+    receipt: &TransactionReceipt,
+    routers: &HashSet<Address>,
+) -> Option<ActivityRecord> {
+    let searcher = receipt.to?;
+    let mut swaps = 0;
+    let mut liquidation = false;
+    let mut flashloan = false;
+    let mut flows: HashMap<Address, I256> = HashMap::new();
+    for log in &receipt.logs {
+        let topic0 = match log.topics.first() {
+            Some(topic0) => *topic0,
+            None => continue,
+        };
+        if topic0 == *V2_SWAP || topic0 == *V3_SWAP {
+            swaps += 1;
+        } else if topic0 == *LIQUIDATION_CALL && log.address == *AAVE_V3_POOL {
+            liquidation = true;
+        } else if FLASHLOANS.contains(&topic0) {
+            flashloan = true;
+        } else if topic0 == *TRANSFER && log.topics.len() == 3 && log.data.len() == 32 {
+            let from = Address::from(log.topics[1]);
+            let to = Address::from(log.topics[2]);
+            let amount = I256::from_raw(U256::from_big_endian(&log.data));
+            let beneficiary = |address: Address| address == searcher || address == receipt.from;
+            // moves between the sender and its contract cancel out
+            if beneficiary(to) && !beneficiary(from) {
+                *flows.entry(log.address).or_default() += amount;
+            } else if beneficiary(from) && !beneficiary(to) {
+                *flows.entry(log.address).or_default() -= amount;
+            }
+        }
+    }
+
+    let lost_any = flows.values().any(|flow| flow.is_negative());
+    let kind = if liquidation {
+        ActivityKind::Liquidation
+    } else if swaps >= 2 && !routers.contains(&searcher) && !lost_any {
+        ActivityKind::Arbitrage
+    } else {
+        return None;
+    };
+    let (profit_token, profit) = match lost_any {
+        true => (None, U256::zero()),
+        false => flows
+            .iter()
+            .max_by_key(|(_, flow)| **flow)
+            .map(|(token, flow)| (Some(*token), flow.into_raw()))
+            .unwrap_or((None, U256::zero())),
+    };
+    Some(ActivityRecord {
+        block: receipt.block_number?.as_u64(),
+        tx_hash: receipt.transaction_hash,
+        kind,
+        searcher,
+        sender: receipt.from,
+        flashloan,
+        swaps,
+        gas_used: receipt.gas_used.unwrap_or_default(),
+        gas_price: receipt.effective_gas_price.unwrap_or_default(),
+        profit_token,
+        profit,
+    })
+}
+
+/// the routers of every supported DEX
+pub fn known_routers() -> HashSet<Address> {
+    UniswapV2::get_all_protoccols()
+        .iter()
+        .map(|protocol| protocol.get_router_address())
+        .chain([UNISWAP_V3.router_address])
+        .collect()
+}
+
+/// Totals of one searcher over the indexed blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearcherStats {
+    pub searcher: Address,
+    pub arbitrages: u64,
+    pub liquidations: u64,
+    pub gas_cost: U256,
+    /// token -> estimated profit
+    pub profit: HashMap<Address, U256>,
+}
+
+/// per searcher totals, most active first
+pub fn summarize(records: &[ActivityRecord]) -> Vec<SearcherStats> {
+    let mut searchers: HashMap<Address, SearcherStats> = HashMap::new();
+    for record in records {
+        let stats = searchers
+            .entry(record.searcher)
+            .or_insert_with(|| SearcherStats {
+                searcher: record.searcher,
+                ..Default::default()
+            });
+        match record.kind {
+            ActivityKind::Arbitrage => stats.arbitrages += 1,
+            ActivityKind::Liquidation => stats.liquidations += 1,
+        }
+        stats.gas_cost += record.gas_cost();
+        if let Some(token) = record.profit_token {
+            *stats.profit.entry(token).or_default() += record.profit;
+        }
+    }
+    let mut ranked: Vec<SearcherStats> = searchers.into_values().collect();
+    ranked.sort_by(|a, b| {
+        (b.arbitrages + b.liquidations)
+            .cmp(&(a.arbitrages + a.liquidations))
+            .then(a.searcher.cmp(&b.searcher))
+    });
+    ranked
+}
+
+pub struct ActivityIndexer<M> {
+    provider: M,
+    routers: HashSet<Address>,
+}
+
+impl<M: Middleware> ActivityIndexer<M> {
+    pub fn new(provider: M) -> Self {
+        Self {
+            provider,
+            routers: known_routers(),
+        }
+    }
+
+    /// Arbitrages and liquidations in `[from_block, to_block]`, oldest
+    /// first. Only txns with a liquidation or at least two swaps have their
+    /// receipt fetched.
+    pub async fn scan(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<ActivityRecord>, M::Error> {
+        let mut records = Vec::new();
+        let mut start = from_block;
+        while start <= to_block {
+            let end = u64::min(start + MAX_LOG_RANGE - 1, to_block);
+            let filter = Filter::new()
+                .topic0(vec![*V2_SWAP, *V3_SWAP, *LIQUIDATION_CALL])
+                .from_block(BlockNumber::Number(U64::from(start)))
+                .to_block(BlockNumber::Number(U64::from(end)));
+
+            let mut swaps: HashMap<H256, usize> = HashMap::new();
+            let mut candidates: Vec<H256> = Vec::new();
+            for log in self.provider.get_logs(&filter).await? {
+                let tx_hash = match log.transaction_hash {
+                    Some(tx_hash) => tx_hash,
+                    None => continue,
+                };
+                let count = swaps.entry(tx_hash).or_default();
+                let liquidation = log.topics.first() == Some(&*LIQUIDATION_CALL);
+                if !liquidation {
+                    *count += 1;
+                }
+                if (liquidation || *count == 2) && !candidates.contains(&tx_hash) {
+                    candidates.push(tx_hash);
+                }
+            }
+
+            let receipts = join_all(
+                candidates
+                    .iter()
+                    .map(|tx_hash| self.provider.get_transaction_receipt(*tx_hash)),
+            )
+            .await;
+            for (tx_hash, receipt) in candidates.iter().zip(receipts) {
+                match receipt? {
+                    Some(receipt) => records.extend(classify(&receipt, &self.routers)),
+                    None => warn!("no receipt for indexed txn {:?}", tx_hash),
+                }
+            }
+            start = end + 1;
+        }
+        records.sort_by_key(|record| record.block);
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Bytes, Log};
+
+    use super::*;
+
+    fn log(address: Address, topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address,
+            topics,
+            data: Bytes::from(data),
+            ..Default::default()
+        }
+    }
+
+    fn transfer(token: Address, from: Address, to: Address, amount: u64) -> Log {
+        let mut data = [0u8; 32];
+        U256::from(amount).to_big_endian(&mut data);
+        log(
+            token,
+            vec![*TRANSFER, from.into(), to.into()],
+            data.to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_classify_arbitrage() {
+        let bot = Address::from_low_u64_be(1);
+        let pair_a = Address::from_low_u64_be(2);
+        let pair_b = Address::from_low_u64_be(3);
+        let usdc = Address::from_low_u64_be(4);
+        let weth = Address::from_low_u64_be(5);
+        let mut receipt = TransactionReceipt {
+            from: Address::from_low_u64_be(9),
+            to: Some(bot),
+            block_number: Some(7.into()),
+            gas_used: Some(200_000.into()),
+            effective_gas_price: Some(30.into()),
+            logs: vec![
+                transfer(usdc, bot, pair_a, 1000),
+                transfer(weth, pair_a, pair_b, 1),
+                log(pair_a, vec![*V2_SWAP], vec![]),
+                transfer(usdc, pair_b, bot, 1010),
+                log(pair_b, vec![*V3_SWAP], vec![]),
+            ],
+            ..Default::default()
+        };
+        let record = classify(&receipt, &known_routers()).unwrap();
+        assert_eq!(record.kind, ActivityKind::Arbitrage);
+        assert_eq!(record.swaps, 2);
+        assert!(!record.flashloan);
+        assert_eq!(record.profit_token, Some(usdc));
+        assert_eq!(record.profit, 10.into());
+        assert_eq!(record.gas_cost(), 6_000_000.into());
+
+        let summary = summarize(&[record.clone(), record]);
+        assert_eq!(summary[0].arbitrages, 2);
+        assert_eq!(summary[0].profit[&usdc], 20.into());
+
+        // a losing round trip isn't an arbitrage
+        receipt.logs[3] = transfer(usdc, pair_b, bot, 990);
+        assert!(classify(&receipt, &known_routers()).is_none());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use ethers::types::Address;
+use lazy_static::lazy_static;
+use log::warn;
+use thiserror::Error;
+
+use crate::storage::{Storage, StorageError, Table, ADDRESS_BOOK};
+
+pub const POLYGON: u64 = 137;
+
+/// the flashloan contract `arb` executes through
+pub const FLASHLOAN_EXECUTOR: &str = "flashloan_executor";
+
+/// the Liquidations contract `frontrunner_aave` liquidates through
+pub const LIQUIDATOR: &str = "liquidator";
+
+lazy_static! {
+    /// our own deployments, used for names the book has no entry for
+    static ref BUILTIN: AddressBook = {
+        let mut book = AddressBook::default();
+        book.insert(
+            POLYGON,
+            FLASHLOAN_EXECUTOR,
+            "0x7472bacc648111408497c087826739e7a1e0a6d2".parse().unwrap(),
+        );
+        book.insert(
+            POLYGON,
+            LIQUIDATOR,
+            "0x5D03B3678c120F3EcC04eb96dAAb6e15B012022e".parse().unwrap(),
+        );
+        book
+    };
+}
+
+#[derive(Debug, Error)]
+#[error("no {name} on chain {chain_id} in the address book, deploy one first")]
+pub struct MissingAddress {
+    pub chain_id: u64,
+    pub name: String,
+}
+
+/// Deployed contracts by chain id and name, written by `deploy` and read by
+/// the bots at startup so redeploying doesn't mean editing addresses in the
+/// code. Kept in the `address_book` table keyed by `<chain id>/<name>`.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct AddressBook {
+    chains: BTreeMap<u64, BTreeMap<String, Address>>,
+}
+
+impl AddressBook {
+    /// the book kept in `storage`, empty if nothing was deployed yet
+    pub async fn load(storage: Arc<dyn Storage>) -> Result<Self, StorageError> {
+        let table: Table<Address> = Table::new(storage, ADDRESS_BOOK);
+        let mut book = Self::default();
+        for (key, address) in table.all().await? {
+            let entry = key
+                .split_once('/')
+                .and_then(|(chain_id, name)| Some((chain_id.parse().ok()?, name)));
+            match entry {
+                Some((chain_id, name)) => {
+                    book.insert(chain_id, name, address);
+                }
+                None => warn!("address book entry {} has no chain id", key),
+            }
+        }
+        Ok(book)
+    }
+
+    /// writes every entry to `storage`, replacing the ones there under the
+    /// same chain id and name
+    pub async fn save(&self, storage: Arc<dyn Storage>) -> Result<(), StorageError> {
+        let table: Table<Address> = Table::new(storage, ADDRESS_BOOK);
+        for (chain_id, name, address) in self.entries() {
+            table
+                .put(&format!("{}/{}", chain_id, name), &address)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// only what the book itself has, see `resolve` for the fallback
+    pub fn get(&self, chain_id: u64, name: &str) -> Option<Address> {
+        self.chains.get(&chain_id)?.get(name).copied()
+    }
+
+    /// `name` on `chain_id`, falling back to our built-in deployments
+    pub fn resolve(&self, chain_id: u64, name: &str) -> Result<Address, MissingAddress> {
+        self.get(chain_id, name)
+            .or_else(|| BUILTIN.get(chain_id, name))
+            .ok_or_else(|| MissingAddress {
+                chain_id,
+                name: name.to_string(),
+            })
+    }
+
+    /// returns the address `name` had on `chain_id` before
+    pub fn insert(
+        &mut self,
+        chain_id: u64,
+        name: impl Into<String>,
+        address: Address,
+    ) -> Option<Address> {
+        self.chains
+            .entry(chain_id)
+            .or_default()
+            .insert(name.into(), address)
+    }
+
+    /// (chain id, name, address) of every entry, by chain then name
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &str, Address)> {
+        self.chains.iter().flat_map(|(chain_id, names)| {
+            names
+                .iter()
+                .map(move |(name, address)| (*chain_id, name.as_str(), *address))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        assert_eq!(
+            AddressBook::load(storage.clone()).await.unwrap(),
+            AddressBook::default()
+        );
+
+        let mut book = AddressBook::default();
+        let executor = Address::random();
+        assert_eq!(
+            book.insert(POLYGON, FLASHLOAN_EXECUTOR, Address::random()),
+            None
+        );
+        assert!(book.insert(POLYGON, FLASHLOAN_EXECUTOR, executor).is_some());
+        book.insert(80001, FLASHLOAN_EXECUTOR, Address::random());
+        book.save(storage.clone()).await.unwrap();
+
+        let loaded = AddressBook::load(storage).await.unwrap();
+        assert_eq!(loaded, book);
+        assert_eq!(loaded.get(POLYGON, FLASHLOAN_EXECUTOR), Some(executor));
+        assert_eq!(loaded.get(1, FLASHLOAN_EXECUTOR), None);
+        assert_eq!(loaded.entries().count(), 2);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut book = AddressBook::default();
+        // built-in deployments unless overridden
+        assert_eq!(
+            book.resolve(POLYGON, LIQUIDATOR).unwrap(),
+            BUILTIN.get(POLYGON, LIQUIDATOR).unwrap()
+        );
+        let liquidator = Address::random();
+        book.insert(POLYGON, LIQUIDATOR, liquidator);
+        assert_eq!(book.resolve(POLYGON, LIQUIDATOR).unwrap(), liquidator);
+
+        let missing = book.resolve(80001, LIQUIDATOR).unwrap_err();
+        assert_eq!(missing.chain_id, 80001);
+        assert_eq!(missing.name, LIQUIDATOR);
+    }
+}
@@ -0,0 +1,223 @@
+//! Labels for addresses, so logs, mempool classifications and the api can
+//! say "Quickswap router" or "known bot" instead of raw hex. Built from the
+//! tokens, protocols and contracts in `constants`, our deployments in the
+//! address book, and a json file of extra or overriding tags:
+//!
+//! ```json
+//! { "0x1111111254EEB25477B68fb85Ed929f73A960582": { "label": "1inch v5 router", "kind": "aggregator" } }
+//! ```
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use enum_map::Enum;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address_book::AddressBook,
+    constants::{
+        protocol::{UniswapV2, UNISWAP_V3},
+        token::ERC20Token,
+    },
+    liquidator::{AAVE_V3_POOL, KNOWN_LIQUIDATORS},
+    utils::calldata::{ONE_INCH_V5_ROUTER, ZERO_EX_PROXY},
+};
+
+pub const DEFAULT_ADDRESS_TAGS: &str = "data/address_tags.json";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagKind {
+    Token,
+    Router,
+    Factory,
+    Pool,
+    LendingPool,
+    Aggregator,
+    /// our own contracts and wallets
+    Ours,
+    Bot,
+    #[default]
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressTag {
+    pub label: String,
+    #[serde(default)]
+    pub kind: TagKind,
+}
+
+impl AddressTag {
+    pub fn new(label: impl Into<String>, kind: TagKind) -> Self {
+        Self {
+            label: label.into(),
+            kind,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AddressTags {
+    tags: HashMap<Address, AddressTag>,
+}
+
+impl AddressTags {
+    /// every address the code itself knows about
+    pub fn builtin() -> Self {
+        let mut tags = Self::default();
+        for token in (0..ERC20Token::LENGTH).map(ERC20Token::from_usize) {
+            tags.insert(
+                token.get_address(),
+                AddressTag::new(token.get_symbol(), TagKind::Token),
+            );
+        }
+        for protocol in UniswapV2::get_all_protoccols() {
+            tags.insert(
+                protocol.get_router_address(),
+                AddressTag::new(format!("{} router", protocol.get_name()), TagKind::Router),
+            );
+            tags.insert(
+                protocol.get_factory_address(),
+                AddressTag::new(format!("{} factory", protocol.get_name()), TagKind::Factory),
+            );
+        }
+        tags.insert(
+            UNISWAP_V3.router_address,
+            AddressTag::new(format!("{} router", UNISWAP_V3.name), TagKind::Router),
+        );
+        tags.insert(
+            UNISWAP_V3.factory_address,
+            AddressTag::new(format!("{} factory", UNISWAP_V3.name), TagKind::Factory),
+        );
+        tags.insert(
+            *AAVE_V3_POOL,
+            AddressTag::new("AAVE pool", TagKind::LendingPool),
+        );
+        tags.insert(
+            *ONE_INCH_V5_ROUTER,
+            AddressTag::new("1inch router", TagKind::Aggregator),
+        );
+        tags.insert(
+            *ZERO_EX_PROXY,
+            AddressTag::new("0x proxy", TagKind::Aggregator),
+        );
+        for (i, liquidator) in KNOWN_LIQUIDATORS.iter().enumerate() {
+            tags.insert(
+                *liquidator,
+                AddressTag::new(format!("known bot {}", i + 1), TagKind::Bot),
+            );
+        }
+        tags
+    }
+
+    /// The built-in tags, our deployments on `chain_id` from `book`, then
+    /// the tags in the file at `path` if there is one.
+    pub fn load(path: impl AsRef<Path>, book: &AddressBook, chain_id: u64) -> io::Result<Self> {
+        let mut tags = Self::builtin();
+        for (_, name, address) in book.entries().filter(|(chain, _, _)| *chain == chain_id) {
+            tags.insert(address, AddressTag::new(name, TagKind::Ours));
+        }
+        match fs::read_to_string(path) {
+            Ok(contents) => tags.extend(serde_json::from_str::<Self>(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(tags)
+    }
+
+    /// returns the tag `address` had before
+    pub fn insert(&mut self, address: Address, tag: AddressTag) -> Option<AddressTag> {
+        self.tags.insert(address, tag)
+    }
+
+    /// adds the tags of `other`, replacing the ones for the same address
+    pub fn extend(&mut self, other: AddressTags) {
+        self.tags.extend(other.tags);
+    }
+
+    pub fn get(&self, address: Address) -> Option<&AddressTag> {
+        self.tags.get(&address)
+    }
+
+    pub fn label(&self, address: Address) -> Option<&str> {
+        self.get(address).map(|tag| tag.label.as_str())
+    }
+
+    /// the label of `address` with the address, or only the address, for
+    /// logs
+    pub fn describe(&self, address: Address) -> String {
+        match self.label(address) {
+            Some(label) => format!("{} ({:?})", label, address),
+            None => format!("{:?}", address),
+        }
+    }
+
+    /// every tag, sorted by address
+    pub fn all(&self) -> Vec<(Address, AddressTag)> {
+        let mut tags: Vec<_> = self
+            .tags
+            .iter()
+            .map(|(address, tag)| (*address, tag.clone()))
+            .collect();
+        tags.sort_by_key(|(address, _)| *address);
+        tags
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address_book::{FLASHLOAN_EXECUTOR, POLYGON};
+
+    #[test]
+    fn test_address_tags() {
+        let path = std::env::temp_dir().join(format!("address-tags-{}.json", std::process::id()));
+        let ours = Address::random();
+        let bot = Address::random();
+        let mut book = AddressBook::default();
+        book.insert(POLYGON, FLASHLOAN_EXECUTOR, ours);
+        book.insert(80001, "elsewhere", Address::random());
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{ "{:?}": {{ "label": "sandwich bot", "kind": "bot" }}, "{:?}": {{ "label": "quickswap" }} }}"#,
+                bot,
+                UniswapV2::QUICKSWAP.get_router_address()
+            ),
+        )
+        .unwrap();
+        let tags = AddressTags::load(&path, &book, POLYGON).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(tags.label(ERC20Token::WETH.get_address()), Some("WETH"));
+        assert_eq!(tags.get(*AAVE_V3_POOL).unwrap().kind, TagKind::LendingPool);
+        assert_eq!(
+            tags.get(ours),
+            Some(&AddressTag::new(FLASHLOAN_EXECUTOR, TagKind::Ours))
+        );
+        assert_eq!(
+            tags.get(bot),
+            Some(&AddressTag::new("sandwich bot", TagKind::Bot))
+        );
+        // the file overrides the built-in tags
+        assert_eq!(
+            tags.get(UniswapV2::QUICKSWAP.get_router_address()),
+            Some(&AddressTag::new("quickswap", TagKind::Other))
+        );
+        assert_eq!(tags.len(), AddressTags::builtin().len() + 2);
+        assert_eq!(
+            tags.describe(Address::zero()),
+            format!("{:?}", Address::zero())
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! Read-only HTTP view of what the bot sees, for dashboards and tools that
+//! shouldn't need their own RPC:
+//!
+//! - `GET /pools`: reserves of every tracked V2 pair
+//! - `GET /quote?in=USDC&out=WETH&amount=1000000`: best single hop quote,
+//!   amounts in the token's smallest unit
+//! - `GET /mempool/pending?to=0x...`: pending txns, optionally only to one
+//!   address
+//! - `GET /mempool/classified?to=0x...`: the same, decoded into what they do
+//!   with their addresses labelled
+//! - `GET /opportunities/recent?limit=20`: latest opportunities, newest first
+//! - `GET /prices`: index price of every token in USDC, see `price_index`
+//! - `GET /prices/history?token=WETH&limit=20`: index prices of one token,
+//!   newest first
+//! - `GET /execution/channels`: per submission channel acceptance and wins
+//! - `GET /routes/heatmap`: per route share of quotes above the edge
+//!   threshold, average edge and best hour, see `heatmap`
+//! - `GET /routes/heatmap/hours?route=USDC>WETH>USDC`: the same per hour of
+//!   day, optionally of one route
+//! - `GET /state?block=N`: state hash and reserves of one of the last 64
+//!   blocks, the latest without `block`
+//! - `GET /addresses?kind=bot`: every tagged address, optionally of one kind
+//! - `GET /inventory`: the wallet's and executor's balance of every token,
+//!   and the change of their total since startup, see `inventory`
+//! - `GET /status`: RPC calls, CPU time, submissions, reverts and gas spent
+//!   per strategy, see `resources`
+//! - `GET /budgets`: every gas budget and what was spent of it, see
+//!   `gas_budget`
+//! - `GET /metrics`: the same, the inventory and the gas budgets in the
+//!   Prometheus text format
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use ethers::{
+    providers::{Middleware, PubsubClient},
+    types::{Address, Transaction, H256, U256},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{
+    address_tags::{AddressTag, AddressTags, TagKind},
+    constants::token::ERC20Token,
+    export::{OpportunityRecord, ReserveRecord},
+    gas_budget::{BudgetStatus, GasBudgets},
+    heatmap::{HeatCell, RouteHeat, RouteHeatmap, DEFAULT_MIN_EDGE_BPS},
+    inventory::{Inventory, TokenInventory},
+    price_index::{IndexPrice, PriceIndex, PRICE_HISTORY},
+    resources::{ResourceUsage, StrategyUsage},
+    snapshot::StateSnapshot,
+    tx_pool::TxPool,
+    utils::{
+        broadcast::{ChannelStats, SubmissionStats},
+        calldata::{DecoderRegistry, Intent},
+    },
+    world::WorldState,
+};
+
+/// opportunities kept for `/opportunities/recent`
+pub const RECENT_OPPORTUNITIES: usize = 100;
+
+type ApiError = (StatusCode, String);
+
+/// the last `capacity` opportunities recorded
+pub struct RecentOpportunities {
+    capacity: usize,
+    records: RwLock<VecDeque<OpportunityRecord>>,
+}
+
+impl RecentOpportunities {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: RwLock::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub async fn push(&self, record: OpportunityRecord) {
+        let mut records = self.records.write().await;
+        if records.len() == self.capacity {
+            records.pop_back();
+        }
+        records.push_front(record);
+    }
+
+    /// newest first
+    pub async fn latest(&self, limit: usize) -> Vec<OpportunityRecord> {
+        let records = self.records.read().await;
+        records.iter().take(limit).cloned().collect()
+    }
+}
+
+pub struct Api<M, P> {
+    world: Arc<WorldState<M, P>>,
+    txpool: Arc<TxPool<M>>,
+    decoders: DecoderRegistry,
+    tags: AddressTags,
+    resources: Arc<ResourceUsage>,
+    pub opportunities: RecentOpportunities,
+    /// filled by whoever drives the block loop
+    pub prices: Arc<PriceIndex>,
+    /// filled by whoever sends through a `TieredSender`
+    pub channels: Arc<SubmissionStats>,
+    /// filled by whoever quotes routes
+    pub heatmap: Arc<RouteHeatmap>,
+    /// filled by `track_inventory`
+    pub inventory: Arc<Inventory>,
+    gas_budgets: Arc<GasBudgets>,
+}
+
+#[derive(Deserialize)]
+struct QuoteParams {
+    #[serde(rename = "in")]
+    token_in: String,
+    #[serde(rename = "out")]
+    token_out: String,
+    amount: String,
+}
+
+#[derive(Serialize)]
+struct Quote {
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    amount_out: U256,
+    protocol: String,
+}
+
+#[derive(Deserialize)]
+struct PendingParams {
+    to: Option<Address>,
+}
+
+/// a pending txn decoded, with the labels of its addresses
+#[derive(Serialize)]
+struct ClassifiedTxn {
+    hash: H256,
+    from: Address,
+    from_label: Option<String>,
+    to: Option<Address>,
+    to_label: Option<String>,
+    intent: Option<Intent>,
+}
+
+#[derive(Serialize)]
+struct TaggedAddress {
+    address: Address,
+    #[serde(flatten)]
+    tag: AddressTag,
+}
+
+#[derive(Serialize)]
+struct Status {
+    strategies: Vec<StrategyUsage>,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    kind: Option<TagKind>,
+}
+
+#[derive(Deserialize)]
+struct RecentParams {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct PriceHistoryParams {
+    token: String,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct HeatmapParams {
+    route: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StateParams {
+    block: Option<u64>,
+}
+
+impl<M, P> Api<M, P>
+where
+    M: Middleware + Clone + 'static,
+    P: PubsubClient + 'static,
+{
+    pub fn new(world: Arc<WorldState<M, P>>, txpool: Arc<TxPool<M>>) -> Self {
+        Self {
+            world,
+            txpool,
+            decoders: DecoderRegistry::polygon(),
+            tags: AddressTags::builtin(),
+            resources: Arc::new(ResourceUsage::new()),
+            opportunities: RecentOpportunities::new(RECENT_OPPORTUNITIES),
+            prices: Arc::new(PriceIndex::new(ERC20Token::USDC, PRICE_HISTORY)),
+            channels: Arc::new(SubmissionStats::new()),
+            heatmap: Arc::new(RouteHeatmap::new(DEFAULT_MIN_EDGE_BPS)),
+            inventory: Arc::new(Inventory::new()),
+            gas_budgets: Arc::new(GasBudgets::default()),
+        }
+    }
+
+    /// labels addresses with `tags` instead of only the built-in ones
+    pub fn with_tags(mut self, tags: AddressTags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// reports the strategy usage the providers record in `resources`
+    pub fn with_resources(mut self, resources: Arc<ResourceUsage>) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    /// reports the spend recorded in `gas_budgets`
+    pub fn with_gas_budgets(mut self, gas_budgets: Arc<GasBudgets>) -> Self {
+        self.gas_budgets = gas_budgets;
+        self
+    }
+
+    /// reports route quotes recorded in `heatmap`
+    pub fn with_heatmap(mut self, heatmap: Arc<RouteHeatmap>) -> Self {
+        self.heatmap = heatmap;
+        self
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/pools", get(Self::pools))
+            .route("/quote", get(Self::quote))
+            .route("/mempool/pending", get(Self::pending))
+            .route("/mempool/classified", get(Self::classified))
+            .route("/opportunities/recent", get(Self::recent_opportunities))
+            .route("/prices", get(Self::prices))
+            .route("/prices/history", get(Self::price_history))
+            .route("/execution/channels", get(Self::channels))
+            .route("/routes/heatmap", get(Self::heatmap))
+            .route("/routes/heatmap/hours", get(Self::heatmap_hours))
+            .route("/state", get(Self::state))
+            .route("/addresses", get(Self::addresses))
+            .route("/inventory", get(Self::inventory))
+            .route("/status", get(Self::status))
+            .route("/budgets", get(Self::budgets))
+            .route("/metrics", get(Self::metrics))
+            .with_state(self)
+    }
+
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<(), hyper::Error> {
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await
+    }
+
+    async fn pools(State(api): State<Arc<Self>>) -> Json<Vec<ReserveRecord>> {
+        Json(api.world.pools().await)
+    }
+
+    async fn quote(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<QuoteParams>,
+    ) -> Result<Json<Quote>, ApiError> {
+        let token_in = parse_token(&params.token_in)?;
+        let token_out = parse_token(&params.token_out)?;
+        if token_in == token_out {
+            return Err((StatusCode::BAD_REQUEST, "in and out are the same".into()));
+        }
+        let amount_in = U256::from_dec_str(&params.amount)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad amount: {}", e)))?;
+        let (amount_out, protocols) = api
+            .world
+            .clone()
+            .compute_best_route(vec![token_in, token_out], amount_in)
+            .await;
+        Ok(Json(Quote {
+            token_in: token_in.get_address(),
+            token_out: token_out.get_address(),
+            amount_in,
+            amount_out,
+            protocol: protocols[0].to_string(),
+        }))
+    }
+
+    async fn pending(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<PendingParams>,
+    ) -> Json<Vec<Transaction>> {
+        let mut txns = api.txpool.get_mempool().await;
+        if let Some(to) = params.to {
+            txns.retain(|txn| txn.to == Some(to));
+        }
+        Json(txns)
+    }
+
+    async fn classified(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<PendingParams>,
+    ) -> Json<Vec<ClassifiedTxn>> {
+        let Json(txns) = Self::pending(State(api.clone()), Query(params)).await;
+        let label = |address| api.tags.label(address).map(str::to_string);
+        Json(
+            txns.into_iter()
+                .map(|txn| ClassifiedTxn {
+                    hash: txn.hash,
+                    from: txn.from,
+                    from_label: label(txn.from),
+                    to: txn.to,
+                    to_label: txn.to.and_then(label),
+                    intent: api.decoders.decode_transaction(&txn),
+                })
+                .collect(),
+        )
+    }
+
+    async fn recent_opportunities(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<RecentParams>,
+    ) -> Json<Vec<OpportunityRecord>> {
+        let limit = params.limit.unwrap_or(RECENT_OPPORTUNITIES);
+        Json(api.opportunities.latest(limit).await)
+    }
+
+    async fn prices(State(api): State<Arc<Self>>) -> Json<Vec<IndexPrice>> {
+        Json(api.prices.latest())
+    }
+
+    async fn price_history(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<PriceHistoryParams>,
+    ) -> Result<Json<Vec<IndexPrice>>, ApiError> {
+        let token = parse_token(&params.token)?;
+        let limit = params.limit.unwrap_or(PRICE_HISTORY);
+        Ok(Json(api.prices.history(token.get_address(), limit)))
+    }
+
+    async fn channels(State(api): State<Arc<Self>>) -> Json<Vec<ChannelStats>> {
+        Json(api.channels.channels())
+    }
+
+    async fn heatmap(State(api): State<Arc<Self>>) -> Json<Vec<RouteHeat>> {
+        Json(api.heatmap.summary())
+    }
+
+    async fn heatmap_hours(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<HeatmapParams>,
+    ) -> Json<Vec<HeatCell>> {
+        let mut cells = api.heatmap.cells();
+        if let Some(route) = params.route {
+            cells.retain(|cell| cell.route == route);
+        }
+        Json(cells)
+    }
+
+    async fn state(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<StateParams>,
+    ) -> Result<Json<StateSnapshot>, ApiError> {
+        api.world
+            .recorded_snapshot(params.block)
+            .map(Json)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "no state recorded for block".into()))
+    }
+
+    async fn addresses(
+        State(api): State<Arc<Self>>,
+        Query(params): Query<AddressParams>,
+    ) -> Json<Vec<TaggedAddress>> {
+        Json(
+            api.tags
+                .all()
+                .into_iter()
+                .filter(|(_, tag)| params.kind.is_none_or(|kind| tag.kind == kind))
+                .map(|(address, tag)| TaggedAddress { address, tag })
+                .collect(),
+        )
+    }
+
+    async fn status(State(api): State<Arc<Self>>) -> Json<Status> {
+        Json(Status {
+            strategies: api.resources.all(),
+        })
+    }
+
+    async fn inventory(State(api): State<Arc<Self>>) -> Json<Vec<TokenInventory>> {
+        Json(api.inventory.tokens())
+    }
+
+    async fn budgets(State(api): State<Arc<Self>>) -> Json<Vec<BudgetStatus>> {
+        Json(api.gas_budgets.status(unix_now()))
+    }
+
+    async fn metrics(State(api): State<Arc<Self>>) -> String {
+        [
+            api.resources.prometheus(),
+            api.inventory.prometheus(),
+            api.gas_budgets.prometheus(unix_now()),
+        ]
+        .concat()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn parse_token(symbol: &str) -> Result<ERC20Token, ApiError> {
+    ERC20Token::from_symbol(symbol)
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("unknown token {}", symbol)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recent_opportunities() {
+        let recent = RecentOpportunities::new(2);
+        for block in 0..3 {
+            recent
+                .push(OpportunityRecord {
+                    block,
+                    route: "USDC>WETH>USDC".to_string(),
+                    amount_in: U256::from(1000),
+                    profit: U256::from(1),
+                    outcome: "unprofitable".to_string(),
+                    tx_hash: None,
+                })
+                .await;
+        }
+        let latest = recent.latest(10).await;
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[0].block, 2);
+        assert_eq!(latest[1].block, 1);
+        assert_eq!(ERC20Token::from_symbol("weth"), Some(ERC20Token::WETH));
+    }
+}
@@ -0,0 +1,363 @@
+//! Backruns of MEV-share hints. The Sync logs a hint reveals are the
+//! reserves its txn leaves its pairs at; every route through one of them is
+//! requoted against those, and the most profitable is signed as the
+//! backrun. Routes are quoted through V2 pairs only, a V3 pool a hinted
+//! swap moved reveals no state to requote it at.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use ethers::{
+    prelude::SignerMiddleware,
+    providers::{Middleware, PubsubClient},
+    signers::Signer,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Log, TransactionRequest, U256,
+    },
+};
+use log::{debug, info, warn};
+
+use super::{is_profitable, ARB_SLIPPAGE_BPS};
+use crate::{
+    arb_params::{ArbParamsBuilder, ExecutorFeatures, Flashloan},
+    constants::token::ERC20Token,
+    leader::LeaderLock,
+    routes::Route,
+    uniswapV2::PairEventKind,
+    utils::mev_share::{Backrun, Backrunner, Hint},
+    warmup::RouteTable,
+    world::{order_tokens, Protocol, WorldState},
+};
+
+/// over the route's estimate at warmup, the backrun can't be estimated
+/// before the hinted txn has run
+const GAS_LIMIT_HEADROOM_BPS: u64 = 2_500;
+
+/// The reserves `hint` reveals its txn leaves pairs at, the last Sync of
+/// each pair.
+pub fn projected_reserves(hint: &Hint) -> HashMap<Address, (U256, U256)> {
+    let mut projected = HashMap::new();
+    for hint_log in &hint.logs {
+        let log = Log {
+            address: hint_log.address,
+            topics: hint_log.topics.clone(),
+            data: hint_log.data.clone(),
+            ..Default::default()
+        };
+        if let Some(PairEventKind::Sync(sync)) = PairEventKind::decode(&log) {
+            projected.insert(
+                log.address,
+                (U256::from(sync.reserve_0), U256::from(sync.reserve_1)),
+            );
+        }
+    }
+    projected
+}
+
+/// A route requoted as a hinted txn leaves its pairs.
+struct Projected {
+    index: usize,
+    amounts_out: Vec<U256>,
+    protocols: Vec<Protocol>,
+    profit: U256,
+}
+
+pub struct ArbBackrunner<M, P, S> {
+    ws: Arc<WorldState<M, P>>,
+    routes: Vec<Route>,
+    table: Arc<RouteTable>,
+    executor: Flashloan<SignerMiddleware<Arc<M>, S>>,
+    features: ExecutorFeatures,
+    exact_output: bool,
+    /// only its holder backruns if set
+    leader: Option<Arc<LeaderLock>>,
+}
+
+impl<M, P, S> ArbBackrunner<M, P, S>
+where
+    M: Middleware + Clone + 'static,
+    P: PubsubClient + 'static,
+    S: Signer + 'static,
+{
+    pub fn new(
+        ws: Arc<WorldState<M, P>>,
+        routes: Vec<Route>,
+        table: Arc<RouteTable>,
+        executor: Flashloan<SignerMiddleware<Arc<M>, S>>,
+        features: ExecutorFeatures,
+    ) -> Self {
+        Self {
+            ws,
+            routes,
+            table,
+            executor,
+            features,
+            exact_output: false,
+            leader: None,
+        }
+    }
+
+    /// buys back exactly the loan on the last hop
+    pub fn with_exact_output(mut self, exact_output: bool) -> Self {
+        self.exact_output = exact_output;
+        self
+    }
+
+    pub fn with_leader(mut self, leader: Arc<LeaderLock>) -> Self {
+        self.leader = Some(leader);
+        self
+    }
+
+    /// The route most profitable once the pairs are at `projected`, of the
+    /// ones passing through one of them. Profits of different start tokens
+    /// are compared scaled to 18 decimals.
+    async fn best_route(&self, projected: &HashMap<Address, (U256, U256)>) -> Option<Projected> {
+        let touched: Vec<(ERC20Token, ERC20Token)> = projected
+            .keys()
+            .filter_map(|pair| self.ws.v2_pair(*pair))
+            .map(|(_, token0, token1)| order_tokens(token0, token1))
+            .collect();
+        let mut best: Option<Projected> = None;
+        for (index, route) in self.routes.iter().enumerate() {
+            if !route
+                .token_path
+                .windows(2)
+                .any(|hop| touched.contains(&order_tokens(hop[0], hop[1])))
+            {
+                continue;
+            }
+            let (amounts_out, protocols) = self
+                .ws
+                .compute_projected_route_hops(&route.token_path, route.amount_in, projected)
+                .await;
+            // one that doesn't go through the hint's pairs was there to take
+            // before it
+            if !self
+                .ws
+                .route_pairs(&route.token_path, &protocols)
+                .into_iter()
+                .flatten()
+                .any(|pair| projected.contains_key(&pair))
+            {
+                continue;
+            }
+            let amount_out = amounts_out.last().copied().unwrap_or_default();
+            if amount_out <= route.amount_in {
+                continue;
+            }
+            let profit = amount_out - route.amount_in;
+            let scaled = self.table[index].normalize(profit);
+            if best
+                .as_ref()
+                .is_none_or(|best| scaled > self.table[best.index].normalize(best.profit))
+            {
+                best = Some(Projected {
+                    index,
+                    amounts_out,
+                    protocols,
+                    profit,
+                });
+            }
+        }
+        best
+    }
+}
+
+#[async_trait]
+impl<M, P, S> Backrunner for ArbBackrunner<M, P, S>
+where
+    M: Middleware + Clone + 'static,
+    P: PubsubClient + 'static,
+    S: Signer + 'static,
+{
+    async fn backrun(&self, hint: &Hint) -> Option<Backrun> {
+        if !self.leader.as_ref().is_none_or(|leader| leader.is_leader()) {
+            return None;
+        }
+        let projected = projected_reserves(hint);
+        if projected.is_empty() {
+            return None;
+        }
+        let best = self.best_route(&projected).await?;
+        let entry = &self.table[best.index];
+        let route = &self.routes[best.index];
+
+        let client = self.executor.client();
+        let gas_price = match client.get_gas_price().await {
+            Ok(gas_price) => gas_price,
+            Err(e) => {
+                warn!("Failed to read the gas price: {}", e);
+                return None;
+            }
+        };
+        if !is_profitable(entry, best.profit, gas_price * entry.gas_estimate) {
+            debug!(
+                "Backrun of {:?} through {} not profitable, profit: {:?}",
+                hint.hash, entry.symbols, best.profit
+            );
+            return None;
+        }
+        let block = match client.get_block_number().await {
+            Ok(head) => head.as_u64() + 1,
+            Err(e) => {
+                warn!("Failed to read the head: {}", e);
+                return None;
+            }
+        };
+        // the node's, not one reserved from the guard: a bundle that doesn't
+        // land would leave a gap in the wallet's nonces
+        let nonce = match client
+            .get_transaction_count(client.address(), Some(BlockNumber::Pending.into()))
+            .await
+        {
+            Ok(nonce) => nonce,
+            Err(e) => {
+                warn!("Failed to read wallet nonce: {}", e);
+                return None;
+            }
+        };
+        let arb_route = ArbParamsBuilder::from_route(
+            route.amount_in,
+            &route.token_path,
+            &best.protocols,
+            &best.amounts_out,
+        )
+        .slippage_bps(ARB_SLIPPAGE_BPS)
+        .exact_output(self.exact_output)
+        .build();
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(client.address())
+            .to(self.executor.address())
+            .data(arb_route.calldata(&self.features, U256::from(block)))
+            .nonce(nonce)
+            .gas(entry.gas_estimate * (10_000 + GAS_LIMIT_HEADROOM_BPS) / 10_000)
+            .gas_price(gas_price)
+            .chain_id(client.signer().chain_id())
+            .into();
+        let signature = match client.signer().sign_transaction(&tx).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                warn!("Failed to sign backrun of {:?}: {}", hint.hash, e);
+                return None;
+            }
+        };
+        info!(
+            "Backrunning {:?} through {}, expected profit: {:?}, gas {:?}",
+            hint.hash, entry.symbols, best.profit, gas_price
+        );
+        Some(Backrun {
+            tx: tx.rlp_signed(&signature),
+            block,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::AbiEncode,
+        contract::EthEvent,
+        providers::Provider,
+        signers::LocalWallet,
+        types::{Bytes, Transaction, H256},
+        utils::rlp::{Decodable, Rlp},
+    };
+
+    use super::*;
+    use crate::{
+        constants::protocol::UniswapV2,
+        uniswapV2::SyncFilter,
+        utils::{batch::fake::FakeTransport, fixed_point::whole_units, mev_share::HintLog},
+    };
+
+    type Fake = Provider<FakeTransport>;
+
+    const USDC: ERC20Token = ERC20Token::USDC;
+    const WETH: ERC20Token = ERC20Token::WETH;
+
+    fn sync(pair: Address, reserves: (U256, U256)) -> HintLog {
+        HintLog {
+            address: pair,
+            topics: vec![SyncFilter::signature()],
+            data: reserves.encode().into(),
+        }
+    }
+
+    fn hint(logs: Vec<HintLog>) -> Hint {
+        Hint {
+            hash: H256::repeat_byte(7),
+            logs,
+            txs: Vec::new(),
+            mev_gas_price: None,
+            gas_used: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backrun() {
+        // WETH at 2000 USDC on both until the hinted txn dumps WETH on
+        // Sushiswap
+        let even = (
+            whole_units(2_000_000, USDC.get_decimals()).unwrap(),
+            whole_units(1_000, WETH.get_decimals()).unwrap(),
+        );
+        let dumped = (
+            whole_units(1_000_000, USDC.get_decimals()).unwrap(),
+            whole_units(2_000, WETH.get_decimals()).unwrap(),
+        );
+        let (sushiswap, quickswap) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        let ws = Arc::new(WorldState::from_pairs(
+            Arc::new(provider.clone()),
+            provider.clone(),
+            &[
+                (UniswapV2::SUSHISWAP, USDC, WETH, sushiswap, even),
+                (UniswapV2::QUICKSWAP, USDC, WETH, quickswap, even),
+            ],
+        ));
+        let routes = vec![Route {
+            amount_in: whole_units(1_000, USDC.get_decimals()).unwrap(),
+            token_path: vec![USDC, WETH, USDC],
+        }];
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64);
+        let client = Arc::new(SignerMiddleware::new(Arc::new(provider), wallet.clone()));
+        let executor = Address::repeat_byte(9);
+        let backrunner: ArbBackrunner<Fake, FakeTransport, LocalWallet> = ArbBackrunner::new(
+            ws,
+            routes.clone(),
+            Arc::new(RouteTable::new(&routes).unwrap()),
+            Flashloan::new(executor, client),
+            ExecutorFeatures::LEGACY,
+        );
+        transport.set_response("eth_gasPrice", "0x64");
+        transport.set_response("eth_blockNumber", "0x64");
+        transport.set_response("eth_getTransactionCount", "0x7");
+
+        // nothing revealed, or a pair moved without opening an arb
+        assert_eq!(backrunner.backrun(&hint(Vec::new())).await, None);
+        assert_eq!(
+            backrunner.backrun(&hint(vec![sync(sushiswap, even)])).await,
+            None
+        );
+
+        let hint = hint(vec![
+            sync(sushiswap, even),
+            sync(sushiswap, dumped),
+            sync(Address::repeat_byte(3), even),
+        ]);
+        assert_eq!(projected_reserves(&hint).len(), 2);
+        let backrun = backrunner.backrun(&hint).await.unwrap();
+        assert_eq!(backrun.block, 101);
+        let tx = Transaction::decode(&Rlp::new(&backrun.tx)).unwrap();
+        assert_eq!(tx.recover_from().unwrap(), wallet.address());
+        assert_eq!(tx.to, Some(executor));
+        assert_eq!(tx.nonce, 7.into());
+        assert_eq!(tx.gas_price, Some(100.into()));
+        assert_eq!(tx.gas, 625_000.into());
+        assert_ne!(tx.input, Bytes::default());
+    }
+}
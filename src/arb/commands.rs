@@ -0,0 +1,126 @@
+//! Sends the execution commands a detector elsewhere pushed to the bridge,
+//! see `bridge::push_command`. Each is sent in a span under the detector's
+//! so both halves end up in the same trace; one whose target block is
+//! already mined is dropped. Their receipts are left to the detector.
+
+use std::{sync::Arc, time::Duration};
+
+use ethers::{
+    prelude::SignerMiddleware,
+    providers::Middleware,
+    signers::Signer,
+    types::{transaction::eip2718::TypedTransaction, TransactionRequest},
+};
+use log::{debug, error, info};
+use tracing::Instrument;
+
+use crate::{
+    bridge::{command_span, next_command, Bridge, ExecutionCommand},
+    leader::LeaderLock,
+    resources::ResourceUsage,
+    schedule::ARB,
+    utils::nonce_guard::NonceGuard,
+};
+
+/// longest wait for a command, and between checks of the lock on standby
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct CommandExecutor<M, S> {
+    bridge: Arc<dyn Bridge>,
+    client: Arc<SignerMiddleware<Arc<M>, S>>,
+    nonces: Arc<NonceGuard>,
+    resources: Arc<ResourceUsage>,
+    /// only its holder pops commands if set, a standby would drop them
+    leader: Option<Arc<LeaderLock>>,
+}
+
+impl<M, S> CommandExecutor<M, S>
+where
+    M: Middleware + 'static,
+    S: Signer + 'static,
+{
+    pub fn new(
+        bridge: Arc<dyn Bridge>,
+        client: Arc<SignerMiddleware<Arc<M>, S>>,
+        nonces: Arc<NonceGuard>,
+        resources: Arc<ResourceUsage>,
+    ) -> Self {
+        Self {
+            bridge,
+            client,
+            nonces,
+            resources,
+            leader: None,
+        }
+    }
+
+    pub fn with_leader(mut self, leader: Arc<LeaderLock>) -> Self {
+        self.leader = Some(leader);
+        self
+    }
+
+    /// Sends every command popped from the bridge, until popping fails.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            if !self.leader.as_ref().is_none_or(|leader| leader.is_leader()) {
+                tokio::time::sleep(COMMAND_POLL_INTERVAL).await;
+                continue;
+            }
+            match next_command(self.bridge.as_ref(), COMMAND_POLL_INTERVAL).await {
+                Ok(Some(command)) => {
+                    let span = command_span(&command);
+                    self.execute(command).instrument(span).await;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to pop execution command: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn execute(&self, command: ExecutionCommand) {
+        match self.client.get_block_number().await {
+            Ok(head) if head.as_u64() >= command.target_block => {
+                debug!(
+                    "Dropping command of block {}, block {} is mined",
+                    command.block, command.target_block
+                );
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to read the head: {:?}", e),
+        }
+        let mut tx: TypedTransaction = TransactionRequest::new()
+            .to(command.to)
+            .data(command.calldata)
+            .gas(command.gas_limit)
+            .gas_price(command.gas_price)
+            .into();
+        // without one the middleware asks the node
+        let nonce = self.nonces.reserve();
+        if let Some(nonce) = nonce {
+            tx.set_nonce(nonce);
+        }
+        match self.client.send_transaction(tx, None).await {
+            Ok(pending) => {
+                let tx_hash = *pending;
+                if let Some(nonce) = nonce {
+                    self.nonces.sent(nonce, tx_hash);
+                }
+                self.resources.record_submission(ARB);
+                info!(
+                    "Command of block {} sent as {:?}, expected profit: {:?}",
+                    command.block, tx_hash, command.expected_profit
+                );
+            }
+            Err(e) => {
+                if let Some(nonce) = nonce {
+                    self.nonces.failed(nonce);
+                }
+                error!("Failed to send command of block {}: {}", command.block, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! Sends candidates through the flashloan executor, the first of each
+//! block only. A standby instance, one degraded for want of a fresh head
+//! and one over its gas budget sends nothing, and neither is anything sent
+//! into a block of a skipped producer; an EOA send that fails or
+//! would wait on a stuck nonce is resubmitted through the smart account.
+
+use std::{collections::HashSet, sync::Arc};
+
+use ethers::{
+    prelude::SignerMiddleware,
+    providers::Middleware,
+    signers::Signer,
+    types::{transaction::eip2718::TypedTransaction, Address, H256, U256},
+};
+use log::{debug, error, info, warn};
+use tracing::Instrument;
+
+use super::{unix_now, ArbCandidate, Submission, ARB_SLIPPAGE_BPS};
+use crate::{
+    arb_params::{ArbParamsBuilder, ExecutorFeatures, Flashloan},
+    bus::{next, Bus},
+    gas_budget::GasBudgets,
+    in_flight::{InFlight, InFlightTx},
+    lag::Lag,
+    leader::LeaderLock,
+    resources::ResourceUsage,
+    schedule::ARB,
+    utils::{
+        broadcast::TieredSender, nonce_guard::NonceGuard, submitter::Submitter,
+        user_op::UserOpSubmitter,
+    },
+};
+
+pub struct Dispatcher<M, S> {
+    client: Arc<SignerMiddleware<Arc<M>, S>>,
+    executor: Flashloan<SignerMiddleware<Arc<M>, S>>,
+    features: ExecutorFeatures,
+    nonces: Arc<NonceGuard>,
+    submitter: Arc<Submitter<Arc<M>, S>>,
+    resources: Arc<ResourceUsage>,
+    gas_budgets: Arc<GasBudgets>,
+    exact_output: bool,
+    /// also sent to the relays if set
+    tiered: Option<TieredSender>,
+    /// resubmits through the smart account if set
+    user_ops: Option<UserOpSubmitter<M>>,
+    /// only its holder sends if set
+    leader: Option<Arc<LeaderLock>>,
+    in_flight: Option<InFlight>,
+    /// bor producers whose blocks nothing is sent into
+    skip_producers: HashSet<Address>,
+}
+
+impl<M, S> Dispatcher<M, S>
+where
+    M: Middleware + 'static,
+    S: Signer + 'static,
+{
+    pub fn new(
+        executor: Flashloan<SignerMiddleware<Arc<M>, S>>,
+        features: ExecutorFeatures,
+        nonces: Arc<NonceGuard>,
+        submitter: Arc<Submitter<Arc<M>, S>>,
+        resources: Arc<ResourceUsage>,
+        gas_budgets: Arc<GasBudgets>,
+    ) -> Self {
+        Self {
+            client: executor.client(),
+            executor,
+            features,
+            nonces,
+            submitter,
+            resources,
+            gas_budgets,
+            exact_output: false,
+            tiered: None,
+            user_ops: None,
+            leader: None,
+            in_flight: None,
+            skip_producers: HashSet::new(),
+        }
+    }
+
+    /// buys back exactly the loan on the last hop
+    pub fn with_exact_output(mut self, exact_output: bool) -> Self {
+        self.exact_output = exact_output;
+        self
+    }
+
+    pub fn with_tiered(mut self, tiered: TieredSender) -> Self {
+        self.tiered = Some(tiered);
+        self
+    }
+
+    pub fn with_user_ops(mut self, user_ops: UserOpSubmitter<M>) -> Self {
+        self.user_ops = Some(user_ops);
+        self
+    }
+
+    pub fn with_leader(mut self, leader: Arc<LeaderLock>) -> Self {
+        self.leader = Some(leader);
+        self
+    }
+
+    /// records every txn sent in `in_flight` until its receipt is handled
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+
+    /// sends nothing while one of `producers` is due to produce the next
+    /// block, e.g. ones known to reorder or drop arbs
+    pub fn with_skip_producers(mut self, producers: impl IntoIterator<Item = Address>) -> Self {
+        self.skip_producers = producers.into_iter().collect();
+        self
+    }
+
+    /// Sends the candidates on `bus`, publishing what's sent to
+    /// `Bus::submissions` and every outcome to `Bus::opportunities`. Stops
+    /// sending while the head lag is degraded, cancelling the arbs in
+    /// flight and outbidding the cancellations pending until it resumes.
+    pub async fn run(self: Arc<Self>, bus: Arc<Bus>) {
+        let mut candidates = bus.candidates.subscribe();
+        let mut lag = bus.lag.subscribe();
+        let mut degraded = false;
+        let mut leading = true;
+        // one arb per block
+        let mut sent_at = None;
+        loop {
+            tokio::select! {
+                // published before the quotes of the head that caused it
+                biased;
+                Some(lag) = next(&mut lag, "lag") => match lag {
+                    Lag::Degraded { .. } => {
+                        degraded = true;
+                        self.rebid_cancellations().await;
+                        self.cancel_in_flight().await;
+                    }
+                    Lag::Resumed { .. } => degraded = false,
+                    _ => {}
+                },
+                Some(candidate) = next(&mut candidates, "candidates") => {
+                    // the instance that held the lock may have used nonces since
+                    let now_leading = self.leader.as_ref().is_none_or(|leader| leader.is_leader());
+                    if now_leading && !leading {
+                        if let Err(e) = self.nonces.resync(self.client.inner().as_ref()).await {
+                            error!("Failed to read wallet nonce: {:?}", e);
+                        }
+                    }
+                    leading = now_leading;
+
+                    let outcome = if sent_at == Some(candidate.block) {
+                        debug!("  Already sent an arb this block");
+                        Some("superseded")
+                    } else if !leading {
+                        debug!("  Standing by, another instance holds the lock");
+                        Some("standby")
+                    } else if degraded {
+                        debug!("  Degraded, no fresh head to land on");
+                        Some("degraded")
+                    } else if let Some(producer) = candidate
+                        .producer
+                        .filter(|producer| self.skip_producers.contains(producer))
+                    {
+                        debug!("  Next block produced by skipped {:?}", producer);
+                        Some("skipped_producer")
+                    } else {
+                        None
+                    };
+                    if let Some(outcome) = outcome {
+                        bus.opportunities.publish(candidate.outcome(outcome, None));
+                        continue;
+                    }
+                    if self.dispatch(candidate.clone(), &bus).await {
+                        sent_at = Some(candidate.block);
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+
+    /// whether `candidate` was sent
+    async fn dispatch(&self, candidate: ArbCandidate, bus: &Bus) -> bool {
+        let arb_route = ArbParamsBuilder::from_route(
+            candidate.route.amount_in,
+            &candidate.route.token_path,
+            &candidate.protocols,
+            &candidate.amounts_out,
+        )
+        .slippage_bps(ARB_SLIPPAGE_BPS)
+        .exact_output(self.exact_output)
+        .build();
+
+        let target_block_number = U256::from(candidate.block + 1);
+        let mut contract_call = self
+            .executor
+            .execute_arbitrage(arb_route.params.clone(), target_block_number);
+        contract_call
+            .tx
+            .set_data(arb_route.calldata(&self.features, target_block_number));
+        let calldata = contract_call.calldata().unwrap_or_default();
+        let gas_price = candidate.gas_price;
+        if self.gas_budgets.is_enabled() {
+            // one that won't estimate fails the send below anyway
+            if let Ok(gas) = contract_call.estimate_gas().await {
+                contract_call.tx.set_gas(gas);
+                for alert in self.gas_budgets.sync(unix_now()).await {
+                    bus.budgets.publish(alert);
+                }
+                if let Some(e) = self.gas_budgets.exceeded(
+                    self.client.address(),
+                    ARB,
+                    gas * gas_price,
+                    unix_now(),
+                ) {
+                    bus.opportunities
+                        .publish(candidate.outcome("over_budget", None));
+                    warn!("  Skipping, {}", e);
+                    return false;
+                }
+            }
+        }
+        // the wallet's nonce is held up by a txn being cancelled, the smart
+        // account doesn't wait on it
+        let stuck = self.user_ops.is_some() && !self.submitter.pending().is_empty();
+        // without one the middleware asks the node
+        let nonce = if stuck { None } else { self.nonces.reserve() };
+        if let Some(nonce) = nonce {
+            contract_call.tx.set_nonce(nonce);
+        }
+        let contract_call = contract_call.gas_price(gas_price);
+        let sent = async {
+            if stuck {
+                return Err("wallet nonce stuck behind a cancellation".to_string());
+            }
+            match &self.tiered {
+                Some(tiered) => self.send_tiered(tiered, contract_call.tx.clone()).await,
+                None => contract_call
+                    .send()
+                    .await
+                    .map(|pending| *pending)
+                    .map_err(|e| e.to_string()),
+            }
+        }
+        .instrument(candidate.span.clone())
+        .await;
+
+        match sent {
+            Ok(tx_hash) => {
+                if let Some(nonce) = nonce {
+                    self.nonces.sent(nonce, tx_hash);
+                }
+                self.resources.record_submission(ARB);
+                bus.opportunities
+                    .publish(candidate.outcome("submitted", Some(tx_hash)));
+                if let Some(in_flight) = &self.in_flight {
+                    let record = InFlightTx {
+                        hash: tx_hash,
+                        nonce,
+                        gas_price,
+                        block: candidate.block,
+                        token: candidate.token().get_symbol().to_string(),
+                        profit: candidate.profit,
+                    };
+                    if let Err(e) = in_flight.sent(&record).await {
+                        error!("  Failed to record txn in flight: {:?}", e);
+                    }
+                }
+                info!(
+                    "  Txn {:?} submitted, expected profit: {:?}, gas {:?}",
+                    tx_hash, candidate.profit, gas_price
+                );
+                info!("  ({}), {}", candidate.index, candidate.key());
+                bus.submissions.publish(Submission {
+                    candidate,
+                    tx_hash,
+                    nonce,
+                });
+                true
+            }
+            Err(e) => {
+                if let Some(nonce) = nonce {
+                    self.nonces.failed(nonce);
+                }
+                if let Some(user_ops) = &self.user_ops {
+                    debug!("  EOA send failed ({}), resubmitting as user op", e);
+                    match user_ops
+                        .submit(self.executor.address(), calldata, gas_price, gas_price)
+                        .instrument(candidate.span.clone())
+                        .await
+                    {
+                        Ok(op_hash) => {
+                            self.resources.record_submission(ARB);
+                            bus.opportunities
+                                .publish(candidate.outcome("submitted_user_op", Some(op_hash)));
+                            info!("  User op {:?} submitted", op_hash);
+                            return true;
+                        }
+                        Err(e) => error!("  User op submission failed: {:?}", e),
+                    }
+                }
+                bus.opportunities
+                    .publish(candidate.outcome("send_failed", None));
+                error!(
+                    "  Err received in sending txn. Expected profit: {:?}, Route: ({}) {}",
+                    candidate.profit,
+                    candidate.index,
+                    candidate.key()
+                );
+                false
+            }
+        }
+    }
+
+    /// signs `tx` and sends it to the node, the relays get it in the
+    /// background
+    async fn send_tiered(
+        &self,
+        tiered: &TieredSender,
+        mut tx: TypedTransaction,
+    ) -> Result<H256, String> {
+        self.client
+            .fill_transaction(&mut tx, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        let signature = self
+            .client
+            .signer()
+            .sign_transaction(&tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        tiered
+            .send(tx.rlp_signed(&signature))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Outbids every cancellation still pending, for the wallet's nonces to
+    /// settle first once blocks are produced again.
+    async fn rebid_cancellations(&self) {
+        if let Err(e) = self.submitter.reconcile().await {
+            error!("{}", e);
+        }
+        for cancellation in self.submitter.pending() {
+            match self.submitter.cancel(cancellation.nonce).await {
+                Ok(cancellation) => info!(
+                    "Cancellation of nonce {} rebid with {:?} at gas price {}",
+                    cancellation.nonce, cancellation.hash, cancellation.gas_price
+                ),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
+    /// Cancels every arb in flight not being cancelled yet. Its target block
+    /// is long past by the time blocks are produced again, and it would
+    /// only hold up the wallet's nonces.
+    async fn cancel_in_flight(&self) {
+        let in_flight = match &self.in_flight {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+        let txs = match in_flight.all().await {
+            Ok(txs) => txs,
+            Err(e) => {
+                error!("Failed to read txns in flight: {:?}", e);
+                return;
+            }
+        };
+        for tx in txs {
+            if self.submitter.is_cancelling(tx.hash) {
+                continue;
+            }
+            // the middleware picked it
+            let nonce = match tx.nonce {
+                Some(nonce) => nonce,
+                None => match self.client.get_transaction(tx.hash).await {
+                    Ok(Some(known)) => known.nonce,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("Failed to read the nonce of txn {:?}: {}", tx.hash, e);
+                        continue;
+                    }
+                },
+            };
+            self.submitter.track(nonce, tx.hash, tx.gas_price);
+            match self.submitter.cancel(nonce).await {
+                Ok(cancellation) => info!(
+                    "Arb {:?} in flight, cancelling nonce {} with {:?} at gas price {}",
+                    tx.hash, nonce, cancellation.hash, cancellation.gas_price
+                ),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{routing::post, Json, Router};
+    use ethers::{
+        abi::AbiEncode,
+        providers::{Http, Provider},
+        signers::LocalWallet,
+    };
+    use serde_json::{json, Value};
+    use tracing::Span;
+
+    use super::*;
+    use crate::{
+        constants::{protocol::UniswapV2, token::ERC20Token},
+        export::OpportunityRecord,
+        gas_budget::BudgetConfig,
+        leader::FileLock,
+        routes::Route,
+        utils::batch::fake::FakeTransport,
+        world::Protocol,
+    };
+
+    type Fake = Provider<FakeTransport>;
+
+    fn wallet() -> LocalWallet {
+        "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64)
+    }
+
+    /// a dispatcher whose sends all go through as `H256::repeat_byte(1)`
+    fn dispatcher(transport: &FakeTransport) -> Dispatcher<Fake, LocalWallet> {
+        let wallet = wallet();
+        let nonces = Arc::new(NonceGuard::new(wallet.address()));
+        let client = Arc::new(SignerMiddleware::new(
+            Arc::new(Provider::new(transport.clone())),
+            wallet,
+        ));
+        transport.set_response("eth_getTransactionCount", "0x7");
+        transport.set_response("eth_estimateGas", "0x30d40");
+        transport.set_response("eth_gasPrice", "0x64");
+        transport.set_response("eth_sendRawTransaction", H256::repeat_byte(1));
+        Dispatcher::new(
+            Flashloan::new(Address::repeat_byte(9), client.clone()),
+            ExecutorFeatures::LEGACY,
+            nonces.clone(),
+            Arc::new(Submitter::new(client, nonces)),
+            Arc::new(ResourceUsage::new()),
+            Arc::new(GasBudgets::new(BudgetConfig::default())),
+        )
+    }
+
+    fn candidate(block: u64, producer: Option<Address>) -> ArbCandidate {
+        let amounts_out = vec![U256::from(10).pow(18.into()), U256::from(1_100_000)];
+        ArbCandidate {
+            block,
+            index: 0,
+            symbols: "USDC>WETH>USDC".to_string(),
+            route: Route {
+                amount_in: U256::from(1_000_000),
+                token_path: vec![ERC20Token::USDC, ERC20Token::WETH, ERC20Token::USDC],
+            },
+            protocols: vec![
+                Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+                Protocol::UniswapV2(UniswapV2::QUICKSWAP),
+            ],
+            quoted: amounts_out.clone(),
+            amounts_out,
+            profit: U256::from(100_000),
+            gas_price: U256::from(100),
+            producer,
+            span: Span::none(),
+        }
+    }
+
+    /// runs `dispatcher` on a fresh bus, with a receiver of its outcomes
+    async fn run(
+        dispatcher: Dispatcher<Fake, LocalWallet>,
+    ) -> (
+        Arc<Bus>,
+        tokio::sync::broadcast::Receiver<OpportunityRecord>,
+    ) {
+        let bus = Arc::new(Bus::default());
+        let opportunities = bus.opportunities.subscribe();
+        tokio::spawn(Arc::new(dispatcher).run(bus.clone()));
+        while bus.candidates.subscribers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        (bus, opportunities)
+    }
+
+    async fn outcome(
+        opportunities: &mut tokio::sync::broadcast::Receiver<OpportunityRecord>,
+    ) -> String {
+        opportunities.recv().await.unwrap().outcome
+    }
+
+    #[tokio::test]
+    async fn test_one_send_per_block() {
+        let transport = FakeTransport::new();
+        let (bus, mut opportunities) = run(dispatcher(&transport)).await;
+        bus.candidates.publish(candidate(5, None));
+        assert_eq!(outcome(&mut opportunities).await, "submitted");
+        bus.candidates.publish(candidate(5, None));
+        assert_eq!(outcome(&mut opportunities).await, "superseded");
+        bus.candidates.publish(candidate(6, None));
+        assert_eq!(outcome(&mut opportunities).await, "submitted");
+    }
+
+    #[tokio::test]
+    async fn test_standby() {
+        let transport = FakeTransport::new();
+        let dir = std::env::temp_dir().join(format!("tsuki-standby-{}", std::process::id()));
+        // never heartbeated, so never held
+        let leader = LeaderLock::new(
+            Box::new(FileLock::new(&dir).unwrap()),
+            "wallet",
+            "a",
+            Duration::from_secs(10),
+        );
+        let (bus, mut opportunities) =
+            run(dispatcher(&transport).with_leader(Arc::new(leader))).await;
+        bus.candidates.publish(candidate(5, None));
+        assert_eq!(outcome(&mut opportunities).await, "standby");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_degraded() {
+        let transport = FakeTransport::new();
+        let (bus, mut opportunities) = run(dispatcher(&transport)).await;
+        bus.lag.publish(Lag::Degraded {
+            last_block: Some(4),
+            secs: 30,
+        });
+        bus.candidates.publish(candidate(5, None));
+        assert_eq!(outcome(&mut opportunities).await, "degraded");
+        bus.lag.publish(Lag::Resumed { block: 6, secs: 40 });
+        bus.candidates.publish(candidate(6, None));
+        assert_eq!(outcome(&mut opportunities).await, "submitted");
+    }
+
+    #[tokio::test]
+    async fn test_skipped_producer() {
+        let transport = FakeTransport::new();
+        let skipped = Address::repeat_byte(3);
+        let (bus, mut opportunities) =
+            run(dispatcher(&transport).with_skip_producers([skipped])).await;
+        bus.candidates.publish(candidate(5, Some(skipped)));
+        assert_eq!(outcome(&mut opportunities).await, "skipped_producer");
+        bus.candidates
+            .publish(candidate(5, Some(Address::repeat_byte(4))));
+        assert_eq!(outcome(&mut opportunities).await, "submitted");
+    }
+
+    /// answers the gas estimate and the send of any user op
+    async fn bundler(Json(request): Json<Value>) -> Json<Value> {
+        let result = match request["method"].as_str() {
+            Some("eth_estimateUserOperationGas") => json!({
+                "preVerificationGas": "0xc350",
+                "verificationGasLimit": "0x186a0",
+                "callGasLimit": "0x30d40",
+            }),
+            _ => json!(H256::repeat_byte(2)),
+        };
+        Json(json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+    }
+
+    #[tokio::test]
+    async fn test_user_op_when_stuck() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/", post(bundler));
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        let transport = FakeTransport::new();
+        let user_ops = UserOpSubmitter::new(
+            Arc::new(Provider::new(transport.clone())),
+            Provider::<Http>::try_from(url.as_str()).unwrap(),
+            wallet(),
+            Address::repeat_byte(5),
+        );
+        let dispatcher = dispatcher(&transport).with_user_ops(user_ops);
+        // nonce 7 is held up by its cancellation
+        dispatcher
+            .submitter
+            .track(7.into(), H256::repeat_byte(3), 100.into());
+        dispatcher.submitter.cancel(7.into()).await.unwrap();
+        // the account's entry point nonce
+        transport.set_response("eth_call", U256::zero().encode_hex());
+        transport.clear_requests();
+
+        let (bus, mut opportunities) = run(dispatcher).await;
+        let mut submissions = bus.submissions.subscribe();
+        bus.candidates.publish(candidate(5, None));
+        let record = opportunities.recv().await.unwrap();
+        assert_eq!(record.outcome, "submitted_user_op");
+        assert_eq!(record.tx_hash, Some(H256::repeat_byte(2)));
+        // nothing went out from the wallet
+        assert!(submissions.try_recv().is_err());
+        assert!(!transport
+            .requests()
+            .iter()
+            .any(|(method, _)| method == "eth_sendRawTransaction"));
+    }
+}
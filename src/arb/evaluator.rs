@@ -0,0 +1,547 @@
+//! Turns route quotes into candidates: caps routes short of thin V3
+//! liquidity, discounts venues by their quote bias, and drops routes on
+//! cooldown, unprofitable at the pool's gas price, vetoed by a filter or
+//! gone once their pools are reloaded.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ethers::{
+    providers::{Middleware, PubsubClient},
+    types::U256,
+};
+use log::{debug, info};
+use tracing::{field, info_span, Instrument};
+
+use super::{
+    is_profitable, opportunity_record, route_key, usd, ArbCandidate, Feedback, RouteQuote,
+};
+use crate::{
+    bus::{next, Bus},
+    heatmap::RouteHeatmap,
+    opportunity_filter::{Candidate, OpportunityFilters, Verdict},
+    price_index::PriceIndex,
+    routes::Route,
+    tx_pool::TxPool,
+    utils::route_health::Standing,
+    warmup::RouteTable,
+    world::{Protocol, WorldState},
+};
+
+/// added to the 90th percentile gas price of the pool to bid over it
+const GAS_PRICE_BUMP: u64 = 100;
+
+pub struct Evaluator<M, P> {
+    ws: Arc<WorldState<M, P>>,
+    txpool: Arc<TxPool<M>>,
+    routes: Vec<Route>,
+    table: Arc<RouteTable>,
+    prices: Arc<PriceIndex>,
+    feedback: Arc<Feedback>,
+    heatmap: Option<Arc<RouteHeatmap>>,
+    filters: OpportunityFilters,
+    /// wait before requoting against reloaded reserves, `None` trusts the
+    /// first quote
+    confirm_delay: Option<Duration>,
+    /// share of a tick range's liquidity under which V3 hops are capped, 0
+    /// never caps them
+    v3_thin_ratio: f64,
+}
+
+impl<M, P> Evaluator<M, P>
+where
+    M: Middleware + Clone + 'static,
+    P: PubsubClient + 'static,
+{
+    pub fn new(
+        ws: Arc<WorldState<M, P>>,
+        txpool: Arc<TxPool<M>>,
+        routes: Vec<Route>,
+        table: Arc<RouteTable>,
+        prices: Arc<PriceIndex>,
+        feedback: Arc<Feedback>,
+    ) -> Self {
+        Self {
+            ws,
+            txpool,
+            routes,
+            table,
+            prices,
+            feedback,
+            heatmap: None,
+            filters: OpportunityFilters::new(),
+            confirm_delay: None,
+            v3_thin_ratio: 0.0,
+        }
+    }
+
+    /// records the edge of every quote in `heatmap`
+    pub fn with_heatmap(mut self, heatmap: Arc<RouteHeatmap>) -> Self {
+        self.heatmap = Some(heatmap);
+        self
+    }
+
+    pub fn with_filters(mut self, filters: OpportunityFilters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// requotes profitable routes against reserves reloaded after `delay`
+    pub fn with_confirm_delay(mut self, delay: Duration) -> Self {
+        self.confirm_delay = Some(delay);
+        self
+    }
+
+    pub fn with_v3_thin_ratio(mut self, ratio: f64) -> Self {
+        self.v3_thin_ratio = ratio;
+        self
+    }
+
+    /// Evaluates every quote on `bus`, publishing the candidates and the
+    /// opportunities dropped.
+    pub async fn run(self: Arc<Self>, bus: Arc<Bus>) {
+        let mut quotes = bus.quotes.subscribe();
+        while let Some(quote) = next(&mut quotes, "quotes").await {
+            if let Some(candidate) = self.evaluate(quote, &bus).await {
+                bus.candidates.publish(candidate);
+            }
+        }
+    }
+
+    async fn evaluate(&self, quote: RouteQuote, bus: &Bus) -> Option<ArbCandidate> {
+        let i = quote.route;
+        let entry = &self.table[i];
+        let token = self.routes[i].token_path[0];
+        let mut route = self.routes[i].clone();
+        let (mut amounts_out, mut protocol_route) = (quote.amounts_out, quote.protocols);
+        if quote.amount_in < route.amount_in {
+            debug!(
+                "  Route {} capped at its depth, {} of {}",
+                i, quote.amount_in, route.amount_in
+            );
+            route.amount_in = quote.amount_in;
+        }
+        let est_amount_out = amounts_out.last().copied().unwrap_or_default();
+        if est_amount_out > route.amount_in && self.v3_thin_ratio > 0.0 {
+            if let Some(constraint) = self
+                .ws
+                .tick_constraint(
+                    &route.token_path,
+                    route.amount_in,
+                    &amounts_out,
+                    &protocol_route,
+                    self.v3_thin_ratio,
+                )
+                .await
+            {
+                info!(
+                    "  Route {} hop {} (UniswapV3 {}) would cross into thin liquidity at tick {} ({} of {} in range), capping {} to {}",
+                    i,
+                    constraint.hop,
+                    constraint.fee,
+                    constraint.boundary.tick,
+                    constraint.boundary.amount_in,
+                    constraint.hop_amount_in,
+                    route.amount_in,
+                    constraint.capped_amount_in
+                );
+                route.amount_in = constraint.capped_amount_in;
+                (amounts_out, protocol_route) = self
+                    .ws
+                    .clone()
+                    .compute_best_route_hops(route.token_path.to_vec(), route.amount_in)
+                    .await;
+            }
+        }
+        // venues that deliver less than they quote are discounted
+        let mut quoted = amounts_out.clone();
+        amounts_out = self.adjust(&protocol_route, &quoted);
+        let est_amount_out = amounts_out.last().copied().unwrap_or_default();
+        let amount_in = route.amount_in;
+        if let Some(heatmap) = self.heatmap.as_ref().filter(|_| !amounts_out.is_empty()) {
+            heatmap.record(
+                &entry.symbols,
+                self.routes[i].amount_in,
+                RouteHeatmap::edge_bps(amount_in, est_amount_out),
+                quote.timestamp,
+            );
+        }
+        if est_amount_out <= amount_in {
+            return None;
+        }
+
+        let profit = est_amount_out - amount_in;
+        let span = info_span!(
+            parent: &quote.span,
+            "opportunity",
+            route = i,
+            profit = %profit,
+            outcome = field::Empty,
+        );
+        let skip = |route: &Route, profit: U256, outcome: &str| {
+            span.record("outcome", outcome);
+            bus.opportunities.publish(opportunity_record(
+                quote.block,
+                route,
+                &entry.symbols,
+                profit,
+                outcome,
+                None,
+            ));
+        };
+
+        let key = route_key(&entry.symbols, &protocol_route);
+        let standing = self
+            .feedback
+            .health
+            .lock()
+            .unwrap()
+            .standing(&key, Instant::now());
+        let success_rate = match standing {
+            Standing::Active { success_rate } => success_rate,
+            Standing::Cooldown { until } => {
+                skip(&route, profit, "cooldown");
+                debug!(
+                    "  Route {} on cooldown for {:?}",
+                    key,
+                    until.saturating_duration_since(Instant::now())
+                );
+                return None;
+            }
+        };
+        // routes that revert often are only worth it for more profit
+        let expected = |profit: U256| {
+            profit * U256::from((success_rate * 10_000.0) as u64) / U256::from(10_000)
+        };
+
+        let gas_price =
+            self.txpool.get_90th_percentile_gas_price().await + U256::from(GAS_PRICE_BUMP);
+        let txn_fees = gas_price.checked_mul(entry.gas_estimate).unwrap();
+        if !is_profitable(entry, expected(profit), txn_fees) {
+            skip(&route, profit, "unprofitable");
+            debug!(
+                "  Arb not profitable, fee: {:?}, profit: {:?}",
+                gas_price, profit
+            );
+            return None;
+        }
+        let candidate = Candidate {
+            block: quote.block,
+            route: entry.symbols.clone(),
+            protocols: protocol_route.iter().map(ToString::to_string).collect(),
+            token: token.get_symbol().to_string(),
+            decimals: token.get_decimals(),
+            amount_in,
+            profit,
+            profit_usd: usd(&self.prices, token, profit),
+            gas_price,
+            success_rate,
+        };
+        let (amount_in, profit) = match self.filters.check(&candidate) {
+            Verdict::Accept => (amount_in, profit),
+            Verdict::Veto(reason) => {
+                skip(&route, profit, "vetoed");
+                debug!("  Vetoed by {}", reason);
+                return None;
+            }
+            Verdict::Resize(resized) => {
+                debug!("  Resized from {} to {}", amount_in, resized);
+                route.amount_in = resized;
+                (quoted, protocol_route) = self
+                    .ws
+                    .clone()
+                    .compute_best_route_hops(route.token_path.to_vec(), resized)
+                    .await;
+                amounts_out = self.adjust(&protocol_route, &quoted);
+                let profit = amounts_out
+                    .last()
+                    .copied()
+                    .unwrap_or_default()
+                    .saturating_sub(resized);
+                if !is_profitable(entry, expected(profit), txn_fees) {
+                    skip(&route, profit, "unprofitable");
+                    debug!("  Arb not profitable once resized, profit: {:?}", profit);
+                    return None;
+                }
+                (resized, profit)
+            }
+        };
+        // the opportunity has to survive a second look at the route's pools,
+        // reloaded from the node
+        let (profit, amounts_out) = match self.confirm_delay {
+            Some(delay) => {
+                let confirmed = self
+                    .ws
+                    .clone()
+                    .confirm_route(&route.token_path, amount_in, &protocol_route, delay)
+                    .instrument(info_span!(parent: &span, "requote"))
+                    .await
+                    .map(|requoted| {
+                        let amounts_out = self.adjust(&protocol_route, &requoted);
+                        quoted = requoted;
+                        let amount_out = amounts_out.last().copied().unwrap_or_default();
+                        (amount_out.saturating_sub(amount_in), amounts_out)
+                    })
+                    .filter(|(profit, _)| is_profitable(entry, expected(*profit), txn_fees));
+                match confirmed {
+                    Some(confirmed) => confirmed,
+                    None => {
+                        skip(&route, profit, "phantom");
+                        debug!("  Route {} gone once its pools were reloaded", i);
+                        return None;
+                    }
+                }
+            }
+            None => (profit, amounts_out),
+        };
+        self.ws.mark_profitable(&route.token_path, &protocol_route);
+        Some(ArbCandidate {
+            block: quote.block,
+            index: i,
+            symbols: entry.symbols.clone(),
+            route,
+            protocols: protocol_route,
+            quoted,
+            amounts_out,
+            profit,
+            gas_price,
+            producer: quote.producer,
+            span,
+        })
+    }
+
+    fn adjust(&self, protocols: &[Protocol], quoted: &[U256]) -> Vec<U256> {
+        self.feedback
+            .quote_bias
+            .lock()
+            .unwrap()
+            .adjust(protocols, quoted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        signers::{LocalWallet, Signer},
+        types::{transaction::eip2718::TypedTransaction, Address, TransactionRequest},
+    };
+    use tracing::Span;
+
+    use super::*;
+    use crate::{
+        constants::{protocol::UniswapV2, token::ERC20Token},
+        utils::{batch::fake::FakeTransport, fixed_point::whole_units},
+        world::{no_v3_quotes, reserves_reply},
+    };
+
+    type Fake = Provider<FakeTransport>;
+
+    const USDC: ERC20Token = ERC20Token::USDC;
+    const WETH: ERC20Token = ERC20Token::WETH;
+
+    struct Harness {
+        transport: FakeTransport,
+        ws: Arc<WorldState<Fake, FakeTransport>>,
+        txpool: Arc<TxPool<Fake>>,
+        routes: Vec<Route>,
+        table: Arc<RouteTable>,
+        feedback: Arc<Feedback>,
+        bus: Bus,
+        /// WETH at 1000 USDC on Sushiswap and 2000 on Quickswap
+        cheap: (U256, U256),
+        dear: (U256, U256),
+    }
+
+    impl Harness {
+        async fn new() -> Self {
+            let cheap = (
+                whole_units(1_000_000, USDC.get_decimals()).unwrap(),
+                whole_units(1_000, WETH.get_decimals()).unwrap(),
+            );
+            let dear = (
+                whole_units(2_000_000, USDC.get_decimals()).unwrap(),
+                whole_units(1_000, WETH.get_decimals()).unwrap(),
+            );
+            let transport = FakeTransport::new();
+            // every V3 quote reverts
+            transport.set_response("eth_call", no_v3_quotes());
+            let provider = Provider::new(transport.clone());
+            let ws = Arc::new(WorldState::from_pairs(
+                Arc::new(provider.clone()),
+                provider.clone(),
+                &[
+                    (
+                        UniswapV2::SUSHISWAP,
+                        USDC,
+                        WETH,
+                        Address::repeat_byte(1),
+                        cheap,
+                    ),
+                    (
+                        UniswapV2::QUICKSWAP,
+                        USDC,
+                        WETH,
+                        Address::repeat_byte(2),
+                        dear,
+                    ),
+                ],
+            ));
+            // the pool bids 30 gwei
+            let txpool = Arc::new(TxPool::init(Arc::new(provider), 16));
+            let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse::<LocalWallet>()
+                .unwrap()
+                .with_chain_id(137u64);
+            let tx: TypedTransaction = TransactionRequest::new()
+                .to(Address::repeat_byte(9))
+                .nonce(0)
+                .gas(21_000)
+                .gas_price(30_000_000_000u64)
+                .chain_id(137)
+                .into();
+            let signature = wallet.sign_transaction_sync(&tx);
+            txpool.insert_raw(&tx.rlp_signed(&signature)).await.unwrap();
+            let routes = vec![Route {
+                amount_in: whole_units(1_000, USDC.get_decimals()).unwrap(),
+                token_path: vec![USDC, WETH, USDC],
+            }];
+            Self {
+                transport,
+                ws,
+                txpool,
+                table: Arc::new(RouteTable::new(&routes).unwrap()),
+                routes,
+                feedback: Arc::new(Feedback::default()),
+                bus: Bus::default(),
+                cheap,
+                dear,
+            }
+        }
+
+        fn evaluator(&self) -> Evaluator<Fake, FakeTransport> {
+            Evaluator::new(
+                self.ws.clone(),
+                self.txpool.clone(),
+                self.routes.clone(),
+                self.table.clone(),
+                Arc::new(PriceIndex::new(USDC, 16)),
+                self.feedback.clone(),
+            )
+        }
+
+        /// the route quoted at `amount_in` against the cached reserves
+        async fn quote(&self, amount_in: U256) -> RouteQuote {
+            let (amounts_out, protocols) = self
+                .ws
+                .clone()
+                .compute_best_route_hops(self.routes[0].token_path.clone(), amount_in)
+                .await;
+            RouteQuote {
+                block: 1,
+                timestamp: 0,
+                route: 0,
+                amount_in,
+                amounts_out,
+                protocols,
+                producer: None,
+                span: Span::none(),
+            }
+        }
+
+        /// evaluates the route's quote, the node answering a reload of its
+        /// pools with `reloaded`
+        async fn evaluate(
+            &self,
+            evaluator: &Evaluator<Fake, FakeTransport>,
+            reloaded: Option<&[(U256, U256)]>,
+        ) -> (Option<ArbCandidate>, Option<String>) {
+            let mut opportunities = self.bus.opportunities.subscribe();
+            let quote = self.quote(self.routes[0].amount_in).await;
+            if let Some(reserves) = reloaded {
+                self.transport
+                    .push_response("eth_call", reserves_reply(reserves));
+            }
+            let candidate = evaluator.evaluate(quote, &self.bus).await;
+            let outcome = opportunities.try_recv().ok().map(|record| record.outcome);
+            (candidate, outcome)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_evaluate() {
+        let harness = Harness::new().await;
+        let (candidate, outcome) = harness.evaluate(&harness.evaluator(), None).await;
+        let candidate = candidate.unwrap();
+        assert_eq!(outcome, None);
+        assert_eq!(candidate.route.amount_in, harness.routes[0].amount_in);
+        assert_eq!(candidate.quoted, candidate.amounts_out);
+        assert!(candidate.profit > whole_units(100, USDC.get_decimals()).unwrap());
+        assert_eq!(candidate.gas_price, U256::from(30_000_000_100u64));
+    }
+
+    #[tokio::test]
+    async fn test_cooldown() {
+        let harness = Harness::new().await;
+        let quote = harness.quote(harness.routes[0].amount_in).await;
+        let key = route_key(&harness.table[0].symbols, &quote.protocols);
+        let now = Instant::now();
+        for _ in 0..3 {
+            harness
+                .feedback
+                .health
+                .lock()
+                .unwrap()
+                .record(key.clone(), true, now);
+        }
+        let (candidate, outcome) = harness.evaluate(&harness.evaluator(), None).await;
+        assert!(candidate.is_none());
+        assert_eq!(outcome.as_deref(), Some("cooldown"));
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let harness = Harness::new().await;
+        let veto = |_: &Candidate| Verdict::Veto("too big".to_string());
+        let evaluator = harness
+            .evaluator()
+            .with_filters(OpportunityFilters::new().register("size", veto));
+        let (candidate, outcome) = harness.evaluate(&evaluator, None).await;
+        assert!(candidate.is_none());
+        assert_eq!(outcome.as_deref(), Some("vetoed"));
+
+        // requoted at the size the filter leaves it at
+        let half = harness.routes[0].amount_in / 2;
+        let resize = move |_: &Candidate| Verdict::Resize(half);
+        let evaluator = harness
+            .evaluator()
+            .with_filters(OpportunityFilters::new().register("size", resize));
+        let (candidate, outcome) = harness.evaluate(&evaluator, None).await;
+        let candidate = candidate.unwrap();
+        assert_eq!(outcome, None);
+        assert_eq!(candidate.route.amount_in, half);
+        assert_eq!(candidate.quoted, harness.quote(half).await.amounts_out);
+        assert_eq!(
+            candidate.profit,
+            candidate.amounts_out.last().unwrap() - half
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requote() {
+        let harness = Harness::new().await;
+        let evaluator = harness.evaluator().with_confirm_delay(Duration::ZERO);
+        let reloaded = [harness.cheap, harness.dear];
+        let (candidate, outcome) = harness.evaluate(&evaluator, Some(&reloaded)).await;
+        assert!(candidate.is_some());
+        assert_eq!(outcome, None);
+
+        // Sushiswap was already arbed back
+        let reloaded = [harness.dear, harness.dear];
+        let (candidate, outcome) = harness.evaluate(&evaluator, Some(&reloaded)).await;
+        assert!(candidate.is_none());
+        assert_eq!(outcome.as_deref(), Some("phantom"));
+    }
+}
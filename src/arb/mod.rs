@@ -0,0 +1,245 @@
+//! The cyclic arb as bus subscribers. The block loop quotes every route
+//! and publishes the quotes to `Bus::quotes`; `Evaluator` turns the
+//! profitable ones into candidates on `Bus::candidates`, `Dispatcher` sends
+//! the first of each block and publishes it to `Bus::submissions`, and
+//! `ReceiptWatcher` settles every submission once it's mined, dropped or
+//! cancelled. What receipts teach about routes and venues flows back to
+//! the evaluator through `Feedback`. `CommandExecutor` sends what a
+//! detector on another machine pushed to the bridge instead, and
+//! `ArbBackrunner` bundles the routes behind MEV-share hints.
+
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ethers::types::{Address, H256, U256};
+use tracing::Span;
+
+use crate::{
+    constants::token::ERC20Token,
+    export::OpportunityRecord,
+    price_index::PriceIndex,
+    routes::Route,
+    shadow::{QuoteBias, QuoteBiasConfig},
+    utils::{
+        fixed_point::to_f64,
+        route_health::{RouteHealth, RouteHealthConfig},
+    },
+    warmup::RouteEntry,
+    world::Protocol,
+};
+
+pub mod backrun;
+pub mod commands;
+pub mod dispatcher;
+pub mod evaluator;
+pub mod receipts;
+
+pub use backrun::ArbBackrunner;
+pub use commands::CommandExecutor;
+pub use dispatcher::Dispatcher;
+pub use evaluator::Evaluator;
+pub use receipts::ReceiptWatcher;
+
+/// per hop slippage allowed off the quotes
+pub const ARB_SLIPPAGE_BPS: u64 = 30;
+
+/// A route quoted at a block.
+#[derive(Clone, Debug)]
+pub struct RouteQuote {
+    pub block: u64,
+    pub timestamp: u64,
+    /// index of the route in the routes and their `RouteTable`
+    pub route: usize,
+    /// the route's amount capped at its depth
+    pub amount_in: U256,
+    /// of every hop
+    pub amounts_out: Vec<U256>,
+    pub protocols: Vec<Protocol>,
+    /// bor producer of the next block, the earliest the arb lands in, `None`
+    /// until the schedule is known
+    pub producer: Option<Address>,
+    /// of the block, the opportunity's span is a child of it
+    pub span: Span,
+}
+
+/// A profitable route that survived the filters, worth sending.
+#[derive(Clone, Debug)]
+pub struct ArbCandidate {
+    pub block: u64,
+    /// index of the route in the routes and their `RouteTable`
+    pub index: usize,
+    /// token symbols joined by `>`
+    pub symbols: String,
+    /// at the amount it's sent with, capped or resized
+    pub route: Route,
+    pub protocols: Vec<Protocol>,
+    /// of every hop as quoted, what receipts are checked against
+    pub quoted: Vec<U256>,
+    /// of every hop discounted by the quote bias, what minimums are set from
+    pub amounts_out: Vec<U256>,
+    pub profit: U256,
+    pub gas_price: U256,
+    /// see `RouteQuote::producer`
+    pub producer: Option<Address>,
+    pub span: Span,
+}
+
+impl ArbCandidate {
+    pub fn token(&self) -> ERC20Token {
+        self.route.token_path[0]
+    }
+
+    /// what revert history is kept under
+    pub fn key(&self) -> String {
+        route_key(&self.symbols, &self.protocols)
+    }
+
+    /// records `outcome` on the span and as an opportunity
+    pub fn outcome(&self, outcome: &str, tx_hash: Option<H256>) -> OpportunityRecord {
+        self.span.record("outcome", outcome);
+        opportunity_record(
+            self.block,
+            &self.route,
+            &self.symbols,
+            self.profit,
+            outcome,
+            tx_hash,
+        )
+    }
+}
+
+/// A candidate the wallet or the smart account sent.
+#[derive(Clone, Debug)]
+pub struct Submission {
+    pub candidate: ArbCandidate,
+    pub tx_hash: H256,
+    /// `None` if the node picked it
+    pub nonce: Option<U256>,
+}
+
+/// What receipts teach the evaluator about routes and venues.
+pub struct Feedback {
+    /// revert history by route and venues, see `ArbCandidate::key`
+    pub health: Mutex<RouteHealth<String>>,
+    pub quote_bias: Mutex<QuoteBias>,
+}
+
+impl Feedback {
+    pub fn new(health: RouteHealthConfig, quote_bias: QuoteBiasConfig) -> Self {
+        Self {
+            health: Mutex::new(RouteHealth::new(health)),
+            quote_bias: Mutex::new(QuoteBias::new(quote_bias)),
+        }
+    }
+}
+
+impl Default for Feedback {
+    fn default() -> Self {
+        Self::new(RouteHealthConfig::default(), QuoteBiasConfig::default())
+    }
+}
+
+pub fn opportunity_record(
+    block: u64,
+    route: &Route,
+    symbols: &str,
+    profit: U256,
+    outcome: &str,
+    tx_hash: Option<H256>,
+) -> OpportunityRecord {
+    OpportunityRecord {
+        block,
+        route: symbols.to_string(),
+        amount_in: route.amount_in,
+        profit,
+        outcome: outcome.to_string(),
+        tx_hash,
+    }
+}
+
+/// what revert history is kept under, the same tokens through other pools
+/// are a different route
+pub fn route_key(symbols: &str, protocol_route: &[Protocol]) -> String {
+    format!(
+        "{} {}",
+        symbols,
+        protocol_route
+            .iter()
+            .map(|protocol| protocol.to_string())
+            .collect::<Vec<_>>()
+            .join(">")
+    )
+}
+
+/// `amount` of `token` in whole USDC at the latest index price, 0 without
+/// one
+pub fn usd(prices: &PriceIndex, token: ERC20Token, amount: U256) -> f64 {
+    let price = match token == prices.quote() {
+        true => Some(1.0),
+        false => prices.price(token.get_address()).map(|price| price.price),
+    };
+    price.unwrap_or_default() * to_f64(amount) / 10f64.powi(token.get_decimals() as i32)
+}
+
+/// whether `profit` of the route of `entry` pays for `txn_fees`
+#[inline(always)]
+pub fn is_profitable(entry: &RouteEntry, profit: U256, txn_fees: U256) -> bool {
+    // normalize profit to 18 decimals for ease of comparison
+    let profit = entry.normalize(profit);
+    // assume 1 MATIC = $0.85
+    let txn_fee_usd = txn_fees
+        .checked_mul(U256::from(85))
+        .unwrap()
+        .checked_div(U256::from(100))
+        .unwrap();
+    profit > txn_fee_usd
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{
+            protocol::UniswapV2,
+            token::ERC20Token::{USDC, WETH},
+        },
+        utils::fixed_point::whole_units,
+    };
+
+    #[test]
+    fn test_route_key() {
+        let protocols = [
+            Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+            Protocol::UniswapV3 { fee: 500 },
+        ];
+        let key = route_key("USDC>WETH>USDC", &protocols);
+        assert_eq!(
+            key,
+            format!("USDC>WETH>USDC {}>{}", protocols[0], protocols[1])
+        );
+        assert_ne!(key, route_key("USDC>WETH>USDC", &protocols[..1]));
+    }
+
+    #[test]
+    fn test_is_profitable() {
+        let route = Route {
+            amount_in: whole_units(1000, USDC.get_decimals()).unwrap(),
+            token_path: vec![USDC, WETH, USDC],
+        };
+        let entry = RouteEntry::new(&route).unwrap();
+        // $1 against 1 MATIC of fees at $0.85
+        let one_usdc = whole_units(1, USDC.get_decimals()).unwrap();
+        let one_matic = whole_units(1, 18).unwrap();
+        assert!(is_profitable(&entry, one_usdc, one_matic));
+        assert!(!is_profitable(&entry, one_usdc / 2, one_matic));
+    }
+}
@@ -0,0 +1,635 @@
+//! Settles submissions: waits for each to be mined, cancels the ones stuck
+//! too long, and records what the mined ones paid and returned in the PnL,
+//! the trade log, the gas budgets and the `Feedback` of the evaluator. One
+//! a reorg orphans is taken back out of the PnL and the trade log and
+//! settled again.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ethers::{
+    providers::{Middleware, PendingTransaction},
+    signers::Signer,
+    types::{Address, TransactionReceipt, H256, U256},
+};
+use log::{error, info};
+use tokio::time::timeout;
+use tracing::{info_span, Instrument};
+
+use super::{unix_now, usd, Feedback, Submission};
+use crate::{
+    bus::{next, Bus, ExecutionEvent},
+    constants::token::ERC20Token::{self, WMATIC},
+    events::ExecutionStatus,
+    gas_budget::{GasBudgets, GasSpend},
+    header_tracker::{Reorg, DEFAULT_MAX_DEPTH},
+    in_flight::{InFlight, InFlightTx, Resolution},
+    pnl::{GasCost, PnlLedger},
+    price_index::PriceIndex,
+    resources::ResourceUsage,
+    schedule::ARB,
+    shadow::{hop_outcomes, realized_profit},
+    storage::Log,
+    trade_report::TradeRecord,
+    utils::{
+        broadcast::SubmissionStats,
+        nonce_guard::NonceGuard,
+        submitter::{CancelStatus, Submitter},
+    },
+};
+
+/// checks of a cancellation while waiting for it
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A settled submission and what was recorded of it in the PnL.
+#[derive(Clone, Debug)]
+pub struct MinedTxn {
+    pub block_hash: H256,
+    pub submission: Submission,
+    /// `None` if it reverted
+    pub profit: Option<U256>,
+    pub gas: GasCost,
+}
+
+/// The blocks the latest mined txns landed in, to tell which a reorg
+/// orphaned after they were recorded.
+#[derive(Debug, Default)]
+pub struct MinedTxns {
+    /// oldest first
+    mined: VecDeque<MinedTxn>,
+}
+
+impl MinedTxns {
+    pub fn record(&mut self, mined: MinedTxn) {
+        if self.mined.len() == DEFAULT_MAX_DEPTH {
+            self.mined.pop_front();
+        }
+        self.mined.push_back(mined);
+    }
+
+    /// takes out the txns mined in blocks `reorg` replaced
+    pub fn orphaned(&mut self, reorg: &Reorg) -> Vec<MinedTxn> {
+        let (orphaned, kept) = self
+            .mined
+            .drain(..)
+            .partition(|mined| reorg.old.iter().any(|old| old.hash == mined.block_hash));
+        self.mined = kept;
+        orphaned.into()
+    }
+}
+
+pub struct ReceiptWatcher<M, S> {
+    provider: Arc<M>,
+    submitter: Arc<Submitter<Arc<M>, S>>,
+    feedback: Arc<Feedback>,
+    prices: Arc<PriceIndex>,
+    resources: Arc<ResourceUsage>,
+    gas_budgets: Arc<GasBudgets>,
+    /// how long a txn can stay unmined before it's cancelled, `None` waits
+    /// for it indefinitely
+    cancel_after: Option<Duration>,
+    /// what relays sent through a `TieredSender` are credited in
+    channels: Option<Arc<SubmissionStats>>,
+    pnl: Option<PnlLedger>,
+    trades: Option<Log<TradeRecord>>,
+    in_flight: Option<InFlight>,
+    /// resynced when a txn a reorg orphaned is dropped, freeing its nonce
+    nonces: Option<Arc<NonceGuard>>,
+    mined: Mutex<MinedTxns>,
+}
+
+impl<M, S> ReceiptWatcher<M, S>
+where
+    M: Middleware + 'static,
+    S: Signer + 'static,
+{
+    pub fn new(
+        provider: Arc<M>,
+        submitter: Arc<Submitter<Arc<M>, S>>,
+        feedback: Arc<Feedback>,
+        prices: Arc<PriceIndex>,
+        resources: Arc<ResourceUsage>,
+        gas_budgets: Arc<GasBudgets>,
+    ) -> Self {
+        Self {
+            provider,
+            submitter,
+            feedback,
+            prices,
+            resources,
+            gas_budgets,
+            cancel_after: None,
+            channels: None,
+            pnl: None,
+            trades: None,
+            in_flight: None,
+            nonces: None,
+            mined: Mutex::new(MinedTxns::default()),
+        }
+    }
+
+    pub fn with_cancel_after(mut self, cancel_after: Duration) -> Self {
+        self.cancel_after = Some(cancel_after);
+        self
+    }
+
+    /// credits the channel that got each txn in first once it's mined
+    pub fn with_channels(mut self, channels: Arc<SubmissionStats>) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn with_pnl(mut self, pnl: PnlLedger) -> Self {
+        self.pnl = Some(pnl);
+        self
+    }
+
+    pub fn with_trades(mut self, trades: Log<TradeRecord>) -> Self {
+        self.trades = Some(trades);
+        self
+    }
+
+    /// resolves every txn settled in `in_flight`
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+
+    pub fn with_nonces(mut self, nonces: Arc<NonceGuard>) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// Settles every submission on `bus` in a task of its own, publishing
+    /// the executions. A txn a reorg orphaned once it was settled is settled
+    /// again.
+    pub async fn run(self: Arc<Self>, bus: Arc<Bus>) {
+        let mut submissions = bus.submissions.subscribe();
+        let mut reorgs = bus.reorgs.subscribe();
+        loop {
+            tokio::select! {
+                Some(submission) = next(&mut submissions, "submissions") => {
+                    let settle = self.clone().settle(submission, bus.clone());
+                    tokio::spawn(self.resources.scope(ARB, settle));
+                }
+                Some(reorg) = next(&mut reorgs, "reorgs") => {
+                    let orphaned = self.mined.lock().unwrap().orphaned(&reorg);
+                    for mined in orphaned {
+                        error!(
+                            "Txn {:?} was settled in a block the reorg past {:?} orphaned, settling it again",
+                            mined.submission.tx_hash,
+                            reorg.common_ancestor()
+                        );
+                        let resettle = self.clone().resettle(mined, bus.clone());
+                        tokio::spawn(self.resources.scope(ARB, resettle));
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn settle(self: Arc<Self>, submission: Submission, bus: Arc<Bus>) -> ExecutionStatus {
+        let Submission {
+            candidate,
+            tx_hash,
+            nonce,
+        } = submission;
+        let gas_price = candidate.gas_price;
+        let token = candidate.token();
+        let confirm = PendingTransaction::new(tx_hash, self.provider.provider())
+            .confirmations(1)
+            .instrument(info_span!(parent: &candidate.span, "confirm"));
+        let receipt = match self.cancel_after {
+            Some(cancel_after) => match timeout(cancel_after, confirm).await {
+                Ok(receipt) => receipt.ok().flatten(),
+                Err(_) => {
+                    self.cancel_stuck(tx_hash, nonce, gas_price, cancel_after)
+                        .await
+                }
+            },
+            None => confirm.await.ok().flatten(),
+        };
+        let status = match &receipt {
+            Some(receipt) if receipt.status == Some(1.into()) => ExecutionStatus::Confirmed,
+            Some(_) => ExecutionStatus::Reverted,
+            None => ExecutionStatus::Dropped,
+        };
+        if let Some(channels) = &self.channels {
+            if let Some(channel) = channels.record_outcome(tx_hash, receipt.is_some()) {
+                info!("  Included, {} accepted it first", channel);
+            }
+        }
+        if let Some(receipt) = &receipt {
+            let hops = match status {
+                ExecutionStatus::Confirmed => hop_outcomes(
+                    receipt,
+                    candidate.route.amount_in,
+                    &candidate.protocols,
+                    &candidate.quoted,
+                ),
+                _ => None,
+            };
+            for hop in hops.iter().flatten() {
+                let bias = self.feedback.quote_bias.lock().unwrap().record(hop);
+                info!(
+                    "  {} quoted {} for {}, returned {} for {} ({:.1} bps short, bias {:.1} bps over {} hops)",
+                    hop.protocol,
+                    hop.quoted_out,
+                    hop.quoted_in,
+                    hop.realized_out,
+                    hop.realized_in,
+                    hop.shortfall_bps(),
+                    bias.bps,
+                    bias.samples
+                );
+            }
+            let gas = gas_cost(self.provider.as_ref(), receipt, gas_price).await;
+            info!(
+                "  Gas paid {} wei ({} burnt, {} tip), bid {}",
+                gas.paid(),
+                gas.burnt(),
+                gas.tip(),
+                gas.bid()
+            );
+            self.resources
+                .record_mined(ARB, status == ExecutionStatus::Reverted, gas.paid());
+            let spend = GasSpend {
+                timestamp: unix_now(),
+                wallet: receipt.from,
+                strategy: ARB.to_string(),
+                paid: gas.paid(),
+            };
+            for alert in self.gas_budgets.record(spend).await {
+                bus.budgets.publish(alert);
+            }
+            let profit = (status == ExecutionStatus::Confirmed).then_some(candidate.profit);
+            if let Some(pnl) = &self.pnl {
+                if let Err(e) = pnl.record(token, profit, &gas).await {
+                    error!("Failed to record PnL: {:?}", e);
+                }
+            }
+            if let Some(trades) = &self.trades {
+                // the receipt's swaps, the quote if they don't match the
+                // route's hops
+                let realized = match (status, &hops) {
+                    (ExecutionStatus::Confirmed, Some(hops)) => realized_profit(hops),
+                    (ExecutionStatus::Confirmed, None) => candidate.profit,
+                    _ => U256::zero(),
+                };
+                let trade = TradeRecord {
+                    block: receipt
+                        .block_number
+                        .map_or(candidate.block, |number| number.as_u64()),
+                    tx_hash,
+                    route: candidate.symbols.clone(),
+                    status,
+                    profit_usd: usd(&self.prices, token, realized),
+                    gas_paid: gas.paid(),
+                    gas_usd: usd(&self.prices, WMATIC, gas.paid()),
+                };
+                if let Err(e) = trades.append(&trade).await {
+                    error!("Failed to record the trade: {:?}", e);
+                }
+            }
+            if let Some(block_hash) = receipt.block_hash {
+                self.mined.lock().unwrap().record(MinedTxn {
+                    block_hash,
+                    submission: Submission {
+                        candidate: candidate.clone(),
+                        tx_hash,
+                        nonce,
+                    },
+                    profit,
+                    gas,
+                });
+            }
+        }
+        if let Some(in_flight) = &self.in_flight {
+            // a cancellation still pending is reconciled on the next start
+            if !self.submitter.is_cancelling(tx_hash) {
+                if let Err(e) = in_flight.resolved(tx_hash).await {
+                    error!("  Failed to resolve txn in flight: {:?}", e);
+                }
+            }
+        }
+        let reverted = match status {
+            ExecutionStatus::Confirmed => Some(false),
+            ExecutionStatus::Reverted => Some(true),
+            // says nothing about the route
+            ExecutionStatus::Dropped => None,
+        };
+        if let Some(reverted) = reverted {
+            self.feedback
+                .health
+                .lock()
+                .unwrap()
+                .record(candidate.key(), reverted, Instant::now());
+        }
+        bus.executions.publish(ExecutionEvent {
+            block: candidate.block,
+            tx_hash,
+            status,
+            gas_used: receipt.and_then(|receipt| receipt.gas_used),
+        });
+        status
+    }
+
+    /// Takes what was recorded of `mined` back out of the PnL and the trade
+    /// log, puts it back in flight and settles it again: mined anew if the
+    /// reorg put it back in the mempool, dropped if not.
+    async fn resettle(self: Arc<Self>, mined: MinedTxn, bus: Arc<Bus>) {
+        let MinedTxn {
+            submission,
+            profit,
+            gas,
+            ..
+        } = mined;
+        let candidate = &submission.candidate;
+        let tx_hash = submission.tx_hash;
+        if let Some(pnl) = &self.pnl {
+            if let Err(e) = pnl.retract(candidate.token(), profit, &gas).await {
+                error!("Failed to take txn {:?} out of the PnL: {:?}", tx_hash, e);
+            }
+        }
+        if let Some(trades) = &self.trades {
+            // until a record of wherever it's mined next supersedes it
+            let trade = TradeRecord {
+                block: candidate.block,
+                tx_hash,
+                route: candidate.symbols.clone(),
+                status: ExecutionStatus::Dropped,
+                profit_usd: 0.0,
+                gas_paid: U256::zero(),
+                gas_usd: 0.0,
+            };
+            if let Err(e) = trades.append(&trade).await {
+                error!("Failed to record the trade: {:?}", e);
+            }
+        }
+        if let Some(in_flight) = &self.in_flight {
+            let record = InFlightTx {
+                hash: tx_hash,
+                nonce: submission.nonce,
+                gas_price: candidate.gas_price,
+                block: candidate.block,
+                token: candidate.token().get_symbol().to_string(),
+                profit: candidate.profit,
+            };
+            if let Err(e) = in_flight.sent(&record).await {
+                error!("Failed to record txn {:?} in flight: {:?}", tx_hash, e);
+            }
+        }
+        let status = self.clone().settle(submission, bus).await;
+        if status != ExecutionStatus::Dropped {
+            return;
+        }
+        // its nonce is free again, and the guard handed out the ones after it
+        if let Some(nonces) = &self.nonces {
+            match nonces.resync(self.provider.as_ref()).await {
+                Ok(nonce) => info!(
+                    "Orphaned txn {:?} dropped, nonce resynced to {}",
+                    tx_hash, nonce
+                ),
+                Err(e) => error!("Failed to resync wallet nonce: {:?}", e),
+            }
+        }
+    }
+
+    /// Settles the txns a previous run of `wallet` left in flight: the
+    /// mined ones go to the PnL, pending ones get `wait` to be mined before
+    /// they're cancelled.
+    pub async fn reconcile(&self, wallet: Address, wait: Duration) {
+        let in_flight = match &self.in_flight {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+        let reconciled = match in_flight.reconcile(self.provider.as_ref(), wallet).await {
+            Ok(reconciled) => reconciled,
+            Err(e) => {
+                error!("Failed to reconcile txns in flight: {}", e);
+                return;
+            }
+        };
+        for reconciled in reconciled {
+            let tx = reconciled.tx;
+            let receipt = match reconciled.resolution {
+                Resolution::Mined(receipt) => Some(*receipt),
+                Resolution::Pending => {
+                    info!(
+                        "Txn {:?} of a previous run still pending, waiting up to {:?}",
+                        tx.hash, wait
+                    );
+                    let confirm =
+                        PendingTransaction::new(tx.hash, self.provider.provider()).confirmations(1);
+                    let receipt = match timeout(wait, confirm).await {
+                        Ok(receipt) => receipt.ok().flatten(),
+                        Err(_) => {
+                            self.cancel_stuck(tx.hash, tx.nonce, tx.gas_price, wait)
+                                .await
+                        }
+                    };
+                    // a cancellation still pending is reconciled on the next start
+                    if !self.submitter.is_cancelling(tx.hash) {
+                        if let Err(e) = in_flight.resolved(tx.hash).await {
+                            error!("Failed to resolve txn {:?} in flight: {:?}", tx.hash, e);
+                        }
+                    }
+                    receipt
+                }
+                resolution => {
+                    info!(
+                        "Txn {:?} of a previous run wasn't mined: {:?}",
+                        tx.hash, resolution
+                    );
+                    None
+                }
+            };
+            let receipt = match receipt {
+                Some(receipt) => receipt,
+                None => continue,
+            };
+            let confirmed = receipt.status == Some(1.into());
+            info!(
+                "Txn {:?} of a previous run mined in block {:?}, {}",
+                tx.hash,
+                receipt.block_number,
+                if confirmed { "confirmed" } else { "reverted" }
+            );
+            let token = match ERC20Token::from_symbol(&tx.token) {
+                Some(token) => token,
+                None => continue,
+            };
+            if let Some(pnl) = &self.pnl {
+                let gas = gas_cost(self.provider.as_ref(), &receipt, tx.gas_price).await;
+                let profit = confirmed.then_some(tx.profit);
+                if let Err(e) = pnl.record(token, profit, &gas).await {
+                    error!("Failed to record PnL: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Cancels `tx_hash`, sent with `nonce` (read from the node if unknown)
+    /// at `gas_price` and not mined within the wait for it, then waits up to
+    /// `wait` more for it or the cancellation to be mined. The receipt of
+    /// `tx_hash` if it was mined after all.
+    async fn cancel_stuck(
+        &self,
+        tx_hash: H256,
+        nonce: Option<U256>,
+        gas_price: U256,
+        wait: Duration,
+    ) -> Option<TransactionReceipt> {
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => match self.provider.get_transaction(tx_hash).await {
+                Ok(Some(tx)) => tx.nonce,
+                Ok(None) => return None,
+                Err(e) => {
+                    error!(
+                        "  Failed to read the nonce of stuck txn {:?}: {}",
+                        tx_hash, e
+                    );
+                    return None;
+                }
+            },
+        };
+        self.submitter.track(nonce, tx_hash, gas_price);
+        match self.submitter.cancel(nonce).await {
+            Ok(cancellation) => info!(
+                "  Txn {:?} stuck, cancelling with {:?} at gas price {}",
+                tx_hash, cancellation.hash, cancellation.gas_price
+            ),
+            Err(e) => {
+                error!("  Txn {:?} stuck: {}", tx_hash, e);
+                return self
+                    .provider
+                    .get_transaction_receipt(tx_hash)
+                    .await
+                    .ok()
+                    .flatten();
+            }
+        }
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline {
+            tokio::time::sleep(CANCEL_POLL_INTERVAL).await;
+            let resolved = match self.submitter.reconcile().await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    error!("  {}", e);
+                    continue;
+                }
+            };
+            if let Some(cancellation) = resolved.iter().find(|c| c.nonce == nonce) {
+                return match cancellation.status {
+                    CancelStatus::Landed => self
+                        .provider
+                        .get_transaction_receipt(tx_hash)
+                        .await
+                        .ok()
+                        .flatten(),
+                    _ => None,
+                };
+            }
+        }
+        error!("  Cancellation of nonce {} not mined yet, moving on", nonce);
+        None
+    }
+}
+
+/// what the mined `receipt` of a txn bidding `gas_price` paid for gas
+async fn gas_cost<M: Middleware>(
+    provider: &M,
+    receipt: &TransactionReceipt,
+    gas_price: U256,
+) -> GasCost {
+    let base_fee = match receipt.block_hash {
+        Some(hash) => provider
+            .get_block(hash)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|block| block.base_fee_per_gas),
+        None => None,
+    };
+    GasCost::from_receipt(receipt, gas_price, base_fee.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::Span;
+
+    use super::*;
+    use crate::{arb::ArbCandidate, header_tracker::HeaderInfo, routes::Route};
+
+    fn header(number: u64, fork: u64) -> HeaderInfo {
+        HeaderInfo {
+            number,
+            hash: H256::from_low_u64_be(number * 100 + fork),
+            parent_hash: H256::from_low_u64_be((number - 1) * 100),
+            timestamp: U256::zero(),
+        }
+    }
+
+    fn mined(block_hash: H256, tx_hash: H256) -> MinedTxn {
+        let candidate = ArbCandidate {
+            block: 1,
+            index: 0,
+            symbols: "USDC>WETH>USDC".to_string(),
+            route: Route {
+                amount_in: U256::zero(),
+                token_path: vec![ERC20Token::USDC, ERC20Token::WETH, ERC20Token::USDC],
+            },
+            protocols: Vec::new(),
+            quoted: Vec::new(),
+            amounts_out: Vec::new(),
+            profit: U256::zero(),
+            gas_price: U256::zero(),
+            producer: None,
+            span: Span::none(),
+        };
+        MinedTxn {
+            block_hash,
+            submission: Submission {
+                candidate,
+                tx_hash,
+                nonce: None,
+            },
+            profit: None,
+            gas: GasCost::default(),
+        }
+    }
+
+    fn hashes(mined: Vec<MinedTxn>) -> Vec<H256> {
+        mined.iter().map(|mined| mined.submission.tx_hash).collect()
+    }
+
+    #[test]
+    fn test_orphaned() {
+        let mut mined_txns = MinedTxns::default();
+        mined_txns.record(mined(header(10, 0).hash, H256::repeat_byte(1)));
+        mined_txns.record(mined(header(11, 0).hash, H256::repeat_byte(2)));
+        let reorg = Reorg {
+            depth: 1,
+            old: vec![header(11, 0)],
+            new: vec![header(11, 1)],
+        };
+        assert_eq!(
+            hashes(mined_txns.orphaned(&reorg)),
+            vec![H256::repeat_byte(2)]
+        );
+        // taken out to be settled again
+        assert!(mined_txns.orphaned(&reorg).is_empty());
+
+        // only the latest are kept
+        mined_txns.record(mined(header(11, 0).hash, H256::repeat_byte(2)));
+        for i in 0..DEFAULT_MAX_DEPTH as u64 {
+            mined_txns.record(mined(header(12 + i, 0).hash, H256::zero()));
+        }
+        assert!(mined_txns.orphaned(&reorg).is_empty());
+    }
+}
@@ -0,0 +1,620 @@
+use ethers::{
+    abi::{AbiDecode, AbiEncode},
+    prelude::abigen,
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, Address, Bytes, U256},
+};
+use thiserror::Error;
+
+use crate::{
+    constants::{protocol::UNISWAP_V3, token::ERC20Token},
+    uniswapV2::{max_amount_in, min_amount_out},
+    utils::{batch::json_rpc_error, fixed_point::mul_div_rounding_up},
+    world::Protocol,
+};
+
+abigen!(
+    Flashloan,
+    "abis/FlashloanV3.json",
+    methods {
+        // the getter of the constant would take `executorVersion`'s names
+        EXECUTOR_VERSION() as executor_version_constant;
+    }
+);
+
+/// newest executor interface this build knows how to call
+pub const MAX_EXECUTOR_VERSION: u64 = 1;
+
+/// executor checks `minProfit`
+pub const FEATURE_MIN_PROFIT: u64 = 1;
+/// executor checks `minAmountsOut` per hop
+pub const FEATURE_HOP_MIN_OUT: u64 = 1 << 1;
+/// executor takes `executeArbitrageExactOutput`
+pub const FEATURE_EXACT_OUTPUT: u64 = 1 << 2;
+
+#[derive(Debug, Error)]
+pub enum ExecutorVersionError {
+    #[error("no contract deployed at {0:?}")]
+    NoContract(Address),
+
+    #[error("executor version {0} is newer than this build supports ({MAX_EXECUTOR_VERSION})")]
+    Unsupported(u64),
+
+    #[error("executor lacks required features {missing:#x} (has {features:#x})")]
+    MissingFeatures { features: u64, missing: u64 },
+
+    #[error("malformed executorVersion() return data")]
+    Malformed,
+
+    #[error("{0}")]
+    Middleware(String),
+}
+
+/// What a deployed executor contract accepts. Contracts without
+/// `executorVersion()` are version 0: `executeArbitrage` only.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExecutorFeatures {
+    pub version: u64,
+    pub features: u64,
+}
+
+impl ExecutorFeatures {
+    pub const LEGACY: Self = Self {
+        version: 0,
+        features: 0,
+    };
+
+    /// decodes `executorVersion()` return data, empty for contracts that
+    /// don't have it but accept any call
+    pub fn decode(data: &[u8]) -> Result<Self, ExecutorVersionError> {
+        if data.is_empty() {
+            return Ok(Self::LEGACY);
+        }
+        let ExecutorVersionReturn { version, features } =
+            ExecutorVersionReturn::decode(data).map_err(|_| ExecutorVersionError::Malformed)?;
+        if version > U256::from(MAX_EXECUTOR_VERSION) {
+            return Err(ExecutorVersionError::Unsupported(version.low_u64()));
+        }
+        Ok(Self {
+            version: version.as_u64(),
+            features: features.low_u64(),
+        })
+    }
+
+    pub fn supports(&self, features: u64) -> bool {
+        self.features & features == features
+    }
+
+    /// fails with the missing ones unless all of `features` are supported
+    pub fn require(&self, features: u64) -> Result<(), ExecutorVersionError> {
+        match features & !self.features {
+            0 => Ok(()),
+            missing => Err(ExecutorVersionError::MissingFeatures {
+                features: self.features,
+                missing,
+            }),
+        }
+    }
+}
+
+/// Reads the interface of the executor at `address`, so calldata matches
+/// what it takes instead of reverting on-chain. `executorVersion()` is
+/// missing on the original contracts.
+pub async fn probe_executor<M>(
+    provider: &M,
+    address: Address,
+) -> Result<ExecutorFeatures, ExecutorVersionError>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    let code = provider
+        .get_code(address, None)
+        .await
+        .map_err(|e| ExecutorVersionError::Middleware(e.to_string()))?;
+    if code.is_empty() {
+        return Err(ExecutorVersionError::NoContract(address));
+    }
+    let tx: TypedTransaction = ethers::types::TransactionRequest::new()
+        .to(address)
+        .data(ExecutorVersionCall.encode())
+        .into();
+    match provider.call(&tx, None).await {
+        Ok(data) => ExecutorFeatures::decode(&data),
+        Err(e) => match json_rpc_error(&e).and_then(|e| e.revert_data()) {
+            // no such function, nor a fallback taking it
+            Some(data) if data.is_empty() => Ok(ExecutorFeatures::LEGACY),
+            _ => Err(ExecutorVersionError::Middleware(e.to_string())),
+        },
+    }
+}
+
+/// Whether the executor at `address` takes calls from `caller` besides its
+/// owner, as from a smart account. Contracts without `executors` only take
+/// the owner's.
+pub async fn accepts_caller<M>(
+    provider: &M,
+    address: Address,
+    caller: Address,
+) -> Result<bool, ExecutorVersionError>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    let tx: TypedTransaction = ethers::types::TransactionRequest::new()
+        .to(address)
+        .data(ExecutorsCall(caller).encode())
+        .into();
+    match provider.call(&tx, None).await {
+        Ok(data) => bool::decode(&data).map_err(|_| ExecutorVersionError::Malformed),
+        Err(e) => match json_rpc_error(&e).and_then(|e| e.revert_data()) {
+            Some(data) if data.is_empty() => Ok(false),
+            _ => Err(ExecutorVersionError::Middleware(e.to_string())),
+        },
+    }
+}
+
+/// Input of a hop quoted to swap `quoted_in` for `quoted_out` that buys
+/// `amount_out`, rounded up. Output falls off with size, so the share of
+/// `quoted_in` is never less than what's needed. `None` if the hop can't
+/// return `amount_out`.
+pub fn exact_output_amount_in(quoted_in: U256, quoted_out: U256, amount_out: U256) -> Option<U256> {
+    if quoted_out < amount_out {
+        return None;
+    }
+    mul_div_rounding_up(quoted_in, amount_out, quoted_out)
+}
+
+/// The last hop of a route buying back exactly the loan, so its repayment
+/// can't come up short by the rounding of the hops before it. Amounts are
+/// of the token the last hop sells.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExactOutputLeg {
+    /// quoted input of the last hop
+    pub amount_in: U256,
+    /// quoted input plus slippage
+    pub max_amount_in: U256,
+    /// quoted surplus left over, the profit
+    pub profit: U256,
+    /// surplus if the hops before return their min amounts and the last
+    /// takes its max
+    pub min_profit: U256,
+}
+
+/// An arb route with what each hop is expected to return. Only executors
+/// with `FEATURE_HOP_MIN_OUT` check `min_amounts_out`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArbRoute {
+    pub amount_in: U256,
+    pub params: ArbParams,
+    /// per hop, quote less slippage
+    pub min_amounts_out: Vec<U256>,
+    /// `None` if the route sells everything on the last hop
+    pub exact_output: Option<ExactOutputLeg>,
+}
+
+impl ArbRoute {
+    /// profit if every hop only returns its min amount, zero if that loses
+    pub fn min_profit(&self) -> U256 {
+        self.min_amounts_out
+            .last()
+            .map_or(U256::zero(), |out| out.saturating_sub(self.amount_in))
+    }
+
+    /// Calldata for the executor, with the per hop and profit checks of
+    /// `executeArbitrageChecked` when it has them. An exact output leg is
+    /// only sent to executors with `FEATURE_EXACT_OUTPUT`, others sell
+    /// everything on the last hop.
+    pub fn calldata(&self, executor: &ExecutorFeatures, block_number: U256) -> Bytes {
+        if let Some(leg) = &self.exact_output {
+            if executor.supports(FEATURE_EXACT_OUTPUT) {
+                return self.exact_output_calldata(executor, leg, block_number);
+            }
+        }
+        if executor.version == 0 {
+            return ExecuteArbitrageCall {
+                params: self.params.clone(),
+                block_number,
+            }
+            .encode()
+            .into();
+        }
+        let min_amounts_out = if executor.supports(FEATURE_HOP_MIN_OUT) {
+            self.min_amounts_out.clone()
+        } else {
+            vec![U256::zero(); self.min_amounts_out.len()]
+        };
+        let min_profit = if executor.supports(FEATURE_MIN_PROFIT) {
+            self.min_profit()
+        } else {
+            U256::zero()
+        };
+        // reverts unless every hop returns its min amount and the loan is
+        // repaid with at least `min_profit` left
+        ExecuteArbitrageCheckedCall {
+            params: self.params.clone(),
+            min_amounts_out,
+            min_profit,
+            block_number,
+        }
+        .encode()
+        .into()
+    }
+
+    fn exact_output_calldata(
+        &self,
+        executor: &ExecutorFeatures,
+        leg: &ExactOutputLeg,
+        block_number: U256,
+    ) -> Bytes {
+        // the last hop's output is the loan
+        let mut min_amounts_out = if executor.supports(FEATURE_HOP_MIN_OUT) {
+            self.min_amounts_out.clone()
+        } else {
+            vec![U256::zero(); self.min_amounts_out.len()]
+        };
+        if let Some(last) = min_amounts_out.last_mut() {
+            *last = self.amount_in;
+        }
+        let min_profit = if executor.supports(FEATURE_MIN_PROFIT) {
+            leg.min_profit
+        } else {
+            U256::zero()
+        };
+        // the last hop buys exactly the loan back for at most
+        // `max_last_amount_in`, what's left of the token it sells goes to the
+        // owner and reverts below `min_profit`
+        ExecuteArbitrageExactOutputCall {
+            params: self.params.clone(),
+            min_amounts_out,
+            max_last_amount_in: leg.max_amount_in,
+            min_profit,
+            block_number,
+        }
+        .encode()
+        .into()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ArbParamsBuilder {
+    amount_in: U256,
+    token_path: Vec<ERC20Token>,
+    hops: Vec<(Protocol, U256)>,
+    slippage_bps: u64,
+    exact_output: bool,
+}
+
+impl ArbParamsBuilder {
+    pub fn new(amount_in: U256, token_in: ERC20Token) -> Self {
+        Self {
+            amount_in,
+            token_path: vec![token_in],
+            hops: Vec::new(),
+            slippage_bps: 0,
+            exact_output: false,
+        }
+    }
+
+    /// from a whole route, `amounts_out[i]` being the quote of hop `i`
+    pub fn from_route(
+        amount_in: U256,
+        token_path: &[ERC20Token],
+        protocol_route: &[Protocol],
+        amounts_out: &[U256],
+    ) -> Self {
+        let mut builder = Self::new(amount_in, token_path[0]);
+        for ((token_out, protocol), amount_out) in
+            token_path[1..].iter().zip(protocol_route).zip(amounts_out)
+        {
+            builder = builder.hop(*token_out, *protocol, *amount_out);
+        }
+        builder
+    }
+
+    /// swap into `token_out` on `protocol`, quoted at `expected_out`
+    pub fn hop(mut self, token_out: ERC20Token, protocol: Protocol, expected_out: U256) -> Self {
+        self.token_path.push(token_out);
+        self.hops.push((protocol, expected_out));
+        self
+    }
+
+    pub fn slippage_bps(mut self, slippage_bps: u64) -> Self {
+        self.slippage_bps = slippage_bps;
+        self
+    }
+
+    /// buy back exactly `amount_in` on the last hop, keeping the surplus of
+    /// the token it sells
+    pub fn exact_output(mut self, exact_output: bool) -> Self {
+        self.exact_output = exact_output;
+        self
+    }
+
+    /// `None` unless asked for and the route is profitable
+    fn exact_output_leg(&self, min_amounts_out: &[U256]) -> Option<ExactOutputLeg> {
+        if !self.exact_output || self.hops.is_empty() {
+            return None;
+        }
+        let last = self.hops.len() - 1;
+        // what the last hop sells, the loan itself on a one hop route
+        let (held, min_held) = match last {
+            0 => (self.amount_in, self.amount_in),
+            _ => (self.hops[last - 1].1, min_amounts_out[last - 1]),
+        };
+        let amount_in = exact_output_amount_in(held, self.hops[last].1, self.amount_in)?;
+        let max_amount_in = max_amount_in(amount_in, self.slippage_bps);
+        Some(ExactOutputLeg {
+            amount_in,
+            max_amount_in,
+            profit: held - amount_in,
+            min_profit: min_held.saturating_sub(max_amount_in),
+        })
+    }
+
+    pub fn build(&self) -> ArbRoute {
+        let mut protocol_path = Vec::with_capacity(self.hops.len());
+        let mut protocol_types = Vec::with_capacity(self.hops.len());
+        let mut fees = Vec::with_capacity(self.hops.len());
+        let mut min_amounts_out = Vec::with_capacity(self.hops.len());
+        for (protocol, expected_out) in &self.hops {
+            match protocol {
+                Protocol::UniswapV2(p) => {
+                    protocol_path.push(p.get_router_address());
+                    protocol_types.push(0);
+                    fees.push(0);
+                }
+                Protocol::UniswapV3 { fee } => {
+                    protocol_path.push(UNISWAP_V3.router_address);
+                    protocol_types.push(1);
+                    fees.push(*fee);
+                }
+            };
+            min_amounts_out.push(min_amount_out(*expected_out, self.slippage_bps));
+        }
+
+        ArbRoute {
+            amount_in: self.amount_in,
+            params: ArbParams {
+                amount_in: self.amount_in,
+                token_path: self.token_path.iter().map(|x| x.get_address()).collect(),
+                protocol_path,
+                protocol_types,
+                fees,
+            },
+            exact_output: self.exact_output_leg(&min_amounts_out),
+            min_amounts_out,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{
+            protocol::UniswapV2,
+            token::ERC20Token::{USDC, WETH},
+        },
+        utils::batch::{common::JsonRpcError, fake::FakeTransport},
+    };
+    use ethers::providers::Provider;
+
+    #[test]
+    fn test_arb_params_builder() {
+        let route = ArbParamsBuilder::new(U256::from(1_000), USDC)
+            .hop(
+                WETH,
+                Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+                U256::from(500),
+            )
+            .hop(USDC, Protocol::UniswapV3 { fee: 500 }, U256::from(1_010))
+            .slippage_bps(100)
+            .build();
+        assert_eq!(
+            route.params.token_path,
+            vec![USDC.get_address(), WETH.get_address(), USDC.get_address()]
+        );
+        assert_eq!(
+            route.params.protocol_path,
+            vec![
+                UniswapV2::SUSHISWAP.get_router_address(),
+                UNISWAP_V3.router_address
+            ]
+        );
+        assert_eq!(route.params.protocol_types, vec![0, 1]);
+        assert_eq!(route.params.fees, vec![0, 500]);
+        assert_eq!(
+            route.min_amounts_out,
+            vec![U256::from(495), U256::from(999)]
+        );
+        assert_eq!(route.min_profit(), U256::zero());
+    }
+
+    #[test]
+    fn test_executor_features() {
+        assert_eq!(
+            ExecutorFeatures::decode(&[]).unwrap(),
+            ExecutorFeatures::LEGACY
+        );
+        let v1 = ExecutorFeatures::decode(&(U256::one(), U256::from(3)).encode()).unwrap();
+        assert!(v1.supports(FEATURE_MIN_PROFIT | FEATURE_HOP_MIN_OUT));
+        assert!(v1.require(FEATURE_HOP_MIN_OUT).is_ok());
+        assert!(matches!(
+            ExecutorFeatures::LEGACY.require(FEATURE_HOP_MIN_OUT),
+            Err(ExecutorVersionError::MissingFeatures { missing: 2, .. })
+        ));
+        assert!(matches!(
+            ExecutorFeatures::decode(&(U256::from(2), U256::zero()).encode()),
+            Err(ExecutorVersionError::Unsupported(2))
+        ));
+        assert!(matches!(
+            ExecutorFeatures::decode(&[1, 2, 3]),
+            Err(ExecutorVersionError::Malformed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_probe_executor() {
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        let executor = Address::repeat_byte(1);
+        let revert = |code: i64, message: &str, data: Option<&str>| JsonRpcError {
+            code,
+            message: message.to_string(),
+            data: data.map(|data| serde_json::json!(data)),
+        };
+        transport.set_response("eth_getCode", Bytes::from(vec![0x60, 0x80]));
+
+        transport.push_response(
+            "eth_call",
+            Bytes::from((U256::one(), U256::from(7)).encode()),
+        );
+        assert_eq!(
+            probe_executor(&provider, executor).await.unwrap(),
+            ExecutorFeatures {
+                version: 1,
+                features: 7
+            }
+        );
+        // reverted without data, the dispatcher of a contract without it
+        transport.push_error("eth_call", revert(3, "execution reverted", Some("0x")));
+        transport.push_error("eth_call", revert(-32000, "execution reverted", None));
+        for _ in 0..2 {
+            assert_eq!(
+                probe_executor(&provider, executor).await.unwrap(),
+                ExecutorFeatures::LEGACY
+            );
+        }
+        // a reason is the contract's own revert, and the rest isn't a revert
+        transport.push_error(
+            "eth_call",
+            revert(
+                3,
+                "execution reverted: paused",
+                Some("0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000006706175736564000000000000000000000000000000000000000000000000000000"),
+            ),
+        );
+        transport.push_error("eth_call", revert(-32000, "header not found", None));
+        transport.push_error("eth_call", revert(-32603, "reverted block", None));
+        for _ in 0..3 {
+            assert!(matches!(
+                probe_executor(&provider, executor).await,
+                Err(ExecutorVersionError::Middleware(_))
+            ));
+        }
+
+        transport.set_response("eth_getCode", Bytes::default());
+        assert!(matches!(
+            probe_executor(&provider, executor).await,
+            Err(ExecutorVersionError::NoContract(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_accepts_caller() {
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        let (executor, account) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        transport.push_response("eth_call", Bytes::from(true.encode()));
+        transport.push_response("eth_call", Bytes::from(false.encode()));
+        transport.push_error(
+            "eth_call",
+            JsonRpcError {
+                code: -32000,
+                message: "execution reverted".to_string(),
+                data: None,
+            },
+        );
+        assert!(accepts_caller(&provider, executor, account).await.unwrap());
+        assert!(!accepts_caller(&provider, executor, account).await.unwrap());
+        // without the allowlist
+        assert!(!accepts_caller(&provider, executor, account).await.unwrap());
+
+        let requests = transport.requests();
+        let data: Bytes = serde_json::from_value(requests[0].1[0]["data"].clone()).unwrap();
+        assert_eq!(ExecutorsCall::decode(&data).unwrap().0, account);
+    }
+
+    #[test]
+    fn test_calldata_by_version() {
+        let route = ArbParamsBuilder::new(U256::from(1_000), USDC)
+            .hop(WETH, Protocol::UniswapV3 { fee: 500 }, U256::from(500))
+            .hop(USDC, Protocol::UniswapV3 { fee: 500 }, U256::from(1_100))
+            .slippage_bps(100)
+            .build();
+        assert_eq!(route.min_profit(), U256::from(89));
+        let block = U256::from(100);
+
+        let legacy = route.calldata(&ExecutorFeatures::LEGACY, block);
+        let decoded = ExecuteArbitrageCall::decode(&legacy).unwrap();
+        assert_eq!(decoded.params, route.params);
+        assert_eq!(decoded.block_number, block);
+
+        let checked = route.calldata(
+            &ExecutorFeatures {
+                version: 1,
+                features: FEATURE_MIN_PROFIT | FEATURE_HOP_MIN_OUT,
+            },
+            block,
+        );
+        let decoded = ExecuteArbitrageCheckedCall::decode(&checked).unwrap();
+        assert_eq!(decoded.params, route.params);
+        assert_eq!(decoded.min_amounts_out, vec![495.into(), 1_089.into()]);
+        assert_eq!(decoded.min_profit, 89.into());
+        assert_eq!(decoded.block_number, block);
+    }
+
+    #[test]
+    fn test_exact_output_leg() {
+        let builder = ArbParamsBuilder::new(U256::from(1_000), USDC)
+            .hop(WETH, Protocol::UniswapV3 { fee: 500 }, U256::from(500))
+            .hop(USDC, Protocol::UniswapV3 { fee: 500 }, U256::from(1_100))
+            .slippage_bps(100);
+        assert_eq!(builder.build().exact_output, None);
+        let route = builder.clone().exact_output(true).build();
+        // 500 WETH buy 1,100 USDC, so 1,000 take 454.5
+        assert_eq!(
+            route.exact_output,
+            Some(ExactOutputLeg {
+                amount_in: 455.into(),
+                max_amount_in: 459.into(),
+                profit: 45.into(),
+                min_profit: 36.into(),
+            })
+        );
+        let block = U256::from(100);
+
+        let calldata = route.calldata(
+            &ExecutorFeatures {
+                version: 1,
+                features: FEATURE_MIN_PROFIT | FEATURE_HOP_MIN_OUT | FEATURE_EXACT_OUTPUT,
+            },
+            block,
+        );
+        let decoded = ExecuteArbitrageExactOutputCall::decode(&calldata).unwrap();
+        assert_eq!(decoded.params, route.params);
+        assert_eq!(decoded.min_amounts_out, vec![495.into(), 1_000.into()]);
+        assert_eq!(decoded.max_last_amount_in, 459.into());
+        assert_eq!(decoded.min_profit, 36.into());
+        assert_eq!(decoded.block_number, block);
+
+        // executors without it sell everything
+        let checked = ExecutorFeatures {
+            version: 1,
+            features: FEATURE_MIN_PROFIT | FEATURE_HOP_MIN_OUT,
+        };
+        assert_eq!(
+            route.calldata(&checked, block),
+            builder.build().calldata(&checked, block)
+        );
+
+        // a losing route can't buy the loan back
+        let losing = ArbParamsBuilder::new(U256::from(1_000), USDC)
+            .hop(WETH, Protocol::UniswapV3 { fee: 500 }, U256::from(500))
+            .hop(USDC, Protocol::UniswapV3 { fee: 500 }, U256::from(990))
+            .exact_output(true)
+            .build();
+        assert_eq!(losing.exact_output, None);
+    }
+}
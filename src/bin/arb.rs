@@ -1,16 +1,39 @@
 use clap::Parser;
 use dotenv::dotenv;
+use enum_map::enum_map;
 use ethers::{
-    prelude::{abigen, SignerMiddleware},
-    providers::{Middleware, Provider, PubsubClient, Ws},
+    prelude::SignerMiddleware,
+    providers::{Http, Ipc, Middleware, Provider, PubsubClient, Ws},
     signers::{LocalWallet, Signer},
     types::{Address, U256},
+    utils::parse_ether,
 };
 use futures_util::StreamExt;
 use log::{debug, error, info};
-use std::{sync::Arc, time::Instant};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::broadcast::error::TryRecvError, time::timeout};
+use tracing::{debug_span, field, info_span, Instrument, Span};
 
 use tsuki::{
+    address_book::{AddressBook, FLASHLOAN_EXECUTOR, POLYGON},
+    address_tags::{AddressTags, DEFAULT_ADDRESS_TAGS},
+    api::Api,
+    arb::{
+        ArbBackrunner, CommandExecutor, Dispatcher, Evaluator, Feedback, ReceiptWatcher,
+        RouteQuote, ARB_SLIPPAGE_BPS,
+    },
+    arb_params::{
+        accepts_caller, probe_executor, ArbParamsBuilder, ExecutorFeatures, Flashloan,
+        FEATURE_EXACT_OUTPUT,
+    },
+    bor::ProducerTracker,
+    bridge::{self, BridgeError, EventSink},
+    bus::{ndjson_sink, next, storage_sink, BlockEvent, Bus},
     constants::{
         protocol::{
             UniswapV2::{self},
@@ -18,81 +41,439 @@ use tsuki::{
         },
         token::ERC20Token::{self, *},
     },
+    events::Ndjson,
+    gas_budget::{BudgetConfig, GasBudgets, DEFAULT_GAS_BUDGETS},
+    header_tracker::{HeaderTracker, DEFAULT_MAX_DEPTH},
+    heatmap::{RouteHeatmap, DEFAULT_MIN_EDGE_BPS},
+    in_flight::InFlight,
+    inventory::{track_inventory, Holder, DEFAULT_INVENTORY_BLOCKS},
+    ladder::{self, RouteGroup, SizeLadder},
+    lag::{HeadLag, Lag, LagConfig, ProductionGap, HEADS},
+    leader::{self, LeaderLock},
+    migration::{CollapseConfig, DEFAULT_COLLAPSE_BPS},
+    opportunity_filter::OpportunityFilters,
+    pnl::PnlLedger,
+    preflight::Preflight,
+    resources::{Metered, ResourceUsage, SHARED},
+    router_probe::ProbeConfig,
+    routes::{load_routes, Route},
+    schedule::{Schedules, Trigger, ARB, DEFAULT_SCHEDULES},
+    secrets::Secrets,
+    storage::{self, Log, Table, DEFAULT_STORAGE, EXECUTIONS, ROUTE_HEATMAP},
+    supervisor::{Supervisor, SupervisorConfig},
+    telemetry,
+    trade_report::TradeRecord,
     tx_pool::TxPool,
-    world::{Protocol, WorldState},
+    utils::{
+        broadcast::{Broadcaster, TieredSender},
+        fixed_point::whole_units,
+        mev_share::{
+            MevShareClient, DEFAULT_MAX_BLOCKS, FLASHBOTS_MEV_SHARE_STREAM, FLASHBOTS_RELAY,
+        },
+        nonce_guard::NonceGuard,
+        poll_schedule::PollConfig,
+        submitter::Submitter,
+        user_op::UserOpSubmitter,
+    },
+    warmup::RouteTable,
+    world::{Protocol, StaleGuardConfig, WorldState},
 };
 
-abigen!(Flashloan, "abis/FlashloanV3.json");
-
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// use ipc (if running on node)
     #[arg(short, long)]
     use_ipc: bool,
-}
 
-struct Route {
-    amount_in: U256,
-    token_path: Vec<ERC20Token>,
+    /// serve the read-only http api on this address, e.g. 127.0.0.1:8080
+    #[arg(long)]
+    api: Option<SocketAddr>,
+
+    /// emit blocks, pool updates, opportunities, executions, lag, expired txns, collapsed pairs and gas budget alerts as ndjson on stdout
+    #[arg(long)]
+    ndjson: bool,
+
+    /// smallest reserve move of a pair emitted as a pool update
+    #[arg(long, default_value_t = 10)]
+    ndjson_pool_threshold_bps: u64,
+
+    /// ERC-4337 bundler to resubmit through when the EOA can't send or its nonce is stuck
+    #[arg(long, env = "BUNDLER_URL", requires = "smart_account")]
+    bundler_url: Option<String>,
+
+    /// paymaster sponsoring the gas of resubmitted calls
+    #[arg(long, env = "PAYMASTER_URL", requires = "bundler_url")]
+    paymaster_url: Option<String>,
+
+    /// smart account owned by PRIVATE_KEY that resubmitted calls come from
+    #[arg(long, env = "SMART_ACCOUNT")]
+    smart_account: Option<Address>,
+
+    /// seconds between checks of sampled pair reserves against the node, 0 disables
+    #[arg(long, default_value_t = 60)]
+    stale_check_secs: u64,
+
+    /// reserve divergence that forces a resync of all pairs
+    #[arg(long, default_value_t = 5)]
+    stale_tolerance_bps: u64,
+
+    /// drop in a pair's liquidity off its peak that stops routing through
+    /// it, 0 never does
+    #[arg(long, default_value_t = DEFAULT_COLLAPSE_BPS)]
+    collapse_bps: u64,
+
+    /// look for a pair of the same tokens on the other factories when one
+    /// collapses
+    #[arg(long)]
+    scan_replacements: bool,
+
+    /// seconds between simulated swaps through every router, 0 disables
+    #[arg(long, default_value_t = 300)]
+    router_probe_secs: u64,
+
+    /// difference between a probe swap and the router's or local quote
+    /// that fails the probe
+    #[arg(long, default_value_t = 50)]
+    router_probe_tolerance_bps: u64,
+
+    /// blocks between reads of the wallet's and executor's token balances,
+    /// 0 never reads them
+    #[arg(long, default_value_t = DEFAULT_INVENTORY_BLOCKS)]
+    inventory_blocks: u64,
+
+    /// json file of route templates to check instead of the built-in routes
+    #[arg(long)]
+    routes: Option<PathBuf>,
+
+    /// sizes to try the routes of a base token at instead of their amounts,
+    /// e.g. USDC=100,300,1000, repeatable
+    #[arg(long)]
+    ladder: Vec<String>,
+
+    /// price impact on the deepest V2 pair of a hop the ladder is capped
+    /// at, 0 never caps it
+    #[arg(long, default_value_t = 300)]
+    ladder_max_impact_bps: u64,
+
+    /// blocks between requotes of V3 pools that haven't moved lately
+    #[arg(long, default_value_t = 5)]
+    poll_quiet_blocks: u64,
+
+    /// V3 quote move that has its pool requoted every block for a while
+    #[arg(long, default_value_t = 20)]
+    poll_move_bps: u64,
+
+    /// V3 pools requoted per block at most
+    #[arg(long, default_value_t = 50)]
+    poll_budget: usize,
+
+    /// cap V3 hops at the end of their tick range when the next range has
+    /// less than this share of its liquidity, 0 disables
+    #[arg(long, default_value_t = 0.25)]
+    v3_thin_ratio: f64,
+
+    /// refuse to start unless the executor has these feature bits
+    /// (1 min profit, 2 per hop min out, 4 exact output)
+    #[arg(long, default_value_t = 0)]
+    require_executor_features: u64,
+
+    /// buy back exactly the loan on the last hop and keep what's left of
+    /// the token it sells, needs executor feature 4
+    #[arg(long)]
+    exact_output: bool,
+
+    /// routes quoted at once per block, 0 for no limit
+    #[arg(long, default_value_t = 32)]
+    max_route_tasks: usize,
+
+    /// longest wait before restarting a crashed task
+    #[arg(long, default_value_t = 60)]
+    max_restart_backoff_secs: u64,
+
+    /// relay or public RPC to also send txns to, after the node has them
+    #[arg(long)]
+    relay: Vec<String>,
+
+    /// how long a relay gets to accept a txn
+    #[arg(long, default_value_t = 2000)]
+    relay_timeout_ms: u64,
+
+    /// bor producer (signer address) not to send arbs into the blocks of
+    #[arg(long)]
+    skip_producer: Vec<Address>,
+
+    /// storage to keep realized PnL in, not kept without. The address book
+    /// `deploy` recorded the flashloan executor in is read from it, or from
+    /// sqlite://data/tsuki.db without
+    #[arg(long, env = "STORAGE_URL")]
+    storage: Option<String>,
+
+    /// json file of address labels, on top of the built-in ones
+    #[arg(long, default_value = DEFAULT_ADDRESS_TAGS)]
+    address_tags: PathBuf,
+
+    /// wait before requoting a profitable route against reserves reloaded
+    /// from the node, sent only if still profitable, 0 to send on the
+    /// first quote
+    #[arg(long, default_value_t = 0)]
+    confirm_ms: u64,
+
+    /// json file of strategy schedules, the arb runs every block without one
+    #[arg(long, default_value = DEFAULT_SCHEDULES)]
+    schedules: PathBuf,
+
+    /// json file of MATIC budgets for gas per hour and day, nothing is
+    /// capped without one
+    #[arg(long, default_value = DEFAULT_GAS_BUDGETS)]
+    gas_budgets: PathBuf,
+
+    /// rhai script defining filter(opp) to veto or resize opportunities,
+    /// run in the order given, needs --features scripting
+    #[arg(long)]
+    opportunity_filter: Vec<PathBuf>,
+
+    /// seconds without a new head before resubscribing, 0 never does
+    #[arg(long, default_value_t = 10)]
+    head_stall_secs: u64,
+
+    /// seconds without a pending txn before resubscribing, 0 never does
+    #[arg(long, default_value_t = 30)]
+    pending_stall_secs: u64,
+
+    /// blocks in a row a pending txn can bid under the base fee before it's
+    /// dropped from the pool, 0 keeps it until evicted
+    #[arg(long, default_value_t = 5)]
+    pending_ttl_blocks: u64,
+
+    /// age of a head on arrival past which the subscription is behind
+    #[arg(long, default_value_t = 15)]
+    max_head_age_secs: u64,
+
+    /// seconds without a fresh head before submissions stop until one
+    /// arrives, 0 never stops them
+    #[arg(long, default_value_t = 30)]
+    degraded_after_secs: u64,
+
+    /// chain the provider must be on
+    #[arg(long, default_value_t = POLYGON)]
+    chain_id: u64,
+
+    /// MATIC the wallet needs at startup to pay for gas
+    #[arg(long, default_value_t = 1.0)]
+    min_gas_balance: f64,
+
+    /// edge over the amount in a route quote needs to count as above the
+    /// threshold in the route heatmap
+    #[arg(long, default_value_t = DEFAULT_MIN_EDGE_BPS)]
+    heatmap_min_edge_bps: f64,
+
+    /// seconds a sent arb can stay unmined before it's cancelled with a
+    /// self-transfer at a higher fee, 0 waits for it indefinitely
+    #[arg(long, default_value_t = 60)]
+    cancel_after_secs: u64,
+
+    /// seconds a txn left in flight by a previous run gets to be mined at
+    /// startup before it's cancelled
+    #[arg(long, default_value_t = 120)]
+    reconcile_wait_secs: u64,
+
+    /// directory or redis url of the lock redundant instances of the wallet
+    /// share, only the holder submits
+    #[arg(long, env = "LOCK_URL")]
+    lock: Option<String>,
+
+    /// name of this instance in the lock, host and pid by default
+    #[arg(long, env = "INSTANCE_ID")]
+    instance_id: Option<String>,
+
+    /// seconds a lock holder keeps it without heartbeating
+    #[arg(long, default_value_t = 9)]
+    lock_ttl_secs: u64,
+
+    /// redis, nats or kafka url to publish pool updates, opportunities,
+    /// executions and prices to, execution commands are popped from redis
+    #[arg(long, env = "BRIDGE_URL")]
+    bridge: Option<String>,
+
+    /// backrun the txns hinted on an MEV-share stream through the routes,
+    /// bundles are signed with MEV_SHARE_AUTH_KEY
+    #[arg(long)]
+    mev_share: bool,
+
+    /// MEV-share hint stream
+    #[arg(long, default_value = FLASHBOTS_MEV_SHARE_STREAM)]
+    mev_share_stream: String,
+
+    /// relay backrun bundles are sent to
+    #[arg(long, default_value = FLASHBOTS_RELAY)]
+    mev_share_relay: String,
 }
 
-#[inline(always)]
-fn is_profitable(token: ERC20Token, profit: U256, txn_fees: U256) -> bool {
-    // normalize profit to 18 decimals for ease of comparison
-    let profit = profit * U256::exp10((18 - token.get_decimals()).into());
-    // assume 1 MATIC = $0.85
-    let txn_fee_usd = txn_fees
-        .checked_mul(U256::from(85))
-        .unwrap()
-        .checked_div(U256::from(100))
-        .unwrap();
-    profit > txn_fee_usd
+/// tokens tracked, and the ones route templates expand over
+const TOKENS: [ERC20Token; 6] = [USDC, USDT, DAI, WBTC, WMATIC, WETH];
+
+/// blocks between saves of the route heatmap to storage
+const HEATMAP_SAVE_BLOCKS: u64 = 100;
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
-fn construct_arb_params(
-    amount_in: U256,
-    token_path: &Vec<ERC20Token>,
-    protocol_route: &Vec<Protocol>,
-) -> ArbParams {
-    let token_path = token_path.iter().map(|x| x.get_address()).collect();
-    let mut protocol_path = Vec::with_capacity(protocol_route.len());
-    let mut protocol_types = Vec::with_capacity(protocol_route.len());
-    let mut fees = Vec::with_capacity(protocol_route.len());
-    for protocol in protocol_route {
-        match protocol {
-            Protocol::UniswapV2(p) => {
-                protocol_path.push(p.get_router_address());
-                protocol_types.push(0);
-                fees.push(0);
-            }
-            Protocol::UniswapV3 { fee } => {
-                protocol_path.push(UNISWAP_V3.router_address);
-                protocol_types.push(1);
-                fees.push(*fee);
-            }
+/// Loads the V3 pools and quotes the first blocks would otherwise load
+/// mid-opportunity, and estimates the gas of every route as it's best now.
+async fn warm_up<P: PubsubClient + Clone + 'static>(
+    ws: &Arc<WorldState<Provider<P>, P>>,
+    client: &SignerMiddleware<Arc<Provider<P>>, LocalWallet>,
+    routes: &[Route],
+    route_groups: &[RouteGroup],
+    args: &Args,
+    executor: Address,
+    executor_features: &ExecutorFeatures,
+) -> Result<RouteTable, Box<dyn std::error::Error>> {
+    let mut table = RouteTable::new(routes)?;
+    let warmup_started = Instant::now();
+    let head = client.get_block_number().await?.as_u64();
+    let mut paths = Vec::with_capacity(route_groups.len());
+    for group in route_groups {
+        let amounts: Vec<U256> = group.routes.iter().map(|i| routes[*i].amount_in).collect();
+        let depth = match args.ladder_max_impact_bps {
+            0 => None,
+            bps => ws.route_depth(&group.token_path, bps).await,
         };
+        let capped = ladder::cap(&amounts, depth).into_iter().flatten().collect();
+        paths.push((group.token_path.clone(), capped));
     }
+    let warmup = ws.clone().warm_up(head, &paths).await;
+    // the calldata of each route as it's best now, for its gas
+    let best = futures_util::future::join_all(routes.iter().map(|route| {
+        ws.clone()
+            .compute_best_route_hops(route.token_path.clone(), route.amount_in)
+    }))
+    .await;
+    for (i, (route, (amounts_out, protocol_route))) in routes.iter().zip(best).enumerate() {
+        let arb_route = ArbParamsBuilder::from_route(
+            route.amount_in,
+            &route.token_path,
+            &protocol_route,
+            &amounts_out,
+        )
+        .slippage_bps(ARB_SLIPPAGE_BPS)
+        .exact_output(args.exact_output)
+        .build();
+        table[i].pairs = ws.route_pairs(&route.token_path, &protocol_route);
+        table[i].calldata = Some(arb_route.calldata(executor_features, U256::from(head + 1)));
+    }
+    let estimated = table
+        .estimate_gas(client.inner().as_ref(), client.address(), executor)
+        .await;
+    info!(
+        "Warmed up {} routes in {:?}: {} V3 pools, {} V3 quotes, {} gas estimates",
+        table.len(),
+        warmup_started.elapsed(),
+        warmup.v3_pools,
+        warmup.quotes.misses,
+        estimated
+    );
+    Ok(table)
+}
 
-    ArbParams {
-        amount_in: amount_in,
-        token_path: token_path,
-        protocol_path: protocol_path,
-        protocol_types: protocol_types,
-        fees: fees,
+/// The amount and best hops of every route, all sizes of a path quoted in
+/// one pass. `None` for a route past its path's depth, a smaller size is
+/// quoted at it.
+async fn quote_routes<P: PubsubClient + Clone + 'static>(
+    ws: &Arc<WorldState<Provider<P>, P>>,
+    supervisor: &Arc<Supervisor>,
+    resources: &Arc<ResourceUsage>,
+    routes: &[Route],
+    route_groups: &[RouteGroup],
+    max_impact_bps: u64,
+    block_span: &Span,
+) -> Vec<Option<(U256, (Vec<U256>, Vec<Protocol>))>> {
+    let mut futures = Vec::with_capacity(route_groups.len());
+    for (g, group) in route_groups.iter().enumerate() {
+        // every size of a path in one pass, capped at its depth
+        let ws = ws.clone();
+        let token_path = group.token_path.clone();
+        let amounts: Vec<U256> = group.routes.iter().map(|i| routes[*i].amount_in).collect();
+        let quote = async move {
+            let depth = match max_impact_bps {
+                0 => None,
+                bps => ws.route_depth(&token_path, bps).await,
+            };
+            let capped = ladder::cap(&amounts, depth);
+            let quoted: Vec<U256> = capped.iter().flatten().copied().collect();
+            let mut quotes = ws
+                .compute_best_route_ladder(token_path, quoted)
+                .await
+                .into_iter();
+            capped
+                .into_iter()
+                .map(|amount_in| {
+                    amount_in.map(|amount_in| (amount_in, quotes.next().unwrap_or_default()))
+                })
+                .collect::<Vec<_>>()
+        };
+        futures.push(
+            supervisor.spawn_limited(
+                "route",
+                resources
+                    .scope(ARB, quote)
+                    .instrument(debug_span!(parent: block_span, "route", group = g)),
+            ),
+        )
     }
+    let mut quotes = vec![None; routes.len()];
+    for (group, future) in route_groups.iter().zip(futures) {
+        let group_quotes = future.await.unwrap_or_default();
+        for (i, quote) in group.routes.iter().zip(group_quotes) {
+            quotes[*i] = quote;
+        }
+    }
+    quotes
 }
 
 async fn run_loop<P: PubsubClient + Clone + 'static>(
     provider: Arc<Provider<P>>,
     stream_provider: Provider<P>,
     routes: Vec<Route>,
-) {
-    let tokens_list = vec![USDC, USDT, DAI, WBTC, WMATIC, WETH];
+    args: Args,
+    resources: Arc<ResourceUsage>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tokens_list = TOKENS.to_vec();
+    let route_groups = ladder::group_by_path(&routes);
 
-    let txpool = TxPool::init(provider.clone(), 1000);
+    let bus = Arc::new(Bus::default());
+    let supervisor = Arc::new(Supervisor::new(
+        SupervisorConfig {
+            max_backoff: Duration::from_secs(args.max_restart_backoff_secs),
+            ..Default::default()
+        },
+        bus.clone(),
+    ));
+    if args.max_route_tasks > 0 {
+        supervisor.set_limit("route", args.max_route_tasks);
+    }
+
+    let mut txpool = TxPool::init(provider.clone(), 1000).with_bus(bus.clone());
+    if args.pending_stall_secs > 0 {
+        txpool = txpool.with_stall_timeout(Duration::from_secs(args.pending_stall_secs));
+    }
+    if args.pending_ttl_blocks > 0 {
+        txpool = txpool.with_expiry(args.pending_ttl_blocks);
+    }
     let txpool = Arc::new(txpool);
-    tokio::spawn(txpool.clone().stream_mempool());
+    {
+        let txpool = txpool.clone();
+        supervisor.supervise("mempool", move || txpool.clone().stream_mempool());
+    }
+    if args.pending_ttl_blocks > 0 {
+        let txpool = txpool.clone();
+        supervisor.supervise("mempool expiry", move || txpool.clone().expire_on_blocks());
+    }
 
     let ws = WorldState::init(
         provider.clone(),
@@ -100,197 +481,727 @@ async fn run_loop<P: PubsubClient + Clone + 'static>(
         tokens_list,
         UniswapV2::get_all_protoccols(),
     )
-    .await;
+    .await
+    .unwrap_or_else(|e| panic!("Failed to load the pairs: {}", e))
+    .with_bus(bus.clone())
+    .with_poll_config(PollConfig {
+        quiet_blocks: args.poll_quiet_blocks,
+        move_bps: args.poll_move_bps,
+        budget: args.poll_budget,
+        ..Default::default()
+    })
+    .with_collapse_config(CollapseConfig {
+        collapse_bps: args.collapse_bps,
+        scan_replacements: args.scan_replacements,
+    });
 
     let ws = Arc::new(ws);
-    tokio::spawn(ws.clone().stream_data());
+    {
+        let ws = ws.clone();
+        supervisor.supervise("reserves", move || ws.clone().stream_data());
+    }
+    if args.stale_check_secs > 0 {
+        let ws = ws.clone();
+        let config = StaleGuardConfig {
+            interval: Duration::from_secs(args.stale_check_secs),
+            tolerance_bps: args.stale_tolerance_bps,
+            ..Default::default()
+        };
+        supervisor.supervise("stale guard", move || ws.clone().guard_reserves(config));
+    }
+    if args.router_probe_secs > 0 {
+        let ws = ws.clone();
+        let config = ProbeConfig {
+            interval: Duration::from_secs(args.router_probe_secs),
+            tolerance_bps: args.router_probe_tolerance_bps,
+            ..Default::default()
+        };
+        supervisor.supervise("router probes", move || ws.clone().probe_routers(config));
+    }
+    {
+        let (provider, bus) = (provider.clone(), bus.clone());
+        supervisor.supervise("header tracker", move || {
+            HeaderTracker::new(provider.clone(), DEFAULT_MAX_DEPTH).publish_reorgs(bus.clone())
+        });
+        let ws = ws.clone();
+        supervisor.supervise("reorgs", move || ws.clone().follow_reorgs());
+    }
+
+    if args.ndjson {
+        let bus = bus.clone();
+        let ndjson = Arc::new(Ndjson::stdout());
+        let threshold_bps = args.ndjson_pool_threshold_bps;
+        supervisor.supervise("ndjson", move || {
+            ndjson_sink(bus.clone(), ndjson.clone(), threshold_bps)
+        });
+    }
+
+    let producers = Arc::new(ProducerTracker::new(provider.clone()));
+    {
+        let producers = producers.clone();
+        supervisor.supervise("producers", move || producers.clone().run());
+    }
+
+    let chain_id = provider.get_chainid().await.unwrap().as_u64();
+    let storage_url = args.storage.as_deref().unwrap_or(DEFAULT_STORAGE);
+    let book_storage = storage::open(&Secrets::from_env().resolve(storage_url)?).await?;
+    let address_book = AddressBook::load(book_storage.clone()).await?;
+    let tags = AddressTags::load(&args.address_tags, &address_book, chain_id).unwrap();
+    let storage = args.storage.is_some().then_some(book_storage);
+    if let Some(storage) = &storage {
+        let (bus, storage, provider) = (bus.clone(), storage.clone(), provider.clone());
+        supervisor.supervise("recorder", move || {
+            storage_sink(bus.clone(), storage.clone(), provider.clone())
+        });
+    }
+    let mut gas_budgets =
+        GasBudgets::new(BudgetConfig::load_or_default(&args.gas_budgets).unwrap());
+    if let Some(storage) = &storage {
+        gas_budgets = gas_budgets.with_storage(storage.clone());
+    }
+    let gas_budgets = Arc::new(gas_budgets);
+    let api = Arc::new(
+        Api::new(ws.clone(), txpool.clone())
+            .with_tags(tags)
+            .with_resources(resources.clone())
+            .with_gas_budgets(gas_budgets.clone())
+            .with_heatmap(Arc::new(RouteHeatmap::new(args.heatmap_min_edge_bps))),
+    );
+    {
+        let api = api.clone();
+        let bus = bus.clone();
+        supervisor.supervise("api opportunities", move || {
+            let api = api.clone();
+            let mut opportunities = bus.opportunities.subscribe();
+            async move {
+                while let Some(record) = next(&mut opportunities, "opportunities").await {
+                    api.opportunities.push(record).await;
+                }
+            }
+        });
+    }
+    if let Some(addr) = args.api {
+        let api = api.clone();
+        supervisor.supervise("api", move || {
+            let api = api.clone();
+            async move {
+                if let Err(e) = api.serve(addr).await {
+                    error!("API server stopped: {:?}", e);
+                }
+            }
+        });
+    }
 
-    let wallet = std::env::var("PRIVATE_KEY")
+    // spend of the last day still counts against the budgets
+    gas_budgets.sync(unix_now()).await;
+    // route quotes build up across restarts when kept
+    let heatmap_table = storage
+        .clone()
+        .map(|storage| Table::new(storage, ROUTE_HEATMAP));
+    if let Some(table) = &heatmap_table {
+        match api.heatmap.load(table).await {
+            Ok(cells) => info!("Loaded {} route heatmap cells", cells),
+            Err(e) => error!("Failed to load the route heatmap: {:?}", e),
+        }
+    }
+
+    let secrets = Secrets::from_env();
+    let wallet = secrets
+        .resolve_env("PRIVATE_KEY")
         .unwrap()
         .parse::<LocalWallet>()
         .unwrap()
-        .with_chain_id(137u64);
-    let client = SignerMiddleware::new(provider.clone(), wallet);
-    let arbitrage_contract = Flashloan::new(
-        "0x7472bacc648111408497c087826739e7a1e0a6d2"
-            .parse::<Address>()
-            .unwrap(),
-        Arc::new(client),
+        .with_chain_id(chain_id);
+    let user_ops = match (args.bundler_url.as_deref(), args.smart_account) {
+        (Some(bundler_url), Some(smart_account)) => {
+            let mut submitter = UserOpSubmitter::new(
+                provider.clone(),
+                Provider::<Http>::try_from(bundler_url)?,
+                wallet.clone(),
+                smart_account,
+            );
+            if let Some(paymaster_url) = args.paymaster_url.as_deref() {
+                submitter = submitter.with_paymaster(Provider::<Http>::try_from(paymaster_url)?);
+            }
+            Some(submitter)
+        }
+        _ => None,
+    };
+    let nonces = Arc::new(NonceGuard::new(wallet.address()));
+    if let Err(e) = nonces.resync(provider.as_ref()).await {
+        error!("Failed to read wallet nonce: {:?}", e);
+    }
+    {
+        let nonces = nonces.clone();
+        let provider = provider.clone();
+        let bus = bus.clone();
+        supervisor.supervise("nonce guard", move || {
+            nonces.clone().watch(provider.clone(), bus.clone())
+        });
+    }
+    let client = Arc::new(SignerMiddleware::new(provider.clone(), wallet));
+    let submitter = Arc::new(Submitter::new(client.clone(), nonces.clone()));
+    let tiered = if args.relay.is_empty() {
+        None
+    } else {
+        let urls: Vec<&str> = args.relay.iter().map(String::as_str).collect();
+        let relays = Broadcaster::connect(&urls, Duration::from_millis(args.relay_timeout_ms))
+            .await
+            .unwrap();
+        Some(TieredSender::new(
+            provider.clone(),
+            relays,
+            api.channels.clone(),
+        ))
+    };
+    // redundant instances keep quoting, only the lock holder submits
+    let leader = match &args.lock {
+        Some(url) => {
+            let url = Secrets::from_env().resolve(url).unwrap();
+            let instance_id = args.instance_id.clone().unwrap_or_else(|| {
+                let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "arb".to_string());
+                format!("{}-{}", host, std::process::id())
+            });
+            let lock = Arc::new(LeaderLock::new(
+                leader::open(&url).await.unwrap(),
+                format!("arb-{:?}", client.address()),
+                instance_id,
+                Duration::from_secs(args.lock_ttl_secs),
+            ));
+            match lock.heartbeat().await {
+                true => info!("{} holds the lock, submitting", lock.owner()),
+                false => info!("{} standing by for the lock", lock.owner()),
+            }
+            {
+                let lock = lock.clone();
+                supervisor.supervise("leader lock", move || lock.clone().run());
+            }
+            Some(lock)
+        }
+        None => None,
+    };
+    let feedback = Arc::new(Feedback::default());
+    let mut receipts = ReceiptWatcher::new(
+        provider.clone(),
+        submitter.clone(),
+        feedback.clone(),
+        api.prices.clone(),
+        resources.clone(),
+        gas_budgets.clone(),
+    )
+    .with_nonces(nonces.clone());
+    if args.cancel_after_secs > 0 {
+        receipts = receipts.with_cancel_after(Duration::from_secs(args.cancel_after_secs));
+    }
+    if tiered.is_some() {
+        receipts = receipts.with_channels(api.channels.clone());
+    }
+    if let Some(storage) = &storage {
+        receipts = receipts
+            .with_pnl(PnlLedger::new(storage.clone()))
+            .with_trades(Log::<TradeRecord>::new(storage.clone(), EXECUTIONS))
+            .with_in_flight(InFlight::new(storage.clone()));
+        // a standby's view of the wallet is the holder's to settle
+        if leader.as_ref().is_none_or(|leader| leader.is_leader()) {
+            receipts
+                .reconcile(
+                    client.address(),
+                    Duration::from_secs(args.reconcile_wait_secs),
+                )
+                .await;
+            if let Err(e) = nonces.resync(provider.as_ref()).await {
+                error!("Failed to read wallet nonce: {:?}", e);
+            }
+        }
+    }
+    let executor = address_book.resolve(chain_id, FLASHLOAN_EXECUTOR)?;
+    let mut preflight = Preflight::new(args.chain_id)
+        .contract(FLASHLOAN_EXECUTOR, executor)
+        .contract(
+            format!("{} router", UNISWAP_V3.name),
+            UNISWAP_V3.router_address,
+        )
+        .contract(
+            format!("{} factory", UNISWAP_V3.name),
+            UNISWAP_V3.factory_address,
+        )
+        .tokens(&TOKENS)
+        .wallet(client.address(), parse_ether(args.min_gas_balance)?);
+    for protocol in UniswapV2::get_all_protoccols() {
+        preflight = preflight
+            .contract(
+                format!("{} router", protocol.get_name()),
+                protocol.get_router_address(),
+            )
+            .contract(
+                format!("{} factory", protocol.get_name()),
+                protocol.get_factory_address(),
+            );
+    }
+    preflight.run(provider.clone()).await?;
+    let executor_features = probe_executor(provider.as_ref(), executor)
+        .await
+        .and_then(|features| {
+            features.require(args.require_executor_features)?;
+            if args.exact_output {
+                features.require(FEATURE_EXACT_OUTPUT)?;
+            }
+            Ok(features)
+        })
+        .map_err(|e| format!("Executor {:?} unusable: {}", executor, e))?;
+    info!(
+        "Executing through {:?}, version {} features {:#x}",
+        executor, executor_features.version, executor_features.features
     );
+    if let Some(smart_account) = args.smart_account.filter(|_| user_ops.is_some()) {
+        if !accepts_caller(provider.as_ref(), executor, smart_account).await? {
+            return Err(format!(
+                "Executor {:?} doesn't take calls from smart account {:?}, setExecutor it",
+                executor, smart_account
+            )
+            .into());
+        }
+    }
+    if args.inventory_blocks > 0 {
+        let inventory = api.inventory.clone();
+        let provider = provider.clone();
+        let bus = bus.clone();
+        let owners = enum_map! {
+            Holder::Wallet => client.address(),
+            Holder::Executor => executor,
+        };
+        let every_blocks = args.inventory_blocks;
+        supervisor.supervise("inventory", move || {
+            track_inventory(
+                inventory.clone(),
+                provider.clone(),
+                bus.clone(),
+                owners,
+                every_blocks,
+            )
+        });
+    }
+
+    let gate = Schedules::load_or_default(&args.schedules)
+        .and_then(|schedules| schedules.gate(ARB, &[Trigger::Blocks, Trigger::PoolUpdates]))
+        .unwrap();
+    let mut pool_updates = bus.pool_updates.subscribe();
+    let mut head_lag = HeadLag::new(LagConfig {
+        max_head_age: Duration::from_secs(args.max_head_age_secs),
+        ..Default::default()
+    });
+    let head_stall = (args.head_stall_secs > 0).then(|| Duration::from_secs(args.head_stall_secs));
+    let degraded_after =
+        (args.degraded_after_secs > 0).then(|| Duration::from_secs(args.degraded_after_secs));
+    let mut production_gap = degraded_after.map(|after| ProductionGap::new(after, unix_now()));
+
+    let table = warm_up(
+        &ws,
+        &client,
+        &routes,
+        &route_groups,
+        &args,
+        executor,
+        &executor_features,
+    )
+    .await?;
+
+    // quotes are evaluated, sent and settled by subscribers of the bus
+    let mut filters = OpportunityFilters::new();
+    for path in &args.opportunity_filter {
+        filters = filters.register_script(path).unwrap();
+    }
+    let table = Arc::new(table);
+    let mut evaluator = Evaluator::new(
+        ws.clone(),
+        txpool.clone(),
+        routes.clone(),
+        table.clone(),
+        api.prices.clone(),
+        feedback,
+    )
+    .with_heatmap(api.heatmap.clone())
+    .with_filters(filters)
+    .with_v3_thin_ratio(args.v3_thin_ratio);
+    if args.confirm_ms > 0 {
+        evaluator = evaluator.with_confirm_delay(Duration::from_millis(args.confirm_ms));
+    }
+    if args.mev_share {
+        // identifies the searcher to the relay, holds no funds
+        let auth_signer = secrets
+            .resolve_env("MEV_SHARE_AUTH_KEY")?
+            .parse::<LocalWallet>()?;
+        let mev_share = Arc::new(MevShareClient::new(
+            &args.mev_share_stream,
+            &args.mev_share_relay,
+            auth_signer,
+        ));
+        let mut backrunner = ArbBackrunner::new(
+            ws.clone(),
+            routes.clone(),
+            table.clone(),
+            Flashloan::new(executor, client.clone()),
+            executor_features,
+        )
+        .with_exact_output(args.exact_output);
+        if let Some(leader) = &leader {
+            backrunner = backrunner.with_leader(leader.clone());
+        }
+        let (backrunner, resources) = (Arc::new(backrunner), resources.clone());
+        supervisor.supervise("mev-share backruns", move || {
+            let (mev_share, backrunner) = (mev_share.clone(), backrunner.clone());
+            resources.scope(ARB, async move {
+                if let Err(e) = mev_share
+                    .run_backruns(backrunner.as_ref(), DEFAULT_MAX_BLOCKS)
+                    .await
+                {
+                    error!("MEV-share hint stream failed: {}", e);
+                }
+            })
+        });
+    }
+    let mut dispatcher = Dispatcher::new(
+        Flashloan::new(executor, client.clone()),
+        executor_features,
+        nonces.clone(),
+        submitter,
+        resources.clone(),
+        gas_budgets,
+    )
+    .with_exact_output(args.exact_output)
+    .with_skip_producers(args.skip_producer.iter().copied());
+    if let Some(tiered) = tiered {
+        dispatcher = dispatcher.with_tiered(tiered);
+    }
+    if let Some(user_ops) = user_ops {
+        dispatcher = dispatcher.with_user_ops(user_ops);
+    }
+    if let Some(leader) = &leader {
+        dispatcher = dispatcher.with_leader(leader.clone());
+    }
+    if let Some(storage) = &storage {
+        dispatcher = dispatcher.with_in_flight(InFlight::new(storage.clone()));
+    }
+    if let Some(url) = &args.bridge {
+        let url = Secrets::from_env().resolve(url)?;
+        let commands = match bridge::open(&url).await {
+            Ok(bridge) => Some(bridge),
+            // nats and kafka only take events
+            Err(BridgeError::UnsupportedUrl(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let sink: Arc<dyn EventSink> = match &commands {
+            Some(bridge) => bridge.clone(),
+            None => bridge::open_sink(&url).await?,
+        };
+        {
+            let (sink, bus) = (sink.clone(), bus.clone());
+            supervisor.supervise("bridge pool updates", move || {
+                bridge::forward(
+                    sink.clone(),
+                    bridge::POOL_UPDATES,
+                    bus.pool_updates.subscribe(),
+                )
+            });
+        }
+        {
+            let (sink, bus) = (sink.clone(), bus.clone());
+            supervisor.supervise("bridge opportunities", move || {
+                bridge::forward(
+                    sink.clone(),
+                    bridge::OPPORTUNITIES,
+                    bus.opportunities.subscribe(),
+                )
+            });
+        }
+        {
+            let (sink, bus) = (sink.clone(), bus.clone());
+            supervisor.supervise("bridge executions", move || {
+                bridge::forward(sink.clone(), bridge::EXECUTIONS, bus.executions.subscribe())
+            });
+        }
+        {
+            let (sink, bus) = (sink.clone(), bus.clone());
+            supervisor.supervise("bridge prices", move || {
+                bridge::forward(sink.clone(), bridge::PRICES, bus.prices.subscribe())
+            });
+        }
+        if let Some(bridge) = commands {
+            let mut commands =
+                CommandExecutor::new(bridge, client.clone(), nonces.clone(), resources.clone());
+            if let Some(leader) = &leader {
+                commands = commands.with_leader(leader.clone());
+            }
+            let (commands, resources) = (Arc::new(commands), resources.clone());
+            supervisor.supervise("bridge commands", move || {
+                resources.scope(ARB, commands.clone().run())
+            });
+        }
+    }
+    {
+        let (evaluator, bus, resources) = (Arc::new(evaluator), bus.clone(), resources.clone());
+        supervisor.supervise("arb evaluator", move || {
+            resources.scope(ARB, evaluator.clone().run(bus.clone()))
+        });
+    }
+    {
+        let (dispatcher, bus, resources) = (Arc::new(dispatcher), bus.clone(), resources.clone());
+        supervisor.supervise("arb dispatcher", move || {
+            resources.scope(ARB, dispatcher.clone().run(bus.clone()))
+        });
+    }
+    {
+        let (receipts, bus, resources) = (Arc::new(receipts), bus.clone(), resources.clone());
+        supervisor.supervise("arb receipts", move || {
+            resources.scope(ARB, receipts.clone().run(bus.clone()))
+        });
+    }
 
     info!("Setup complete. Detecting arbitrage opportunities...");
     let mut block_stream = provider.subscribe_blocks().await.unwrap();
-    while let Some(block) = block_stream.next().await {
+    loop {
+        // woken without a head to check for stalls
+        let wait = head_stall.or(degraded_after.map(|after| after / 2));
+        let block = match wait {
+            Some(wait) => match timeout(wait, block_stream.next()).await {
+                Ok(block) => block,
+                Err(_) => {
+                    if let Some(lag) = production_gap
+                        .as_mut()
+                        .and_then(|gap| gap.check(unix_now()))
+                    {
+                        error!("No fresh head: {:?}, not submitting until one arrives", lag);
+                        bus.lag.publish(lag);
+                    }
+                    let stall_after = match head_stall {
+                        Some(stall_after) => stall_after,
+                        None => continue,
+                    };
+                    let lag = Lag::Stalled {
+                        subscription: HEADS.to_string(),
+                        secs: stall_after.as_secs(),
+                    };
+                    error!("Head subscription behind: {:?}, resubscribing", lag);
+                    bus.lag.publish(lag);
+                    if let Err(e) = ws.resync_reserves().await {
+                        error!("Failed to resync reserves: {}", e);
+                    }
+                    match provider.subscribe_blocks().await {
+                        Ok(stream) => block_stream = stream,
+                        Err(e) => error!("Resubscribing to heads failed: {}", e),
+                    }
+                    continue;
+                }
+            },
+            None => block_stream.next().await,
+        };
+        let block = match block {
+            Some(block) => block,
+            None => break,
+        };
         let now = Instant::now();
+        let number = block.number.unwrap().as_u64();
 
-        let mut futures = Vec::with_capacity(routes.len());
-        for route in &routes {
-            // calc arb opportunity on each route
-            futures.push(tokio::spawn(
-                ws.clone()
-                    .compute_best_route(route.token_path.to_vec(), route.amount_in),
-            ))
+        // catch up before acting on a head the subscription was late with
+        let head_seen = unix_now();
+        let lags = head_lag.on_head(number, block.timestamp.as_u64(), head_seen);
+        let production = production_gap
+            .as_mut()
+            .and_then(|gap| gap.on_head(number, block.timestamp.as_u64(), head_seen));
+        match production {
+            Some(lag @ Lag::Resumed { .. }) => {
+                info!("Fresh head again: {:?}, submitting", lag);
+                bus.lag.publish(lag);
+            }
+            Some(lag) => {
+                error!("No fresh head: {:?}, not submitting until one arrives", lag);
+                bus.lag.publish(lag);
+            }
+            None => {}
         }
-
-        for (i, future) in futures.into_iter().enumerate() {
-            let token = routes[i].token_path[0];
-            let (est_amount_out, protocol_route) = future.await.unwrap_or_default();
-            let amount_in = routes[i].amount_in;
-            if est_amount_out > amount_in {
-                let profit = est_amount_out - amount_in;
-
-                let params =
-                    construct_arb_params(amount_in, &routes[i].token_path, &protocol_route);
-
-                let est_gas_usage = U256::from(500000);
-                let gas_price = txpool.get_90th_percentile_gas_price().await + U256::from(100);
-                let txn_fees = gas_price.checked_mul(est_gas_usage).unwrap();
-                if !is_profitable(token, profit, txn_fees) {
-                    debug!(
-                        "  Arb not profitable, fee: {:?}, profit: {:?}",
-                        gas_price, profit
-                    );
-                    continue;
+        for lag in &lags {
+            error!("Head subscription behind: {:?}", lag);
+            bus.lag.publish(lag.clone());
+            if let Lag::Gap { from, to } = *lag {
+                for missed in head_lag.fetch_missed(provider.as_ref(), from, to).await {
+                    bus.blocks.publish(missed);
                 }
+            }
+        }
+        if !lags.is_empty() {
+            if let Err(e) = ws.resync_reserves().await {
+                error!("Failed to resync reserves: {}", e);
+            }
+        }
 
-                let current_block_number = block.number.unwrap();
-                let target_block_number = U256::from(current_block_number.as_u64() + 1);
-                let contract_call =
-                    arbitrage_contract.execute_arbitrage(params, target_block_number);
-                match contract_call.gas_price(gas_price).send().await {
-                    Ok(pending_txn) => {
-                        let _ = pending_txn.confirmations(1).await;
-                        info!("  Txn submitted, curr block: {:?}", block.number.unwrap());
-                    }
-                    Err(_) => {
-                        error!(
-                            "  Err received in sending txn. Expected profit: {:?}, Route: {:?}){:?}",
-                            profit,
-                            i,
-                            protocol_route
-                                .into_iter()
-                                .map(|x| match x {
-                                    Protocol::UniswapV2(v) => v.get_name().to_string(),
-                                    Protocol::UniswapV3 { fee } => format!("UniswapV3 {fee}"),
-                                })
-                                .collect::<Vec<String>>()
-                        );
-                        continue;
-                    }
+        let block_span = info_span!("block", number, producer = field::Empty);
+        // the arb lands in the next block at the earliest
+        let producer = producers
+            .schedule()
+            .await
+            .and_then(|schedule| schedule.producer_at(number + 1));
+        if let Some(producer) = producer {
+            block_span.record("producer", field::debug(producer));
+        }
+        let quote_stats = ws.start_block(number);
+        bus.blocks.publish(BlockEvent {
+            number,
+            timestamp: block.timestamp.as_u64(),
+            base_fee: block.base_fee_per_gas,
+        });
+        let snapshot = ws.snapshot().await;
+        info!(
+            "State hash {:?} ({} pools)",
+            snapshot.hash,
+            snapshot.pools.len()
+        );
+        bus.prices
+            .publish(api.prices.update(snapshot.block, &snapshot.pools));
+        debug!(
+            "V3 quote cache hit rate {:.2} ({} hits, {} misses)",
+            quote_stats.hit_rate(),
+            quote_stats.hits,
+            quote_stats.misses
+        );
+
+        // Sync events applied since the last block
+        let mut updated = false;
+        while let Ok(_) | Err(TryRecvError::Lagged(_)) = pool_updates.try_recv() {
+            updated = true;
+        }
+        let event = match updated {
+            true => Trigger::PoolUpdates,
+            false => Trigger::Blocks,
+        };
+        if !gate.admits_now(event) {
+            debug!("  Skipping block, not scheduled on {:?}", event);
+            continue;
+        }
+
+        if let Some(table) = &heatmap_table {
+            if number % HEATMAP_SAVE_BLOCKS == 0 {
+                if let Err(e) = api.heatmap.save(table).await {
+                    error!("Failed to save the route heatmap: {:?}", e);
                 }
+            }
+        }
 
-                info!("  expected profit: {:?}, gas {:?}", profit, gas_price);
-                info!(
-                    "  ({i}), {:?}",
-                    protocol_route
-                        .into_iter()
-                        .map(|x| match x {
-                            Protocol::UniswapV2(v) => v.get_name().to_string(),
-                            Protocol::UniswapV3 { fee } => format!("UniswapV3 {fee}"),
-                        })
-                        .collect::<Vec<String>>(),
-                );
-                break;
+        let quotes = quote_routes(
+            &ws,
+            &supervisor,
+            &resources,
+            &routes,
+            &route_groups,
+            args.ladder_max_impact_bps,
+            &block_span,
+        )
+        .await;
+        // past its depth a route has no quote, a smaller size is quoted at it
+        for (i, quote) in quotes.into_iter().enumerate() {
+            if let Some((amount_in, (amounts_out, protocols))) = quote {
+                bus.quotes.publish(RouteQuote {
+                    block: number,
+                    timestamp: block.timestamp.as_u64(),
+                    route: i,
+                    amount_in,
+                    amounts_out,
+                    protocols,
+                    producer,
+                    span: block_span.clone(),
+                });
             }
         }
         debug!("Time elasped: {:?}ms", now.elapsed().as_millis());
     }
+    Ok(())
+}
+
+/// the routes checked without `--routes`
+fn default_routes() -> Vec<Route> {
+    let paths = [
+        vec![USDC, WETH, USDC],
+        vec![USDC, WMATIC, USDC],
+        vec![USDT, WETH, USDT],
+        vec![USDT, WMATIC, USDT],
+    ];
+    let mut routes = Vec::new();
+    for amount in [10000, 5000, 1000, 300] {
+        for token_path in &paths {
+            if let Some(amount_in) = whole_units(amount, token_path[0].get_decimals()) {
+                routes.push(Route {
+                    amount_in,
+                    token_path: token_path.clone(),
+                });
+            }
+        }
+    }
+    routes
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    env_logger::init();
+    let _telemetry = telemetry::init("arb");
     let args = Args::parse();
 
-    let routes = vec![
-        Route {
-            amount_in: U256::from(10000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WETH, USDC],
-        },
-        Route {
-            amount_in: U256::from(10000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WMATIC, USDC],
-        },
-        Route {
-            amount_in: U256::from(10000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WETH, USDT],
-        },
-        Route {
-            amount_in: U256::from(10000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WMATIC, USDT],
-        },
-        Route {
-            amount_in: U256::from(5000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WETH, USDC],
-        },
-        Route {
-            amount_in: U256::from(5000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WMATIC, USDC],
-        },
-        Route {
-            amount_in: U256::from(5000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WETH, USDT],
-        },
-        Route {
-            amount_in: U256::from(5000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WMATIC, USDT],
-        },
-        Route {
-            amount_in: U256::from(1000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WETH, USDC],
-        },
-        Route {
-            amount_in: U256::from(1000) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WMATIC, USDC],
-        },
-        Route {
-            amount_in: U256::from(1000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WETH, USDT],
-        },
-        Route {
-            amount_in: U256::from(1000) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WMATIC, USDT],
-        },
-        Route {
-            amount_in: U256::from(300) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WETH, USDC],
-        },
-        Route {
-            amount_in: U256::from(300) * U256::exp10(USDC.get_decimals().into()),
-            token_path: vec![USDC, WMATIC, USDC],
-        },
-        Route {
-            amount_in: U256::from(300) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WETH, USDT],
-        },
-        Route {
-            amount_in: U256::from(300) * U256::exp10(USDT.get_decimals().into()),
-            token_path: vec![USDT, WMATIC, USDT],
-        },
-    ];
+    let routes = match &args.routes {
+        Some(path) => load_routes(path, &TOKENS)?,
+        None => default_routes(),
+    };
+    let routes = SizeLadder::parse(&args.ladder)?.apply(routes);
+    info!("Checking {} routes", routes.len());
 
-    let rpc_node_ws_url = std::env::var("ALCHEMY_POLYGON_RPC_WS_URL")?;
-    let alc_provider_ws = Arc::new(Provider::<Ws>::connect(&rpc_node_ws_url).await?);
+    // requests are counted against the arb when its loop or route quoting
+    // makes them, against everything else when the background tasks do
+    let resources = Arc::new(ResourceUsage::new());
+    resources.register(ARB);
+    let rpc_node_ws_url = Secrets::from_env().resolve_env("ALCHEMY_POLYGON_RPC_WS_URL")?;
     if args.use_ipc {
         info!("Using IPC");
-        let provider_ipc = Provider::connect_ipc("path/to/your/bor.ipc").await?;
+        let provider_ipc = Metered::provider(
+            Ipc::connect("path/to/your/bor.ipc").await?,
+            resources.clone(),
+            SHARED,
+        );
         let provider_ipc = Arc::new(provider_ipc);
-        run_loop(
+        let run = run_loop(
             provider_ipc,
-            Provider::connect_ipc("path/to/your/bor.ipc").await?,
+            Metered::provider(
+                Ipc::connect("path/to/your/bor.ipc").await?,
+                resources.clone(),
+                SHARED,
+            ),
             routes,
-        )
-        .await;
+            args,
+            resources.clone(),
+        );
+        // the exit code prints the error's Debug, the operator wants its message
+        resources
+            .scope(ARB, run)
+            .await
+            .inspect_err(|e| error!("{}", e))?;
     } else {
         info!("Using Alchemy");
-
-        run_loop(
-            alc_provider_ws.clone(),
-            Provider::<Ws>::connect(&rpc_node_ws_url).await?,
+        let alc_provider_ws = Arc::new(Metered::provider(
+            Ws::connect(&rpc_node_ws_url).await?,
+            resources.clone(),
+            SHARED,
+        ));
+        let run = run_loop(
+            alc_provider_ws,
+            Metered::provider(
+                Ws::connect(&rpc_node_ws_url).await?,
+                resources.clone(),
+                SHARED,
+            ),
             routes,
-        )
-        .await;
+            args,
+            resources.clone(),
+        );
+        resources
+            .scope(ARB, run)
+            .await
+            .inspect_err(|e| error!("{}", e))?;
     }
 
     Ok(())
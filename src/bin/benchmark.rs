@@ -5,14 +5,13 @@ use ethers::providers::Ipc;
 use ethers::signers::{LocalWallet, Signer, Wallet};
 use ethers::types::{BigEndianHash, BlockNumber, H256, H64};
 use ethers::types::{Transaction, TxHash, U64};
-use ethers::utils::{hex, rlp};
+use ethers::utils::rlp;
 use ethers::{
     providers::{Middleware, Provider},
     types::{Address, Bytes, GethDebugTracingOptions, TransactionRequest, U256},
     utils,
 };
 use futures_util::StreamExt;
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::time::Duration;
@@ -20,71 +19,17 @@ use std::{sync::Arc, time::Instant};
 use tsuki::constants::protocol::UniswapV2;
 use tsuki::constants::token::ERC20Token;
 use tsuki::tx_pool::TxPool;
-use tsuki::uniswapV2::UniswapV2Client;
+use tsuki::uniswapV2::{SwapParams, UniswapV2Client};
 use tsuki::utils::batch::common::BatchRequest;
 use tsuki::utils::batch::BatchProvider;
 use tsuki::utils::block::{self, Block, PartialHeader};
+use tsuki::utils::block_simulator::BlockSimulator;
+use tsuki::utils::tracer::{DebugTraceExt, TraceConfig};
 use tsuki::utils::transaction::{
-    build_typed_transaction, EIP1559Transaction, EIP2930Transaction, EthTransactionRequest,
+    sign_typed_request, EIP1559Transaction, EIP2930Transaction, EthTransactionRequest,
     TypedTransaction,
 };
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct TraceConfig {
-    pub disable_storage: bool,
-    pub disable_stack: bool,
-    pub enable_memory: bool,
-    pub enable_return_data: bool,
-    pub tracer: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tracer_config: Option<TracerConfig>,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct TracerConfig {
-    pub only_top_call: bool,
-    pub with_log: bool,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-pub struct Res {
-    pub result: BlockTraceResult,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct BlockTraceResult {
-    pub from: Address,
-    pub gas: U256,
-    pub gas_used: U256,
-    pub input: Bytes,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub output: Option<Bytes>,
-    pub to: Address,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub r#type: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub value: Option<U256>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub calls: Option<Vec<BlockTraceResult>>,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct TxpoolEntry {
-    pub hash: H256,
-    pub gas_price: U256,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-pub struct TxpoolContent {
-    pub pending: HashMap<Address, HashMap<U256, TxpoolEntry>>,
-    pub queued: HashMap<Address, HashMap<U256, TxpoolEntry>>,
-}
+use tsuki::utils::txpool::TxpoolExt;
 
 abigen!(
     ERC20,
@@ -100,28 +45,21 @@ fn gen_txn(
     gas_price: U256,
     nonce: U256,
 ) -> TypedTransaction {
-    let txn = txn.as_eip1559_ref().unwrap();
-
-    let txn_req: EthTransactionRequest = tsuki::utils::transaction::EthTransactionRequest {
-        from: Some(signer_client.address()),
-        to: Some(to),
-        gas_price: None,
-        max_fee_per_gas: Some(gas_price),
-        max_priority_fee_per_gas: Some(gas_price),
-        gas: Some(500_000.into()),
-        value: Some(0.into()),
-        data: txn.data.clone(),
-        nonce: Some(nonce),
-        access_list: None,
-        transaction_type: None,
-    };
+    let mut txn_req: EthTransactionRequest = txn.into();
+    txn_req.from = Some(signer_client.address());
+    txn_req.to = Some(to);
+    txn_req.gas = Some(500_000.into());
+    txn_req.value = Some(0.into());
+    txn_req.nonce = Some(nonce);
+    if txn_req.gas_price.is_some() {
+        txn_req.gas_price = Some(gas_price);
+    } else {
+        txn_req.max_fee_per_gas = Some(gas_price);
+        txn_req.max_priority_fee_per_gas = Some(gas_price);
+    }
 
     let ttr = txn_req.into_typed_request().unwrap();
-    let mut ethers_ttr: ethers::types::transaction::eip2718::TypedTransaction = ttr.clone().into();
-    ethers_ttr.set_from(signer_client.address());
-    ethers_ttr.set_chain_id(137);
-    let signature = signer_client.signer().sign_transaction_sync(&ethers_ttr);
-    return build_typed_transaction(ttr, signature);
+    return sign_typed_request(ttr, signer_client.signer());
 }
 
 #[tokio::main]
@@ -177,11 +115,21 @@ async fn old_main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("{:?}", approve_tx.tx);
 
+    let expected_out = uniswap_client
+        .quote(
+            UniswapV2::SUSHISWAP,
+            ERC20Token::USDC,
+            ERC20Token::USDT,
+            U256::from(1_000_000),
+        )
+        .await?;
     let swap_tx = uniswap_client.get_swapExactTokensForTokens_txn(
         UniswapV2::SUSHISWAP,
         tsuki::constants::token::ERC20Token::USDC,
         tsuki::constants::token::ERC20Token::USDT,
         U256::from(1_000_000),
+        expected_out,
+        &SwapParams::new(signer_client.address()),
     );
 
     let approve_tx = gen_txn(
@@ -200,39 +148,14 @@ async fn old_main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     let block_number = provider_ipc.get_block_number().await?.as_u64();
-    let block_number = utils::serialize(&block_number);
-
-    let bytes = provider_ipc
-        .request::<_, Bytes>("debug_getBlockRlp", [block_number])
-        .await?;
-
-    let block: Block = rlp::decode(&bytes)?;
-    let mut txns = block.transactions;
-    txns.push(swap_tx);
-    txns.push(approve_tx);
-    let sim_block: Block = Block::new(block.header.into(), txns, block.ommers);
-
-    let sim_block_rlp = rlp::encode(&sim_block);
-    let sim_block_rlp = ["0x", &hex::encode(sim_block_rlp)].join("");
-    let sim_block_rlp = utils::serialize(&sim_block_rlp);
-
-    let config = TraceConfig {
-        disable_storage: true,
-        disable_stack: true,
-        enable_memory: false,
-        enable_return_data: false,
-        tracer: "callTracer".to_string(),
-        tracer_config: Some(TracerConfig {
-            only_top_call: true,
-            with_log: false,
-        }),
-    };
-    let config = utils::serialize(&config);
+    let mut simulator = BlockSimulator::from_block(provider_ipc.clone(), block_number)
+        .await?
+        .with_config(TraceConfig::call_tracer(true));
+    simulator.push(swap_tx);
+    simulator.push(approve_tx);
 
     let now = Instant::now();
-    let result = provider_ipc
-        .request::<_, Vec<Res>>("debug_traceBlock", [sim_block_rlp, config])
-        .await?;
+    let result = simulator.simulate().await?;
     println!("Time elapsed: {}ms", now.elapsed().as_millis());
 
     println!("Number in result: {:?}", result.len());
@@ -264,16 +187,12 @@ async fn txpool_content() -> Result<(), Box<dyn std::error::Error>> {
     while let Some(block) = block_stream.next().await {
         if block.number.unwrap() == start_block_num + 2 {
             // pull mempool transactions
-            let content = provider_ipc
-                .request::<_, TxpoolContent>("txpool_content", ())
-                .await?;
-            let pending = content.pending;
-            for (_address, nonce_map) in pending {
-                for (_nonce, entry) in nonce_map {
-                    pending_txn_hashs.insert(entry.hash);
-                    gas_prices.push(entry.gas_price);
-                    mapping.insert(entry.hash, entry.gas_price);
-                }
+            let content = provider_ipc.get_txpool_content().await?;
+            for entry in content.pending_transactions() {
+                let gas_price = entry.gas_price.unwrap_or_default();
+                pending_txn_hashs.insert(entry.hash);
+                gas_prices.push(gas_price);
+                mapping.insert(entry.hash, gas_price);
             }
         } else if block.number.unwrap() == start_block_num + 3 {
             let mut local_gas_prices = Vec::<U256>::new();
@@ -323,27 +242,9 @@ async fn debug_traceBlock() -> Result<(), Box<dyn std::error::Error>> {
 
     let block: Block = rlp::decode(&bytes)?;
     println!("Number of txns: {:?}", block.transactions.len());
-    // let block_rlp = rlp::encode(&block);
-    let block_rlp = ["0x", &hex::encode(bytes)].join("");
-    // println!("{:?}", block_rlp);
-    let block_rlp = utils::serialize(&block_rlp);
-
-    let config = TraceConfig {
-        disable_storage: true,
-        disable_stack: true,
-        enable_memory: false,
-        enable_return_data: false,
-        tracer: "callTracer".to_string(),
-        tracer_config: Some(TracerConfig {
-            only_top_call: true,
-            with_log: false,
-        }),
-    };
-    let config = utils::serialize(&config);
+    let config = TraceConfig::call_tracer(true);
 
-    let result = provider_ipc
-        .request::<_, Vec<Res>>("debug_traceBlock", [block_rlp, config])
-        .await?;
+    let result = provider_ipc.call_trace_block(bytes, &config).await?;
 
     println!("Number in result: {:?}", result.len());
     println!("{:?}", result);
@@ -356,26 +257,12 @@ async fn debug_traceBlockByNumber() -> Result<(), Box<dyn std::error::Error>> {
     let provider_ipc = Arc::new(provider_ipc);
 
     let block_number = provider_ipc.get_block_number().await?;
-    let config = TraceConfig {
-        disable_storage: true,
-        disable_stack: true,
-        enable_memory: false,
-        enable_return_data: false,
-        tracer: "callTracer".to_string(),
-        tracer_config: Some(TracerConfig {
-            only_top_call: true,
-            with_log: false,
-        }),
-    };
+    let config = TraceConfig::call_tracer(true);
     let mut results = vec![];
     let now = Instant::now();
     for i in 0..4 {
-        let block_number = utils::serialize(&(block_number - i));
-        let config = utils::serialize(&config);
-        results.push(provider_ipc.request::<_, Vec<BlockTraceResult>>(
-            "debug_traceBlockByNumber",
-            [block_number, config],
-        ));
+        let block_number = BlockNumber::Number(block_number - i);
+        results.push(provider_ipc.call_trace_block_by_number(block_number, &config));
     }
     for result in results {
         let _res = result.await?;
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use ethers::{
+    providers::{Http, Provider},
+    types::Address,
+};
+
+use tsuki::{
+    activity::{summarize, ActivityIndexer, ActivityRecord},
+    export::{export, Dataset, Format},
+    spreads::{recorded_spread_report, SpreadConfig},
+    storage::{self, Log, ACTIVITY, DEFAULT_STORAGE},
+    trade_report::recorded_trade_report,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// storage the bots record to
+    #[arg(long, env = "STORAGE_URL", default_value = DEFAULT_STORAGE)]
+    storage: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// dump a recorded dataset to csv or parquet
+    Export {
+        #[arg(value_enum)]
+        dataset: Dataset,
+        #[arg(short, long, value_enum, default_value = "parquet")]
+        format: Format,
+        #[arg(short, long)]
+        out: PathBuf,
+        /// only entries after this sequence number, for incremental dumps
+        #[arg(long, default_value_t = 0)]
+        after: u64,
+    },
+    /// report spreads between venues of the same pair over the recorded
+    /// reserves, and how often they stayed too small to arb after gas
+    Spreads {
+        /// gas of a two hop arb
+        #[arg(long, default_value_t = 500_000)]
+        gas_units: u64,
+        /// trade the gas cost is spread over
+        #[arg(long, default_value_t = 1_000.0)]
+        trade_size_usd: f64,
+        #[arg(long, default_value_t = 0.85)]
+        matic_usd: f64,
+        /// venue pairs printed
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// trade statistics of the arbs the bot executed in a block range, to
+    /// compare runs with different thresholds or configs
+    Report {
+        #[arg(long, default_value_t = 0)]
+        from_block: u64,
+        #[arg(long, default_value_t = u64::MAX)]
+        to_block: u64,
+        /// print the report as json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// record the arbitrages and liquidations mined in a block range, and
+    /// print who executed them
+    Index {
+        #[arg(long)]
+        from: u64,
+        #[arg(long)]
+        to: u64,
+        #[arg(long, env = "ALCHEMY_POLYGON_RPC_URL")]
+        rpc_url: String,
+        /// our bot's contract, marked in the summary
+        #[arg(long)]
+        ours: Option<Address>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+    env_logger::init();
+    let args = Args::parse();
+
+    let storage = storage::open(&args.storage).await?;
+    match args.command {
+        Command::Export {
+            dataset,
+            format,
+            out,
+            after,
+        } => {
+            let rows = export(storage, dataset, format, &out, after).await?;
+            println!("wrote {} rows to {}", rows, out.display());
+        }
+        Command::Spreads {
+            gas_units,
+            trade_size_usd,
+            matic_usd,
+            top,
+        } => {
+            let config = SpreadConfig {
+                gas_units,
+                trade_size_usd,
+                matic_usd,
+                ..Default::default()
+            };
+            for stats in recorded_spread_report(storage, config)
+                .await?
+                .iter()
+                .take(top)
+            {
+                println!(
+                    "{:?}/{:?} {}-{} blocks {} spread mean {:.1} p50 {:.1} p90 {:.1} bps, cost {:.1} bps, below cost {:.0}% (longest {} blocks), arbable {}",
+                    stats.token0,
+                    stats.token1,
+                    stats.venue_a,
+                    stats.venue_b,
+                    stats.blocks,
+                    stats.mean_spread_bps,
+                    stats.p50_spread_bps,
+                    stats.p90_spread_bps,
+                    stats.mean_cost_bps,
+                    stats.below_cost_share() * 100.0,
+                    stats.longest_below_cost_run,
+                    stats.arbable_blocks,
+                );
+            }
+        }
+        Command::Report {
+            from_block,
+            to_block,
+            json,
+        } => {
+            let report = recorded_trade_report(storage, from_block, to_block).await?;
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&report)?),
+                false => print!("{}", report),
+            }
+        }
+        Command::Index {
+            from,
+            to,
+            rpc_url,
+            ours,
+        } => {
+            let indexer = ActivityIndexer::new(Provider::<Http>::try_from(rpc_url)?);
+            let records = indexer.scan(from, to).await?;
+            let log: Log<ActivityRecord> = Log::new(storage, ACTIVITY);
+            for record in &records {
+                log.append(record).await?;
+            }
+            println!("indexed {} txns in blocks {}..={}", records.len(), from, to);
+            for stats in summarize(&records) {
+                println!(
+                    "{:?}{} arbitrages {} liquidations {} gas {} profit {:?}",
+                    stats.searcher,
+                    if Some(stats.searcher) == ours {
+                        " (ours)"
+                    } else {
+                        ""
+                    },
+                    stats.arbitrages,
+                    stats.liquidations,
+                    stats.gas_cost,
+                    stats.profit,
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,41 +1,68 @@
-use std::{convert::TryFrom, sync::Arc};
+use std::{convert::TryFrom, path::PathBuf, sync::Arc};
 
+use clap::Parser;
 use dotenv::dotenv;
 use ethers::{
-    prelude::{abigen, SignerMiddleware},
+    prelude::SignerMiddleware,
     providers::{Http, Middleware, Provider},
     signers::{LocalWallet, Signer},
-    types::Address,
 };
 
-abigen!(Liquidations, "abis/Liquidations.json");
-abigen!(Flashloan, "abis/FlashloanV3.json");
+use tsuki::{
+    address_book::AddressBook,
+    deploy::{deploy, DeployConfig},
+    secrets::Secrets,
+    storage::{self, DEFAULT_STORAGE},
+};
+
+/// Deploys a contract from PRIVATE_KEY and records its address in the
+/// address book
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// json with the artifact, constructor args, address book name and gas policy
+    config: PathBuf,
+
+    #[arg(long, env = "ALCHEMY_POLYGON_RPC_URL")]
+    rpc_url: String,
+
+    /// storage the bots read the address book from
+    #[arg(long, env = "STORAGE_URL", default_value = DEFAULT_STORAGE)]
+    storage: String,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    env_logger::init();
+    let args = Args::parse();
+    let config = DeployConfig::load(&args.config)?;
 
-    let rpc_node_url = std::env::var("ALCHEMY_POLYGON_RPC_URL")?;
-
-    let wallet = std::env::var("PRIVATE_KEY")?
+    let secrets = Secrets::from_env();
+    let provider = Provider::<Http>::try_from(secrets.resolve(&args.rpc_url)?)?;
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let wallet = secrets
+        .resolve_env("PRIVATE_KEY")?
         .parse::<LocalWallet>()?
-        .with_chain_id(137_u64);
-    let provider = Provider::<Http>::try_from(rpc_node_url)?;
-    let provider = Arc::new(provider);
-
-    // 4. instantiate the client with the wallet
-    let client = Arc::new(SignerMiddleware::new(provider.clone(), wallet));
-
-    let gas_price = provider.get_gas_price().await?;
-    let deploy_txn = Flashloan::deploy(
-        client,
-        "0xBA12222222228d8Ba445958a75a0704d566BF2C8"
-            .parse::<Address>()
-            .unwrap(),
-    )
-    .unwrap();
+        .with_chain_id(chain_id);
+    let client = Arc::new(SignerMiddleware::new(provider, wallet));
 
-    deploy_txn.gas_price(gas_price).send().await?;
+    let (address, receipt) = deploy(client, &config).await?;
+    println!(
+        "deployed {} at {:?} in block {:?}, txn {:?}, gas used {}",
+        config.name,
+        address,
+        receipt.block_number.unwrap_or_default(),
+        receipt.transaction_hash,
+        receipt.gas_used.unwrap_or_default(),
+    );
 
+    let storage = storage::open(&secrets.resolve(&args.storage)?).await?;
+    let mut book = AddressBook::load(storage.clone()).await?;
+    if let Some(previous) = book.insert(chain_id, config.name.clone(), address) {
+        println!("replaced {} at {:?}", config.name, previous);
+    }
+    book.save(storage).await?;
+    println!("recorded in the address book");
     Ok(())
 }
@@ -1,291 +1,471 @@
-use std::str::FromStr;
-use std::sync::Arc;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use dotenv::dotenv;
+use enum_map::Enum;
 use ethers::prelude::{abigen, SignerMiddleware};
-use ethers::providers::{Http, ProviderError, SubscriptionStream};
+use ethers::providers::Http;
+use ethers::providers::{Middleware, PendingTransaction, Ws};
 use ethers::signers::{LocalWallet, Signer};
-use ethers::types::{GethTrace, Transaction, U256, U64};
-use ethers::utils;
-use ethers::{
-    abi::{parse_abi, Token},
-    prelude::{BaseContract, Provider},
-    providers::{Middleware, Ws},
-    types::{Address, Bytes},
-};
+use ethers::types::{BlockNumber, Filter, Log, U256};
+use ethers::utils::parse_ether;
 use futures_util::StreamExt;
-use serde::{Deserialize, Serialize};
-use serde_json::value::RawValue;
+use tokio::sync::RwLock;
+use tsuki::address_book::{AddressBook, LIQUIDATOR, POLYGON};
+use tsuki::address_tags::{AddressTags, DEFAULT_ADDRESS_TAGS};
+use tsuki::arb_params::probe_executor;
+use tsuki::bus::{next, BlockEvent, Bus, ExecutionEvent};
+use tsuki::constants::{protocol::UniswapV2, token::ERC20Token};
+use tsuki::events::ExecutionStatus;
+use tsuki::gas_budget::{BudgetConfig, GasBudgets, GasSpend, DEFAULT_GAS_BUDGETS};
+use tsuki::liquidator::{
+    competitors::{CompetitorSet, LIQUIDATION_CALL_EVENT},
+    gas::{effective_gas_price, GasAuction, RivalBids},
+    postmortem::{RaceReport, RaceTracker},
+    routes::{FlashloanPools, RouteFinder, DEFAULT_FLASHLOAN_POOLS},
+    simulation::simulate_liquidation,
+    Liquidator, OpportunitySource, AAVE_V3_POOL, KNOWN_LIQUIDATORS,
+};
+use tsuki::preflight::Preflight;
+use tsuki::resources::{Metered, ResourceUsage};
+use tsuki::schedule::{Schedules, Trigger, DEFAULT_SCHEDULES, LIQUIDATIONS};
+use tsuki::secrets::Secrets;
+use tsuki::storage::{self, DEFAULT_STORAGE, RACES};
+use tsuki::uniswapV2::IUniswapV2Router02;
 
 abigen!(Liquidations, "abis/Liquidations.json");
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct PendingTransactionOptions {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub from_address: Option<Vec<String>>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub to_address: Option<Vec<String>>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub hashes_only: Option<bool>,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct DebugTraceCallOptions {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub from: Option<String>,
-    #[serde(default)]
-    pub to: String,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub gas_price: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub value: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub data: Option<String>,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct DebugTraceCallTracer {
-    #[serde(default)]
-    pub tracer: String,
-}
-
-impl DebugTraceCallOptions {
-    pub fn generate(txn: Transaction) -> Self {
-        // let gas_price = match txn.gas_price {
-        //     //Some(gp) => Some(format!("{:#x}", gp.as_u128())),
-        //     Some(_) => Some("0x7a120".to_string()),
-        //     None => None,
-        // };
-        DebugTraceCallOptions {
-            from: Some(format!("{:?}", txn.from)),
-            to: format!("{:?}", txn.to.unwrap()),
-            gas_price: None,
-            value: Some(format!("{:#x}", txn.value.as_u128())),
-            data: Some(txn.input.to_string()),
-        }
-    }
-}
-
-impl DebugTraceCallTracer {
-    pub fn new() -> Self {
-        DebugTraceCallTracer {
-            tracer: "callTracer".to_string(),
-        }
-    }
-}
-
-async fn get_args(
-    provider: &Provider<Http>,
-    txn: Transaction,
-    encoded_function_preface: &str,
-) -> Option<String> {
-    let a = DebugTraceCallOptions::generate(txn);
-    let a = utils::serialize(&a);
-    let b = "pending";
-    let b = utils::serialize(&b);
-    let c = DebugTraceCallTracer::new();
-    let c = utils::serialize(&c);
-
-    let res: ProviderError = provider
-        .request::<_, GethTrace>("debug_traceCall", [a, b, c])
-        .await
-        .unwrap_err();
-    let response = res.to_string();
-    match response.find(encoded_function_preface) {
-        Some(index) => {
-            let str = &response[index..index + 330];
-            Some(str.to_string())
-        }
-        None => None,
-    }
-}
-
-fn parse_args(contract: &BaseContract, input: &str) -> Vec<Token> {
-    let bytes = Bytes::from_str(input).unwrap();
-    let args = contract.decode_raw("liquidationCall", bytes).unwrap();
-    return args;
-}
-
-const WETH: &str = "0x7ceb23fd6bc0add59e62ac25578270cff1b9f619";
-const USDT: &str = "0xc2132d05d31c914a87c6611c10748aeb04b58e8f";
-const DAI: &str = "0x8f3cf7ad23cd3cadbd9735aff958023239c6a063";
-const WBTC: &str = "0x1bfd67037b42cf73acf2047067bd4f2c47d9bfd6";
-const WMATIC: &str = "0x0d500b1d8e8ef31e21c99d1db9a6444d3adf1270";
-const USDC: &str = "0x2791bca1f2de4661ed88a30c99a7a9449aa84174";
-
-const QUICKSWAP: &str = "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff";
-
-fn get_dodo_pool(token_address: Address) -> Option<Address> {
-    match format!("{:?}", token_address).as_str() {
-        WETH => Some(
-            "0x5333Eb1E32522F1893B7C9feA3c263807A02d561"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        USDT => Some(
-            "0x20B5F71DAF95c712E776Af8A3b7926fa8FDA5909"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        DAI => Some(
-            "0x20B5F71DAF95c712E776Af8A3b7926fa8FDA5909"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        WBTC => Some(
-            "0xe020008465cD72301A18b97d33D73bF44858A4b7"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        WMATIC => Some(
-            "0xeB5CE2e035Dd9562a6d0a639A68D372eFb21D22e"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        USDC => Some(
-            "0x5333Eb1E32522F1893B7C9feA3c263807A02d561"
-                .parse::<Address>()
-                .unwrap(),
-        ),
-        _ => None,
-    }
-}
+// roughly a day of polygon blocks
+const COMPETITOR_LOOKBACK: u64 = 40_000;
+const NUM_WATCHED_LIQUIDATORS: usize = 25;
+// races still open after this were never liquidated
+const RACE_TIMEOUT: Duration = Duration::from_secs(600);
+const USAGE_LOG_INTERVAL: Duration = Duration::from_secs(600);
+// MATIC the wallet needs at startup to pay for gas
+const MIN_GAS_BALANCE: u64 = 1;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    let rpc_node_ws_url = std::env::var("ALCHEMY_POLYGON_RPC_WS_URL")?;
-    let provider = Provider::<Http>::try_from(std::env::var("ALCHEMY_POLYGON_RPC_URL")?)?;
+    let secrets = Secrets::from_env();
+    let rpc_node_ws_url = secrets.resolve_env("ALCHEMY_POLYGON_RPC_WS_URL")?;
+    // everything this binary does is the liquidation strategy's
+    let resources = Arc::new(ResourceUsage::new());
+    let provider = Metered::provider(
+        Http::from_str(&secrets.resolve_env("ALCHEMY_POLYGON_RPC_URL")?)?,
+        resources.clone(),
+        LIQUIDATIONS,
+    );
     let provider = Arc::new(provider);
-    let provider_ws = Provider::<Ws>::connect(&rpc_node_ws_url).await?;
+    let provider_ws = Metered::provider(
+        Ws::connect(&rpc_node_ws_url).await?,
+        resources.clone(),
+        LIQUIDATIONS,
+    );
     let provider_ws = Arc::new(provider_ws);
+    let usage_log = resources.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(USAGE_LOG_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for usage in usage_log.all() {
+                println!(
+                    "Strategy {}: {} rpc calls, {} submissions",
+                    usage.strategy, usage.rpc_calls, usage.submissions
+                );
+            }
+        }
+    });
 
-    let wallet = std::env::var("PRIVATE_KEY")?
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let wallet = secrets
+        .resolve_env("PRIVATE_KEY")?
         .parse::<LocalWallet>()?
-        .with_chain_id(137u64);
+        .with_chain_id(chain_id);
+    let wallet_address = wallet.address();
 
     let client = SignerMiddleware::new(provider_ws.clone(), wallet);
     let client = Arc::new(client);
 
-    let contract = BaseContract::from(
-        parse_abi(&[
-            "function liquidationCall(address collateral, address debt, address user, uint256 debtToCover, bool receiveAToken)",
-        ])?
-    );
+    // shared with the arb when both keep their state in the same storage
+    let storage_url = std::env::var("STORAGE_URL").unwrap_or_else(|_| DEFAULT_STORAGE.to_string());
+    let storage = storage::open(&secrets.resolve(&storage_url)?).await?;
 
-    let liquidations_contract = Liquidations::new(
-        "0x5D03B3678c120F3EcC04eb96dAAb6e15B012022e".parse::<Address>()?,
-        client,
-    );
+    let address_book = AddressBook::load(storage.clone()).await?;
+    let tags = AddressTags::load(DEFAULT_ADDRESS_TAGS, &address_book, chain_id)?;
+    let liquidations_contract =
+        Liquidations::new(address_book.resolve(chain_id, LIQUIDATOR)?, client);
+    let mut preflight = Preflight::new(POLYGON)
+        .contract(LIQUIDATOR, liquidations_contract.address())
+        .contract("AAVE pool", *AAVE_V3_POOL)
+        .tokens(
+            &(0..ERC20Token::LENGTH)
+                .map(ERC20Token::from_usize)
+                .collect::<Vec<_>>(),
+        )
+        .wallet(wallet_address, parse_ether(MIN_GAS_BALANCE)?);
+    for protocol in UniswapV2::get_all_protoccols() {
+        preflight = preflight.contract(
+            format!("{} router", protocol.get_name()),
+            protocol.get_router_address(),
+        );
+    }
+    preflight.run(provider.clone()).await?;
 
-    let encoded_prefix = "0x00a718a9";
+    // only the original interface is called, but a missing or newer
+    // contract should stop us here rather than on-chain
+    let executor = probe_executor(&*provider, liquidations_contract.address()).await?;
+    println!(
+        "Liquidating through {:?}, version {} features {:#x}",
+        liquidations_contract.address(),
+        executor.version,
+        executor.features
+    );
 
     // TODO maybe change? this is quite a alot
     let max_gas = U256::from(15_650_000);
 
-    // construct stream
-    let known_liquidators = [
-        "0x54999CBEA7ec48A373aCE8A5dDc1D6e6fF7F8202",
-        "0x28d62d755D561e7468734Cd63c62ec960Cd4c1A7",
-        "0x87C76A8A5d8D24250752F93BDC232B18997dDa15",
-        "0x0000000eb7D8244007Da6CD63A512eC69494b231",
-        "0xB8f013e063F59719D05b3F1F9076b4DC7e56FAe7",
-        "0xEb7e2AeB58b55bc419BDAD48A8c39e2C6d7CEB84",
-        "0x14770cD80fa8055c12BC092255496CA8D0fFCF5e",
-        "0x88E2840bA66c7B618f37AEE2DD9c448997D41690",
-        "0x774b407f518C91ae79250625291AA14440D5d8fB",
-        "0x98648D396a35D1FF9ED354432B2C98C37931F69C",
-        "0x3BB7a0f2fe88ABA35408C64F588345481490Fe93",
-    ]
-    .map(|x| x.to_string())
-    .to_vec();
-    let method = utils::serialize(&"alchemy_pendingTransactions");
-    let method_params = utils::serialize(&PendingTransactionOptions {
-        to_address: Some(known_liquidators),
-        from_address: None,
-        hashes_only: None,
-    });
-    let mut pending_txn_stream: SubscriptionStream<Ws, Box<RawValue>> =
-        provider_ws.subscribe([method, method_params]).await?;
+    // learn who has been winning liquidations lately and watch them
+    let mut competitors = CompetitorSet::load(storage.clone()).await?;
+    let latest_block = provider.get_block_number().await?.as_u64();
+    let from_block = u64::max(
+        competitors.last_scanned_block + 1,
+        latest_block.saturating_sub(COMPETITOR_LOOKBACK),
+    );
+    let num_found = competitors
+        .scan(&*provider, from_block, latest_block)
+        .await?;
+    competitors.prune(latest_block, COMPETITOR_LOOKBACK * 7);
+    if competitors.is_empty() {
+        // nothing learned yet, fall back to the liquidators we already know about
+        competitors = CompetitorSet::with_seed(&KNOWN_LIQUIDATORS);
+    }
+    competitors.save(storage.clone()).await?;
+    println!(
+        "Found {} liquidations since block {}, watching {} liquidators",
+        num_found,
+        from_block,
+        competitors.len().min(NUM_WATCHED_LIQUIDATORS)
+    );
 
-    println!("Listening to transactions");
-    while let Some(item) = pending_txn_stream.next().await {
-        if let Ok(txn) = serde_json::from_str::<Transaction>(item.get()) {
+    let gate =
+        Schedules::load_or_default(DEFAULT_SCHEDULES)?.gate(LIQUIDATIONS, &[Trigger::Mempool])?;
+    let gas_budgets = Arc::new(
+        GasBudgets::new(BudgetConfig::load_or_default(DEFAULT_GAS_BUDGETS)?)
+            .with_storage(storage.clone()),
+    );
+    let flashloan_pools = FlashloanPools::load(DEFAULT_FLASHLOAN_POOLS)?;
+
+    let liquidator = Liquidator::new(
+        provider.clone(),
+        Metered::provider(
+            Ws::connect(&rpc_node_ws_url).await?,
+            resources.clone(),
+            LIQUIDATIONS,
+        ),
+        competitors.top(NUM_WATCHED_LIQUIDATORS),
+    );
+    let liquidator = Arc::new(liquidator);
+    let mut opportunities = liquidator.opportunities().await;
+
+    // heads, executions and budget alerts go through the bus to whoever
+    // tracks or prints them
+    let bus = Arc::new(Bus::default());
+    let mut budget_alerts = bus.budgets.subscribe();
+    tokio::spawn(async move {
+        while let Some(alert) = next(&mut budget_alerts, "budgets").await {
+            println!(
+                "{} {} gas budget {:?}: {} of {} wei spent",
+                alert.window,
+                alert.scope,
+                alert.level.unwrap(),
+                alert.spent,
+                alert.limit
+            );
+        }
+    });
+    let mut executions = bus.executions.subscribe();
+    tokio::spawn(async move {
+        while let Some(execution) = next(&mut executions, "executions").await {
             println!(
-                "Detected liquidation transaction with hash: {}",
-                format!("{:?}", txn.hash)
+                "Txn {:?} of block {} {:?}, gas used {}",
+                execution.tx_hash,
+                execution.block,
+                execution.status,
+                execution.gas_used.unwrap_or_default()
             );
+        }
+    });
 
-            let gas_fee: Option<U256> = match txn.transaction_type {
-                Some(id) if id == U64::from(2) => {
-                    let max_priority_fee_per_gas = txn.max_priority_fee_per_gas;
-                    let max_gas_fee = txn.max_fee_per_gas;
-                    if max_priority_fee_per_gas == None && max_gas_fee == None {
-                        println!("  Needed to compute gas price on own");
-                        Some(provider.get_gas_price().await.unwrap())
-                    } else if let Some(f) = max_priority_fee_per_gas {
-                        Some(f)
-                    } else {
-                        Some(max_gas_fee.unwrap())
-                    }
-                }
-                _ => {
-                    // if let Some(gas_price) = txn.gas_price {
-                    //     // todo complete
-                    //     return Some(gas_price);
-                    // }
-                    // return None;
-                    let val = provider.get_gas_price().await.unwrap();
-                    Some(val)
-                }
-            };
+    // time, number and base fee of the latest block, for timing gas bids
+    let latest = provider.get_block(BlockNumber::Latest).await?.unwrap();
+    let head = Arc::new(RwLock::new((
+        Instant::now(),
+        latest.number.unwrap().as_u64(),
+        latest.base_fee_per_gas.unwrap_or_default(),
+    )));
+    let head_writer = head.clone();
+    let mut blocks = bus.blocks.subscribe();
+    tokio::spawn(async move {
+        while let Some(block) = next(&mut blocks, "blocks").await {
+            *head_writer.write().await = (
+                Instant::now(),
+                block.number,
+                block.base_fee.unwrap_or_default(),
+            );
+        }
+    });
+    let block_provider = provider_ws.clone();
+    let block_bus = bus.clone();
+    tokio::spawn(async move {
+        let mut stream = block_provider.subscribe_blocks().await.unwrap();
+        while let Some(block) = stream.next().await {
+            block_bus.blocks.publish(BlockEvent {
+                number: block.number.unwrap().as_u64(),
+                timestamp: block.timestamp.as_u64(),
+                base_fee: block.base_fee_per_gas,
+            });
+        }
+    });
+
+    let auction = GasAuction::default();
+    let mut rival_bids = RivalBids::default();
+    let mut routes = RouteFinder::new(provider.clone(), flashloan_pools);
+    // prices profits in matic for gas bidding
+    let router =
+        IUniswapV2Router02::new(UniswapV2::QUICKSWAP.get_router_address(), provider.clone());
+    let wmatic = ERC20Token::WMATIC.get_address();
+
+    // settle races against mined liquidations to see why we lose them
+    let liquidation_filter = Filter::new()
+        .address(*AAVE_V3_POOL)
+        .event(LIQUIDATION_CALL_EVENT);
+    let mut liquidations = provider_ws.subscribe_logs(&liquidation_filter).await?;
+    let mut races = RaceTracker::new(liquidations_contract.address());
+    let race_log = storage::Log::<RaceReport>::new(storage.clone(), RACES);
+
+    println!("Listening to transactions");
+    loop {
+        let opportunity = tokio::select! {
+            Some(log) = liquidations.next() => {
+                settle_race(&*provider, &mut races, &race_log, &tags, &log).await;
+                continue;
+            }
+            opportunity = opportunities.next() => match opportunity {
+                Some(opportunity) => opportunity,
+                None => break,
+            },
+        };
+        races.expire(RACE_TIMEOUT);
+        if !gate.admits_now(Trigger::Mempool) {
+            continue;
+        }
+        let OpportunitySource::PendingTransaction(txn) = &opportunity.source;
+        println!(
+            "Detected liquidation transaction with hash: {:?} from {}, expected bonus: {}",
+            txn.hash,
+            tags.describe(txn.from),
+            opportunity.expected_bonus
+        );
+
+        let (last_block_at, block_number, base_fee) = *head.read().await;
+        let rival_bid = effective_gas_price(txn, base_fee);
+        let rival_bid = rival_bids.record(block_number, opportunity.user, rival_bid);
+        races.observe(&opportunity, rival_bid);
+        let (user, debt) = (opportunity.user, opportunity.debt);
 
-            if gas_fee == None {
-                println!("  Could not estimate gas...");
+        let route = match routes
+            .find(
+                opportunity.collateral,
+                opportunity.debt,
+                opportunity.debt_to_cover,
+            )
+            .await
+        {
+            Ok(route) => route,
+            Err(e) => {
+                println!("  Skipping: {}", e);
+                races.skipped(user, debt, e);
                 continue;
             }
-            let gas_fee = gas_fee.unwrap();
+        };
+        println!("  Swapping collateral on {}", route.protocol.get_name());
 
-            if let Some(liquidation_call_args) = get_args(&provider, txn, encoded_prefix).await {
-                let args = parse_args(&contract, liquidation_call_args.as_str());
-                let mut args = args.into_iter();
+        // pass args into smart contract and win $$$
+        let contract_call = liquidations_contract
+            .liquidation(
+                route.flashloan_pool,
+                route.router(),
+                opportunity.collateral,
+                opportunity.debt,
+                opportunity.user,
+                opportunity.debt_to_cover,
+            )
+            .from(wallet_address)
+            .gas(max_gas);
 
-                let collateral = args.next().unwrap().into_address().unwrap();
-                let debt = args.next().unwrap().into_address().unwrap();
-                let user = args.next().unwrap().into_address().unwrap();
-                let debt_amount = args.next().unwrap().into_uint().unwrap();
+        // only broadcast if the whole flashloan -> liquidation -> swap nets a profit
+        let sim = match simulate_liquidation(
+            &*provider,
+            &contract_call.tx,
+            opportunity.debt,
+            wallet_address,
+        )
+        .await
+        {
+            Ok(sim) if sim.is_profitable() => {
+                println!("  Simulated profit: {}, gas: {}", sim.profit, sim.gas_used);
+                races.simulated(user, debt, sim.profit);
+                sim
+            }
+            Ok(sim) => {
+                let reason = match sim.revert {
+                    Some(reason) => format!("simulation reverted: {}", reason),
+                    None => "simulation not profitable".to_string(),
+                };
+                println!("  {}", reason);
+                races.skipped(user, debt, reason);
+                continue;
+            }
+            Err(e) => {
+                println!("  Simulation failed: {}", e);
+                races.skipped(user, debt, format!("simulation failed: {}", e));
+                continue;
+            }
+        };
 
-                let dodo_pool = get_dodo_pool(debt);
-                if let Some(dodo_pool) = dodo_pool {
-                    let uniswap_router = QUICKSWAP.parse::<Address>().unwrap();
+        // gas is paid in matic, price the profit in it before bidding
+        let profit = if opportunity.debt == wmatic {
+            sim.profit
+        } else {
+            match router
+                .get_amounts_out(sim.profit, vec![opportunity.debt, wmatic])
+                .call()
+                .await
+            {
+                Ok(amounts) => amounts[1],
+                Err(e) => {
+                    println!("  Could not price profit: {}", e);
+                    races.skipped(user, debt, format!("could not price profit: {}", e));
+                    continue;
+                }
+            }
+        };
+        let gas_price = match auction.bid(
+            profit,
+            sim.gas_used,
+            Some(rival_bid),
+            last_block_at.elapsed(),
+        ) {
+            Some(gas_price) => gas_price,
+            None => {
+                println!("  Rival bid {} exceeds our gas cap", rival_bid);
+                races.skipped(user, debt, "rival bid exceeds gas cap");
+                continue;
+            }
+        };
+        println!("  Bidding gas price {} against {}", gas_price, rival_bid);
+        for alert in gas_budgets.sync(unix_now()).await {
+            bus.budgets.publish(alert);
+        }
+        // the simulated gas, the limit is far above what it uses
+        if let Some(e) = gas_budgets.exceeded(
+            wallet_address,
+            LIQUIDATIONS,
+            sim.gas_used * gas_price,
+            unix_now(),
+        ) {
+            println!("  Skipping, {}", e);
+            races.skipped(user, debt, e.to_string());
+            continue;
+        }
 
-                    // pass args into smart contract and win $$$
-                    match liquidations_contract
-                        .liquidation(
-                            dodo_pool,
-                            uniswap_router,
-                            collateral,
-                            debt,
-                            user,
-                            debt_amount,
-                        )
-                        .gas(max_gas)
-                        .gas_price(gas_fee + gas_fee) // double gas price for speedup
-                        .send()
+        match contract_call.gas_price(gas_price).send().await {
+            Ok(pending_txn) => {
+                println!("  Txn submitted: {}", pending_txn.tx_hash());
+                resources.record_submission(LIQUIDATIONS);
+                races.submitted(user, debt, pending_txn.tx_hash(), gas_price);
+                let tx_hash = pending_txn.tx_hash();
+                let provider = provider.clone();
+                let gas_budgets = gas_budgets.clone();
+                let bus = bus.clone();
+                tokio::spawn(async move {
+                    let receipt = PendingTransaction::new(tx_hash, provider.as_ref())
                         .await
-                    {
-                        Ok(pending_txn) => {
-                            println!("  Txn submitted: {}", pending_txn.tx_hash())
+                        .ok()
+                        .flatten();
+                    if let Some(receipt) = &receipt {
+                        let paid = receipt.gas_used.unwrap_or_default()
+                            * receipt.effective_gas_price.unwrap_or_default();
+                        let alerts = gas_budgets
+                            .record(GasSpend {
+                                timestamp: unix_now(),
+                                wallet: wallet_address,
+                                strategy: LIQUIDATIONS.to_string(),
+                                paid,
+                            })
+                            .await;
+                        for alert in alerts {
+                            bus.budgets.publish(alert);
                         }
-                        Err(e) => println!("    Err received: {}", e),
                     }
-                }
+                    let status = match &receipt {
+                        Some(receipt) if receipt.status == Some(1.into()) => {
+                            ExecutionStatus::Confirmed
+                        }
+                        Some(_) => ExecutionStatus::Reverted,
+                        None => ExecutionStatus::Dropped,
+                    };
+                    bus.executions.publish(ExecutionEvent {
+                        block: block_number,
+                        tx_hash,
+                        status,
+                        gas_used: receipt.and_then(|receipt| receipt.gas_used),
+                    });
+                });
             }
-        }
+            Err(e) => println!("    Err received: {}", e),
+        };
     }
 
     Ok(())
 }
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn settle_race<M: Middleware>(
+    provider: &M,
+    races: &mut RaceTracker,
+    race_log: &storage::Log<RaceReport>,
+    tags: &AddressTags,
+    log: &Log,
+) {
+    let (tx_hash, block_hash) = match (log.transaction_hash, log.block_hash) {
+        (Some(tx_hash), Some(block_hash)) => (tx_hash, block_hash),
+        _ => return,
+    };
+    let winning_gas_price = match provider.get_transaction_receipt(tx_hash).await {
+        Ok(Some(receipt)) => receipt.effective_gas_price.unwrap_or_default(),
+        _ => U256::zero(),
+    };
+    let block_timestamp = match provider.get_block(block_hash).await {
+        Ok(Some(block)) => block.timestamp.as_u64(),
+        _ => 0,
+    };
+    if let Some(report) = races.settle(log, winning_gas_price, block_timestamp) {
+        println!("{}", report);
+        println!("  winner: {}", tags.describe(report.winner));
+        if let Err(e) = race_log.append(&report).await {
+            println!("  Could not save race report: {}", e);
+        }
+    }
+}
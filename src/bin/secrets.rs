@@ -0,0 +1,46 @@
+use std::{io::Read, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+
+use tsuki::secrets::{encrypt_to_file, Secrets, PASSPHRASE_ENV};
+
+/// Encrypts secrets for `file:` references and checks that references
+/// resolve
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// encrypts the secret on stdin to a file with SECRETS_PASSPHRASE
+    Encrypt { path: PathBuf },
+    /// resolves a reference, printing only whether it did
+    Check { reference: String },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+    match Args::parse().command {
+        Command::Encrypt { path } => {
+            let passphrase = std::env::var(PASSPHRASE_ENV)
+                .map_err(|_| format!("{} must be set", PASSPHRASE_ENV))?;
+            let mut secret = String::new();
+            std::io::stdin().read_to_string(&mut secret)?;
+            encrypt_to_file(&path, secret.trim_end_matches('\n'), &passphrase)?;
+            println!(
+                "encrypted to {}, use file:{}",
+                path.display(),
+                path.display()
+            );
+        }
+        Command::Check { reference } => {
+            let secret = Secrets::from_env().resolve(&reference)?;
+            println!("resolved, {} characters", secret.len());
+        }
+    }
+    Ok(())
+}
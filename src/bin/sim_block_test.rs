@@ -5,9 +5,12 @@ use ethers::providers::{JsonRpcClient, Middleware, ProviderError};
 use ethers::types::transaction::eip2718::TypedTransaction;
 use ethers::types::{GethTrace, Transaction};
 use ethers::utils::serialize;
-use ethers::{providers::Provider, types::U256};
+use ethers::{
+    providers::Provider,
+    types::{Address, U256},
+};
 use tsuki::constants::{protocol::UniswapV2, token::ERC20Token};
-use tsuki::uniswapV2::UniswapV2Client;
+use tsuki::uniswapV2::{SwapParams, UniswapV2Client};
 
 abigen!(
     ERC20,
@@ -31,11 +34,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let provider_ipc = Arc::new(provider_ipc);
 
     let client = UniswapV2Client::new(provider_ipc.clone());
+    let expected_out = client
+        .quote(
+            UniswapV2::QUICKSWAP,
+            ERC20Token::USDC,
+            ERC20Token::USDT,
+            U256::from(1_000_000),
+        )
+        .await?;
+    let recipient = "0x06a92D032d97D5a3c9F550e551B4B6f42518A07B"
+        .parse::<Address>()
+        .unwrap();
     let tx = client.get_swapExactTokensForTokens_txn(
         UniswapV2::QUICKSWAP,
         ERC20Token::USDC,
         ERC20Token::USDT,
         U256::from(1_000_000),
+        expected_out,
+        &SwapParams::new(recipient),
     );
 
     let token_contract = ERC20::new(ERC20Token::USDC.get_address(), provider_ipc.clone());
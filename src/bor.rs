@@ -0,0 +1,278 @@
+//! Who produces Polygon PoS blocks. Bor hands every sprint (`SPRINT_LENGTH`
+//! consecutive blocks) to one producer picked from the validator set by
+//! Tendermint style proposer priority, so the producer of the next sprint
+//! can be worked out from the current set. Backup producers only sign when
+//! the primary misses its slot, the schedule here is the primary's.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Middleware, ProviderError, PubsubClient},
+    types::Address,
+};
+use futures_util::StreamExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+/// blocks per sprint since the Delhi fork
+pub const SPRINT_LENGTH: u64 = 16;
+
+// bor rescales priorities so they stay within this many times the total power
+const PRIORITY_WINDOW_SIZE_FACTOR: i64 = 2;
+
+/// as returned by `bor_getCurrentValidators`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validator {
+    #[serde(rename = "ID")]
+    pub id: u64,
+    pub signer: Address,
+    pub power: i64,
+    /// proposer priority
+    pub accum: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorSet {
+    validators: Vec<Validator>,
+}
+
+impl ValidatorSet {
+    pub fn new(validators: Vec<Validator>) -> Self {
+        Self { validators }
+    }
+
+    pub fn validators(&self) -> &[Validator] {
+        &self.validators
+    }
+
+    pub fn total_power(&self) -> i64 {
+        self.validators.iter().map(|v| v.power).sum()
+    }
+
+    /// The set one sprint on and the producer it picks, as bor's
+    /// `IncrementProposerPriority(1)`. Bor also shifts priorities by their
+    /// average, which never changes who is picked so it's left out.
+    pub fn increment(&self) -> Option<(ValidatorSet, Address)> {
+        let total_power = self.total_power();
+        let mut validators = self.validators.clone();
+
+        let max = validators.iter().map(|v| v.accum).max()?;
+        let min = validators.iter().map(|v| v.accum).min()?;
+        let window = PRIORITY_WINDOW_SIZE_FACTOR * total_power;
+        let diff = max - min;
+        if window > 0 && diff > window {
+            let ratio = (diff + window - 1) / window;
+            for validator in validators.iter_mut() {
+                validator.accum /= ratio;
+            }
+        }
+
+        for validator in validators.iter_mut() {
+            validator.accum += validator.power;
+        }
+        // highest priority, ties go to the lower address
+        let proposer = validators.iter_mut().reduce(|best, v| {
+            if v.accum > best.accum || (v.accum == best.accum && v.signer < best.signer) {
+                v
+            } else {
+                best
+            }
+        })?;
+        proposer.accum -= total_power;
+        let signer = proposer.signer;
+        Some((ValidatorSet::new(validators), signer))
+    }
+}
+
+/// Producers of the sprint a block was seen in and of the one after it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub sprint_length: u64,
+    pub sprint: u64,
+    pub current: Address,
+    pub next: Address,
+}
+
+impl Schedule {
+    pub fn sprint_of(&self, block: u64) -> u64 {
+        block / self.sprint_length
+    }
+
+    /// first block of `sprint`
+    pub fn sprint_start(&self, sprint: u64) -> u64 {
+        sprint * self.sprint_length
+    }
+
+    /// `None` past the next sprint, or for blocks before the current one
+    pub fn producer_at(&self, block: u64) -> Option<Address> {
+        let sprint = self.sprint_of(block);
+        if sprint == self.sprint {
+            Some(self.current)
+        } else if sprint == self.sprint + 1 {
+            Some(self.next)
+        } else {
+            None
+        }
+    }
+
+    /// blocks after `block` still produced by the same producer
+    pub fn blocks_left(&self, block: u64) -> u64 {
+        self.sprint_start(self.sprint_of(block) + 1) - block - 1
+    }
+}
+
+/// bor_* extensions, implemented for every middleware
+#[async_trait]
+pub trait BorExt: Middleware {
+    async fn current_proposer(&self) -> Result<Address, ProviderError> {
+        self.provider().request("bor_getCurrentProposer", ()).await
+    }
+
+    async fn current_validators(&self) -> Result<Vec<Validator>, ProviderError> {
+        self.provider()
+            .request("bor_getCurrentValidators", ())
+            .await
+    }
+}
+
+impl<M: Middleware> BorExt for M {}
+
+/// Keeps the producer schedule up to date with the chain, refetching the
+/// validator set from bor when a new sprint starts.
+pub struct ProducerTracker<M> {
+    provider: Arc<M>,
+    sprint_length: u64,
+    schedule: RwLock<Option<Schedule>>,
+}
+
+impl<M: Middleware + 'static> ProducerTracker<M> {
+    pub fn new(provider: Arc<M>) -> Self {
+        Self {
+            provider,
+            sprint_length: SPRINT_LENGTH,
+            schedule: RwLock::new(None),
+        }
+    }
+
+    /// for chains (or forks of the past) with other sprints
+    pub fn with_sprint_length(mut self, sprint_length: u64) -> Self {
+        self.sprint_length = sprint_length;
+        self
+    }
+
+    /// the schedule as of the last refresh, `None` before the first
+    pub async fn schedule(&self) -> Option<Schedule> {
+        self.schedule.read().await.clone()
+    }
+
+    pub async fn current_producer(&self) -> Option<Address> {
+        self.schedule().await.map(|schedule| schedule.current)
+    }
+
+    pub async fn next_producer(&self) -> Option<Address> {
+        self.schedule().await.map(|schedule| schedule.next)
+    }
+
+    /// refetches the producers for the sprint of `block`, the latest block
+    pub async fn refresh(&self, block: u64) -> Result<Schedule, ProviderError> {
+        let current = self.provider.current_proposer().await?;
+        let validators = ValidatorSet::new(self.provider.current_validators().await?);
+        // an empty set can't produce blocks, keep the producer then
+        let next = validators
+            .increment()
+            .map(|(_, next)| next)
+            .unwrap_or(current);
+        let schedule = Schedule {
+            sprint_length: self.sprint_length,
+            sprint: block / self.sprint_length,
+            current,
+            next,
+        };
+        *self.schedule.write().await = Some(schedule.clone());
+        Ok(schedule)
+    }
+
+    /// Refreshes on the first block seen of every sprint, until the block
+    /// subscription ends or can't be made.
+    pub async fn run(self: Arc<Self>)
+    where
+        <M as Middleware>::Provider: PubsubClient,
+    {
+        let mut block_stream = match self.provider.subscribe_blocks().await {
+            Ok(block_stream) => block_stream,
+            Err(e) => {
+                warn!("Failed to subscribe to blocks: {}", e);
+                return;
+            }
+        };
+        while let Some(block) = block_stream.next().await {
+            let number = match block.number {
+                Some(number) => number.as_u64(),
+                None => continue,
+            };
+            let stale = match self.schedule().await {
+                Some(schedule) => schedule.sprint != schedule.sprint_of(number),
+                None => true,
+            };
+            if !stale {
+                continue;
+            }
+            match self.refresh(number).await {
+                Ok(schedule) => debug!(
+                    "Sprint {} produced by {:?}, next by {:?}",
+                    schedule.sprint, schedule.current, schedule.next
+                ),
+                Err(e) => warn!("Failed to refresh bor producers: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(id: u64, power: i64, accum: i64) -> Validator {
+        Validator {
+            id,
+            signer: Address::from_low_u64_be(id),
+            power,
+            accum,
+        }
+    }
+
+    #[test]
+    fn test_bor_producer_rotation() {
+        let json = r#"[{"ID":1,"signer":"0x0000000000000000000000000000000000000001","power":3,"accum":0}]"#;
+        let parsed: Vec<Validator> = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed, vec![validator(1, 3, 0)]);
+
+        // powers 3:1, the heavier validator produces 3 sprints out of 4
+        let mut set = ValidatorSet::new(vec![validator(1, 3, 0), validator(2, 1, 0)]);
+        let mut producers = Vec::new();
+        for _ in 0..4 {
+            let (next, producer) = set.increment().unwrap();
+            producers.push(producer.to_low_u64_be());
+            set = next;
+        }
+        assert_eq!(producers, vec![1, 1, 2, 1]);
+        // equal priority goes to the lower address
+        let tied = ValidatorSet::new(vec![validator(2, 1, 0), validator(1, 1, 0)]);
+        assert_eq!(tied.increment().unwrap().1, Address::from_low_u64_be(1));
+        assert!(ValidatorSet::new(vec![]).increment().is_none());
+
+        let schedule = Schedule {
+            sprint_length: SPRINT_LENGTH,
+            sprint: 10,
+            current: Address::from_low_u64_be(1),
+            next: Address::from_low_u64_be(2),
+        };
+        assert_eq!(schedule.producer_at(175), Some(Address::from_low_u64_be(1)));
+        assert_eq!(schedule.producer_at(176), Some(Address::from_low_u64_be(2)));
+        assert_eq!(schedule.producer_at(192), None);
+        assert_eq!(schedule.blocks_left(160), 15);
+        assert_eq!(schedule.blocks_left(175), 0);
+    }
+}
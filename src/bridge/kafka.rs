@@ -0,0 +1,66 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use rskafka::{
+    chrono::Utc,
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        Client, ClientBuilder,
+    },
+    record::Record,
+};
+use tokio::sync::Mutex;
+
+use super::{BridgeError, EventSink};
+
+/// Produces to partition 0 of the Kafka topic named after the topic, so
+/// consumers see events in the order they happened. Topics have to exist,
+/// producing to a missing one fails instead of waiting for it.
+pub struct KafkaSink {
+    client: Client,
+    partitions: Mutex<HashMap<String, Arc<PartitionClient>>>,
+}
+
+impl KafkaSink {
+    /// `brokers` like `["localhost:9092"]`
+    pub async fn connect(brokers: Vec<String>) -> Result<Self, BridgeError> {
+        Ok(Self {
+            client: ClientBuilder::new(brokers).build().await?,
+            partitions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn partition(&self, topic: &str) -> Result<Arc<PartitionClient>, BridgeError> {
+        let mut partitions = self.partitions.lock().await;
+        if let Some(partition) = partitions.get(topic) {
+            return Ok(partition.clone());
+        }
+        let partition = Arc::new(
+            self.client
+                .partition_client(topic, 0, UnknownTopicHandling::Error)
+                .await?,
+        );
+        partitions.insert(topic.to_string(), partition.clone());
+        Ok(partition)
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+        let record = Record {
+            key: None,
+            value: Some(payload),
+            headers: BTreeMap::new(),
+            timestamp: Utc::now(),
+        };
+        self.partition(topic)
+            .await?
+            .produce(vec![record], Compression::NoCompression)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+//! Message bridges for running the detector and the executor as separate
+//! processes, on separate machines if need be. The detector publishes pool
+//! updates and opportunities to topics and pushes execution commands to a
+//! queue, each command is taken by exactly one executor.
+//!
+//! Payloads are json. Commands carry the detector's trace context (see
+//! `telemetry::trace_context`) so both halves end up in the same trace.
+//!
+//! Kafka and NATS are sinks only, for streaming the same events into
+//! existing pipelines rather than driving an executor.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use ethers::types::{Address, Bytes, U256};
+use futures_util::{stream::BoxStream, StreamExt};
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{info_span, Span};
+
+use crate::telemetry;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "redis")]
+pub mod redis;
+
+#[cfg(feature = "kafka")]
+pub use self::kafka::KafkaSink;
+#[cfg(feature = "nats")]
+pub use self::nats::NatsSink;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisBridge;
+
+pub const POOL_UPDATES: &str = "tsuki.pool_updates";
+pub const OPPORTUNITIES: &str = "tsuki.opportunities";
+pub const MEMPOOL_CLASSIFICATIONS: &str = "tsuki.mempool_classifications";
+pub const EXECUTIONS: &str = "tsuki.executions";
+pub const PRICES: &str = "tsuki.prices";
+pub const COMMANDS: &str = "tsuki.commands";
+
+#[derive(Error, Debug)]
+pub enum BridgeError {
+    #[cfg(feature = "redis")]
+    #[error(transparent)]
+    RedisError(#[from] ::redis::RedisError),
+
+    #[cfg(feature = "nats")]
+    #[error(transparent)]
+    NatsConnectError(#[from] async_nats::ConnectError),
+
+    #[cfg(feature = "nats")]
+    #[error(transparent)]
+    NatsPublishError(#[from] async_nats::PublishError),
+
+    #[cfg(feature = "kafka")]
+    #[error(transparent)]
+    KafkaError(#[from] rskafka::client::error::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("no bridge for {0}, built without the feature of its scheme?")]
+    UnsupportedUrl(String),
+}
+
+/// A txn the detector wants sent, for whichever executor pops it first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionCommand {
+    /// block the opportunity was found at
+    pub block: u64,
+    /// pointless to send once this block is mined
+    pub target_block: u64,
+    pub to: Address,
+    pub calldata: Bytes,
+    pub gas_limit: U256,
+    pub gas_price: U256,
+    /// in the route's start token
+    pub expected_profit: U256,
+    #[serde(default)]
+    pub trace_context: HashMap<String, String>,
+}
+
+/// Anything events can be published to.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), BridgeError>;
+}
+
+/// What a broker needs to provide on top of publishing, topics are fan out
+/// and queues deliver each payload once.
+#[async_trait]
+pub trait Bridge: EventSink {
+    /// payloads published to `topic` from now on
+    async fn subscribe(&self, topic: &str) -> Result<BoxStream<'static, Vec<u8>>, BridgeError>;
+
+    async fn push(&self, queue: &str, payload: Vec<u8>) -> Result<(), BridgeError>;
+
+    /// the oldest payload of `queue`, waiting up to `timeout` for one
+    async fn pop(&self, queue: &str, timeout: Duration) -> Result<Option<Vec<u8>>, BridgeError>;
+}
+
+/// The bridge at `url`, only `redis://` ones drive executors.
+pub async fn open(url: &str) -> Result<Arc<dyn Bridge>, BridgeError> {
+    #[cfg(feature = "redis")]
+    if url.starts_with("redis://") || url.starts_with("rediss://") {
+        return Ok(Arc::new(RedisBridge::connect(url).await?));
+    }
+    Err(BridgeError::UnsupportedUrl(url.to_string()))
+}
+
+/// The sink at `url` for events only, `nats://...` or `kafka://` followed
+/// by brokers separated by commas.
+pub async fn open_sink(url: &str) -> Result<Arc<dyn EventSink>, BridgeError> {
+    #[cfg(feature = "nats")]
+    if url.starts_with("nats://") {
+        return Ok(Arc::new(NatsSink::connect(url).await?));
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(brokers) = url.strip_prefix("kafka://") {
+        let brokers = brokers.split(',').map(String::from).collect();
+        return Ok(Arc::new(KafkaSink::connect(brokers).await?));
+    }
+    Err(BridgeError::UnsupportedUrl(url.to_string()))
+}
+
+pub async fn publish<T: Serialize>(
+    sink: &dyn EventSink,
+    topic: &str,
+    message: &T,
+) -> Result<(), BridgeError> {
+    sink.publish(topic, serde_json::to_vec(message)?).await
+}
+
+/// messages of `topic`, payloads that don't deserialize are skipped
+pub async fn subscribe<T: DeserializeOwned + Send + 'static>(
+    bridge: &dyn Bridge,
+    topic: &str,
+) -> Result<BoxStream<'static, T>, BridgeError> {
+    let topic = topic.to_string();
+    Ok(bridge
+        .subscribe(&topic)
+        .await?
+        .filter_map(move |payload| {
+            let message = serde_json::from_slice(&payload)
+                .map_err(|e| warn!("Dropping malformed message on {}: {}", topic, e))
+                .ok();
+            async move { message }
+        })
+        .boxed())
+}
+
+/// Queues `command` for an executor, carrying the current span's trace
+/// context unless it has one already.
+pub async fn push_command(
+    bridge: &dyn Bridge,
+    command: &ExecutionCommand,
+) -> Result<(), BridgeError> {
+    let mut command = command.clone();
+    if command.trace_context.is_empty() {
+        command.trace_context = telemetry::trace_context(&Span::current());
+    }
+    bridge.push(COMMANDS, serde_json::to_vec(&command)?).await
+}
+
+pub async fn next_command(
+    bridge: &dyn Bridge,
+    timeout: Duration,
+) -> Result<Option<ExecutionCommand>, BridgeError> {
+    match bridge.pop(COMMANDS, timeout).await? {
+        Some(payload) => Ok(Some(serde_json::from_slice(&payload)?)),
+        None => Ok(None),
+    }
+}
+
+/// Span to execute `command` in, a child of the detector's span it was
+/// pushed from.
+pub fn command_span(command: &ExecutionCommand) -> Span {
+    let span = info_span!(
+        "command",
+        block = command.block,
+        target_block = command.target_block
+    );
+    telemetry::set_remote_parent(&span, &command.trace_context);
+    span
+}
+
+/// Publishes everything `receiver` gets to `topic` until its sender is
+/// dropped, e.g. the bus's `pool_updates` to `POOL_UPDATES`.
+pub async fn forward<T: Serialize + Clone>(
+    sink: Arc<dyn EventSink>,
+    topic: &'static str,
+    mut receiver: broadcast::Receiver<T>,
+) {
+    loop {
+        match receiver.recv().await {
+            Ok(message) => {
+                if let Err(e) = publish(sink.as_ref(), topic, &message).await {
+                    warn!("Failed to publish to {}: {}", topic, e);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Bridge to {} lagging, skipped {} messages", topic, skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        published: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    #[async_trait]
+    impl EventSink for Recorder {
+        async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+            let mut published = self.published.lock().unwrap();
+            published.push((topic.to_string(), payload));
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Bridge for Recorder {
+        async fn subscribe(
+            &self,
+            _topic: &str,
+        ) -> Result<BoxStream<'static, Vec<u8>>, BridgeError> {
+            Ok(futures_util::stream::empty().boxed())
+        }
+
+        async fn push(&self, queue: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+            self.publish(queue, payload).await
+        }
+
+        async fn pop(
+            &self,
+            queue: &str,
+            _timeout: Duration,
+        ) -> Result<Option<Vec<u8>>, BridgeError> {
+            let mut published = self.published.lock().unwrap();
+            let i = published.iter().position(|(topic, _)| topic == queue);
+            Ok(i.map(|i| published.remove(i).1))
+        }
+    }
+
+    fn command(trace_context: HashMap<String, String>) -> ExecutionCommand {
+        ExecutionCommand {
+            block: 1,
+            target_block: 2,
+            to: Address::repeat_byte(1),
+            calldata: vec![0x12, 0x34].into(),
+            gas_limit: 500_000.into(),
+            gas_price: 100.into(),
+            expected_profit: 1000.into(),
+            trace_context,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_trace_context() {
+        let bridge = Recorder::default();
+        let traced = command(
+            [(
+                "traceparent".to_string(),
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+            )]
+            .into(),
+        );
+        push_command(&bridge, &traced).await.unwrap();
+        // the current span's, nothing outside of one or without OTLP export
+        push_command(&bridge, &command(HashMap::new()))
+            .await
+            .unwrap();
+
+        let timeout = Duration::from_millis(10);
+        let popped = next_command(&bridge, timeout).await.unwrap().unwrap();
+        assert_eq!(popped, traced);
+        let _span = command_span(&popped).entered();
+        let popped = next_command(&bridge, timeout).await.unwrap().unwrap();
+        assert_eq!(
+            popped.trace_context,
+            telemetry::trace_context(&Span::current())
+        );
+        assert!(next_command(&bridge, timeout).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_open_unsupported_url() {
+        assert!(matches!(
+            open("nats://localhost:4222").await,
+            Err(BridgeError::UnsupportedUrl(_))
+        ));
+        assert!(matches!(
+            open_sink("redis://localhost:6379").await,
+            Err(BridgeError::UnsupportedUrl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forward_publishes_until_closed() {
+        let recorder = Arc::new(Recorder::default());
+        let (sender, receiver) = broadcast::channel(4);
+        let forwarding = tokio::spawn(forward(recorder.clone(), EXECUTIONS, receiver));
+        sender.send(1u64).unwrap();
+        sender.send(2u64).unwrap();
+        drop(sender);
+        forwarding.await.unwrap();
+
+        let published = recorder.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[1], (EXECUTIONS.to_string(), b"2".to_vec()));
+    }
+}
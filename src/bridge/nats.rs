@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+
+use super::{BridgeError, EventSink};
+
+/// Publishes to the NATS subject named after the topic. Core NATS, events
+/// published while nothing is subscribed are gone, put a JetStream stream
+/// on `tsuki.>` to keep them.
+pub struct NatsSink {
+    client: async_nats::Client,
+}
+
+impl NatsSink {
+    /// `url` like `nats://localhost:4222`
+    pub async fn connect(url: &str) -> Result<Self, BridgeError> {
+        Ok(Self {
+            client: async_nats::connect(url).await?,
+        })
+    }
+
+    pub fn client(&self) -> &async_nats::Client {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+        self.client
+            .publish(topic.to_string(), payload.into())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, StreamExt};
+use redis::{aio::ConnectionManager, AsyncCommands, Client};
+use tokio::sync::Mutex;
+
+use super::{Bridge, BridgeError, EventSink};
+
+/// Topics are redis pub/sub channels, queues are lists pushed on the left
+/// and popped on the right.
+pub struct RedisBridge {
+    client: Client,
+    conn: ConnectionManager,
+    /// `BRPOP` blocks its connection, so it gets one of its own
+    blocking: Mutex<Option<redis::aio::Connection>>,
+}
+
+impl RedisBridge {
+    pub async fn connect(url: &str) -> Result<Self, BridgeError> {
+        let client = Client::open(url)?;
+        let conn = ConnectionManager::new(client.clone()).await?;
+        Ok(Self {
+            client,
+            conn,
+            blocking: Mutex::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for RedisBridge {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+        self.conn
+            .clone()
+            .publish::<_, _, ()>(topic, payload)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Bridge for RedisBridge {
+    async fn subscribe(&self, topic: &str) -> Result<BoxStream<'static, Vec<u8>>, BridgeError> {
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(topic).await?;
+        Ok(pubsub
+            .into_on_message()
+            .map(|message| message.get_payload_bytes().to_vec())
+            .boxed())
+    }
+
+    async fn push(&self, queue: &str, payload: Vec<u8>) -> Result<(), BridgeError> {
+        self.conn.clone().lpush::<_, _, ()>(queue, payload).await?;
+        Ok(())
+    }
+
+    async fn pop(&self, queue: &str, timeout: Duration) -> Result<Option<Vec<u8>>, BridgeError> {
+        let mut blocking = self.blocking.lock().await;
+        if blocking.is_none() {
+            *blocking = Some(self.client.get_async_connection().await?);
+        }
+        let popped: Result<Option<(String, Vec<u8>)>, _> = redis::cmd("BRPOP")
+            .arg(queue)
+            .arg(timeout.as_secs_f64())
+            .query_async(blocking.as_mut().unwrap())
+            .await;
+        match popped {
+            Ok(popped) => Ok(popped.map(|(_, payload)| payload)),
+            Err(e) => {
+                // reconnect on the next pop
+                *blocking = None;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use super::*;
+    use crate::bridge::{next_command, push_command, ExecutionCommand};
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[tokio::test]
+    #[ignore = "needs a redis server at REDIS_URL"]
+    async fn test_redis_bridge_commands() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL");
+        let bridge = RedisBridge::connect(&url).await.unwrap();
+        let command = ExecutionCommand {
+            block: 1,
+            target_block: 2,
+            to: Address::repeat_byte(1),
+            calldata: vec![0x12, 0x34].into(),
+            gas_limit: 500_000.into(),
+            gas_price: 100.into(),
+            expected_profit: 1000.into(),
+            trace_context: [("traceparent".to_string(), TRACEPARENT.to_string())].into(),
+        };
+        push_command(&bridge, &command).await.unwrap();
+        let popped = next_command(&bridge, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(popped, Some(command));
+        assert!(next_command(&bridge, Duration::from_millis(100))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
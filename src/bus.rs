@@ -0,0 +1,287 @@
+//! In-process event bus. Subsystems publish what they see to one typed
+//! topic per kind of event and anything interested subscribes to it, so a
+//! binary creates one `Bus`, hands it to the subsystems it runs and attaches
+//! sinks (ndjson, the api, a bridge) instead of wiring channels between
+//! each pair by hand.
+//!
+//! Topics are broadcast channels: every subscriber sees every event
+//! published after it subscribed, and one that falls `BUS_CAPACITY` events
+//! behind misses the oldest ones instead of slowing publishers down.
+
+use std::sync::Arc;
+
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, Transaction, H256, U256},
+};
+use log::{error, warn};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    arb::{ArbCandidate, RouteQuote, Submission},
+    events::{Event, ExecutionStatus, Ndjson, PoolUpdateFilter},
+    export::{GasPriceRecord, OpportunityRecord, Record, ReserveRecord},
+    gas_budget::BudgetStatus,
+    header_tracker::Reorg,
+    lag::Lag,
+    migration::Collapse,
+    price_index::IndexPrice,
+    storage::{Log, Storage},
+    supervisor::TaskEvent,
+    tx_pool::ExpiredTx,
+};
+
+/// events buffered per subscriber of a topic
+pub const BUS_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockEvent {
+    pub number: u64,
+    pub timestamp: u64,
+    pub base_fee: Option<U256>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExecutionEvent {
+    pub block: u64,
+    pub tx_hash: H256,
+    pub status: ExecutionStatus,
+    pub gas_used: Option<U256>,
+}
+
+pub struct Topic<T> {
+    sender: broadcast::Sender<T>,
+}
+
+impl<T: Clone> Topic<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// returns how many subscribers will see `event`, none is fine
+    pub fn publish(&self, event: T) -> usize {
+        self.sender.send(event).unwrap_or(0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.sender.subscribe()
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+pub struct Bus {
+    /// new heads, published by the binary driving the block loop
+    pub blocks: Topic<BlockEvent>,
+    /// reserves of every Sync event `WorldState` applies
+    pub pool_updates: Topic<ReserveRecord>,
+    /// transactions `TxPool` sees enter the mempool
+    pub pending_txs: Topic<Transaction>,
+    /// transactions `TxPool` dropped for bidding under the base fee too long
+    pub expired_txs: Topic<ExpiredTx>,
+    /// pairs `WorldState` pulled from routing after losing their liquidity
+    pub collapses: Topic<Collapse>,
+    /// routes the arb quoted, once per route and block
+    pub quotes: Topic<RouteQuote>,
+    /// quotes the arb's evaluator found worth sending
+    pub candidates: Topic<ArbCandidate>,
+    /// candidates the arb sent, awaiting their receipts
+    pub submissions: Topic<Submission>,
+    pub opportunities: Topic<OpportunityRecord>,
+    pub executions: Topic<ExecutionEvent>,
+    /// panics, exits and restarts of supervised tasks
+    pub tasks: Topic<TaskEvent>,
+    /// index prices of every token, once per block
+    pub prices: Topic<Vec<IndexPrice>>,
+    /// subscriptions found behind the chain
+    pub lag: Topic<Lag>,
+    /// canonical blocks `HeaderTracker` saw replaced
+    pub reorgs: Topic<Reorg>,
+    /// gas budgets reaching their alert threshold or limit
+    pub budgets: Topic<BudgetStatus>,
+}
+
+impl Bus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: Topic::new(capacity),
+            pool_updates: Topic::new(capacity),
+            pending_txs: Topic::new(capacity),
+            expired_txs: Topic::new(capacity),
+            collapses: Topic::new(capacity),
+            quotes: Topic::new(capacity),
+            candidates: Topic::new(capacity),
+            submissions: Topic::new(capacity),
+            opportunities: Topic::new(capacity),
+            executions: Topic::new(capacity),
+            tasks: Topic::new(capacity),
+            prices: Topic::new(capacity),
+            lag: Topic::new(capacity),
+            reorgs: Topic::new(capacity),
+            budgets: Topic::new(capacity),
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new(BUS_CAPACITY)
+    }
+}
+
+/// the next event of `receiver`, logging what it missed, `None` once closed
+pub async fn next<T: Clone>(receiver: &mut broadcast::Receiver<T>, topic: &str) -> Option<T> {
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(skipped)) => {
+                error!("Subscriber of {} missed {} events", topic, skipped)
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Writes blocks, pool updates that moved at least `pool_threshold_bps`,
+/// opportunities, executions, lag, expired txns and collapsed pairs from
+/// `bus` as ndjson. Runs until the bus is dropped.
+pub async fn ndjson_sink(bus: Arc<Bus>, ndjson: Arc<Ndjson>, pool_threshold_bps: u64) {
+    let mut blocks = bus.blocks.subscribe();
+    let mut pool_updates = bus.pool_updates.subscribe();
+    let mut opportunities = bus.opportunities.subscribe();
+    let mut executions = bus.executions.subscribe();
+    let mut lag = bus.lag.subscribe();
+    let mut expired_txs = bus.expired_txs.subscribe();
+    let mut collapses = bus.collapses.subscribe();
+    let mut budgets = bus.budgets.subscribe();
+    // only subscriptions keep the sink alive
+    drop(bus);
+    let mut filter = PoolUpdateFilter::new(pool_threshold_bps);
+    loop {
+        let event = tokio::select! {
+            Some(block) = next(&mut blocks, "blocks") => Event::Block {
+                number: block.number,
+                timestamp: block.timestamp,
+                base_fee: block.base_fee,
+            },
+            Some(reserves) = next(&mut pool_updates, "pool updates") => {
+                match filter.check(reserves) {
+                    Some(event) => event,
+                    None => continue,
+                }
+            }
+            Some(record) = next(&mut opportunities, "opportunities") => Event::Opportunity(record),
+            Some(execution) = next(&mut executions, "executions") => Event::Execution {
+                block: execution.block,
+                tx_hash: execution.tx_hash,
+                status: execution.status,
+                gas_used: execution.gas_used,
+            },
+            Some(lag) = next(&mut lag, "lag") => Event::Lag(lag),
+            Some(expired) = next(&mut expired_txs, "expired txns") => Event::Expired(expired),
+            Some(collapse) = next(&mut collapses, "collapses") => Event::Collapse(collapse),
+            Some(budget) = next(&mut budgets, "budgets") => Event::Budget(budget),
+            else => break,
+        };
+        if let Err(e) = ndjson.emit(&event) {
+            error!("Failed to emit ndjson event: {:?}", e);
+        }
+    }
+}
+
+/// Appends the reserves of every pool update, the fees of every block and
+/// every opportunity from `bus` to their logs in `storage`, for `data
+/// export` and `data spreads`. A block's priority fee is the median its
+/// txns paid, asked of `provider`. Runs until the bus is dropped.
+pub async fn storage_sink<M: Middleware>(
+    bus: Arc<Bus>,
+    storage: Arc<dyn Storage>,
+    provider: Arc<M>,
+) {
+    let mut blocks = bus.blocks.subscribe();
+    let mut pool_updates = bus.pool_updates.subscribe();
+    let mut opportunities = bus.opportunities.subscribe();
+    // only subscriptions keep the sink alive
+    drop(bus);
+    let reserves: Log<ReserveRecord> = Log::new(storage.clone(), ReserveRecord::LOG);
+    let gas_prices: Log<GasPriceRecord> = Log::new(storage.clone(), GasPriceRecord::LOG);
+    let opportunity_log: Log<OpportunityRecord> = Log::new(storage, OpportunityRecord::LOG);
+    loop {
+        let appended = tokio::select! {
+            Some(block) = next(&mut blocks, "blocks") => {
+                let priority_fee = match provider
+                    .fee_history(1, BlockNumber::Number(block.number.into()), &[50.0])
+                    .await
+                {
+                    Ok(history) => history
+                        .reward
+                        .first()
+                        .and_then(|rewards| rewards.first().copied())
+                        .unwrap_or_default(),
+                    Err(e) => {
+                        warn!("Failed to read the fees of block {}: {}", block.number, e);
+                        U256::zero()
+                    }
+                };
+                gas_prices
+                    .append(&GasPriceRecord {
+                        block: block.number,
+                        timestamp: block.timestamp,
+                        base_fee: block.base_fee.unwrap_or_default(),
+                        priority_fee,
+                    })
+                    .await
+            }
+            Some(record) = next(&mut pool_updates, "pool updates") => reserves.append(&record).await,
+            Some(record) = next(&mut opportunities, "opportunities") => {
+                opportunity_log.append(&record).await
+            }
+            else => break,
+        };
+        if let Err(e) = appended {
+            error!("Failed to record to storage: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_topics() {
+        let bus = Bus::new(2);
+        // nobody listening yet
+        assert_eq!(bus.executions.publish(execution(1)), 0);
+
+        let mut first = bus.executions.subscribe();
+        let mut second = bus.executions.subscribe();
+        assert_eq!(bus.executions.publish(execution(2)), 2);
+        assert_eq!(next(&mut first, "executions").await, Some(execution(2)));
+        assert_eq!(next(&mut second, "executions").await, Some(execution(2)));
+
+        // a slow subscriber skips ahead to what's still buffered
+        for block in 3..6 {
+            bus.executions.publish(execution(block));
+        }
+        assert_eq!(next(&mut first, "executions").await, Some(execution(4)));
+
+        drop(bus);
+        assert_eq!(next(&mut first, "executions").await, Some(execution(5)));
+        assert_eq!(next(&mut first, "executions").await, None);
+    }
+
+    fn execution(block: u64) -> ExecutionEvent {
+        ExecutionEvent {
+            block,
+            tx_hash: H256::zero(),
+            status: ExecutionStatus::Confirmed,
+            gas_used: None,
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use enum_map::{enum_map, Enum, EnumMap};
-use ethers::types::Address;
+use ethers::types::{Address, H256};
 use lazy_static::lazy_static;
 
 #[derive(PartialEq, Debug, Enum, Clone, Copy)]
@@ -15,6 +15,9 @@ struct UniswapV2Data {
     pub name: &'static str,
     pub router_address: Address,
     pub factory_address: Address,
+    /// keccak of the pair creation code, `None` where we haven't confirmed
+    /// it and the factory is asked instead
+    pub init_code_hash: Option<H256>,
 }
 
 pub struct UniswapV3Data {
@@ -38,14 +41,20 @@ lazy_static! {
             router_address: "0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506"
                 .parse::<Address>()
                 .unwrap(),
-            factory_address: "0xc35DADB65012eC5796536bD9864eD8773aBc74C4".parse::<Address>().unwrap()
+            factory_address: "0xc35DADB65012eC5796536bD9864eD8773aBc74C4".parse::<Address>().unwrap(),
+            init_code_hash: None,
         },
         UniswapV2::QUICKSWAP => UniswapV2Data {
             name: "Quickswap",
             router_address: "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff"
                 .parse::<Address>()
                 .unwrap(),
-            factory_address: "0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32".parse::<Address>().unwrap()
+            factory_address: "0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32".parse::<Address>().unwrap(),
+            init_code_hash: Some(
+                "0x96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f"
+                    .parse::<H256>()
+                    .unwrap()
+            ),
         },
         UniswapV2::POLYCAT => UniswapV2Data {
             name: "Polycat",
@@ -53,6 +62,7 @@ lazy_static! {
                 .parse::<Address>()
                 .unwrap(),
             factory_address: "0x477Ce834Ae6b7aB003cCe4BC4d8697763FF456FA".parse::<Address>().unwrap(),
+            init_code_hash: None,
         },
         UniswapV2::APESWAP => UniswapV2Data {
             name: "Apeswap",
@@ -60,13 +70,19 @@ lazy_static! {
                 .parse::<Address>()
                 .unwrap(),
             factory_address: "0xCf083Be4164828f00cAE704EC15a36D711491284".parse::<Address>().unwrap(),
+            init_code_hash: Some(
+                "0x511f0f358fe530cda0859ec20becf391718fdf5a329be02f4c95361f3d6a42d8"
+                    .parse::<H256>()
+                    .unwrap()
+            ),
         },
         UniswapV2::MESHSWAP => UniswapV2Data {
             name: "Meshswap",
             router_address: "0x10f4a785f458bc144e3706575924889954946639"
                 .parse::<Address>()
                 .unwrap(),
-            factory_address: "0x9f3044f7f9fc8bc9ed615d54845b4577b833282d".parse::<Address>().unwrap()
+            factory_address: "0x9f3044f7f9fc8bc9ed615d54845b4577b833282d".parse::<Address>().unwrap(),
+            init_code_hash: None,
         },
     };
     pub static ref UNISWAP_V3: UniswapV3Data = UniswapV3Data {
@@ -93,6 +109,10 @@ impl UniswapV2 {
         PROTOCOL_MAPPING[*self].factory_address
     }
 
+    pub fn get_init_code_hash(&self) -> Option<H256> {
+        PROTOCOL_MAPPING[*self].init_code_hash
+    }
+
     pub fn get_all_protoccols() -> Vec<UniswapV2> {
         // keep in enum order!
         vec![
@@ -17,6 +17,8 @@ struct ERC20TokenData {
     pub name: &'static str,
     pub symbol: &'static str,
     pub decimals: u8,
+    /// taken by the token on every transfer, in bps
+    pub transfer_tax_bps: u64,
 }
 
 lazy_static! {
@@ -28,6 +30,7 @@ lazy_static! {
             name: "USD Coin",
             symbol: "USDC",
             decimals: 6,
+            transfer_tax_bps: 0,
         },
         ERC20Token::USDT => ERC20TokenData {
             address: "0xc2132d05d31c914a87c6611c10748aeb04b58e8f"
@@ -36,6 +39,7 @@ lazy_static! {
             name: "Tether USD",
             symbol: "USDT",
             decimals: 6,
+            transfer_tax_bps: 0,
         },
         ERC20Token::DAI => ERC20TokenData {
             address: "0x8f3cf7ad23cd3cadbd9735aff958023239c6a063"
@@ -44,6 +48,7 @@ lazy_static! {
             name: "Dai Stablecoin",
             symbol: "DAI",
             decimals: 18,
+            transfer_tax_bps: 0,
         },
         ERC20Token::WBTC => ERC20TokenData {
             address: "0x1bfd67037b42cf73acf2047067bd4f2c47d9bfd6"
@@ -52,6 +57,7 @@ lazy_static! {
             name: "Wrapped BTC",
             symbol: "WBTC",
             decimals: 8,
+            transfer_tax_bps: 0,
         },
         ERC20Token::WMATIC => ERC20TokenData {
             address: "0x0d500b1d8e8ef31e21c99d1db9a6444d3adf1270"
@@ -60,6 +66,7 @@ lazy_static! {
             name: "Wrapped Matic",
             symbol: "WMATIC",
             decimals: 18,
+            transfer_tax_bps: 0,
         },
         ERC20Token::WETH => ERC20TokenData {
             address: "0x7ceb23fd6bc0add59e62ac25578270cff1b9f619"
@@ -68,10 +75,17 @@ lazy_static! {
             name: "Wrapped Ether",
             symbol: "WETH",
             decimals: 18,
+            transfer_tax_bps: 0,
         }
     };
 }
 
+#[cfg(test)]
+thread_local! {
+    static TAX_OVERRIDES: std::cell::RefCell<EnumMap<ERC20Token, Option<u64>>> =
+        std::cell::RefCell::new(EnumMap::default());
+}
+
 impl ERC20Token {
     pub fn get_address(self) -> Address {
         ERC20_MAPPING[self].address
@@ -88,6 +102,41 @@ impl ERC20Token {
     pub fn get_decimals(self) -> u8 {
         ERC20_MAPPING[self].decimals
     }
+
+    pub fn get_transfer_tax_bps(self) -> u64 {
+        #[cfg(test)]
+        if let Some(bps) = TAX_OVERRIDES.with(|overrides| overrides.borrow()[self]) {
+            return bps;
+        }
+        ERC20_MAPPING[self].transfer_tax_bps
+    }
+
+    /// taxes `self` at `bps` for the rest of the calling test's thread, none
+    /// of the registry's tokens is taxed
+    #[cfg(test)]
+    pub fn override_transfer_tax_bps(self, bps: u64) {
+        TAX_OVERRIDES.with(|overrides| overrides.borrow_mut()[self] = Some(bps));
+    }
+
+    /// swaps through taxed tokens need the router's fee-on-transfer methods
+    pub fn is_taxed(self) -> bool {
+        self.get_transfer_tax_bps() > 0
+    }
+
+    pub fn from_address(address: Address) -> Option<ERC20Token> {
+        ERC20_MAPPING
+            .iter()
+            .find(|(_, token_data)| token_data.address == address)
+            .map(|(token, _)| token)
+    }
+
+    /// case insensitive, e.g. `usdc`
+    pub fn from_symbol(symbol: &str) -> Option<ERC20Token> {
+        ERC20_MAPPING
+            .iter()
+            .find(|(_, token_data)| token_data.symbol.eq_ignore_ascii_case(symbol))
+            .map(|(token, _)| token)
+    }
 }
 
 pub fn ERC20Lookup(address: Address) -> ERC20Token {
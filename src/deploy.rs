@@ -0,0 +1,253 @@
+//! Contract deployment from a json config:
+//!
+//! ```json
+//! {
+//!     "artifact": "abis/FlashloanV3.json",
+//!     "name": "flashloan_executor",
+//!     "args": ["0xBA12222222228d8Ba445958a75a0704d566BF2C8"]
+//! }
+//! ```
+//!
+//! `artifact` is a hardhat artifact with the abi and creation bytecode,
+//! `args` the constructor arguments in abi order as strings (`"1000"`,
+//! `"0x.."`, `"true"`, `"[1,2]"`), and `name` what the deployed address is
+//! recorded under in the address book.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ethers::{
+    abi::{
+        token::{LenientTokenizer, Tokenizer},
+        Abi, Token,
+    },
+    prelude::ContractFactory,
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Bytes, TransactionReceipt, H256, U256,
+    },
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::utils::fee_history::{fee_history, FeeHistory};
+
+/// blocks of fee history the priority fee is taken from
+const FEE_HISTORY_BLOCKS: u64 = 20;
+
+#[derive(Error, Debug)]
+pub enum DeployError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Abi(#[from] ethers::abi::Error),
+
+    #[error("constructor takes {expected} args, got {got}")]
+    ArgCount { expected: usize, got: usize },
+
+    #[error("max fee {needed} over the {cap} cap")]
+    FeeCap { needed: U256, cap: U256 },
+
+    /// provider and contract errors, generic over the middleware
+    #[error("{0}")]
+    Middleware(String),
+
+    #[error("deployment {0:?} reverted")]
+    Reverted(H256),
+}
+
+/// How deployment fees are bid: the tip recent blocks paid at
+/// `priority_percentile`, on top of a base fee that may rise for
+/// `base_fee_blocks` blocks before the transaction lands.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GasPolicy {
+    pub priority_percentile: f64,
+    pub base_fee_blocks: u32,
+    /// never pay more than this per gas, in gwei
+    pub max_fee_gwei: Option<u64>,
+    pub confirmations: usize,
+}
+
+impl Default for GasPolicy {
+    fn default() -> Self {
+        Self {
+            priority_percentile: 50.0,
+            base_fee_blocks: 3,
+            max_fee_gwei: None,
+            confirmations: 2,
+        }
+    }
+}
+
+impl GasPolicy {
+    /// (max fee, max priority fee), the tip falling back to `min_tip` when
+    /// recent blocks were empty
+    pub fn fees(&self, history: &FeeHistory, min_tip: U256) -> Result<(U256, U256), DeployError> {
+        let tip = history
+            .suggested_priority_fee(self.priority_percentile)
+            .map_or(min_tip, |tip| U256::max(tip, min_tip));
+        let max_fee = history.suggested_max_fee(tip, self.base_fee_blocks);
+        if let Some(cap) = self.max_fee_gwei {
+            let cap = U256::from(cap) * U256::exp10(9);
+            if max_fee > cap {
+                return Err(DeployError::FeeCap {
+                    needed: max_fee,
+                    cap,
+                });
+            }
+        }
+        Ok((max_fee, tip))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeployConfig {
+    pub artifact: PathBuf,
+    pub name: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub gas: GasPolicy,
+}
+
+impl DeployConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DeployError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Artifact {
+    pub abi: Abi,
+    #[serde(alias = "bin")]
+    pub bytecode: Bytes,
+}
+
+impl Artifact {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DeployError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// `args` parsed as the constructor's parameter types
+    pub fn constructor_args(&self, args: &[String]) -> Result<Vec<Token>, DeployError> {
+        let params = self
+            .abi
+            .constructor()
+            .map(|constructor| constructor.inputs.as_slice())
+            .unwrap_or_default();
+        if params.len() != args.len() {
+            return Err(DeployError::ArgCount {
+                expected: params.len(),
+                got: args.len(),
+            });
+        }
+        Ok(params
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| LenientTokenizer::tokenize(&param.kind, arg))
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+/// Deploys `config`'s contract from `client`, waiting for
+/// `config.gas.confirmations` blocks. Returns the address and receipt.
+pub async fn deploy<M: Middleware + 'static>(
+    client: Arc<M>,
+    config: &DeployConfig,
+) -> Result<(Address, TransactionReceipt), DeployError> {
+    let artifact = Artifact::load(&config.artifact)?;
+    let args = artifact.constructor_args(&config.args)?;
+
+    let history = fee_history(
+        client.as_ref(),
+        FEE_HISTORY_BLOCKS,
+        &[config.gas.priority_percentile],
+    )
+    .await
+    .map_err(|e| DeployError::Middleware(e.to_string()))?;
+    // polygon validators ignore anything with less than a 30 gwei tip
+    let (max_fee, tip) = config.gas.fees(&history, U256::from(30_000_000_000u64))?;
+
+    let mut deployer = ContractFactory::new(artifact.abi, artifact.bytecode, client)
+        .deploy_tokens(args)
+        .map_err(|e| DeployError::Middleware(e.to_string()))?
+        .confirmations(config.gas.confirmations);
+    if let TypedTransaction::Eip1559(tx) = &mut deployer.tx {
+        tx.max_fee_per_gas = Some(max_fee);
+        tx.max_priority_fee_per_gas = Some(tip);
+    }
+    let (contract, receipt) = deployer
+        .send_with_receipt()
+        .await
+        .map_err(|e| DeployError::Middleware(e.to_string()))?;
+    if receipt.status != Some(1.into()) {
+        return Err(DeployError::Reverted(receipt.transaction_hash));
+    }
+    Ok((contract.address(), receipt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::FeeHistory as EthersFeeHistory;
+
+    #[test]
+    fn test_constructor_args() {
+        let artifact = Artifact::load("abis/FlashloanV3.json").unwrap();
+        assert!(!artifact.bytecode.is_empty());
+        let vault = "0xBA12222222228d8Ba445958a75a0704d566BF2C8";
+        assert_eq!(
+            artifact.constructor_args(&[vault.to_string()]).unwrap(),
+            vec![Token::Address(vault.parse().unwrap())]
+        );
+        assert!(matches!(
+            artifact.constructor_args(&[]),
+            Err(DeployError::ArgCount {
+                expected: 1,
+                got: 0
+            })
+        ));
+        assert!(artifact.constructor_args(&["vault".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_gas_policy_fees() {
+        let gwei = U256::exp10(9);
+        let history = FeeHistory::new(
+            EthersFeeHistory {
+                base_fee_per_gas: vec![gwei * 64, gwei * 64],
+                gas_used_ratio: vec![0.5],
+                oldest_block: U256::from(1000),
+                reward: vec![vec![gwei * 40]],
+            },
+            &[50.0],
+        );
+        let policy = GasPolicy {
+            base_fee_blocks: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.fees(&history, gwei * 30).unwrap(),
+            (gwei * 72 + gwei * 40, gwei * 40)
+        );
+        // the tip never drops below the floor
+        assert_eq!(policy.fees(&history, gwei * 50).unwrap().1, gwei * 50);
+
+        let capped = GasPolicy {
+            max_fee_gwei: Some(100),
+            ..policy
+        };
+        assert!(matches!(
+            capped.fees(&history, gwei * 30),
+            Err(DeployError::FeeCap { .. })
+        ));
+    }
+}
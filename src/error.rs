@@ -0,0 +1,236 @@
+//! Failure kinds shared across the crate, for callers that act on why
+//! something failed rather than only log it: retry on a `TransportError`,
+//! resync on a `StateError`, skip the route on a `QuoteError`, check the
+//! wallet on an `ExecutionError`. Modules keep their own error enums for
+//! the details and convert into these at the library boundary; `Error` is
+//! whichever of the four a call can fail with.
+
+use ethers::{
+    prelude::ContractError,
+    providers::{Middleware, ProviderError},
+    types::{Address, H256},
+};
+use thiserror::Error;
+
+use crate::{
+    constants::token::ERC20Token,
+    utils::{
+        batch::common::BatchError, bundle::BundleError, submitter::SubmitError,
+        transaction::RawTransactionError,
+    },
+};
+
+/// The node couldn't be asked or didn't answer.
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+
+    /// a middleware in the stack failed the request, only its message is
+    /// kept as its type is generic
+    #[error("middleware error: {0}")]
+    Middleware(String),
+}
+
+impl TransportError {
+    pub fn middleware<M: Middleware>(e: M::Error) -> Self {
+        TransportError::Middleware(e.to_string())
+    }
+}
+
+/// whether the node failed a call because it reverted, geth and bor answer
+/// `execution reverted`, other clients some other revert message
+pub fn is_revert(message: &str) -> bool {
+    message.contains("revert")
+}
+
+/// The node's failure if the node failed `e`, else the message of the call's
+/// own: a revert, or output that doesn't decode.
+fn contract_failure<M: Middleware>(
+    e: ContractError<M>,
+) -> std::result::Result<TransportError, String> {
+    let message = e.to_string();
+    if is_revert(&message) {
+        return Err(message);
+    }
+    match e {
+        ContractError::ProviderError(e) => Ok(TransportError::Provider(e)),
+        ContractError::MiddlewareError(e) => Ok(TransportError::middleware::<M>(e)),
+        _ => Err(message),
+    }
+}
+
+/// What the node returned doesn't fit the local view of the chain.
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("pair {0:?} isn't tracked")]
+    UnknownPair(Address),
+
+    #[error("token {0:?} isn't in the registry")]
+    UnknownToken(Address),
+}
+
+/// A quote couldn't be made.
+#[derive(Error, Debug)]
+pub enum QuoteError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    #[error("no pool of {} and {} quotes the amount", .token_in.get_symbol(), .token_out.get_symbol())]
+    NoLiquidity {
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+    },
+
+    /// the quoting contract reverted or returned what doesn't decode
+    #[error("quote call failed: {0}")]
+    Call(String),
+}
+
+impl<M: Middleware> From<ContractError<M>> for QuoteError {
+    fn from(e: ContractError<M>) -> Self {
+        match contract_failure(e) {
+            Ok(e) => QuoteError::Transport(e),
+            Err(message) => QuoteError::Call(message),
+        }
+    }
+}
+
+/// A txn couldn't be built, sent or landed.
+#[derive(Error, Debug)]
+pub enum ExecutionError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    #[error(transparent)]
+    RawTransaction(#[from] RawTransactionError),
+
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+
+    #[error("txn {0:?} reverted")]
+    Reverted(H256),
+
+    /// a call or gas estimate reverted, or returned what doesn't decode
+    #[error("call failed: {0}")]
+    Call(String),
+}
+
+impl<M: Middleware> From<ContractError<M>> for ExecutionError {
+    fn from(e: ContractError<M>) -> Self {
+        match contract_failure(e) {
+            Ok(e) => ExecutionError::Transport(e),
+            Err(message) => ExecutionError::Call(message),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    #[error(transparent)]
+    State(#[from] StateError),
+
+    #[error(transparent)]
+    Quote(#[from] QuoteError),
+
+    #[error(transparent)]
+    Execution(#[from] ExecutionError),
+}
+
+impl Error {
+    /// whether the node failed rather than what was asked of it, worth
+    /// retrying as is
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Error::Transport(_)
+                | Error::Quote(QuoteError::Transport(_))
+                | Error::Execution(ExecutionError::Transport(_))
+        )
+    }
+}
+
+impl From<ProviderError> for Error {
+    fn from(e: ProviderError) -> Self {
+        let message = e.to_string();
+        match is_revert(&message) {
+            true => Error::Execution(ExecutionError::Call(message)),
+            false => Error::Transport(e.into()),
+        }
+    }
+}
+
+impl<M: Middleware> From<ContractError<M>> for Error {
+    fn from(e: ContractError<M>) -> Self {
+        match contract_failure(e) {
+            Ok(e) => Error::Transport(e),
+            Err(message) => Error::Execution(ExecutionError::Call(message)),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{MockProvider, Provider};
+
+    use super::*;
+
+    fn quote(fail: bool) -> Result<u64, QuoteError> {
+        match fail {
+            true => Err(TransportError::from(ProviderError::CustomError(
+                "connection reset".to_string(),
+            ))
+            .into()),
+            false => Err(QuoteError::NoLiquidity {
+                token_in: ERC20Token::USDC,
+                token_out: ERC20Token::WETH,
+            }),
+        }
+    }
+
+    fn route() -> Result<u64> {
+        Ok(quote(true)?)
+    }
+
+    fn reverted() -> ContractError<Provider<MockProvider>> {
+        ContractError::ProviderError(ProviderError::CustomError(
+            "execution reverted: UniswapV2: K".to_string(),
+        ))
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let e = route().unwrap_err();
+        assert!(e.is_transport());
+        assert!(matches!(
+            e,
+            Error::Quote(QuoteError::Transport(TransportError::Provider(_)))
+        ));
+        let e: Error = quote(false).unwrap_err().into();
+        assert!(!e.is_transport());
+        assert_eq!(e.to_string(), "no pool of USDC and WETH quotes the amount");
+        let e: Error = ProviderError::CustomError("connection reset".to_string()).into();
+        assert!(matches!(e, Error::Transport(TransportError::Provider(_))));
+
+        // the node answered, retrying the same call reverts again
+        let e: Error = reverted().into();
+        assert!(!e.is_transport());
+        assert!(matches!(e, Error::Execution(ExecutionError::Call(_))));
+        assert!(matches!(QuoteError::from(reverted()), QuoteError::Call(_)));
+        let e: Error = ProviderError::CustomError("execution reverted".to_string()).into();
+        assert!(!e.is_transport());
+        let e: Error = ContractError::<Provider<MockProvider>>::ConstructorError.into();
+        assert!(!e.is_transport());
+    }
+}
@@ -5,6 +5,8 @@ use ethers::{
 };
 use serde::{Deserialize, Serialize};
 
+use crate::error::TransportError;
+
 #[derive(Serialize, Deserialize)]
 pub struct EthSubscribeLogArgs {
     pub address: Vec<Address>,
@@ -24,7 +26,7 @@ impl EthSubscribeLogArgs {
 pub async fn get_pair_sync_stream<P: PubsubClient>(
     provider: &Provider<P>,
     pair_addresses: Vec<Address>,
-) -> SubscriptionStream<P, Log> {
+) -> Result<SubscriptionStream<P, Log>, TransportError> {
     let command = "logs";
     let command = utils::serialize(&command);
 
@@ -35,8 +37,7 @@ pub async fn get_pair_sync_stream<P: PubsubClient>(
     let args = EthSubscribeLogArgs::new(pair_addresses, topics);
     let args = utils::serialize(&args);
 
-    let stream = provider.subscribe::<_, Log>([command, args]).await.unwrap();
-    return stream;
+    Ok(provider.subscribe::<_, Log>([command, args]).await?)
 }
 
 #[cfg(test)]
@@ -83,7 +84,8 @@ mod tests {
                 .parse::<Address>()
                 .unwrap()],
         )
-        .await;
+        .await
+        .unwrap();
 
         while let Some(log) = stream.next().await {
             println!(
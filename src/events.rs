@@ -0,0 +1,228 @@
+//! Significant events as one json object per line, for piping the bot into
+//! jq, fluentbit or a separate execution process. Every line has `type` and
+//! `ts_ms` (unix millis when emitted), the other fields depend on the type:
+//!
+//! - `block`: `number`, `timestamp`, `base_fee`
+//! - `pool_update`: the fields of `ReserveRecord` plus `change_bps`, the
+//!   largest move of either reserve since the pair's last `pool_update`
+//! - `opportunity`: the fields of `OpportunityRecord`
+//! - `execution`: `block`, `tx_hash`, `status` (`confirmed`, `reverted` or
+//!   `dropped`) and `gas_used`
+//! - `lag`: a subscription behind the chain, `lag` is `gap` (`from`, `to`,
+//!   the heads missed), `stale` (`block`, `age_secs`) or `stalled`
+//!   (`subscription`, `secs`)
+//! - `expired`: a pending txn dropped for bidding under the base fee too
+//!   many blocks in a row, `hash`, `gas_price`, `floor` (the base fee) and
+//!   `block`
+//! - `collapse`: a pair pulled from routing after losing its liquidity,
+//!   `block`, `pair`, `protocol`, `token0`, `token1`, `peak_liquidity`,
+//!   `liquidity` (`sqrt(reserve0 * reserve1)`) and `replacement`, a pair
+//!   of the same tokens on another factory (`pair`, `protocol`,
+//!   `liquidity`, `tracked`) or null
+//! - `budget`: a gas budget reaching `level` `warning` (its alert threshold)
+//!   or `exhausted`, `scope` (`global`, `wallet` or `strategy`), `of` (the
+//!   wallet or strategy), `window` (`hourly` or `daily`), `spent` and
+//!   `limit` in wei
+//!
+//! Block numbers and timestamps are json numbers, token amounts and fees hex
+//! quantities as in JSON-RPC, addresses and hashes 0x hex. Fields are only
+//! ever added.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ethers::types::{Address, H256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    export::{OpportunityRecord, ReserveRecord},
+    gas_budget::BudgetStatus,
+    lag::Lag,
+    migration::Collapse,
+    tx_pool::ExpiredTx,
+};
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Block {
+        number: u64,
+        timestamp: u64,
+        base_fee: Option<U256>,
+    },
+    PoolUpdate {
+        #[serde(flatten)]
+        reserves: ReserveRecord,
+        change_bps: u64,
+    },
+    Opportunity(OpportunityRecord),
+    Execution {
+        block: u64,
+        tx_hash: H256,
+        status: ExecutionStatus,
+        gas_used: Option<U256>,
+    },
+    Lag(Lag),
+    Expired(ExpiredTx),
+    Collapse(Collapse),
+    Budget(BudgetStatus),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionStatus {
+    Confirmed,
+    Reverted,
+    /// never mined
+    Dropped,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts_ms: u64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Writes events as ndjson, or drops them when disabled so callers don't
+/// have to check.
+pub struct Ndjson {
+    out: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Ndjson {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Some(Mutex::new(Box::new(out))),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    pub fn disabled() -> Self {
+        Self { out: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.out.is_some()
+    }
+
+    /// one line, flushed right away so readers see it immediately
+    pub fn emit(&self, event: &Event) -> io::Result<()> {
+        let out = match &self.out {
+            Some(out) => out,
+            None => return Ok(()),
+        };
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut line = serde_json::to_vec(&Line { ts_ms, event })?;
+        line.push(b'\n');
+        let mut out = out.lock().unwrap();
+        out.write_all(&line)?;
+        out.flush()
+    }
+}
+
+/// Lets through reserve updates that moved either reserve of their pair by
+/// at least `threshold_bps` since the last one let through.
+pub struct PoolUpdateFilter {
+    threshold_bps: u64,
+    last: HashMap<Address, (U256, U256)>,
+}
+
+impl PoolUpdateFilter {
+    pub fn new(threshold_bps: u64) -> Self {
+        Self {
+            threshold_bps,
+            last: HashMap::new(),
+        }
+    }
+
+    /// the `pool_update` event for `reserves`, if it moved enough. The first
+    /// update of a pair always does.
+    pub fn check(&mut self, reserves: ReserveRecord) -> Option<Event> {
+        let current = (reserves.reserve0, reserves.reserve1);
+        let change_bps = match self.last.get(&reserves.pair) {
+            Some(last) => change_bps(last.0, current.0).max(change_bps(last.1, current.1)),
+            None => u64::MAX,
+        };
+        if change_bps < self.threshold_bps {
+            return None;
+        }
+        self.last.insert(reserves.pair, current);
+        Some(Event::PoolUpdate {
+            reserves,
+            change_bps: change_bps.min(10_000),
+        })
+    }
+}
+
+fn change_bps(from: U256, to: U256) -> u64 {
+    if from.is_zero() {
+        return if to.is_zero() { 0 } else { u64::MAX };
+    }
+    let diff = if to > from { to - from } else { from - to };
+    let bps = diff.saturating_mul(U256::from(10_000)) / from;
+    if bps > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        bps.as_u64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ndjson_pool_updates() {
+        let reserves = |reserve0: u64| ReserveRecord {
+            block: 1,
+            pair: Address::repeat_byte(1),
+            protocol: "Quickswap".to_string(),
+            token0: Address::repeat_byte(2),
+            token1: Address::repeat_byte(3),
+            reserve0: reserve0.into(),
+            reserve1: 1_000_000.into(),
+        };
+        let mut filter = PoolUpdateFilter::new(10);
+        let first = filter.check(reserves(1_000_000)).unwrap();
+        // 5 bps, below the threshold
+        assert!(filter.check(reserves(1_000_500)).is_none());
+        assert!(filter.check(reserves(1_001_000)).is_some());
+
+        let buffer = Buffer::default();
+        let ndjson = Ndjson::new(buffer.clone());
+        ndjson.emit(&first).unwrap();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with('\n'));
+        let line: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["type"], "pool_update");
+        assert_eq!(line["change_bps"], 10_000);
+        assert_eq!(line["reserve0"], "0xf4240");
+        assert!(line["ts_ms"].is_u64());
+    }
+}
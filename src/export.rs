@@ -0,0 +1,545 @@
+//! Dumps what was recorded to storage (reserves, gas prices, opportunities,
+//! indexed activity, the route heatmap) to csv or parquet for analysis in
+//! pandas/duckdb. The arb records the first three with `--storage`, see
+//! `bus::storage_sink`.
+//!
+//! Schemas are part of the interface, columns are only ever appended. Block
+//! numbers and timestamps are int64, addresses and hashes lowercase 0x hex,
+//! token amounts decimal strings since they overflow int64, and bps double.
+
+use std::{fs::File, io, path::Path, sync::Arc};
+
+use ethers::types::{Address, H256, U256};
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    activity::ActivityRecord,
+    heatmap::HeatCell,
+    storage::{self, Log, Storage, StorageError, Table},
+};
+
+// log entries read per page, and rows per parquet row group
+const PAGE_SIZE: usize = 10_000;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error(transparent)]
+    StorageError(#[from] StorageError),
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+
+    #[error(transparent)]
+    CsvError(#[from] csv::Error),
+
+    #[error(transparent)]
+    ParquetError(#[from] ParquetError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Int64,
+    Float64,
+    Utf8,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    pub name: &'static str,
+    pub ty: ColumnType,
+    pub nullable: bool,
+}
+
+pub(crate) const fn int64(name: &'static str) -> Column {
+    Column {
+        name,
+        ty: ColumnType::Int64,
+        nullable: false,
+    }
+}
+
+pub(crate) const fn float64(name: &'static str) -> Column {
+    Column {
+        name,
+        ty: ColumnType::Float64,
+        nullable: false,
+    }
+}
+
+pub(crate) const fn utf8(name: &'static str) -> Column {
+    Column {
+        name,
+        ty: ColumnType::Utf8,
+        nullable: false,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Int64(i64),
+    Float64(f64),
+    Utf8(String),
+    Null,
+}
+
+impl From<u64> for Cell {
+    fn from(value: u64) -> Self {
+        Cell::Int64(value as i64)
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(value: f64) -> Self {
+        Cell::Float64(value)
+    }
+}
+
+impl From<Address> for Cell {
+    fn from(value: Address) -> Self {
+        Cell::Utf8(format!("{:?}", value))
+    }
+}
+
+impl From<H256> for Cell {
+    fn from(value: H256) -> Self {
+        Cell::Utf8(format!("{:?}", value))
+    }
+}
+
+impl From<U256> for Cell {
+    fn from(value: U256) -> Self {
+        Cell::Utf8(value.to_string())
+    }
+}
+
+impl From<String> for Cell {
+    fn from(value: String) -> Self {
+        Cell::Utf8(value)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Cell::Null, Into::into)
+    }
+}
+
+impl Cell {
+    fn to_csv(&self) -> String {
+        match self {
+            Cell::Int64(value) => value.to_string(),
+            Cell::Float64(value) => value.to_string(),
+            Cell::Utf8(value) => value.clone(),
+            Cell::Null => String::new(),
+        }
+    }
+}
+
+/// An entry type of a storage log (or table) that can be exported as one
+/// row.
+pub trait Record: Serialize + DeserializeOwned {
+    /// storage log the records are appended to, or table they're kept in
+    const LOG: &'static str;
+
+    fn columns() -> &'static [Column];
+
+    /// one cell per column, in the order of `columns`
+    fn cells(&self) -> Vec<Cell>;
+}
+
+/// reserves of a pair after the last sync of a block
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReserveRecord {
+    pub block: u64,
+    pub pair: Address,
+    pub protocol: String,
+    pub token0: Address,
+    pub token1: Address,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+impl Record for ReserveRecord {
+    const LOG: &'static str = storage::RESERVES;
+
+    fn columns() -> &'static [Column] {
+        const COLUMNS: &[Column] = &[
+            int64("block"),
+            utf8("pair"),
+            utf8("protocol"),
+            utf8("token0"),
+            utf8("token1"),
+            utf8("reserve0"),
+            utf8("reserve1"),
+        ];
+        COLUMNS
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.block.into(),
+            self.pair.into(),
+            self.protocol.clone().into(),
+            self.token0.into(),
+            self.token1.into(),
+            self.reserve0.into(),
+            self.reserve1.into(),
+        ]
+    }
+}
+
+/// fees of a block, in wei
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasPriceRecord {
+    pub block: u64,
+    pub timestamp: u64,
+    pub base_fee: U256,
+    /// median priority fee of the block's txns
+    pub priority_fee: U256,
+}
+
+impl Record for GasPriceRecord {
+    const LOG: &'static str = storage::GAS_PRICES;
+
+    fn columns() -> &'static [Column] {
+        const COLUMNS: &[Column] = &[
+            int64("block"),
+            int64("timestamp"),
+            utf8("base_fee"),
+            utf8("priority_fee"),
+        ];
+        COLUMNS
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.block.into(),
+            self.timestamp.into(),
+            self.base_fee.into(),
+            self.priority_fee.into(),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpportunityRecord {
+    pub block: u64,
+    /// token symbols joined by `>`
+    pub route: String,
+    pub amount_in: U256,
+    pub profit: U256,
+    /// `unprofitable`, `cooldown`, `vetoed`, `phantom`, `standby`,
+    /// `degraded`, `skipped_producer`, `over_budget`, `superseded` (another
+    /// route of the block was sent), `submitted`, `submitted_user_op` or
+    /// `send_failed`
+    pub outcome: String,
+    pub tx_hash: Option<H256>,
+}
+
+impl Record for OpportunityRecord {
+    const LOG: &'static str = storage::OPPORTUNITIES;
+
+    fn columns() -> &'static [Column] {
+        const COLUMNS: &[Column] = &[
+            int64("block"),
+            utf8("route"),
+            utf8("amount_in"),
+            utf8("profit"),
+            utf8("outcome"),
+            Column {
+                name: "tx_hash",
+                ty: ColumnType::Utf8,
+                nullable: true,
+            },
+        ];
+        COLUMNS
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.block.into(),
+            self.route.clone().into(),
+            self.amount_in.into(),
+            self.profit.into(),
+            self.outcome.clone().into(),
+            self.tx_hash.into(),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dataset {
+    Reserves,
+    GasPrices,
+    Opportunities,
+    Activity,
+    RouteHeatmap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Parquet,
+}
+
+/// Writes the entries of `dataset` with a sequence number above `after` to
+/// `path`, returns how many rows were written. The route heatmap is a
+/// table, it's written whole.
+pub async fn export(
+    storage: Arc<dyn Storage>,
+    dataset: Dataset,
+    format: Format,
+    path: impl AsRef<Path>,
+    after: u64,
+) -> Result<usize, ExportError> {
+    match dataset {
+        Dataset::Reserves => export_log::<ReserveRecord>(storage, format, path, after).await,
+        Dataset::GasPrices => export_log::<GasPriceRecord>(storage, format, path, after).await,
+        Dataset::Opportunities => {
+            export_log::<OpportunityRecord>(storage, format, path, after).await
+        }
+        Dataset::Activity => export_log::<ActivityRecord>(storage, format, path, after).await,
+        Dataset::RouteHeatmap => {
+            let table: Table<HeatCell> = Table::new(storage, storage::ROUTE_HEATMAP);
+            let rows: Vec<HeatCell> = table.all().await?.into_iter().map(|(_, r)| r).collect();
+            export_rows(&rows, format, path)
+        }
+    }
+}
+
+/// writes `rows` to `path`, returns how many were written
+pub fn export_rows<R: Record>(
+    rows: &[R],
+    format: Format,
+    path: impl AsRef<Path>,
+) -> Result<usize, ExportError> {
+    let mut writer = match format {
+        Format::Csv => Writer::csv::<R>(path)?,
+        Format::Parquet => Writer::parquet::<R>(path)?,
+    };
+    for page in rows.chunks(PAGE_SIZE) {
+        let page: Vec<Vec<Cell>> = page.iter().map(Record::cells).collect();
+        writer.write(R::columns(), &page)?;
+    }
+    writer.close()?;
+    Ok(rows.len())
+}
+
+pub async fn export_log<R: Record>(
+    storage: Arc<dyn Storage>,
+    format: Format,
+    path: impl AsRef<Path>,
+    mut after: u64,
+) -> Result<usize, ExportError> {
+    let log: Log<R> = Log::new(storage, R::LOG);
+    let mut writer = match format {
+        Format::Csv => Writer::csv::<R>(path)?,
+        Format::Parquet => Writer::parquet::<R>(path)?,
+    };
+    let mut rows = 0;
+    loop {
+        let entries = log.read(after, PAGE_SIZE).await?;
+        if entries.is_empty() {
+            break;
+        }
+        after = entries.last().unwrap().0;
+        rows += entries.len();
+        let page: Vec<Vec<Cell>> = entries.iter().map(|(_, record)| record.cells()).collect();
+        writer.write(R::columns(), &page)?;
+    }
+    writer.close()?;
+    Ok(rows)
+}
+
+enum Writer {
+    Csv(csv::Writer<File>),
+    Parquet(SerializedFileWriter<File>),
+}
+
+impl Writer {
+    fn csv<R: Record>(path: impl AsRef<Path>) -> Result<Self, ExportError> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(R::columns().iter().map(|column| column.name))?;
+        Ok(Writer::Csv(writer))
+    }
+
+    fn parquet<R: Record>(path: impl AsRef<Path>) -> Result<Self, ExportError> {
+        let schema = parse_message_type(&parquet_schema(R::LOG, R::columns()))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(Writer::Parquet(SerializedFileWriter::new(
+            File::create(path)?,
+            Arc::new(schema),
+            Arc::new(properties),
+        )?))
+    }
+
+    fn write(&mut self, columns: &[Column], rows: &[Vec<Cell>]) -> Result<(), ExportError> {
+        match self {
+            Writer::Csv(writer) => {
+                for row in rows {
+                    writer.write_record(row.iter().map(Cell::to_csv))?;
+                }
+            }
+            // one row group per page
+            Writer::Parquet(writer) => {
+                let mut row_group = writer.next_row_group()?;
+                for (i, column) in columns.iter().enumerate() {
+                    let mut column_writer = row_group.next_column()?.unwrap();
+                    let def_levels: Vec<i16> = rows
+                        .iter()
+                        .map(|row| (row[i] != Cell::Null) as i16)
+                        .collect();
+                    let def_levels = column.nullable.then_some(def_levels.as_slice());
+                    match column.ty {
+                        ColumnType::Int64 => {
+                            let values: Vec<i64> = rows
+                                .iter()
+                                .filter_map(|row| match row[i] {
+                                    Cell::Int64(value) => Some(value),
+                                    _ => None,
+                                })
+                                .collect();
+                            column_writer
+                                .typed::<Int64Type>()
+                                .write_batch(&values, def_levels, None)?;
+                        }
+                        ColumnType::Float64 => {
+                            let values: Vec<f64> = rows
+                                .iter()
+                                .filter_map(|row| match row[i] {
+                                    Cell::Float64(value) => Some(value),
+                                    _ => None,
+                                })
+                                .collect();
+                            column_writer
+                                .typed::<DoubleType>()
+                                .write_batch(&values, def_levels, None)?;
+                        }
+                        ColumnType::Utf8 => {
+                            let values: Vec<ByteArray> = rows
+                                .iter()
+                                .filter_map(|row| match &row[i] {
+                                    Cell::Utf8(value) => Some(value.as_str().into()),
+                                    _ => None,
+                                })
+                                .collect();
+                            column_writer
+                                .typed::<ByteArrayType>()
+                                .write_batch(&values, def_levels, None)?;
+                        }
+                    }
+                    column_writer.close()?;
+                }
+                row_group.close()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<(), ExportError> {
+        match self {
+            Writer::Csv(mut writer) => writer.flush()?,
+            Writer::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parquet_schema(name: &str, columns: &[Column]) -> String {
+    let fields: String = columns
+        .iter()
+        .map(|column| {
+            let repetition = if column.nullable {
+                "OPTIONAL"
+            } else {
+                "REQUIRED"
+            };
+            match column.ty {
+                ColumnType::Int64 => format!("{} INT64 {}; ", repetition, column.name),
+                ColumnType::Float64 => format!("{} DOUBLE {}; ", repetition, column.name),
+                ColumnType::Utf8 => format!("{} BYTE_ARRAY {} (UTF8); ", repetition, column.name),
+            }
+        })
+        .collect();
+    format!("message {} {{ {}}}", name, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_export_opportunities() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let log: Log<OpportunityRecord> = Log::new(storage.clone(), storage::OPPORTUNITIES);
+        for block in 0..3 {
+            let record = OpportunityRecord {
+                block,
+                route: "USDC>WETH>USDC".to_string(),
+                amount_in: U256::exp10(30),
+                profit: U256::from(1000),
+                outcome: "submitted".to_string(),
+                tx_hash: (block == 1).then(|| H256::repeat_byte(1)),
+            };
+            log.append(&record).await.unwrap();
+        }
+
+        let dir = std::env::temp_dir();
+        let csv_path = dir.join("test_export_opportunities.csv");
+        let rows = export(
+            storage.clone(),
+            Dataset::Opportunities,
+            Format::Csv,
+            &csv_path,
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows, 3);
+        let contents = std::fs::read_to_string(&csv_path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[0], "block,route,amount_in,profit,outcome,tx_hash");
+        assert_eq!(
+            lines[1],
+            "0,USDC>WETH>USDC,1000000000000000000000000000000,1000,submitted,"
+        );
+
+        let parquet_path = dir.join("test_export_opportunities.parquet");
+        export(
+            storage,
+            Dataset::Opportunities,
+            Format::Parquet,
+            &parquet_path,
+            1,
+        )
+        .await
+        .unwrap();
+        let reader = SerializedFileReader::new(File::open(&parquet_path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_eq!(
+            reader
+                .metadata()
+                .file_metadata()
+                .schema_descr()
+                .num_columns(),
+            OpportunityRecord::columns().len()
+        );
+        std::fs::remove_file(csv_path).unwrap();
+        std::fs::remove_file(parquet_path).unwrap();
+    }
+}
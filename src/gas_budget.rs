@@ -0,0 +1,642 @@
+//! Caps on the MATIC spent on gas, from a json file shared by the binaries:
+//!
+//! ```json
+//! {
+//!     "global": { "hourly": 5.0, "daily": 40.0 },
+//!     "wallets": { "0x...": { "daily": 25.0 } },
+//!     "strategies": { "arb": { "hourly": 2.5 }, "liquidations": { "daily": 15.0 } },
+//!     "alert_at": 0.8
+//! }
+//! ```
+//!
+//! Amounts are whole MATIC over the trailing hour or day, a scope without
+//! one is unlimited in that window. Before signing, a submission's
+//! estimated cost has to fit under every budget it falls under (the
+//! global one, its wallet's and its strategy's) on top of what mined txns
+//! paid in the window; one that doesn't is skipped. A budget reaching
+//! `alert_at` of its limit, and then the limit, is alerted on once until
+//! its spend ages back under. Without storage spend is counted per
+//! process, so budgets shared by both binaries bound each of them rather
+//! than their sum. With storage every spend goes to the `gas_spends` log,
+//! and the spends other processes logged are counted before each check,
+//! so the budgets bound every bot sharing the storage.
+//! Unlike the PnL they never net gas against profits: a bot winning on
+//! paper still stops spending.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ethers::types::{Address, U256};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    storage::{Log, Storage, GAS_SPENDS},
+    utils::fixed_point::to_f64,
+};
+
+pub const DEFAULT_GAS_BUDGETS: &str = "data/gas_budgets.json";
+
+/// share of a budget spent when it's alerted on
+pub const DEFAULT_ALERT_AT: f64 = 0.8;
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+// log entries read per page
+const PAGE_SIZE: usize = 10_000;
+
+#[derive(Debug, Error)]
+pub enum GasBudgetError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetWindow {
+    Hourly,
+    Daily,
+}
+
+impl BudgetWindow {
+    pub const ALL: [BudgetWindow; 2] = [BudgetWindow::Hourly, BudgetWindow::Daily];
+
+    pub fn secs(self) -> u64 {
+        match self {
+            BudgetWindow::Hourly => HOUR_SECS,
+            BudgetWindow::Daily => DAY_SECS,
+        }
+    }
+}
+
+impl fmt::Display for BudgetWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BudgetWindow::Hourly => write!(f, "hourly"),
+            BudgetWindow::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+/// MATIC a scope may spend per window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub struct Limits {
+    pub hourly: Option<f64>,
+    pub daily: Option<f64>,
+}
+
+impl Limits {
+    /// in wei
+    pub fn get(&self, window: BudgetWindow) -> Option<U256> {
+        let matic = match window {
+            BudgetWindow::Hourly => self.hourly,
+            BudgetWindow::Daily => self.daily,
+        }?;
+        Some(U256::from((matic * 1e18) as u128))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BudgetConfig {
+    #[serde(default)]
+    pub global: Limits,
+    #[serde(default)]
+    pub wallets: HashMap<Address, Limits>,
+    /// by the names in `schedule`
+    #[serde(default)]
+    pub strategies: HashMap<String, Limits>,
+    #[serde(default = "default_alert_at")]
+    pub alert_at: f64,
+}
+
+fn default_alert_at() -> f64 {
+    DEFAULT_ALERT_AT
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            global: Limits::default(),
+            wallets: HashMap::new(),
+            strategies: HashMap::new(),
+            alert_at: DEFAULT_ALERT_AT,
+        }
+    }
+}
+
+impl BudgetConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GasBudgetError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// like `load`, but a missing file budgets nothing
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, GasBudgetError> {
+        match Self::load(path) {
+            Err(GasBudgetError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            config => config,
+        }
+    }
+
+    /// every budget set, global first, in wei
+    fn budgets(&self) -> Vec<(BudgetScope, BudgetWindow, U256)> {
+        let mut scopes = vec![(BudgetScope::Global, self.global)];
+        let mut wallets: Vec<_> = self.wallets.iter().collect();
+        wallets.sort_by_key(|(wallet, _)| **wallet);
+        scopes.extend(
+            wallets
+                .into_iter()
+                .map(|(wallet, limits)| (BudgetScope::Wallet(*wallet), *limits)),
+        );
+        let mut strategies: Vec<_> = self.strategies.iter().collect();
+        strategies.sort_by_key(|(strategy, _)| strategy.as_str());
+        scopes.extend(
+            strategies
+                .into_iter()
+                .map(|(strategy, limits)| (BudgetScope::Strategy(strategy.clone()), *limits)),
+        );
+        scopes
+            .into_iter()
+            .flat_map(|(scope, limits)| {
+                BudgetWindow::ALL.into_iter().filter_map(move |window| {
+                    limits
+                        .get(window)
+                        .map(|limit| (scope.clone(), window, limit))
+                })
+            })
+            .collect()
+    }
+}
+
+/// what a budget covers
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "scope", content = "of", rename_all = "snake_case")]
+pub enum BudgetScope {
+    Global,
+    Wallet(Address),
+    Strategy(String),
+}
+
+impl BudgetScope {
+    pub fn covers(&self, wallet: Address, strategy: &str) -> bool {
+        match self {
+            BudgetScope::Global => true,
+            BudgetScope::Wallet(of) => *of == wallet,
+            BudgetScope::Strategy(of) => of == strategy,
+        }
+    }
+}
+
+impl fmt::Display for BudgetScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BudgetScope::Global => write!(f, "global"),
+            BudgetScope::Wallet(wallet) => write!(f, "wallet {:?}", wallet),
+            BudgetScope::Strategy(strategy) => write!(f, "strategy {}", strategy),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertLevel {
+    /// past `alert_at` of the limit
+    Warning,
+    Exhausted,
+}
+
+/// gas a mined txn paid
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasSpend {
+    /// unix seconds
+    pub timestamp: u64,
+    pub wallet: Address,
+    pub strategy: String,
+    /// wei of MATIC
+    pub paid: U256,
+}
+
+/// a budget and what was spent of it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BudgetStatus {
+    #[serde(flatten)]
+    pub scope: BudgetScope,
+    pub window: BudgetWindow,
+    /// wei, as is `limit`
+    pub spent: U256,
+    pub limit: U256,
+    pub level: Option<AlertLevel>,
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("{window} {scope} gas budget exceeded, {spent} of {limit} wei spent and {cost} more")]
+pub struct OverBudget {
+    pub scope: BudgetScope,
+    pub window: BudgetWindow,
+    pub spent: U256,
+    pub cost: U256,
+    pub limit: U256,
+}
+
+#[derive(Debug, Default)]
+struct Spending {
+    /// of the last day, oldest first
+    spends: VecDeque<GasSpend>,
+    alerted: HashMap<(BudgetScope, BudgetWindow), AlertLevel>,
+}
+
+impl Spending {
+    fn spent(&self, scope: &BudgetScope, window: BudgetWindow, now: u64) -> U256 {
+        let since = now.saturating_sub(window.secs());
+        self.spends
+            .iter()
+            .filter(|spend| spend.timestamp > since && scope.covers(spend.wallet, &spend.strategy))
+            .fold(U256::zero(), |spent, spend| {
+                spent.saturating_add(spend.paid)
+            })
+    }
+
+    /// counts `spends`, dropping what's a day old at `now`
+    fn count(&mut self, spends: impl IntoIterator<Item = GasSpend>, now: u64) {
+        self.spends.extend(spends);
+        while self
+            .spends
+            .front()
+            .is_some_and(|spend| spend.timestamp + DAY_SECS <= now)
+        {
+            self.spends.pop_front();
+        }
+    }
+}
+
+/// Spend against the budgets of `config`.
+#[derive(Default)]
+pub struct GasBudgets {
+    config: BudgetConfig,
+    budgets: Vec<(BudgetScope, BudgetWindow, U256)>,
+    spending: Mutex<Spending>,
+    /// spends of every process sharing the storage
+    log: Option<Log<GasSpend>>,
+    /// last entry of `log` counted, held while reading it so no entry is
+    /// counted twice
+    synced: tokio::sync::Mutex<u64>,
+}
+
+impl fmt::Debug for GasBudgets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GasBudgets")
+            .field("budgets", &self.budgets)
+            .field("shared", &self.log.is_some())
+            .finish()
+    }
+}
+
+impl GasBudgets {
+    pub fn new(config: BudgetConfig) -> Self {
+        Self {
+            budgets: config.budgets(),
+            config,
+            spending: Mutex::new(Spending::default()),
+            log: None,
+            synced: tokio::sync::Mutex::new(0),
+        }
+    }
+
+    /// Shares the spend with every process keeping its spends in the
+    /// `gas_spends` log of `storage`.
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.log = Some(Log::new(storage, GAS_SPENDS));
+        self
+    }
+
+    /// whether any budget is set
+    pub fn is_enabled(&self) -> bool {
+        !self.budgets.is_empty()
+    }
+
+    /// The first budget a txn of `strategy` from `wallet` costing `cost`
+    /// wei doesn't fit in, if any.
+    pub fn exceeded(
+        &self,
+        wallet: Address,
+        strategy: &str,
+        cost: U256,
+        now: u64,
+    ) -> Option<OverBudget> {
+        let spending = self.spending.lock().unwrap();
+        for (scope, window, limit) in &self.budgets {
+            if !scope.covers(wallet, strategy) {
+                continue;
+            }
+            let spent = spending.spent(scope, *window, now);
+            if spent.saturating_add(cost) > *limit {
+                return Some(OverBudget {
+                    scope: scope.clone(),
+                    window: *window,
+                    spent,
+                    cost,
+                    limit: *limit,
+                });
+            }
+        }
+        None
+    }
+
+    /// Counts `spend`, logging it to the storage if shared, returning the
+    /// budgets that reached a higher alert level with it and any spends of
+    /// other processes counted along.
+    pub async fn record(&self, spend: GasSpend) -> Vec<BudgetStatus> {
+        let now = spend.timestamp;
+        if let Some(log) = &self.log {
+            match log.append(&spend).await {
+                Ok(_) => return self.sync(now).await,
+                Err(e) => warn!("Failed to log gas spend: {:?}", e),
+            }
+        }
+        let mut spending = self.spending.lock().unwrap();
+        spending.count([spend], now);
+        self.alerts(&mut spending, now)
+    }
+
+    /// Counts the spends other processes logged to the shared storage since
+    /// the last sync, returning the budgets that reached a higher alert
+    /// level with them. Without storage there's nothing to count.
+    pub async fn sync(&self, now: u64) -> Vec<BudgetStatus> {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return Vec::new(),
+        };
+        let mut synced = self.synced.lock().await;
+        let since = now.saturating_sub(DAY_SECS);
+        let mut spends = Vec::new();
+        loop {
+            let page = match log.read(*synced, PAGE_SIZE).await {
+                Ok(page) => page,
+                Err(e) => {
+                    // checked against what's counted so far
+                    warn!("Failed to read gas spends: {:?}", e);
+                    break;
+                }
+            };
+            let last = match page.last() {
+                Some((seq, _)) => *seq,
+                None => break,
+            };
+            spends.extend(
+                page.into_iter()
+                    .map(|(_, spend)| spend)
+                    .filter(|spend| spend.timestamp > since),
+            );
+            *synced = last;
+        }
+        let mut spending = self.spending.lock().unwrap();
+        spending.count(spends, now);
+        self.alerts(&mut spending, now)
+    }
+
+    fn alerts(&self, spending: &mut Spending, now: u64) -> Vec<BudgetStatus> {
+        let mut alerts = Vec::new();
+        for status in self.statuses(spending, now) {
+            let key = (status.scope.clone(), status.window);
+            let previous = match status.level {
+                Some(level) => spending.alerted.insert(key, level),
+                None => spending.alerted.remove(&key),
+            };
+            if status.level > previous {
+                warn!(
+                    "{} {} gas budget {:?}: {:.4} of {:.4} MATIC spent",
+                    status.window,
+                    status.scope,
+                    status.level.unwrap(),
+                    to_f64(status.spent) / 1e18,
+                    to_f64(status.limit) / 1e18
+                );
+                alerts.push(status);
+            }
+        }
+        alerts
+    }
+
+    /// every budget at `now`
+    pub fn status(&self, now: u64) -> Vec<BudgetStatus> {
+        self.statuses(&self.spending.lock().unwrap(), now)
+    }
+
+    fn statuses(&self, spending: &Spending, now: u64) -> Vec<BudgetStatus> {
+        self.budgets
+            .iter()
+            .map(|(scope, window, limit)| {
+                let spent = spending.spent(scope, *window, now);
+                let level = if spent >= *limit {
+                    Some(AlertLevel::Exhausted)
+                } else if to_f64(spent) >= to_f64(*limit) * self.config.alert_at {
+                    Some(AlertLevel::Warning)
+                } else {
+                    None
+                };
+                BudgetStatus {
+                    scope: scope.clone(),
+                    window: *window,
+                    spent,
+                    limit: *limit,
+                    level,
+                }
+            })
+            .collect()
+    }
+
+    /// the budgets at `now` in the Prometheus text format
+    pub fn prometheus(&self, now: u64) -> String {
+        let all = self.status(now);
+        let mut out = String::new();
+        let mut metric = |name: &str, help: &str, value: &dyn Fn(&BudgetStatus) -> U256| {
+            let _ = writeln!(out, "# HELP tsuki_gas_budget_{name} {help}");
+            let _ = writeln!(out, "# TYPE tsuki_gas_budget_{name} gauge");
+            for status in &all {
+                let of = match &status.scope {
+                    BudgetScope::Global => String::new(),
+                    BudgetScope::Wallet(wallet) => format!("{:?}", wallet),
+                    BudgetScope::Strategy(strategy) => strategy.clone(),
+                };
+                let scope = serde_json::to_value(&status.scope).unwrap();
+                let _ = writeln!(
+                    out,
+                    "tsuki_gas_budget_{name}{{scope={},of=\"{}\",window=\"{}\"}} {}",
+                    scope["scope"],
+                    of,
+                    status.window,
+                    to_f64(value(status)) / 1e18
+                );
+            }
+        };
+        metric(
+            "spent_matic",
+            "gas paid by mined txns in the window",
+            &|status| status.spent,
+        );
+        metric("limit_matic", "gas budget of the window", &|status| {
+            status.limit
+        });
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    const MATIC: u64 = 1_000_000_000_000_000_000;
+
+    #[tokio::test]
+    async fn test_gas_budgets() {
+        let wallet = Address::repeat_byte(1);
+        let config: BudgetConfig = serde_json::from_str(&format!(
+            r#"{{
+                "global": {{ "daily": 10.0 }},
+                "wallets": {{ "{:?}": {{ "hourly": 1.0 }} }},
+                "strategies": {{ "arb": {{ "hourly": 2.0 }} }}
+            }}"#,
+            wallet
+        ))
+        .unwrap();
+        assert_eq!(config.alert_at, DEFAULT_ALERT_AT);
+        let budgets = GasBudgets::new(config);
+        assert!(budgets.is_enabled());
+        assert!(!GasBudgets::default().is_enabled());
+
+        let spend = |timestamp: u64, wallet: Address, tenths: u64| GasSpend {
+            timestamp,
+            wallet,
+            strategy: "arb".to_string(),
+            paid: U256::from(tenths * MATIC / 10),
+        };
+        let now = 1_700_000_000;
+        assert!(budgets.record(spend(now, wallet, 5)).await.is_empty());
+        // the wallet's 0.8 MATIC alert
+        let alerts = budgets.record(spend(now + 60, wallet, 3)).await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].scope, BudgetScope::Wallet(wallet));
+        assert_eq!(alerts[0].level, Some(AlertLevel::Warning));
+        assert!(budgets.record(spend(now + 120, wallet, 1)).await.is_empty());
+
+        assert_eq!(
+            budgets.exceeded(wallet, "arb", U256::from(2 * MATIC / 10), now + 180),
+            Some(OverBudget {
+                scope: BudgetScope::Wallet(wallet),
+                window: BudgetWindow::Hourly,
+                spent: U256::from(9 * MATIC / 10),
+                cost: U256::from(2 * MATIC / 10),
+                limit: U256::from(MATIC),
+            })
+        );
+        // other wallets only have the strategy's 2 MATIC left to fill
+        let other = Address::repeat_byte(2);
+        assert!(budgets
+            .exceeded(other, "arb", U256::from(11 * MATIC / 10), now + 180)
+            .is_none());
+        assert!(matches!(
+            budgets.exceeded(other, "arb", U256::from(12 * MATIC / 10), now + 180),
+            Some(OverBudget {
+                scope: BudgetScope::Strategy(_),
+                ..
+            })
+        ));
+        assert!(budgets
+            .exceeded(other, "liquidations", U256::from(5 * MATIC), now + 180)
+            .is_none());
+        // an hour on the wallet's spend aged out
+        assert!(budgets
+            .exceeded(
+                wallet,
+                "arb",
+                U256::from(2 * MATIC / 10),
+                now + HOUR_SECS + 120
+            )
+            .is_none());
+
+        let status = budgets.status(now + 180);
+        assert_eq!(status.len(), 3);
+        assert_eq!(status[0].scope, BudgetScope::Global);
+        assert_eq!(
+            serde_json::to_value(&status[1]).unwrap()["of"],
+            serde_json::to_value(wallet).unwrap()
+        );
+        assert!(budgets.prometheus(now + 180).contains(
+            "tsuki_gas_budget_limit_matic{scope=\"strategy\",of=\"arb\",window=\"hourly\"} 2"
+        ));
+
+        // alerted again once it aged back under
+        assert!(budgets
+            .record(spend(now + 2 * HOUR_SECS, wallet, 1))
+            .await
+            .is_empty());
+        assert_eq!(
+            budgets.record(spend(now + 2 * HOUR_SECS, wallet, 8)).await[0].level,
+            Some(AlertLevel::Warning)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shared_gas_budgets() {
+        let config: BudgetConfig =
+            serde_json::from_str(r#"{ "global": { "hourly": 1.0 } }"#).unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let now = 1_700_000_000;
+        let spend = |timestamp: u64, strategy: &str, tenths: u64| GasSpend {
+            timestamp,
+            wallet: Address::repeat_byte(1),
+            strategy: strategy.to_string(),
+            paid: U256::from(tenths * MATIC / 10),
+        };
+        // a day old, not counted
+        Log::new(storage.clone(), GAS_SPENDS)
+            .append(&spend(now - DAY_SECS, "arb", 9))
+            .await
+            .unwrap();
+
+        let arb = GasBudgets::new(config.clone()).with_storage(storage.clone());
+        let liquidations = GasBudgets::new(config).with_storage(storage);
+        assert!(arb.sync(now).await.is_empty());
+        assert!(arb.record(spend(now, "arb", 6)).await.is_empty());
+        assert_eq!(arb.status(now)[0].spent, U256::from(6 * MATIC / 10));
+
+        let cost = U256::from(5 * MATIC / 10);
+        let wallet = Address::repeat_byte(1);
+        assert!(liquidations
+            .exceeded(wallet, "liquidations", cost, now)
+            .is_none());
+        // the arb's spend pushes it past the global hour
+        assert!(liquidations.sync(now + 60).await.is_empty());
+        assert_eq!(
+            liquidations
+                .exceeded(wallet, "liquidations", cost, now + 60)
+                .map(|e| e.spent),
+            Some(U256::from(6 * MATIC / 10))
+        );
+        let alerts = liquidations
+            .record(spend(now + 120, "liquidations", 3))
+            .await;
+        assert_eq!(alerts[0].level, Some(AlertLevel::Warning));
+        // counted once each
+        assert_eq!(
+            arb.sync(now + 180).await[0].spent,
+            U256::from(9 * MATIC / 10)
+        );
+        assert!(arb.sync(now + 240).await.is_empty());
+        assert_eq!(
+            arb.status(now + 240)[0].spent,
+            liquidations.status(now + 240)[0].spent
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use ethers::{
+    providers::{Middleware, PubsubClient},
+    types::{Block, BlockId, H256, U256},
+};
+use futures_channel::mpsc;
+use futures_util::{Stream, StreamExt};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::bus::Bus;
+
+/// canonical headers kept, polygon reorgs rarely go deeper
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Error)]
+pub enum HeaderError<E: std::error::Error + 'static> {
+    #[error(transparent)]
+    Provider(E),
+
+    /// the node announced a block it can't serve the parent of yet, the
+    /// header is left for the next one to link
+    #[error("node doesn't have parent {0:?}")]
+    MissingParent(H256),
+}
+
+/// the parts of a header fork choice cares about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderInfo {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub timestamp: U256,
+}
+
+impl<T> TryFrom<&Block<T>> for HeaderInfo {
+    type Error = ();
+
+    /// fails for pending blocks, they have no number or hash yet
+    fn try_from(block: &Block<T>) -> Result<Self, ()> {
+        Ok(Self {
+            number: block.number.ok_or(())?.as_u64(),
+            hash: block.hash.ok_or(())?,
+            parent_hash: block.parent_hash,
+            timestamp: block.timestamp,
+        })
+    }
+}
+
+/// `old` blocks were replaced by `new`, both oldest first
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reorg {
+    pub depth: usize,
+    pub old: Vec<HeaderInfo>,
+    pub new: Vec<HeaderInfo>,
+}
+
+impl Reorg {
+    /// last block both chains agree on, none if they fork at genesis
+    pub fn common_ancestor(&self) -> Option<u64> {
+        self.new.first()?.number.checked_sub(1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChainEvent {
+    NewHead(HeaderInfo),
+    Reorg(Reorg),
+}
+
+/// where a branch attaches to the canonical chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// index of the canonical block the branch builds on
+    Ancestor(usize),
+    /// the branch's parent is unknown, fetch it and retry
+    NeedParent(H256),
+    /// the branch forks off below what we keep, start over from it
+    Unlinked,
+}
+
+/// The last `max_depth` canonical headers.
+#[derive(Clone, Debug)]
+pub struct ChainView {
+    canonical: VecDeque<HeaderInfo>,
+    max_depth: usize,
+}
+
+impl ChainView {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            canonical: VecDeque::with_capacity(max_depth),
+            max_depth,
+        }
+    }
+
+    pub fn tip(&self) -> Option<&HeaderInfo> {
+        self.canonical.back()
+    }
+
+    pub fn contains(&self, hash: H256) -> bool {
+        self.canonical.iter().any(|block| block.hash == hash)
+    }
+
+    /// canonical hash at `number`, if we still keep it
+    pub fn hash_at(&self, number: u64) -> Option<H256> {
+        let oldest = self.canonical.front()?.number;
+        self.canonical
+            .get(number.checked_sub(oldest)? as usize)
+            .map(|block| block.hash)
+    }
+
+    /// `branch` is oldest first
+    pub fn link(&self, branch: &[HeaderInfo]) -> Link {
+        let first = match (branch.first(), self.canonical.front()) {
+            (Some(first), Some(_)) => first,
+            _ => return Link::Unlinked,
+        };
+        if let Some(idx) = self
+            .canonical
+            .iter()
+            .position(|block| block.hash == first.parent_hash)
+        {
+            return Link::Ancestor(idx);
+        }
+        if first.number <= self.canonical.front().unwrap().number {
+            return Link::Unlinked;
+        }
+        Link::NeedParent(first.parent_hash)
+    }
+
+    /// makes `branch` canonical on top of `link`
+    pub fn apply(&mut self, branch: Vec<HeaderInfo>, link: Link) -> Vec<ChainEvent> {
+        let mut events = Vec::new();
+        let old: Vec<HeaderInfo> = match link {
+            Link::Ancestor(idx) => self.canonical.drain(idx + 1..).collect(),
+            Link::Unlinked | Link::NeedParent(_) => self.canonical.drain(..).collect(),
+        };
+        if !old.is_empty() {
+            events.push(ChainEvent::Reorg(Reorg {
+                depth: old.len(),
+                old,
+                new: branch.clone(),
+            }));
+        }
+        let head = branch.last().copied();
+        self.canonical.extend(branch);
+        while self.canonical.len() > self.max_depth {
+            self.canonical.pop_front();
+        }
+        if let Some(head) = head {
+            events.push(ChainEvent::NewHead(head));
+        }
+        events
+    }
+}
+
+/// Follows new heads and keeps the canonical chain view, fetching missing
+/// ancestors by hash to find where a reorg forked off.
+pub struct HeaderTracker<M> {
+    provider: Arc<M>,
+    view: ChainView,
+}
+
+impl<M: Middleware + 'static> HeaderTracker<M> {
+    pub fn new(provider: Arc<M>, max_depth: usize) -> Self {
+        Self {
+            provider,
+            view: ChainView::new(max_depth),
+        }
+    }
+
+    pub fn view(&self) -> &ChainView {
+        &self.view
+    }
+
+    pub async fn on_header(
+        &mut self,
+        header: HeaderInfo,
+    ) -> Result<Vec<ChainEvent>, HeaderError<M::Error>> {
+        // duplicate notification, or a reorg back onto a block we still
+        // consider canonical, which the next head sorts out
+        if self.view.contains(header.hash) {
+            return Ok(Vec::new());
+        }
+        let mut branch = vec![header];
+        let link = loop {
+            match self.view.link(&branch) {
+                Link::NeedParent(parent_hash) => {
+                    let parent = self
+                        .provider
+                        .get_block(BlockId::Hash(parent_hash))
+                        .await
+                        .map_err(HeaderError::Provider)?
+                        .and_then(|block| HeaderInfo::try_from(&block).ok())
+                        .ok_or(HeaderError::MissingParent(parent_hash))?;
+                    branch.insert(0, parent);
+                }
+                link => break link,
+            }
+        };
+        Ok(self.view.apply(branch, link))
+    }
+
+    /// Chain events for every new head. The stream ends if the subscription
+    /// does or can't be made.
+    pub async fn stream(mut self) -> impl Stream<Item = ChainEvent>
+    where
+        <M as Middleware>::Provider: PubsubClient,
+    {
+        let (sender, receiver) = mpsc::unbounded();
+        tokio::spawn(async move {
+            let provider = self.provider.clone();
+            let mut block_stream = match provider.subscribe_blocks().await {
+                Ok(block_stream) => block_stream,
+                Err(e) => {
+                    warn!("Failed to subscribe to blocks: {}", e);
+                    return;
+                }
+            };
+            while let Some(block) = block_stream.next().await {
+                let header = match HeaderInfo::try_from(&block) {
+                    Ok(header) => header,
+                    Err(_) => continue,
+                };
+                let events = match self.on_header(header).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        warn!("could not fetch ancestors of {:?}: {}", header.hash, e);
+                        continue;
+                    }
+                };
+                for event in events {
+                    if sender.unbounded_send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Publishes every reorg to `bus` until the subscription ends.
+    pub async fn publish_reorgs(self, bus: Arc<Bus>)
+    where
+        <M as Middleware>::Provider: PubsubClient,
+    {
+        let mut events = self.stream().await;
+        while let Some(event) = events.next().await {
+            if let ChainEvent::Reorg(reorg) = event {
+                error!(
+                    "Reorg of depth {} past block {:?}",
+                    reorg.depth,
+                    reorg.common_ancestor()
+                );
+                bus.reorgs.publish(reorg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+
+    use super::*;
+    use crate::utils::batch::fake::FakeTransport;
+
+    fn header(number: u64, fork: u64, parent: &HeaderInfo) -> HeaderInfo {
+        HeaderInfo {
+            number,
+            hash: H256::from_low_u64_be(number * 100 + fork),
+            parent_hash: parent.hash,
+            timestamp: U256::zero(),
+        }
+    }
+
+    #[test]
+    fn test_chain_view_reorg() {
+        let mut view = ChainView::new(4);
+        let genesis = HeaderInfo {
+            number: 1,
+            hash: H256::from_low_u64_be(100),
+            parent_hash: H256::zero(),
+            timestamp: U256::zero(),
+        };
+        let b2 = header(2, 0, &genesis);
+        let b3 = header(3, 0, &b2);
+        view.apply(vec![genesis], Link::Unlinked);
+        assert_eq!(view.link(&[b2]), Link::Ancestor(0));
+        view.apply(vec![b2], Link::Ancestor(0));
+        assert_eq!(
+            view.apply(vec![b3], view.link(&[b3])),
+            vec![ChainEvent::NewHead(b3)]
+        );
+
+        // competing 3 and 4 arrive, 4 first
+        let b3_fork = header(3, 1, &b2);
+        let b4_fork = header(4, 1, &b3_fork);
+        assert_eq!(view.link(&[b4_fork]), Link::NeedParent(b3_fork.hash));
+        let link = view.link(&[b3_fork, b4_fork]);
+        assert_eq!(link, Link::Ancestor(1));
+        let events = view.apply(vec![b3_fork, b4_fork], link);
+        assert_eq!(
+            events,
+            vec![
+                ChainEvent::Reorg(Reorg {
+                    depth: 1,
+                    old: vec![b3],
+                    new: vec![b3_fork, b4_fork],
+                }),
+                ChainEvent::NewHead(b4_fork),
+            ]
+        );
+        assert_eq!(view.hash_at(3), Some(b3_fork.hash));
+        assert_eq!(view.tip(), Some(&b4_fork));
+
+        // only the last 4 blocks are kept
+        let b5 = header(5, 1, &b4_fork);
+        view.apply(vec![b5], view.link(&[b5]));
+        assert_eq!(view.hash_at(1), None);
+        assert_eq!(view.hash_at(5), Some(b5.hash));
+    }
+
+    #[tokio::test]
+    async fn test_missing_parent() {
+        let transport = FakeTransport::new();
+        let mut tracker = HeaderTracker::new(Arc::new(Provider::new(transport.clone())), 4);
+        let genesis = HeaderInfo {
+            number: 1,
+            hash: H256::from_low_u64_be(100),
+            parent_hash: H256::zero(),
+            timestamp: U256::zero(),
+        };
+        let b2 = header(2, 0, &genesis);
+        tracker.view.apply(vec![genesis, b2], Link::Unlinked);
+
+        // not a reorg of everything kept, the node just hasn't got 3 yet
+        let b3 = header(3, 0, &b2);
+        let b4 = header(4, 0, &b3);
+        transport.push_response("eth_getBlockByHash", serde_json::Value::Null);
+        assert!(matches!(
+            tracker.on_header(b4).await,
+            Err(HeaderError::MissingParent(hash)) if hash == b3.hash
+        ));
+        assert_eq!(tracker.view().tip(), Some(&b2));
+    }
+}
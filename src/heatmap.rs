@@ -0,0 +1,261 @@
+//! Where the edge is: every quote of every route is counted by UTC hour of
+//! day, with how often it cleared `min_edge_bps` and its average edge
+//! (amount out over amount in, in bps, negative when the route loses). The
+//! routes that clear it most often, and the hours they do, are the ones
+//! worth tighter latency work.
+//!
+//! Cells are kept in the `route_heatmap` table so the counts build up
+//! across restarts, and can be exported with `data export route-heatmap`.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    export::{float64, int64, utf8, Cell, Column, Record},
+    storage::{self, StorageError, Table},
+    utils::fixed_point::to_f64,
+};
+
+/// edge a quote needs to count as above the threshold
+pub const DEFAULT_MIN_EDGE_BPS: f64 = 10.0;
+
+/// Evaluations of one route (path and amount) in one hour of the day.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeatCell {
+    /// token symbols joined by `>`
+    pub route: String,
+    pub amount_in: U256,
+    /// UTC, 0 to 23
+    pub hour: u8,
+    pub evaluations: u64,
+    /// evaluations with an edge of at least `min_edge_bps`
+    pub above_threshold: u64,
+    pub edge_bps_sum: f64,
+}
+
+impl HeatCell {
+    pub fn mean_edge_bps(&self) -> f64 {
+        match self.evaluations {
+            0 => 0.0,
+            evaluations => self.edge_bps_sum / evaluations as f64,
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("{} {} {}", self.route, self.amount_in, self.hour)
+    }
+}
+
+impl Record for HeatCell {
+    const LOG: &'static str = storage::ROUTE_HEATMAP;
+
+    fn columns() -> &'static [Column] {
+        const COLUMNS: &[Column] = &[
+            utf8("route"),
+            utf8("amount_in"),
+            int64("hour"),
+            int64("evaluations"),
+            int64("above_threshold"),
+            float64("mean_edge_bps"),
+        ];
+        COLUMNS
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.route.clone().into(),
+            self.amount_in.into(),
+            (self.hour as u64).into(),
+            self.evaluations.into(),
+            self.above_threshold.into(),
+            self.mean_edge_bps().into(),
+        ]
+    }
+}
+
+/// One route over every hour.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteHeat {
+    pub route: String,
+    pub amount_in: U256,
+    pub evaluations: u64,
+    pub above_threshold: u64,
+    /// share of evaluations above the threshold
+    pub frequency: f64,
+    pub mean_edge_bps: f64,
+    /// the hour with the most evaluations above the threshold, the higher
+    /// mean edge on ties; none if it never cleared it
+    pub best_hour: Option<u8>,
+}
+
+/// above threshold evaluations and mean edge of an hour, higher is better
+type HourRank = (u64, f64);
+
+pub struct RouteHeatmap {
+    min_edge_bps: f64,
+    cells: Mutex<BTreeMap<(String, U256, u8), HeatCell>>,
+}
+
+impl RouteHeatmap {
+    pub fn new(min_edge_bps: f64) -> Self {
+        Self {
+            min_edge_bps,
+            cells: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// edge of `amount_out` for `amount_in`, in bps of `amount_in`
+    pub fn edge_bps(amount_in: U256, amount_out: U256) -> f64 {
+        match amount_in.is_zero() {
+            true => 0.0,
+            false => (to_f64(amount_out) / to_f64(amount_in) - 1.0) * 10_000.0,
+        }
+    }
+
+    /// counts a quote of `route` for `amount_in` with `edge_bps`, made at
+    /// unix time `at`
+    pub fn record(&self, route: &str, amount_in: U256, edge_bps: f64, at: u64) {
+        let hour = ((at / 3600) % 24) as u8;
+        let mut cells = self.cells.lock().unwrap();
+        let cell = cells
+            .entry((route.to_string(), amount_in, hour))
+            .or_insert_with(|| HeatCell {
+                route: route.to_string(),
+                amount_in,
+                hour,
+                evaluations: 0,
+                above_threshold: 0,
+                edge_bps_sum: 0.0,
+            });
+        cell.evaluations += 1;
+        cell.above_threshold += (edge_bps >= self.min_edge_bps) as u64;
+        cell.edge_bps_sum += edge_bps;
+    }
+
+    /// every cell, by route, amount and hour
+    pub fn cells(&self) -> Vec<HeatCell> {
+        self.cells.lock().unwrap().values().cloned().collect()
+    }
+
+    /// every route, the ones most often above the threshold first
+    pub fn summary(&self) -> Vec<RouteHeat> {
+        // with the rank of the best hour so far
+        let mut routes: BTreeMap<(String, U256), (RouteHeat, Option<HourRank>)> = BTreeMap::new();
+        for cell in self.cells() {
+            let (heat, best) = routes
+                .entry((cell.route.clone(), cell.amount_in))
+                .or_insert_with(|| {
+                    let heat = RouteHeat {
+                        route: cell.route.clone(),
+                        amount_in: cell.amount_in,
+                        evaluations: 0,
+                        above_threshold: 0,
+                        frequency: 0.0,
+                        mean_edge_bps: 0.0,
+                        best_hour: None,
+                    };
+                    (heat, None)
+                });
+            heat.evaluations += cell.evaluations;
+            heat.above_threshold += cell.above_threshold;
+            // the sum until every cell is in
+            heat.mean_edge_bps += cell.edge_bps_sum;
+            let rank = (cell.above_threshold, cell.mean_edge_bps());
+            if cell.above_threshold > 0 && best.is_none_or(|best| rank > best) {
+                *best = Some(rank);
+                heat.best_hour = Some(cell.hour);
+            }
+        }
+        let mut summary: Vec<RouteHeat> = routes
+            .into_values()
+            .map(|(mut heat, _)| {
+                if heat.evaluations > 0 {
+                    heat.frequency = heat.above_threshold as f64 / heat.evaluations as f64;
+                    heat.mean_edge_bps /= heat.evaluations as f64;
+                }
+                heat
+            })
+            .collect();
+        summary.sort_by(|a, b| {
+            b.frequency
+                .total_cmp(&a.frequency)
+                .then(b.mean_edge_bps.total_cmp(&a.mean_edge_bps))
+        });
+        summary
+    }
+
+    /// adds the cells kept in `table` to the counts
+    pub async fn load(&self, table: &Table<HeatCell>) -> Result<usize, StorageError> {
+        let stored = table.all().await?;
+        let mut cells = self.cells.lock().unwrap();
+        for (_, stored) in &stored {
+            let key = (stored.route.clone(), stored.amount_in, stored.hour);
+            match cells.get_mut(&key) {
+                Some(cell) => {
+                    cell.evaluations += stored.evaluations;
+                    cell.above_threshold += stored.above_threshold;
+                    cell.edge_bps_sum += stored.edge_bps_sum;
+                }
+                None => {
+                    cells.insert(key, stored.clone());
+                }
+            }
+        }
+        Ok(stored.len())
+    }
+
+    /// overwrites `table` with the current counts
+    pub async fn save(&self, table: &Table<HeatCell>) -> Result<(), StorageError> {
+        for cell in self.cells() {
+            table.put(&cell.key(), &cell).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_route_heatmap() {
+        let heatmap = RouteHeatmap::new(10.0);
+        let amount = U256::exp10(9);
+        let hour = |h: u64| 1_700_006_400 + h * 3600;
+        assert!((RouteHeatmap::edge_bps(amount, amount + amount / 1000) - 10.0).abs() < 1e-9);
+        heatmap.record("USDC>WETH>USDC", amount, 12.0, hour(3));
+        heatmap.record("USDC>WETH>USDC", amount, -4.0, hour(3));
+        heatmap.record("USDC>WETH>USDC", amount, 30.0, hour(14));
+        heatmap.record("USDC>WETH>USDC", amount, 20.0, hour(14) + 60);
+        heatmap.record("USDC>DAI>USDC", amount, -2.0, hour(3));
+
+        let summary = heatmap.summary();
+        assert_eq!(
+            summary[0],
+            RouteHeat {
+                route: "USDC>WETH>USDC".to_string(),
+                amount_in: amount,
+                evaluations: 4,
+                above_threshold: 3,
+                frequency: 0.75,
+                mean_edge_bps: 14.5,
+                best_hour: Some(14),
+            }
+        );
+        assert_eq!(summary[1].best_hour, None);
+
+        // counts carry over a restart
+        let table = Table::new(Arc::new(MemoryStorage::new()), storage::ROUTE_HEATMAP);
+        heatmap.save(&table).await.unwrap();
+        let restarted = RouteHeatmap::new(10.0);
+        assert_eq!(restarted.load(&table).await.unwrap(), 3);
+        restarted.record("USDC>WETH>USDC", amount, 11.0, hour(3));
+        assert_eq!(restarted.summary()[0].evaluations, 5);
+        assert_eq!(restarted.summary()[0].best_hour, Some(14));
+    }
+}
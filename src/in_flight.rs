@@ -0,0 +1,216 @@
+//! Arb txns sent but not resolved yet, kept in the `in_flight` table from
+//! the send until they're mined or their nonce is used up. A run that
+//! crashes in between leaves them there; on restart `reconcile` asks the
+//! node what became of each, so a txn that landed still counts in the PnL
+//! and one still in the mempool can be waited for or cancelled instead of
+//! holding up every nonce after it.
+
+use std::sync::Arc;
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, TransactionReceipt, H256, U256},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    error::TransportError,
+    storage::{Storage, StorageError, Table, IN_FLIGHT},
+};
+
+#[derive(Error, Debug)]
+pub enum InFlightError {
+    #[error(transparent)]
+    StorageError(#[from] StorageError),
+
+    #[error(transparent)]
+    TransportError(#[from] TransportError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InFlightTx {
+    pub hash: H256,
+    /// `None` if the middleware filled it in
+    pub nonce: Option<U256>,
+    pub gas_price: U256,
+    /// block it was sent in
+    pub block: u64,
+    /// symbol of the route's start token
+    pub token: String,
+    /// quoted, in the start token
+    pub profit: U256,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    Mined(Box<TransactionReceipt>),
+    /// its nonce was mined with another txn, a cancellation or a
+    /// replacement
+    Replaced,
+    /// the node doesn't know it anymore and its nonce is free
+    Dropped,
+    /// still in the mempool, the record is kept
+    Pending,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reconciled {
+    /// with its nonce filled in if the node knew it
+    pub tx: InFlightTx,
+    pub resolution: Resolution,
+}
+
+pub struct InFlight {
+    table: Table<InFlightTx>,
+}
+
+impl InFlight {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            table: Table::new(storage, IN_FLIGHT),
+        }
+    }
+
+    pub async fn sent(&self, tx: &InFlightTx) -> Result<(), StorageError> {
+        self.table.put(&format!("{:?}", tx.hash), tx).await
+    }
+
+    /// whether `hash` was in flight
+    pub async fn resolved(&self, hash: H256) -> Result<bool, StorageError> {
+        self.table.remove(&format!("{:?}", hash)).await
+    }
+
+    pub async fn all(&self) -> Result<Vec<InFlightTx>, StorageError> {
+        Ok(self
+            .table
+            .all()
+            .await?
+            .into_iter()
+            .map(|(_, tx)| tx)
+            .collect())
+    }
+
+    /// Checks every txn in flight from `wallet` against the chain, forgetting
+    /// the ones that resolved. Pending ones are left to the caller to wait
+    /// for or cancel, then mark resolved.
+    pub async fn reconcile<M: Middleware>(
+        &self,
+        provider: &M,
+        wallet: Address,
+    ) -> Result<Vec<Reconciled>, InFlightError> {
+        let in_flight = self.all().await?;
+        if in_flight.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mined_count = provider
+            .get_transaction_count(wallet, Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(TransportError::middleware::<M>)?;
+        let mut reconciled = Vec::new();
+        for mut tx in in_flight {
+            let receipt = provider
+                .get_transaction_receipt(tx.hash)
+                .await
+                .map_err(TransportError::middleware::<M>)?;
+            let resolution = match receipt {
+                Some(receipt) => Resolution::Mined(Box::new(receipt)),
+                None => {
+                    let known = provider
+                        .get_transaction(tx.hash)
+                        .await
+                        .map_err(TransportError::middleware::<M>)?;
+                    tx.nonce = tx.nonce.or(known.as_ref().map(|known| known.nonce));
+                    if tx.nonce.is_some_and(|nonce| nonce < mined_count) {
+                        Resolution::Replaced
+                    } else if known.is_some() {
+                        Resolution::Pending
+                    } else {
+                        Resolution::Dropped
+                    }
+                }
+            };
+            if resolution != Resolution::Pending {
+                self.resolved(tx.hash).await?;
+            }
+            reconciled.push(Reconciled { tx, resolution });
+        }
+        Ok(reconciled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        types::{Transaction, U64},
+    };
+
+    use super::*;
+    use crate::{storage::MemoryStorage, utils::batch::fake::FakeTransport};
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let in_flight = InFlight::new(Arc::new(MemoryStorage::new()));
+        let tx = |byte: u8, nonce: Option<u64>| InFlightTx {
+            hash: H256::repeat_byte(byte),
+            nonce: nonce.map(U256::from),
+            gas_price: 100.into(),
+            block: 1_000,
+            token: "USDC".to_string(),
+            profit: 5_000.into(),
+        };
+        for tx in [tx(1, Some(4)), tx(2, Some(5)), tx(3, None), tx(4, Some(8))] {
+            in_flight.sent(&tx).await.unwrap();
+        }
+
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        // nonces up to 6 are mined
+        transport.push_response("eth_getTransactionCount", "0x7");
+        transport.push_response(
+            "eth_getTransactionReceipt",
+            TransactionReceipt {
+                transaction_hash: H256::repeat_byte(1),
+                status: Some(U64::one()),
+                ..Default::default()
+            },
+        );
+        transport.set_response("eth_getTransactionReceipt", ());
+        // nonce 5 went out with a cancellation
+        transport.push_response("eth_getTransactionByHash", ());
+        transport.push_response(
+            "eth_getTransactionByHash",
+            Transaction {
+                hash: H256::repeat_byte(3),
+                nonce: 7.into(),
+                ..Default::default()
+            },
+        );
+        transport.push_response("eth_getTransactionByHash", ());
+
+        let reconciled = in_flight
+            .reconcile(&provider, Address::repeat_byte(9))
+            .await
+            .unwrap();
+        let resolutions: Vec<_> = reconciled
+            .iter()
+            .map(|reconciled| match &reconciled.resolution {
+                Resolution::Mined(_) => "mined",
+                Resolution::Replaced => "replaced",
+                Resolution::Dropped => "dropped",
+                Resolution::Pending => "pending",
+            })
+            .collect();
+        assert_eq!(resolutions, vec!["mined", "replaced", "pending", "dropped"]);
+        assert_eq!(reconciled[2].tx.nonce, Some(7.into()));
+        // only the pending one is left to resolve
+        assert_eq!(in_flight.all().await.unwrap(), vec![tx(3, None)]);
+        assert!(in_flight.resolved(H256::repeat_byte(3)).await.unwrap());
+        assert!(in_flight
+            .reconcile(&provider, Address::repeat_byte(9))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+//! Token inventory of the hot wallet and the flashloan executor: the
+//! `balanceOf` of each of them in every registry token, read in one
+//! multicall every few blocks. The first read is kept as the baseline, so
+//! the change of the combined holdings since startup is what the arbs
+//! realized, plus whatever was sent in or out, to hold against the quoted
+//! profit in the PnL. The executor's balances are what a sweep would move
+//! back to the wallet.
+
+use std::{
+    fmt::Write,
+    sync::{Arc, RwLock as StdRwLock},
+};
+
+use enum_map::{Enum, EnumMap};
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, I256, U256},
+};
+use log::{error, warn};
+use serde::Serialize;
+
+use crate::{
+    bus::{next, Bus},
+    constants::token::ERC20Token,
+    error::Error,
+    utils::{fixed_point::to_f64, multicall::Multicall},
+};
+
+abigen!(
+    Erc20Balance,
+    r#"[
+        function balanceOf(address owner) external view returns (uint256)
+    ]"#,
+);
+
+/// blocks between reads of the balances
+pub const DEFAULT_INVENTORY_BLOCKS: u64 = 20;
+
+#[derive(Clone, Copy, Debug, Enum, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Holder {
+    Wallet,
+    Executor,
+}
+
+/// Balances of every holder in every token as of one block.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Balances {
+    pub block: u64,
+    amounts: EnumMap<Holder, EnumMap<ERC20Token, U256>>,
+}
+
+impl Balances {
+    pub fn new(block: u64) -> Self {
+        Self {
+            block,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, holder: Holder, token: ERC20Token) -> U256 {
+        self.amounts[holder][token]
+    }
+
+    pub fn set(&mut self, holder: Holder, token: ERC20Token, amount: U256) {
+        self.amounts[holder][token] = amount;
+    }
+
+    /// held by the wallet and the executor together
+    pub fn total(&self, token: ERC20Token) -> U256 {
+        self.amounts.values().fold(U256::zero(), |total, amounts| {
+            total.saturating_add(amounts[token])
+        })
+    }
+}
+
+/// What `/inventory` reports of one token, amounts in its smallest unit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenInventory {
+    pub token: String,
+    pub address: Address,
+    pub wallet: U256,
+    pub executor: U256,
+    /// of the total since the first read
+    pub change: I256,
+}
+
+/// The first and latest balances read.
+#[derive(Debug, Default)]
+pub struct Inventory {
+    baseline: StdRwLock<Option<Balances>>,
+    latest: StdRwLock<Option<Balances>>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, balances: Balances) {
+        self.baseline
+            .write()
+            .unwrap()
+            .get_or_insert_with(|| balances.clone());
+        *self.latest.write().unwrap() = Some(balances);
+    }
+
+    pub fn latest(&self) -> Option<Balances> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// change of the combined holdings of `token` since the first read,
+    /// `None` before any
+    pub fn change(&self, token: ERC20Token) -> Option<I256> {
+        let baseline = self.baseline.read().unwrap();
+        let latest = self.latest.read().unwrap();
+        match (baseline.as_ref(), latest.as_ref()) {
+            (Some(baseline), Some(latest)) => {
+                Some(I256::from_raw(latest.total(token)) - I256::from_raw(baseline.total(token)))
+            }
+            _ => None,
+        }
+    }
+
+    /// the executor's non-zero balances, what a sweep moves to the wallet
+    pub fn sweepable(&self) -> Vec<(ERC20Token, U256)> {
+        let latest = self.latest.read().unwrap();
+        match latest.as_ref() {
+            Some(latest) => latest.amounts[Holder::Executor]
+                .iter()
+                .filter(|(_, amount)| !amount.is_zero())
+                .map(|(token, amount)| (token, *amount))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn tokens(&self) -> Vec<TokenInventory> {
+        let latest = match self.latest() {
+            Some(latest) => latest,
+            None => return Vec::new(),
+        };
+        (0..ERC20Token::LENGTH)
+            .map(ERC20Token::from_usize)
+            .map(|token| TokenInventory {
+                token: token.get_symbol().to_string(),
+                address: token.get_address(),
+                wallet: latest.get(Holder::Wallet, token),
+                executor: latest.get(Holder::Executor, token),
+                change: self.change(token).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// the balances in whole tokens, in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let latest = match self.latest() {
+            Some(latest) => latest,
+            None => return out,
+        };
+        let whole = |token: ERC20Token, amount: U256| {
+            to_f64(amount) / 10f64.powi(token.get_decimals() as i32)
+        };
+        let _ = writeln!(
+            out,
+            "# HELP tsuki_inventory_balance tokens held, in whole units"
+        );
+        let _ = writeln!(out, "# TYPE tsuki_inventory_balance gauge");
+        for (holder, amounts) in latest.amounts.iter() {
+            for (token, amount) in amounts.iter() {
+                let holder = serde_json::to_value(holder).unwrap();
+                let _ = writeln!(
+                    out,
+                    "tsuki_inventory_balance{{holder={},token=\"{}\"}} {}",
+                    holder,
+                    token.get_symbol(),
+                    whole(token, *amount)
+                );
+            }
+        }
+        let _ = writeln!(out, "# HELP tsuki_inventory_block block of the balances");
+        let _ = writeln!(out, "# TYPE tsuki_inventory_block gauge");
+        let _ = writeln!(out, "tsuki_inventory_block {}", latest.block);
+        out
+    }
+}
+
+/// Reads the balance of every holder of `owners` in every token with one
+/// multicall. A `balanceOf` that fails keeps its amount of `previous`.
+pub async fn fetch_balances<M: Middleware>(
+    provider: Arc<M>,
+    owners: &EnumMap<Holder, Address>,
+    block: u64,
+    previous: Option<&Balances>,
+) -> Result<Balances, Error> {
+    let mut multicall = Multicall::new(provider.clone());
+    let mut reads = Vec::new();
+    for (holder, owner) in owners.iter() {
+        for token in (0..ERC20Token::LENGTH).map(ERC20Token::from_usize) {
+            let contract = Erc20Balance::new(token.get_address(), provider.clone());
+            multicall.add_call(contract.balance_of(*owner));
+            reads.push((holder, token));
+        }
+    }
+    let return_data = multicall.call_raw().await?;
+
+    let mut balances = Balances::new(block);
+    for ((holder, token), output) in reads.into_iter().zip(return_data) {
+        match output.and_then(|tokens| tokens.into_iter().next()?.into_uint()) {
+            Some(amount) => balances.set(holder, token, amount),
+            None => {
+                warn!("balanceOf {:?} in {} failed", holder, token.get_symbol());
+                balances.set(
+                    holder,
+                    token,
+                    previous
+                        .map(|previous| previous.get(holder, token))
+                        .unwrap_or_default(),
+                );
+            }
+        }
+    }
+    Ok(balances)
+}
+
+/// Rereads the balances every `every_blocks` blocks published on `bus`.
+pub async fn track_inventory<M: Middleware>(
+    inventory: Arc<Inventory>,
+    provider: Arc<M>,
+    bus: Arc<Bus>,
+    owners: EnumMap<Holder, Address>,
+    every_blocks: u64,
+) {
+    let mut blocks = bus.blocks.subscribe();
+    while let Some(block) = next(&mut blocks, "blocks").await {
+        let previous = inventory.latest();
+        if previous
+            .as_ref()
+            .is_some_and(|latest| block.number < latest.block + every_blocks)
+        {
+            continue;
+        }
+        match fetch_balances(provider.clone(), &owners, block.number, previous.as_ref()).await {
+            Ok(balances) => inventory.update(balances),
+            Err(e) => error!("Failed to read the inventory: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inventory() {
+        let inventory = Inventory::new();
+        assert_eq!(inventory.change(ERC20Token::USDC), None);
+        assert!(inventory.prometheus().is_empty());
+
+        let mut balances = Balances::new(100);
+        balances.set(Holder::Wallet, ERC20Token::USDC, 1_000_000.into());
+        inventory.update(balances.clone());
+        // profit lands in the executor, gas is paid in MATIC
+        balances.block = 120;
+        balances.set(Holder::Executor, ERC20Token::USDC, 250_000.into());
+        balances.set(Holder::Wallet, ERC20Token::WETH, 1.into());
+        inventory.update(balances.clone());
+        assert_eq!(inventory.change(ERC20Token::USDC), Some(250_000.into()));
+        assert_eq!(
+            inventory.sweepable(),
+            vec![(ERC20Token::USDC, 250_000.into())]
+        );
+
+        // moving it to the wallet isn't a change
+        balances.set(Holder::Executor, ERC20Token::USDC, U256::zero());
+        balances.set(Holder::Wallet, ERC20Token::USDC, 1_250_000.into());
+        inventory.update(balances);
+        assert_eq!(inventory.change(ERC20Token::USDC), Some(250_000.into()));
+        assert!(inventory
+            .sweepable()
+            .iter()
+            .all(|(token, _)| *token != ERC20Token::USDC));
+
+        let usdc = inventory
+            .tokens()
+            .into_iter()
+            .find(|token| token.token == "USDC")
+            .unwrap();
+        assert_eq!(usdc.wallet, 1_250_000.into());
+        assert!(inventory
+            .prometheus()
+            .contains("tsuki_inventory_balance{holder=\"wallet\",token=\"USDC\"} 1.25"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Trade size ladders: the sizes a route is tried at, per base token, e.g.
+//! `USDC=100,300,1000` quotes every USDC route at 100, 300 and 1000 USDC
+//! instead of its configured amounts. The rungs of a path are quoted
+//! together, every hop once for all of them, and each block the ladder is
+//! capped at the route's depth: rungs past it are dropped and the lowest
+//! of them is tried at the depth instead.
+
+use enum_map::EnumMap;
+use ethers::types::U256;
+use thiserror::Error;
+
+use crate::{constants::token::ERC20Token, routes::Route, utils::fixed_point::whole_units};
+
+#[derive(Debug, Error)]
+pub enum LadderError {
+    #[error("unknown token {0}")]
+    UnknownToken(String),
+
+    #[error("ladder {0} isn't TOKEN=size,size,...")]
+    Malformed(String),
+}
+
+/// Sizes in whole units of each base token, ascending.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeLadder {
+    rungs: EnumMap<ERC20Token, Vec<u64>>,
+}
+
+impl SizeLadder {
+    /// from `TOKEN=size,size,...` specs, a later spec of a token replacing
+    /// an earlier one
+    pub fn parse(specs: &[String]) -> Result<Self, LadderError> {
+        let mut ladder = Self::default();
+        for spec in specs {
+            let malformed = || LadderError::Malformed(spec.clone());
+            let (symbol, sizes) = spec.split_once('=').ok_or_else(malformed)?;
+            let token = ERC20Token::from_symbol(symbol.trim())
+                .ok_or_else(|| LadderError::UnknownToken(symbol.trim().to_string()))?;
+            let mut rungs = sizes
+                .split(',')
+                .map(|size| size.trim().parse::<u64>().ok().filter(|size| *size > 0))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(malformed)?;
+            rungs.sort_unstable();
+            rungs.dedup();
+            ladder.rungs[token] = rungs;
+        }
+        Ok(ladder)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rungs.values().all(Vec::is_empty)
+    }
+
+    pub fn rungs(&self, token: ERC20Token) -> &[u64] {
+        &self.rungs[token]
+    }
+
+    /// `routes` with the amounts of every path starting at a laddered token
+    /// replaced by its rungs, in rung then path order
+    pub fn apply(&self, routes: Vec<Route>) -> Vec<Route> {
+        let mut laddered: Vec<Vec<ERC20Token>> = Vec::new();
+        let mut applied: Vec<Route> = Vec::new();
+        for route in routes {
+            if self.rungs[route.token_path[0]].is_empty() {
+                if !applied.contains(&route) {
+                    applied.push(route);
+                }
+            } else if !laddered.contains(&route.token_path) {
+                laddered.push(route.token_path);
+            }
+        }
+        let rungs = laddered.iter().map(|path| self.rungs[path[0]].len()).max();
+        for rung in 0..rungs.unwrap_or_default() {
+            for path in &laddered {
+                let size = self.rungs[path[0]].get(rung);
+                if let Some(amount_in) =
+                    size.and_then(|size| whole_units(*size, path[0].get_decimals()))
+                {
+                    applied.push(Route {
+                        amount_in,
+                        token_path: path.clone(),
+                    });
+                }
+            }
+        }
+        applied
+    }
+}
+
+/// Routes of the same path, quoted in one pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteGroup {
+    pub token_path: Vec<ERC20Token>,
+    /// indices into the routes, in their order
+    pub routes: Vec<usize>,
+}
+
+/// `routes` grouped by path, in order of first appearance
+pub fn group_by_path(routes: &[Route]) -> Vec<RouteGroup> {
+    let mut groups: Vec<RouteGroup> = Vec::new();
+    for (i, route) in routes.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|group| group.token_path == route.token_path)
+        {
+            Some(group) => group.routes.push(i),
+            None => groups.push(RouteGroup {
+                token_path: route.token_path.clone(),
+                routes: vec![i],
+            }),
+        }
+    }
+    groups
+}
+
+/// What each of `amounts` is quoted at under `depth`: itself when within
+/// it, the depth for the smallest amount past it, `None` for the rest,
+/// they would only requote the depth.
+pub fn cap(amounts: &[U256], depth: Option<U256>) -> Vec<Option<U256>> {
+    let depth = match depth {
+        Some(depth) => depth,
+        None => return amounts.iter().copied().map(Some).collect(),
+    };
+    let capped_at = amounts
+        .iter()
+        .enumerate()
+        .filter(|(_, amount)| **amount > depth)
+        .min_by_key(|(_, amount)| **amount)
+        .map(|(i, _)| i);
+    amounts
+        .iter()
+        .enumerate()
+        .map(|(i, amount)| match *amount > depth {
+            false => Some(*amount),
+            true => (Some(i) == capped_at && !depth.is_zero()).then_some(depth),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::token::ERC20Token::*;
+
+    #[test]
+    fn test_size_ladder() {
+        let ladder = SizeLadder::parse(&["usdc=1000, 100,300".to_string()]).unwrap();
+        assert_eq!(ladder.rungs(USDC), &[100, 300, 1000]);
+        assert!(ladder.rungs(USDT).is_empty());
+        assert!(matches!(
+            SizeLadder::parse(&["PEPE=1".to_string()]),
+            Err(LadderError::UnknownToken(symbol)) if symbol == "PEPE"
+        ));
+        assert!(matches!(
+            SizeLadder::parse(&["USDC=1,,2".to_string()]),
+            Err(LadderError::Malformed(_))
+        ));
+
+        let route = |amount: u64, token_path: Vec<ERC20Token>| Route {
+            amount_in: whole_units(amount, token_path[0].get_decimals()).unwrap(),
+            token_path,
+        };
+        let routes = ladder.apply(vec![
+            route(10_000, vec![USDC, WETH, USDC]),
+            route(10_000, vec![USDT, WETH, USDT]),
+            route(5_000, vec![USDC, WETH, USDC]),
+        ]);
+        assert_eq!(
+            routes,
+            vec![
+                route(10_000, vec![USDT, WETH, USDT]),
+                route(100, vec![USDC, WETH, USDC]),
+                route(300, vec![USDC, WETH, USDC]),
+                route(1_000, vec![USDC, WETH, USDC]),
+            ]
+        );
+        let groups = group_by_path(&routes);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].routes, vec![1, 2, 3]);
+
+        let amounts: Vec<U256> = [100u64, 300, 1_000].map(U256::from).to_vec();
+        assert_eq!(
+            cap(&amounts, Some(200.into())),
+            vec![Some(100.into()), Some(200.into()), None]
+        );
+        assert_eq!(
+            cap(&amounts, None),
+            amounts.iter().copied().map(Some).collect::<Vec<_>>()
+        );
+        assert_eq!(cap(&amounts, Some(U256::zero())), vec![None, None, None]);
+    }
+}
@@ -0,0 +1,259 @@
+//! Detects the head and pending txn subscriptions falling behind the
+//! chain: heads skipping numbers, heads arriving long after they were
+//! produced, or nothing arriving at all. Whoever drives a subscription
+//! catches up (fetching the missed heads, reloading reserves,
+//! resubscribing) instead of quoting against state that stopped moving.
+//!
+//! When no head at all shows up for long, the chain stopped producing
+//! blocks or the node stopped importing them; either way nothing sent can
+//! land, so `ProductionGap` puts the strategies in degraded mode until a
+//! fresh head arrives.
+
+use std::time::Duration;
+
+use ethers::{providers::Middleware, types::BlockNumber};
+use futures_util::future::join_all;
+use serde::Serialize;
+
+use crate::bus::BlockEvent;
+
+/// subscriptions watched for stalls
+pub const HEADS: &str = "heads";
+pub const PENDING_TXS: &str = "pending_txs";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "lag", rename_all = "snake_case")]
+pub enum Lag {
+    /// heads `from` to `to` never came through the subscription
+    Gap { from: u64, to: u64 },
+    /// head `block` came through `age_secs` after it was produced
+    Stale { block: u64, age_secs: u64 },
+    /// nothing came through `subscription` for `secs`
+    Stalled { subscription: String, secs: u64 },
+    /// no fresh head for `secs` after `last_block`, submissions stop
+    Degraded { last_block: Option<u64>, secs: u64 },
+    /// head `block` arrived fresh after `secs` degraded
+    Resumed { block: u64, secs: u64 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LagConfig {
+    /// a head older than this on arrival is stale
+    pub max_head_age: Duration,
+    /// most missed heads fetched when catching up, the latest ones
+    pub max_catch_up: u64,
+}
+
+impl Default for LagConfig {
+    fn default() -> Self {
+        Self {
+            max_head_age: Duration::from_secs(15),
+            max_catch_up: 64,
+        }
+    }
+}
+
+/// Checks every head the subscription delivers against the one before.
+pub struct HeadLag {
+    config: LagConfig,
+    last: Option<u64>,
+}
+
+impl HeadLag {
+    pub fn new(config: LagConfig) -> Self {
+        Self { config, last: None }
+    }
+
+    /// lags head `number` with `timestamp` shows when it arrives at unix
+    /// time `now`
+    pub fn on_head(&mut self, number: u64, timestamp: u64, now: u64) -> Vec<Lag> {
+        let mut lags = Vec::new();
+        // lower or repeated numbers are reorgs, not lag
+        if let Some(last) = self.last.filter(|last| number > last + 1) {
+            lags.push(Lag::Gap {
+                from: last + 1,
+                to: number - 1,
+            });
+        }
+        let age_secs = now.saturating_sub(timestamp);
+        if age_secs > self.config.max_head_age.as_secs() {
+            lags.push(Lag::Stale {
+                block: number,
+                age_secs,
+            });
+        }
+        self.last = Some(number);
+        lags
+    }
+
+    /// Fetches the heads from `from` to `to`, at most the latest
+    /// `max_catch_up` of them, oldest first. Heads the node fails to
+    /// return are left out.
+    pub async fn fetch_missed<M: Middleware>(
+        &self,
+        provider: &M,
+        from: u64,
+        to: u64,
+    ) -> Vec<BlockEvent> {
+        let from = from.max((to + 1).saturating_sub(self.config.max_catch_up));
+        let blocks = join_all(
+            (from..=to).map(|number| provider.get_block(BlockNumber::Number(number.into()))),
+        )
+        .await;
+        blocks
+            .into_iter()
+            .filter_map(|block| block.ok().flatten())
+            .filter_map(|block| {
+                Some(BlockEvent {
+                    number: block.number?.as_u64(),
+                    timestamp: block.timestamp.as_u64(),
+                    base_fee: block.base_fee_per_gas,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Degraded mode, entered once no head arrived for `after` or the one
+/// that did was produced longer than `after` ago, left on the first head
+/// produced within it. Times are unix seconds.
+pub struct ProductionGap {
+    after: Duration,
+    last_block: Option<u64>,
+    /// of the last head, or the start before the first
+    last_arrival: u64,
+    /// since when, while degraded
+    degraded_since: Option<u64>,
+}
+
+impl ProductionGap {
+    /// the first head is due within `after` of `started`
+    pub fn new(after: Duration, started: u64) -> Self {
+        Self {
+            after,
+            last_block: None,
+            last_arrival: started,
+            degraded_since: None,
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded_since.is_some()
+    }
+
+    /// The lag head `number` produced at `timestamp` and arriving at `now`
+    /// shows: `Resumed` if fresh while degraded, `Degraded` if it's old
+    /// enough to start it.
+    pub fn on_head(&mut self, number: u64, timestamp: u64, now: u64) -> Option<Lag> {
+        self.last_block = Some(number);
+        self.last_arrival = now;
+        let fresh = now.saturating_sub(timestamp) <= self.after.as_secs();
+        match (self.degraded_since, fresh) {
+            (Some(since), true) => {
+                self.degraded_since = None;
+                Some(Lag::Resumed {
+                    block: number,
+                    secs: now.saturating_sub(since),
+                })
+            }
+            (None, false) => {
+                self.degraded_since = Some(now);
+                Some(Lag::Degraded {
+                    last_block: Some(number),
+                    secs: now.saturating_sub(timestamp),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// `Degraded` if no head arrived for `after` by `now` and that starts it
+    pub fn check(&mut self, now: u64) -> Option<Lag> {
+        let secs = now.saturating_sub(self.last_arrival);
+        if self.is_degraded() || secs <= self.after.as_secs() {
+            return None;
+        }
+        self.degraded_since = Some(now);
+        Some(Lag::Degraded {
+            last_block: self.last_block,
+            secs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    #[test]
+    fn test_head_lag() {
+        let mut lag = HeadLag::new(LagConfig::default());
+        assert!(lag.on_head(100, 1_000, 1_002).is_empty());
+        assert!(lag.on_head(101, 1_002, 1_004).is_empty());
+        assert_eq!(
+            lag.on_head(105, 1_010, 1_011),
+            vec![Lag::Gap { from: 102, to: 104 }]
+        );
+        // reorged back, not a gap
+        assert!(lag.on_head(104, 1_010, 1_012).is_empty());
+        assert_eq!(
+            lag.on_head(105, 1_012, 1_040),
+            vec![Lag::Stale {
+                block: 105,
+                age_secs: 28
+            }]
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Lag(Lag::Gap { from: 1, to: 2 })).unwrap(),
+            r#"{"type":"lag","lag":"gap","from":1,"to":2}"#
+        );
+    }
+
+    #[test]
+    fn test_production_gap() {
+        let mut gap = ProductionGap::new(Duration::from_secs(30), 1_000);
+        assert_eq!(gap.check(1_020), None);
+        assert_eq!(gap.on_head(100, 1_020, 1_021), None);
+        assert_eq!(gap.check(1_050), None);
+        assert_eq!(
+            gap.check(1_060),
+            Some(Lag::Degraded {
+                last_block: Some(100),
+                secs: 39
+            })
+        );
+        assert!(gap.is_degraded());
+        // reported once
+        assert_eq!(gap.check(1_090), None);
+        // the node catching up on old heads isn't production resuming
+        assert_eq!(gap.on_head(101, 1_022, 1_095), None);
+        assert!(gap.is_degraded());
+        assert_eq!(
+            gap.on_head(102, 1_096, 1_097),
+            Some(Lag::Resumed {
+                block: 102,
+                secs: 37
+            })
+        );
+        assert!(!gap.is_degraded());
+        // an old head arriving is enough to tell
+        assert_eq!(
+            gap.on_head(103, 1_050, 1_100),
+            Some(Lag::Degraded {
+                last_block: Some(103),
+                secs: 50
+            })
+        );
+
+        // nothing since startup
+        let mut gap = ProductionGap::new(Duration::from_secs(30), 1_000);
+        assert_eq!(
+            gap.check(1_031),
+            Some(Lag::Degraded {
+                last_block: None,
+                secs: 31
+            })
+        );
+    }
+}
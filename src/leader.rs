@@ -0,0 +1,376 @@
+//! Leader election between redundant instances sending from the same
+//! wallet. Every instance keeps its state warm (streams, reserves, quotes)
+//! but only the one holding the lock submits, so a standby can take over
+//! within one lease of the leader dying without both sending the same
+//! opportunity.
+//!
+//! The lock is a lease the leader renews with heartbeats, kept in a
+//! directory every instance can reach (`--lock /shared/dir`) or in redis
+//! (`--lock redis://...`, with the `redis` feature). An instance stops
+//! submitting a third of a lease before its lease runs out, so a leader
+//! that misses heartbeats has stopped by the time a standby takes over.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[cfg(feature = "redis")]
+    #[error(transparent)]
+    RedisError(#[from] ::redis::RedisError),
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("{0} is held by another instance mid-update")]
+    Contended(PathBuf),
+
+    #[error("unsupported lock url {0}")]
+    UnsupportedUrl(String),
+}
+
+/// Where leases are kept. `owner` identifies the instance, a lease held by
+/// the caller is renewed rather than refused so a restarted instance gets
+/// its lock back.
+#[async_trait]
+pub trait LockBackend: Send + Sync {
+    /// takes `key` for `ttl` if it's free, expired or already `owner`'s
+    async fn acquire(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError>;
+
+    /// extends `owner`'s lease of `key` by `ttl`, false if it lost it
+    async fn renew(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError>;
+
+    /// gives `key` up if `owner` holds it
+    async fn release(&self, key: &str, owner: &str) -> Result<(), LockError>;
+}
+
+/// Opens the backend at `url`: `redis://...` with the `redis` feature,
+/// anything else is a directory.
+pub async fn open(url: &str) -> Result<Box<dyn LockBackend>, LockError> {
+    if url.starts_with("redis://") || url.starts_with("rediss://") {
+        #[cfg(feature = "redis")]
+        return Ok(Box::new(RedisLock::connect(url).await?));
+        #[cfg(not(feature = "redis"))]
+        return Err(LockError::UnsupportedUrl(url.to_string()));
+    }
+    let dir = url.strip_prefix("file://").unwrap_or(url);
+    Ok(Box::new(FileLock::new(dir)?))
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    owner: String,
+    /// unix ms
+    expires: u64,
+}
+
+/// Leases as json files `<key>.lease` in a directory shared by the
+/// instances, on one host or a network filesystem with clocks in sync.
+/// Updates happen under a `<key>.guard` file created exclusively, one left
+/// behind by a crash is cleared once it's older than a lease.
+pub struct FileLock {
+    dir: PathBuf,
+}
+
+/// attempts at the guard, 10ms apart
+const GUARD_ATTEMPTS: u32 = 50;
+
+impl FileLock {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, LockError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn lease_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.lease", key))
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Lease>, LockError> {
+        match fs::read(self.lease_path(key)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes).ok()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, key: &str, owner: &str, ttl: Duration) -> Result<(), LockError> {
+        let lease = Lease {
+            owner: owner.to_string(),
+            expires: unix_ms() + ttl.as_millis() as u64,
+        };
+        // readers never see a half written lease
+        let tmp = self
+            .dir
+            .join(format!("{}.lease.{}", key, std::process::id()));
+        fs::write(&tmp, serde_json::to_vec(&lease)?)?;
+        fs::rename(&tmp, self.lease_path(key))?;
+        Ok(())
+    }
+
+    /// runs `update` holding the guard of `key`
+    async fn guarded<T>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        update: impl FnOnce() -> Result<T, LockError>,
+    ) -> Result<T, LockError> {
+        let guard = self.dir.join(format!("{}.guard", key));
+        for _ in 0..GUARD_ATTEMPTS {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&guard)
+            {
+                Ok(_) => {
+                    let result = update();
+                    fs::remove_file(&guard)?;
+                    return result;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let abandoned = fs::metadata(&guard)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| modified.elapsed().unwrap_or_default() > ttl)
+                        .unwrap_or(false);
+                    if abandoned {
+                        warn!("Clearing abandoned lock guard {}", guard.display());
+                        let _ = fs::remove_file(&guard);
+                        continue;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(LockError::Contended(guard))
+    }
+}
+
+#[async_trait]
+impl LockBackend for FileLock {
+    async fn acquire(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError> {
+        self.guarded(key, ttl, || {
+            let free = match self.read(key)? {
+                Some(lease) => lease.owner == owner || lease.expires <= unix_ms(),
+                None => true,
+            };
+            if free {
+                self.write(key, owner, ttl)?;
+            }
+            Ok(free)
+        })
+        .await
+    }
+
+    async fn renew(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError> {
+        self.guarded(key, ttl, || match self.read(key)? {
+            Some(lease) if lease.owner == owner => {
+                self.write(key, owner, ttl)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+        .await
+    }
+
+    async fn release(&self, key: &str, owner: &str) -> Result<(), LockError> {
+        self.guarded(key, Duration::from_secs(60), || {
+            if matches!(self.read(key)?, Some(lease) if lease.owner == owner) {
+                fs::remove_file(self.lease_path(key))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Leases as redis keys holding the owner, expiring with the lease.
+#[cfg(feature = "redis")]
+pub struct RedisLock {
+    conn: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl RedisLock {
+    pub async fn connect(url: &str) -> Result<Self, LockError> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            conn: redis::aio::ConnectionManager::new(client).await?,
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl LockBackend for RedisLock {
+    async fn acquire(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError> {
+        let set: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(owner)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        match set {
+            Some(_) => Ok(true),
+            None => self.renew(key, owner, ttl).await,
+        }
+    }
+
+    async fn renew(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, LockError> {
+        let renewed: i64 = redis::Script::new(
+            "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+                return redis.call('PEXPIRE', KEYS[1], ARGV[2]) \
+            else return 0 end",
+        )
+        .key(key)
+        .arg(owner)
+        .arg(ttl.as_millis() as u64)
+        .invoke_async(&mut self.conn.clone())
+        .await?;
+        Ok(renewed == 1)
+    }
+
+    async fn release(&self, key: &str, owner: &str) -> Result<(), LockError> {
+        redis::Script::new(
+            "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+                return redis.call('DEL', KEYS[1]) \
+            else return 0 end",
+        )
+        .key(key)
+        .arg(owner)
+        .invoke_async::<_, i64>(&mut self.conn.clone())
+        .await?;
+        Ok(())
+    }
+}
+
+/// The lock of one wallet as seen by one instance. `run` heartbeats it,
+/// `is_leader` says whether to submit.
+pub struct LeaderLock {
+    backend: Box<dyn LockBackend>,
+    key: String,
+    owner: String,
+    ttl: Duration,
+    /// local end of the lease, measured from before the request that got it
+    lease_until: Mutex<Option<Instant>>,
+}
+
+impl LeaderLock {
+    pub fn new(
+        backend: Box<dyn LockBackend>,
+        key: impl Into<String>,
+        owner: impl Into<String>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            backend,
+            key: key.into(),
+            owner: owner.into(),
+            ttl,
+            lease_until: Mutex::new(None),
+        }
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// holds the lock with a third of the lease to spare
+    pub fn is_leader(&self) -> bool {
+        match *self.lease_until.lock().unwrap() {
+            Some(until) => Instant::now() + self.ttl / 3 < until,
+            None => false,
+        }
+    }
+
+    /// Renews the lease if held, tries to take it otherwise. Whether this
+    /// instance leads afterwards; on errors the lease held runs out.
+    pub async fn heartbeat(&self) -> bool {
+        let start = Instant::now();
+        let held = self.lease_until.lock().unwrap().is_some();
+        let result = match held {
+            true => self.backend.renew(&self.key, &self.owner, self.ttl).await,
+            false => self.backend.acquire(&self.key, &self.owner, self.ttl).await,
+        };
+        match result {
+            Ok(true) => *self.lease_until.lock().unwrap() = Some(start + self.ttl),
+            Ok(false) => *self.lease_until.lock().unwrap() = None,
+            Err(e) => error!("Heartbeat of lock {} failed: {}", self.key, e),
+        }
+        self.is_leader()
+    }
+
+    /// heartbeats every third of a lease, logging leadership changes
+    pub async fn run(self: Arc<Self>) {
+        let mut leading = self.is_leader();
+        loop {
+            let now_leading = self.heartbeat().await;
+            if now_leading != leading {
+                match now_leading {
+                    true => info!("{} took lock {}, submitting", self.owner, self.key),
+                    false => warn!("{} lost lock {}, standing by", self.owner, self.key),
+                }
+                leading = now_leading;
+            }
+            tokio::time::sleep(self.ttl / 3).await;
+        }
+    }
+
+    /// gives the lock up for a standby to take right away
+    pub async fn release(&self) -> Result<(), LockError> {
+        *self.lease_until.lock().unwrap() = None;
+        self.backend.release(&self.key, &self.owner).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_lock() {
+        let dir = std::env::temp_dir().join(format!("tsuki-lock-{}", std::process::id()));
+        let ttl = Duration::from_millis(300);
+        let a = LeaderLock::new(Box::new(FileLock::new(&dir).unwrap()), "wallet", "a", ttl);
+        let b = LeaderLock::new(Box::new(FileLock::new(&dir).unwrap()), "wallet", "b", ttl);
+
+        assert!(a.heartbeat().await);
+        assert!(!b.heartbeat().await);
+        assert!(a.heartbeat().await);
+
+        // a stops heartbeating, b takes over once its lease ran out
+        tokio::time::sleep(ttl * 5 / 6).await;
+        assert!(!a.is_leader());
+        assert!(!b.heartbeat().await);
+        tokio::time::sleep(ttl).await;
+        assert!(b.heartbeat().await);
+        assert!(!a.heartbeat().await);
+
+        b.release().await.unwrap();
+        assert!(!b.is_leader());
+        assert!(a.heartbeat().await);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
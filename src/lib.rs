@@ -1,8 +1,49 @@
+pub mod activity;
+pub mod address_book;
+pub mod address_tags;
+pub mod api;
+pub mod arb;
+pub mod arb_params;
 pub mod balancer;
+pub mod bor;
+pub mod bridge;
+pub mod bus;
 pub mod constants;
+pub mod deploy;
+pub mod error;
 pub mod event_monitor;
+pub mod events;
+pub mod export;
+pub mod gas_budget;
+pub mod header_tracker;
+pub mod heatmap;
+pub mod in_flight;
+pub mod inventory;
+pub mod ladder;
+pub mod lag;
+pub mod leader;
+pub mod liquidator;
+pub mod migration;
+pub mod opportunity_filter;
+pub mod pnl;
+pub mod pool_check;
+pub mod preflight;
+pub mod price_index;
+pub mod resources;
+pub mod router_probe;
+pub mod routes;
+pub mod schedule;
+pub mod secrets;
+pub mod shadow;
+pub mod snapshot;
+pub mod spreads;
+pub mod storage;
+pub mod supervisor;
+pub mod telemetry;
+pub mod trade_report;
 pub mod tx_pool;
 pub mod uniswapV2;
 pub mod uniswapV3;
 pub mod utils;
+pub mod warmup;
 pub mod world;
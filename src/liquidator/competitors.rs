@@ -0,0 +1,191 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, Filter, Log, U64},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::AAVE_V3_POOL;
+use crate::storage::{Storage, StorageError, Table, COMPETITORS};
+
+/// `LiquidationCall(collateralAsset, debtAsset, user, debtToCover, liquidatedCollateralAmount, liquidator, receiveAToken)`
+pub const LIQUIDATION_CALL_EVENT: &str =
+    "LiquidationCall(address,address,address,uint256,uint256,address,bool)";
+
+/// key of the set in the `competitors` table
+const AAVE_V3: &str = "aave_v3";
+
+// node providers cap the block range of a single eth_getLogs
+pub(crate) const MAX_LOG_RANGE: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct CompetitorStats {
+    pub liquidations: u64,
+    pub last_seen_block: u64,
+}
+
+/// Liquidator addresses ranked by how many liquidations they won recently,
+/// kept in the `competitors` table so the ranking survives restarts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct CompetitorSet {
+    liquidators: HashMap<Address, CompetitorStats>,
+    /// last block included in a scan
+    pub last_scanned_block: u64,
+}
+
+impl CompetitorSet {
+    pub fn with_seed(addresses: &[Address]) -> Self {
+        let mut set = Self::default();
+        for address in addresses {
+            set.liquidators.entry(*address).or_default();
+        }
+        set
+    }
+
+    /// the set kept in `storage`, empty if nothing was scanned yet
+    pub async fn load(storage: Arc<dyn Storage>) -> Result<Self, StorageError> {
+        let table: Table<Self> = Table::new(storage, COMPETITORS);
+        Ok(table.get(AAVE_V3).await?.unwrap_or_default())
+    }
+
+    pub async fn save(&self, storage: Arc<dyn Storage>) -> Result<(), StorageError> {
+        Table::new(storage, COMPETITORS).put(AAVE_V3, self).await
+    }
+
+    pub fn len(&self) -> usize {
+        self.liquidators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.liquidators.is_empty()
+    }
+
+    pub fn get(&self, liquidator: &Address) -> Option<&CompetitorStats> {
+        self.liquidators.get(liquidator)
+    }
+
+    pub fn record(&mut self, liquidator: Address, block_number: u64) {
+        let stats = self.liquidators.entry(liquidator).or_default();
+        stats.liquidations += 1;
+        stats.last_seen_block = stats.last_seen_block.max(block_number);
+    }
+
+    /// forget liquidators not seen in the last `max_age` blocks
+    pub fn prune(&mut self, current_block: u64, max_age: u64) {
+        self.liquidators
+            .retain(|_, stats| stats.last_seen_block + max_age >= current_block);
+    }
+
+    /// most active first, ties broken by recency
+    pub fn ranked(&self) -> Vec<(Address, CompetitorStats)> {
+        let mut ranked: Vec<(Address, CompetitorStats)> =
+            self.liquidators.iter().map(|(a, s)| (*a, *s)).collect();
+        ranked.sort_by(|(_, a), (_, b)| {
+            b.liquidations
+                .cmp(&a.liquidations)
+                .then(b.last_seen_block.cmp(&a.last_seen_block))
+        });
+        ranked
+    }
+
+    pub fn top(&self, n: usize) -> Vec<Address> {
+        self.ranked().into_iter().take(n).map(|(a, _)| a).collect()
+    }
+
+    /// Scans AAVE `LiquidationCall` events in `[from_block, to_block]` and
+    /// records the liquidator of every successful liquidation.
+    pub async fn scan<M: Middleware>(
+        &mut self,
+        provider: &M,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<usize, M::Error> {
+        let mut recorded = 0;
+        let mut start = from_block;
+        while start <= to_block {
+            let end = u64::min(start + MAX_LOG_RANGE - 1, to_block);
+            let filter = Filter::new()
+                .address(*AAVE_V3_POOL)
+                .event(LIQUIDATION_CALL_EVENT)
+                .from_block(BlockNumber::Number(U64::from(start)))
+                .to_block(BlockNumber::Number(U64::from(end)));
+
+            for log in provider.get_logs(&filter).await? {
+                match parse_liquidator(&log) {
+                    Some((liquidator, block_number)) => {
+                        self.record(liquidator, block_number);
+                        recorded += 1;
+                    }
+                    None => warn!("malformed LiquidationCall log: {:?}", log.transaction_hash),
+                }
+            }
+            start = end + 1;
+        }
+        self.last_scanned_block = self.last_scanned_block.max(to_block);
+        Ok(recorded)
+    }
+}
+
+/// liquidator is the third non-indexed word of the event data
+pub(super) fn parse_liquidator(log: &Log) -> Option<(Address, u64)> {
+    let word = log.data.get(64..96)?;
+    let block_number = log.block_number?.as_u64();
+    Some((Address::from_slice(&word[12..]), block_number))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, Bytes, Log, U64};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_ranking() {
+        let a = Address::random();
+        let b = Address::random();
+        let c = Address::random();
+        let mut set = CompetitorSet::with_seed(&[c]);
+        set.record(a, 10);
+        set.record(b, 12);
+        set.record(b, 15);
+        set.record(a, 20);
+        set.record(a, 21);
+
+        assert_eq!(set.top(2), vec![a, b]);
+        assert_eq!(set.top(5), vec![a, b, c]);
+
+        set.prune(1000, 100);
+        assert!(set.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        assert!(CompetitorSet::load(storage.clone())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut set = CompetitorSet::with_seed(&[Address::random()]);
+        set.record(Address::random(), 10);
+        set.last_scanned_block = 12;
+        set.save(storage.clone()).await.unwrap();
+        assert_eq!(CompetitorSet::load(storage).await.unwrap(), set);
+    }
+
+    #[test]
+    fn test_parse_liquidator() {
+        let liquidator = Address::random();
+        let mut data = vec![0u8; 128];
+        data[76..96].copy_from_slice(liquidator.as_bytes());
+        let log = Log {
+            data: Bytes::from(data),
+            block_number: Some(U64::from(7)),
+            ..Default::default()
+        };
+        assert_eq!(parse_liquidator(&log), Some((liquidator, 7)));
+    }
+}
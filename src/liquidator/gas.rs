@@ -0,0 +1,136 @@
+use std::{collections::HashMap, time::Duration};
+
+use ethers::types::{Address, Transaction, U256, U64};
+
+use crate::utils::fee_history::FeeHistory;
+
+pub const POLYGON_BLOCK_TIME: Duration = Duration::from_secs(2);
+
+/// Gas bidding for liquidation races. Bids are legacy gas prices (base fee
+/// included) and never exceed the share of the expected profit we're willing
+/// to burn on gas.
+#[derive(Clone, Copy, Debug)]
+pub struct GasAuction {
+    /// share of the expected profit spent on gas at most, in bps
+    pub max_profit_share_bps: u64,
+    /// how far to bid above the best rival, in bps
+    pub outbid_bps: u64,
+    pub min_gas_price: U256,
+    pub block_time: Duration,
+}
+
+impl Default for GasAuction {
+    fn default() -> Self {
+        Self {
+            max_profit_share_bps: 5_000,
+            // nodes need a 10% bump to replace a txn, stay above that
+            outbid_bps: 1_250,
+            // polygon validators ignore anything with less than a 30 gwei tip
+            min_gas_price: U256::from(30_000_000_000u64),
+            block_time: POLYGON_BLOCK_TIME,
+        }
+    }
+}
+
+impl GasAuction {
+    /// highest gas price at which the liquidation is still worth sending
+    pub fn max_gas_price(&self, expected_profit: U256, gas_used: U256) -> U256 {
+        if gas_used.is_zero() {
+            return U256::zero();
+        }
+        expected_profit * self.max_profit_share_bps / 10_000 / gas_used
+    }
+
+    /// Outbids the best rival and escalates towards the profitability cap as
+    /// the block closes. `expected_profit` is in wei. Returns `None` if even
+    /// the opening bid would exceed the cap.
+    pub fn bid(
+        &self,
+        expected_profit: U256,
+        gas_used: U256,
+        rival_bid: Option<U256>,
+        since_last_block: Duration,
+    ) -> Option<U256> {
+        let cap = self.max_gas_price(expected_profit, gas_used);
+        let rival = rival_bid.unwrap_or_default();
+        let opening = U256::max(self.min_gas_price, rival + rival * self.outbid_bps / 10_000);
+        if opening > cap {
+            return None;
+        }
+
+        let progress_bps = u64::min(
+            (since_last_block.as_millis() * 10_000 / self.block_time.as_millis().max(1)) as u64,
+            10_000,
+        );
+        Some(opening + (cap - opening) * progress_bps / 10_000)
+    }
+
+    /// raises `min_gas_price` to what recent blocks paid at `percentile`, so
+    /// uncontested bids still land in the next block
+    pub fn with_fee_history(mut self, history: &FeeHistory, percentile: f64) -> Self {
+        if let Some(tip) = history.suggested_priority_fee(percentile) {
+            self.min_gas_price = U256::max(self.min_gas_price, history.next_base_fee + tip);
+        }
+        self
+    }
+}
+
+/// gas price `txn` pays per unit of gas at the given base fee
+pub fn effective_gas_price(txn: &Transaction, base_fee: U256) -> U256 {
+    match (txn.transaction_type, txn.max_fee_per_gas) {
+        (Some(id), Some(max_fee)) if id == U64::from(2) => {
+            let tip = txn.max_priority_fee_per_gas.unwrap_or_default();
+            U256::min(max_fee, base_fee + tip)
+        }
+        _ => txn.gas_price.unwrap_or_default(),
+    }
+}
+
+/// Best gas price seen from competing liquidators per borrower, reset every
+/// block since the race is over once a liquidation is mined.
+#[derive(Clone, Debug, Default)]
+pub struct RivalBids {
+    block_number: u64,
+    bids: HashMap<Address, U256>,
+}
+
+impl RivalBids {
+    /// records a rival bid for `user` and returns the best one this block
+    pub fn record(&mut self, block_number: u64, user: Address, gas_price: U256) -> U256 {
+        if block_number > self.block_number {
+            self.block_number = block_number;
+            self.bids.clear();
+        }
+        let best = self.bids.entry(user).or_default();
+        *best = U256::max(*best, gas_price);
+        *best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bid_escalation() {
+        let auction = GasAuction::default();
+        let gwei = U256::exp10(9);
+        // 1 matic profit over 500k gas, cap is 1000 gwei
+        let profit = U256::exp10(18);
+        let gas_used = U256::from(500_000);
+        let rival = Some(gwei * 100);
+
+        let early = auction.bid(profit, gas_used, rival, Duration::ZERO);
+        let mid = auction.bid(profit, gas_used, rival, Duration::from_secs(1));
+        let late = auction.bid(profit, gas_used, rival, Duration::from_secs(5));
+        assert_eq!(early, Some(gwei * 1125 / 10));
+        assert_eq!(mid, Some((gwei * 1125 / 10 + gwei * 1000) / 2));
+        assert_eq!(late, Some(gwei * 1000));
+
+        // rival already bids more than we can afford
+        assert_eq!(
+            auction.bid(profit, gas_used, Some(gwei * 950), Duration::ZERO),
+            None
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::{Arc, Mutex};
+
+use ethers::{
+    abi::Address,
+    prelude::abigen,
+    providers::{Middleware, Provider, PubsubClient, SubscriptionStream},
+    types::{BlockNumber, Transaction, H256, U256},
+    utils,
+};
+use futures_channel::mpsc;
+use futures_util::{Stream, StreamExt};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::utils::{
+    calldata::{DecoderRegistry, Intent},
+    sim_cache::{SimCache, SimKey},
+    tracer::{BlockTraceResult, DebugTraceExt, TraceConfig},
+};
+
+pub mod competitors;
+pub mod gas;
+pub mod postmortem;
+pub mod routes;
+pub mod simulation;
+
+abigen!(AavePool, "abis/AavePool.json");
+abigen!(Liquidations, "abis/Liquidations.json");
+
+/// traces of pending transactions kept, all against the current head
+pub const TRACE_CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    pub static ref AAVE_V3_POOL: Address = "0x794a61358D6845594F94dc1DB02A252b5b4814aD"
+        .parse::<Address>()
+        .unwrap();
+
+    /// liquidator contracts observed competing on AAVE polygon
+    pub static ref KNOWN_LIQUIDATORS: Vec<Address> = [
+        "0x54999CBEA7ec48A373aCE8A5dDc1D6e6fF7F8202",
+        "0x28d62d755D561e7468734Cd63c62ec960Cd4c1A7",
+        "0x87C76A8A5d8D24250752F93BDC232B18997dDa15",
+        "0x0000000eb7D8244007Da6CD63A512eC69494b231",
+        "0xB8f013e063F59719D05b3F1F9076b4DC7e56FAe7",
+        "0xEb7e2AeB58b55bc419BDAD48A8c39e2C6d7CEB84",
+        "0x14770cD80fa8055c12BC092255496CA8D0fFCF5e",
+        "0x88E2840bA66c7B618f37AEE2DD9c448997D41690",
+        "0x774b407f518C91ae79250625291AA14440D5d8fB",
+        "0x98648D396a35D1FF9ED354432B2C98C37931F69C",
+        "0x3BB7a0f2fe88ABA35408C64F588345481490Fe93",
+    ]
+    .iter()
+    .map(|x| x.parse::<Address>().unwrap())
+    .collect();
+
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransactionOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_address: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_address: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashes_only: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugTraceCallOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+impl DebugTraceCallOptions {
+    pub fn generate(txn: &Transaction) -> Self {
+        DebugTraceCallOptions {
+            from: Some(format!("{:?}", txn.from)),
+            to: format!("{:?}", txn.to.unwrap_or_default()),
+            gas_price: None,
+            value: Some(format!("{:#x}", txn.value)),
+            data: Some(txn.input.to_string()),
+        }
+    }
+}
+
+/// where a liquidation opportunity was detected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpportunitySource {
+    /// pending transaction of a competing liquidator, copied from the mempool
+    PendingTransaction(Transaction),
+}
+
+/// arguments of an AAVE `liquidationCall` plus the bonus we expect for executing it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiquidationOpportunity {
+    pub user: Address,
+    pub collateral: Address,
+    pub debt: Address,
+    pub debt_to_cover: U256,
+    /// liquidation bonus denominated in the debt asset (assumes oracle parity)
+    pub expected_bonus: U256,
+    pub source: OpportunitySource,
+}
+
+/// Finds liquidation opportunities without executing them, consumers decide
+/// whether to route them to an executor or just log them.
+pub struct Liquidator<M, P> {
+    provider: Arc<M>,
+    stream_provider: Provider<P>,
+    aave_pool: AavePool<M>,
+    watched_liquidators: Vec<Address>,
+    /// hash of the latest block, what pending transactions are traced on
+    head: Mutex<Option<H256>>,
+    traces: SimCache<BlockTraceResult>,
+    /// what finds the `liquidationCall` in a trace
+    decoders: DecoderRegistry,
+}
+
+impl<M: Middleware + 'static, P: PubsubClient + 'static> Liquidator<M, P> {
+    pub fn new(
+        provider: Arc<M>,
+        stream_provider: Provider<P>,
+        watched_liquidators: Vec<Address>,
+    ) -> Self {
+        Self {
+            provider: provider.clone(),
+            stream_provider,
+            aave_pool: AavePool::new(*AAVE_V3_POOL, provider),
+            watched_liquidators,
+            head: Mutex::new(None),
+            traces: SimCache::new(TRACE_CACHE_CAPACITY),
+            decoders: DecoderRegistry::polygon(),
+        }
+    }
+
+    /// traces are cached against `block_hash` from now on, older ones dropped
+    pub fn set_head(&self, block_hash: H256) {
+        *self.head.lock().unwrap() = Some(block_hash);
+        self.traces.retain_state(block_hash);
+    }
+
+    /// Stream of opportunities copied from pending transactions sent to the
+    /// watched liquidator contracts (requires `alchemy_pendingTransactions`).
+    pub async fn opportunities(self: Arc<Self>) -> impl Stream<Item = LiquidationOpportunity> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let method = utils::serialize(&"alchemy_pendingTransactions");
+        let method_params = utils::serialize(&PendingTransactionOptions {
+            to_address: Some(
+                self.watched_liquidators
+                    .iter()
+                    .map(|x| format!("{:?}", x))
+                    .collect(),
+            ),
+            from_address: None,
+            hashes_only: None,
+        });
+
+        {
+            let liquidator = self.clone();
+            tokio::spawn(async move {
+                let mut blocks = match liquidator.stream_provider.subscribe_blocks().await {
+                    Ok(blocks) => blocks,
+                    Err(e) => {
+                        warn!("Failed to subscribe to blocks: {}", e);
+                        return;
+                    }
+                };
+                while let Some(block) = blocks.next().await {
+                    if let Some(hash) = block.hash {
+                        liquidator.set_head(hash);
+                    }
+                }
+            });
+        }
+
+        tokio::spawn(async move {
+            let mut pending_txn_stream: SubscriptionStream<P, Box<RawValue>> = match self
+                .stream_provider
+                .subscribe([method, method_params])
+                .await
+            {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to subscribe to pending liquidations: {}", e);
+                    return;
+                }
+            };
+
+            while let Some(item) = pending_txn_stream.next().await {
+                let txn = match serde_json::from_str::<Transaction>(item.get()) {
+                    Ok(txn) => txn,
+                    Err(_) => continue,
+                };
+                debug!("Detected liquidation transaction with hash: {:?}", txn.hash);
+                if let Some(opportunity) = self.decode_opportunity(txn).await {
+                    if sender.unbounded_send(opportunity).is_err() {
+                        // receiver dropped, nobody is listening anymore
+                        break;
+                    }
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Traces the transaction and extracts the nested `liquidationCall`, if
+    /// any. Traces are reused while the head hasn't moved.
+    pub async fn decode_opportunity(&self, txn: Transaction) -> Option<LiquidationOpportunity> {
+        let options = DebugTraceCallOptions::generate(&txn);
+        let config = TraceConfig::call_tracer(false);
+        let trace_call = || {
+            self.provider
+                .call_trace_call(&options, BlockNumber::Pending, &config)
+        };
+        let head = *self.head.lock().unwrap();
+        let trace = match head {
+            Some(state) => {
+                let key = SimKey {
+                    tx: txn.hash,
+                    state,
+                };
+                self.traces.get_or_try_simulate(key, trace_call).await
+            }
+            None => trace_call().await,
+        };
+        let trace = match trace {
+            Ok(trace) => trace,
+            Err(e) => {
+                warn!("debug_traceCall failed for {:?}: {}", txn.hash, e);
+                return None;
+            }
+        };
+
+        let (collateral, debt, user, debt_to_cover) =
+            match find_liquidation(&self.decoders, &trace)? {
+                Intent::Liquidation {
+                    collateral,
+                    debt,
+                    user,
+                    debt_to_cover,
+                    ..
+                } => (collateral, debt, user, debt_to_cover),
+                _ => return None,
+            };
+        let expected_bonus = self.expected_bonus(collateral, debt_to_cover).await;
+
+        Some(LiquidationOpportunity {
+            user,
+            collateral,
+            debt,
+            debt_to_cover,
+            expected_bonus,
+            source: OpportunitySource::PendingTransaction(txn),
+        })
+    }
+
+    /// Bonus AAVE pays for repaying `debt_to_cover`, read from the collateral's reserve config.
+    pub async fn expected_bonus(&self, collateral: Address, debt_to_cover: U256) -> U256 {
+        match self.aave_pool.get_configuration(collateral).call().await {
+            Ok(config) => {
+                let bonus_bps = liquidation_bonus_bps(config.data);
+                debt_to_cover * bonus_bps.saturating_sub(U256::from(10000)) / U256::from(10000)
+            }
+            Err(e) => {
+                warn!("could not read reserve config for {:?}: {}", collateral, e);
+                U256::zero()
+            }
+        }
+    }
+}
+
+/// bits 32-47 of the reserve configuration bitmap, eg. 10500 for a 5% bonus
+pub fn liquidation_bonus_bps(config: U256) -> U256 {
+    (config >> 32) & U256::from(0xffff)
+}
+
+/// depth first search for the first call frame `decoders` decode into a
+/// liquidation
+fn find_liquidation(decoders: &DecoderRegistry, frame: &BlockTraceResult) -> Option<Intent> {
+    let value = frame.value.unwrap_or_default();
+    if let Some(intent @ Intent::Liquidation { .. }) =
+        decoders.decode(frame.to, &frame.input, value)
+    {
+        return Some(intent);
+    }
+    frame
+        .calls
+        .as_ref()?
+        .iter()
+        .find_map(|call| find_liquidation(decoders, call))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{prelude::BaseContract, types::Bytes};
+
+    use super::*;
+
+    #[test]
+    fn test_liquidation_bonus_bps() {
+        // ltv 8000, threshold 8250, bonus 10500
+        let config = U256::from(8000) | (U256::from(8250) << 16) | (U256::from(10500) << 32);
+        assert_eq!(liquidation_bonus_bps(config), U256::from(10500));
+    }
+
+    #[test]
+    fn test_find_nested_liquidation() {
+        let pool = BaseContract::from(AAVEPOOL_ABI.clone());
+        let user = Address::random();
+        let input = pool
+            .encode(
+                "liquidationCall",
+                (
+                    Address::zero(),
+                    Address::zero(),
+                    user,
+                    U256::from(42),
+                    false,
+                ),
+            )
+            .unwrap();
+
+        let decoders = DecoderRegistry::polygon();
+        let trace = BlockTraceResult {
+            input: Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]),
+            calls: Some(vec![
+                // same selector, not the pool
+                BlockTraceResult {
+                    to: Address::random(),
+                    input: input.clone(),
+                    ..Default::default()
+                },
+                BlockTraceResult {
+                    to: *AAVE_V3_POOL,
+                    input,
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_liquidation(&decoders, &trace),
+            Some(Intent::Liquidation {
+                collateral: Address::zero(),
+                debt: Address::zero(),
+                user,
+                debt_to_cover: U256::from(42),
+                receive_a_token: false,
+            })
+        );
+        assert_eq!(
+            find_liquidation(&decoders, &BlockTraceResult::default()),
+            None
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ethers::types::{Address, Log, H256, U256};
+use serde::{Deserialize, Serialize};
+
+use super::{competitors::parse_liquidator, LiquidationOpportunity, OpportunitySource};
+
+/// One liquidation race we took part in, from the first rival txn we saw
+/// until a `LiquidationCall` for the same borrower and debt is mined.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Race {
+    pub user: Address,
+    pub collateral: Address,
+    pub debt: Address,
+    /// first rival txn seen in the mempool
+    pub rival_tx: H256,
+    pub rival_gas_price: U256,
+    /// unix ms the rival txn reached us
+    pub first_seen_ms: u64,
+    pub simulated_profit: Option<U256>,
+    pub our_tx: Option<H256>,
+    pub our_gas_price: Option<U256>,
+    /// unix ms we broadcast our txn
+    pub submitted_ms: Option<u64>,
+    /// why we didn't submit, if we didn't
+    pub skipped: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaceOutcome {
+    Won,
+    /// we never submitted, see `Race::skipped`
+    NotSubmitted,
+    /// the winner paid a higher gas price
+    Outbid,
+    /// we bid at least as much but were ordered after the winner, usually
+    /// because our txn reached the validator too late
+    Ordering,
+}
+
+impl fmt::Display for RaceOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaceOutcome::Won => write!(f, "won"),
+            RaceOutcome::NotSubmitted => write!(f, "not submitted"),
+            RaceOutcome::Outbid => write!(f, "outbid"),
+            RaceOutcome::Ordering => write!(f, "ordered after winner"),
+        }
+    }
+}
+
+/// what happened in a settled race, appended to the `races` log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RaceReport {
+    pub race: Race,
+    pub outcome: RaceOutcome,
+    pub winner: Address,
+    pub winning_tx: H256,
+    pub winning_gas_price: U256,
+    pub block_number: u64,
+    /// ms between the rival txn reaching us and us broadcasting
+    pub reaction_ms: Option<u64>,
+    /// ms between the rival txn reaching us and the winning block's timestamp
+    pub first_seen_to_block_ms: i64,
+}
+
+impl fmt::Display for RaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "race for {:?} {}: winner {:?} in block {} at gas price {} (ours {:?}), reaction {:?}ms, profit {:?}",
+            self.race.user,
+            self.outcome,
+            self.winner,
+            self.block_number,
+            self.winning_gas_price,
+            self.race.our_gas_price,
+            self.reaction_ms,
+            self.race.simulated_profit,
+        )?;
+        if let Some(skipped) = &self.race.skipped {
+            write!(f, ", skipped: {}", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tracks open races and settles them against mined `LiquidationCall` events.
+pub struct RaceTracker {
+    /// liquidator address our txns show up as in `LiquidationCall`
+    our_liquidator: Address,
+    races: HashMap<(Address, Address), Race>,
+}
+
+impl RaceTracker {
+    pub fn new(our_liquidator: Address) -> Self {
+        Self {
+            our_liquidator,
+            races: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.races.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.races.is_empty()
+    }
+
+    /// opens a race for the opportunity unless one is already running
+    pub fn observe(&mut self, opportunity: &LiquidationOpportunity, rival_gas_price: U256) {
+        let OpportunitySource::PendingTransaction(txn) = &opportunity.source;
+        self.races
+            .entry((opportunity.user, opportunity.debt))
+            .or_insert_with(|| Race {
+                user: opportunity.user,
+                collateral: opportunity.collateral,
+                debt: opportunity.debt,
+                rival_tx: txn.hash,
+                rival_gas_price,
+                first_seen_ms: unix_ms(SystemTime::now()),
+                simulated_profit: None,
+                our_tx: None,
+                our_gas_price: None,
+                submitted_ms: None,
+                skipped: None,
+            });
+    }
+
+    pub fn simulated(&mut self, user: Address, debt: Address, profit: U256) {
+        if let Some(race) = self.races.get_mut(&(user, debt)) {
+            race.simulated_profit = Some(profit);
+        }
+    }
+
+    pub fn skipped(&mut self, user: Address, debt: Address, reason: impl ToString) {
+        if let Some(race) = self.races.get_mut(&(user, debt)) {
+            race.skipped = Some(reason.to_string());
+        }
+    }
+
+    pub fn submitted(&mut self, user: Address, debt: Address, tx: H256, gas_price: U256) {
+        if let Some(race) = self.races.get_mut(&(user, debt)) {
+            race.our_tx = Some(tx);
+            race.our_gas_price = Some(gas_price);
+            race.submitted_ms = Some(unix_ms(SystemTime::now()));
+            race.skipped = None;
+        }
+    }
+
+    /// Closes the race a mined `LiquidationCall` log belongs to. Returns
+    /// `None` for liquidations we weren't racing.
+    pub fn settle(
+        &mut self,
+        log: &Log,
+        winning_gas_price: U256,
+        block_timestamp: u64,
+    ) -> Option<RaceReport> {
+        // topics are the event signature, collateral, debt and user
+        let debt = Address::from(*log.topics.get(2)?);
+        let user = Address::from(*log.topics.get(3)?);
+        let (winner, block_number) = parse_liquidator(log)?;
+        let race = self.races.remove(&(user, debt))?;
+
+        let outcome = if winner == self.our_liquidator {
+            RaceOutcome::Won
+        } else {
+            match race.our_gas_price {
+                None => RaceOutcome::NotSubmitted,
+                Some(ours) if ours < winning_gas_price => RaceOutcome::Outbid,
+                Some(_) => RaceOutcome::Ordering,
+            }
+        };
+        Some(RaceReport {
+            reaction_ms: race
+                .submitted_ms
+                .map(|ms| ms.saturating_sub(race.first_seen_ms)),
+            first_seen_to_block_ms: (block_timestamp * 1000) as i64 - race.first_seen_ms as i64,
+            outcome,
+            winner,
+            winning_tx: log.transaction_hash.unwrap_or_default(),
+            winning_gas_price,
+            block_number,
+            race,
+        })
+    }
+
+    /// drops races nobody settled, e.g. the borrower repaid in time
+    pub fn expire(&mut self, max_age: Duration) -> Vec<Race> {
+        let cutoff = unix_ms(SystemTime::now()).saturating_sub(max_age.as_millis() as u64);
+        let expired: Vec<(Address, Address)> = self
+            .races
+            .iter()
+            .filter(|(_, race)| race.first_seen_ms < cutoff)
+            .map(|(key, _)| *key)
+            .collect();
+        expired
+            .iter()
+            .filter_map(|key| self.races.remove(key))
+            .collect()
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Bytes, Transaction, U64};
+
+    use super::*;
+
+    fn liquidation_log(debt: Address, user: Address, liquidator: Address) -> Log {
+        let mut data = vec![0u8; 128];
+        data[76..96].copy_from_slice(liquidator.as_bytes());
+        Log {
+            topics: vec![
+                H256::zero(),
+                H256::zero(),
+                H256::from(debt),
+                H256::from(user),
+            ],
+            data: Bytes::from(data),
+            block_number: Some(U64::from(100)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_settle_race() {
+        let ours = Address::random();
+        let rival = Address::random();
+        let user = Address::random();
+        let debt = Address::random();
+        let opportunity = LiquidationOpportunity {
+            user,
+            collateral: Address::random(),
+            debt,
+            debt_to_cover: U256::from(1000),
+            expected_bonus: U256::from(50),
+            source: OpportunitySource::PendingTransaction(Transaction::default()),
+        };
+
+        let mut tracker = RaceTracker::new(ours);
+        tracker.observe(&opportunity, U256::from(100));
+        tracker.submitted(user, debt, H256::random(), U256::from(90));
+        let log = liquidation_log(debt, user, rival);
+        let report = tracker.settle(&log, U256::from(100), 0).unwrap();
+        assert_eq!(report.outcome, RaceOutcome::Outbid);
+        assert_eq!(report.winner, rival);
+        assert!(tracker.is_empty());
+
+        // nothing left to settle
+        assert_eq!(tracker.settle(&log, U256::from(100), 0), None);
+
+        tracker.observe(&opportunity, U256::from(100));
+        let log = liquidation_log(debt, user, ours);
+        let report = tracker.settle(&log, U256::from(100), 0).unwrap();
+        assert_eq!(report.outcome, RaceOutcome::Won);
+    }
+}
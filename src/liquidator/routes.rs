@@ -0,0 +1,298 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path, sync::Arc};
+
+use ethers::{
+    abi::parse_abi,
+    prelude::BaseContract,
+    providers::Middleware,
+    types::{Address, TransactionRequest, U256},
+};
+use lazy_static::lazy_static;
+use log::warn;
+use thiserror::Error;
+
+use crate::{
+    constants::{protocol::UniswapV2, token::ERC20Token},
+    uniswapV2::{IUniswapV2Factory, IUniswapV2Pair},
+};
+
+lazy_static! {
+    static ref ERC20_ABI: BaseContract = BaseContract::from(
+        parse_abi(&["function balanceOf(address owner) external view returns (uint256)"]).unwrap()
+    );
+}
+
+/// DODO pools the liquidation contract can flashloan each debt token from,
+/// by token symbol:
+///
+/// ```json
+/// { "USDC": ["0x5333Eb1E32522F1893B7C9feA3c263807A02d561"] }
+/// ```
+pub const DEFAULT_FLASHLOAN_POOLS: &str = "data/flashloan_pools.json";
+
+#[derive(Debug, Error)]
+pub enum FlashloanPoolsError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("unknown token {0}")]
+    UnknownToken(String),
+}
+
+/// Flashloan pools of each debt token, any of which can lend it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlashloanPools(HashMap<Address, Vec<Address>>);
+
+impl FlashloanPools {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FlashloanPoolsError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(json: &str) -> Result<Self, FlashloanPoolsError> {
+        let by_symbol: HashMap<String, Vec<Address>> = serde_json::from_str(json)?;
+        let mut pools = HashMap::new();
+        for (symbol, addresses) in by_symbol {
+            let token = ERC20Token::from_symbol(&symbol)
+                .ok_or(FlashloanPoolsError::UnknownToken(symbol))?;
+            pools.insert(token.get_address(), addresses);
+        }
+        Ok(Self(pools))
+    }
+
+    pub fn get(&self, token: &Address) -> &[Address] {
+        self.0.get(token).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// where to borrow the debt asset and where to swap the seized collateral back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidationRoute {
+    pub flashloan_pool: Address,
+    pub protocol: UniswapV2,
+}
+
+impl LiquidationRoute {
+    pub fn router(&self) -> Address {
+        self.protocol.get_router_address()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoRoute {
+    /// no known flashloan pool holds the debt asset
+    FlashloanSource,
+    /// no flashloan pool can cover the debt
+    FlashloanLiquidity,
+    /// no uniswap v2 fork has a collateral/debt pair
+    SwapRoute,
+}
+
+impl fmt::Display for NoRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoRoute::FlashloanSource => write!(f, "no flashloan source for debt asset"),
+            NoRoute::FlashloanLiquidity => write!(f, "no flashloan pool can cover the debt"),
+            NoRoute::SwapRoute => write!(f, "no swap route from collateral to debt"),
+        }
+    }
+}
+
+/// Finds a flashloan source and swap route for arbitrary collateral/debt
+/// pairs, caching which uniswap v2 forks have a pair for them.
+pub struct RouteFinder<M> {
+    provider: Arc<M>,
+    flashloan_pools: FlashloanPools,
+    factories: Vec<(UniswapV2, IUniswapV2Factory<M>)>,
+    pairs: HashMap<(Address, Address), Vec<(UniswapV2, Address)>>,
+}
+
+impl<M: Middleware> RouteFinder<M> {
+    pub fn new(provider: Arc<M>, flashloan_pools: FlashloanPools) -> Self {
+        let factories = UniswapV2::get_all_protoccols()
+            .into_iter()
+            .map(|protocol| {
+                let factory =
+                    IUniswapV2Factory::new(protocol.get_factory_address(), provider.clone());
+                (protocol, factory)
+            })
+            .collect();
+        Self {
+            provider,
+            flashloan_pools,
+            factories,
+            pairs: HashMap::new(),
+        }
+    }
+
+    pub async fn find(
+        &mut self,
+        collateral: Address,
+        debt: Address,
+        debt_to_cover: U256,
+    ) -> Result<LiquidationRoute, NoRoute> {
+        let flashloan_pool = self.flashloan_pool(debt, debt_to_cover).await?;
+        let protocol = self
+            .deepest_pair(collateral, debt)
+            .await
+            .ok_or(NoRoute::SwapRoute)?;
+        Ok(LiquidationRoute {
+            flashloan_pool,
+            protocol,
+        })
+    }
+
+    /// pool holding the most of `debt` of those that can lend it
+    async fn flashloan_pool(&self, debt: Address, amount: U256) -> Result<Address, NoRoute> {
+        let pools = self.flashloan_pools.get(&debt);
+        if pools.is_empty() {
+            return Err(NoRoute::FlashloanSource);
+        }
+        let mut deepest: Option<(Address, U256)> = None;
+        for &pool in pools {
+            let balance = self.balance_of(debt, pool).await;
+            if balance >= amount && deepest.is_none_or(|(_, best)| balance > best) {
+                deepest = Some((pool, balance));
+            }
+        }
+        deepest
+            .map(|(pool, _)| pool)
+            .ok_or(NoRoute::FlashloanLiquidity)
+    }
+
+    /// fork whose collateral/debt pair holds the most debt asset
+    async fn deepest_pair(&mut self, collateral: Address, debt: Address) -> Option<UniswapV2> {
+        let mut deepest: Option<(UniswapV2, u128)> = None;
+        for (protocol, pair) in self.pairs(collateral, debt).await {
+            let pair = IUniswapV2Pair::new(pair, self.provider.clone());
+            let (reserve0, reserve1, _) = match pair.get_reserves().call().await {
+                Ok(reserves) => reserves,
+                Err(e) => {
+                    warn!("failed to fetch reserves on {}: {}", protocol.get_name(), e);
+                    continue;
+                }
+            };
+            // token0 is the lower address
+            let debt_reserve = if debt < collateral {
+                reserve0
+            } else {
+                reserve1
+            };
+            if debt_reserve > 0 && deepest.is_none_or(|(_, best)| debt_reserve > best) {
+                deepest = Some((protocol, debt_reserve));
+            }
+        }
+        deepest.map(|(protocol, _)| protocol)
+    }
+
+    async fn pairs(&mut self, token0: Address, token1: Address) -> Vec<(UniswapV2, Address)> {
+        let key = if token0 < token1 {
+            (token0, token1)
+        } else {
+            (token1, token0)
+        };
+        if let Some(pairs) = self.pairs.get(&key) {
+            return pairs.clone();
+        }
+
+        let mut pairs = Vec::new();
+        for (protocol, factory) in &self.factories {
+            match factory.get_pair(key.0, key.1).call().await {
+                Ok(pair) if pair != Address::zero() => pairs.push((*protocol, pair)),
+                Ok(_) => {}
+                Err(e) => {
+                    // don't cache a partial lookup
+                    warn!("failed to look up pair on {}: {}", protocol.get_name(), e);
+                    return pairs;
+                }
+            }
+        }
+        self.pairs.insert(key, pairs.clone());
+        pairs
+    }
+
+    async fn balance_of(&self, token: Address, owner: Address) -> U256 {
+        let tx = TransactionRequest::new()
+            .to(token)
+            .data(ERC20_ABI.encode("balanceOf", owner).unwrap());
+        match self.provider.call(&tx.into(), None).await {
+            Ok(output) => ERC20_ABI
+                .decode_output("balanceOf", output)
+                .unwrap_or_default(),
+            Err(e) => {
+                warn!("failed to fetch balance of {:?}: {}", token, e);
+                U256::zero()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{abi::Token, providers::Provider, types::Bytes};
+
+    use super::*;
+    use crate::utils::batch::fake::FakeTransport;
+
+    fn balance(amount: u64) -> Bytes {
+        ethers::abi::encode(&[Token::Uint(U256::from(amount))]).into()
+    }
+
+    #[test]
+    fn test_parse_flashloan_pools() {
+        let pools = FlashloanPools::parse(
+            r#"{ "usdc": ["0x0000000000000000000000000000000000000001"], "WETH": [] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            pools.get(&ERC20Token::USDC.get_address()),
+            &[Address::from_low_u64_be(1)]
+        );
+        assert!(pools.get(&ERC20Token::WETH.get_address()).is_empty());
+        assert!(pools.get(&ERC20Token::DAI.get_address()).is_empty());
+        assert!(matches!(
+            FlashloanPools::parse(r#"{ "NOPE": [] }"#),
+            Err(FlashloanPoolsError::UnknownToken(symbol)) if symbol == "NOPE"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_flashloan_pool() {
+        let usdc = ERC20Token::USDC.get_address();
+        let (small, deep, deeper) = (
+            Address::from_low_u64_be(1),
+            Address::from_low_u64_be(2),
+            Address::from_low_u64_be(3),
+        );
+        let pools = FlashloanPools([(usdc, vec![small, deep, deeper])].into());
+        let transport = FakeTransport::new();
+        let routes = RouteFinder::new(Arc::new(Provider::new(transport.clone())), pools);
+
+        for amount in [100, 5_000, 4_000] {
+            transport.push_response("eth_call", balance(amount));
+        }
+        // the deepest that covers the debt
+        assert_eq!(
+            routes.flashloan_pool(usdc, U256::from(1_000)).await,
+            Ok(deep)
+        );
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].1[0]["to"], serde_json::json!(usdc));
+
+        for amount in [100, 5_000, 4_000] {
+            transport.push_response("eth_call", balance(amount));
+        }
+        assert_eq!(
+            routes.flashloan_pool(usdc, U256::from(6_000)).await,
+            Err(NoRoute::FlashloanLiquidity)
+        );
+        assert_eq!(
+            routes
+                .flashloan_pool(ERC20Token::DAI.get_address(), U256::from(1))
+                .await,
+            Err(NoRoute::FlashloanSource)
+        );
+    }
+}
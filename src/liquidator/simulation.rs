@@ -0,0 +1,206 @@
+use std::fmt;
+
+use ethers::{
+    abi::{self, ParamType, Token},
+    providers::{Middleware, ProviderError},
+    types::{transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, U256},
+};
+
+use crate::utils::tracer::{BlockTraceResult, DebugTraceExt, TraceConfig};
+
+/// selector of `Error(string)`
+const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// selector of `transfer(address,uint256)`
+const TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+
+/// Why a simulated liquidation reverted, AAVE v3 reverts with numeric error codes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RevertReason {
+    /// '45', health factor recovered (or somebody liquidated first)
+    HealthFactorNotBelowThreshold,
+    /// '46', collateral isn't enabled for the user
+    CollateralCannotBeLiquidated,
+    /// '47', no debt left in the debt asset, usually already liquidated
+    SpecifiedCurrencyNotBorrowedByUser,
+    /// any other `Error(string)` revert
+    Message(String),
+    /// revert without a decodable reason, holds the tracer error
+    Unknown(String),
+}
+
+impl RevertReason {
+    pub fn from_frame(frame: &BlockTraceResult) -> Option<Self> {
+        let error = frame.error.as_ref()?;
+        let message = frame.output.as_ref().and_then(decode_error_string);
+        Some(match message.as_deref() {
+            Some("45") => RevertReason::HealthFactorNotBelowThreshold,
+            Some("46") => RevertReason::CollateralCannotBeLiquidated,
+            Some("47") => RevertReason::SpecifiedCurrencyNotBorrowedByUser,
+            Some(msg) => RevertReason::Message(msg.to_string()),
+            None => RevertReason::Unknown(error.clone()),
+        })
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::HealthFactorNotBelowThreshold => {
+                write!(f, "health factor not below threshold")
+            }
+            RevertReason::CollateralCannotBeLiquidated => {
+                write!(f, "collateral cannot be liquidated")
+            }
+            RevertReason::SpecifiedCurrencyNotBorrowedByUser => {
+                write!(f, "debt asset not borrowed by user")
+            }
+            RevertReason::Message(msg) => write!(f, "reverted: {}", msg),
+            RevertReason::Unknown(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// result of tracing a liquidation transaction against the pending state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiquidationSimulation {
+    pub gas_used: U256,
+    /// amount of the profit token transferred to the beneficiary
+    pub profit: U256,
+    pub revert: Option<RevertReason>,
+}
+
+impl LiquidationSimulation {
+    pub fn from_trace(
+        trace: &BlockTraceResult,
+        profit_token: Address,
+        beneficiary: Address,
+    ) -> Self {
+        let revert = RevertReason::from_frame(trace);
+        let profit = if revert.is_none() {
+            sum_transfers(trace, profit_token, beneficiary)
+        } else {
+            U256::zero()
+        };
+        Self {
+            gas_used: trace.gas_used,
+            profit,
+            revert,
+        }
+    }
+
+    pub fn is_profitable(&self) -> bool {
+        self.revert.is_none() && !self.profit.is_zero()
+    }
+}
+
+/// Runs `tx` through `debug_traceCall` on the pending block and measures how
+/// much `profit_token` ends up at `beneficiary`. `tx` must have `from` set.
+pub async fn simulate_liquidation<M: Middleware>(
+    provider: &M,
+    tx: &TypedTransaction,
+    profit_token: Address,
+    beneficiary: Address,
+) -> Result<LiquidationSimulation, ProviderError> {
+    let trace = provider
+        .call_trace_call(tx, BlockNumber::Pending, &TraceConfig::call_tracer(false))
+        .await?;
+    Ok(LiquidationSimulation::from_trace(
+        &trace,
+        profit_token,
+        beneficiary,
+    ))
+}
+
+fn decode_error_string(output: &Bytes) -> Option<String> {
+    if !output.starts_with(&ERROR_STRING_SELECTOR) {
+        return None;
+    }
+    match abi::decode(&[ParamType::String], &output[4..])
+        .ok()?
+        .pop()?
+    {
+        Token::String(msg) => Some(msg),
+        _ => None,
+    }
+}
+
+/// total of successful `token.transfer(recipient, amount)` calls in the call tree
+fn sum_transfers(frame: &BlockTraceResult, token: Address, recipient: Address) -> U256 {
+    if frame.error.is_some() {
+        return U256::zero();
+    }
+    let mut total = U256::zero();
+    if frame.to == token && frame.input.starts_with(&TRANSFER_SELECTOR) {
+        if let Ok(tokens) = abi::decode(
+            &[ParamType::Address, ParamType::Uint(256)],
+            &frame.input[4..],
+        ) {
+            if let (Token::Address(to), Token::Uint(amount)) = (&tokens[0], &tokens[1]) {
+                if *to == recipient {
+                    total += *amount;
+                }
+            }
+        }
+    }
+    for call in frame.calls.iter().flatten() {
+        total += sum_transfers(call, token, recipient);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{self, Token},
+        types::{Address, Bytes, U256},
+    };
+
+    use super::*;
+
+    fn transfer_frame(token: Address, to: Address, amount: u64) -> BlockTraceResult {
+        let mut input = TRANSFER_SELECTOR.to_vec();
+        input.extend(abi::encode(&[
+            Token::Address(to),
+            Token::Uint(amount.into()),
+        ]));
+        BlockTraceResult {
+            to: token,
+            input: Bytes::from(input),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_profit_from_transfers() {
+        let token = Address::random();
+        let wallet = Address::random();
+        let trace = BlockTraceResult {
+            gas_used: U256::from(400_000),
+            calls: Some(vec![
+                transfer_frame(token, Address::random(), 1_000),
+                transfer_frame(token, wallet, 25),
+            ]),
+            ..Default::default()
+        };
+        let sim = LiquidationSimulation::from_trace(&trace, token, wallet);
+        assert_eq!(sim.profit, U256::from(25));
+        assert!(sim.is_profitable());
+    }
+
+    #[test]
+    fn test_aave_revert_reason() {
+        let mut output = ERROR_STRING_SELECTOR.to_vec();
+        output.extend(abi::encode(&[Token::String("45".to_string())]));
+        let trace = BlockTraceResult {
+            error: Some("execution reverted".to_string()),
+            output: Some(Bytes::from(output)),
+            ..Default::default()
+        };
+        let sim = LiquidationSimulation::from_trace(&trace, Address::zero(), Address::zero());
+        assert_eq!(
+            sim.revert,
+            Some(RevertReason::HealthFactorNotBelowThreshold)
+        );
+        assert!(!sim.is_profitable());
+    }
+}
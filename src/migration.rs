@@ -0,0 +1,123 @@
+//! Pools whose liquidity is pulled, by an LP migrating to another DEX or a
+//! rug. Swaps only ever grow `sqrt(reserve0 * reserve1)`, so a pair whose
+//! liquidity by that measure falls `collapse_bps` under the highest seen
+//! lost it to withdrawals. `WorldState` takes such pairs out of routing
+//! for the rest of the run and publishes a `Collapse` on the bus, with the
+//! pair of another factory the liquidity likely went to if asked to look.
+
+use std::collections::HashMap;
+
+use ethers::types::{Address, U256};
+use serde::Serialize;
+
+/// liquidity drop off the peak that counts as a collapse
+pub const DEFAULT_COLLAPSE_BPS: u64 = 9_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollapseConfig {
+    /// drop off the peak that pulls a pair from routing, 0 never does
+    pub collapse_bps: u64,
+    /// ask the other factories for a pair of the same tokens on a collapse
+    pub scan_replacements: bool,
+}
+
+impl Default for CollapseConfig {
+    fn default() -> Self {
+        Self {
+            collapse_bps: DEFAULT_COLLAPSE_BPS,
+            scan_replacements: false,
+        }
+    }
+}
+
+/// A pair of the same tokens on another factory.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Replacement {
+    pub pair: Address,
+    pub protocol: String,
+    pub liquidity: U256,
+    /// already quoted, created after startup otherwise and routing it
+    /// takes a restart
+    pub tracked: bool,
+}
+
+/// A pair pulled from routing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Collapse {
+    pub block: u64,
+    pub pair: Address,
+    pub protocol: String,
+    pub token0: Address,
+    pub token1: Address,
+    pub peak_liquidity: U256,
+    pub liquidity: U256,
+    pub replacement: Option<Replacement>,
+}
+
+/// `sqrt(reserve0 * reserve1)`, what swaps keep and withdrawals shrink
+pub fn liquidity((reserve0, reserve1): (U256, U256)) -> U256 {
+    reserve0.saturating_mul(reserve1).integer_sqrt()
+}
+
+/// whether `liquidity` is at least `collapse_bps` under `peak`
+pub fn collapsed(peak: U256, liquidity: U256, collapse_bps: u64) -> bool {
+    if collapse_bps == 0 || peak.is_zero() || liquidity >= peak {
+        return false;
+    }
+    (peak - liquidity).saturating_mul(U256::from(10_000)) >= peak * collapse_bps
+}
+
+/// Highest liquidity seen of every pair.
+#[derive(Debug, Default)]
+pub struct LiquidityPeaks {
+    peaks: HashMap<Address, U256>,
+}
+
+impl LiquidityPeaks {
+    /// records `reserves` of `pair`, returning its peak and current liquidity
+    pub fn observe(&mut self, pair: Address, reserves: (U256, U256)) -> (U256, U256) {
+        let liquidity = liquidity(reserves);
+        let peak = self.peaks.entry(pair).or_default();
+        *peak = U256::max(*peak, liquidity);
+        (*peak, liquidity)
+    }
+
+    pub fn forget(&mut self, pair: &Address) {
+        self.peaks.remove(pair);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse() {
+        let pair = Address::repeat_byte(1);
+        let mut peaks = LiquidityPeaks::default();
+        let reserves = |r0: u64, r1: u64| (U256::from(r0), U256::from(r1));
+        assert_eq!(
+            peaks.observe(pair, reserves(1_000_000, 4_000_000)),
+            (2_000_000.into(), 2_000_000.into())
+        );
+        // a swap moves the price, not the liquidity
+        let (peak, now) = peaks.observe(pair, reserves(2_000_000, 2_000_000));
+        assert!(!collapsed(peak, now, DEFAULT_COLLAPSE_BPS));
+
+        // half withdrawn
+        let (peak, now) = peaks.observe(pair, reserves(1_000_000, 1_000_000));
+        assert!(!collapsed(peak, now, DEFAULT_COLLAPSE_BPS));
+        assert!(collapsed(peak, now, 5_000));
+
+        // the rest but dust migrated, the peak still counts
+        let (peak, now) = peaks.observe(pair, reserves(100_000, 100_000));
+        assert_eq!(peak, 2_000_000.into());
+        assert!(collapsed(peak, now, DEFAULT_COLLAPSE_BPS));
+        assert!(!collapsed(peak, now, 0));
+
+        // never had liquidity to lose
+        assert!(!collapsed(U256::zero(), U256::zero(), DEFAULT_COLLAPSE_BPS));
+        peaks.forget(&pair);
+        assert_eq!(peaks.observe(pair, reserves(1, 1)), (1.into(), 1.into()));
+    }
+}
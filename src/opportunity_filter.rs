@@ -0,0 +1,366 @@
+//! Bespoke risk rules over detected opportunities without forking the
+//! engine. Filters see every opportunity that quoted profitably, in the
+//! order they were registered, and accept it, veto it or resize it: a Rust
+//! closure registered on `OpportunityFilters`, or with the `scripting`
+//! feature a Rhai script defining `filter(opp)`:
+//!
+//! ```rhai
+//! fn filter(opp) {
+//!     if opp.protocols.contains("Balancer") { return "no balancer"; }
+//!     if opp.token == "WETH" && opp.amount_in > 5.0 { return 5.0; }
+//! }
+//! ```
+//!
+//! `opp` has `block`, `route` (symbols joined by `>`), `protocols`,
+//! `token` (the start token), `amount_in`, `profit` (both in whole start
+//! tokens), `profit_usd`, `gas_price_gwei` and `success_rate` (of the route
+//! lately). Returning nothing or `true` accepts, `false` or a string (the
+//! reason) vetoes and a number resizes to that many start tokens. A script
+//! that fails vetoes. A resized opportunity is requoted at its new size,
+//! the filters after the resizing one see its profit scaled pro rata.
+
+use std::path::Path;
+
+use ethers::types::U256;
+use thiserror::Error;
+
+use crate::utils::fixed_point::to_f64;
+
+#[derive(Debug, Error)]
+pub enum FilterError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to compile {path}: {error}")]
+    Compile { path: String, error: String },
+
+    #[error("{0} doesn't define filter(opp)")]
+    NoFilter(String),
+
+    #[error("{0} needs the scripting feature")]
+    Unsupported(String),
+}
+
+/// an opportunity as filters see it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub block: u64,
+    /// token symbols joined by `>`
+    pub route: String,
+    pub protocols: Vec<String>,
+    /// symbol of the start token, the amounts are in its smallest unit
+    pub token: String,
+    pub decimals: u8,
+    pub amount_in: U256,
+    /// quoted
+    pub profit: U256,
+    pub profit_usd: f64,
+    pub gas_price: U256,
+    pub success_rate: f64,
+}
+
+impl Candidate {
+    #[cfg(feature = "scripting")]
+    fn whole(&self, amount: U256) -> f64 {
+        to_f64(amount) / 10f64.powi(self.decimals as i32)
+    }
+
+    #[cfg(feature = "scripting")]
+    fn units(&self, amount: f64) -> U256 {
+        U256::from((amount * 10f64.powi(self.decimals as i32)) as u128)
+    }
+
+    /// at `amount_in`, the profit scaled with it
+    fn resized(&self, amount_in: U256) -> Self {
+        let scale = match self.amount_in.is_zero() {
+            true => 0.0,
+            false => to_f64(amount_in) / to_f64(self.amount_in),
+        };
+        Self {
+            amount_in,
+            profit: U256::from((to_f64(self.profit) * scale) as u128),
+            profit_usd: self.profit_usd * scale,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// with the reason
+    Veto(String),
+    /// to this `amount_in`
+    Resize(U256),
+}
+
+pub trait OpportunityFilter: Send + Sync {
+    fn check(&self, candidate: &Candidate) -> Verdict;
+}
+
+impl<F: Fn(&Candidate) -> Verdict + Send + Sync> OpportunityFilter for F {
+    fn check(&self, candidate: &Candidate) -> Verdict {
+        self(candidate)
+    }
+}
+
+/// The filters every opportunity goes through, none accepts everything.
+#[derive(Default)]
+pub struct OpportunityFilters {
+    filters: Vec<(String, Box<dyn OpportunityFilter>)>,
+}
+
+impl OpportunityFilters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// runs `filter` after the ones registered so far, `name` is in its
+    /// vetoes
+    pub fn register(
+        mut self,
+        name: impl Into<String>,
+        filter: impl OpportunityFilter + 'static,
+    ) -> Self {
+        self.filters.push((name.into(), Box::new(filter)));
+        self
+    }
+
+    /// registers the script at `path` under its file name
+    pub fn register_script(self, path: impl AsRef<Path>) -> Result<Self, FilterError> {
+        let name = path.as_ref().display().to_string();
+        #[cfg(feature = "scripting")]
+        {
+            Ok(self.register(name, ScriptFilter::load(path)?))
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            Err(FilterError::Unsupported(name))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The first veto, or the size the filters leave `candidate` at if they
+    /// resized it.
+    pub fn check(&self, candidate: &Candidate) -> Verdict {
+        let mut candidate = candidate.clone();
+        let mut resized = false;
+        for (name, filter) in &self.filters {
+            match filter.check(&candidate) {
+                Verdict::Accept => {}
+                Verdict::Veto(reason) => return Verdict::Veto(format!("{}: {}", name, reason)),
+                Verdict::Resize(amount_in) => {
+                    candidate = candidate.resized(amount_in);
+                    resized = true;
+                }
+            }
+        }
+        match resized {
+            true => Verdict::Resize(candidate.amount_in),
+            false => Verdict::Accept,
+        }
+    }
+}
+
+/// limit on the operations a script runs per opportunity, so a runaway
+/// loop vetoes instead of stalling the block
+#[cfg(feature = "scripting")]
+const MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+
+/// A Rhai script defining `filter(opp)`.
+#[cfg(feature = "scripting")]
+pub struct ScriptFilter {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+}
+
+#[cfg(feature = "scripting")]
+impl ScriptFilter {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FilterError> {
+        let name = path.as_ref().display().to_string();
+        Self::compile(&name, &std::fs::read_to_string(path)?)
+    }
+
+    /// `script` named `name` in errors
+    pub fn compile(name: &str, script: &str) -> Result<Self, FilterError> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+        let ast = engine.compile(script).map_err(|e| FilterError::Compile {
+            path: name.to_string(),
+            error: e.to_string(),
+        })?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "filter" && f.params.len() == 1)
+        {
+            return Err(FilterError::NoFilter(name.to_string()));
+        }
+        Ok(Self { engine, ast })
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl OpportunityFilter for ScriptFilter {
+    fn check(&self, candidate: &Candidate) -> Verdict {
+        use rhai::Dynamic;
+
+        let mut opp = rhai::Map::new();
+        opp.insert("block".into(), (candidate.block as i64).into());
+        opp.insert("route".into(), candidate.route.clone().into());
+        opp.insert(
+            "protocols".into(),
+            candidate
+                .protocols
+                .iter()
+                .cloned()
+                .map(Dynamic::from)
+                .collect::<rhai::Array>()
+                .into(),
+        );
+        opp.insert("token".into(), candidate.token.clone().into());
+        opp.insert(
+            "amount_in".into(),
+            candidate.whole(candidate.amount_in).into(),
+        );
+        opp.insert("profit".into(), candidate.whole(candidate.profit).into());
+        opp.insert("profit_usd".into(), candidate.profit_usd.into());
+        opp.insert(
+            "gas_price_gwei".into(),
+            (to_f64(candidate.gas_price) / 1e9).into(),
+        );
+        opp.insert("success_rate".into(), candidate.success_rate.into());
+
+        let verdict =
+            self.engine
+                .call_fn::<Dynamic>(&mut rhai::Scope::new(), &self.ast, "filter", (opp,));
+        let verdict = match verdict {
+            Ok(verdict) => verdict,
+            Err(e) => return Verdict::Veto(format!("script failed: {}", e)),
+        };
+        if verdict.is_unit() {
+            return Verdict::Accept;
+        }
+        if let Ok(accept) = verdict.as_bool() {
+            return match accept {
+                true => Verdict::Accept,
+                false => Verdict::Veto("vetoed".to_string()),
+            };
+        }
+        let amount = verdict
+            .as_float()
+            .ok()
+            .or_else(|| verdict.as_int().ok().map(|amount| amount as f64));
+        match amount {
+            Some(amount) if amount.is_finite() && amount > 0.0 => {
+                Verdict::Resize(candidate.units(amount))
+            }
+            Some(amount) => Verdict::Veto(format!("resized to {}", amount)),
+            None if verdict.is_string() => Verdict::Veto(verdict.to_string()),
+            None => Verdict::Veto(format!("unexpected {}", verdict.type_name())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate() -> Candidate {
+        Candidate {
+            block: 100,
+            route: "USDC>WETH>USDC".to_string(),
+            protocols: vec!["UniswapV3".to_string(), "Sushiswap".to_string()],
+            token: "USDC".to_string(),
+            decimals: 6,
+            amount_in: U256::from(10_000_000_000u64),
+            profit: U256::from(40_000_000),
+            profit_usd: 40.0,
+            gas_price: U256::from(100_000_000_000u64),
+            success_rate: 0.9,
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            OpportunityFilters::new().check(&candidate()),
+            Verdict::Accept
+        );
+        let filters = OpportunityFilters::new()
+            .register("size cap", |candidate: &Candidate| {
+                match candidate.amount_in > U256::from(5_000_000_000u64) {
+                    true => Verdict::Resize(U256::from(5_000_000_000u64)),
+                    false => Verdict::Accept,
+                }
+            })
+            .register("min profit", |candidate: &Candidate| {
+                match candidate.profit_usd < 25.0 {
+                    true => Verdict::Veto(format!("${} profit", candidate.profit_usd)),
+                    false => Verdict::Accept,
+                }
+            });
+        // halved to $20 by the cap
+        assert_eq!(
+            filters.check(&candidate()),
+            Verdict::Veto("min profit: $20 profit".to_string())
+        );
+        let big = Candidate {
+            profit: U256::from(80_000_000),
+            profit_usd: 80.0,
+            ..candidate()
+        };
+        assert_eq!(
+            filters.check(&big),
+            Verdict::Resize(U256::from(5_000_000_000u64))
+        );
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_script_filter() {
+        let script = r#"
+            fn filter(opp) {
+                if opp.protocols.contains("Balancer") { return "no balancer"; }
+                if opp.gas_price_gwei > 500.0 { return false; }
+                if opp.amount_in > 5000.0 { return 5000; }
+            }
+        "#;
+        let filter = ScriptFilter::compile("test", script).unwrap();
+        assert_eq!(
+            filter.check(&candidate()),
+            Verdict::Resize(U256::from(5_000_000_000u64))
+        );
+        let small = Candidate {
+            amount_in: U256::from(1_000_000_000u64),
+            ..candidate()
+        };
+        assert_eq!(filter.check(&small), Verdict::Accept);
+        let balancer = Candidate {
+            protocols: vec!["Balancer".to_string()],
+            ..candidate()
+        };
+        assert_eq!(
+            filter.check(&balancer),
+            Verdict::Veto("no balancer".to_string())
+        );
+        let expensive = Candidate {
+            gas_price: U256::from(600_000_000_000u64),
+            ..candidate()
+        };
+        assert_eq!(
+            filter.check(&expensive),
+            Verdict::Veto("vetoed".to_string())
+        );
+
+        // runaway scripts veto
+        let endless = ScriptFilter::compile("endless", "fn filter(opp) { loop {} }").unwrap();
+        assert!(matches!(endless.check(&small), Verdict::Veto(_)));
+        assert!(matches!(
+            ScriptFilter::compile("empty", "let x = 1;"),
+            Err(FilterError::NoFilter(_))
+        ));
+    }
+}
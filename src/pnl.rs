@@ -0,0 +1,216 @@
+//! Realized PnL of executed arbs per route start token, kept in the `pnl`
+//! table. Gas is accounted at what the txn actually paid rather than what
+//! it bid: an EIP-1559 txn pays `min(max fee, base fee + tip)` per gas, the
+//! base fee part is burnt and only the tip reaches the validator, and
+//! whatever the bid cap allowed beyond that is never spent.
+
+use std::sync::Arc;
+
+use ethers::types::{TransactionReceipt, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::token::ERC20Token,
+    storage::{Storage, StorageError, Table, PNL},
+};
+
+/// gas of one mined txn, amounts in wei of the native token
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasCost {
+    pub gas_used: U256,
+    /// most the txn was willing to pay per gas, the max fee of EIP-1559 txns
+    pub bid_gas_price: U256,
+    pub effective_gas_price: U256,
+    pub base_fee: U256,
+}
+
+impl GasCost {
+    /// from the receipt of a txn that bid `bid_gas_price`, mined in a block
+    /// with `base_fee`. Receipts without an effective gas price are legacy
+    /// txns, which pay their bid.
+    pub fn from_receipt(receipt: &TransactionReceipt, bid_gas_price: U256, base_fee: U256) -> Self {
+        Self {
+            gas_used: receipt.gas_used.unwrap_or_default(),
+            bid_gas_price,
+            effective_gas_price: receipt.effective_gas_price.unwrap_or(bid_gas_price),
+            base_fee,
+        }
+    }
+
+    pub fn paid(&self) -> U256 {
+        self.gas_used * self.effective_gas_price
+    }
+
+    pub fn bid(&self) -> U256 {
+        self.gas_used * self.bid_gas_price
+    }
+
+    pub fn burnt(&self) -> U256 {
+        self.gas_used * U256::min(self.base_fee, self.effective_gas_price)
+    }
+
+    /// to the validator
+    pub fn tip(&self) -> U256 {
+        self.paid() - self.burnt()
+    }
+
+    /// allowed by the bid but not paid
+    pub fn unspent(&self) -> U256 {
+        self.bid().saturating_sub(self.paid())
+    }
+}
+
+/// running totals of the executions of routes starting at one token
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenPnl {
+    /// mined, reverted or not
+    pub executions: u64,
+    pub reverts: u64,
+    /// quoted profit of the executions that didn't revert, in the token
+    pub gross_profit: U256,
+    /// the gas totals are in wei of the native token
+    pub gas_paid: U256,
+    pub gas_burnt: U256,
+    pub gas_tip: U256,
+    /// what the same executions would have cost at their bids
+    pub gas_bid: U256,
+}
+
+impl TokenPnl {
+    /// adds an execution, `profit` is `None` if it reverted
+    pub fn add(&mut self, profit: Option<U256>, gas: &GasCost) {
+        self.executions += 1;
+        match profit {
+            Some(profit) => self.gross_profit += profit,
+            None => self.reverts += 1,
+        }
+        self.gas_paid += gas.paid();
+        self.gas_burnt += gas.burnt();
+        self.gas_tip += gas.tip();
+        self.gas_bid += gas.bid();
+    }
+
+    /// takes back an execution `add` counted, one a reorg orphaned
+    pub fn remove(&mut self, profit: Option<U256>, gas: &GasCost) {
+        self.executions = self.executions.saturating_sub(1);
+        match profit {
+            Some(profit) => self.gross_profit = self.gross_profit.saturating_sub(profit),
+            None => self.reverts = self.reverts.saturating_sub(1),
+        }
+        self.gas_paid = self.gas_paid.saturating_sub(gas.paid());
+        self.gas_burnt = self.gas_burnt.saturating_sub(gas.burnt());
+        self.gas_tip = self.gas_tip.saturating_sub(gas.tip());
+        self.gas_bid = self.gas_bid.saturating_sub(gas.bid());
+    }
+}
+
+pub struct PnlLedger {
+    table: Table<TokenPnl>,
+}
+
+impl PnlLedger {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            table: Table::new(storage, PNL),
+        }
+    }
+
+    /// adds an execution of a route starting at `token`, returning the new
+    /// totals of the token
+    pub async fn record(
+        &self,
+        token: ERC20Token,
+        profit: Option<U256>,
+        gas: &GasCost,
+    ) -> Result<TokenPnl, StorageError> {
+        let key = token.get_symbol();
+        let mut pnl = self.table.get(key).await?.unwrap_or_default();
+        pnl.add(profit, gas);
+        self.table.put(key, &pnl).await?;
+        Ok(pnl)
+    }
+
+    /// takes back an execution `record` added, returning the new totals of
+    /// the token
+    pub async fn retract(
+        &self,
+        token: ERC20Token,
+        profit: Option<U256>,
+        gas: &GasCost,
+    ) -> Result<TokenPnl, StorageError> {
+        let key = token.get_symbol();
+        let mut pnl = self.table.get(key).await?.unwrap_or_default();
+        pnl.remove(profit, gas);
+        self.table.put(key, &pnl).await?;
+        Ok(pnl)
+    }
+
+    /// totals per token symbol
+    pub async fn all(&self) -> Result<Vec<(String, TokenPnl)>, StorageError> {
+        self.table.all().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn gwei(amount: u64) -> U256 {
+        U256::from(amount) * U256::exp10(9)
+    }
+
+    #[test]
+    fn test_gas_cost() {
+        let receipt = TransactionReceipt {
+            gas_used: Some(200_000.into()),
+            effective_gas_price: Some(gwei(130)),
+            ..Default::default()
+        };
+        // bid 500 gwei, paid 100 base fee + 30 tip
+        let gas = GasCost::from_receipt(&receipt, gwei(500), gwei(100));
+        assert_eq!(gas.paid(), gwei(130) * 200_000);
+        assert_eq!(gas.burnt(), gwei(100) * 200_000);
+        assert_eq!(gas.tip(), gwei(30) * 200_000);
+        assert_eq!(gas.unspent(), gwei(370) * 200_000);
+
+        // legacy txns pay their bid
+        let legacy = TransactionReceipt {
+            gas_used: Some(100_000.into()),
+            ..Default::default()
+        };
+        let gas = GasCost::from_receipt(&legacy, gwei(150), gwei(100));
+        assert_eq!(gas.paid(), gas.bid());
+        assert_eq!(gas.tip(), gwei(50) * 100_000);
+    }
+
+    #[tokio::test]
+    async fn test_pnl_ledger() {
+        let ledger = PnlLedger::new(Arc::new(MemoryStorage::new()));
+        let gas = GasCost {
+            gas_used: 100_000.into(),
+            bid_gas_price: gwei(200),
+            effective_gas_price: gwei(120),
+            base_fee: gwei(100),
+        };
+        ledger
+            .record(ERC20Token::USDC, Some(5_000_000.into()), &gas)
+            .await
+            .unwrap();
+        let pnl = ledger.record(ERC20Token::USDC, None, &gas).await.unwrap();
+        assert_eq!((pnl.executions, pnl.reverts), (2, 1));
+        assert_eq!(pnl.gross_profit, 5_000_000.into());
+        assert_eq!(pnl.gas_paid, gwei(120) * 200_000);
+        assert_eq!(pnl.gas_bid, gwei(200) * 200_000);
+        assert_eq!(pnl.gas_burnt + pnl.gas_tip, pnl.gas_paid);
+
+        let all = ledger.all().await.unwrap();
+        assert_eq!(all, vec![("USDC".to_string(), pnl)]);
+
+        // the revert was orphaned
+        let pnl = ledger.retract(ERC20Token::USDC, None, &gas).await.unwrap();
+        assert_eq!((pnl.executions, pnl.reverts), (1, 0));
+        assert_eq!(pnl.gross_profit, 5_000_000.into());
+        assert_eq!(pnl.gas_paid, gwei(120) * 100_000);
+    }
+}
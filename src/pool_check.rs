@@ -0,0 +1,260 @@
+//! Checks a V2 protocol's configuration against the chain before trading
+//! through it: the router must trade against the configured factory, pairs
+//! must sit at the CREATE2 address the init code hash derives, and the
+//! router's own `getAmountOut` must agree with the fee the local quotes
+//! assume. A protocol with any mismatch quotes wrong amounts or sends to
+//! the wrong pair, so it's not traded through at all.
+
+use std::sync::Arc;
+
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, H256, U256},
+    utils::{get_create2_address_from_hash, keccak256},
+};
+use log::debug;
+use thiserror::Error;
+
+use crate::{
+    constants::{protocol::UniswapV2, token::ERC20Token},
+    uniswapV2::{IUniswapV2Pair, IUniswapV2Router02, UniswapV2Pair},
+};
+
+abigen!(
+    PairCodeHashFactory,
+    r#"[
+        function INIT_CODE_PAIR_HASH() external view returns (bytes32)
+    ]"#,
+);
+
+/// amount in and (equal) reserves the fee is probed with
+const FEE_PROBE_AMOUNT: u64 = 1_000_000;
+const FEE_PROBE_RESERVES: u64 = 1_000_000_000_000;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ConfigMismatch {
+    #[error("router trades against factory {router_factory:?}, configured {configured:?}")]
+    Factory {
+        configured: Address,
+        router_factory: Address,
+    },
+
+    #[error("pair {pair:?} belongs to factory {pair_factory:?}")]
+    PairFactory {
+        pair: Address,
+        pair_factory: Address,
+    },
+
+    #[error("factory reports init code hash {onchain:?}, configured {configured:?}")]
+    InitCodeHash { configured: H256, onchain: H256 },
+
+    #[error("pair {pair:?} is not at {derived:?} derived from the init code hash")]
+    PairAddress { pair: Address, derived: Address },
+
+    #[error("router quotes {router} where the local fee math quotes {local}")]
+    Fee { local: U256, router: U256 },
+}
+
+/// CREATE2 address of the `token_a`/`token_b` pair of `factory`
+pub fn pair_address(
+    factory: Address,
+    token_a: Address,
+    token_b: Address,
+    init_code_hash: H256,
+) -> Address {
+    let (token0, token1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+    // abi.encodePacked(token0, token1)
+    let salt = keccak256([token0.as_bytes(), token1.as_bytes()].concat());
+    get_create2_address_from_hash(factory, salt.to_vec(), init_code_hash.as_bytes().to_vec())
+}
+
+/// what the local quote of a pair with `fees` gives for the fee probe
+fn local_probe(protocol: UniswapV2, token0: ERC20Token, token1: ERC20Token, fees: U256) -> U256 {
+    let mut pair = UniswapV2Pair::default();
+    pair.update_metadata(protocol, token0, token1, fees);
+    pair.update_reserves(
+        U256::from(FEE_PROBE_RESERVES),
+        U256::from(FEE_PROBE_RESERVES),
+    );
+    pair.get_amounts_out(U256::from(FEE_PROBE_AMOUNT), token0)
+}
+
+/// Checks `protocol` through one of its pairs, `pair` with `token0`,
+/// `token1` and `fees` as read from it. Checks the chain can't answer (no
+/// init code hash anywhere, a router without `getAmountOut`) are skipped.
+pub async fn verify_protocol<M: Middleware>(
+    provider: Arc<M>,
+    protocol: UniswapV2,
+    pair: Address,
+    token0: ERC20Token,
+    token1: ERC20Token,
+    fees: U256,
+) -> Vec<ConfigMismatch> {
+    let mut mismatches = Vec::new();
+    let factory = protocol.get_factory_address();
+
+    let router = IUniswapV2Router02::new(protocol.get_router_address(), provider.clone());
+    match router.factory().call().await {
+        Ok(router_factory) if router_factory != factory => {
+            mismatches.push(ConfigMismatch::Factory {
+                configured: factory,
+                router_factory,
+            })
+        }
+        Ok(_) => {}
+        Err(e) => debug!("{} router has no factory(): {:?}", protocol.get_name(), e),
+    }
+
+    match IUniswapV2Pair::new(pair, provider.clone())
+        .factory()
+        .call()
+        .await
+    {
+        Ok(pair_factory) if pair_factory != factory => {
+            mismatches.push(ConfigMismatch::PairFactory { pair, pair_factory })
+        }
+        Ok(_) => {}
+        Err(e) => debug!("{} pair has no factory(): {:?}", protocol.get_name(), e),
+    }
+
+    let onchain_hash = PairCodeHashFactory::new(factory, provider.clone())
+        .init_code_pair_hash()
+        .call()
+        .await
+        .ok()
+        .map(H256::from);
+    let init_code_hash = match (protocol.get_init_code_hash(), onchain_hash) {
+        (Some(configured), Some(onchain)) if configured != onchain => {
+            mismatches.push(ConfigMismatch::InitCodeHash {
+                configured,
+                onchain,
+            });
+            None
+        }
+        (configured, onchain) => configured.or(onchain),
+    };
+    match init_code_hash {
+        Some(init_code_hash) => {
+            let derived = pair_address(
+                factory,
+                token0.get_address(),
+                token1.get_address(),
+                init_code_hash,
+            );
+            if derived != pair {
+                mismatches.push(ConfigMismatch::PairAddress { pair, derived });
+            }
+        }
+        None => debug!(
+            "No init code hash for {}, pair address not checked",
+            protocol.get_name()
+        ),
+    }
+
+    let local = local_probe(protocol, token0, token1, fees);
+    match router
+        .get_amount_out(
+            U256::from(FEE_PROBE_AMOUNT),
+            U256::from(FEE_PROBE_RESERVES),
+            U256::from(FEE_PROBE_RESERVES),
+        )
+        .call()
+        .await
+    {
+        Ok(router) if router != local => mismatches.push(ConfigMismatch::Fee { local, router }),
+        Ok(_) => {}
+        Err(e) => debug!(
+            "{} router has no getAmountOut: {:?}",
+            protocol.get_name(),
+            e
+        ),
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::token::ERC20Token::{USDC, WETH};
+
+    #[test]
+    fn test_pair_address() {
+        // Uniswap V2 USDC/WETH on mainnet
+        let factory = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"
+            .parse()
+            .unwrap();
+        let usdc = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+            .parse()
+            .unwrap();
+        let weth = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+            .parse()
+            .unwrap();
+        let init_code_hash = UniswapV2::QUICKSWAP.get_init_code_hash().unwrap();
+        let expected: Address = "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"
+            .parse()
+            .unwrap();
+        assert_eq!(pair_address(factory, usdc, weth, init_code_hash), expected);
+        assert_eq!(pair_address(factory, weth, usdc, init_code_hash), expected);
+    }
+
+    #[test]
+    fn test_protocol_pair_addresses() {
+        use crate::constants::token::ERC20Token::WMATIC;
+
+        let pairs = [
+            (
+                UniswapV2::QUICKSWAP,
+                USDC,
+                WETH,
+                "0x853Ee4b2A13f8a742d64C8F088bE7bA2131f670d",
+            ),
+            (
+                UniswapV2::QUICKSWAP,
+                WMATIC,
+                USDC,
+                "0x6e7a5FAFcEc6BB1e78bAE2A1F0B612012BF14827",
+            ),
+            (
+                UniswapV2::APESWAP,
+                USDC,
+                WETH,
+                "0x84964d9f9480a1dB644c2B2D1022765179A40F68",
+            ),
+            (
+                UniswapV2::APESWAP,
+                WMATIC,
+                USDC,
+                "0x019011032a7ac3A87eE885B6c08467AC46ad11CD",
+            ),
+        ];
+        for (protocol, token_a, token_b, expected) in pairs {
+            let derived = pair_address(
+                protocol.get_factory_address(),
+                token_a.get_address(),
+                token_b.get_address(),
+                protocol.get_init_code_hash().unwrap(),
+            );
+            assert_eq!(derived, expected.parse::<Address>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_local_probe() {
+        // 0.3% fee: 1e6 * 997 * 1e12 / (1e12 * 1000 + 1e6 * 997)
+        assert_eq!(
+            local_probe(UniswapV2::QUICKSWAP, USDC, WETH, U256::zero()),
+            U256::from(996_999)
+        );
+        // Meshswap reads its fee from the pair, in bps
+        assert_eq!(
+            local_probe(UniswapV2::MESHSWAP, USDC, WETH, U256::from(10)),
+            U256::from(998_999)
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Startup checks of the network a bot is about to trade on: the provider
+//! is on the expected chain, the contracts it calls have code there, token
+//! decimals match `constants`, and the wallet can pay for gas. All failures
+//! are reported at once, each saying what to fix, instead of the first
+//! trade reverting.
+
+use std::{fmt, sync::Arc};
+
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, U256},
+};
+use thiserror::Error;
+
+use crate::constants::token::ERC20Token;
+
+abigen!(
+    Erc20Decimals,
+    r#"[
+        function decimals() external view returns (uint8)
+    ]"#,
+);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PreflightError {
+    #[error("provider is on chain {actual}, expected {expected}; check the RPC url")]
+    ChainId { expected: u64, actual: u64 },
+
+    #[error("{name} at {address:?} has no code on this chain; deploy it or fix its address")]
+    NoCode { name: String, address: Address },
+
+    #[error("{symbol} reports {onchain} decimals, constants say {configured}; fix its entry in constants/token.rs")]
+    Decimals {
+        symbol: &'static str,
+        configured: u8,
+        onchain: u8,
+    },
+
+    #[error("wallet {wallet:?} holds {balance} wei, less than the {required} wei needed for gas; fund it")]
+    Balance {
+        wallet: Address,
+        balance: U256,
+        required: U256,
+    },
+
+    #[error("couldn't check {check}: {message}")]
+    Provider { check: String, message: String },
+}
+
+/// every check that failed
+#[derive(Debug, PartialEq, Eq)]
+pub struct PreflightFailed(pub Vec<PreflightError>);
+
+impl fmt::Display for PreflightFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} startup checks failed", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PreflightFailed {}
+
+/// What to check, run with `run` once the provider is up.
+pub struct Preflight {
+    chain_id: u64,
+    contracts: Vec<(String, Address)>,
+    tokens: Vec<ERC20Token>,
+    wallet: Option<(Address, U256)>,
+}
+
+impl Preflight {
+    pub fn new(chain_id: u64) -> Self {
+        Self {
+            chain_id,
+            contracts: Vec::new(),
+            tokens: Vec::new(),
+            wallet: None,
+        }
+    }
+
+    /// `address` must have code
+    pub fn contract(mut self, name: impl Into<String>, address: Address) -> Self {
+        self.contracts.push((name.into(), address));
+        self
+    }
+
+    /// the `decimals()` of `tokens` must match their constants
+    pub fn tokens(mut self, tokens: &[ERC20Token]) -> Self {
+        self.tokens.extend_from_slice(tokens);
+        self
+    }
+
+    /// `wallet` must hold at least `min_balance` of the native token
+    pub fn wallet(mut self, wallet: Address, min_balance: U256) -> Self {
+        self.wallet = Some((wallet, min_balance));
+        self
+    }
+
+    /// Runs the checks against `provider`. On the wrong chain nothing else
+    /// is checked, the rest would fail for the same reason.
+    pub async fn run<M: Middleware + 'static>(
+        &self,
+        provider: Arc<M>,
+    ) -> Result<(), PreflightFailed> {
+        let provider_error = |check: &str, e: M::Error| PreflightError::Provider {
+            check: check.to_string(),
+            message: e.to_string(),
+        };
+        let actual = provider
+            .get_chainid()
+            .await
+            .map_err(|e| PreflightFailed(vec![provider_error("chain id", e)]))?
+            .as_u64();
+        if actual != self.chain_id {
+            return Err(PreflightFailed(vec![PreflightError::ChainId {
+                expected: self.chain_id,
+                actual,
+            }]));
+        }
+
+        let mut errors = Vec::new();
+        for (name, address) in &self.contracts {
+            match provider.get_code(*address, None).await {
+                Ok(code) if code.is_empty() => errors.push(PreflightError::NoCode {
+                    name: name.clone(),
+                    address: *address,
+                }),
+                Ok(_) => {}
+                Err(e) => errors.push(provider_error(name, e)),
+            }
+        }
+        for token in &self.tokens {
+            let contract = Erc20Decimals::new(token.get_address(), provider.clone());
+            match contract.decimals().call().await {
+                Ok(onchain) if onchain != token.get_decimals() => {
+                    errors.push(PreflightError::Decimals {
+                        symbol: token.get_symbol(),
+                        configured: token.get_decimals(),
+                        onchain,
+                    })
+                }
+                Ok(_) => {}
+                Err(e) => errors.push(PreflightError::Provider {
+                    check: format!("{} decimals", token.get_symbol()),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        if let Some((wallet, required)) = self.wallet {
+            match provider.get_balance(wallet, None).await {
+                Ok(balance) if balance < required => errors.push(PreflightError::Balance {
+                    wallet,
+                    balance,
+                    required,
+                }),
+                Ok(_) => {}
+                Err(e) => errors.push(provider_error("wallet balance", e)),
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(PreflightFailed(errors)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+
+    use super::*;
+    use crate::{
+        address_book::POLYGON,
+        constants::token::ERC20Token::{USDC, WETH},
+        utils::batch::fake::FakeTransport,
+    };
+
+    fn uint(value: u64) -> String {
+        format!("0x{:064x}", value)
+    }
+
+    #[tokio::test]
+    async fn test_preflight() {
+        let executor = Address::random();
+        let router = Address::random();
+        let wallet = Address::random();
+        let checks = Preflight::new(POLYGON)
+            .contract("executor", executor)
+            .contract("router", router)
+            .tokens(&[USDC, WETH])
+            .wallet(wallet, 1_000.into());
+
+        let transport = FakeTransport::new();
+        transport.set_response("eth_chainId", "0x89");
+        transport.push_response("eth_getCode", "0x6080");
+        transport.push_response("eth_getCode", "0x");
+        transport.push_response("eth_call", uint(6));
+        transport.push_response("eth_call", uint(8));
+        transport.push_response("eth_getBalance", "0x64");
+        let failed = checks
+            .run(Arc::new(Provider::new(transport.clone())))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            failed.0,
+            vec![
+                PreflightError::NoCode {
+                    name: "router".to_string(),
+                    address: router,
+                },
+                PreflightError::Decimals {
+                    symbol: "WETH",
+                    configured: 18,
+                    onchain: 8,
+                },
+                PreflightError::Balance {
+                    wallet,
+                    balance: 100.into(),
+                    required: 1_000.into(),
+                },
+            ]
+        );
+        assert!(failed.to_string().starts_with("3 startup checks failed"));
+
+        // nothing else is checked on the wrong chain
+        transport.set_response("eth_chainId", "0x1");
+        transport.clear_requests();
+        let failed = checks
+            .run(Arc::new(Provider::new(transport.clone())))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            failed.0,
+            vec![PreflightError::ChainId {
+                expected: POLYGON,
+                actual: 1,
+            }]
+        );
+        assert_eq!(transport.requests().len(), 1);
+    }
+}
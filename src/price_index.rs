@@ -0,0 +1,213 @@
+//! Mid price of every tracked token in one quote token, averaged over the
+//! venues with a direct pair and weighted by the quote side of their
+//! reserves, so a thin pool far off the market barely moves it. Kept for
+//! the last `PRICE_HISTORY` blocks for monitoring and for checking quotes
+//! against.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+use ethers::types::Address;
+use serde::Serialize;
+
+use crate::{constants::token::ERC20Token, export::ReserveRecord, utils::fixed_point::to_f64};
+
+/// blocks of prices kept
+pub const PRICE_HISTORY: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexPrice {
+    pub block: u64,
+    pub token: Address,
+    pub symbol: String,
+    /// whole quote tokens per whole token
+    pub price: f64,
+    /// quote side reserves of the venues summed, in whole quote tokens
+    pub liquidity: f64,
+    pub venues: usize,
+}
+
+/// Index prices of `block` from the reserves of `pools`, tokens without a
+/// direct pair with `quote` have none.
+pub fn index_prices(block: u64, pools: &[ReserveRecord], quote: ERC20Token) -> Vec<IndexPrice> {
+    let quote_unit = 10f64.powi(quote.get_decimals() as i32);
+    // token -> (sum of price * weight, sum of weights, venues)
+    let mut sums: BTreeMap<Address, (ERC20Token, f64, f64, usize)> = BTreeMap::new();
+    for pool in pools {
+        let (token, token_reserve, quote_reserve) = if pool.token0 == quote.get_address() {
+            (pool.token1, pool.reserve1, pool.reserve0)
+        } else if pool.token1 == quote.get_address() {
+            (pool.token0, pool.reserve0, pool.reserve1)
+        } else {
+            continue;
+        };
+        let token = match ERC20Token::from_address(token) {
+            Some(token) => token,
+            None => continue,
+        };
+        if token_reserve.is_zero() || quote_reserve.is_zero() {
+            continue;
+        }
+        let token_amount = to_f64(token_reserve) / 10f64.powi(token.get_decimals() as i32);
+        let quote_amount = to_f64(quote_reserve) / quote_unit;
+        let sum = sums
+            .entry(token.get_address())
+            .or_insert((token, 0.0, 0.0, 0));
+        sum.1 += quote_amount / token_amount * quote_amount;
+        sum.2 += quote_amount;
+        sum.3 += 1;
+    }
+    sums.into_iter()
+        .map(
+            |(address, (token, weighted, liquidity, venues))| IndexPrice {
+                block,
+                token: address,
+                symbol: token.get_symbol().to_string(),
+                price: weighted / liquidity,
+                liquidity,
+                venues,
+            },
+        )
+        .collect()
+}
+
+/// Index prices of the last `capacity` blocks.
+pub struct PriceIndex {
+    quote: ERC20Token,
+    capacity: usize,
+    /// oldest block first
+    history: Mutex<VecDeque<Vec<IndexPrice>>>,
+}
+
+impl PriceIndex {
+    pub fn new(quote: ERC20Token, capacity: usize) -> Self {
+        Self {
+            quote,
+            capacity,
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn quote(&self) -> ERC20Token {
+        self.quote
+    }
+
+    /// indexes the reserves of `pools` as of `block`, returning the prices
+    pub fn update(&self, block: u64, pools: &[ReserveRecord]) -> Vec<IndexPrice> {
+        let prices = index_prices(block, pools, self.quote);
+        let mut history = self.history.lock().unwrap();
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(prices.clone());
+        prices
+    }
+
+    pub fn latest(&self) -> Vec<IndexPrice> {
+        let history = self.history.lock().unwrap();
+        history.back().cloned().unwrap_or_default()
+    }
+
+    pub fn price(&self, token: Address) -> Option<IndexPrice> {
+        self.latest().into_iter().find(|price| price.token == token)
+    }
+
+    /// prices of `token` in the last `limit` blocks, newest first
+    pub fn history(&self, token: Address, limit: usize) -> Vec<IndexPrice> {
+        let history = self.history.lock().unwrap();
+        history
+            .iter()
+            .rev()
+            .filter_map(|prices| prices.iter().find(|price| price.token == token))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// how far `price` (whole quote tokens per whole `token`) is from the
+    /// index, in bps of the index, `None` without an index price
+    pub fn deviation_bps(&self, token: Address, price: f64) -> Option<f64> {
+        let index = self.price(token)?;
+        Some((price - index.price).abs() / index.price * 10_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+
+    use super::*;
+    use crate::constants::token::ERC20Token::{DAI, USDC, WETH};
+
+    fn pool(
+        block: u64,
+        token0: ERC20Token,
+        token1: ERC20Token,
+        reserve0: U256,
+        reserve1: U256,
+    ) -> ReserveRecord {
+        ReserveRecord {
+            block,
+            pair: Address::random(),
+            protocol: "Quickswap".to_string(),
+            token0: token0.get_address(),
+            token1: token1.get_address(),
+            reserve0,
+            reserve1,
+        }
+    }
+
+    fn units(amount: u64, token: ERC20Token) -> U256 {
+        crate::utils::fixed_point::whole_units(amount, token.get_decimals()).unwrap()
+    }
+
+    #[test]
+    fn test_index_prices() {
+        let pools = vec![
+            // 2000 USDC per WETH with 3M USDC of liquidity
+            pool(1, USDC, WETH, units(3_000_000, USDC), units(1_500, WETH)),
+            // 2200 per WETH with 1M, token order flipped
+            pool(1, WETH, USDC, units(500, WETH), units(1_100_000, USDC)),
+            // no USDC side
+            pool(1, DAI, WETH, units(2_000, DAI), units(1, WETH)),
+        ];
+        let prices = index_prices(1, &pools, USDC);
+        assert_eq!(prices.len(), 1);
+        let weth = &prices[0];
+        assert_eq!(weth.symbol, "WETH");
+        assert_eq!(weth.venues, 2);
+        assert_eq!(weth.liquidity, 4_100_000.0);
+        // (2000 * 3M + 2200 * 1.1M) / 4.1M
+        assert!((weth.price - 2053.658).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_price_index_history() {
+        let index = PriceIndex::new(USDC, 2);
+        for block in 1..=3 {
+            index.update(
+                block,
+                &[pool(
+                    block,
+                    USDC,
+                    WETH,
+                    units(2_000 * block, USDC),
+                    units(1, WETH),
+                )],
+            );
+        }
+        let history = index.history(WETH.get_address(), 10);
+        assert_eq!(
+            history.iter().map(|price| price.block).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+        assert_eq!(index.price(WETH.get_address()).unwrap().price, 6_000.0);
+        assert_eq!(
+            index.deviation_bps(WETH.get_address(), 6_060.0),
+            Some(100.0)
+        );
+        assert_eq!(index.deviation_bps(DAI.get_address(), 1.0), None);
+    }
+}
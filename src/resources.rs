@@ -0,0 +1,294 @@
+//! What each strategy costs: RPC calls, CPU time, submissions, reverts and
+//! gas spent. Providers built on a `Metered` transport count every request
+//! against the strategy whose work issued it, the one `ResourceUsage::scope`
+//! set for the running task, or the transport's default (`shared` for the
+//! background tasks that serve every strategy). CPU time is the time spent
+//! polling a scoped future, so only work run under `scope` has any.
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Write},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{JsonRpcClient, Provider, PubsubClient},
+    types::U256,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::utils::fixed_point::to_f64;
+
+/// what requests outside any strategy are counted against
+pub const SHARED: &str = "shared";
+
+tokio::task_local! {
+    static STRATEGY: &'static str;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StrategyUsage {
+    pub strategy: String,
+    pub rpc_calls: u64,
+    /// per JSON-RPC method
+    pub rpc_methods: HashMap<String, u64>,
+    pub cpu_ms: f64,
+    /// txns sent, whatever happened to them
+    pub submissions: u64,
+    pub reverts: u64,
+    /// wei of the native token paid by mined txns, reverted or not
+    pub gas_spent: U256,
+}
+
+#[derive(Debug, Default)]
+pub struct ResourceUsage {
+    strategies: Mutex<HashMap<String, StrategyUsage>>,
+}
+
+impl ResourceUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// lists `strategy` before it used anything
+    pub fn register(&self, strategy: &str) {
+        self.update(strategy, |_| {});
+    }
+
+    /// Runs `future` as work of `strategy`: requests it makes through a
+    /// `Metered` transport and the time spent polling it count against the
+    /// strategy. Tasks it spawns aren't covered, scope them too.
+    pub fn scope<F: Future>(
+        self: &Arc<Self>,
+        strategy: &'static str,
+        future: F,
+    ) -> impl Future<Output = F::Output> {
+        STRATEGY.scope(
+            strategy,
+            Timed {
+                future: Box::pin(future),
+                usage: self.clone(),
+                strategy,
+            },
+        )
+    }
+
+    /// the strategy of the running task, if it's scoped
+    pub fn current() -> Option<&'static str> {
+        STRATEGY.try_with(|strategy| *strategy).ok()
+    }
+
+    pub fn record_rpc(&self, strategy: &str, method: &str) {
+        self.update(strategy, |usage| {
+            usage.rpc_calls += 1;
+            *usage.rpc_methods.entry(method.to_string()).or_default() += 1;
+        });
+    }
+
+    pub fn record_cpu(&self, strategy: &str, elapsed: Duration) {
+        self.update(strategy, |usage| {
+            usage.cpu_ms += elapsed.as_secs_f64() * 1000.0
+        });
+    }
+
+    pub fn record_submission(&self, strategy: &str) {
+        self.update(strategy, |usage| usage.submissions += 1);
+    }
+
+    /// a txn of `strategy` was mined, paying `gas_paid`
+    pub fn record_mined(&self, strategy: &str, reverted: bool, gas_paid: U256) {
+        self.update(strategy, |usage| {
+            usage.reverts += reverted as u64;
+            usage.gas_spent += gas_paid;
+        });
+    }
+
+    /// every strategy, sorted by name
+    pub fn all(&self) -> Vec<StrategyUsage> {
+        let mut all: Vec<_> = self.strategies.lock().unwrap().values().cloned().collect();
+        all.sort_by(|a, b| a.strategy.cmp(&b.strategy));
+        all
+    }
+
+    /// the totals in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let all = self.all();
+        let mut out = String::new();
+        let mut metric =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&StrategyUsage) -> f64| {
+                let _ = writeln!(out, "# HELP tsuki_strategy_{name} {help}");
+                let _ = writeln!(out, "# TYPE tsuki_strategy_{name} {kind}");
+                for usage in &all {
+                    let _ = writeln!(
+                        out,
+                        "tsuki_strategy_{name}{{strategy=\"{}\"}} {}",
+                        usage.strategy,
+                        value(usage)
+                    );
+                }
+            };
+        metric(
+            "rpc_calls_total",
+            "counter",
+            "JSON-RPC requests sent",
+            &|usage| usage.rpc_calls as f64,
+        );
+        metric(
+            "cpu_seconds_total",
+            "counter",
+            "time spent polling strategy work",
+            &|usage| usage.cpu_ms / 1000.0,
+        );
+        metric("submissions_total", "counter", "txns sent", &|usage| {
+            usage.submissions as f64
+        });
+        metric(
+            "reverts_total",
+            "counter",
+            "txns mined reverted",
+            &|usage| usage.reverts as f64,
+        );
+        metric(
+            "gas_spent_wei_total",
+            "counter",
+            "gas paid by mined txns",
+            &|usage| to_f64(usage.gas_spent),
+        );
+        out
+    }
+
+    fn update(&self, strategy: &str, f: impl FnOnce(&mut StrategyUsage)) {
+        let mut strategies = self.strategies.lock().unwrap();
+        let usage = strategies
+            .entry(strategy.to_string())
+            .or_insert_with(|| StrategyUsage {
+                strategy: strategy.to_string(),
+                ..Default::default()
+            });
+        f(usage);
+    }
+}
+
+/// adds the time spent in `poll` to the strategy's CPU time
+struct Timed<F> {
+    future: Pin<Box<F>>,
+    usage: Arc<ResourceUsage>,
+    strategy: &'static str,
+}
+
+impl<F: Future> Future for Timed<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let start = Instant::now();
+        let poll = self.future.as_mut().poll(cx);
+        self.usage.record_cpu(self.strategy, start.elapsed());
+        poll
+    }
+}
+
+/// Transport counting every request in `usage`, against the strategy of
+/// the task making it or `default`.
+#[derive(Clone, Debug)]
+pub struct Metered<T> {
+    inner: T,
+    usage: Arc<ResourceUsage>,
+    default: &'static str,
+}
+
+impl<T> Metered<T> {
+    pub fn new(inner: T, usage: Arc<ResourceUsage>, default: &'static str) -> Self {
+        usage.register(default);
+        Self {
+            inner,
+            usage,
+            default,
+        }
+    }
+}
+
+impl<T: JsonRpcClient> Metered<T> {
+    /// a provider over `inner` counting its requests in `usage`
+    pub fn provider(inner: T, usage: Arc<ResourceUsage>, default: &'static str) -> Provider<Self> {
+        Provider::new(Self::new(inner, usage, default))
+    }
+}
+
+#[async_trait]
+impl<T: JsonRpcClient> JsonRpcClient for Metered<T> {
+    type Error = T::Error;
+
+    async fn request<P, R>(&self, method: &str, params: P) -> Result<R, T::Error>
+    where
+        P: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let strategy = ResourceUsage::current().unwrap_or(self.default);
+        self.usage.record_rpc(strategy, method);
+        self.inner.request(method, params).await
+    }
+}
+
+impl<T: PubsubClient> PubsubClient for Metered<T> {
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe<I: Into<U256>>(&self, id: I) -> Result<T::NotificationStream, T::Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe<I: Into<U256>>(&self, id: I) -> Result<(), T::Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Middleware;
+
+    use super::*;
+    use crate::utils::batch::fake::FakeTransport;
+
+    #[tokio::test]
+    async fn test_resource_usage() {
+        let usage = Arc::new(ResourceUsage::new());
+        usage.register("liquidations");
+        let transport = FakeTransport::new();
+        transport.push_response("eth_blockNumber", serde_json::json!("0x10"));
+        transport.push_response("eth_blockNumber", serde_json::json!("0x11"));
+        let provider = Metered::provider(transport, usage.clone(), SHARED);
+
+        provider.get_block_number().await.unwrap();
+        usage
+            .scope("arb", async {
+                assert_eq!(ResourceUsage::current(), Some("arb"));
+                provider.get_block_number().await.unwrap();
+            })
+            .await;
+        usage.record_submission("arb");
+        usage.record_mined("arb", true, 21_000.into());
+
+        let all = usage.all();
+        assert_eq!(
+            all.iter()
+                .map(|usage| usage.strategy.as_str())
+                .collect::<Vec<_>>(),
+            vec!["arb", "liquidations", SHARED]
+        );
+        let (arb, liquidations, shared) = (&all[0], &all[1], &all[2]);
+        assert_eq!(
+            (arb.rpc_calls, shared.rpc_calls, liquidations.rpc_calls),
+            (1, 1, 0)
+        );
+        assert_eq!(arb.rpc_methods["eth_blockNumber"], 1);
+        assert_eq!((arb.submissions, arb.reverts), (1, 1));
+        assert_eq!(arb.gas_spent, 21_000.into());
+        assert!(usage
+            .prometheus()
+            .contains("tsuki_strategy_rpc_calls_total{strategy=\"arb\"} 1\n"));
+    }
+}
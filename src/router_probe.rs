@@ -0,0 +1,229 @@
+//! Periodic probes of the V2 routers: a tiny WMATIC swap simulated through
+//! each router with `eth_call`, the probe account's balance and allowance
+//! faked with state overrides. A router that reverts it, or whose swap
+//! disagrees with its own `getAmountsOut` or with the local quote of the
+//! pair, was likely paused, upgraded or deprecated; after
+//! `failures_to_exclude` bad probes in a row its protocol stops being
+//! quoted until a probe passes again.
+
+use std::{sync::Arc, time::Duration};
+
+use enum_map::EnumMap;
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, Bytes, U256},
+};
+use log::{error, info};
+
+use crate::{
+    constants::{protocol::UniswapV2, token::ERC20Token},
+    uniswapV2::IUniswapV2Router02,
+    utils::batch::state_override::StateOverride,
+};
+
+/// swaps are simulated from here, it holds nothing on chain
+pub const PROBE_ACCOUNT: Address = Address::repeat_byte(0x7e);
+
+/// WMATIC storage layout, that of WETH9
+const WMATIC_BALANCES_SLOT: u64 = 3;
+const WMATIC_ALLOWANCES_SLOT: u64 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeConfig {
+    pub interval: Duration,
+    /// WMATIC swapped, small enough not to move the price
+    pub amount_in: U256,
+    /// what the WMATIC is swapped for, needs a pair on every protocol
+    pub token_out: ERC20Token,
+    /// largest difference between the swap and either quote
+    pub tolerance_bps: u64,
+    /// bad probes in a row before the protocol is excluded
+    pub failures_to_exclude: u32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(300),
+            amount_in: U256::exp10(16),
+            token_out: ERC20Token::USDC,
+            tolerance_bps: 50,
+            failures_to_exclude: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    Healthy,
+    Reverted(String),
+    /// amounts out of the simulated swap, `getAmountsOut` and the local
+    /// quote
+    Inconsistent {
+        swap: U256,
+        quote: U256,
+        local: U256,
+    },
+}
+
+/// whether `swap` is within `tolerance_bps` of both `quote` and `local`
+pub fn judge(swap: U256, quote: U256, local: U256, tolerance_bps: u64) -> ProbeOutcome {
+    let within = |expected: U256| {
+        let diff = if swap > expected {
+            swap - expected
+        } else {
+            expected - swap
+        };
+        diff.saturating_mul(U256::from(10_000)) <= expected.saturating_mul(tolerance_bps.into())
+    };
+    match !swap.is_zero() && within(quote) && within(local) {
+        true => ProbeOutcome::Healthy,
+        false => ProbeOutcome::Inconsistent { swap, quote, local },
+    }
+}
+
+/// Simulates swapping `config.amount_in` WMATIC for `config.token_out`
+/// through `protocol`'s router, `local` being what the local reserves
+/// quote for it.
+pub async fn probe_router<M: Middleware>(
+    provider: Arc<M>,
+    protocol: UniswapV2,
+    config: &ProbeConfig,
+    local: U256,
+) -> ProbeOutcome {
+    let wmatic = ERC20Token::WMATIC.get_address();
+    let path = vec![wmatic, config.token_out.get_address()];
+    let router = IUniswapV2Router02::new(protocol.get_router_address(), provider.clone());
+    let quote = match router
+        .get_amounts_out(config.amount_in, path.clone())
+        .call()
+        .await
+    {
+        Ok(amounts) => amounts.last().copied().unwrap_or_default(),
+        Err(e) => return ProbeOutcome::Reverted(format!("getAmountsOut: {}", e)),
+    };
+
+    let overrides = StateOverride::new()
+        .erc20_balance(
+            wmatic,
+            PROBE_ACCOUNT,
+            config.amount_in,
+            WMATIC_BALANCES_SLOT.into(),
+        )
+        .erc20_allowance(
+            wmatic,
+            PROBE_ACCOUNT,
+            router.address(),
+            config.amount_in,
+            WMATIC_ALLOWANCES_SLOT.into(),
+        );
+    let swap = router
+        .swap_exact_tokens_for_tokens(
+            config.amount_in,
+            U256::zero(),
+            path,
+            PROBE_ACCOUNT,
+            U256::MAX,
+        )
+        .from(PROBE_ACCOUNT);
+    let output: Bytes = match provider
+        .provider()
+        .request("eth_call", (&swap.tx, BlockNumber::Latest, &overrides))
+        .await
+    {
+        Ok(output) => output,
+        Err(e) => return ProbeOutcome::Reverted(format!("swapExactTokensForTokens: {}", e)),
+    };
+    let amounts: Vec<U256> = match router.decode_output("swapExactTokensForTokens", output) {
+        Ok(amounts) => amounts,
+        Err(e) => return ProbeOutcome::Reverted(format!("swapExactTokensForTokens: {}", e)),
+    };
+    judge(
+        amounts.last().copied().unwrap_or_default(),
+        quote,
+        local,
+        config.tolerance_bps,
+    )
+}
+
+/// Bad probes in a row per protocol and which are excluded.
+#[derive(Debug, Default)]
+pub struct RouterHealth {
+    failures: EnumMap<UniswapV2, u32>,
+    excluded: EnumMap<UniswapV2, bool>,
+}
+
+impl RouterHealth {
+    /// Counts `outcome` for `protocol`, returning whether it should be
+    /// excluded now if that changed.
+    pub fn record(
+        &mut self,
+        protocol: UniswapV2,
+        outcome: &ProbeOutcome,
+        failures_to_exclude: u32,
+    ) -> Option<bool> {
+        let excluded = match outcome {
+            ProbeOutcome::Healthy => {
+                self.failures[protocol] = 0;
+                false
+            }
+            _ => {
+                self.failures[protocol] += 1;
+                self.excluded[protocol] || self.failures[protocol] >= failures_to_exclude
+            }
+        };
+        if excluded == self.excluded[protocol] {
+            return None;
+        }
+        self.excluded[protocol] = excluded;
+        match excluded {
+            true => error!(
+                "{} router failed {} probes in a row ({:?}), not trading through it",
+                protocol.get_name(),
+                self.failures[protocol],
+                outcome
+            ),
+            false => info!("{} router is healthy again", protocol.get_name()),
+        }
+        Some(excluded)
+    }
+
+    pub fn is_excluded(&self, protocol: UniswapV2) -> bool {
+        self.excluded[protocol]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_router_health() {
+        let out = U256::from(1_000_000);
+        assert_eq!(judge(out, out, out + 4_000, 50), ProbeOutcome::Healthy);
+        // a router quoting more than it swaps took a fee it didn't have
+        assert!(matches!(
+            judge(out, out * 2, out, 50),
+            ProbeOutcome::Inconsistent { .. }
+        ));
+        assert!(matches!(
+            judge(U256::zero(), U256::zero(), U256::zero(), 50),
+            ProbeOutcome::Inconsistent { .. }
+        ));
+
+        let mut health = RouterHealth::default();
+        let reverted = ProbeOutcome::Reverted("paused".to_string());
+        assert_eq!(health.record(UniswapV2::APESWAP, &reverted, 2), None);
+        assert!(!health.is_excluded(UniswapV2::APESWAP));
+        assert_eq!(health.record(UniswapV2::APESWAP, &reverted, 2), Some(true));
+        assert_eq!(health.record(UniswapV2::APESWAP, &reverted, 2), None);
+        assert!(health.is_excluded(UniswapV2::APESWAP));
+        assert!(!health.is_excluded(UniswapV2::QUICKSWAP));
+        // one passing probe is enough to trade again
+        assert_eq!(
+            health.record(UniswapV2::APESWAP, &ProbeOutcome::Healthy, 2),
+            Some(false)
+        );
+        assert_eq!(health.record(UniswapV2::APESWAP, &reverted, 2), None);
+    }
+}
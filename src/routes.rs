@@ -0,0 +1,236 @@
+//! Routes to check for arbitrage, loaded from a json file of templates:
+//!
+//! ```json
+//! [
+//!     { "path": ["STABLE", "WETH", "STABLE"], "amounts": [10000, 1000] },
+//!     { "path": ["*", "WMATIC", "*"], "amounts": [300] }
+//! ]
+//! ```
+//!
+//! A path position is a token symbol, `STABLE` for any of USDC, USDT and
+//! DAI, or `*` for any token. Templates expand against every token at load
+//! time into the cyclic paths they match, the same token never twice in a
+//! row. Amounts are whole units of the path's first token.
+
+use std::{fs, io, path::Path};
+
+use ethers::types::U256;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    constants::token::ERC20Token::{self, *},
+    utils::fixed_point::whole_units,
+};
+
+const STABLES: [ERC20Token; 3] = [USDC, USDT, DAI];
+
+#[derive(Debug, Error)]
+pub enum RouteConfigError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("unknown token {0}")]
+    UnknownToken(String),
+
+    #[error("route template {0} has fewer than 3 tokens")]
+    TooShort(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub amount_in: U256,
+    pub token_path: Vec<ERC20Token>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenPattern {
+    Token(ERC20Token),
+    Stable,
+    Any,
+}
+
+impl TokenPattern {
+    pub fn parse(pattern: &str) -> Result<Self, RouteConfigError> {
+        match pattern {
+            "*" => Ok(TokenPattern::Any),
+            _ if pattern.eq_ignore_ascii_case("STABLE") => Ok(TokenPattern::Stable),
+            _ => ERC20Token::from_symbol(pattern)
+                .map(TokenPattern::Token)
+                .ok_or_else(|| RouteConfigError::UnknownToken(pattern.to_string())),
+        }
+    }
+
+    pub fn matches(self, token: ERC20Token) -> bool {
+        match self {
+            TokenPattern::Token(pattern) => pattern == token,
+            TokenPattern::Stable => STABLES.contains(&token),
+            TokenPattern::Any => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawTemplate {
+    path: Vec<String>,
+    amounts: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteTemplate {
+    pub path: Vec<TokenPattern>,
+    /// whole units of the first token
+    pub amounts: Vec<u64>,
+}
+
+impl RouteTemplate {
+    fn from_raw(raw: RawTemplate) -> Result<Self, RouteConfigError> {
+        if raw.path.len() < 3 {
+            return Err(RouteConfigError::TooShort(raw.path.join(",")));
+        }
+        Ok(Self {
+            path: raw
+                .path
+                .iter()
+                .map(|pattern| TokenPattern::parse(pattern))
+                .collect::<Result<_, _>>()?,
+            amounts: raw.amounts,
+        })
+    }
+
+    /// every cyclic path of `tokens` the template matches
+    pub fn token_paths(&self, tokens: &[ERC20Token]) -> Vec<Vec<ERC20Token>> {
+        let mut paths: Vec<Vec<ERC20Token>> = vec![vec![]];
+        for pattern in &self.path {
+            let mut extended = Vec::new();
+            for path in &paths {
+                for token in tokens {
+                    if pattern.matches(*token) && path.last() != Some(token) {
+                        let mut path = path.clone();
+                        path.push(*token);
+                        extended.push(path);
+                    }
+                }
+            }
+            paths = extended;
+        }
+        paths.retain(|path| path.first() == path.last());
+        paths
+    }
+
+    pub fn expand(&self, tokens: &[ERC20Token]) -> Vec<Route> {
+        let paths = self.token_paths(tokens);
+        self.amounts
+            .iter()
+            .flat_map(|amount| {
+                paths.iter().filter_map(move |path| {
+                    Some(Route {
+                        amount_in: whole_units(*amount, path[0].get_decimals())?,
+                        token_path: path.clone(),
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parses templates from json, see the module docs for the format.
+pub fn parse_templates(json: &str) -> Result<Vec<RouteTemplate>, RouteConfigError> {
+    serde_json::from_str::<Vec<RawTemplate>>(json)?
+        .into_iter()
+        .map(RouteTemplate::from_raw)
+        .collect()
+}
+
+/// Every route the templates in `path` expand to over `tokens`, in template
+/// then amount order. Duplicates across templates are dropped.
+pub fn load_routes(
+    path: impl AsRef<Path>,
+    tokens: &[ERC20Token],
+) -> Result<Vec<Route>, RouteConfigError> {
+    let templates = parse_templates(&fs::read_to_string(path)?)?;
+    let mut routes: Vec<Route> = Vec::new();
+    for route in templates
+        .iter()
+        .flat_map(|template| template.expand(tokens))
+    {
+        if !routes.contains(&route) {
+            routes.push(route);
+        }
+    }
+    Ok(routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKENS: [ERC20Token; 6] = [USDC, USDT, DAI, WBTC, WMATIC, WETH];
+
+    #[test]
+    fn test_parse_templates() {
+        let templates =
+            parse_templates(r#"[{ "path": ["stable", "WETH", "*"], "amounts": [1] }]"#).unwrap();
+        assert_eq!(
+            templates[0].path,
+            vec![
+                TokenPattern::Stable,
+                TokenPattern::Token(WETH),
+                TokenPattern::Any
+            ]
+        );
+        assert!(matches!(
+            parse_templates(r#"[{ "path": ["USDC", "PEPE", "USDC"], "amounts": [1] }]"#),
+            Err(RouteConfigError::UnknownToken(symbol)) if symbol == "PEPE"
+        ));
+        assert!(matches!(
+            parse_templates(r#"[{ "path": ["USDC", "USDC"], "amounts": [1] }]"#),
+            Err(RouteConfigError::TooShort(_))
+        ));
+    }
+
+    #[test]
+    fn test_token_paths() {
+        let template =
+            parse_templates(r#"[{ "path": ["STABLE", "WETH", "STABLE"], "amounts": [] }]"#)
+                .unwrap()
+                .remove(0);
+        assert_eq!(
+            template.token_paths(&TOKENS),
+            vec![
+                vec![USDC, WETH, USDC],
+                vec![USDT, WETH, USDT],
+                vec![DAI, WETH, DAI]
+            ]
+        );
+
+        // the pinned token can't also stand in for a wildcard next to it
+        let template = parse_templates(r#"[{ "path": ["*", "WMATIC", "*"], "amounts": [] }]"#)
+            .unwrap()
+            .remove(0);
+        let paths = template.token_paths(&TOKENS);
+        assert_eq!(paths.len(), 5);
+        assert!(paths
+            .iter()
+            .all(|path| path[0] == path[2] && path[0] != WMATIC));
+    }
+
+    #[test]
+    fn test_expand() {
+        let template = RouteTemplate {
+            path: vec![
+                TokenPattern::Token(USDC),
+                TokenPattern::Token(WETH),
+                TokenPattern::Token(USDC),
+            ],
+            amounts: vec![10000, 300],
+        };
+        let routes = template.expand(&TOKENS);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].amount_in, U256::from(10_000_000_000u64));
+        assert_eq!(routes[1].amount_in, U256::from(300_000_000u64));
+    }
+}
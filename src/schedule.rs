@@ -0,0 +1,288 @@
+//! When each strategy runs, from a json file shared by the binaries:
+//!
+//! ```json
+//! {
+//!     "arb": { "trigger": "pool_updates" },
+//!     "liquidations": {
+//!         "trigger": "mempool",
+//!         "paused": [{ "days": ["tue"], "from": "02:00", "to": "04:30" }]
+//!     }
+//! }
+//! ```
+//!
+//! A strategy runs on its `trigger` (`blocks`, every block; `pool_updates`,
+//! only blocks that changed a tracked pool; `mempool`, pending
+//! transactions) while inside one of its `active` windows (always without
+//! any) and outside all of its `paused` ones. Windows are UTC times of day,
+//! on the listed `days` or every day, and wrap past midnight when `to` is
+//! before `from`. Strategies missing from the file run on their default
+//! trigger at all times.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+pub const DEFAULT_SCHEDULES: &str = "data/schedules.json";
+
+/// names strategies are scheduled under
+pub const ARB: &str = "arb";
+pub const LIQUIDATIONS: &str = "liquidations";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("strategy {strategy} can't run on {trigger:?}")]
+    UnsupportedTrigger { strategy: String, trigger: Trigger },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Blocks,
+    PoolUpdates,
+    Mempool,
+}
+
+impl Trigger {
+    /// whether a strategy on this trigger runs on `event`, every block
+    /// includes the ones that updated pools
+    pub fn fires_on(self, event: Trigger) -> bool {
+        self == event || (self == Trigger::Blocks && event == Trigger::PoolUpdates)
+    }
+}
+
+/// minutes since midnight UTC, parsed from "HH:MM"
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    });
+    parsed.ok_or_else(|| serde::de::Error::custom(format!("invalid time of day {}", time)))
+}
+
+/// days of the week as 0 for monday to 6 for sunday
+fn days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|day| {
+            DAYS.iter()
+                .position(|name| day.to_ascii_lowercase().starts_with(name))
+                .map(|day| day as u32)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown day {}", day)))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Window {
+    /// every day when empty
+    #[serde(default, deserialize_with = "days")]
+    pub days: Vec<u32>,
+    #[serde(deserialize_with = "time_of_day")]
+    pub from: u32,
+    #[serde(deserialize_with = "time_of_day")]
+    pub to: u32,
+}
+
+impl Window {
+    /// whether unix time `now` is in the window, a window wrapping past
+    /// midnight belongs to the day it starts on
+    pub fn contains(&self, now: u64) -> bool {
+        let minute = ((now % DAY_SECS) / 60) as u32;
+        // 1970-01-01 was a thursday
+        let today = ((now / DAY_SECS + 3) % 7) as u32;
+        let on = |day: u32| self.days.is_empty() || self.days.contains(&day);
+        if self.from <= self.to {
+            on(today) && self.from <= minute && minute < self.to
+        } else {
+            (on(today) && minute >= self.from) || (on((today + 6) % 7) && minute < self.to)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Schedule {
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub active: Vec<Window>,
+    #[serde(default)]
+    pub paused: Vec<Window>,
+}
+
+impl Schedule {
+    pub fn always(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            active: vec![],
+            paused: vec![],
+        }
+    }
+
+    /// whether the strategy may run at unix time `now`, whatever the event
+    pub fn is_open(&self, now: u64) -> bool {
+        (self.active.is_empty() || self.active.iter().any(|window| window.contains(now)))
+            && !self.paused.iter().any(|window| window.contains(now))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Schedules(HashMap<String, Schedule>);
+
+impl Schedules {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScheduleError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// like `load`, but a missing file schedules nothing
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, ScheduleError> {
+        match Self::load(path) {
+            Err(ScheduleError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            schedules => schedules,
+        }
+    }
+
+    /// The gate of strategy `name`, which can react to the `supported`
+    /// events, running on the first of them when not scheduled.
+    pub fn gate(&self, name: &str, supported: &[Trigger]) -> Result<StrategyGate, ScheduleError> {
+        let schedule = match self.0.get(name) {
+            Some(schedule) => schedule.clone(),
+            None => Schedule::always(supported[0]),
+        };
+        if !supported
+            .iter()
+            .any(|&event| schedule.trigger.fires_on(event))
+        {
+            return Err(ScheduleError::UnsupportedTrigger {
+                strategy: name.to_string(),
+                trigger: schedule.trigger,
+            });
+        }
+        Ok(StrategyGate::new(name, schedule))
+    }
+}
+
+/// Enforces a strategy's schedule, the runner asks it before every run.
+pub struct StrategyGate {
+    name: String,
+    schedule: Schedule,
+    /// for logging when windows open and close
+    open: AtomicBool,
+}
+
+impl StrategyGate {
+    pub fn new(name: &str, schedule: Schedule) -> Self {
+        Self {
+            name: name.to_string(),
+            schedule,
+            open: AtomicBool::new(true),
+        }
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// whether the strategy runs on `event` at unix time `now`
+    pub fn admits(&self, event: Trigger, now: u64) -> bool {
+        let open = self.schedule.is_open(now);
+        if self.open.swap(open, Ordering::Relaxed) != open {
+            match open {
+                true => info!("Strategy {} resumed", self.name),
+                false => info!("Strategy {} paused by its schedule", self.name),
+            }
+        }
+        open && self.schedule.trigger.fires_on(event)
+    }
+
+    /// `admits` at the current time
+    pub fn admits_now(&self, event: Trigger) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.admits(event, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // monday 2024-01-01 00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+
+    fn at(day: u64, hours: u64, minutes: u64) -> u64 {
+        MONDAY + day * DAY_SECS + hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn test_windows() {
+        let schedules: Schedules = serde_json::from_str(
+            r#"{
+                "liquidations": {
+                    "trigger": "mempool",
+                    "paused": [{ "days": ["Tue"], "from": "02:00", "to": "04:30" }]
+                },
+                "night": {
+                    "trigger": "blocks",
+                    "active": [{ "days": ["sun"], "from": "22:00", "to": "06:00" }]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let gate = schedules.gate(LIQUIDATIONS, &[Trigger::Mempool]).unwrap();
+        assert!(gate.admits(Trigger::Mempool, at(1, 1, 59)));
+        assert!(!gate.admits(Trigger::Mempool, at(1, 2, 0)));
+        assert!(!gate.admits(Trigger::Mempool, at(1, 4, 29)));
+        assert!(gate.admits(Trigger::Mempool, at(1, 4, 30)));
+        // other days
+        assert!(gate.admits(Trigger::Mempool, at(0, 3, 0)));
+
+        // wraps into monday
+        let night = &schedules.0["night"];
+        assert!(night.is_open(at(6, 23, 0)));
+        assert!(night.is_open(at(7, 5, 59)));
+        assert!(!night.is_open(at(7, 6, 0)));
+        assert!(!night.is_open(at(0, 23, 0)));
+    }
+
+    #[test]
+    fn test_triggers() {
+        let schedules: Schedules =
+            serde_json::from_str(r#"{ "arb": { "trigger": "pool_updates" } }"#).unwrap();
+        let supported = [Trigger::Blocks, Trigger::PoolUpdates];
+        let gate = schedules.gate(ARB, &supported).unwrap();
+        assert!(gate.admits(Trigger::PoolUpdates, MONDAY));
+        assert!(!gate.admits(Trigger::Blocks, MONDAY));
+
+        // not scheduled, every block
+        let gate = Schedules::default().gate(ARB, &supported).unwrap();
+        assert!(gate.admits(Trigger::Blocks, MONDAY));
+        assert!(gate.admits(Trigger::PoolUpdates, MONDAY));
+
+        assert!(schedules.gate(ARB, &[Trigger::Mempool]).is_err());
+        assert!(serde_json::from_str::<Schedules>(
+            r#"{ "arb": { "trigger": "blocks", "paused": [{ "from": "25:00", "to": "01:00" }] } }"#
+        )
+        .is_err());
+    }
+}
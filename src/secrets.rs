@@ -0,0 +1,252 @@
+//! Private keys and API tokens, read through a reference instead of being
+//! pasted into `.env` or flags. A value of `PRIVATE_KEY`, an RPC url or
+//! any other secret setting can be:
+//!
+//! - `env:NAME`: the environment variable `NAME`
+//! - `file:path/to/secret.json`: an encrypted Web3 Secret Storage file (the
+//!   keystore format geth and foundry write), decrypted with the passphrase
+//!   in `SECRETS_PASSPHRASE`. Create one with `secrets encrypt`.
+//! - `keyring:name`: entry `name` of the OS keyring under the service in
+//!   `SECRETS_KEYRING_SERVICE` (`tsuki` by default), read with
+//!   `secret-tool` on Linux and `security` on macOS
+//!
+//! Anything else is the secret itself, so existing settings keep working.
+
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ethers::utils::hex;
+use thiserror::Error;
+
+pub const PASSPHRASE_ENV: &str = "SECRETS_PASSPHRASE";
+pub const KEYRING_SERVICE_ENV: &str = "SECRETS_KEYRING_SERVICE";
+pub const DEFAULT_KEYRING_SERVICE: &str = "tsuki";
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("environment variable {0} is not set")]
+    MissingEnv(String),
+
+    #[error("{PASSPHRASE_ENV} must be set to decrypt {}", .0.display())]
+    MissingPassphrase(PathBuf),
+
+    #[error("can't decrypt {}: {message}", path.display())]
+    Decrypt { path: PathBuf, message: String },
+
+    #[error("can't encrypt {}: {message}", path.display())]
+    Encrypt { path: PathBuf, message: String },
+
+    #[error("keyring has no {name} under {service}: {message}")]
+    Keyring {
+        service: String,
+        name: String,
+        message: String,
+    },
+}
+
+/// Where secrets of one `scheme:` come from, `name` is what follows it.
+pub trait SecretBackend: Send + Sync {
+    fn fetch(&self, name: &str) -> Result<String, SecretError>;
+}
+
+pub struct EnvBackend;
+
+impl SecretBackend for EnvBackend {
+    fn fetch(&self, name: &str) -> Result<String, SecretError> {
+        env::var(name).map_err(|_| SecretError::MissingEnv(name.to_string()))
+    }
+}
+
+/// Encrypted files, `name` is the path.
+pub struct KeystoreBackend {
+    passphrase: Option<String>,
+}
+
+impl KeystoreBackend {
+    pub fn new(passphrase: Option<String>) -> Self {
+        Self { passphrase }
+    }
+}
+
+impl SecretBackend for KeystoreBackend {
+    /// text secrets as written, raw keys (as in wallet keystores) 0x hex
+    fn fetch(&self, name: &str) -> Result<String, SecretError> {
+        let path = PathBuf::from(name);
+        let passphrase = match &self.passphrase {
+            Some(passphrase) => passphrase,
+            None => return Err(SecretError::MissingPassphrase(path)),
+        };
+        let bytes =
+            eth_keystore::decrypt_key(&path, passphrase).map_err(|e| SecretError::Decrypt {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+        Ok(String::from_utf8(bytes).unwrap_or_else(|e| format!("0x{}", hex::encode(e.as_bytes()))))
+    }
+}
+
+/// The OS keyring through its command line tool.
+pub struct KeyringBackend {
+    service: String,
+}
+
+impl KeyringBackend {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+}
+
+impl SecretBackend for KeyringBackend {
+    fn fetch(&self, name: &str) -> Result<String, SecretError> {
+        let mut command = match cfg!(target_os = "macos") {
+            true => {
+                let mut command = Command::new("security");
+                command.args([
+                    "find-generic-password",
+                    "-s",
+                    &self.service,
+                    "-a",
+                    name,
+                    "-w",
+                ]);
+                command
+            }
+            false => {
+                let mut command = Command::new("secret-tool");
+                command.args(["lookup", "service", &self.service, "account", name]);
+                command
+            }
+        };
+        let error = |message: String| SecretError::Keyring {
+            service: self.service.clone(),
+            name: name.to_string(),
+            message,
+        };
+        let output = command.output().map_err(|e| error(e.to_string()))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(error(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        let secret = String::from_utf8(output.stdout).map_err(|e| error(e.to_string()))?;
+        Ok(secret.trim_end_matches('\n').to_string())
+    }
+}
+
+/// Resolves secret references through a backend per scheme.
+pub struct Secrets {
+    backends: HashMap<String, Box<dyn SecretBackend>>,
+}
+
+impl Secrets {
+    /// no backends, every value is literal
+    pub fn empty() -> Self {
+        Self {
+            backends: HashMap::new(),
+        }
+    }
+
+    /// `env:`, `file:` and `keyring:`, configured from the environment
+    pub fn from_env() -> Self {
+        let service =
+            env::var(KEYRING_SERVICE_ENV).unwrap_or_else(|_| DEFAULT_KEYRING_SERVICE.to_string());
+        Self::empty()
+            .with_backend("env", EnvBackend)
+            .with_backend("file", KeystoreBackend::new(env::var(PASSPHRASE_ENV).ok()))
+            .with_backend("keyring", KeyringBackend::new(service))
+    }
+
+    pub fn with_backend(mut self, scheme: &str, backend: impl SecretBackend + 'static) -> Self {
+        self.backends.insert(scheme.to_string(), Box::new(backend));
+        self
+    }
+
+    /// the secret `reference` points to, or `reference` itself if it
+    /// doesn't start with a known scheme
+    pub fn resolve(&self, reference: &str) -> Result<String, SecretError> {
+        match reference
+            .split_once(':')
+            .and_then(|(scheme, name)| Some((self.backends.get(scheme)?, name)))
+        {
+            Some((backend, name)) => backend.fetch(name),
+            None => Ok(reference.to_string()),
+        }
+    }
+
+    /// the secret environment variable `var` holds or points to
+    pub fn resolve_env(&self, var: &str) -> Result<String, SecretError> {
+        let reference = env::var(var).map_err(|_| SecretError::MissingEnv(var.to_string()))?;
+        self.resolve(&reference)
+    }
+}
+
+/// Writes `secret` to `path` encrypted with `passphrase`, for `file:`.
+pub fn encrypt_to_file(
+    path: impl AsRef<Path>,
+    secret: &str,
+    passphrase: &str,
+) -> Result<(), SecretError> {
+    let path = path.as_ref();
+    let error = |message: String| SecretError::Encrypt {
+        path: path.to_path_buf(),
+        message,
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| error("not a file path".to_string()))?;
+    eth_keystore::encrypt_key(dir, &mut rand::thread_rng(), secret, passphrase, Some(name))
+        .map_err(|e| error(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let path = env::temp_dir().join(format!("secret-{}.json", std::process::id()));
+        let key = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        encrypt_to_file(&path, key, "hunter2").unwrap();
+        env::set_var("TSUKI_TEST_SECRET", "an api token");
+
+        let secrets = Secrets::empty()
+            .with_backend("env", EnvBackend)
+            .with_backend("file", KeystoreBackend::new(Some("hunter2".to_string())));
+        let reference = format!("file:{}", path.display());
+        assert_eq!(secrets.resolve(&reference).unwrap(), key);
+        assert_eq!(
+            secrets.resolve("env:TSUKI_TEST_SECRET").unwrap(),
+            "an api token"
+        );
+        // urls and plain keys are literal
+        assert_eq!(
+            secrets.resolve("wss://polygon.example/v2/abc").unwrap(),
+            "wss://polygon.example/v2/abc"
+        );
+        assert_eq!(secrets.resolve(key).unwrap(), key);
+        assert!(matches!(
+            secrets.resolve("env:TSUKI_TEST_UNSET"),
+            Err(SecretError::MissingEnv(_))
+        ));
+
+        let wrong = Secrets::empty()
+            .with_backend("file", KeystoreBackend::new(Some("hunter3".to_string())));
+        assert!(matches!(
+            wrong.resolve(&reference),
+            Err(SecretError::Decrypt { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+//! Execution shadowing: what every hop of an executed arb returned on-chain,
+//! read from the Swap logs of its receipt, against what it was quoted.
+//! Venues whose quotes are systematically optimistic (a transfer tax the
+//! quote misses, V3 quotes off a tick that moved) build up a bias, and
+//! their later quotes are discounted by it so profit checks and per hop
+//! minimums are set against what they actually deliver.
+
+use std::collections::HashMap;
+
+use ethers::types::{TransactionReceipt, I256, U256};
+use serde::Serialize;
+
+use crate::{
+    activity::{V2_SWAP, V3_SWAP},
+    utils::fixed_point::to_f64,
+    world::Protocol,
+};
+
+const PPM: u64 = 1_000_000;
+
+/// amount out of every swap in `receipt`, in log order
+pub fn swap_amounts_out(receipt: &TransactionReceipt) -> Vec<U256> {
+    let word = |data: &[u8], i: usize| U256::from_big_endian(&data[i * 32..(i + 1) * 32]);
+    receipt
+        .logs
+        .iter()
+        .filter_map(|log| {
+            let topic0 = *log.topics.first()?;
+            if topic0 == *V2_SWAP && log.data.len() >= 128 {
+                // amount0In, amount1In, amount0Out, amount1Out
+                Some(word(&log.data, 2).max(word(&log.data, 3)))
+            } else if topic0 == *V3_SWAP && log.data.len() >= 64 {
+                // signed pool deltas, what left the pool is negative
+                let (amount0, amount1) = (
+                    I256::from_raw(word(&log.data, 0)),
+                    I256::from_raw(word(&log.data, 1)),
+                );
+                Some(amount0.min(amount1).abs().into_raw())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HopOutcome {
+    pub protocol: String,
+    pub quoted_in: U256,
+    pub quoted_out: U256,
+    pub realized_in: U256,
+    pub realized_out: U256,
+}
+
+impl HopOutcome {
+    /// how much worse the realized rate was than the quoted one, in bps,
+    /// negative when it was better
+    pub fn shortfall_bps(&self) -> f64 {
+        if self.quoted_out.is_zero() || self.realized_in.is_zero() {
+            return 0.0;
+        }
+        let quoted_rate = to_f64(self.quoted_out) / to_f64(self.quoted_in);
+        let realized_rate = to_f64(self.realized_out) / to_f64(self.realized_in);
+        (1.0 - realized_rate / quoted_rate) * 10_000.0
+    }
+}
+
+/// Hops of an executed route against `quoted`, the quoted amount out of
+/// every hop. `None` if the receipt doesn't have one swap per hop.
+pub fn hop_outcomes(
+    receipt: &TransactionReceipt,
+    amount_in: U256,
+    protocols: &[Protocol],
+    quoted: &[U256],
+) -> Option<Vec<HopOutcome>> {
+    let realized = swap_amounts_out(receipt);
+    if realized.len() != protocols.len() || quoted.len() != protocols.len() {
+        return None;
+    }
+    let ins = |outs: &[U256]| {
+        std::iter::once(amount_in)
+            .chain(outs.iter().copied())
+            .collect::<Vec<_>>()
+    };
+    let (quoted_ins, realized_ins) = (ins(quoted), ins(&realized));
+    Some(
+        protocols
+            .iter()
+            .enumerate()
+            .map(|(i, protocol)| HopOutcome {
+                protocol: protocol.to_string(),
+                quoted_in: quoted_ins[i],
+                quoted_out: quoted[i],
+                realized_in: realized_ins[i],
+                realized_out: realized[i],
+            })
+            .collect(),
+    )
+}
+
+/// what the route returned on top of what went into its first hop, 0 if
+/// it returned less
+pub fn realized_profit(hops: &[HopOutcome]) -> U256 {
+    match (hops.first(), hops.last()) {
+        (Some(first), Some(last)) => last.realized_out.saturating_sub(first.realized_in),
+        _ => U256::zero(),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QuoteBiasConfig {
+    /// weight of the latest hop in the moving average
+    pub alpha: f64,
+    /// hops seen before a venue's quotes are discounted
+    pub min_samples: u64,
+    /// most a venue's quotes are discounted
+    pub max_bias_bps: f64,
+}
+
+impl Default for QuoteBiasConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 0.2,
+            min_samples: 3,
+            max_bias_bps: 500.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Bias {
+    /// moving average of the shortfall
+    pub bps: f64,
+    pub samples: u64,
+}
+
+/// Quote bias per venue (`Protocol` as displayed, so per V3 fee tier).
+pub struct QuoteBias {
+    config: QuoteBiasConfig,
+    biases: HashMap<String, Bias>,
+}
+
+impl QuoteBias {
+    pub fn new(config: QuoteBiasConfig) -> Self {
+        Self {
+            config,
+            biases: HashMap::new(),
+        }
+    }
+
+    /// adds an executed hop, returning the venue's new bias
+    pub fn record(&mut self, hop: &HopOutcome) -> Bias {
+        let shortfall = hop.shortfall_bps();
+        let bias = self.biases.entry(hop.protocol.clone()).or_default();
+        bias.bps = match bias.samples {
+            0 => shortfall,
+            _ => bias.bps + self.config.alpha * (shortfall - bias.bps),
+        };
+        bias.samples += 1;
+        *bias
+    }
+
+    pub fn bias(&self, protocol: &Protocol) -> Bias {
+        self.biases
+            .get(&protocol.to_string())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// what the quotes of `protocol` are discounted by, only optimism is
+    /// corrected
+    pub fn discount_bps(&self, protocol: &Protocol) -> f64 {
+        let bias = self.bias(protocol);
+        if bias.samples < self.config.min_samples {
+            return 0.0;
+        }
+        bias.bps.clamp(0.0, self.config.max_bias_bps)
+    }
+
+    /// `amounts_out` of a route through `protocols` with every hop
+    /// discounted, a discount carrying over to the hops after it
+    pub fn adjust(&self, protocols: &[Protocol], amounts_out: &[U256]) -> Vec<U256> {
+        let mut factor = U256::from(PPM);
+        protocols
+            .iter()
+            .zip(amounts_out)
+            .map(|(protocol, amount_out)| {
+                let discount = (self.discount_bps(protocol) * 100.0) as u64;
+                factor = factor * (PPM - discount) / PPM;
+                *amount_out * factor / PPM
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Bytes, Log};
+
+    use super::*;
+    use crate::constants::protocol::UniswapV2;
+
+    fn swap_log(topic: ethers::types::H256, words: &[I256]) -> Log {
+        let mut data = Vec::new();
+        for word in words {
+            let mut bytes = [0u8; 32];
+            word.into_raw().to_big_endian(&mut bytes);
+            data.extend_from_slice(&bytes);
+        }
+        Log {
+            topics: vec![topic],
+            data: Bytes::from(data),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hop_outcomes() {
+        let quickswap = Protocol::UniswapV2(UniswapV2::QUICKSWAP);
+        let v3 = Protocol::UniswapV3 { fee: 500 };
+        let receipt = TransactionReceipt {
+            logs: vec![
+                // 1000 in, 495 out as token1
+                swap_log(*V2_SWAP, &[1000.into(), 0.into(), 0.into(), 495.into()]),
+                // 495 in as token1, 990 out as token0
+                swap_log(*V3_SWAP, &[I256::from(-990), 495.into()]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(swap_amounts_out(&receipt), vec![495.into(), 990.into()]);
+
+        let quoted = [500.into(), 1000.into()];
+        let hops = hop_outcomes(&receipt, 1000.into(), &[quickswap, v3], &quoted).unwrap();
+        // 1% short of the quoted rate
+        assert!((hops[0].shortfall_bps() - 100.0).abs() < 1e-6);
+        // same rate as quoted on what it got
+        assert!(hops[1].shortfall_bps().abs() < 1e-6);
+        // 990 back for 1000 in
+        assert_eq!(realized_profit(&hops), U256::zero());
+        let hops = hop_outcomes(&receipt, 900.into(), &[quickswap, v3], &quoted).unwrap();
+        assert_eq!(realized_profit(&hops), U256::from(90));
+        assert!(hop_outcomes(&receipt, 1000.into(), &[quickswap], &quoted[..1]).is_none());
+    }
+
+    #[test]
+    fn test_quote_bias() {
+        let quickswap = Protocol::UniswapV2(UniswapV2::QUICKSWAP);
+        let sushiswap = Protocol::UniswapV2(UniswapV2::SUSHISWAP);
+        let mut bias = QuoteBias::new(QuoteBiasConfig::default());
+        let hop = HopOutcome {
+            protocol: quickswap.to_string(),
+            quoted_in: 1000.into(),
+            quoted_out: 1000.into(),
+            realized_in: 1000.into(),
+            realized_out: 990.into(),
+        };
+        bias.record(&hop);
+        bias.record(&hop);
+        // not enough samples yet
+        assert_eq!(bias.discount_bps(&quickswap), 0.0);
+        assert_eq!(bias.record(&hop).samples, 3);
+        assert!((bias.discount_bps(&quickswap) - 100.0).abs() < 1e-6);
+
+        // the discount carries into later hops
+        let adjusted = bias.adjust(
+            &[quickswap, sushiswap],
+            &[1_000_000.into(), 2_000_000.into()],
+        );
+        assert_eq!(adjusted, vec![990_000.into(), 1_980_000.into()]);
+
+        // pessimistic quotes aren't corrected
+        for _ in 0..20 {
+            bias.record(&HopOutcome {
+                realized_out: 1010.into(),
+                ..hop.clone()
+            });
+        }
+        assert!(bias.bias(&quickswap).bps < 0.0);
+        assert_eq!(bias.discount_bps(&quickswap), 0.0);
+    }
+}
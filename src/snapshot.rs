@@ -0,0 +1,126 @@
+//! Per-block checksums of the tracked V2 reserves. Two instances (or a
+//! replay) that applied the same Sync events log the same hash for a block,
+//! so a mismatch points at the first block they diverged on, and the full
+//! reserves of recent blocks can be dumped to find the pair that differs.
+
+use std::collections::VecDeque;
+
+use ethers::{types::H256, utils::keccak256};
+use serde::Serialize;
+
+use crate::export::ReserveRecord;
+
+/// blocks whose full state is kept for dumping
+pub const STATE_HISTORY: usize = 64;
+
+/// keccak256 over (pair, reserve0, reserve1) of every pool, sorted by pair,
+/// so it doesn't depend on the order pools were loaded in
+pub fn state_hash(pools: &[ReserveRecord]) -> H256 {
+    let mut pools: Vec<&ReserveRecord> = pools.iter().collect();
+    pools.sort_by_key(|pool| pool.pair);
+    let mut bytes = Vec::with_capacity(pools.len() * 84);
+    let mut word = [0u8; 32];
+    for pool in pools {
+        bytes.extend_from_slice(pool.pair.as_bytes());
+        for reserve in [pool.reserve0, pool.reserve1] {
+            reserve.to_big_endian(&mut word);
+            bytes.extend_from_slice(&word);
+        }
+    }
+    H256::from(keccak256(bytes))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StateSnapshot {
+    pub block: u64,
+    pub hash: H256,
+    pub pools: Vec<ReserveRecord>,
+}
+
+impl StateSnapshot {
+    pub fn new(block: u64, pools: Vec<ReserveRecord>) -> Self {
+        Self {
+            block,
+            hash: state_hash(&pools),
+            pools,
+        }
+    }
+}
+
+/// the snapshots of the last `capacity` blocks
+pub struct SnapshotHistory {
+    capacity: usize,
+    snapshots: VecDeque<StateSnapshot>,
+}
+
+impl SnapshotHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// replaces an earlier snapshot of the same block
+    pub fn push(&mut self, snapshot: StateSnapshot) {
+        self.snapshots.retain(|kept| kept.block != snapshot.block);
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn get(&self, block: u64) -> Option<&StateSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.block == block)
+    }
+
+    pub fn latest(&self) -> Option<&StateSnapshot> {
+        self.snapshots.back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, U256};
+
+    use super::*;
+
+    fn pool(pair: u64, reserve0: u64, reserve1: u64) -> ReserveRecord {
+        ReserveRecord {
+            block: 1,
+            pair: Address::from_low_u64_be(pair),
+            protocol: "Quickswap".to_string(),
+            token0: Address::zero(),
+            token1: Address::zero(),
+            reserve0: reserve0.into(),
+            reserve1: reserve1.into(),
+        }
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let pools = vec![pool(1, 100, 200), pool(2, 300, 400)];
+        let reordered = vec![pool(2, 300, 400), pool(1, 100, 200)];
+        assert_eq!(state_hash(&pools), state_hash(&reordered));
+        // swapping reserves is a different state
+        let swapped = vec![pool(1, 200, 100), pool(2, 300, 400)];
+        assert_ne!(state_hash(&pools), state_hash(&swapped));
+        let moved = vec![pool(1, 100, 200), pool(2, 300, 401)];
+        assert_ne!(state_hash(&pools), state_hash(&moved));
+    }
+
+    #[test]
+    fn test_snapshot_history() {
+        let mut history = SnapshotHistory::new(2);
+        for block in 1..=3 {
+            history.push(StateSnapshot::new(block, vec![pool(1, block, block)]));
+        }
+        assert!(history.get(1).is_none());
+        assert_eq!(history.get(2).unwrap().pools[0].reserve0, U256::from(2));
+        history.push(StateSnapshot::new(3, vec![pool(1, 5, 5)]));
+        assert_eq!(history.latest().unwrap().pools[0].reserve0, U256::from(5));
+        assert!(history.get(2).is_some());
+    }
+}
@@ -0,0 +1,411 @@
+//! Spreads between venues of the same token pair over recorded reserve
+//! history, set against what capturing them would cost. Spreads that keep
+//! showing up but stay under the cost of an arb are where quoting passively
+//! (LP or JIT liquidity) could earn what arbing can't.
+//!
+//! A venue's price is the ratio of its reserves, so spreads are in bps of
+//! the cheaper venue and independent of token decimals. The cost of an arb
+//! is the swap fee of both legs plus gas for a trade of `trade_size_usd`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use ethers::types::{Address, U256};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    export::{GasPriceRecord, ReserveRecord},
+    storage::{self, Log, Storage, StorageError},
+    utils::fixed_point::to_f64,
+};
+
+// log entries read per page
+const PAGE_SIZE: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpreadConfig {
+    /// gas of a two hop arb
+    pub gas_units: u64,
+    /// swap fee of each leg
+    pub fee_bps: f64,
+    /// trade the gas cost is spread over
+    pub trade_size_usd: f64,
+    pub matic_usd: f64,
+    /// spreads below this are noise, not an opportunity of any kind
+    pub min_spread_bps: f64,
+}
+
+impl Default for SpreadConfig {
+    fn default() -> Self {
+        Self {
+            gas_units: 500_000,
+            fee_bps: 30.0,
+            trade_size_usd: 1_000.0,
+            matic_usd: 0.85,
+            min_spread_bps: 5.0,
+        }
+    }
+}
+
+impl SpreadConfig {
+    /// cost of an arb at `gas_price` (wei), in bps of the trade
+    pub fn cost_bps(&self, gas_price: U256) -> f64 {
+        let gas_matic = self.gas_units as f64 * gas_price.low_u128() as f64 / 1e18;
+        2.0 * self.fee_bps + gas_matic * self.matic_usd / self.trade_size_usd * 10_000.0
+    }
+}
+
+/// Spreads between two venues of a pair over every block either moved in.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpreadStats {
+    /// lower address first
+    pub token0: Address,
+    pub token1: Address,
+    pub venue_a: String,
+    pub venue_b: String,
+    pub blocks: u64,
+    pub mean_spread_bps: f64,
+    pub p50_spread_bps: f64,
+    pub p90_spread_bps: f64,
+    pub mean_cost_bps: f64,
+    /// blocks with a spread over `min_spread_bps` but under the cost
+    pub below_cost_blocks: u64,
+    /// longest stretch of blocks the spread stayed below cost
+    pub longest_below_cost_run: u64,
+    /// blocks with a spread over the cost
+    pub arbable_blocks: u64,
+}
+
+impl SpreadStats {
+    pub fn below_cost_share(&self) -> f64 {
+        if self.blocks == 0 {
+            return 0.0;
+        }
+        self.below_cost_blocks as f64 / self.blocks as f64
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Samples {
+    spreads: Vec<f64>,
+    cost_sum: f64,
+    below_cost: u64,
+    arbable: u64,
+    /// first block of the current below cost stretch
+    run_start: Option<u64>,
+    longest_run: u64,
+}
+
+type VenuePair = (Address, Address, String, String);
+
+/// Replays reserve and gas price records block by block.
+pub struct SpreadTracker {
+    config: SpreadConfig,
+    /// pair -> (tokens ordered, protocol, price of token0 in token1)
+    prices: HashMap<Address, ((Address, Address), String, f64)>,
+    gas_price: U256,
+    samples: HashMap<VenuePair, Samples>,
+}
+
+impl SpreadTracker {
+    pub fn new(config: SpreadConfig) -> Self {
+        Self {
+            config,
+            prices: HashMap::new(),
+            gas_price: U256::zero(),
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn gas_price(&mut self, record: &GasPriceRecord) {
+        self.gas_price = record.base_fee + record.priority_fee;
+    }
+
+    /// Applies the reserves synced in `block` and samples the spread of
+    /// every venue pair they touched.
+    pub fn block(&mut self, block: u64, records: &[ReserveRecord]) {
+        let mut touched: Vec<(Address, Address)> = Vec::new();
+        for record in records {
+            let (tokens, price) = if record.token0 < record.token1 {
+                (
+                    (record.token0, record.token1),
+                    (record.reserve1, record.reserve0),
+                )
+            } else {
+                (
+                    (record.token1, record.token0),
+                    (record.reserve0, record.reserve1),
+                )
+            };
+            if price.1.is_zero() || price.0.is_zero() {
+                self.prices.remove(&record.pair);
+                continue;
+            }
+            let price = to_f64(price.0) / to_f64(price.1);
+            self.prices
+                .insert(record.pair, (tokens, record.protocol.clone(), price));
+            if !touched.contains(&tokens) {
+                touched.push(tokens);
+            }
+        }
+
+        let cost = self.config.cost_bps(self.gas_price);
+        for tokens in touched {
+            let mut venues: Vec<(&String, f64)> = self
+                .prices
+                .values()
+                .filter(|(pair_tokens, _, _)| *pair_tokens == tokens)
+                .map(|(_, protocol, price)| (protocol, *price))
+                .collect();
+            venues.sort_by(|a, b| a.0.cmp(b.0));
+            for i in 0..venues.len() {
+                for j in i + 1..venues.len() {
+                    let (low, high) = if venues[i].1 < venues[j].1 {
+                        (venues[i].1, venues[j].1)
+                    } else {
+                        (venues[j].1, venues[i].1)
+                    };
+                    let spread = (high - low) / low * 10_000.0;
+                    let key = (tokens.0, tokens.1, venues[i].0.clone(), venues[j].0.clone());
+                    let samples = self.samples.entry(key).or_default();
+                    samples.spreads.push(spread);
+                    samples.cost_sum += cost;
+                    if spread > cost {
+                        samples.arbable += 1;
+                    }
+                    if spread > self.config.min_spread_bps && spread <= cost {
+                        samples.below_cost += 1;
+                        let start = *samples.run_start.get_or_insert(block);
+                        samples.longest_run = samples.longest_run.max(block - start + 1);
+                    } else {
+                        samples.run_start = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// venue pairs that sat below cost most often first
+    pub fn report(self) -> Vec<SpreadStats> {
+        let mut report: Vec<SpreadStats> = self
+            .samples
+            .into_iter()
+            .map(|((token0, token1, venue_a, venue_b), mut samples)| {
+                samples.spreads.sort_by(|a, b| a.total_cmp(b));
+                let blocks = samples.spreads.len();
+                let percentile = |p: usize| samples.spreads[(blocks - 1) * p / 100];
+                SpreadStats {
+                    token0,
+                    token1,
+                    venue_a,
+                    venue_b,
+                    blocks: blocks as u64,
+                    mean_spread_bps: samples.spreads.iter().sum::<f64>() / blocks as f64,
+                    p50_spread_bps: percentile(50),
+                    p90_spread_bps: percentile(90),
+                    mean_cost_bps: samples.cost_sum / blocks as f64,
+                    below_cost_blocks: samples.below_cost,
+                    longest_below_cost_run: samples.longest_run,
+                    arbable_blocks: samples.arbable,
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| {
+            b.below_cost_blocks
+                .cmp(&a.below_cost_blocks)
+                .then(a.token0.cmp(&b.token0))
+                .then(a.token1.cmp(&b.token1))
+                .then(a.venue_a.cmp(&b.venue_a))
+                .then(a.venue_b.cmp(&b.venue_b))
+        });
+        report
+    }
+}
+
+/// Spread report over recorded history, records in any order.
+pub fn spread_report(
+    reserves: &[ReserveRecord],
+    gas_prices: &[GasPriceRecord],
+    config: SpreadConfig,
+) -> Vec<SpreadStats> {
+    let mut blocks: BTreeMap<u64, Vec<ReserveRecord>> = BTreeMap::new();
+    for record in reserves {
+        blocks.entry(record.block).or_default().push(record.clone());
+    }
+    let mut gas_prices: Vec<&GasPriceRecord> = gas_prices.iter().collect();
+    gas_prices.sort_by_key(|record| record.block);
+    let mut gas_prices = gas_prices.into_iter().peekable();
+
+    let mut tracker = SpreadTracker::new(config);
+    for (block, records) in blocks {
+        while let Some(record) = gas_prices.next_if(|record| record.block <= block) {
+            tracker.gas_price(record);
+        }
+        tracker.block(block, &records);
+    }
+    tracker.report()
+}
+
+async fn read_all<T: Serialize + DeserializeOwned>(
+    storage: Arc<dyn Storage>,
+    name: &'static str,
+) -> Result<Vec<T>, StorageError> {
+    let log: Log<T> = Log::new(storage, name);
+    let mut entries = Vec::new();
+    let mut after = 0;
+    loop {
+        let page = log.read(after, PAGE_SIZE).await?;
+        match page.last() {
+            Some((seq, _)) => after = *seq,
+            None => break,
+        }
+        entries.extend(page.into_iter().map(|(_, entry)| entry));
+    }
+    Ok(entries)
+}
+
+/// Spread report over the reserves and gas prices recorded to `storage`.
+pub async fn recorded_spread_report(
+    storage: Arc<dyn Storage>,
+    config: SpreadConfig,
+) -> Result<Vec<SpreadStats>, StorageError> {
+    let reserves: Vec<ReserveRecord> = read_all(storage.clone(), storage::RESERVES).await?;
+    let gas_prices: Vec<GasPriceRecord> = read_all(storage, storage::GAS_PRICES).await?;
+    Ok(spread_report(&reserves, &gas_prices, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+
+    use super::*;
+    use crate::{
+        bus::{storage_sink, BlockEvent, Bus},
+        storage::MemoryStorage,
+        utils::batch::fake::FakeTransport,
+    };
+
+    fn reserves(block: u64, pair: u64, protocol: &str, reserve1: u64) -> ReserveRecord {
+        ReserveRecord {
+            block,
+            pair: Address::from_low_u64_be(pair),
+            protocol: protocol.to_string(),
+            token0: Address::from_low_u64_be(1),
+            token1: Address::from_low_u64_be(2),
+            reserve0: U256::from(1_000_000),
+            reserve1: U256::from(reserve1),
+        }
+    }
+
+    #[test]
+    fn test_cost_bps() {
+        let config = SpreadConfig {
+            matic_usd: 1.0,
+            ..Default::default()
+        };
+        // 500k gas at 1000 gwei is 0.5 matic, 5 bps of $1000
+        let cost = config.cost_bps(U256::from(1_000_000_000_000u64));
+        assert!((cost - 65.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spread_report() {
+        let gas_prices = vec![GasPriceRecord {
+            block: 1,
+            timestamp: 0,
+            base_fee: U256::from(1_000_000_000_000u64),
+            priority_fee: U256::zero(),
+        }];
+        let config = SpreadConfig {
+            matic_usd: 1.0,
+            ..Default::default()
+        };
+        let history = vec![
+            reserves(1, 10, "Quickswap", 1_000_000),
+            reserves(1, 11, "Sushiswap", 1_002_000),
+            // 20 bps for two blocks, then 100 bps
+            reserves(2, 10, "Quickswap", 1_000_000),
+            reserves(3, 11, "Sushiswap", 1_010_000),
+        ];
+        let report = spread_report(&history, &gas_prices, config);
+        assert_eq!(report.len(), 1);
+        let stats = &report[0];
+        assert_eq!(
+            (stats.venue_a.as_str(), stats.venue_b.as_str()),
+            ("Quickswap", "Sushiswap")
+        );
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.below_cost_blocks, 2);
+        assert_eq!(stats.longest_below_cost_run, 2);
+        assert_eq!(stats.arbable_blocks, 1);
+        assert!((stats.p50_spread_bps - 20.0).abs() < 1e-6);
+        assert!((stats.mean_cost_bps - 65.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_recorded_spread_report() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let transport = FakeTransport::new();
+        transport.set_response(
+            "eth_feeHistory",
+            serde_json::json!({
+                "oldestBlock": "0x1",
+                "baseFeePerGas": ["0xe8d4a51000", "0xe8d4a51000"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x0"]],
+            }),
+        );
+        let bus = Arc::new(Bus::new(16));
+        let sink = tokio::spawn(storage_sink(
+            bus.clone(),
+            storage.clone(),
+            Arc::new(Provider::new(transport)),
+        ));
+        while bus.opportunities.subscribers() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        bus.blocks.publish(BlockEvent {
+            number: 1,
+            timestamp: 0,
+            base_fee: Some(U256::from(1_000_000_000_000u64)),
+        });
+        for record in [
+            reserves(1, 10, "Quickswap", 1_000_000),
+            reserves(1, 11, "Sushiswap", 1_002_000),
+            reserves(2, 10, "Quickswap", 1_000_000),
+            reserves(3, 11, "Sushiswap", 1_010_000),
+        ] {
+            bus.pool_updates.publish(record);
+        }
+        drop(bus);
+        sink.await.unwrap();
+
+        let config = SpreadConfig {
+            matic_usd: 1.0,
+            ..Default::default()
+        };
+        let report = recorded_spread_report(storage, config).await.unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].blocks, 3);
+        assert_eq!(report[0].below_cost_blocks, 2);
+        assert!((report[0].mean_cost_bps - 65.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_token_order() {
+        // a pair listing its tokens the other way round quotes the same price
+        let mut flipped = reserves(1, 11, "Meshswap", 1_000_000);
+        std::mem::swap(&mut flipped.token0, &mut flipped.token1);
+        std::mem::swap(&mut flipped.reserve0, &mut flipped.reserve1);
+        let report = spread_report(
+            &[reserves(1, 10, "Quickswap", 1_000_000), flipped],
+            &[],
+            SpreadConfig::default(),
+        );
+        assert_eq!(report[0].p50_spread_bps, 0.0);
+        assert_eq!(report[0].below_cost_blocks, 0);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{Storage, StorageError};
+
+#[derive(Default)]
+struct State {
+    tables: HashMap<String, BTreeMap<String, Value>>,
+    logs: HashMap<String, Vec<(u64, Value)>>,
+    last_seq: u64,
+}
+
+/// Storage that's gone with the process, for tests and dry runs.
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn put(&self, table: &str, key: &str, value: Value) -> Result<(), StorageError> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .tables
+            .entry(table.to_string())
+            .or_default()
+            .insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, StorageError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .tables
+            .get(table)
+            .and_then(|records| records.get(key))
+            .cloned())
+    }
+
+    async fn remove(&self, table: &str, key: &str) -> Result<bool, StorageError> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state
+            .tables
+            .get_mut(table)
+            .and_then(|records| records.remove(key))
+            .is_some())
+    }
+
+    async fn scan(&self, table: &str) -> Result<Vec<(String, Value)>, StorageError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .tables
+            .get(table)
+            .map(|records| {
+                records
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn append(&self, log: &str, value: Value) -> Result<u64, StorageError> {
+        let mut state = self.state.lock().unwrap();
+        state.last_seq += 1;
+        let seq = state.last_seq;
+        state
+            .logs
+            .entry(log.to_string())
+            .or_default()
+            .push((seq, value));
+        Ok(seq)
+    }
+
+    async fn read_log(
+        &self,
+        log: &str,
+        after: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Value)>, StorageError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .logs
+            .get(log)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|(seq, _)| *seq > after)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
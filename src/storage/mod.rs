@@ -0,0 +1,204 @@
+//! One place for whatever has to survive a restart. Features keep their
+//! state as keyed records in a table (address book, PnL per token, competing
+//! liquidators) or as an append-only log (executions, liquidation races), both
+//! stored as json so a feature only needs its type to be serde.
+//!
+//! Backends are picked by url in `open`, sqlite by default and postgres with
+//! the `postgres` feature, for when several bots share the same state.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use memory::MemoryStorage;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+/// where the bots keep their state unless told otherwise
+pub const DEFAULT_STORAGE: &str = "sqlite://data/tsuki.db";
+
+pub const ADDRESS_BOOK: &str = "address_book";
+pub const COMPETITORS: &str = "competitors";
+pub const RACES: &str = "races";
+pub const PNL: &str = "pnl";
+pub const OPPORTUNITIES: &str = "opportunities";
+pub const RESERVES: &str = "reserves";
+pub const GAS_PRICES: &str = "gas_prices";
+pub const ACTIVITY: &str = "activity";
+pub const ROUTE_HEATMAP: &str = "route_heatmap";
+pub const IN_FLIGHT: &str = "in_flight";
+pub const EXECUTIONS: &str = "executions";
+pub const GAS_SPENDS: &str = "gas_spends";
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("unsupported storage url {0}")]
+    UnsupportedUrl(String),
+
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    PostgresError(#[from] tokio_postgres::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    JoinError(#[from] tokio::task::JoinError),
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// inserts or replaces the record under `key` of `table`
+    async fn put(&self, table: &str, key: &str, value: Value) -> Result<(), StorageError>;
+
+    async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, StorageError>;
+
+    /// whether there was a record to remove
+    async fn remove(&self, table: &str, key: &str) -> Result<bool, StorageError>;
+
+    /// every record of `table`, ordered by key
+    async fn scan(&self, table: &str) -> Result<Vec<(String, Value)>, StorageError>;
+
+    /// appends `value` to `log`, returning its sequence number
+    async fn append(&self, log: &str, value: Value) -> Result<u64, StorageError>;
+
+    /// up to `limit` entries of `log` with a sequence number above `after`,
+    /// oldest first
+    async fn read_log(
+        &self,
+        log: &str,
+        after: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Value)>, StorageError>;
+}
+
+/// Opens the storage at `url`: `memory`, `sqlite::memory:`, `sqlite://<path>`
+/// or, with the `postgres` feature, `postgres://...`.
+pub async fn open(url: &str) -> Result<Arc<dyn Storage>, StorageError> {
+    if url == "memory" {
+        return Ok(Arc::new(MemoryStorage::new()));
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        if url == "sqlite::memory:" {
+            return Ok(Arc::new(SqliteStorage::open_in_memory()?));
+        }
+        if let Some(path) = url.strip_prefix("sqlite://") {
+            return Ok(Arc::new(SqliteStorage::open(path)?));
+        }
+    }
+    #[cfg(feature = "postgres")]
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        return Ok(Arc::new(PostgresStorage::connect(url).await?));
+    }
+    Err(StorageError::UnsupportedUrl(url.to_string()))
+}
+
+/// `table` of `storage` with its records (de)serialized as `T`. Addresses
+/// are keyed by their `{:?}`, `Display` of `H160` is truncated.
+pub struct Table<T> {
+    storage: Arc<dyn Storage>,
+    name: &'static str,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Table<T> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            name: self.name,
+            record: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Table<T> {
+    pub fn new(storage: Arc<dyn Storage>, name: &'static str) -> Self {
+        Self {
+            storage,
+            name,
+            record: PhantomData,
+        }
+    }
+
+    pub async fn put(&self, key: &str, record: &T) -> Result<(), StorageError> {
+        let value = serde_json::to_value(record)?;
+        self.storage.put(self.name, key, value).await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<T>, StorageError> {
+        match self.storage.get(self.name, key).await? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<bool, StorageError> {
+        self.storage.remove(self.name, key).await
+    }
+
+    pub async fn all(&self) -> Result<Vec<(String, T)>, StorageError> {
+        self.storage
+            .scan(self.name)
+            .await?
+            .into_iter()
+            .map(|(key, value)| Ok((key, serde_json::from_value(value)?)))
+            .collect()
+    }
+}
+
+/// `log` of `storage` with its entries (de)serialized as `T`
+pub struct Log<T> {
+    storage: Arc<dyn Storage>,
+    name: &'static str,
+    entry: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Log<T> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            name: self.name,
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Log<T> {
+    pub fn new(storage: Arc<dyn Storage>, name: &'static str) -> Self {
+        Self {
+            storage,
+            name,
+            entry: PhantomData,
+        }
+    }
+
+    pub async fn append(&self, entry: &T) -> Result<u64, StorageError> {
+        let value = serde_json::to_value(entry)?;
+        self.storage.append(self.name, value).await
+    }
+
+    pub async fn read(&self, after: u64, limit: usize) -> Result<Vec<(u64, T)>, StorageError> {
+        self.storage
+            .read_log(self.name, after, limit)
+            .await?
+            .into_iter()
+            .map(|(seq, value)| Ok((seq, serde_json::from_value(value)?)))
+            .collect()
+    }
+}
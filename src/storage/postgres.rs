@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use log::error;
+use serde_json::Value;
+use tokio_postgres::{Client, NoTls};
+
+use super::{Storage, StorageError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS records (
+        tbl TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (tbl, key)
+    );
+    CREATE TABLE IF NOT EXISTS log (
+        seq BIGSERIAL PRIMARY KEY,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS log_name_seq ON log (name, seq);
+";
+
+/// Storage in postgres, same tables as `SqliteStorage`.
+pub struct PostgresStorage {
+    client: Client,
+}
+
+impl PostgresStorage {
+    /// connects to `url` and creates the tables if needed
+    pub async fn connect(url: &str) -> Result<Self, StorageError> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("postgres connection closed: {}", e);
+            }
+        });
+        client.batch_execute(SCHEMA).await?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn put(&self, table: &str, key: &str, value: Value) -> Result<(), StorageError> {
+        let value = serde_json::to_string(&value)?;
+        self.client
+            .execute(
+                "INSERT INTO records (tbl, key, value) VALUES ($1, $2, $3)
+                 ON CONFLICT (tbl, key) DO UPDATE SET value = excluded.value",
+                &[&table, &key, &value],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, StorageError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT value FROM records WHERE tbl = $1 AND key = $2",
+                &[&table, &key],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(Some(serde_json::from_str(row.get(0))?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn remove(&self, table: &str, key: &str) -> Result<bool, StorageError> {
+        let removed = self
+            .client
+            .execute(
+                "DELETE FROM records WHERE tbl = $1 AND key = $2",
+                &[&table, &key],
+            )
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn scan(&self, table: &str) -> Result<Vec<(String, Value)>, StorageError> {
+        let rows = self
+            .client
+            .query(
+                "SELECT key, value FROM records WHERE tbl = $1 ORDER BY key",
+                &[&table],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| Ok((row.get(0), serde_json::from_str(row.get(1))?)))
+            .collect()
+    }
+
+    async fn append(&self, log: &str, value: Value) -> Result<u64, StorageError> {
+        let value = serde_json::to_string(&value)?;
+        let row = self
+            .client
+            .query_one(
+                "INSERT INTO log (name, value) VALUES ($1, $2) RETURNING seq",
+                &[&log, &value],
+            )
+            .await?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn read_log(
+        &self,
+        log: &str,
+        after: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Value)>, StorageError> {
+        let rows = self
+            .client
+            .query(
+                "SELECT seq, value FROM log WHERE name = $1 AND seq > $2 ORDER BY seq LIMIT $3",
+                &[&log, &(after as i64), &(limit as i64)],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok((
+                    row.get::<_, i64>(0) as u64,
+                    serde_json::from_str(row.get(1))?,
+                ))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+
+use super::{Storage, StorageError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS records (
+        tbl TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (tbl, key)
+    );
+    CREATE TABLE IF NOT EXISTS log (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS log_name_seq ON log (name, seq);
+";
+
+/// Storage in a sqlite file. Queries run on the blocking pool so they don't
+/// stall the runtime.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// opens the database at `path`, creating it and its tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        // writers wait on each other instead of failing with SQLITE_BUSY
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn run<R, F>(&self, f: F) -> Result<R, StorageError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Connection) -> Result<R, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&conn.lock().unwrap())).await?
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn put(&self, table: &str, key: &str, value: Value) -> Result<(), StorageError> {
+        let (table, key) = (table.to_string(), key.to_string());
+        let value = serde_json::to_string(&value)?;
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO records (tbl, key, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (tbl, key) DO UPDATE SET value = excluded.value",
+                params![table, key, value],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, table: &str, key: &str) -> Result<Option<Value>, StorageError> {
+        let (table, key) = (table.to_string(), key.to_string());
+        self.run(move |conn| {
+            let value: Option<String> = conn
+                .query_row(
+                    "SELECT value FROM records WHERE tbl = ?1 AND key = ?2",
+                    params![table, key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(value
+                .map(|value| serde_json::from_str(&value))
+                .transpose()?)
+        })
+        .await
+    }
+
+    async fn remove(&self, table: &str, key: &str) -> Result<bool, StorageError> {
+        let (table, key) = (table.to_string(), key.to_string());
+        self.run(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM records WHERE tbl = ?1 AND key = ?2",
+                params![table, key],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn scan(&self, table: &str) -> Result<Vec<(String, Value)>, StorageError> {
+        let table = table.to_string();
+        self.run(move |conn| {
+            let mut statement =
+                conn.prepare("SELECT key, value FROM records WHERE tbl = ?1 ORDER BY key")?;
+            let rows = statement.query_map(params![table], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            let mut records = Vec::new();
+            for row in rows {
+                let (key, value) = row?;
+                records.push((key, serde_json::from_str(&value)?));
+            }
+            Ok(records)
+        })
+        .await
+    }
+
+    async fn append(&self, log: &str, value: Value) -> Result<u64, StorageError> {
+        let log = log.to_string();
+        let value = serde_json::to_string(&value)?;
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO log (name, value) VALUES (?1, ?2)",
+                params![log, value],
+            )?;
+            Ok(conn.last_insert_rowid() as u64)
+        })
+        .await
+    }
+
+    async fn read_log(
+        &self,
+        log: &str,
+        after: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Value)>, StorageError> {
+        let log = log.to_string();
+        self.run(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT seq, value FROM log WHERE name = ?1 AND seq > ?2 ORDER BY seq LIMIT ?3",
+            )?;
+            let rows = statement.query_map(params![log, after as i64, limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            let mut entries = Vec::new();
+            for row in rows {
+                let (seq, value) = row?;
+                entries.push((seq as u64, serde_json::from_str(&value)?));
+            }
+            Ok(entries)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers::types::Address;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::storage::{Log, Table, OPPORTUNITIES};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Opportunity {
+        block: u64,
+        profit: String,
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open_in_memory().unwrap());
+
+        let denylist: Table<String> = Table::new(storage.clone(), "denylist");
+        let address = format!("{:?}", Address::repeat_byte(1));
+        denylist
+            .put(&address, &"sandwich".to_string())
+            .await
+            .unwrap();
+        denylist
+            .put(&address, &"honeypot".to_string())
+            .await
+            .unwrap();
+        assert_eq!(denylist.get(&address).await.unwrap().unwrap(), "honeypot");
+        assert_eq!(denylist.all().await.unwrap().len(), 1);
+        assert!(denylist.remove(&address).await.unwrap());
+        assert!(denylist.get(&address).await.unwrap().is_none());
+
+        let opportunities: Log<Opportunity> = Log::new(storage, OPPORTUNITIES);
+        for block in 0..3 {
+            let opportunity = Opportunity {
+                block,
+                profit: "1000".to_string(),
+            };
+            opportunities.append(&opportunity).await.unwrap();
+        }
+        let entries = opportunities.read(1, 10).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].1.block, 1);
+    }
+}
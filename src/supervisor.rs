@@ -0,0 +1,316 @@
+//! Owns the tasks a binary spawns. Long-running tasks (streams, guards,
+//! sinks) are restarted with exponential backoff when they panic or return,
+//! short-lived ones (per-route quoting) run under a per-group concurrency
+//! limit. Every exit, panic and restart is logged and published to the
+//! bus's `tasks` topic instead of disappearing with a detached `JoinHandle`.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::FutureExt;
+use log::{error, warn};
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinHandle};
+
+use crate::bus::Bus;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SupervisorConfig {
+    /// wait before the first restart, doubled on every restart after
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// a task that ran this long before dying restarts from the initial
+    /// backoff again
+    pub healthy_after: Duration,
+    /// restarts in a row before giving up on a task, `None` never does
+    pub max_restarts: Option<u32>,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            healthy_after: Duration::from_secs(300),
+            max_restarts: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TaskStatus {
+    Panicked {
+        message: String,
+    },
+    /// a long-running task returned
+    Exited,
+    Restarting {
+        attempt: u32,
+        backoff_ms: u64,
+    },
+    GaveUp {
+        restarts: u32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TaskEvent {
+    pub task: String,
+    #[serde(flatten)]
+    pub status: TaskStatus,
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+pub struct Supervisor {
+    config: SupervisorConfig,
+    bus: Arc<Bus>,
+    limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+    tasks: Mutex<Vec<(String, JoinHandle<()>)>>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig, bus: Arc<Bus>) -> Self {
+        Self {
+            config,
+            bus,
+            limits: Mutex::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn report(&self, task: &str, status: TaskStatus) {
+        match &status {
+            TaskStatus::Panicked { message } => error!("Task {} panicked: {}", task, message),
+            TaskStatus::Exited => warn!("Task {} exited", task),
+            TaskStatus::Restarting {
+                attempt,
+                backoff_ms,
+            } => warn!(
+                "Restarting task {} in {}ms (attempt {})",
+                task, backoff_ms, attempt
+            ),
+            TaskStatus::GaveUp { restarts } => {
+                error!("Giving up on task {} after {} restarts", task, restarts)
+            }
+        }
+        self.bus.tasks.publish(TaskEvent {
+            task: task.to_string(),
+            status,
+        });
+    }
+
+    /// Runs `make()` as task `name`, making a fresh one whenever it panics
+    /// or returns, until `max_restarts` restarts in a row.
+    pub fn supervise<F, Fut>(self: &Arc<Self>, name: &str, mut make: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let task = name.to_string();
+        let handle = tokio::spawn(async move {
+            let config = supervisor.config;
+            let mut backoff = config.initial_backoff;
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                let status = match tokio::spawn(make()).await {
+                    Ok(()) => TaskStatus::Exited,
+                    Err(e) if e.is_panic() => TaskStatus::Panicked {
+                        message: panic_message(e.into_panic().as_ref()),
+                    },
+                    // aborted, nothing to restart
+                    Err(_) => return,
+                };
+                supervisor.report(&task, status);
+
+                if started.elapsed() >= config.healthy_after {
+                    backoff = config.initial_backoff;
+                    restarts = 0;
+                }
+                if config.max_restarts.is_some_and(|max| restarts >= max) {
+                    supervisor.report(&task, TaskStatus::GaveUp { restarts });
+                    return;
+                }
+                restarts += 1;
+                supervisor.report(
+                    &task,
+                    TaskStatus::Restarting {
+                        attempt: restarts,
+                        backoff_ms: backoff.as_millis() as u64,
+                    },
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(config.max_backoff);
+            }
+        });
+        self.tasks.lock().unwrap().push((name.to_string(), handle));
+    }
+
+    /// at most `permits` tasks of `group` run at once from now on
+    pub fn set_limit(&self, group: &str, permits: usize) {
+        self.limits
+            .lock()
+            .unwrap()
+            .insert(group.to_string(), Arc::new(Semaphore::new(permits)));
+    }
+
+    /// Spawns `future` as one of `group`, waiting for a permit first when
+    /// the group has a limit. A panic is reported, then passed on to the
+    /// `JoinHandle` as usual.
+    pub fn spawn_limited<T, Fut>(self: &Arc<Self>, group: &str, future: Fut) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let limit = self.limits.lock().unwrap().get(group).cloned();
+        let supervisor = self.clone();
+        let group = group.to_string();
+        tokio::spawn(async move {
+            let _permit = match limit {
+                Some(limit) => Some(limit.acquire_owned().await.unwrap()),
+                None => None,
+            };
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(output) => output,
+                Err(panic) => {
+                    supervisor.report(
+                        &group,
+                        TaskStatus::Panicked {
+                            message: panic_message(panic.as_ref()),
+                        },
+                    );
+                    std::panic::resume_unwind(panic)
+                }
+            }
+        })
+    }
+
+    /// names of the supervised tasks still running
+    pub fn running(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, handle)| !handle.is_finished())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// aborts every supervised task
+    pub fn shutdown(&self) {
+        for (_, handle) in self.tasks.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::bus::next;
+
+    #[tokio::test]
+    async fn test_restarts_with_backoff() {
+        let bus = Arc::new(Bus::default());
+        let mut events = bus.tasks.subscribe();
+        let supervisor = Arc::new(Supervisor::new(
+            SupervisorConfig {
+                initial_backoff: Duration::from_millis(1),
+                max_restarts: Some(2),
+                ..Default::default()
+            },
+            bus,
+        ));
+        let runs = Arc::new(AtomicU32::new(0));
+        {
+            let runs = runs.clone();
+            supervisor.supervise("flaky", move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if run == 0 {
+                        panic!("first run fails");
+                    }
+                }
+            });
+        }
+
+        let mut statuses = Vec::new();
+        while let Some(event) = next(&mut events, "tasks").await {
+            assert_eq!(event.task, "flaky");
+            let gave_up = matches!(event.status, TaskStatus::GaveUp { .. });
+            statuses.push(event.status);
+            if gave_up {
+                break;
+            }
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                TaskStatus::Panicked {
+                    message: "first run fails".to_string()
+                },
+                TaskStatus::Restarting {
+                    attempt: 1,
+                    backoff_ms: 1
+                },
+                TaskStatus::Exited,
+                TaskStatus::Restarting {
+                    attempt: 2,
+                    backoff_ms: 2
+                },
+                TaskStatus::Exited,
+                TaskStatus::GaveUp { restarts: 2 },
+            ]
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let supervisor = Arc::new(Supervisor::new(
+            SupervisorConfig::default(),
+            Arc::new(Bus::default()),
+        ));
+        supervisor.set_limit("routes", 2);
+        let running = Arc::new(AtomicU32::new(0));
+        let peak = Arc::new(AtomicU32::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (running, peak) = (running.clone(), peak.clone());
+                supervisor.spawn_limited("routes", async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        // panics still reach the caller
+        let panicked = supervisor.spawn_limited("routes", async { panic!("boom") });
+        assert!(panicked.await.unwrap_err().is_panic());
+    }
+}
@@ -0,0 +1,114 @@
+//! Logging setup for the bins. Built with the `otel` feature and with
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` set, tracing spans (RPC calls, blocks,
+//! opportunities) are also exported over OTLP, so a detector and an executor
+//! running as separate processes show up in the same trace.
+
+use std::collections::HashMap;
+
+use tracing::Span;
+
+/// Flushes spans not exported yet when dropped, keep it alive in `main`.
+pub struct Telemetry {
+    otlp: bool,
+}
+
+impl Telemetry {
+    pub fn is_exporting(&self) -> bool {
+        self.otlp
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Logs as configured by `RUST_LOG` either way. Without OTLP export that's
+/// plain env_logger, with it `log` records go through the tracing subscriber.
+pub fn init(service_name: &'static str) -> Telemetry {
+    #[cfg(feature = "otel")]
+    let otlp_error = match std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Some(_) => match otel::init(service_name) {
+            Ok(()) => return Telemetry { otlp: true },
+            Err(e) => Some(e),
+        },
+        None => None,
+    };
+    let _ = service_name;
+    env_logger::init();
+    #[cfg(feature = "otel")]
+    if let Some(e) = otlp_error {
+        log::warn!("OTLP export disabled: {}", e);
+    }
+    Telemetry { otlp: false }
+}
+
+/// W3C trace context of `span`, to send along with whatever is handed to
+/// another process. Empty without OTLP export.
+pub fn trace_context(span: &Span) -> HashMap<String, String> {
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let mut carrier = HashMap::new();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&span.context(), &mut carrier)
+        });
+        carrier
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = span;
+        HashMap::new()
+    }
+}
+
+/// makes `span` a child of the span `carrier` came from
+pub fn set_remote_parent(span: &Span, carrier: &HashMap<String, String>) {
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(carrier)
+        });
+        span.set_parent(parent);
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, carrier);
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::{
+        global,
+        sdk::{propagation::TraceContextPropagator, trace, Resource},
+        trace::TraceError,
+        KeyValue,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+    pub fn init(service_name: &'static str) -> Result<(), TraceError> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer =
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", service_name),
+                ])))
+                .install_batch(opentelemetry::runtime::Tokio)?;
+        tracing_subscriber::registry()
+            .with(EnvFilter::from_default_env())
+            // stdout is for `--ndjson` events
+            .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .try_init()
+            .map_err(|e| TraceError::Other(e.into()))
+    }
+}
@@ -0,0 +1,257 @@
+//! Report of the live arb's trades: win rate, mean and median net profit,
+//! max drawdown, gas spend and a per route breakdown, as json or a summary,
+//! over the mined executions it appends to the `executions` log, picked by
+//! block range. Amounts are in USDC at the index price when the arb was
+//! mined, so routes starting at different tokens add up.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+
+use ethers::types::{H256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::ExecutionStatus,
+    storage::{Log, Storage, StorageError, EXECUTIONS},
+    utils::fixed_point::to_f64,
+};
+
+// log entries read per page
+const PAGE_SIZE: usize = 10_000;
+
+/// one mined arb, or one a reorg orphaned recorded as dropped
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub block: u64,
+    pub tx_hash: H256,
+    /// token symbols joined by `>`
+    pub route: String,
+    pub status: ExecutionStatus,
+    /// realized, read from the Swap logs of the receipt (quoted if they
+    /// don't match the route), 0 if it reverted
+    pub profit_usd: f64,
+    /// wei of MATIC
+    pub gas_paid: U256,
+    pub gas_usd: f64,
+}
+
+impl TradeRecord {
+    pub fn net_usd(&self) -> f64 {
+        self.profit_usd - self.gas_usd
+    }
+
+    /// confirmed and worth more than its gas
+    pub fn is_win(&self) -> bool {
+        self.status == ExecutionStatus::Confirmed && self.net_usd() > 0.0
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RouteReport {
+    pub route: String,
+    pub trades: u64,
+    pub wins: u64,
+    pub reverts: u64,
+    pub net_usd: f64,
+    pub gas_usd: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TradeReport {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    pub trades: u64,
+    pub wins: u64,
+    pub reverts: u64,
+    pub win_rate: f64,
+    pub mean_net_usd: f64,
+    pub median_net_usd: f64,
+    pub net_usd: f64,
+    /// largest fall of the cumulative net from a previous high
+    pub max_drawdown_usd: f64,
+    /// in whole MATIC
+    pub gas_matic: f64,
+    pub gas_usd: f64,
+    /// most net first
+    pub routes: Vec<RouteReport>,
+}
+
+impl TradeReport {
+    /// over `trades` in the order they were mined
+    pub fn new(trades: &[TradeRecord]) -> Self {
+        let mut report = Self {
+            from_block: trades.iter().map(|trade| trade.block).min(),
+            to_block: trades.iter().map(|trade| trade.block).max(),
+            trades: trades.len() as u64,
+            ..Default::default()
+        };
+        let mut routes: BTreeMap<&str, RouteReport> = BTreeMap::new();
+        let (mut peak, mut gas_paid) = (0.0f64, U256::zero());
+        for trade in trades {
+            let route = routes.entry(&trade.route).or_insert_with(|| RouteReport {
+                route: trade.route.clone(),
+                ..Default::default()
+            });
+            route.trades += 1;
+            if trade.is_win() {
+                report.wins += 1;
+                route.wins += 1;
+            }
+            if trade.status == ExecutionStatus::Reverted {
+                report.reverts += 1;
+                route.reverts += 1;
+            }
+            route.net_usd += trade.net_usd();
+            route.gas_usd += trade.gas_usd;
+            report.net_usd += trade.net_usd();
+            report.gas_usd += trade.gas_usd;
+            gas_paid = gas_paid.saturating_add(trade.gas_paid);
+            peak = peak.max(report.net_usd);
+            report.max_drawdown_usd = report.max_drawdown_usd.max(peak - report.net_usd);
+        }
+        report.gas_matic = to_f64(gas_paid) / 1e18;
+        if !trades.is_empty() {
+            report.win_rate = report.wins as f64 / report.trades as f64;
+            report.mean_net_usd = report.net_usd / report.trades as f64;
+            let mut nets: Vec<f64> = trades.iter().map(TradeRecord::net_usd).collect();
+            nets.sort_by(f64::total_cmp);
+            let mid = nets.len() / 2;
+            report.median_net_usd = match nets.len() % 2 {
+                0 => (nets[mid - 1] + nets[mid]) / 2.0,
+                _ => nets[mid],
+            };
+        }
+        report.routes = routes.into_values().collect();
+        report
+            .routes
+            .sort_by(|a, b| b.net_usd.total_cmp(&a.net_usd));
+        report
+    }
+}
+
+impl fmt::Display for TradeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.from_block, self.to_block) {
+            (Some(from), Some(to)) => writeln!(f, "blocks {}..={}", from, to)?,
+            _ => return writeln!(f, "no trades"),
+        }
+        writeln!(
+            f,
+            "trades {} wins {} reverts {} win rate {:.1}%",
+            self.trades,
+            self.wins,
+            self.reverts,
+            self.win_rate * 100.0
+        )?;
+        writeln!(
+            f,
+            "net ${:.2} mean ${:.2} median ${:.2} max drawdown ${:.2}",
+            self.net_usd, self.mean_net_usd, self.median_net_usd, self.max_drawdown_usd
+        )?;
+        writeln!(f, "gas {:.4} MATIC (${:.2})", self.gas_matic, self.gas_usd)?;
+        for route in &self.routes {
+            writeln!(
+                f,
+                "  {} trades {} wins {} reverts {} net ${:.2} gas ${:.2}",
+                route.route, route.trades, route.wins, route.reverts, route.net_usd, route.gas_usd
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The report of the trades recorded to `storage` mined in
+/// `[from_block, to_block]`.
+pub async fn recorded_trade_report(
+    storage: Arc<dyn Storage>,
+    from_block: u64,
+    to_block: u64,
+) -> Result<TradeReport, StorageError> {
+    let log: Log<TradeRecord> = Log::new(storage, EXECUTIONS);
+    let mut trades = Vec::new();
+    let mut after = 0;
+    loop {
+        let page = log.read(after, PAGE_SIZE).await?;
+        let last = match page.last() {
+            Some((seq, _)) => *seq,
+            None => break,
+        };
+        trades.extend(page.into_iter().map(|(_, trade)| trade));
+        after = last;
+    }
+    // a txn a reorg orphaned is recorded again, dropped or mined anew, and
+    // only its last record holds
+    let latest: HashMap<H256, usize> = trades
+        .iter()
+        .enumerate()
+        .map(|(i, trade)| (trade.tx_hash, i))
+        .collect();
+    let trades: Vec<TradeRecord> = trades
+        .into_iter()
+        .enumerate()
+        .filter(|(i, trade)| latest[&trade.tx_hash] == *i)
+        .map(|(_, trade)| trade)
+        .filter(|trade| {
+            trade.status != ExecutionStatus::Dropped
+                && (from_block..=to_block).contains(&trade.block)
+        })
+        .collect();
+    Ok(TradeReport::new(&trades))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn trade(block: u64, route: &str, status: ExecutionStatus, profit_usd: f64) -> TradeRecord {
+        TradeRecord {
+            block,
+            tx_hash: H256::from_low_u64_be(block),
+            route: route.to_string(),
+            status,
+            profit_usd,
+            gas_paid: U256::exp10(17),
+            gas_usd: 1.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trade_report() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let log: Log<TradeRecord> = Log::new(storage.clone(), EXECUTIONS);
+        let trades = [
+            trade(100, "USDC>WETH>USDC", ExecutionStatus::Confirmed, 11.0),
+            trade(101, "USDC>WETH>USDC", ExecutionStatus::Reverted, 0.0),
+            trade(102, "USDT>DAI>USDT", ExecutionStatus::Confirmed, 0.5),
+            trade(103, "USDC>WETH>USDC", ExecutionStatus::Confirmed, 4.0),
+            // outside the run
+            trade(200, "USDT>DAI>USDT", ExecutionStatus::Confirmed, 100.0),
+            // orphaned by a reorg and dropped
+            trade(104, "USDC>WETH>USDC", ExecutionStatus::Confirmed, 50.0),
+            trade(104, "USDC>WETH>USDC", ExecutionStatus::Dropped, 0.0),
+        ];
+        for trade in &trades {
+            log.append(trade).await.unwrap();
+        }
+
+        let report = recorded_trade_report(storage, 100, 199).await.unwrap();
+        assert_eq!(report.trades, 4);
+        assert_eq!(report.wins, 2);
+        assert_eq!(report.reverts, 1);
+        assert_eq!(report.win_rate, 0.5);
+        // nets 10, -1, -0.5, 3
+        assert_eq!(report.net_usd, 11.5);
+        assert_eq!(report.median_net_usd, 1.25);
+        assert_eq!(report.max_drawdown_usd, 1.5);
+        assert!((report.gas_matic - 0.4).abs() < 1e-9);
+        assert_eq!(report.routes[0].route, "USDC>WETH>USDC");
+        assert_eq!(report.routes[0].net_usd, 12.0);
+        assert_eq!(report.routes[1].net_usd, -0.5);
+        assert!(report.to_string().contains("win rate 50.0%"));
+        assert_eq!(TradeReport::new(&[]).to_string(), "no trades\n");
+    }
+}
@@ -1,16 +1,51 @@
-use std::{num::NonZeroUsize, sync::Arc};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use ethers::{
-    providers::{Middleware, PubsubClient},
+    providers::{Middleware, ProviderError, PubsubClient},
     types::{Transaction, H256, U256},
 };
 use futures_util::StreamExt;
+use log::{debug, error, info, warn};
 use lru::LruCache;
-use tokio::sync::RwLock;
+use serde::Serialize;
+use tokio::{sync::RwLock, time::timeout};
+
+use crate::{
+    bus::{next, Bus},
+    lag::{Lag, PENDING_TXS},
+    utils::{
+        transaction::{decode_raw_transaction, RawTransactionError},
+        txpool::TxpoolExt,
+    },
+};
+
+/// A pending txn dropped from the pool for bidding under the inclusion
+/// floor too long, as opposed to being evicted to make room.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExpiredTx {
+    pub hash: H256,
+    pub gas_price: U256,
+    /// base fee of the block that expired it
+    pub floor: U256,
+    pub block: u64,
+}
 
 pub struct TxPool<M> {
     provider: Arc<M>,
     lru_cache: RwLock<LruCache<H256, Transaction>>, // tx hash -> gas price
+    /// where streamed pending transactions are published
+    bus: Arc<Bus>,
+    /// silence on the subscription that counts as a stall
+    stall_after: Option<Duration>,
+    /// blocks in a row under the floor after which a txn expires
+    expire_after: Option<u64>,
+    /// blocks in a row each txn has been under the floor
+    below_floor: Mutex<HashMap<H256, u64>>,
 }
 
 impl<M: Middleware + Clone> TxPool<M> {
@@ -18,9 +53,34 @@ impl<M: Middleware + Clone> TxPool<M> {
         TxPool {
             provider: provider.clone(),
             lru_cache: RwLock::new(LruCache::new(NonZeroUsize::new(capacity).unwrap())),
+            bus: Arc::new(Bus::default()),
+            stall_after: None,
+            expire_after: None,
+            below_floor: Mutex::new(HashMap::new()),
         }
     }
 
+    /// publishes pending transactions to `bus` instead of a bus of its own
+    pub fn with_bus(mut self, bus: Arc<Bus>) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Treats `stall_after` without a pending txn as the subscription
+    /// having stalled: the pool is backfilled from the node and
+    /// `stream_mempool` returns, to be resubscribed by its supervisor.
+    pub fn with_stall_timeout(mut self, stall_after: Duration) -> Self {
+        self.stall_after = Some(stall_after);
+        self
+    }
+
+    /// Expires txns whose gas price (fee cap for eip1559 ones) was below
+    /// the base fee for `blocks` heads in a row, see `expire`.
+    pub fn with_expiry(mut self, blocks: u64) -> Self {
+        self.expire_after = Some(blocks);
+        self
+    }
+
     pub async fn get_mempool(&self) -> Vec<Transaction> {
         let mut txns: Vec<Transaction> = Vec::new();
         let lru_cache = self.lru_cache.read().await;
@@ -66,34 +126,212 @@ impl<M: Middleware + Clone> TxPool<M> {
         return num_removed;
     }
 
+    /// Adds a transaction received as raw bytes, eg. from a feed relaying
+    /// `eth_sendRawTransaction` payloads, without asking the node for it.
+    pub async fn insert_raw(&self, raw: &[u8]) -> Result<H256, RawTransactionError> {
+        let (typed_txn, from) = decode_raw_transaction(raw)?;
+        let mut txn: Transaction = typed_txn.into();
+        txn.from = from;
+        // nodes report the fee cap as gas price of pending eip1559 txns
+        txn.gas_price = txn.gas_price.or(txn.max_fee_per_gas);
+        let hash = txn.hash;
+        self.lru_cache.write().await.push(hash, txn);
+        Ok(hash)
+    }
+
+    /// Seeds the cache with the node's pending pool, the subscription only
+    /// sees txns that arrive after it starts. Returns the number added.
+    pub async fn backfill(&self) -> Result<usize, ProviderError> {
+        let content = self.provider.get_txpool_content().await?;
+        let mut lru_cache = self.lru_cache.write().await;
+        let mut num_added = 0;
+        for txn in content.pending_transactions() {
+            let mut txn = txn.clone();
+            txn.gas_price = txn.gas_price.or(txn.max_fee_per_gas);
+            if lru_cache.push(txn.hash, txn).is_none() {
+                num_added += 1;
+            }
+        }
+        Ok(num_added)
+    }
+
     pub async fn stream_mempool(self: Arc<TxPool<M>>)
     where
         <M as Middleware>::Provider: PubsubClient,
     {
-        let mut pending_tx_stream = self
-            .provider
-            .subscribe_pending_txs()
-            .await
-            .unwrap()
-            .transactions_unordered(16); // TODO: what n is ideal?
+        let mut pending_tx_stream = match self.provider.subscribe_pending_txs().await {
+            Ok(stream) => stream.transactions_unordered(16), // TODO: what n is ideal?
+            Err(e) => {
+                warn!("Failed to subscribe to pending txns: {}", e);
+                return;
+            }
+        };
 
-        while let Some(Ok(pending_txn)) = pending_tx_stream.next().await {
+        loop {
+            let next = match self.stall_after {
+                Some(stall_after) => match timeout(stall_after, pending_tx_stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => return self.catch_up(stall_after).await,
+                },
+                None => pending_tx_stream.next().await,
+            };
+            let pending_txn = match next {
+                Some(Ok(pending_txn)) => pending_txn,
+                _ => break,
+            };
+            if self.bus.pending_txs.subscribers() > 0 {
+                self.bus.pending_txs.publish(pending_txn.clone());
+            }
             self.lru_cache
                 .write()
                 .await
                 .push(pending_txn.hash, pending_txn);
         }
     }
+
+    /// Counts head `block` with base fee `floor` against every txn bidding
+    /// below it, and drops the ones that have for the configured number of
+    /// heads in a row. A txn bidding at or above it starts over. The
+    /// expired txns are published to the bus and returned.
+    pub async fn expire(&self, block: u64, floor: U256) -> Vec<ExpiredTx> {
+        let expire_after = match self.expire_after {
+            Some(expire_after) => expire_after,
+            None => return Vec::new(),
+        };
+        let mut lru_cache = self.lru_cache.write().await;
+        let mut below_floor = self.below_floor.lock().unwrap();
+        // only txns still in the pool keep a count, evicted ones drop out
+        let mut counts = HashMap::with_capacity(below_floor.len());
+        let mut expired = Vec::new();
+        for (hash, txn) in lru_cache.iter() {
+            let gas_price = txn.gas_price.unwrap_or_default();
+            if gas_price >= floor {
+                continue;
+            }
+            let count = below_floor.get(hash).copied().unwrap_or_default() + 1;
+            match count >= expire_after {
+                true => expired.push(ExpiredTx {
+                    hash: *hash,
+                    gas_price,
+                    floor,
+                    block,
+                }),
+                false => {
+                    counts.insert(*hash, count);
+                }
+            }
+        }
+        *below_floor = counts;
+        for expired in &expired {
+            lru_cache.pop(&expired.hash);
+            self.bus.expired_txs.publish(expired.clone());
+        }
+        expired
+    }
+
+    /// runs `expire` on every head published to the bus with a base fee
+    pub async fn expire_on_blocks(self: Arc<TxPool<M>>) {
+        let mut blocks = self.bus.blocks.subscribe();
+        while let Some(block) = next(&mut blocks, "blocks").await {
+            if let Some(base_fee) = block.base_fee {
+                let expired = self.expire(block.number, base_fee).await;
+                if !expired.is_empty() {
+                    debug!(
+                        "Expired {} pending txns under the {} base fee",
+                        expired.len(),
+                        base_fee
+                    );
+                }
+            }
+        }
+    }
+
+    /// alerts on a stalled subscription and refills what it missed
+    async fn catch_up(&self, stalled_for: Duration) {
+        let secs = stalled_for.as_secs();
+        error!("No pending txns for {}s, resubscribing", secs);
+        self.bus.lag.publish(Lag::Stalled {
+            subscription: PENDING_TXS.to_string(),
+            secs,
+        });
+        match self.backfill().await {
+            Ok(num_added) => info!("Backfilled {} pending txns", num_added),
+            Err(e) => warn!("Backfilling pending txns failed: {}", e),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use ethers::providers::{Middleware, Provider, Ws};
+    use ethers::{
+        providers::{Middleware, Provider, Ws},
+        types::{Transaction, H256, U256},
+    };
     use futures_util::StreamExt;
 
-    use super::TxPool;
+    use super::{ExpiredTx, TxPool};
+    use crate::utils::batch::fake::FakeTransport;
+
+    #[tokio::test]
+    async fn test_stream_mempool_fake_transport() {
+        let transport = FakeTransport::new();
+        let txn = Transaction {
+            hash: H256::repeat_byte(1),
+            gas_price: Some(U256::from(30)),
+            ..Default::default()
+        };
+        transport.push_response("eth_subscribe", U256::one());
+        transport.push_response("eth_getTransactionByHash", &txn);
+        transport.notify(1, txn.hash);
+        transport.end_subscription(1);
+
+        let txpool = Arc::new(TxPool::init(Arc::new(Provider::new(transport)), 10));
+        txpool.clone().stream_mempool().await;
+        assert_eq!(txpool.get_mempool().await, vec![txn]);
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let txpool = TxPool::init(Arc::new(Provider::new(FakeTransport::new())), 10).with_expiry(2);
+        let mut expired = txpool.bus.expired_txs.subscribe();
+        for (byte, gas_price) in [(1, 30), (2, 50), (3, 29)] {
+            let txn = Transaction {
+                hash: H256::repeat_byte(byte),
+                gas_price: Some(U256::from(gas_price)),
+                ..Default::default()
+            };
+            txpool.lru_cache.write().await.push(txn.hash, txn);
+        }
+
+        assert!(txpool.expire(100, U256::from(40)).await.is_empty());
+        // the first is back at the floor and starts over
+        let expected = ExpiredTx {
+            hash: H256::repeat_byte(3),
+            gas_price: U256::from(29),
+            floor: U256::from(30),
+            block: 101,
+        };
+        assert_eq!(
+            txpool.expire(101, U256::from(30)).await,
+            vec![expected.clone()]
+        );
+        assert_eq!(expired.try_recv().unwrap(), expected);
+        assert_eq!(txpool.get_mempool().await.len(), 2);
+
+        assert!(txpool.expire(102, U256::from(40)).await.is_empty());
+        assert_eq!(
+            txpool.expire(103, U256::from(40)).await,
+            vec![ExpiredTx {
+                hash: H256::repeat_byte(1),
+                gas_price: U256::from(30),
+                floor: U256::from(40),
+                block: 103,
+            }]
+        );
+        assert_eq!(txpool.get_mempool().await[0].hash, H256::repeat_byte(2));
+    }
 
     #[tokio::test]
     async fn test_mempool_stream_alchemy() {
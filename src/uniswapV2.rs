@@ -1,16 +1,20 @@
 use std::{
-    ops::{Add, Mul},
-    sync::Arc,
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use ethers::{
     abi::Token::{self, *},
-    contract::Contract,
+    contract::{Contract, EthEvent, EthLogDecode},
     core::abi::Abi,
-    prelude::{abigen, builders::ContractCall},
-    providers::Middleware,
-    types::{Address, U256},
+    prelude::{abigen, builders::ContractCall, ContractError},
+    providers::{Middleware, PubsubClient},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Filter, Log, ValueOrArray, H256, U256, U64,
+    },
 };
+use futures_util::{future::join_all, Stream, StreamExt};
 use log::{debug, error, warn};
 
 use crate::{
@@ -18,7 +22,8 @@ use crate::{
         protocol::UniswapV2,
         token::{ERC20Lookup, ERC20Token},
     },
-    utils::multicall::Multicall,
+    error::{Error, QuoteError, StateError},
+    utils::{fixed_point::mul_div, multicall::Multicall},
 };
 
 abigen!(
@@ -28,6 +33,65 @@ abigen!(
 abigen!(IUniswapV2Factory, "abis/uniswap/v2/IUniswapV2Factory.json");
 abigen!(IUniswapV2Pair, "abis/uniswap/v2/IUniswapV2Pair.json");
 
+/// pairs per `getReserves` multicall in `get_reserves_many`
+pub const RESERVES_CHUNK_SIZE: usize = 500;
+
+/// the pair events that move reserves or volume
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PairEventKind {
+    Sync(SyncFilter),
+    Swap(SwapFilter),
+    Mint(MintFilter),
+    Burn(BurnFilter),
+}
+
+impl PairEventKind {
+    pub fn topics() -> Vec<H256> {
+        vec![
+            SyncFilter::signature(),
+            SwapFilter::signature(),
+            MintFilter::signature(),
+            BurnFilter::signature(),
+        ]
+    }
+
+    /// `None` for any other event, e.g. `Transfer` of the LP token
+    pub fn decode(log: &Log) -> Option<Self> {
+        let raw = (log.topics.clone(), log.data.to_vec()).into();
+        match IUniswapV2PairEvents::decode_log(&raw).ok()? {
+            IUniswapV2PairEvents::SyncFilter(event) => Some(Self::Sync(event)),
+            IUniswapV2PairEvents::SwapFilter(event) => Some(Self::Swap(event)),
+            IUniswapV2PairEvents::MintFilter(event) => Some(Self::Mint(event)),
+            IUniswapV2PairEvents::BurnFilter(event) => Some(Self::Burn(event)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PairEvent {
+    pub pair: Address,
+    pub block_number: Option<U64>,
+    pub transaction_hash: Option<H256>,
+    pub log_index: Option<U256>,
+    /// set when the log was dropped by a reorg
+    pub removed: bool,
+    pub kind: PairEventKind,
+}
+
+impl PairEvent {
+    pub fn from_log(log: &Log) -> Option<Self> {
+        Some(Self {
+            pair: log.address,
+            block_number: log.block_number,
+            transaction_hash: log.transaction_hash,
+            log_index: log.log_index,
+            removed: log.removed.unwrap_or(false),
+            kind: PairEventKind::decode(log)?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct UniswapV2Pair {
     protocol: UniswapV2,
@@ -68,35 +132,191 @@ impl UniswapV2Pair {
         self.reserve1 = reserve1;
     }
 
-    fn get_amount_out(self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
-        if reserve_in == U256::zero() || reserve_out == U256::zero() {
-            return U256::zero();
-        }
-        // account for each exchange's fees
-        let (numerator_fee_mul, denominator_fee_mul) = match self.protocol {
+    pub fn protocol(&self) -> UniswapV2 {
+        self.protocol
+    }
+
+    pub fn tokens(&self) -> (ERC20Token, ERC20Token) {
+        (self.token0, self.token1)
+    }
+
+    pub fn reserves(&self) -> (U256, U256) {
+        (self.reserve0, self.reserve1)
+    }
+
+    // account for each exchange's fees
+    fn fee_multipliers(&self) -> (u32, u32) {
+        match self.protocol {
             UniswapV2::MESHSWAP => (10000 - self.fees.as_u32(), 10000_u32),
             UniswapV2::POLYCAT => (9976_u32, 10000_u32),
             UniswapV2::APESWAP => (998_u32, 1000_u32),
             _ => (997_u32, 1000_u32),
-        };
-        let amount_in_with_fee: U256 = amount_in.mul(numerator_fee_mul);
-        let numerator: U256 = amount_in_with_fee.mul(reserve_out);
-        let denominator: U256 = reserve_in.mul(denominator_fee_mul).add(amount_in_with_fee);
-        numerator / denominator
+        }
     }
 
+    /// `None` if the pair is empty or the amounts overflow
+    fn get_amount_out(self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return None;
+        }
+        let (numerator_fee_mul, denominator_fee_mul) = self.fee_multipliers();
+        let amount_in_with_fee = amount_in.checked_mul(numerator_fee_mul.into())?;
+        let denominator = reserve_in
+            .checked_mul(denominator_fee_mul.into())?
+            .checked_add(amount_in_with_fee)?;
+        mul_div(amount_in_with_fee, reserve_out, denominator)
+    }
+
+    /// `None` if the pair can't pay out `amount_out` or the amounts overflow
+    fn get_amount_in(self, amount_out: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+        if reserve_in.is_zero() || amount_out >= reserve_out {
+            return None;
+        }
+        let (numerator_fee_mul, denominator_fee_mul) = self.fee_multipliers();
+        let denominator = (reserve_out - amount_out).checked_mul(numerator_fee_mul.into())?;
+        let numerator = amount_out.checked_mul(denominator_fee_mul.into())?;
+        mul_div(reserve_in, numerator, denominator)?.checked_add(U256::one())
+    }
+
+    /// output of the other token for `amount_in` of `token`, 0 if the pair
+    /// can't quote it
     pub fn get_amounts_out(&self, amount_in: U256, token: ERC20Token) -> U256 {
+        let amount_out = if token == self.token0 {
+            self.get_amount_out(amount_in, self.reserve0, self.reserve1)
+        } else {
+            self.get_amount_out(amount_in, self.reserve1, self.reserve0)
+        };
+        amount_out.unwrap_or_default()
+    }
+
+    /// input of the other token needed to get `amount_out` of `token`
+    pub fn get_amounts_in(&self, amount_out: U256, token: ERC20Token) -> Option<U256> {
         if token == self.token0 {
-            return self.get_amount_out(amount_in, self.reserve0, self.reserve1);
+            return self.get_amount_in(amount_out, self.reserve1, self.reserve0);
+        }
+        self.get_amount_in(amount_out, self.reserve0, self.reserve1)
+    }
+}
+
+/// router `getAmountsOut` against the given pairs, one per hop of `path`
+pub fn amounts_out(pairs: &[UniswapV2Pair], amount_in: U256, path: &[ERC20Token]) -> Vec<U256> {
+    let mut amounts = Vec::with_capacity(path.len());
+    amounts.push(amount_in);
+    for (pair, token_in) in pairs.iter().zip(path) {
+        let amount = pair.get_amounts_out(*amounts.last().unwrap(), *token_in);
+        amounts.push(amount);
+    }
+    amounts
+}
+
+/// router `getAmountsIn` against the given pairs, `None` if a hop lacks
+/// liquidity
+pub fn amounts_in(
+    pairs: &[UniswapV2Pair],
+    amount_out: U256,
+    path: &[ERC20Token],
+) -> Option<Vec<U256>> {
+    let mut amounts = vec![amount_out];
+    for (pair, token_out) in pairs.iter().zip(&path[1..]).rev() {
+        amounts.push(pair.get_amounts_in(*amounts.last().unwrap(), *token_out)?);
+    }
+    amounts.reverse();
+    Some(amounts)
+}
+
+/// recipient, slippage and deadline of a router swap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapParams {
+    pub recipient: Address,
+    pub slippage_bps: u64,
+    /// unix timestamp in seconds
+    pub deadline: U256,
+}
+
+impl SwapParams {
+    /// 0.5% slippage, valid for 2 minutes
+    pub fn new(recipient: Address) -> Self {
+        Self {
+            recipient,
+            slippage_bps: 50,
+            deadline: deadline_after(Duration::from_secs(120)),
         }
-        return self.get_amount_out(amount_in, self.reserve1, self.reserve0);
+    }
+
+    pub fn with_slippage_bps(mut self, slippage_bps: u64) -> Self {
+        self.slippage_bps = slippage_bps;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: U256) -> Self {
+        self.deadline = deadline;
+        self
+    }
+}
+
+/// unix timestamp `valid_for` from now
+pub fn deadline_after(valid_for: Duration) -> U256 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    U256::from((now + valid_for).as_secs())
+}
+
+/// `amountOutMin` for a quoted output
+pub fn min_amount_out(expected_out: U256, slippage_bps: u64) -> U256 {
+    let slippage_bps = slippage_bps.min(10000);
+    // never more than `expected_out`, so it always fits
+    mul_div(
+        expected_out,
+        U256::from(10000 - slippage_bps),
+        U256::from(10000u64),
+    )
+    .unwrap_or(expected_out)
+}
+
+/// `amountInMax` for a quoted input, at most twice it as slippage is
+/// clamped to 100%
+pub fn max_amount_in(expected_in: U256, slippage_bps: u64) -> U256 {
+    let slippage_bps = slippage_bps.min(10000);
+    mul_div(
+        expected_in,
+        U256::from(10000 + slippage_bps),
+        U256::from(10000u64),
+    )
+    .unwrap_or(U256::MAX)
+}
+
+/// what's left of `amount` after each token in `tokens` takes its transfer tax
+pub fn after_transfer_taxes(amount: U256, tokens: &[ERC20Token]) -> U256 {
+    tokens.iter().fold(amount, |amount, token| {
+        let tax_bps = token.get_transfer_tax_bps().min(10000);
+        mul_div(amount, U256::from(10000 - tax_bps), U256::from(10000u64)).unwrap_or(amount)
+    })
+}
+
+/// cache key of a pair, tokens sorted by address
+fn pair_key(
+    protocol: UniswapV2,
+    token_a: ERC20Token,
+    token_b: ERC20Token,
+) -> (usize, Address, Address) {
+    let (a, b) = (token_a.get_address(), token_b.get_address());
+    if a < b {
+        (protocol as usize, a, b)
+    } else {
+        (protocol as usize, b, a)
     }
 }
 
+#[derive(Default)]
+struct PairCache {
+    pairs: HashMap<(usize, Address, Address), UniswapV2Pair>,
+    keys: HashMap<Address, (usize, Address, Address)>,
+}
+
 pub struct UniswapV2Client<M> {
     provider: Arc<M>,
     router_mapping: Vec<IUniswapV2Router02<M>>,
     factory_mapping: Vec<IUniswapV2Factory<M>>,
+    pair_cache: RwLock<PairCache>,
 }
 
 impl<M: Middleware> UniswapV2Client<M> {
@@ -121,6 +341,7 @@ impl<M: Middleware> UniswapV2Client<M> {
             provider: provider.clone(),
             router_mapping: router_list,
             factory_mapping: factory_list,
+            pair_cache: RwLock::new(PairCache::default()),
         }
     }
 
@@ -145,12 +366,16 @@ impl<M: Middleware> UniswapV2Client<M> {
     //     address to,
     //     uint256 deadline
     // ) external returns (uint256[] memory amounts);
+    /// `expected_out` is the quoted output, `params.slippage_bps` below it
+    /// becomes `amountOutMin`
     pub fn get_swapExactTokensForTokens_txn(
         &self,
         protocol: UniswapV2,
         token_in: ERC20Token,
         token_out: ERC20Token,
         amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
     ) -> ContractCall<M, Vec<U256>> {
         let router = &self.router_mapping[protocol as usize];
         let path = vec![token_in, token_out]
@@ -159,28 +384,274 @@ impl<M: Middleware> UniswapV2Client<M> {
             .collect();
         return router.swap_exact_tokens_for_tokens(
             amount_in,
-            U256::zero(),
+            min_amount_out(expected_out, params.slippage_bps),
             path,
-            "0x06a92D032d97D5a3c9F550e551B4B6f42518A07B"
-                .parse::<Address>()
-                .unwrap(),
-            U256::from(2670725202_u32),
+            params.recipient,
+            params.deadline,
         );
     }
 
+    // function swapTokensForExactTokens(
+    //     uint256 amountOut,
+    //     uint256 amountInMax,
+    //     address[] calldata path,
+    //     address to,
+    //     uint256 deadline
+    // ) external returns (uint256[] memory amounts);
+    /// `expected_in` is the quoted input, `params.slippage_bps` above it
+    /// becomes `amountInMax`
+    pub fn get_swap_tokens_for_exact_tokens_txn(
+        &self,
+        protocol: UniswapV2,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        amount_out: U256,
+        expected_in: U256,
+        params: &SwapParams,
+    ) -> ContractCall<M, Vec<U256>> {
+        let router = &self.router_mapping[protocol as usize];
+        let path = vec![token_in.get_address(), token_out.get_address()];
+        router.swap_tokens_for_exact_tokens(
+            amount_out,
+            max_amount_in(expected_in, params.slippage_bps),
+            path,
+            params.recipient,
+            params.deadline,
+        )
+    }
+
+    /// Exact input swap through whichever router method the tokens need,
+    /// the fee-on-transfer variant if either side is taxed.
+    pub fn get_swap_exact_in_txn(
+        &self,
+        protocol: UniswapV2,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> TypedTransaction {
+        if token_in.is_taxed() || token_out.is_taxed() {
+            return self
+                .get_swap_exact_tokens_supporting_fee_txn(
+                    protocol,
+                    token_in,
+                    token_out,
+                    amount_in,
+                    expected_out,
+                    params,
+                )
+                .tx;
+        }
+        self.get_swapExactTokensForTokens_txn(
+            protocol,
+            token_in,
+            token_out,
+            amount_in,
+            expected_out,
+            params,
+        )
+        .tx
+    }
+
+    // function swapExactTokensForTokensSupportingFeeOnTransferTokens(
+    //     uint amountIn,
+    //     uint amountOutMin,
+    //     address[] calldata path,
+    //     address to,
+    //     uint deadline
+    // ) external;
+    /// `expected_out` is the untaxed quote, both tokens' transfer taxes are
+    /// taken off before slippage
+    pub fn get_swap_exact_tokens_supporting_fee_txn(
+        &self,
+        protocol: UniswapV2,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> ContractCall<M, ()> {
+        let router = &self.router_mapping[protocol as usize];
+        let expected_out = after_transfer_taxes(expected_out, &[token_in, token_out]);
+        router.swap_exact_tokens_for_tokens_supporting_fee_on_transfer_tokens(
+            amount_in,
+            min_amount_out(expected_out, params.slippage_bps),
+            vec![token_in.get_address(), token_out.get_address()],
+            params.recipient,
+            params.deadline,
+        )
+    }
+
+    /// swaps `amount_in` native MATIC, sent as the txn value, for `token_out`
+    pub fn get_swap_exact_eth_supporting_fee_txn(
+        &self,
+        protocol: UniswapV2,
+        token_out: ERC20Token,
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> ContractCall<M, ()> {
+        let router = &self.router_mapping[protocol as usize];
+        let expected_out = after_transfer_taxes(expected_out, &[token_out]);
+        router
+            .swap_exact_eth_for_tokens_supporting_fee_on_transfer_tokens(
+                min_amount_out(expected_out, params.slippage_bps),
+                vec![ERC20Token::WMATIC.get_address(), token_out.get_address()],
+                params.recipient,
+                params.deadline,
+            )
+            .value(amount_in)
+    }
+
+    /// swaps `amount_in` of `token_in` for native MATIC
+    pub fn get_swap_exact_tokens_for_eth_supporting_fee_txn(
+        &self,
+        protocol: UniswapV2,
+        token_in: ERC20Token,
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> ContractCall<M, ()> {
+        let router = &self.router_mapping[protocol as usize];
+        let expected_out = after_transfer_taxes(expected_out, &[token_in]);
+        router.swap_exact_tokens_for_eth_supporting_fee_on_transfer_tokens(
+            amount_in,
+            min_amount_out(expected_out, params.slippage_bps),
+            vec![token_in.get_address(), ERC20Token::WMATIC.get_address()],
+            params.recipient,
+            params.deadline,
+        )
+    }
+
     pub async fn quote(
         &self,
         protocol: UniswapV2,
         token_in: ERC20Token,
         token_out: ERC20Token,
         amount_in: U256,
-    ) -> U256 {
+    ) -> Result<U256, QuoteError> {
         let result = self
             .get_quote_txn(protocol, token_in, token_out, amount_in)
             .call()
             .await
-            .unwrap();
-        return result[1];
+            .map_err(QuoteError::from)?;
+        Ok(result[1])
+    }
+
+    /// router `getAmountsOut` over rpc, to verify the local quotes
+    pub async fn quote_amounts_out(
+        &self,
+        protocol: UniswapV2,
+        amount_in: U256,
+        path: &[ERC20Token],
+    ) -> Result<Vec<U256>, ContractError<M>> {
+        let router = &self.router_mapping[protocol as usize];
+        let path = path.iter().map(|x| x.get_address()).collect();
+        router.get_amounts_out(amount_in, path).call().await
+    }
+
+    /// router `getAmountsIn` over rpc, to verify the local quotes
+    pub async fn quote_amounts_in(
+        &self,
+        protocol: UniswapV2,
+        amount_out: U256,
+        path: &[ERC20Token],
+    ) -> Result<Vec<U256>, ContractError<M>> {
+        let router = &self.router_mapping[protocol as usize];
+        let path = path.iter().map(|x| x.get_address()).collect();
+        router.get_amounts_in(amount_out, path).call().await
+    }
+
+    /// Loads metadata and reserves of `pairs_list` into the local cache used
+    /// by `get_amounts_out`/`get_amounts_in`. Pairs that don't exist are skipped.
+    pub async fn cache_pairs(
+        &self,
+        pairs_list: Vec<(UniswapV2, ERC20Token, ERC20Token)>,
+    ) -> Result<(), Error> {
+        let pair_addresses = self.get_pair_address_multicall(pairs_list.clone()).await?;
+        let (pairs_list, pair_addresses): (Vec<_>, Vec<Address>) = pairs_list
+            .into_iter()
+            .zip(pair_addresses)
+            .filter(|(_, address)| !address.is_zero())
+            .unzip();
+        let metadatas = self.get_pair_metadata_multicall(&pair_addresses).await?;
+        let reserves = self.get_pair_reserves_multicall(&pair_addresses).await?;
+
+        let mut cache = self.pair_cache.write().unwrap();
+        for (i, (protocol, token_a, token_b)) in pairs_list.into_iter().enumerate() {
+            let (token0, token1, fees) = metadatas[i];
+            let mut pair = UniswapV2Pair::default();
+            pair.update_metadata(protocol, token0, token1, fees);
+            pair.update_reserves(reserves[i].0, reserves[i].1);
+            let key = pair_key(protocol, token_a, token_b);
+            cache.pairs.insert(key, pair);
+            cache.keys.insert(pair_addresses[i], key);
+        }
+        Ok(())
+    }
+
+    /// applies a `Sync` event to the cache, false if the pair isn't cached
+    pub fn update_cached_reserves(
+        &self,
+        pair_address: Address,
+        reserve0: U256,
+        reserve1: U256,
+    ) -> bool {
+        let mut cache = self.pair_cache.write().unwrap();
+        let key = match cache.keys.get(&pair_address) {
+            Some(key) => *key,
+            None => return false,
+        };
+        match cache.pairs.get_mut(&key) {
+            Some(pair) => {
+                pair.update_reserves(reserve0, reserve1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cached_pair(
+        &self,
+        protocol: UniswapV2,
+        token_a: ERC20Token,
+        token_b: ERC20Token,
+    ) -> Option<UniswapV2Pair> {
+        let cache = self.pair_cache.read().unwrap();
+        cache
+            .pairs
+            .get(&pair_key(protocol, token_a, token_b))
+            .copied()
+    }
+
+    fn cached_path(&self, protocol: UniswapV2, path: &[ERC20Token]) -> Option<Vec<UniswapV2Pair>> {
+        path.windows(2)
+            .map(|hop| self.cached_pair(protocol, hop[0], hop[1]))
+            .collect()
+    }
+
+    /// `getAmountsOut` from cached reserves, `None` if a hop isn't cached
+    pub fn get_amounts_out(
+        &self,
+        protocol: UniswapV2,
+        amount_in: U256,
+        path: &[ERC20Token],
+    ) -> Option<Vec<U256>> {
+        let pairs = self.cached_path(protocol, path)?;
+        Some(amounts_out(&pairs, amount_in, path))
+    }
+
+    /// `getAmountsIn` from cached reserves, `None` if a hop isn't cached or
+    /// lacks liquidity
+    pub fn get_amounts_in(
+        &self,
+        protocol: UniswapV2,
+        amount_out: U256,
+        path: &[ERC20Token],
+    ) -> Option<Vec<U256>> {
+        let pairs = self.cached_path(protocol, path)?;
+        amounts_in(&pairs, amount_out, path)
     }
 
     pub async fn get_pair_address(
@@ -188,20 +659,19 @@ impl<M: Middleware> UniswapV2Client<M> {
         protocol: UniswapV2,
         token0: ERC20Token,
         token1: ERC20Token,
-    ) -> Address {
+    ) -> Result<Address, Error> {
         let factory = &self.factory_mapping[protocol as usize];
         let pair_address: Address = factory
             .get_pair(token0.get_address(), token1.get_address())
             .call()
-            .await
-            .unwrap();
-        return pair_address;
+            .await?;
+        Ok(pair_address)
     }
 
     pub async fn get_pair_address_multicall(
         &self,
         pairs_list: Vec<(UniswapV2, ERC20Token, ERC20Token)>,
-    ) -> Vec<Address> {
+    ) -> Result<Vec<Address>, Error> {
         let mut multicall = Multicall::new(self.provider.clone());
 
         for pair in pairs_list {
@@ -249,7 +719,7 @@ impl<M: Middleware> UniswapV2Client<M> {
             multicall.add_call(call);
         }
 
-        let return_data = multicall.call_raw().await;
+        let return_data = multicall.call_raw().await?;
         let mut data: Vec<Address> = Vec::with_capacity(return_data.len());
         for token in return_data {
             let address: Address;
@@ -270,20 +740,20 @@ impl<M: Middleware> UniswapV2Client<M> {
             };
             data.push(address);
         }
-        return data;
+        Ok(data)
     }
 
-    pub async fn get_pair_reserves(&self, pair_address: Address) -> (u128, u128) {
+    pub async fn get_pair_reserves(&self, pair_address: Address) -> Result<(u128, u128), Error> {
         let pair_contract = IUniswapV2Pair::new(pair_address, self.provider.clone());
         let (reserve0, reserve1, _): (u128, u128, u32) =
-            pair_contract.get_reserves().call().await.unwrap();
-        return (reserve0, reserve1);
+            pair_contract.get_reserves().call().await?;
+        Ok((reserve0, reserve1))
     }
 
     pub async fn get_pair_reserves_multicall(
         &self,
         pair_addresses: &Vec<Address>,
-    ) -> Vec<(U256, U256)> {
+    ) -> Result<Vec<(U256, U256)>, Error> {
         let mut multicall = Multicall::new(self.provider.clone());
 
         for pair_address in pair_addresses {
@@ -325,7 +795,7 @@ impl<M: Middleware> UniswapV2Client<M> {
             multicall.add_call(call);
         }
 
-        let return_data: Vec<Option<Vec<Token>>> = multicall.call_raw().await;
+        let return_data: Vec<Option<Vec<Token>>> = multicall.call_raw().await?;
         let mut data: Vec<(U256, U256)> = Vec::new();
         for token in return_data {
             match token {
@@ -347,25 +817,82 @@ impl<M: Middleware> UniswapV2Client<M> {
                 }
             }
         }
-        return data;
+        Ok(data)
+    }
+
+    /// Reserves of every pair in `pair_addresses`, a multicall per
+    /// `RESERVES_CHUNK_SIZE` pairs so hundreds of pairs stay under the
+    /// `eth_call` gas cap. Pairs whose `getReserves` reverts are left out,
+    /// a multicall that fails fails the lot.
+    pub async fn get_reserves_many(
+        &self,
+        pair_addresses: &[Address],
+    ) -> Result<HashMap<Address, (U256, U256)>, Error> {
+        let chunks = join_all(
+            pair_addresses
+                .chunks(RESERVES_CHUNK_SIZE)
+                .map(|chunk| async move {
+                    let mut multicall = Multicall::new(self.provider.clone());
+                    for pair_address in chunk {
+                        let pair = IUniswapV2Pair::new(*pair_address, self.provider.clone());
+                        multicall.add_call(pair.get_reserves());
+                    }
+                    Ok(chunk
+                        .iter()
+                        .zip(multicall.call_raw().await?)
+                        .collect::<Vec<_>>())
+                }),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut reserves = HashMap::with_capacity(pair_addresses.len());
+        for (pair_address, tokens) in chunks.into_iter().flatten() {
+            if let Some(tokens) = tokens {
+                if let (Some(Uint(reserve0)), Some(Uint(reserve1))) =
+                    (tokens.first(), tokens.get(1))
+                {
+                    reserves.insert(*pair_address, (*reserve0, *reserve1));
+                }
+            }
+        }
+        Ok(reserves)
+    }
+
+    /// Sync, Swap, Mint and Burn events of `pairs` as they are mined
+    pub async fn subscribe_pair_events(
+        &self,
+        pairs: Vec<Address>,
+    ) -> Result<impl Stream<Item = PairEvent> + '_, M::Error>
+    where
+        <M as Middleware>::Provider: PubsubClient,
+    {
+        let filter = Filter::new()
+            .address(ValueOrArray::Array(pairs))
+            .topic0(ValueOrArray::Array(PairEventKind::topics()));
+        let stream = self.provider.subscribe_logs(&filter).await?;
+        Ok(stream.filter_map(|log| async move { PairEvent::from_log(&log) }))
     }
 
-    pub async fn get_pair_metadata(&self, pair_address: Address) -> (ERC20Token, ERC20Token, U256) {
+    /// tokens and fee of `pair_address`, which must be of registry tokens
+    pub async fn get_pair_metadata(
+        &self,
+        pair_address: Address,
+    ) -> Result<(ERC20Token, ERC20Token, U256), Error> {
         let pair_contract = IUniswapV2Pair::new(pair_address, self.provider.clone());
-        let token_0_address = pair_contract.token_0().call().await.unwrap();
-        let token_1_address = pair_contract.token_1().call().await.unwrap();
+        let token_0_address = pair_contract.token_0().call().await?;
+        let token_1_address = pair_contract.token_1().call().await?;
         let fees = pair_contract.fee().call().await.unwrap_or(U256::zero());
-        (
-            ERC20Lookup(token_0_address),
-            ERC20Lookup(token_1_address),
-            fees,
-        )
+        let lookup =
+            |address| ERC20Token::from_address(address).ok_or(StateError::UnknownToken(address));
+        Ok((lookup(token_0_address)?, lookup(token_1_address)?, fees))
     }
 
     pub async fn get_pair_metadata_multicall(
         &self,
         pair_addresses: &Vec<Address>,
-    ) -> Vec<(ERC20Token, ERC20Token, U256)> {
+    ) -> Result<Vec<(ERC20Token, ERC20Token, U256)>, Error> {
         let mut multicall0 = Multicall::new(self.provider.clone());
         let mut multicall1 = Multicall::new(self.provider.clone());
         let mut multicall_fees = Multicall::new(self.provider.clone());
@@ -379,9 +906,9 @@ impl<M: Middleware> UniswapV2Client<M> {
             multicall1.add_call(contract_call1);
             multicall_fees.add_call(contract_call_fee);
         }
-        let return_data0: Vec<Option<Vec<Token>>> = multicall0.call_raw().await;
-        let return_data1: Vec<Option<Vec<Token>>> = multicall1.call_raw().await;
-        let return_data_fee: Vec<Option<Vec<Token>>> = multicall_fees.call_raw().await;
+        let return_data0: Vec<Option<Vec<Token>>> = multicall0.call_raw().await?;
+        let return_data1: Vec<Option<Vec<Token>>> = multicall1.call_raw().await?;
+        let return_data_fee: Vec<Option<Vec<Token>>> = multicall_fees.call_raw().await?;
         let mut data: Vec<(ERC20Token, ERC20Token, U256)> = Vec::new();
         for (i, tokens0) in return_data0.into_iter().enumerate() {
             let mut tuple = (ERC20Token::USDC, ERC20Token::USDC, U256::zero());
@@ -436,7 +963,7 @@ impl<M: Middleware> UniswapV2Client<M> {
             }
             data.push(tuple);
         }
-        return data;
+        Ok(data)
     }
 }
 
@@ -445,13 +972,160 @@ mod tests {
     use std::str::FromStr;
     use std::sync::Arc;
 
-    use ethers::providers::{Provider, Ws};
-    use ethers::types::{Address, U256};
+    use ethers::abi::Token;
+    use ethers::providers::{Http, Provider, Ws};
+    use ethers::types::{Address, Bytes, Log, H256, U256};
 
     use crate::constants::protocol::UniswapV2::*;
     use crate::constants::token::ERC20Token::{USDC, USDT, WETH, WMATIC};
+    use crate::utils::fixed_point::mul_div;
+
+    use super::{
+        after_transfer_taxes, amounts_in, amounts_out, max_amount_in, min_amount_out, PairEvent,
+        PairEventKind, SwapParams, SyncFilter, UniswapV2Client, UniswapV2Pair,
+    };
+
+    #[test]
+    fn test_local_amounts_out_in() {
+        let mut usdc_weth = UniswapV2Pair::default();
+        usdc_weth.update_metadata(QUICKSWAP, USDC, WETH, U256::zero());
+        usdc_weth.update_reserves(U256::from(1_000_000), U256::from(500));
+        let mut weth_usdt = UniswapV2Pair::default();
+        weth_usdt.update_metadata(APESWAP, USDT, WETH, U256::zero());
+        weth_usdt.update_reserves(U256::from(2_000_000), U256::from(1_000));
+
+        let path = [USDC, WETH, USDT];
+        let pairs = [usdc_weth, weth_usdt];
+        let out = amounts_out(&pairs, U256::from(10_000), &path);
+        // 10000 * 997 * 500 / (1000000 * 1000 + 10000 * 997)
+        assert_eq!(out[1], U256::from(4));
+        // 4 * 998 * 2000000 / (1000 * 1000 + 4 * 998)
+        assert_eq!(out[2], U256::from(7952));
+
+        let needed = amounts_in(&pairs, out[2], &path).unwrap();
+        assert_eq!(needed[2], out[2]);
+        assert!(needed[0] <= U256::from(10_000));
+        assert_eq!(amounts_out(&pairs, needed[0], &path)[2], out[2]);
+        assert_eq!(amounts_in(&pairs, U256::from(2_000_000), &path), None);
+
+        // quotes that overflow are no quotes rather than a panic
+        let mut deep = UniswapV2Pair::default();
+        deep.update_metadata(QUICKSWAP, USDC, WETH, U256::zero());
+        deep.update_reserves(U256::MAX, U256::MAX);
+        assert_eq!(deep.get_amounts_out(U256::MAX, USDC), U256::zero());
+        assert_eq!(deep.get_amounts_in(U256::MAX - 1, WETH), None);
+    }
+
+    #[test]
+    fn test_slippage_bounds() {
+        assert_eq!(
+            min_amount_out(U256::from(1_000_000), 50),
+            U256::from(995_000)
+        );
+        assert_eq!(
+            max_amount_in(U256::from(1_000_000), 50),
+            U256::from(1_005_000)
+        );
+        assert_eq!(min_amount_out(U256::from(1_000_000), 20_000), U256::zero());
+        assert_eq!(
+            max_amount_in(U256::from(1_000_000), 20_000),
+            U256::from(2_000_000)
+        );
+        assert_eq!(max_amount_in(U256::MAX, u64::MAX), U256::MAX);
+        assert_eq!(
+            min_amount_out(U256::MAX, 50),
+            mul_div(U256::MAX, U256::from(9950), U256::from(10000)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_swap_exact_in_method() {
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let client = UniswapV2Client::new(Arc::new(provider));
+        let params = SwapParams::new(Address::random());
+        let amount = U256::from(1_000_000);
 
-    use super::{UniswapV2Client, UniswapV2Pair};
+        // untaxed tokens go through the plain method
+        let txn = client.get_swap_exact_in_txn(QUICKSWAP, USDC, USDT, amount, amount, &params);
+        let plain =
+            client.get_swapExactTokensForTokens_txn(QUICKSWAP, USDC, USDT, amount, amount, &params);
+        assert_eq!(txn.data(), plain.tx.data());
+
+        let call =
+            client.get_swap_exact_eth_supporting_fee_txn(QUICKSWAP, USDC, amount, amount, &params);
+        assert_eq!(call.tx.value(), Some(&amount));
+        assert_eq!(
+            call.function.name,
+            "swapExactETHForTokensSupportingFeeOnTransferTokens"
+        );
+    }
+
+    #[test]
+    fn test_taxed_swap() {
+        WETH.override_transfer_tax_bps(500);
+        assert!(WETH.is_taxed());
+        let amount = U256::from(1_000_000);
+        assert_eq!(
+            after_transfer_taxes(amount, &[USDC, WETH]),
+            U256::from(950_000)
+        );
+        assert_eq!(
+            after_transfer_taxes(U256::MAX, &[WETH]),
+            mul_div(U256::MAX, U256::from(9500), U256::from(10000)).unwrap()
+        );
+
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let client = UniswapV2Client::new(Arc::new(provider));
+        let params = SwapParams::new(Address::random());
+        let txn = client.get_swap_exact_in_txn(QUICKSWAP, USDC, WETH, amount, amount, &params);
+        let taxed = client.get_swap_exact_tokens_supporting_fee_txn(
+            QUICKSWAP, USDC, WETH, amount, amount, &params,
+        );
+        assert_eq!(txn.data(), taxed.tx.data());
+        assert_eq!(
+            taxed.function.name,
+            "swapExactTokensForTokensSupportingFeeOnTransferTokens"
+        );
+        // 5% tax off the expected output, then 0.5% slippage
+        let inputs = taxed
+            .function
+            .decode_input(&taxed.tx.data().unwrap()[4..])
+            .unwrap();
+        assert_eq!(inputs[1], Token::Uint(U256::from(945_250)));
+    }
+
+    #[test]
+    fn test_decode_pair_events() {
+        let pair = Address::random();
+        let mut log = Log {
+            address: pair,
+            topics: vec![
+                "0x1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1"
+                    .parse::<H256>()
+                    .unwrap(),
+            ],
+            data: Bytes::from_str("0x00000000000000000000000000000000000000000000000000000115f9862b59000000000000000000000000000000000000000000000028f28f6b108a83ce90").unwrap(),
+            ..Default::default()
+        };
+        let event = PairEvent::from_log(&log).unwrap();
+        assert_eq!(event.pair, pair);
+        assert_eq!(
+            event.kind,
+            PairEventKind::Sync(SyncFilter {
+                reserve_0: 0x115f9862b59,
+                reserve_1: 0x28f28f6b108a83ce90,
+            })
+        );
+
+        // lp token Transfer
+        log.topics[0] = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+            .parse::<H256>()
+            .unwrap();
+        log.topics.push(H256::zero());
+        log.topics.push(H256::zero());
+        log.data = Bytes::from(vec![0; 32]);
+        assert_eq!(PairEvent::from_log(&log), None);
+    }
 
     #[tokio::test]
     async fn test_get_pair_address() {
@@ -464,7 +1138,8 @@ mod tests {
         let uniswapV2_client = UniswapV2Client::new(provider_ws);
         let pair_address = uniswapV2_client
             .get_pair_address(SUSHISWAP, USDC, WETH)
-            .await;
+            .await
+            .unwrap();
         assert_eq!(
             Address::from_str("0x34965ba0ac2451a34a0471f04cca3f990b8dea27").unwrap(),
             pair_address
@@ -482,10 +1157,14 @@ mod tests {
         let uniswapV2_client = UniswapV2Client::new(provider_ws);
         let pair_address = uniswapV2_client
             .get_pair_address(SUSHISWAP, USDC, WETH)
-            .await;
+            .await
+            .unwrap();
 
         // TODO - assert_eq! to something here (or add any general check)
-        uniswapV2_client.get_pair_reserves(pair_address).await;
+        uniswapV2_client
+            .get_pair_reserves(pair_address)
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
@@ -502,7 +1181,8 @@ mod tests {
 
         let results = uniswapV2_client
             .get_pair_address_multicall(pairs_list)
-            .await;
+            .await
+            .unwrap();
         println!("{:?}", results);
     }
 
@@ -525,10 +1205,32 @@ mod tests {
         ];
         let result = uniswapV2_client
             .get_pair_reserves_multicall(&pair_addresses)
-            .await;
+            .await
+            .unwrap();
         println!("{:?}", result);
     }
 
+    #[tokio::test]
+    #[ignore = "needs a polygon node at ALCHEMY_POLYGON_RPC_WS_URL"]
+    async fn test_get_reserves_many() {
+        dotenv::dotenv().ok();
+        let rpc_node_ws_url = std::env::var("ALCHEMY_POLYGON_RPC_WS_URL").unwrap();
+
+        let provider_ws = Provider::<Ws>::connect(&rpc_node_ws_url).await.unwrap();
+        let provider_ws = Arc::new(provider_ws);
+
+        let client = UniswapV2Client::new(provider_ws);
+        let pair = "0x34965ba0ac2451a34a0471f04cca3f990b8dea26"
+            .parse::<Address>()
+            .unwrap();
+        let not_a_pair = "0x34965ba0ac2451a34a0471f04cca3f990b8dea27"
+            .parse::<Address>()
+            .unwrap();
+        let result = client.get_reserves_many(&[pair, not_a_pair]).await.unwrap();
+        assert!(result.contains_key(&pair));
+        assert!(!result.contains_key(&not_a_pair));
+    }
+
     #[tokio::test]
     async fn test_get_pair_metadata_multicall() {
         dotenv::dotenv().ok();
@@ -543,7 +1245,8 @@ mod tests {
             .unwrap()];
         let result = uniswapV2_client
             .get_pair_metadata_multicall(&pair_addresses)
-            .await;
+            .await
+            .unwrap();
         println!(
             "{:?}",
             result
@@ -573,16 +1276,24 @@ mod tests {
             // load in pair and save reserve data
             let pair_address = uniswapV2_client
                 .get_pair_address(route.0, route.1, route.2)
-                .await;
+                .await
+                .unwrap();
             let amount_out = uniswapV2_client
                 .quote(route.0, route.1, route.2, amount_in)
-                .await;
+                .await
+                .unwrap();
 
-            let (reserve0, reserve1) = uniswapV2_client.get_pair_reserves(pair_address).await;
+            let (reserve0, reserve1) = uniswapV2_client
+                .get_pair_reserves(pair_address)
+                .await
+                .unwrap();
             let reserve0 = U256::from(reserve0);
             let reserve1 = U256::from(reserve1);
             let mut pair = UniswapV2Pair::default();
-            let (token0, token1, fees) = uniswapV2_client.get_pair_metadata(pair_address).await;
+            let (token0, token1, fees) = uniswapV2_client
+                .get_pair_metadata(pair_address)
+                .await
+                .unwrap();
             pair.update_metadata(route.0, token0, token1, fees);
             pair.update_reserves(reserve0, reserve1);
             let i_amount_out = pair.get_amounts_out(amount_in, route.1);
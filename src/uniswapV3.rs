@@ -1,16 +1,180 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use ethers::{
     abi::{Abi, Token::Uint},
     contract::Contract,
-    prelude::abigen,
+    prelude::{abigen, builders::ContractCall},
     providers::Middleware,
-    types::{Address, U256},
+    types::{Address, Bytes, U256},
 };
+use thiserror::Error;
 
-use crate::{constants::token::ERC20Token, utils::multicall::Multicall};
+use crate::{
+    constants::{protocol::UNISWAP_V3, token::ERC20Token},
+    error::QuoteError,
+    uniswapV2::{min_amount_out, SwapParams},
+    utils::{
+        fixed_point::{to_f64, Q96},
+        multicall::Multicall,
+    },
+};
 
 abigen!(Quoter, "abis/uniswap/v3/Quoter.json");
+abigen!(
+    SwapRouter,
+    r#"[
+        struct ExactInputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }
+        struct ExactInputParams { bytes path; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; }
+        function exactInputSingle(ExactInputSingleParams calldata params) external payable returns (uint256 amountOut)
+        function exactInput(ExactInputParams calldata params) external payable returns (uint256 amountOut)
+    ]"#,
+);
+
+abigen!(
+    UniswapV3Pool,
+    r#"[
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked)
+        function liquidity() external view returns (uint128)
+        function tickSpacing() external view returns (int24)
+        function tickBitmap(int16 wordPosition) external view returns (uint256)
+        function ticks(int24 tick) external view returns (uint128 liquidityGross, int128 liquidityNet, uint256 feeGrowthOutside0X128, uint256 feeGrowthOutside1X128, int56 tickCumulativeOutside, uint160 secondsPerLiquidityOutsideX128, uint32 secondsOutside, bool initialized)
+    ]"#,
+);
+abigen!(
+    UniswapV3Factory,
+    r#"[
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool)
+    ]"#,
+);
+
+/// fee tiers quoted
+pub const FEE_TIERS: [u32; 2] = [500, 3000];
+
+/// A deployed pool, what of it never changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct V3Pool {
+    pub address: Address,
+    pub tick_spacing: i32,
+}
+
+/// Where the liquidity range a pool is trading in ends, in the direction of
+/// a swap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TickBoundary {
+    pub tick: i32,
+    /// amount in that moves the price right up to `tick`, fee included
+    pub amount_in: U256,
+    pub liquidity: u128,
+    /// active liquidity past `tick`
+    pub next_liquidity: u128,
+}
+
+impl TickBoundary {
+    /// the range past the boundary has less than `thin_ratio` of the
+    /// current liquidity
+    pub fn is_thin(&self, thin_ratio: f64) -> bool {
+        (self.next_liquidity as f64) < self.liquidity as f64 * thin_ratio
+    }
+}
+
+fn compress_tick(tick: i32, tick_spacing: i32) -> i32 {
+    tick.div_euclid(tick_spacing)
+}
+
+/// `tickBitmap` word holding the next tick `next_initialized_tick` looks at
+pub fn bitmap_word(tick: i32, tick_spacing: i32, lte: bool) -> i16 {
+    let compressed = compress_tick(tick, tick_spacing) + if lte { 0 } else { 1 };
+    (compressed >> 8) as i16
+}
+
+/// Port of `TickBitmap.nextInitializedTickWithinOneWord`: the next
+/// initialized tick at or below `tick` (`lte`) or above it, and whether
+/// there was one in `word` at all. Without one, the word's last tick.
+pub fn next_initialized_tick(word: U256, tick: i32, tick_spacing: i32, lte: bool) -> (i32, bool) {
+    let compressed = compress_tick(tick, tick_spacing);
+    if lte {
+        let bit = compressed.rem_euclid(256) as usize;
+        let mask = (U256::one() << bit) - 1 + (U256::one() << bit);
+        let masked = word & mask;
+        if masked.is_zero() {
+            ((compressed - bit as i32) * tick_spacing, false)
+        } else {
+            let msb = masked.bits() - 1;
+            ((compressed - (bit - msb) as i32) * tick_spacing, true)
+        }
+    } else {
+        let compressed = compressed + 1;
+        let bit = compressed.rem_euclid(256) as usize;
+        let mask = !((U256::one() << bit) - 1);
+        let masked = word & mask;
+        if masked.is_zero() {
+            ((compressed + (255 - bit) as i32) * tick_spacing, false)
+        } else {
+            let lsb = masked.trailing_zeros() as usize;
+            ((compressed + (lsb - bit) as i32) * tick_spacing, true)
+        }
+    }
+}
+
+/// Amount in, fee included, that moves a pool at `sqrt_price_x96` with
+/// `liquidity` to `tick`. Floating point, good for sizing, not for quotes.
+pub fn amount_in_to_tick(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    tick: i32,
+    zero_for_one: bool,
+    fee: u32,
+) -> U256 {
+    let sqrt_price = to_f64(sqrt_price_x96) / to_f64(Q96);
+    let sqrt_target = 1.0001f64.powf(tick as f64 / 2.0);
+    let liquidity = liquidity as f64;
+    let amount = if zero_for_one {
+        liquidity * (1.0 / sqrt_target - 1.0 / sqrt_price)
+    } else {
+        liquidity * (sqrt_target - sqrt_price)
+    };
+    let amount = amount.max(0.0) / (1.0 - fee as f64 / 1e6);
+    if amount >= 1e38 {
+        return U256::from(u128::MAX);
+    }
+    U256::from(amount as u128)
+}
+
+/// A multi-hop path the router can't be given.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PathError {
+    #[error("{tokens} tokens need one fee tier per hop, got {fees}")]
+    FeeCount { tokens: usize, fees: usize },
+
+    /// fee tiers are encoded in 3 bytes
+    #[error("fee tier {0} is over 24 bits")]
+    Fee(u32),
+}
+
+/// `token0 ++ fee0 ++ token1 ++ ... ++ tokenN`, fees as 3 byte big endian
+/// integers, `fees[i]` being the fee tier between `tokens[i]` and `tokens[i + 1]`
+pub fn encode_v3_path(tokens: &[Address], fees: &[u32]) -> Result<Bytes, PathError> {
+    if tokens.len() != fees.len() + 1 {
+        return Err(PathError::FeeCount {
+            tokens: tokens.len(),
+            fees: fees.len(),
+        });
+    }
+    if let Some(fee) = fees.iter().find(|fee| **fee >= 1 << 24) {
+        return Err(PathError::Fee(*fee));
+    }
+    let mut path = Vec::with_capacity(tokens.len() * 20 + fees.len() * 3);
+    for (i, token) in tokens.iter().enumerate() {
+        path.extend_from_slice(token.as_bytes());
+        if let Some(fee) = fees.get(i) {
+            path.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+    }
+    Ok(Bytes::from(path))
+}
 
 static QUOTE_ABI_STR: &str = r#"[{
     "inputs": [
@@ -54,8 +218,12 @@ static QUOTE_ABI_STR: &str = r#"[{
 
 pub struct UniswapV3Client<M> {
     provider: Arc<M>,
+    factory: UniswapV3Factory<M>,
     quoter: Quoter<M>,
     quote_contract: Contract<M>,
+    router: SwapRouter<M>,
+    /// by token addresses sorted and fee, `None` for tiers without a pool
+    pools: RwLock<HashMap<(Address, Address, u32), Option<V3Pool>>>,
 }
 
 impl<M: Middleware + Clone> UniswapV3Client<M> {
@@ -66,8 +234,11 @@ impl<M: Middleware + Clone> UniswapV3Client<M> {
         let quote_abi: Abi = serde_json::from_str(QUOTE_ABI_STR).unwrap();
         Self {
             provider: provider.clone(),
+            factory: UniswapV3Factory::new(UNISWAP_V3.factory_address, provider.clone()),
             quoter: Quoter::new(router_address, provider.clone()),
             quote_contract: Contract::new(router_address, quote_abi, provider.clone()),
+            router: SwapRouter::new(UNISWAP_V3.router_address, provider.clone()),
+            pools: RwLock::new(HashMap::new()),
         }
     }
 
@@ -77,7 +248,7 @@ impl<M: Middleware + Clone> UniswapV3Client<M> {
         token_out: ERC20Token,
         amount_in: U256,
         fee: u32,
-    ) -> U256 {
+    ) -> Result<U256, QuoteError> {
         let amount_out = self
             .quoter
             .quote_exact_input_single(
@@ -89,18 +260,145 @@ impl<M: Middleware + Clone> UniswapV3Client<M> {
             )
             .call()
             .await
-            .unwrap();
-        amount_out
+            .map_err(QuoteError::from)?;
+        Ok(amount_out)
+    }
+
+    /// `expected_out` is the quoted output, `params.slippage_bps` below it
+    /// becomes `amountOutMinimum`
+    pub fn get_exact_input_single_txn(
+        &self,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        fee: u32,
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> ContractCall<M, U256> {
+        self.router.exact_input_single(ExactInputSingleParams {
+            token_in: token_in.get_address(),
+            token_out: token_out.get_address(),
+            fee,
+            recipient: params.recipient,
+            deadline: params.deadline,
+            amount_in,
+            amount_out_minimum: min_amount_out(expected_out, params.slippage_bps),
+            sqrt_price_limit_x96: U256::zero(),
+        })
     }
 
-    /// Returns best quote, returns fee where quote exists
+    /// multi-hop swap along `path`, `fees[i]` being the fee tier of the pool
+    /// between `path[i]` and `path[i + 1]`
+    pub fn get_exact_input_txn(
+        &self,
+        path: &[ERC20Token],
+        fees: &[u32],
+        amount_in: U256,
+        expected_out: U256,
+        params: &SwapParams,
+    ) -> Result<ContractCall<M, U256>, PathError> {
+        let tokens: Vec<Address> = path.iter().map(|x| x.get_address()).collect();
+        Ok(self.router.exact_input(ExactInputParams {
+            path: encode_v3_path(&tokens, fees)?,
+            recipient: params.recipient,
+            deadline: params.deadline,
+            amount_in,
+            amount_out_minimum: min_amount_out(expected_out, params.slippage_bps),
+        }))
+    }
+
+    /// The `fee` pool of the pair, from the factory the first time and from
+    /// memory after. `None` if there's none, or the calls failed (asked
+    /// again next time).
+    pub async fn pool(
+        &self,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        fee: u32,
+    ) -> Option<V3Pool> {
+        let (token_in, token_out) = (token_in.get_address(), token_out.get_address());
+        let key = match token_in < token_out {
+            true => (token_in, token_out, fee),
+            false => (token_out, token_in, fee),
+        };
+        if let Some(pool) = self.pools.read().unwrap().get(&key) {
+            return *pool;
+        }
+        let address = self
+            .factory
+            .get_pool(token_in, token_out, fee)
+            .call()
+            .await
+            .ok()?;
+        let pool = match address.is_zero() {
+            true => None,
+            false => Some(V3Pool {
+                address,
+                tick_spacing: UniswapV3Pool::new(address, self.provider.clone())
+                    .tick_spacing()
+                    .call()
+                    .await
+                    .ok()?,
+            }),
+        };
+        self.pools.write().unwrap().insert(key, pool);
+        pool
+    }
+
+    /// Where the current liquidity range of the `fee` pool of the pair ends
+    /// for a swap of `token_in`, `None` if the pool doesn't exist or a call
+    /// fails.
+    pub async fn tick_boundary(
+        &self,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        fee: u32,
+    ) -> Option<TickBoundary> {
+        let V3Pool {
+            address,
+            tick_spacing,
+        } = self.pool(token_in, token_out, fee).await?;
+        let (token_in, token_out) = (token_in.get_address(), token_out.get_address());
+        let pool = UniswapV3Pool::new(address, self.provider.clone());
+        let (sqrt_price_x96, tick, ..) = pool.slot_0().call().await.ok()?;
+        let liquidity = pool.liquidity().call().await.ok()?;
+
+        // token0 in pushes the price, and the tick, down
+        let zero_for_one = token_in < token_out;
+        let word = pool
+            .tick_bitmap(bitmap_word(tick, tick_spacing, zero_for_one))
+            .call()
+            .await
+            .ok()?;
+        let (boundary, initialized) = next_initialized_tick(word, tick, tick_spacing, zero_for_one);
+        let next_liquidity = if initialized {
+            let (_, liquidity_net, ..) = pool.ticks(boundary).call().await.ok()?;
+            let liquidity_net = if zero_for_one {
+                -liquidity_net
+            } else {
+                liquidity_net
+            };
+            (liquidity as i128 + liquidity_net).max(0) as u128
+        } else {
+            liquidity
+        };
+        Some(TickBoundary {
+            tick: boundary,
+            amount_in: amount_in_to_tick(sqrt_price_x96, liquidity, boundary, zero_for_one, fee),
+            liquidity,
+            next_liquidity,
+        })
+    }
+
+    /// Returns best quote, returns fee where quote exists. Tiers without a
+    /// pool quote zero, only a failed multicall is an error.
     pub async fn quote_multicall(
         &self,
         token_in: ERC20Token,
         token_out: ERC20Token,
         amount_in: U256,
-    ) -> (u32, U256) {
-        let fees: [u32; 2] = [500, 3000];
+    ) -> Result<(u32, U256), QuoteError> {
+        let fees = FEE_TIERS;
         let mut multicall = Multicall::new(self.provider.clone());
 
         for fee in fees {
@@ -120,7 +418,7 @@ impl<M: Middleware + Clone> UniswapV3Client<M> {
             multicall.add_call(call);
         }
 
-        let return_data = multicall.call_raw().await;
+        let return_data = multicall.call_raw().await?;
         let mut amount_outs: [(u32, U256); 2] = [
             (fees[0], U256::zero()),
             (fees[1], U256::zero()),
@@ -142,10 +440,12 @@ impl<M: Middleware + Clone> UniswapV3Client<M> {
             }
         }
 
-        *amount_outs
-            .iter()
-            .max_by(|(_, a), (_, b)| a.cmp(b))
-            .unwrap()
+        Ok(amount_outs
+            .into_iter()
+            .fold(amount_outs[0], |best, quote| match quote.1 >= best.1 {
+                true => quote,
+                false => best,
+            }))
     }
 }
 
@@ -155,11 +455,105 @@ mod tests {
 
     use ethers::{
         providers::{Http, Provider, Ws},
-        types::U256,
+        types::{Address, U256},
     };
 
-    use super::UniswapV3Client;
+    use super::{
+        amount_in_to_tick, bitmap_word, encode_v3_path, next_initialized_tick, PathError,
+        UniswapV3Client,
+    };
+    use crate::constants::protocol::UNISWAP_V3;
     use crate::constants::token::ERC20Token::{DAI, USDC, USDT, WETH};
+    use crate::uniswapV2::SwapParams;
+    use crate::utils::calldata::{DecoderRegistry, Intent, SwapAmount};
+
+    #[test]
+    fn test_exact_input_calldata() {
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let client = UniswapV3Client::new(Arc::new(provider));
+        let recipient = Address::random();
+        let params = SwapParams::new(recipient).with_slippage_bps(100);
+        let call = client
+            .get_exact_input_txn(
+                &[USDC, WETH, DAI],
+                &[500, 3000],
+                U256::from(1_000_000),
+                U256::from(2_000_000),
+                &params,
+            )
+            .unwrap();
+        assert_eq!(call.tx.to_addr(), Some(&UNISWAP_V3.router_address));
+
+        let intent = DecoderRegistry::polygon()
+            .decode(
+                UNISWAP_V3.router_address,
+                call.tx.data().unwrap(),
+                U256::zero(),
+            )
+            .unwrap();
+        match intent {
+            Intent::Swap(swap) => {
+                assert_eq!(
+                    swap.path,
+                    vec![USDC.get_address(), WETH.get_address(), DAI.get_address()]
+                );
+                assert_eq!(swap.recipient, recipient);
+                assert_eq!(
+                    swap.amount,
+                    SwapAmount::ExactIn {
+                        amount_in: U256::from(1_000_000),
+                        amount_out_min: U256::from(1_980_000),
+                    }
+                );
+            }
+            intent => panic!("unexpected intent {:?}", intent),
+        }
+
+        let tokens = [USDC.get_address(), WETH.get_address()];
+        assert_eq!(
+            encode_v3_path(&tokens, &[500, 3000]),
+            Err(PathError::FeeCount { tokens: 2, fees: 2 })
+        );
+        assert_eq!(
+            encode_v3_path(&tokens, &[1 << 24]),
+            Err(PathError::Fee(1 << 24))
+        );
+    }
+
+    #[test]
+    fn test_next_initialized_tick() {
+        // ticks 60 and 600 initialized at spacing 60: bits 1 and 10 of word 0
+        let word = (U256::one() << 1) | (U256::one() << 10);
+        assert_eq!(next_initialized_tick(word, 100, 60, true), (60, true));
+        assert_eq!(next_initialized_tick(word, 100, 60, false), (600, true));
+        // nothing above 600 in the word, stop at its last tick
+        assert_eq!(
+            next_initialized_tick(word, 600, 60, false),
+            (255 * 60, false)
+        );
+        // negative ticks round down before compressing
+        assert_eq!(bitmap_word(-1, 60, true), -1);
+        assert_eq!(
+            next_initialized_tick(U256::zero(), -1, 60, true),
+            (-256 * 60, false)
+        );
+    }
+
+    #[test]
+    fn test_amount_in_to_tick() {
+        // price 1, moving to tick 200 (price ~1.0202) with 1e18 liquidity
+        // takes ~1e16 of token1
+        let sqrt_price_x96 = U256::one() << 96;
+        let liquidity = 1_000_000_000_000_000_000u128;
+        let amount = amount_in_to_tick(sqrt_price_x96, liquidity, 200, false, 0);
+        let expected = 1e18 * (1.0001f64.powf(100.0) - 1.0);
+        assert!((amount.as_u128() as f64 - expected).abs() / expected < 1e-9);
+        // fees are paid on top
+        let with_fee = amount_in_to_tick(sqrt_price_x96, liquidity, 200, false, 3000);
+        assert!(with_fee > amount);
+        // the wrong direction takes nothing
+        assert!(amount_in_to_tick(sqrt_price_x96, liquidity, 200, true, 0).is_zero());
+    }
 
     #[tokio::test]
     async fn test_quote() {
@@ -177,7 +571,8 @@ mod tests {
 
         let amounts_out = uniswapV3_client
             .quote(token_in, token_out, amount_in, fee)
-            .await;
+            .await
+            .unwrap();
         println!("{}", amounts_out);
     }
 
@@ -198,7 +593,8 @@ mod tests {
 
         let amounts_out = uniswapV3_client
             .quote_multicall(token_in, token_out, amount_in)
-            .await;
+            .await
+            .unwrap();
         println!("{:?}", amounts_out);
     }
 }
@@ -0,0 +1,352 @@
+//! An Anvil fork of Polygon for integration tests: the executor contracts
+//! deployed on it, test wallets funded, and cheatcodes for moving the fork
+//! around. Needs `anvil` on the PATH.
+
+use std::{error::Error, process::Command, sync::Arc};
+
+use ethers::{
+    abi::{encode, Token},
+    prelude::{abigen, SignerMiddleware},
+    providers::{Http, Middleware, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, H256, U256},
+    utils::{keccak256, Anvil, AnvilInstance},
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    arb_params::Flashloan,
+    liquidator::{Liquidations, AAVE_V3_POOL},
+};
+
+abigen!(
+    IERC20Balance,
+    r#"[
+        function balanceOf(address owner) external view returns (uint256)
+    ]"#,
+);
+
+/// block the fork is pinned to, so tests see the same pools every run
+pub const FORK_BLOCK: u64 = 38_000_000;
+/// storage slots probed for a token's balance mapping in `deal`
+const MAX_BALANCE_SLOT: u64 = 20;
+
+lazy_static! {
+    pub static ref BALANCER_VAULT: Address = "0xBA12222222228d8Ba445958a75a0704d566BF2C8"
+        .parse::<Address>()
+        .unwrap();
+}
+
+pub type ForkClient = SignerMiddleware<Arc<Provider<Http>>, LocalWallet>;
+
+pub struct ForkHarness {
+    // killed on drop
+    anvil: AnvilInstance,
+    pub provider: Arc<Provider<Http>>,
+    /// anvil's dev accounts, each starting with 10000 MATIC
+    pub wallets: Vec<LocalWallet>,
+    /// signs as `wallets[0]`, which owns the deployed contracts
+    pub client: Arc<ForkClient>,
+    pub flashloan: Flashloan<ForkClient>,
+    pub liquidations: Liquidations<ForkClient>,
+}
+
+impl ForkHarness {
+    /// forks `fork_url` at `block_number` and deploys the executors on it
+    pub async fn spawn(fork_url: &str, block_number: u64) -> Result<Self, Box<dyn Error>> {
+        let anvil = Anvil::new()
+            .fork(fork_url)
+            .fork_block_number(block_number)
+            .chain_id(137u64)
+            .spawn();
+        let provider = Arc::new(Provider::<Http>::try_from(anvil.endpoint())?);
+        let wallets: Vec<LocalWallet> = anvil
+            .keys()
+            .iter()
+            .map(|key| LocalWallet::from(key.clone()).with_chain_id(anvil.chain_id()))
+            .collect();
+        let client = Arc::new(SignerMiddleware::new(provider.clone(), wallets[0].clone()));
+
+        let flashloan = Flashloan::deploy(client.clone(), *BALANCER_VAULT)?
+            .send()
+            .await?;
+        let liquidations = Liquidations::deploy(client.clone(), *AAVE_V3_POOL)?
+            .send()
+            .await?;
+        Ok(Self {
+            anvil,
+            provider,
+            wallets,
+            client,
+            flashloan,
+            liquidations,
+        })
+    }
+
+    /// Forks `ALCHEMY_POLYGON_RPC_URL` at `FORK_BLOCK`, an error if that
+    /// isn't set or anvil isn't installed.
+    pub async fn from_env() -> Result<Self, Box<dyn Error>> {
+        let fork_url = std::env::var("ALCHEMY_POLYGON_RPC_URL")
+            .map_err(|_| "ALCHEMY_POLYGON_RPC_URL isn't set")?;
+        if Command::new("anvil").arg("--version").output().is_err() {
+            return Err("anvil isn't on the PATH".into());
+        }
+        Self::spawn(&fork_url, FORK_BLOCK).await
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.anvil.endpoint()
+    }
+
+    pub fn ws_endpoint(&self) -> String {
+        self.anvil.ws_endpoint()
+    }
+
+    /// a client signing as `wallets[index]`
+    pub fn client_for(&self, index: usize) -> Arc<ForkClient> {
+        Arc::new(SignerMiddleware::new(
+            self.provider.clone(),
+            self.wallets[index].clone(),
+        ))
+    }
+
+    pub async fn set_balance(&self, address: Address, balance: U256) -> Result<(), Box<dyn Error>> {
+        self.provider
+            .request::<_, ()>("anvil_setBalance", (address, balance))
+            .await?;
+        Ok(())
+    }
+
+    /// Sets `to`'s balance of `token` to `amount` by finding the token's
+    /// balance mapping and writing to it, no whale needed.
+    pub async fn deal(
+        &self,
+        token: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<(), Box<dyn Error>> {
+        let contract = IERC20Balance::new(token, self.provider.clone());
+        let mut value = H256::zero();
+        amount.to_big_endian(value.as_bytes_mut());
+        for slot in 0..MAX_BALANCE_SLOT {
+            let key = H256::from(keccak256(encode(&[
+                Token::Address(to),
+                Token::Uint(slot.into()),
+            ])));
+            let previous = self.provider.get_storage_at(token, key, None).await?;
+            self.set_storage_at(token, key, value).await?;
+            if contract.balance_of(to).call().await? == amount {
+                return Ok(());
+            }
+            self.set_storage_at(token, key, previous).await?;
+        }
+        Err(format!(
+            "no balance mapping of {:?} in its first {} slots",
+            token, MAX_BALANCE_SLOT
+        )
+        .into())
+    }
+
+    pub async fn set_storage_at(
+        &self,
+        address: Address,
+        slot: H256,
+        value: H256,
+    ) -> Result<(), Box<dyn Error>> {
+        self.provider
+            .request::<_, bool>("anvil_setStorageAt", (address, slot, value))
+            .await?;
+        Ok(())
+    }
+
+    /// lets unsigned txns from `address` through, e.g. a victim's swap
+    pub async fn impersonate(&self, address: Address) -> Result<(), Box<dyn Error>> {
+        self.provider
+            .request::<_, ()>("anvil_impersonateAccount", [address])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn stop_impersonating(&self, address: Address) -> Result<(), Box<dyn Error>> {
+        self.provider
+            .request::<_, ()>("anvil_stopImpersonatingAccount", [address])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn mine(&self, blocks: u64) -> Result<(), Box<dyn Error>> {
+        self.provider
+            .request::<_, ()>("anvil_mine", [U256::from(blocks)])
+            .await?;
+        Ok(())
+    }
+
+    /// id to `revert_to`, each test case can start from the same state
+    pub async fn snapshot(&self) -> Result<U256, Box<dyn Error>> {
+        Ok(self.provider.request("evm_snapshot", ()).await?)
+    }
+
+    pub async fn revert_to(&self, snapshot: U256) -> Result<bool, Box<dyn Error>> {
+        Ok(self.provider.request("evm_revert", [snapshot]).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Ws,
+        types::{TransactionRequest, U64},
+    };
+
+    use super::*;
+    use crate::{
+        arb_params::{probe_executor, ArbParamsBuilder},
+        constants::{
+            protocol::UniswapV2,
+            token::ERC20Token::{self, DAI, USDC, USDT, WBTC, WETH, WMATIC},
+        },
+        uniswapV2::IUniswapV2Router02,
+        utils::fixed_point::whole_units,
+        world::{Protocol, WorldState},
+    };
+
+    abigen!(
+        IERC20Approve,
+        r#"[
+            function approve(address spender, uint256 amount) external returns (bool)
+        ]"#,
+    );
+
+    const TOKENS: [ERC20Token; 6] = [USDC, USDT, DAI, WBTC, WMATIC, WETH];
+
+    async fn harness() -> ForkHarness {
+        dotenv::dotenv().ok();
+        ForkHarness::from_env().await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and ALCHEMY_POLYGON_RPC_URL"]
+    async fn test_fork_harness() {
+        let harness = harness().await;
+        let owner = harness.wallets[0].address();
+        assert_eq!(harness.flashloan.owner().call().await.unwrap(), owner);
+        assert_eq!(harness.liquidations.owner().call().await.unwrap(), owner);
+
+        let snapshot = harness.snapshot().await.unwrap();
+        let amount = U256::from(1_000_000_000u64);
+        harness
+            .deal(USDC.get_address(), owner, amount)
+            .await
+            .unwrap();
+        let usdc = IERC20Balance::new(USDC.get_address(), harness.provider.clone());
+        assert_eq!(usdc.balance_of(owner).call().await.unwrap(), amount);
+        assert!(harness.revert_to(snapshot).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and ALCHEMY_POLYGON_RPC_URL"]
+    async fn test_unprofitable_arbitrage_reverts() {
+        let harness = harness().await;
+        let block_number = harness.provider.get_block_number().await.unwrap();
+        let route = ArbParamsBuilder::new(whole_units(1000, USDC.get_decimals()).unwrap(), USDC)
+            .hop(
+                WETH,
+                Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+                U256::zero(),
+            )
+            .hop(
+                USDC,
+                Protocol::UniswapV2(UniswapV2::QUICKSWAP),
+                U256::zero(),
+            )
+            .build();
+        let call = harness
+            .flashloan
+            .execute_arbitrage(route.params, (block_number.as_u64() + 1).into());
+        assert!(call.call().await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and ALCHEMY_POLYGON_RPC_URL"]
+    async fn test_detected_arbitrage_executes() {
+        let harness = harness().await;
+        // dumping WETH on Sushiswap makes it cheap there
+        let whale = harness.client_for(1);
+        let weth_in = whole_units(500, WETH.get_decimals()).unwrap();
+        let sushiswap = UniswapV2::SUSHISWAP.get_router_address();
+        harness
+            .deal(WETH.get_address(), whale.address(), weth_in)
+            .await
+            .unwrap();
+        IERC20Approve::new(WETH.get_address(), whale.clone())
+            .approve(sushiswap, weth_in)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        IUniswapV2Router02::new(sushiswap, whale.clone())
+            .swap_exact_tokens_for_tokens(
+                weth_in,
+                U256::zero(),
+                vec![WETH.get_address(), USDC.get_address()],
+                whale.address(),
+                U256::MAX,
+            )
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let stream_provider = Provider::<Ws>::connect(harness.ws_endpoint())
+            .await
+            .unwrap();
+        let ws = Arc::new(
+            WorldState::init(
+                harness.provider.clone(),
+                stream_provider,
+                TOKENS.to_vec(),
+                UniswapV2::get_all_protoccols(),
+            )
+            .await
+            .unwrap(),
+        );
+        let amount_in = whole_units(1000, USDC.get_decimals()).unwrap();
+        let token_path = vec![USDC, WETH, USDC];
+        let (amounts_out, protocols) = ws
+            .clone()
+            .compute_best_route_hops(token_path.clone(), amount_in)
+            .await;
+        assert!(*amounts_out.last().unwrap() > amount_in);
+        assert_eq!(
+            protocols[0],
+            Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+            "should buy the cheap WETH"
+        );
+
+        let features = probe_executor(harness.provider.as_ref(), harness.flashloan.address())
+            .await
+            .unwrap();
+        let route = ArbParamsBuilder::from_route(amount_in, &token_path, &protocols, &amounts_out)
+            .slippage_bps(30)
+            .build();
+        let block_number = harness.provider.get_block_number().await.unwrap();
+        let owner = harness.client.address();
+        let usdc = IERC20Balance::new(USDC.get_address(), harness.provider.clone());
+        let before = usdc.balance_of(owner).call().await.unwrap();
+        let tx = TransactionRequest::new()
+            .to(harness.flashloan.address())
+            .data(route.calldata(&features, (block_number.as_u64() + 1).into()));
+        let receipt = harness
+            .client
+            .send_transaction(tx, None)
+            .await
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.status, Some(U64::one()));
+        assert!(usdc.balance_of(owner).call().await.unwrap() > before);
+    }
+}
@@ -0,0 +1,325 @@
+//! Bounded channel for the transports, with a policy for a message sent to
+//! a full one: wait for room, drop the oldest queued message, or fail.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures_util::stream::{FusedStream, Stream};
+use thiserror::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// the sender waits for room
+    Block,
+    /// the oldest queued message is dropped to make room
+    DropOldest,
+    /// the send fails with `SendError::Full`
+    Error,
+}
+
+/// how many messages a channel holds and what happens to one more
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backpressure {
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Backpressure {
+    /// `None` for a capacity of 0, a channel that never takes a message
+    pub fn new(capacity: usize, overflow: Overflow) -> Option<Self> {
+        (capacity > 0).then_some(Self { capacity, overflow })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum SendError {
+    #[error("channel full")]
+    Full,
+
+    #[error("channel closed")]
+    Closed,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiving: bool,
+    /// messages dropped by `Overflow::DropOldest`
+    dropped: u64,
+    receiver: Option<Waker>,
+    /// sends waiting for room, one waker each
+    blocked: HashMap<u64, Waker>,
+    next_send: u64,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    backpressure: Backpressure,
+    state: Mutex<State<T>>,
+}
+
+pub fn channel<T>(backpressure: Backpressure) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        backpressure,
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(backpressure.capacity.min(1024)),
+            senders: 1,
+            receiving: true,
+            dropped: 0,
+            receiver: None,
+            blocked: HashMap::new(),
+            next_send: 0,
+        }),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            terminated: false,
+        },
+    )
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `msg`, waiting for room if the channel blocks.
+    pub async fn send(&self, msg: T) -> Result<(), SendError> {
+        let waiting = Waiting::new(&self.shared);
+        let mut msg = Some(msg);
+        poll_fn(|cx| match self.push(&mut msg, Some((waiting.id, cx))) {
+            Err(SendError::Full) if self.shared.backpressure.overflow == Overflow::Block => {
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        })
+        .await
+    }
+
+    /// Queues `msg` if it fits, a channel that blocks fails with
+    /// `SendError::Full` instead of waiting.
+    pub fn try_send(&self, msg: T) -> Result<(), SendError> {
+        self.push(&mut Some(msg), None)
+    }
+
+    /// takes `msg` if it's queued, registering `cx` as the waker of send
+    /// `id` to be woken when a full channel has room
+    fn push(
+        &self,
+        msg: &mut Option<T>,
+        cx: Option<(u64, &mut Context<'_>)>,
+    ) -> Result<(), SendError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.receiving {
+            return Err(SendError::Closed);
+        }
+        if state.queue.len() >= self.shared.backpressure.capacity {
+            match self.shared.backpressure.overflow {
+                Overflow::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                Overflow::Block => {
+                    if let Some((id, cx)) = cx {
+                        state.blocked.insert(id, cx.waker().clone());
+                    }
+                    return Err(SendError::Full);
+                }
+                Overflow::Error => return Err(SendError::Full),
+            }
+        }
+        state.queue.extend(msg.take());
+        if let Some(waker) = state.receiver.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.shared.state.lock().unwrap().receiving
+    }
+
+    /// messages dropped to make room so far
+    pub fn dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped
+    }
+}
+
+/// A pending send, whose waker is forgotten once it's done or dropped.
+struct Waiting<'a, T> {
+    shared: &'a Shared<T>,
+    id: u64,
+}
+
+impl<'a, T> Waiting<'a, T> {
+    fn new(shared: &'a Shared<T>) -> Self {
+        let mut state = shared.state.lock().unwrap();
+        let id = state.next_send;
+        state.next_send += 1;
+        Self { shared, id }
+    }
+}
+
+impl<T> Drop for Waiting<'_, T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().blocked.remove(&self.id);
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.receiver.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Ends once every sender is dropped and the queue is drained.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    terminated: bool,
+}
+
+impl<T> Receiver<T> {
+    /// messages dropped to make room so far
+    pub fn dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(msg) = state.queue.pop_front() {
+            for (_, waker) in state.blocked.drain() {
+                waker.wake();
+            }
+            return Poll::Ready(Some(msg));
+        }
+        if state.senders == 0 {
+            drop(state);
+            self.terminated = true;
+            return Poll::Ready(None);
+        }
+        state.receiver = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiving = false;
+        state.queue.clear();
+        for (_, waker) in state.blocked.drain() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_overflow() {
+        let (tx, mut rx) = channel(Backpressure::new(2, Overflow::DropOldest).unwrap());
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(rx.next().await, Some(3));
+        assert_eq!(rx.dropped(), 3);
+
+        let (tx, mut rx) = channel(Backpressure::new(2, Overflow::Error).unwrap());
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(tx.send(3).await, Err(SendError::Full));
+        assert_eq!(rx.next().await, Some(1));
+        tx.send(3).await.unwrap();
+
+        let (tx, mut rx) = channel(Backpressure::new(1, Overflow::Block).unwrap());
+        tx.send(1).await.unwrap();
+        assert_eq!(tx.try_send(2), Err(SendError::Full));
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+        assert_eq!(rx.next().await, Some(1));
+        blocked.await.unwrap().unwrap();
+        drop(tx);
+        assert_eq!(rx.next().await, Some(2));
+        assert_eq!(rx.next().await, None);
+        assert!(rx.is_terminated());
+
+        let (tx, rx) = channel::<u32>(Backpressure::new(1, Overflow::Block).unwrap());
+        drop(rx);
+        assert_eq!(tx.send(1).await, Err(SendError::Closed));
+
+        assert_eq!(Backpressure::new(0, Overflow::Block), None);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_send_waker() {
+        let (tx, mut rx) = channel(Backpressure::new(1, Overflow::Block).unwrap());
+        tx.send(1).await.unwrap();
+        let mut send = Box::pin(tx.send(2));
+        for _ in 0..10 {
+            assert!(futures_util::poll!(&mut send).is_pending());
+        }
+        // polled again and again, still one waker
+        assert_eq!(tx.shared.state.lock().unwrap().blocked.len(), 1);
+        drop(send);
+        assert!(tx.shared.state.lock().unwrap().blocked.is_empty());
+        assert_eq!(rx.next().await, Some(1));
+    }
+}
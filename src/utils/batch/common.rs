@@ -7,7 +7,7 @@ use serde::{
 use serde_json::{value::RawValue, Value};
 use thiserror::Error;
 
-use ethers::types::U256;
+use ethers::types::{Bytes, U256};
 
 #[derive(Deserialize, Debug, Clone, Error)]
 /// A JSON-RPC 2.0 error
@@ -30,6 +30,20 @@ impl fmt::Display for JsonRpcError {
     }
 }
 
+impl JsonRpcError {
+    /// What an `eth_call` or `eth_estimateGas` that reverted returned,
+    /// `None` for any other error. Nodes answer a revert with code 3 and
+    /// its data, geth one without data with a plain -32000.
+    pub fn revert_data(&self) -> Option<Bytes> {
+        match (self.code, &self.data) {
+            (3, Some(Value::String(data))) => data.parse().ok(),
+            (3, None) => Some(Bytes::default()),
+            (-32000, None) if self.message == "execution reverted" => Some(Bytes::default()),
+            _ => None,
+        }
+    }
+}
+
 fn is_zst<T>(_t: &T) -> bool {
     std::mem::size_of::<T>() == 0
 }
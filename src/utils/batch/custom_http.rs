@@ -0,0 +1,158 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use ethers::providers::ProviderError;
+use reqwest::{IntoUrl, Url};
+use thiserror::Error;
+
+use super::common::{BatchError, BatchRequest, BatchResponse, JsonRpcError, Response};
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error(transparent)]
+    ReqwestError(#[from] reqwest::Error),
+
+    /// the whole batch was refused
+    #[error(transparent)]
+    JsonRpcError(#[from] JsonRpcError),
+
+    /// the batch is empty
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+
+    #[error("failed to parse batch response: {err}: {text}")]
+    SerdeJson {
+        err: serde_json::Error,
+        text: String,
+    },
+}
+
+impl From<HttpError> for ProviderError {
+    fn from(src: HttpError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+/// HTTP transport for JSON-RPC batches, posted as one array. Single
+/// requests are better sent through the ethers `Http`.
+#[derive(Debug, Clone)]
+pub struct Http {
+    id: Arc<AtomicU64>,
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl Http {
+    pub fn new(url: impl IntoUrl) -> Result<Self, HttpError> {
+        Ok(Self {
+            id: Arc::new(AtomicU64::new(1)),
+            client: reqwest::Client::new(),
+            url: url.into_url()?,
+        })
+    }
+
+    /// Executes the batch of JSON-RPC requests.
+    ///
+    /// # Arguments
+    ///
+    /// `batch` - batch of JSON-RPC requests.
+    #[tracing::instrument(level = "debug", name = "rpc_batch", skip_all, fields(len = batch.len()))]
+    pub async fn execute_batch(
+        &self,
+        batch: &mut BatchRequest,
+    ) -> Result<BatchResponse, HttpError> {
+        // The request id of the client is incremented by the batch size.
+        let next_id = self.id.fetch_add(batch.len() as u64, Ordering::SeqCst);
+
+        // Ids in the batch will start from next_id.
+        batch.set_ids(next_id)?;
+        let body = self
+            .client
+            .post(self.url.clone())
+            .json(batch.requests()?)
+            .send()
+            .await?
+            .bytes()
+            .await?;
+
+        match serde_json::from_slice::<Vec<Response>>(&body) {
+            Ok(responses) => Ok(BatchResponse::new(responses)),
+            // servers answer a batch they won't run (too large, rate
+            // limited) with a single error
+            Err(err) => match serde_json::from_slice(&body) {
+                Ok(Response::Error { error, .. }) => Err(error.into()),
+                _ => Err(HttpError::SerdeJson {
+                    err,
+                    text: String::from_utf8_lossy(&body).to_string(),
+                }),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::utils::batch::BatchProvider;
+
+    /// answers every request with its method, in reverse order, and
+    /// refuses batches of more than two
+    async fn rpc(Json(requests): Json<Vec<Value>>) -> Json<Value> {
+        if requests.len() > 2 {
+            return Json(json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "error": {"code": -32005, "message": "batch too large"}
+            }));
+        }
+        let responses: Vec<Value> = requests
+            .iter()
+            .rev()
+            .map(|request| {
+                json!({"jsonrpc": "2.0", "id": request["id"], "result": request["method"]})
+            })
+            .collect();
+        Json(json!(responses))
+    }
+
+    #[tokio::test]
+    async fn test_http_batch() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/", post(rpc));
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        let provider = BatchProvider::connect_http(&url).unwrap();
+        let mut batch = BatchRequest::new();
+        batch.add_request("eth_blockNumber", ()).unwrap();
+        batch.add_request("eth_chainId", ()).unwrap();
+        let mut responses = provider.execute_batch(&mut batch).await.unwrap();
+        // lined up with the requests though answered in reverse
+        assert_eq!(
+            responses.next_response::<String>().unwrap().unwrap(),
+            "eth_blockNumber"
+        );
+        assert_eq!(
+            responses.next_response::<String>().unwrap().unwrap(),
+            "eth_chainId"
+        );
+
+        batch.add_request("eth_gasPrice", ()).unwrap();
+        assert!(matches!(
+            provider.execute_batch(&mut batch).await,
+            Err(HttpError::JsonRpcError(JsonRpcError { code: -32005, .. }))
+        ));
+        assert!(matches!(
+            provider.execute_batch(&mut BatchRequest::new()).await,
+            Err(HttpError::Batch(BatchError::EmptyBatch))
+        ));
+    }
+}
@@ -2,25 +2,27 @@ use std::{
     cell::RefCell,
     convert::Infallible,
     hash::BuildHasherDefault,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
     thread,
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use bytes::{Buf as _, BytesMut};
 use ethers::{
-    providers::{IpcError, JsonRpcClient, PubsubClient},
+    providers::{JsonRpcClient, ProviderError, PubsubClient},
     types::U256,
 };
 use futures_channel::mpsc;
-use futures_util::stream::StreamExt as _;
+use futures_util::stream::{FusedStream as _, StreamExt as _};
 use hashers::fx_hash::FxHasher64;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{value::RawValue, Deserializer};
+use thiserror::Error;
 use tokio::{
     io::{AsyncReadExt as _, AsyncWriteExt as _, BufReader},
     net::{
@@ -28,22 +30,104 @@ use tokio::{
         UnixStream,
     },
     runtime,
-    sync::oneshot::{self},
+    sync::oneshot::{self, error::RecvError},
 };
 
-use super::common::{BatchRequest, BatchResponse, JsonRpcError, Params, Request, Response};
+use super::{
+    bounded::{self, Backpressure, Overflow, SendError},
+    common::{BatchError, BatchRequest, BatchResponse, JsonRpcError, Params, Request, Response},
+};
 
 type FxHashMap<K, V> = std::collections::HashMap<K, V, BuildHasherDefault<FxHasher64>>;
 
 type Pending = oneshot::Sender<Result<Box<RawValue>, JsonRpcError>>;
 type BatchPending = oneshot::Sender<BatchResponse>;
-type Subscription = mpsc::UnboundedSender<Box<RawValue>>;
+type Subscription = bounded::Sender<Box<RawValue>>;
+
+/// wait before the first attempt to reconnect, doubled on every failed one
+const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// wait for the response to a request, then for a whole batch
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_BATCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How much the transport queues before its overflow policy applies: requests
+/// and batches the server hasn't written yet, and notifications a subscriber
+/// hasn't taken yet (per subscription).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpcConfig {
+    pub requests: Backpressure,
+    pub subscriptions: Backpressure,
+}
+
+impl Default for IpcConfig {
+    /// callers wait for a slow node, a slow subscriber misses the oldest
+    /// notifications rather than stall everyone's responses
+    fn default() -> Self {
+        Self {
+            requests: Backpressure::new(1024, Overflow::Block).unwrap(),
+            subscriptions: Backpressure::new(4096, Overflow::DropOldest).unwrap(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum IpcError {
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// the node answered with an error
+    #[error(transparent)]
+    JsonRpcError(#[from] JsonRpcError),
+
+    /// the batch is empty
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+
+    #[error("{0}")]
+    ChannelError(String),
+
+    #[error(transparent)]
+    RequestCancelled(#[from] RecvError),
+
+    #[error("The IPC server has exited")]
+    ServerExit,
+
+    /// with `Overflow::Error`, too many requests are queued for the node
+    #[error("IPC request queue full")]
+    QueueFull,
+
+    /// the node never answered, the request is forgotten
+    #[error("no IPC response after {0:?}")]
+    Timeout(Duration),
+}
+
+impl From<IpcError> for ProviderError {
+    fn from(src: IpcError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
 
-/// Unix Domain Sockets (IPC) transport.
+/// Unix Domain Sockets (IPC) transport. When the node goes away (a bor
+/// restart) it reconnects with backoff and subscribes again to everything
+/// subscribed to, notifications keep coming to the same streams. Requests
+/// waiting on the lost connection, or made before it's back, fail with
+/// `IpcError::RequestCancelled` to be retried. One the node doesn't answer
+/// in time fails with `IpcError::Timeout`. Requests and notifications are
+/// queued in bounded channels, see `IpcConfig`.
 #[derive(Debug, Clone)]
 pub struct Ipc {
     id: Arc<AtomicU64>,
-    request_tx: mpsc::UnboundedSender<TransportMessage>,
+    request_tx: bounded::Sender<TransportMessage>,
+    /// subscriptions and cancellations, never held up by queued requests
+    control_tx: mpsc::UnboundedSender<TransportMessage>,
+    subscriptions: Backpressure,
+    request_timeout: Duration,
+    batch_timeout: Duration,
 }
 
 #[derive(Debug)]
@@ -51,6 +135,7 @@ enum TransportMessage {
     Request {
         id: u64,
         request: Box<[u8]>,
+        kind: RequestKind,
         sender: Pending,
     },
     Subscribe {
@@ -65,18 +150,73 @@ enum TransportMessage {
         requests: Box<[u8]>,
         sender: BatchPending,
     },
+    /// the request or batch `id` timed out
+    Cancel {
+        id: u64,
+    },
+}
+
+/// What the server keeps track of for a request to replay subscriptions.
+#[derive(Debug)]
+enum RequestKind {
+    Call,
+    /// `eth_subscribe` with its params
+    Subscribe(Box<RawValue>),
+    /// `eth_unsubscribe` of the id the subscription was first given
+    Unsubscribe(U256),
+}
+
+impl RequestKind {
+    fn new<T: Serialize>(method: &str, params: &T) -> Result<Self, IpcError> {
+        Ok(match method {
+            "eth_subscribe" => RequestKind::Subscribe(serde_json::value::to_raw_value(params)?),
+            "eth_unsubscribe" => {
+                match serde_json::from_value::<[U256; 1]>(serde_json::to_value(params)?) {
+                    Ok([id]) => RequestKind::Unsubscribe(id),
+                    Err(_) => RequestKind::Call,
+                }
+            }
+            _ => RequestKind::Call,
+        })
+    }
 }
 
 impl Ipc {
     /// Creates a new IPC transport from a given path using Unix sockets.
     pub async fn connect(path: impl AsRef<Path>) -> Result<Self, IpcError> {
+        Self::connect_with(path, IpcConfig::default()).await
+    }
+
+    /// `connect` with the queues of `config`
+    pub async fn connect_with(path: impl AsRef<Path>, config: IpcConfig) -> Result<Self, IpcError> {
         let id = Arc::new(AtomicU64::new(1));
-        let (request_tx, request_rx) = mpsc::unbounded();
+        let (request_tx, requests) = bounded::channel(config.requests);
+        let (control_tx, control) = mpsc::unbounded();
 
-        let stream = UnixStream::connect(path).await?;
-        spawn_ipc_server(stream, request_rx);
+        let path = path.as_ref().to_path_buf();
+        let stream = UnixStream::connect(&path).await?;
+        spawn_ipc_server(path, stream, id.clone(), Inbox { requests, control });
 
-        Ok(Self { id, request_tx })
+        Ok(Self {
+            id,
+            request_tx,
+            control_tx,
+            subscriptions: config.subscriptions,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            batch_timeout: DEFAULT_BATCH_TIMEOUT,
+        })
+    }
+
+    /// waits `timeout` for the response to a single request
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// waits `timeout` for the responses to a batch
+    pub fn with_batch_timeout(mut self, timeout: Duration) -> Self {
+        self.batch_timeout = timeout;
+        self
     }
 
     /// Executes the batch of JSON-RPC requests.
@@ -84,36 +224,61 @@ impl Ipc {
     /// # Arguments
     ///
     /// `batch` - batch of JSON-RPC requests.
+    #[tracing::instrument(level = "debug", name = "rpc_batch", skip_all, fields(len = batch.len()))]
     pub async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, IpcError> {
         // The request id of the client is incremented by the batch size.
         let next_id = self.id.fetch_add(batch.len() as u64, Ordering::SeqCst);
 
         // Ids in the batch will start from next_id.
-        batch.set_ids(next_id).unwrap();
+        batch.set_ids(next_id)?;
         // Send the message.
         let (sender, receiver) = oneshot::channel();
         // The id of the first request in the batch matches the id of the channel in the pending
         // map.
         let payload = TransportMessage::Batch {
             id: next_id,
-            requests: serde_json::to_vec(batch.requests().unwrap())
-                .unwrap()
-                .into_boxed_slice(),
+            requests: serde_json::to_vec(batch.requests()?)?.into_boxed_slice(),
             sender,
         };
 
         // Send the data.
-        self.send(payload)?;
+        self.send(payload).await?;
 
         // Wait for the response (the request itself may have errors as well).
-        let res = receiver.await?;
+        let res = self.wait(next_id, receiver, self.batch_timeout).await?;
 
         // Returns the batch of JSON-RPC responses.
         Ok(res)
     }
 
-    fn send(&self, msg: TransportMessage) -> Result<(), IpcError> {
-        self.request_tx
+    /// Waits for the response to `id`, making the server forget the request
+    /// if it doesn't come in `timeout`.
+    async fn wait<T>(
+        &self,
+        id: u64,
+        receiver: oneshot::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, IpcError> {
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(res) => Ok(res?),
+            Err(_) => {
+                // the server is gone if it can't be told
+                let _ = self.send_control(TransportMessage::Cancel { id });
+                Err(IpcError::Timeout(timeout))
+            }
+        }
+    }
+
+    /// Queues a request or batch, waiting for room if the queue blocks.
+    async fn send(&self, msg: TransportMessage) -> Result<(), IpcError> {
+        self.request_tx.send(msg).await.map_err(|err| match err {
+            SendError::Full => IpcError::QueueFull,
+            SendError::Closed => IpcError::ChannelError("IPC server receiver dropped".to_string()),
+        })
+    }
+
+    fn send_control(&self, msg: TransportMessage) -> Result<(), IpcError> {
+        self.control_tx
             .unbounded_send(msg)
             .map_err(|_| IpcError::ChannelError("IPC server receiver dropped".to_string()))?;
 
@@ -125,6 +290,7 @@ impl Ipc {
 impl JsonRpcClient for Ipc {
     type Error = IpcError;
 
+    #[tracing::instrument(level = "debug", name = "rpc", skip(self, params))]
     async fn request<T: Serialize + Send + Sync, R: DeserializeOwned>(
         &self,
         method: &str,
@@ -136,15 +302,17 @@ impl JsonRpcClient for Ipc {
         let (sender, receiver) = oneshot::channel();
         let payload = TransportMessage::Request {
             id: next_id,
+            kind: RequestKind::new(method, &params)?,
             request: serde_json::to_vec(&Request::new(next_id, method, params))?.into_boxed_slice(),
             sender,
         };
 
         // Send the request to the IPC server to be handled.
-        self.send(payload)?;
+        self.send(payload).await?;
 
-        // Wait for the response from the IPC server.
-        let res = receiver.await.unwrap().unwrap();
+        // Wait for the response from the IPC server, cancelled if the
+        // connection was lost.
+        let res = self.wait(next_id, receiver, self.request_timeout).await??;
 
         // Parse JSON response.
         Ok(serde_json::from_str(res.get())?)
@@ -152,11 +320,11 @@ impl JsonRpcClient for Ipc {
 }
 
 impl PubsubClient for Ipc {
-    type NotificationStream = mpsc::UnboundedReceiver<Box<RawValue>>;
+    type NotificationStream = bounded::Receiver<Box<RawValue>>;
 
     fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, IpcError> {
-        let (sink, stream) = mpsc::unbounded();
-        self.send(TransportMessage::Subscribe {
+        let (sink, stream) = bounded::channel(self.subscriptions);
+        self.send_control(TransportMessage::Subscribe {
             id: id.into(),
             sink,
         })?;
@@ -164,11 +332,33 @@ impl PubsubClient for Ipc {
     }
 
     fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), IpcError> {
-        self.send(TransportMessage::Unsubscribe { id: id.into() })
+        self.send_control(TransportMessage::Unsubscribe { id: id.into() })
+    }
+}
+
+/// What the server takes messages from.
+struct Inbox {
+    requests: bounded::Receiver<TransportMessage>,
+    control: mpsc::UnboundedReceiver<TransportMessage>,
+}
+
+impl Inbox {
+    /// The next message, subscriptions and cancellations first. `None` once
+    /// every handle is dropped.
+    async fn next(&mut self) -> Option<TransportMessage> {
+        tokio::select! {
+            biased;
+            Some(msg) = self.control.next() => Some(msg),
+            msg = self.requests.next() => msg,
+        }
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.requests.is_terminated()
     }
 }
 
-fn spawn_ipc_server(stream: UnixStream, request_rx: mpsc::UnboundedReceiver<TransportMessage>) {
+fn spawn_ipc_server(path: PathBuf, stream: UnixStream, id: Arc<AtomicU64>, inbox: Inbox) {
     // 65 KiB should be more than enough for this thread, as all unbounded data
     // growth occurs on heap-allocated data structures and buffers and the call
     // stack is not going to do anything crazy either
@@ -181,57 +371,171 @@ fn spawn_ipc_server(stream: UnixStream, request_rx: mpsc::UnboundedReceiver<Tran
         .spawn(move || {
             let rt = runtime::Builder::new_current_thread()
                 .enable_io()
+                .enable_time()
                 .build()
                 .expect("failed to create ipc-server-thread async runtime");
 
-            rt.block_on(run_ipc_server(stream, request_rx));
+            rt.block_on(run_ipc_server(path, stream, id, inbox));
         })
         .expect("failed to spawn ipc server thread");
 }
 
 async fn run_ipc_server(
+    path: PathBuf,
     mut stream: UnixStream,
-    request_rx: mpsc::UnboundedReceiver<TransportMessage>,
+    id: Arc<AtomicU64>,
+    mut inbox: Inbox,
 ) {
     // the shared state for both reads & writes
-    let shared = Shared::default();
+    let shared = Shared::new(id);
 
-    // split the stream and run two independent concurrently (local), thereby
-    // allowing reads and writes to occurr concurrently
-    let (reader, writer) = stream.split();
-    let read = shared.handle_ipc_reads(reader);
-    let write = shared.handle_ipc_writes(writer, request_rx);
+    loop {
+        // split the stream and run two independent concurrently (local), thereby
+        // allowing reads and writes to occurr concurrently
+        let (reader, mut writer) = stream.split();
+        if let Err(err) = shared.resubscribe(&mut writer).await {
+            tracing::error!(?err, "failed to resubscribe over IPC");
+        }
+        let read = shared.handle_ipc_reads(reader);
+        let write = shared.handle_ipc_writes(writer, &mut inbox);
 
-    // run both loops concurrently, until either encounts an error
-    if let Err(e) = futures_util::try_join!(read, write) {
+        // run both loops concurrently, until either encounts an error
+        let Err(e) = futures_util::try_join!(read, write);
         match e {
             IpcError::ServerExit => {}
-            err => tracing::error!(?err, "exiting IPC server due to error"),
+            err => tracing::error!(?err, "IPC connection failed"),
         }
+        // every handle was dropped
+        if inbox.is_terminated() {
+            return;
+        }
+
+        tracing::warn!(path = %path.display(), "IPC connection lost, reconnecting");
+        shared.disconnected();
+        stream = match shared.reconnect(&path, &mut inbox).await {
+            Some(stream) => stream,
+            None => return,
+        };
+        tracing::info!(path = %path.display(), "IPC reconnected");
     }
 }
 
+/// A subscription to make again on a new connection.
+struct Replay {
+    params: Box<RawValue>,
+    /// the id the node notifies it with now
+    node_id: U256,
+}
+
 struct Shared {
+    id: Arc<AtomicU64>,
     pending: RefCell<FxHashMap<u64, Pending>>,
     batch_pending: RefCell<FxHashMap<u64, BatchPending>>,
     subs: RefCell<FxHashMap<U256, Subscription>>,
+    /// `eth_subscribe` params by the id of the request awaiting its
+    /// subscription id
+    subscribing: RefCell<FxHashMap<u64, Box<RawValue>>>,
+    /// live subscriptions by the id they were first given, the one their
+    /// subscribers know
+    replays: RefCell<FxHashMap<U256, Replay>>,
+    /// subscriptions made again, by the id of the request awaiting their new
+    /// id
+    resubscribing: RefCell<FxHashMap<u64, U256>>,
+    /// the ids the subscriptions were first given by the ids the node
+    /// notifies them with since they were made again
+    aliases: RefCell<FxHashMap<U256, U256>>,
 }
 
-impl Default for Shared {
-    fn default() -> Self {
+fn fx_map<K, V>() -> RefCell<FxHashMap<K, V>> {
+    FxHashMap::with_capacity_and_hasher(64, BuildHasherDefault::default()).into()
+}
+
+impl Shared {
+    fn new(id: Arc<AtomicU64>) -> Self {
         Self {
-            pending: FxHashMap::with_capacity_and_hasher(64, BuildHasherDefault::default()).into(),
-            batch_pending: FxHashMap::with_capacity_and_hasher(64, BuildHasherDefault::default())
-                .into(),
-            subs: FxHashMap::with_capacity_and_hasher(64, BuildHasherDefault::default()).into(),
+            id,
+            pending: fx_map(),
+            batch_pending: fx_map(),
+            subs: fx_map(),
+            subscribing: fx_map(),
+            replays: fx_map(),
+            resubscribing: fx_map(),
+            aliases: fx_map(),
         }
     }
-}
 
-impl Shared {
+    /// Forgets what was sent over the lost connection, dropping the senders
+    /// fails the requests waiting on it with `RequestCancelled`.
+    fn disconnected(&self) {
+        self.pending.borrow_mut().clear();
+        self.batch_pending.borrow_mut().clear();
+        self.subscribing.borrow_mut().clear();
+        self.resubscribing.borrow_mut().clear();
+        // the node forgot the subscriptions as well
+        self.aliases.borrow_mut().clear();
+    }
+
+    /// Connects to `path` again with backoff. Requests made meanwhile are
+    /// cancelled, `None` if every handle is dropped first.
+    async fn reconnect(&self, path: &Path, inbox: &mut Inbox) -> Option<UnixStream> {
+        let mut backoff = RECONNECT_INITIAL_BACKOFF;
+        loop {
+            let wait = tokio::time::sleep(backoff);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    msg = inbox.next() => match msg {
+                        Some(msg) => self.handle_disconnected(msg),
+                        None => return None,
+                    },
+                }
+            }
+            match UnixStream::connect(path).await {
+                Ok(stream) => return Some(stream),
+                Err(err) => tracing::warn!(?err, ?backoff, "failed to reconnect over IPC"),
+            }
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+        }
+    }
+
+    fn handle_disconnected(&self, msg: TransportMessage) {
+        match msg {
+            // dropping the sender cancels it
+            TransportMessage::Request { kind, .. } => {
+                if let RequestKind::Unsubscribe(id) = kind {
+                    self.replays.borrow_mut().remove(&id);
+                }
+            }
+            TransportMessage::Batch { .. } | TransportMessage::Cancel { .. } => {}
+            msg => self.handle_subscription(msg),
+        }
+    }
+
+    /// Sends `eth_subscribe` again for every live subscription.
+    async fn resubscribe(&self, writer: &mut WriteHalf<'_>) -> Result<(), IpcError> {
+        let replays: Vec<(U256, Vec<u8>)> = self
+            .replays
+            .borrow()
+            .iter()
+            .map(|(first_id, replay)| {
+                let id = self.id.fetch_add(1, Ordering::SeqCst);
+                self.resubscribing.borrow_mut().insert(id, *first_id);
+                let request = Request::new(id, "eth_subscribe", &replay.params);
+                Ok((*first_id, serde_json::to_vec(&request)?))
+            })
+            .collect::<Result<_, IpcError>>()?;
+        for (first_id, request) in replays {
+            tracing::debug!(%first_id, "resubscribing over IPC");
+            writer.write_all(&request).await?;
+        }
+        Ok(())
+    }
+
     async fn handle_ipc_reads(&self, reader: ReadHalf<'_>) -> Result<Infallible, IpcError> {
         let mut reader = BufReader::new(reader);
         let mut buf = BytesMut::with_capacity(4096);
+        let mut notifications = Vec::new();
 
         loop {
             // try to read the next batch of bytes into the buffer
@@ -242,28 +546,56 @@ impl Shared {
             }
 
             // parse the received bytes into 0-n jsonrpc messages
-            let read = self.handle_bytes(&buf)?;
+            let read = self.handle_bytes(&buf, &mut notifications)?;
             // split off all bytes that were parsed into complete messages
             // any remaining bytes that correspond to incomplete messages remain
             // in the buffer
             buf.advance(read);
+
+            // a subscription that blocks stops the reads until its subscriber
+            // catches up
+            for (id, result) in notifications.drain(..) {
+                self.send_notification(id, result).await;
+            }
         }
     }
 
     async fn handle_ipc_writes(
         &self,
         mut writer: WriteHalf<'_>,
-        mut request_rx: mpsc::UnboundedReceiver<TransportMessage>,
+        inbox: &mut Inbox,
     ) -> Result<Infallible, IpcError> {
         use TransportMessage::*;
 
-        while let Some(msg) = request_rx.next().await {
+        while let Some(msg) = inbox.next().await {
             match msg {
                 Request {
                     id,
-                    request,
+                    mut request,
+                    kind,
                     sender,
                 } => {
+                    match kind {
+                        RequestKind::Call => {}
+                        RequestKind::Subscribe(params) => {
+                            self.subscribing.borrow_mut().insert(id, params);
+                        }
+                        RequestKind::Unsubscribe(first_id) => {
+                            // the node knows a subscription made again by
+                            // its new id
+                            if let Some(replay) = self.replays.borrow_mut().remove(&first_id) {
+                                self.aliases.borrow_mut().remove(&replay.node_id);
+                                if replay.node_id != first_id {
+                                    request = serde_json::to_vec(&super::common::Request::new(
+                                        id,
+                                        "eth_unsubscribe",
+                                        [replay.node_id],
+                                    ))?
+                                    .into_boxed_slice();
+                                }
+                            }
+                        }
+                    }
                     let prev = self.pending.borrow_mut().insert(id, sender);
                     assert!(prev.is_none(), "replaced pending IPC request (id={})", id);
 
@@ -285,22 +617,8 @@ impl Shared {
                         self.batch_pending.borrow_mut().remove(&id);
                     }
                 }
-                Subscribe { id, sink } => {
-                    if self.subs.borrow_mut().insert(id, sink).is_some() {
-                        tracing::warn!(
-                            %id,
-                            "replaced already-registered subscription"
-                        );
-                    }
-                }
-                Unsubscribe { id } => {
-                    if self.subs.borrow_mut().remove(&id).is_none() {
-                        tracing::warn!(
-                            %id,
-                            "attempted to unsubscribe from non-existent subscription"
-                        );
-                    }
-                }
+                Cancel { id } => self.cancel(id),
+                msg => self.handle_subscription(msg),
             }
         }
 
@@ -311,14 +629,68 @@ impl Shared {
         Err(IpcError::ServerExit)
     }
 
-    /// Tries to  deserialize all complete jsonrpc responses in the buffer.
-    fn parse_response(&self, bytes: &BytesMut) -> Result<usize, IpcError> {
+    /// Forgets the request or batch `id` timed out, its response is
+    /// dropped if it still comes.
+    fn cancel(&self, id: u64) {
+        tracing::warn!(%id, "IPC request timed out");
+        self.pending.borrow_mut().remove(&id);
+        self.batch_pending.borrow_mut().remove(&id);
+        self.subscribing.borrow_mut().remove(&id);
+    }
+
+    fn handle_subscription(&self, msg: TransportMessage) {
+        use TransportMessage::*;
+
+        match msg {
+            Subscribe { id, sink } => {
+                if self.subs.borrow_mut().insert(id, sink).is_some() {
+                    tracing::warn!(
+                        %id,
+                        "replaced already-registered subscription"
+                    );
+                }
+            }
+            Unsubscribe { id } => {
+                if self.subs.borrow_mut().remove(&id).is_none() {
+                    tracing::warn!(
+                        %id,
+                        "attempted to unsubscribe from non-existent subscription"
+                    );
+                }
+            }
+            Request { .. } | Batch { .. } | Cancel { .. } => {
+                unreachable!("not a subscription message")
+            }
+        }
+    }
+
+    /// Tries to  deserialize all complete jsonrpc responses in the buffer,
+    /// the notifications are left in `notifications` to be sent.
+    fn parse_response(
+        &self,
+        bytes: &BytesMut,
+        notifications: &mut Vec<(U256, Box<RawValue>)>,
+    ) -> Result<usize, IpcError> {
         let mut de = Deserializer::from_slice(bytes.as_ref()).into_iter();
         while let Some(Ok(response)) = de.next() {
             match response {
-                Response::Success { id, result } => self.send_response(id, Ok(result.to_owned())),
-                Response::Error { id, error } => self.send_response(id, Err(error)),
-                Response::Notification { params, .. } => self.send_notification(params),
+                Response::Success { id, result } => {
+                    if self.resubscribed(id, Ok(result)) {
+                        continue;
+                    }
+                    self.subscribed(id, Some(result));
+                    self.send_response(id, Ok(result.to_owned()))
+                }
+                Response::Error { id, error } => {
+                    if self.resubscribed(id, Err(&error)) {
+                        continue;
+                    }
+                    self.subscribed(id, None);
+                    self.send_response(id, Err(error))
+                }
+                Response::Notification { params, .. } => {
+                    notifications.push(self.notification(params))
+                }
             };
         }
 
@@ -330,17 +702,68 @@ impl Shared {
         while let Some(Ok(responses)) = de.next() {
             // Build the batch with the JSON-RPC responses.
             let batch = BatchResponse::new(responses);
-            // Get id.
-            let id = batch.id().unwrap();
-            // Send the batch.
-            self.send_batch(id, batch);
+            // Get id, an empty array answers no batch.
+            if let Ok(id) = batch.id() {
+                // Send the batch.
+                self.send_batch(id, batch);
+            }
         }
 
         Ok(de.byte_offset())
     }
 
-    fn handle_bytes(&self, bytes: &BytesMut) -> Result<usize, IpcError> {
-        Ok(self.parse_response(bytes)? + self.parse_batch(bytes)?)
+    fn handle_bytes(
+        &self,
+        bytes: &BytesMut,
+        notifications: &mut Vec<(U256, Box<RawValue>)>,
+    ) -> Result<usize, IpcError> {
+        Ok(self.parse_response(bytes, notifications)? + self.parse_batch(bytes)?)
+    }
+
+    /// Records the subscription `eth_subscribe` request `id` made, to make
+    /// again after a reconnect.
+    fn subscribed(&self, id: u64, result: Option<&RawValue>) {
+        let params = match self.subscribing.borrow_mut().remove(&id) {
+            Some(params) => params,
+            None => return,
+        };
+        if let Some(Ok(node_id)) = result.map(|result| serde_json::from_str::<U256>(result.get())) {
+            self.replays
+                .borrow_mut()
+                .insert(node_id, Replay { params, node_id });
+        }
+    }
+
+    /// Whether `id` made a subscription again, which is routed to the
+    /// stream it had. One the node refuses is ended.
+    fn resubscribed(&self, id: u64, result: Result<&RawValue, &JsonRpcError>) -> bool {
+        let first_id = match self.resubscribing.borrow_mut().remove(&id) {
+            Some(first_id) => first_id,
+            None => return false,
+        };
+        match result.map(|result| serde_json::from_str::<U256>(result.get())) {
+            Ok(Ok(node_id)) => {
+                // unless it was unsubscribed from meanwhile
+                if let Some(replay) = self.replays.borrow_mut().get_mut(&first_id) {
+                    replay.node_id = node_id;
+                    self.aliases.borrow_mut().insert(node_id, first_id);
+                }
+            }
+            Ok(Err(err)) => {
+                tracing::error!(?err, %first_id, "malformed subscription id, ending the subscription");
+                self.end_subscription(first_id);
+            }
+            Err(err) => {
+                tracing::error!(?err, %first_id, "failed to resubscribe, ending the subscription");
+                self.end_subscription(first_id);
+            }
+        }
+        true
+    }
+
+    fn end_subscription(&self, first_id: U256) {
+        self.replays.borrow_mut().remove(&first_id);
+        self.subs.borrow_mut().remove(&first_id);
     }
 
     fn send_response(&self, id: u64, result: Result<Box<RawValue>, JsonRpcError>) {
@@ -373,24 +796,265 @@ impl Shared {
         let _ = response_tx.send(result);
     }
 
+    /// The id of the subscription `params` notifies, the one its subscriber
+    /// knows, and the result.
+    fn notification(&self, params: Params<'_>) -> (U256, Box<RawValue>) {
+        // subscriptions made again are notified by their new id
+        let id = self
+            .aliases
+            .borrow()
+            .get(&params.subscription)
+            .copied()
+            .unwrap_or(params.subscription);
+        (id, params.result.to_owned())
+    }
+
     /// Sends notification through the channel based on the ID of the subscription.
     /// This handles streaming responses.
-    fn send_notification(&self, params: Params<'_>) {
-        // retrieve the channel sender for notifying the subscription stream
-        let subs = self.subs.borrow();
-        let tx = match subs.get(&params.subscription) {
-            Some(tx) => tx,
+    async fn send_notification(&self, id: U256, result: Box<RawValue>) {
+        // retrieve the channel sender for notifying the subscription stream,
+        // not borrowed while it waits for room
+        let tx = match self.subs.borrow().get(&id) {
+            Some(tx) => tx.clone(),
             None => {
-                tracing::warn!(
-                    id = ?params.subscription,
-                    "no subscription exists for the notification ID"
-                );
+                tracing::warn!(%id, "no subscription exists for the notification ID");
                 return;
             }
         };
 
-        // a failure to send the response indicates that the pending request has
-        // been dropped in the mean time (and should have been unsubscribed!)
-        let _ = tx.unbounded_send(params.result.to_owned());
+        let dropped = tx.dropped();
+        match tx.send(result).await {
+            Ok(()) if dropped == 0 && tx.dropped() > 0 => {
+                tracing::warn!(%id, "subscriber falling behind, dropping its oldest notifications");
+            }
+            Ok(()) => {}
+            Err(SendError::Full) => {
+                tracing::error!(%id, "subscriber falling behind, ending the subscription");
+                self.end_subscription(id);
+            }
+            // the subscriber was dropped in the mean time (and should have
+            // been unsubscribed!)
+            Err(SendError::Closed) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    /// the next request the client wrote, one at a time
+    async fn next_request(node: &mut UnixStream) -> Value {
+        let mut buf = Vec::new();
+        loop {
+            let mut chunk = [0u8; 1024];
+            let read = node.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "client hung up");
+            buf.extend_from_slice(&chunk[..read]);
+            if let Ok(request) = serde_json::from_slice(&buf) {
+                return request;
+            }
+        }
+    }
+
+    async fn reply(node: &mut UnixStream, message: Value) {
+        node.write_all(&serde_json::to_vec(&message).unwrap())
+            .await
+            .unwrap();
+    }
+
+    async fn answer(node: &mut UnixStream, method: &str, params: Value, result: Value) {
+        let request = next_request(node).await;
+        assert_eq!(request["method"], method);
+        assert_eq!(request["params"], params);
+        reply(
+            node,
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+        )
+        .await;
+    }
+
+    async fn notify(node: &mut UnixStream, subscription: &str, result: &str) {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {"subscription": subscription, "result": result}
+        });
+        reply(node, notification).await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resubscribes() {
+        let path = std::env::temp_dir().join(format!("tsuki-ipc-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let ipc = Ipc::connect(&path).await.unwrap();
+        let (mut node, _) = listener.accept().await.unwrap();
+
+        let subscribe = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_subscribe", ["newHeads"]).await }
+        });
+        answer(
+            &mut node,
+            "eth_subscribe",
+            json!(["newHeads"]),
+            json!("0x1"),
+        )
+        .await;
+        assert_eq!(subscribe.await.unwrap().unwrap(), U256::one());
+        let mut heads = ipc.subscribe(1).unwrap();
+        // the subscription is registered before the request after it is sent
+        let block_number = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_blockNumber", ()).await }
+        });
+        let request = next_request(&mut node).await;
+        reply(
+            &mut node,
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x64"}),
+        )
+        .await;
+        assert_eq!(block_number.await.unwrap().unwrap(), U256::from(100));
+        notify(&mut node, "0x1", "first").await;
+        assert_eq!(heads.next().await.unwrap().get(), "\"first\"");
+
+        // bor restarts with a request in flight
+        let in_flight = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_chainId", ()).await }
+        });
+        next_request(&mut node).await;
+        drop(node);
+        assert!(matches!(
+            in_flight.await.unwrap(),
+            Err(IpcError::RequestCancelled(_))
+        ));
+
+        let (mut node, _) = listener.accept().await.unwrap();
+        answer(
+            &mut node,
+            "eth_subscribe",
+            json!(["newHeads"]),
+            json!("0x2"),
+        )
+        .await;
+        notify(&mut node, "0x2", "second").await;
+        assert_eq!(heads.next().await.unwrap().get(), "\"second\"");
+
+        // unsubscribing by the first id unsubscribes the new one
+        let unsubscribe = tokio::spawn({
+            let ipc = ipc.clone();
+            async move {
+                ipc.request::<_, bool>("eth_unsubscribe", [U256::one()])
+                    .await
+            }
+        });
+        answer(&mut node, "eth_unsubscribe", json!(["0x2"]), json!(true)).await;
+        assert!(unsubscribe.await.unwrap().unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let path =
+            std::env::temp_dir().join(format!("tsuki-ipc-timeout-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let ipc = Ipc::connect(&path)
+            .await
+            .unwrap()
+            .with_request_timeout(Duration::from_millis(50))
+            .with_batch_timeout(Duration::from_millis(50));
+        let (mut node, _) = listener.accept().await.unwrap();
+
+        let chain_id = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_chainId", ()).await }
+        });
+        let unanswered = next_request(&mut node).await;
+        assert!(matches!(chain_id.await.unwrap(), Err(IpcError::Timeout(_))));
+        let batch = tokio::spawn({
+            let ipc = ipc.clone();
+            async move {
+                let mut batch = BatchRequest::new();
+                batch.add_request("eth_blockNumber", ()).unwrap();
+                ipc.execute_batch(&mut batch).await
+            }
+        });
+        next_request(&mut node).await;
+        assert!(matches!(batch.await.unwrap(), Err(IpcError::Timeout(_))));
+
+        // answered too late, dropped
+        reply(
+            &mut node,
+            json!({"jsonrpc": "2.0", "id": unanswered["id"], "result": "0x89"}),
+        )
+        .await;
+        let block_number = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_blockNumber", ()).await }
+        });
+        answer(&mut node, "eth_blockNumber", json!(null), json!("0x64")).await;
+        assert_eq!(block_number.await.unwrap().unwrap(), U256::from(100));
+
+        let call = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_call", ()).await }
+        });
+        let request = next_request(&mut node).await;
+        let error = json!({"code": 3, "message": "execution reverted", "data": "0x"});
+        reply(
+            &mut node,
+            json!({"jsonrpc": "2.0", "id": request["id"], "error": error}),
+        )
+        .await;
+        match call.await.unwrap() {
+            Err(IpcError::JsonRpcError(error)) => assert_eq!(error.code, 3),
+            res => panic!("expected the node's error, got {res:?}"),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let path = std::env::temp_dir().join(format!(
+            "tsuki-ipc-backpressure-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = IpcConfig {
+            subscriptions: Backpressure::new(2, Overflow::DropOldest).unwrap(),
+            ..Default::default()
+        };
+        let ipc = Ipc::connect_with(&path, config).await.unwrap();
+        let (mut node, _) = listener.accept().await.unwrap();
+
+        let mut heads = ipc.subscribe(1).unwrap();
+        // registered once the request after it is sent
+        let block_number = tokio::spawn({
+            let ipc = ipc.clone();
+            async move { ipc.request::<_, U256>("eth_blockNumber", ()).await }
+        });
+        answer(&mut node, "eth_blockNumber", json!(null), json!("0x64")).await;
+        block_number.await.unwrap().unwrap();
+        for head in ["1", "2", "3", "4"] {
+            notify(&mut node, "0x1", head).await;
+        }
+        for _ in 0..100 {
+            if heads.dropped() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // the subscriber only missed the oldest
+        assert_eq!(heads.dropped(), 2);
+        assert_eq!(heads.next().await.unwrap().get(), "\"3\"");
+        assert_eq!(heads.next().await.unwrap().get(), "\"4\"");
+        let _ = std::fs::remove_file(&path);
     }
 }
@@ -0,0 +1,426 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{JsonRpcClient, ProviderError, PubsubClient},
+    types::U256,
+};
+use futures_channel::mpsc;
+use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use thiserror::Error;
+use tokio::{net::TcpStream, sync::oneshot};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, protocol::CloseFrame, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use super::common::{BatchError, BatchRequest, BatchResponse, JsonRpcError, Request, Response};
+
+type Pending = oneshot::Sender<Result<Box<RawValue>, JsonRpcError>>;
+type BatchPending = oneshot::Sender<BatchResponse>;
+type Subscription = mpsc::UnboundedSender<Box<RawValue>>;
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Error, Debug)]
+pub enum WsError {
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    JsonRpcError(#[from] JsonRpcError),
+
+    /// the batch is empty
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+
+    /// boxed, it's most of the size of the enum otherwise
+    #[error(transparent)]
+    TungsteniteError(Box<tungstenite::Error>),
+
+    #[error("websocket responded with unexpected binary data")]
+    UnexpectedBinary(Vec<u8>),
+
+    /// the server closed the connection, with its reason if it gave one
+    #[error("websocket closed: {0:?}")]
+    Closed(Option<CloseFrame<'static>>),
+
+    #[error("{0}")]
+    ChannelError(String),
+
+    #[error(transparent)]
+    Canceled(#[from] oneshot::error::RecvError),
+}
+
+impl From<tungstenite::Error> for WsError {
+    fn from(src: tungstenite::Error) -> Self {
+        WsError::TungsteniteError(Box::new(src))
+    }
+}
+
+impl From<WsError> for ProviderError {
+    fn from(src: WsError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+/// WebSocket transport that, unlike the ethers one, can send JSON-RPC
+/// batches, for remote nodes (Alchemy, Infura) where there's no IPC socket.
+#[derive(Debug, Clone)]
+pub struct Ws {
+    id: Arc<AtomicU64>,
+    request_tx: mpsc::UnboundedSender<TransportMessage>,
+}
+
+#[derive(Debug)]
+enum TransportMessage {
+    Request {
+        id: u64,
+        request: String,
+        sender: Pending,
+    },
+    Subscribe {
+        id: U256,
+        sink: Subscription,
+    },
+    Unsubscribe {
+        id: U256,
+    },
+    Batch {
+        id: u64,
+        requests: String,
+        sender: BatchPending,
+    },
+}
+
+impl Ws {
+    /// Connects to the `ws://` or `wss://` endpoint at `url`.
+    pub async fn connect(url: &str) -> Result<Self, WsError> {
+        let id = Arc::new(AtomicU64::new(1));
+        let (request_tx, request_rx) = mpsc::unbounded();
+
+        let (stream, _) = connect_async(url).await?;
+        tokio::spawn(run_ws_server(stream, request_rx));
+
+        Ok(Self { id, request_tx })
+    }
+
+    /// Executes the batch of JSON-RPC requests.
+    ///
+    /// # Arguments
+    ///
+    /// `batch` - batch of JSON-RPC requests.
+    #[tracing::instrument(level = "debug", name = "rpc_batch", skip_all, fields(len = batch.len()))]
+    pub async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, WsError> {
+        // The request id of the client is incremented by the batch size.
+        let next_id = self.id.fetch_add(batch.len() as u64, Ordering::SeqCst);
+
+        // Ids in the batch will start from next_id.
+        batch.set_ids(next_id)?;
+        let (sender, receiver) = oneshot::channel();
+        // The id of the first request in the batch matches the id of the channel in the pending
+        // map.
+        let payload = TransportMessage::Batch {
+            id: next_id,
+            requests: serde_json::to_string(batch.requests()?)?,
+            sender,
+        };
+
+        self.send(payload)?;
+
+        Ok(receiver.await?)
+    }
+
+    fn send(&self, msg: TransportMessage) -> Result<(), WsError> {
+        self.request_tx
+            .unbounded_send(msg)
+            .map_err(|_| WsError::ChannelError("WS server receiver dropped".to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Ws {
+    type Error = WsError;
+
+    #[tracing::instrument(level = "debug", name = "rpc", skip(self, params))]
+    async fn request<T: Serialize + Send + Sync, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, WsError> {
+        let next_id = self.id.fetch_add(1, Ordering::SeqCst);
+
+        let (sender, receiver) = oneshot::channel();
+        let payload = TransportMessage::Request {
+            id: next_id,
+            request: serde_json::to_string(&Request::new(next_id, method, params))?,
+            sender,
+        };
+
+        self.send(payload)?;
+
+        let res = receiver.await??;
+        Ok(serde_json::from_str(res.get())?)
+    }
+}
+
+impl PubsubClient for Ws {
+    type NotificationStream = mpsc::UnboundedReceiver<Box<RawValue>>;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, WsError> {
+        let (sink, stream) = mpsc::unbounded();
+        self.send(TransportMessage::Subscribe {
+            id: id.into(),
+            sink,
+        })?;
+        Ok(stream)
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), WsError> {
+        self.send(TransportMessage::Unsubscribe { id: id.into() })
+    }
+}
+
+/// Sends what the handles ask for and routes the responses back, until every
+/// handle is dropped or the connection fails.
+async fn run_ws_server(
+    mut ws: WsStream,
+    mut request_rx: mpsc::UnboundedReceiver<TransportMessage>,
+) {
+    let mut shared = Shared::default();
+
+    let exit = loop {
+        tokio::select! {
+            msg = request_rx.next() => match msg {
+                Some(msg) => shared.handle_request(&mut ws, msg).await,
+                None => break Ok(()),
+            },
+            frame = ws.next() => match frame {
+                Some(Ok(frame)) => {
+                    if let Err(e) = shared.handle_frame(frame) {
+                        break Err(e);
+                    }
+                }
+                Some(Err(e)) => break Err(e.into()),
+                None => break Err(WsError::Closed(None)),
+            },
+        }
+    };
+
+    // dropping the pending senders fails their requests with `Canceled`
+    match exit {
+        Ok(()) => {
+            let _ = ws.close(None).await;
+        }
+        Err(err) => tracing::error!(?err, "exiting WS server due to error"),
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    pending: HashMap<u64, Pending>,
+    batch_pending: HashMap<u64, BatchPending>,
+    subs: HashMap<U256, Subscription>,
+}
+
+impl Shared {
+    async fn handle_request(&mut self, ws: &mut WsStream, msg: TransportMessage) {
+        use TransportMessage::*;
+
+        match msg {
+            Request {
+                id,
+                request,
+                sender,
+            } => {
+                let prev = self.pending.insert(id, sender);
+                assert!(prev.is_none(), "replaced pending WS request (id={})", id);
+
+                if let Err(err) = ws.send(Message::Text(request)).await {
+                    tracing::error!("WS connection error: {:?}", err);
+                    self.pending.remove(&id);
+                }
+            }
+            Batch {
+                id,
+                requests,
+                sender,
+            } => {
+                let prev = self.batch_pending.insert(id, sender);
+                assert!(prev.is_none(), "replaced pending WS request (id={})", id);
+
+                if let Err(err) = ws.send(Message::Text(requests)).await {
+                    tracing::error!("WS connection error: {:?}", err);
+                    self.batch_pending.remove(&id);
+                }
+            }
+            Subscribe { id, sink } => {
+                if self.subs.insert(id, sink).is_some() {
+                    tracing::warn!(%id, "replaced already-registered subscription");
+                }
+            }
+            Unsubscribe { id } => {
+                if self.subs.remove(&id).is_none() {
+                    tracing::warn!(
+                        %id,
+                        "attempted to unsubscribe from non-existent subscription"
+                    );
+                }
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Message) -> Result<(), WsError> {
+        match frame {
+            Message::Text(text) => {
+                self.handle_text(&text);
+                Ok(())
+            }
+            Message::Binary(bytes) => Err(WsError::UnexpectedBinary(bytes)),
+            Message::Close(frame) => Err(WsError::Closed(frame)),
+            // tungstenite answers pings itself
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Ok(()),
+        }
+    }
+
+    /// A message is one response, a notification, or the responses of a
+    /// batch. One that can't be parsed is dropped, what it answered is left
+    /// waiting.
+    fn handle_text(&mut self, text: &str) {
+        if text.trim_start().starts_with('[') {
+            match serde_json::from_str::<Vec<Response>>(text) {
+                Ok(responses) => self.send_batch(BatchResponse::new(responses)),
+                Err(err) => tracing::warn!(?err, "failed to parse WS batch response"),
+            }
+            return;
+        }
+        match serde_json::from_str(text) {
+            Ok(Response::Success { id, result }) => self.send_response(id, Ok(result.to_owned())),
+            Ok(Response::Error { id, error }) => self.send_response(id, Err(error)),
+            Ok(Response::Notification { params, .. }) => {
+                // the stream may be dropped already, and should have been
+                // unsubscribed
+                match self.subs.get(&params.subscription) {
+                    Some(tx) => {
+                        let _ = tx.unbounded_send(params.result.to_owned());
+                    }
+                    None => tracing::warn!(
+                        id = ?params.subscription,
+                        "no subscription exists for the notification ID"
+                    ),
+                }
+            }
+            Err(err) => tracing::warn!(?err, "failed to parse WS response"),
+        }
+    }
+
+    fn send_response(&mut self, id: u64, result: Result<Box<RawValue>, JsonRpcError>) {
+        match self.pending.remove(&id) {
+            // the request may have been dropped in the mean time
+            Some(tx) => {
+                let _ = tx.send(result);
+            }
+            None => tracing::warn!(%id, "no pending request exists for the response ID"),
+        }
+    }
+
+    fn send_batch(&mut self, batch: BatchResponse) {
+        let id = match batch.id() {
+            Ok(id) => id,
+            Err(_) => {
+                tracing::warn!("empty WS batch response");
+                return;
+            }
+        };
+        match self.batch_pending.remove(&id) {
+            Some(tx) => {
+                let _ = tx.send(batch);
+            }
+            None => tracing::warn!(%id, "no pending batch exists for the response ID"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{TransactionReceipt, H256};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+    use crate::utils::batch::BatchProvider;
+
+    /// Answers every batch with each request's method, in reverse order,
+    /// and receipts for `eth_getTransactionReceipt`.
+    async fn serve(listener: TcpListener) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = accept_async(stream).await.unwrap();
+        while let Some(Ok(Message::Text(text))) = ws.next().await {
+            let requests: Vec<Value> = serde_json::from_str(&text).unwrap();
+            let responses: Vec<Value> = requests
+                .iter()
+                .rev()
+                .map(|request| {
+                    let result = match request["method"].as_str().unwrap() {
+                        "eth_getTransactionReceipt" => json!(TransactionReceipt {
+                            transaction_hash: serde_json::from_value(request["params"][0].clone())
+                                .unwrap(),
+                            ..Default::default()
+                        }),
+                        method => json!(method),
+                    };
+                    json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                })
+                .collect();
+            ws.send(Message::Text(serde_json::to_string(&responses).unwrap()))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_batch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener));
+
+        let provider = BatchProvider::connect_ws(&url).await.unwrap();
+        let mut batch = BatchRequest::new();
+        batch.add_request("eth_blockNumber", ()).unwrap();
+        batch.add_request("eth_chainId", ()).unwrap();
+        let mut responses = provider.execute_batch(&mut batch).await.unwrap();
+        assert_eq!(responses.len(), 2);
+        // lined up with the requests though answered in reverse
+        assert_eq!(
+            responses.next_response::<String>().unwrap().unwrap(),
+            "eth_blockNumber"
+        );
+        assert_eq!(
+            responses.next_response::<String>().unwrap().unwrap(),
+            "eth_chainId"
+        );
+        assert!(matches!(
+            provider.execute_batch(&mut BatchRequest::new()).await,
+            Err(WsError::Batch(BatchError::EmptyBatch))
+        ));
+
+        let hashes = [H256::repeat_byte(1), H256::repeat_byte(2)];
+        let receipts = provider.get_receipts(&hashes).await.unwrap();
+        let mined: Vec<_> = receipts
+            .into_iter()
+            .map(|receipt| receipt.unwrap().unwrap().transaction_hash)
+            .collect();
+        assert_eq!(mined, hashes);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{JsonRpcClient, ProviderError, PubsubClient},
+    types::U256,
+};
+use futures_channel::mpsc;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{value::RawValue, Value};
+use thiserror::Error;
+
+use super::common::{BatchError, BatchRequest, BatchResponse, JsonRpcError, Response};
+
+#[derive(Error, Debug)]
+pub enum FakeTransportError {
+    #[error("no response scripted for {0}")]
+    Unscripted(String),
+
+    #[error(transparent)]
+    JsonRpcError(#[from] JsonRpcError),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+}
+
+impl From<FakeTransportError> for ProviderError {
+    fn from(src: FakeTransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+type Reply = Result<Box<RawValue>, JsonRpcError>;
+
+#[derive(Debug)]
+struct Subscription {
+    sink: Option<mpsc::UnboundedSender<Box<RawValue>>>,
+    /// taken by the first `subscribe`
+    stream: Option<mpsc::UnboundedReceiver<Box<RawValue>>>,
+}
+
+impl Subscription {
+    fn new() -> Self {
+        let (sink, stream) = mpsc::unbounded();
+        Self {
+            sink: Some(sink),
+            stream: Some(stream),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    /// answered in order, per method
+    replies: HashMap<String, VecDeque<Reply>>,
+    /// answers every call once `replies` of the method run out
+    defaults: HashMap<String, Reply>,
+    subscriptions: HashMap<U256, Subscription>,
+    requests: Vec<(String, Value)>,
+}
+
+impl Script {
+    fn reply(&mut self, method: &str, params: Value) -> Result<Reply, FakeTransportError> {
+        self.requests.push((method.to_string(), params));
+        if let Some(reply) = self.replies.get_mut(method).and_then(VecDeque::pop_front) {
+            return Ok(reply);
+        }
+        self.defaults
+            .get(method)
+            .cloned()
+            .ok_or_else(|| FakeTransportError::Unscripted(method.to_string()))
+    }
+
+    fn subscription(&mut self, id: U256) -> &mut Subscription {
+        self.subscriptions
+            .entry(id)
+            .or_insert_with(Subscription::new)
+    }
+}
+
+/// Transport that answers with canned JSON-RPC responses and streams
+/// scripted subscription notifications, for testing anything generic over
+/// a `Provider` without a node. Nothing is timed, responses come back in the
+/// order they were scripted.
+#[derive(Clone, Debug, Default)]
+pub struct FakeTransport {
+    script: Arc<Mutex<Script>>,
+}
+
+impl FakeTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `result` answers the next unanswered call of `method`
+    pub fn push_response(&self, method: &str, result: impl Serialize) {
+        let reply = Ok(to_raw(&result));
+        self.push_reply(method, reply);
+    }
+
+    pub fn push_error(&self, method: &str, error: JsonRpcError) {
+        self.push_reply(method, Err(error));
+    }
+
+    /// `result` answers every call of `method` nothing was pushed for
+    pub fn set_response(&self, method: &str, result: impl Serialize) {
+        let mut script = self.script.lock().unwrap();
+        script
+            .defaults
+            .insert(method.to_string(), Ok(to_raw(&result)));
+    }
+
+    /// Streams `item` to subscription `id`, the id `eth_subscribe` was
+    /// answered with. Buffered if nothing has subscribed yet.
+    pub fn notify(&self, id: impl Into<U256>, item: impl Serialize) {
+        let mut script = self.script.lock().unwrap();
+        if let Some(sink) = &script.subscription(id.into()).sink {
+            // the stream may be dropped already
+            let _ = sink.unbounded_send(to_raw(&item));
+        }
+    }
+
+    /// ends the stream of subscription `id` after what was notified so far
+    pub fn end_subscription(&self, id: impl Into<U256>) {
+        let mut script = self.script.lock().unwrap();
+        script.subscription(id.into()).sink = None;
+    }
+
+    /// every call so far as (method, params), batched ones included
+    pub fn requests(&self) -> Vec<(String, Value)> {
+        self.script.lock().unwrap().requests.clone()
+    }
+
+    pub fn clear_requests(&self) {
+        self.script.lock().unwrap().requests.clear();
+    }
+
+    /// answers every request of `batch` from the script
+    pub async fn execute_batch(
+        &self,
+        batch: &mut BatchRequest,
+    ) -> Result<BatchResponse, FakeTransportError> {
+        if batch.is_empty() {
+            return Ok(BatchResponse::new(Vec::new()));
+        }
+        batch.set_ids(1)?;
+        let requests = batch.requests()?.to_vec();
+        let mut script = self.script.lock().unwrap();
+        let mut replies = Vec::with_capacity(requests.len());
+        for request in requests {
+            let id = request["id"].as_u64().unwrap_or_default();
+            let method = request["method"].as_str().unwrap_or_default();
+            let params = request.get("params").cloned().unwrap_or(Value::Null);
+            replies.push((id, script.reply(method, params)?));
+        }
+        Ok(BatchResponse::new(
+            replies
+                .iter()
+                .map(|(id, reply)| match reply {
+                    Ok(result) => Response::Success {
+                        id: *id,
+                        result: result.as_ref(),
+                    },
+                    Err(error) => Response::Error {
+                        id: *id,
+                        error: error.clone(),
+                    },
+                })
+                .collect(),
+        ))
+    }
+
+    fn push_reply(&self, method: &str, reply: Reply) {
+        let mut script = self.script.lock().unwrap();
+        script
+            .replies
+            .entry(method.to_string())
+            .or_default()
+            .push_back(reply);
+    }
+}
+
+fn to_raw(value: &impl Serialize) -> Box<RawValue> {
+    RawValue::from_string(serde_json::to_string(value).unwrap()).unwrap()
+}
+
+#[async_trait]
+impl JsonRpcClient for FakeTransport {
+    type Error = FakeTransportError;
+
+    async fn request<T: Serialize + Send + Sync, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, FakeTransportError> {
+        let params = serde_json::to_value(params)?;
+        let reply = self.script.lock().unwrap().reply(method, params)?;
+        Ok(serde_json::from_str(reply?.get())?)
+    }
+}
+
+impl PubsubClient for FakeTransport {
+    type NotificationStream = mpsc::UnboundedReceiver<Box<RawValue>>;
+
+    fn subscribe<T: Into<U256>>(
+        &self,
+        id: T,
+    ) -> Result<Self::NotificationStream, FakeTransportError> {
+        let id = id.into();
+        let mut script = self.script.lock().unwrap();
+        script
+            .subscription(id)
+            .stream
+            .take()
+            .ok_or_else(|| FakeTransportError::Unscripted(format!("second subscribe to {}", id)))
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), FakeTransportError> {
+        self.script.lock().unwrap().subscriptions.remove(&id.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::{Middleware, Provider},
+        types::{H256, U64},
+    };
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fake_transport_replays_script() {
+        let transport = FakeTransport::new();
+        transport.push_response("eth_blockNumber", U64::from(10));
+        transport.set_response("eth_blockNumber", U64::from(11));
+        transport.push_error(
+            "eth_chainId",
+            JsonRpcError {
+                code: -32000,
+                message: "unavailable".to_string(),
+                data: None,
+            },
+        );
+        let provider = Provider::new(transport.clone());
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(10));
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(11));
+        assert!(provider.get_chainid().await.is_err());
+        assert!(provider.get_gas_price().await.is_err());
+        assert_eq!(transport.requests()[0].0, "eth_blockNumber");
+
+        let hash = H256::repeat_byte(1);
+        transport.push_response("eth_subscribe", U256::from(7));
+        transport.notify(7, hash);
+        transport.end_subscription(7);
+        let hashes: Vec<H256> = provider
+            .subscribe_pending_txs()
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(hashes, vec![hash]);
+
+        let mut batch = BatchRequest::new();
+        batch.add_request("eth_blockNumber", ()).unwrap();
+        batch.add_request("eth_blockNumber", ()).unwrap();
+        let mut responses = transport.execute_batch(&mut batch).await.unwrap();
+        assert_eq!(
+            responses.next_response::<U64>().unwrap().unwrap(),
+            U64::from(11)
+        );
+        assert_eq!(responses.len(), 1);
+    }
+}
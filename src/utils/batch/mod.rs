@@ -1,9 +1,121 @@
-use ethers::providers::{IpcError, ProviderError};
+use async_trait::async_trait;
+use ethers::{
+    providers::{HttpClientError, ProviderError, WsClientError},
+    types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, TransactionReceipt, TxHash},
+};
 
-use self::common::{BatchRequest, BatchResponse};
+use self::{
+    common::{BatchError, BatchRequest, BatchResponse, JsonRpcError},
+    custom_ipc::IpcError,
+    state_override::StateOverride,
+};
 
+pub mod bounded;
 pub mod common;
+pub mod custom_http;
 pub mod custom_ipc;
+pub mod custom_ws;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fake;
+pub mod state_override;
+
+/// A transport that can send a batch of JSON-RPC requests in one message.
+#[async_trait]
+pub trait BatchTransport: Send + Sync {
+    type Error: std::error::Error + From<BatchError> + Send + Sync + 'static;
+
+    async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, Self::Error>;
+}
+
+#[async_trait]
+impl BatchTransport for custom_ipc::Ipc {
+    type Error = IpcError;
+
+    async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, IpcError> {
+        custom_ipc::Ipc::execute_batch(self, batch).await
+    }
+}
+
+#[async_trait]
+impl BatchTransport for custom_ws::Ws {
+    type Error = custom_ws::WsError;
+
+    async fn execute_batch(
+        &self,
+        batch: &mut BatchRequest,
+    ) -> Result<BatchResponse, custom_ws::WsError> {
+        custom_ws::Ws::execute_batch(self, batch).await
+    }
+}
+
+#[async_trait]
+impl BatchTransport for custom_http::Http {
+    type Error = custom_http::HttpError;
+
+    async fn execute_batch(
+        &self,
+        batch: &mut BatchRequest,
+    ) -> Result<BatchResponse, custom_http::HttpError> {
+        custom_http::Http::execute_batch(self, batch).await
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[async_trait]
+impl BatchTransport for fake::FakeTransport {
+    type Error = fake::FakeTransportError;
+
+    async fn execute_batch(
+        &self,
+        batch: &mut BatchRequest,
+    ) -> Result<BatchResponse, fake::FakeTransportError> {
+        fake::FakeTransport::execute_batch(self, batch).await
+    }
+}
+
+/// The JSON-RPC error the node answered with, if that's what `err` is,
+/// through a `ProviderError` over ethers' transports or ours.
+pub fn json_rpc_error(err: &(dyn std::error::Error + 'static)) -> Option<JsonRpcError> {
+    // ethers' own is private, only its fields can be read
+    macro_rules! copy {
+        ($e:expr) => {
+            Some(JsonRpcError {
+                code: $e.code,
+                message: $e.message.clone(),
+                data: $e.data.clone(),
+            })
+        };
+    }
+    if let Some(ProviderError::JsonRpcClientError(inner)) = err.downcast_ref() {
+        return json_rpc_error(inner.as_ref());
+    }
+    if let Some(e) = err.downcast_ref::<JsonRpcError>() {
+        return Some(e.clone());
+    }
+    if let Some(IpcError::JsonRpcError(e)) = err.downcast_ref() {
+        return Some(e.clone());
+    }
+    if let Some(custom_ws::WsError::JsonRpcError(e)) = err.downcast_ref() {
+        return Some(e.clone());
+    }
+    if let Some(custom_http::HttpError::JsonRpcError(e)) = err.downcast_ref() {
+        return Some(e.clone());
+    }
+    #[cfg(any(test, feature = "test-utils"))]
+    if let Some(fake::FakeTransportError::JsonRpcError(e)) = err.downcast_ref() {
+        return Some(e.clone());
+    }
+    if let Some(HttpClientError::JsonRpcError(e)) = err.downcast_ref() {
+        return copy!(e);
+    }
+    if let Some(WsClientError::JsonRpcError(e)) = err.downcast_ref() {
+        return copy!(e);
+    }
+    if let Some(ethers::providers::IpcError::JsonRpcError(e)) = err.downcast_ref() {
+        return copy!(e);
+    }
+    err.source().and_then(json_rpc_error)
+}
 
 pub struct BatchProvider<P> {
     pub inner: P,
@@ -11,11 +123,178 @@ pub struct BatchProvider<P> {
 
 impl BatchProvider<custom_ipc::Ipc> {
     pub async fn connect_ipc(path: impl AsRef<std::path::Path>) -> Result<Self, ProviderError> {
-        let ipc = custom_ipc::Ipc::connect(path).await.unwrap();
+        let ipc = custom_ipc::Ipc::connect(path).await?;
         Ok(Self { inner: ipc })
     }
+}
+
+impl BatchProvider<custom_ws::Ws> {
+    /// for remote nodes, `url` is a `ws://` or `wss://` endpoint
+    pub async fn connect_ws(url: &str) -> Result<Self, ProviderError> {
+        let ws = custom_ws::Ws::connect(url).await?;
+        Ok(Self { inner: ws })
+    }
+}
+
+impl BatchProvider<custom_http::Http> {
+    /// for nodes without a socket to batch over, `url` is a JSON-RPC HTTP
+    /// endpoint
+    pub fn connect_http(url: &str) -> Result<Self, ProviderError> {
+        let http = custom_http::Http::new(url)?;
+        Ok(Self { inner: http })
+    }
+}
 
-    pub async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, IpcError> {
+impl<P: BatchTransport> BatchProvider<P> {
+    pub async fn execute_batch(&self, batch: &mut BatchRequest) -> Result<BatchResponse, P::Error> {
         self.inner.execute_batch(batch).await
     }
+
+    /// Fetches the receipts of `hashes` in a single round trip. Results line up
+    /// with `hashes`, `None` for txns that aren't mined (yet).
+    pub async fn get_receipts(
+        &self,
+        hashes: &[TxHash],
+    ) -> Result<Vec<Result<Option<TransactionReceipt>, BatchError>>, P::Error> {
+        if hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut batch = BatchRequest::with_capacity(hashes.len());
+        for hash in hashes {
+            batch.add_request("eth_getTransactionReceipt", [hash])?;
+        }
+        let mut responses = self.execute_batch(&mut batch).await?;
+        Ok(hashes
+            .iter()
+            .map(|_| {
+                responses
+                    .next_response()
+                    .unwrap_or(Err(BatchError::EmptyBatch))
+            })
+            .collect())
+    }
+
+    /// Runs `calls` against `block` in a single round trip, every one of
+    /// them seeing `overrides`. Results line up with `calls`, a revert is
+    /// an error of its call only.
+    pub async fn call_many(
+        &self,
+        calls: &[TypedTransaction],
+        block: BlockId,
+        overrides: &StateOverride,
+    ) -> Result<Vec<Result<Bytes, BatchError>>, P::Error> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut batch = BatchRequest::with_capacity(calls.len());
+        state_override::add_calls(&mut batch, calls, block, overrides)?;
+        let mut responses = self.execute_batch(&mut batch).await?;
+        Ok(state_override::call_results(&mut responses, calls.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, BlockNumber, TransactionRequest, U64};
+
+    use super::{common::JsonRpcError, fake::FakeTransport, *};
+
+    #[tokio::test]
+    async fn test_get_receipts() {
+        let transport = FakeTransport::new();
+        let provider = BatchProvider {
+            inner: transport.clone(),
+        };
+        let mined = TxHash::repeat_byte(1);
+        let pending = TxHash::repeat_byte(2);
+        transport.push_response(
+            "eth_getTransactionReceipt",
+            TransactionReceipt {
+                transaction_hash: mined,
+                status: Some(U64::one()),
+                ..Default::default()
+            },
+        );
+        transport.push_response("eth_getTransactionReceipt", ());
+
+        let receipts = provider.get_receipts(&[mined, pending]).await.unwrap();
+        assert_eq!(receipts.len(), 2);
+        let receipt = receipts[0].as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(receipt.transaction_hash, mined);
+        assert!(receipts[1].as_ref().unwrap().is_none());
+        // one batch, both hashes
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].1[0], serde_json::json!(pending));
+
+        transport.clear_requests();
+        assert!(provider.get_receipts(&[]).await.unwrap().is_empty());
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_receipts_errors() {
+        let transport = FakeTransport::new();
+        let provider = BatchProvider {
+            inner: transport.clone(),
+        };
+        let hashes = [TxHash::repeat_byte(1), TxHash::repeat_byte(2)];
+        transport.push_error(
+            "eth_getTransactionReceipt",
+            JsonRpcError {
+                code: -32000,
+                message: "header not found".to_string(),
+                data: None,
+            },
+        );
+        transport.push_response("eth_getTransactionReceipt", ());
+
+        // a failed lookup fails its hash only
+        let receipts = provider.get_receipts(&hashes).await.unwrap();
+        assert!(matches!(receipts[0], Err(BatchError::JsonRpcError(_))));
+        assert!(receipts[1].as_ref().unwrap().is_none());
+
+        // nothing scripted fails the whole batch
+        assert!(matches!(
+            provider.get_receipts(&hashes).await,
+            Err(fake::FakeTransportError::Unscripted(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_call_many() {
+        let transport = FakeTransport::new();
+        let provider = BatchProvider {
+            inner: transport.clone(),
+        };
+        let calls: Vec<TypedTransaction> = vec![
+            TransactionRequest::new().to(Address::repeat_byte(1)).into(),
+            TransactionRequest::new().to(Address::repeat_byte(2)).into(),
+        ];
+        transport.push_error(
+            "eth_call",
+            JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            },
+        );
+        transport.push_response("eth_call", Bytes::from(vec![7u8]));
+
+        let results = provider
+            .call_many(&calls, BlockNumber::Latest.into(), &StateOverride::new())
+            .await
+            .unwrap();
+        // a revert fails its call only
+        assert!(matches!(results[0], Err(BatchError::JsonRpcError(_))));
+        assert_eq!(results[1].as_ref().unwrap(), &Bytes::from(vec![7u8]));
+
+        // nothing scripted fails the whole batch
+        assert!(matches!(
+            provider
+                .call_many(&calls, BlockNumber::Latest.into(), &StateOverride::new())
+                .await,
+            Err(fake::FakeTransportError::Unscripted(_))
+        ));
+    }
 }
@@ -0,0 +1,210 @@
+//! `eth_call` with geth's state override set, the third parameter that
+//! swaps in balances, code or storage slots of any account for the call
+//! only. Profit checks use it to run the executor as if it already had the
+//! approvals and funds a route needs, without sending setup txns first.
+
+use std::collections::BTreeMap;
+
+use ethers::{
+    types::{transaction::eip2718::TypedTransaction, Address, BlockId, Bytes, H256, U256, U64},
+    utils::keccak256,
+};
+use serde::Serialize;
+
+use super::common::{BatchError, BatchRequest, BatchResponse};
+
+/// What an `eth_call` sees of one account instead of its state.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    /// slots patched over the account's storage
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub state_diff: BTreeMap<H256, H256>,
+}
+
+/// Overrides by account, serialized as the state override set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct StateOverride {
+    accounts: BTreeMap<Address, AccountOverride>,
+}
+
+impl StateOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn account(&mut self, address: Address) -> &mut AccountOverride {
+        self.accounts.entry(address).or_default()
+    }
+
+    /// `address` holds `balance` of the native token
+    pub fn balance(mut self, address: Address, balance: U256) -> Self {
+        self.account(address).balance = Some(balance);
+        self
+    }
+
+    /// `address` runs `code`, e.g. an executor not deployed yet
+    pub fn code(mut self, address: Address, code: Bytes) -> Self {
+        self.account(address).code = Some(code);
+        self
+    }
+
+    pub fn storage(mut self, address: Address, slot: H256, value: U256) -> Self {
+        let mut word = H256::zero();
+        value.to_big_endian(word.as_bytes_mut());
+        self.account(address).state_diff.insert(slot, word);
+        self
+    }
+
+    /// `holder` has `amount` of `token`, whose balances are the mapping at
+    /// storage slot `balances_slot`
+    pub fn erc20_balance(
+        self,
+        token: Address,
+        holder: Address,
+        amount: U256,
+        balances_slot: U256,
+    ) -> Self {
+        self.storage(token, mapping_slot(holder, balances_slot), amount)
+    }
+
+    /// `owner` approved `spender` for `amount` of `token`, whose allowances
+    /// are the nested mapping at storage slot `allowances_slot`
+    pub fn erc20_allowance(
+        self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        amount: U256,
+        allowances_slot: U256,
+    ) -> Self {
+        let inner = mapping_slot(owner, allowances_slot);
+        self.storage(
+            token,
+            mapping_slot(spender, U256::from_big_endian(inner.as_bytes())),
+            amount,
+        )
+    }
+}
+
+/// storage slot of `key` in a solidity mapping declared at `slot`
+pub fn mapping_slot(key: Address, slot: U256) -> H256 {
+    let mut preimage = [0u8; 64];
+    preimage[12..32].copy_from_slice(key.as_bytes());
+    slot.to_big_endian(&mut preimage[32..]);
+    H256(keccak256(preimage))
+}
+
+/// Adds an `eth_call` of every txn of `calls` against `block` to `batch`,
+/// all of them seeing `overrides`.
+pub fn add_calls(
+    batch: &mut BatchRequest,
+    calls: &[TypedTransaction],
+    block: BlockId,
+    overrides: &StateOverride,
+) -> Result<(), BatchError> {
+    for tx in calls {
+        match overrides.is_empty() {
+            true => batch.add_request("eth_call", (tx, block))?,
+            false => batch.add_request("eth_call", (tx, block, overrides))?,
+        }
+    }
+    Ok(())
+}
+
+/// the return data of `count` calls added with `add_calls`, in order
+pub fn call_results(responses: &mut BatchResponse, count: usize) -> Vec<Result<Bytes, BatchError>> {
+    (0..count)
+        .map(|_| {
+            responses
+                .next_response()
+                .unwrap_or(Err(BatchError::EmptyBatch))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{BlockNumber, TransactionRequest};
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::batch::{common::JsonRpcError, fake::FakeTransport};
+
+    #[tokio::test]
+    async fn test_calls_with_overrides() {
+        let executor = Address::repeat_byte(0xee);
+        let token = Address::repeat_byte(0x70);
+        let router = Address::repeat_byte(0x80);
+        let overrides = StateOverride::new()
+            .balance(executor, U256::exp10(18))
+            .erc20_balance(token, executor, 500.into(), 0.into())
+            .erc20_allowance(token, executor, router, U256::MAX, 1.into());
+        let calls: Vec<TypedTransaction> = vec![
+            TransactionRequest::new().to(executor).into(),
+            TransactionRequest::new().to(router).into(),
+        ];
+
+        let transport = FakeTransport::new();
+        transport.push_response("eth_call", Bytes::from(vec![1u8]));
+        transport.push_error(
+            "eth_call",
+            JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            },
+        );
+        let mut batch = BatchRequest::new();
+        add_calls(&mut batch, &calls, BlockNumber::Latest.into(), &overrides).unwrap();
+        let mut responses = transport.execute_batch(&mut batch).await.unwrap();
+        let results = call_results(&mut responses, calls.len());
+        assert_eq!(results[0].as_ref().unwrap(), &Bytes::from(vec![1u8]));
+        assert!(results[1].is_err());
+
+        let (method, params) = &transport.requests()[0];
+        assert_eq!(method, "eth_call");
+        assert_eq!(params[1], json!("latest"));
+        let account = &params[2][format!("{:?}", executor)];
+        assert_eq!(account["balance"], json!("0xde0b6b3a7640000"));
+        assert!(account.get("stateDiff").is_none());
+        // balanceOf[executor] at slot 0, allowance[executor][router] at slot 1
+        let slots = params[2][format!("{:?}", token)]["stateDiff"]
+            .as_object()
+            .unwrap();
+        assert_eq!(slots.len(), 2);
+        let balance_slot = format!("{:?}", mapping_slot(executor, 0.into()));
+        assert_eq!(
+            slots[&balance_slot],
+            json!(format!("{:?}", H256::from_low_u64_be(500)))
+        );
+
+        // no overrides, no third parameter
+        let mut batch = BatchRequest::new();
+        add_calls(
+            &mut batch,
+            &calls[..1],
+            BlockNumber::Latest.into(),
+            &StateOverride::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            batch.requests().unwrap()[0]["params"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
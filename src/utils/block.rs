@@ -7,13 +7,19 @@ use ethers::{
 };
 use serde::{Deserialize, Serialize};
 
-use super::{transaction::TypedTransaction, trie};
+use super::{
+    transaction::{enveloped, StateSyncTransaction, TypedTransaction, STATE_SYNC_TX_TYPE},
+    trie,
+};
 
 /// ethereum block
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Block {
     pub header: Header,
     pub transactions: Vec<TypedTransaction>,
+    /// bor state-sync txn, encoded after `transactions`
+    #[serde(default)]
+    pub state_sync: Option<StateSyncTransaction>,
     pub ommers: Vec<Header>,
 }
 
@@ -22,33 +28,72 @@ impl Block {
         partial_header: PartialHeader,
         transactions: Vec<TypedTransaction>,
         ommers: Vec<Header>,
+    ) -> Self {
+        Self::with_state_sync(partial_header, transactions, None, ommers)
+    }
+
+    pub fn with_state_sync(
+        partial_header: PartialHeader,
+        transactions: Vec<TypedTransaction>,
+        state_sync: Option<StateSyncTransaction>,
+        ommers: Vec<Header>,
     ) -> Self {
         let ommers_hash = H256::from_slice(keccak256(&rlp::encode_list(&ommers)[..]).as_slice());
-        let transactions_root =
-            trie::ordered_trie_root(transactions.iter().map(|r| rlp::encode(r).freeze()));
+        let transactions_root = trie::ordered_trie_root(
+            transactions
+                .iter()
+                .map(|r| rlp::encode(r).freeze())
+                .chain(state_sync.iter().map(encode_state_sync)),
+        );
 
         Self {
             header: Header::new(partial_header, ommers_hash, transactions_root),
             transactions,
+            state_sync,
             ommers,
         }
     }
 }
 
+fn encode_state_sync(tx: &StateSyncTransaction) -> bytes::Bytes {
+    let mut s = RlpStream::new();
+    enveloped(STATE_SYNC_TX_TYPE, tx, &mut s);
+    s.out().freeze()
+}
+
 impl Encodable for Block {
     fn rlp_append(&self, s: &mut RlpStream) {
         s.begin_list(3);
         s.append(&self.header);
-        s.append_list(&self.transactions);
+        s.begin_list(self.transactions.len() + self.state_sync.is_some() as usize);
+        for tx in &self.transactions {
+            s.append(tx);
+        }
+        if let Some(state_sync) = &self.state_sync {
+            s.append_raw(&encode_state_sync(state_sync), 1);
+        }
         s.append_list(&self.ommers);
     }
 }
 
 impl Decodable for Block {
     fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let mut transactions = Vec::new();
+        let mut state_sync = None;
+        for tx in rlp.at(1)?.iter() {
+            let data = tx.data()?;
+            // typed txns are wrapped in an rlp string, bor's state-sync txn
+            // isn't one `TypedTransaction` knows about
+            if !tx.is_list() && data.first() == Some(&STATE_SYNC_TX_TYPE) {
+                state_sync = Some(rlp::decode(&data[1..])?);
+            } else {
+                transactions.push(tx.as_val()?);
+            }
+        }
         Ok(Self {
             header: rlp.val_at(0)?,
-            transactions: rlp.list_at(1)?,
+            transactions,
+            state_sync,
             ommers: rlp.list_at(2)?,
         })
     }
@@ -210,6 +255,7 @@ mod tests {
     use ethers::{types::H160, utils::hex};
 
     use super::*;
+    use crate::utils::transaction::StateSyncData;
 
     #[test]
     fn header_rlp_roundtrip() {
@@ -243,6 +289,24 @@ mod tests {
         assert_eq!(header, decoded);
     }
 
+    #[test]
+    fn block_with_state_sync_rlp_roundtrip() {
+        let state_sync = StateSyncTransaction {
+            state_sync_data: vec![StateSyncData {
+                id: 2066,
+                contract: H160::from_low_u64_be(0x1001),
+                data: hex::decode("deadbeef").unwrap().into(),
+                tx_hash: H256::from_low_u64_be(7),
+            }],
+        };
+        let block =
+            Block::with_state_sync(PartialHeader::default(), vec![], Some(state_sync), vec![]);
+
+        let encoded = rlp::encode(&block);
+        let decoded: Block = rlp::decode(encoded.as_ref()).unwrap();
+        assert_eq!(block, decoded);
+    }
+
     #[test]
     // Test vector from: https://github.com/ethereum/tests/blob/f47bbef4da376a49c8fc3166f09ab8a6d182f765/BlockchainTests/ValidBlocks/bcEIP1559/baseFee.json#L15-L36
     fn test_eip1559_block_header_hash() {
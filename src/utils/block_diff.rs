@@ -0,0 +1,273 @@
+use std::{collections::HashMap, fmt};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, H256, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use super::block_simulator::BlockSimulator;
+
+/// uniswap v2 style reserves of a pool after a block
+pub type Reserves = (U256, U256);
+
+/// what we expected the next block to look like
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PredictedBlock {
+    /// txn hashes in predicted block order
+    pub transactions: Vec<H256>,
+    /// pool reserves after the predicted block
+    pub pool_states: HashMap<Address, Reserves>,
+}
+
+impl PredictedBlock {
+    pub fn from_simulator<M: Middleware>(
+        simulator: &BlockSimulator<M>,
+        pool_states: HashMap<Address, Reserves>,
+    ) -> Self {
+        Self {
+            transactions: simulator
+                .transactions()
+                .iter()
+                .map(|tx| tx.hash())
+                .collect(),
+            pool_states,
+        }
+    }
+}
+
+/// an arb we planned around the predicted block
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PredictedArb {
+    /// txn the arb backruns, if any
+    pub victim: Option<H256>,
+    pub our_tx: H256,
+    /// pools the arb trades through
+    pub pools: Vec<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolDivergence {
+    pub pool: Address,
+    pub predicted: Reserves,
+    /// `None` if we have no mined state for the pool
+    pub mined: Option<Reserves>,
+}
+
+/// why a predicted arb didn't end up in the mined block as planned
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissReason {
+    /// our txn was mined right where we wanted it
+    Materialized,
+    /// the txn we backran wasn't mined
+    VictimDropped,
+    /// somebody else's txn landed between the victim and our slot
+    CompetitorInserted { tx: H256, index: usize },
+    /// ordering was as predicted but the pools ended up elsewhere
+    StateDivergence { pools: Vec<Address> },
+    /// our txn wasn't mined and nothing above explains why
+    NotIncluded,
+}
+
+impl fmt::Display for MissReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissReason::Materialized => write!(f, "materialized"),
+            MissReason::VictimDropped => write!(f, "victim dropped"),
+            MissReason::CompetitorInserted { tx, index } => {
+                write!(f, "competitor {:?} inserted at index {}", tx, index)
+            }
+            MissReason::StateDivergence { pools } => {
+                write!(f, "state diverged on {} pool(s): {:?}", pools.len(), pools)
+            }
+            MissReason::NotIncluded => write!(f, "not included"),
+        }
+    }
+}
+
+/// differences between a predicted and a mined block
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BlockDiff {
+    /// predicted txns that weren't mined
+    pub missing: Vec<H256>,
+    /// mined txns we didn't predict, with their index in the mined block
+    pub unexpected: Vec<(H256, usize)>,
+    /// txns mined in a different relative order, with predicted and mined index
+    pub reordered: Vec<(H256, usize, usize)>,
+    pub pool_divergence: Vec<PoolDivergence>,
+    /// mined txn hash -> index
+    #[serde(skip)]
+    mined_index: HashMap<H256, usize>,
+}
+
+impl BlockDiff {
+    /// `mined` is the mined block's txn hashes in order, `mined_states` the
+    /// pool reserves after it
+    pub fn new(
+        predicted: &PredictedBlock,
+        mined: &[H256],
+        mined_states: &HashMap<Address, Reserves>,
+    ) -> Self {
+        let mined_index: HashMap<H256, usize> = mined
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (*hash, i))
+            .collect();
+        let predicted_index: HashMap<H256, usize> = predicted
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (*hash, i))
+            .collect();
+
+        let missing = predicted
+            .transactions
+            .iter()
+            .filter(|hash| !mined_index.contains_key(hash))
+            .copied()
+            .collect();
+        let unexpected = mined
+            .iter()
+            .enumerate()
+            .filter(|(_, hash)| !predicted_index.contains_key(hash))
+            .map(|(i, hash)| (*hash, i))
+            .collect();
+
+        // compare positions among the txns both blocks share, so a single
+        // insertion doesn't show up as everything after it being reordered
+        let shared_predicted: Vec<H256> = predicted
+            .transactions
+            .iter()
+            .filter(|hash| mined_index.contains_key(hash))
+            .copied()
+            .collect();
+        let shared_mined: Vec<H256> = mined
+            .iter()
+            .filter(|hash| predicted_index.contains_key(hash))
+            .copied()
+            .collect();
+        let reordered = shared_predicted
+            .iter()
+            .zip(&shared_mined)
+            .filter(|(predicted, mined)| predicted != mined)
+            .map(|(hash, _)| (*hash, predicted_index[hash], mined_index[hash]))
+            .collect();
+
+        let mut pool_divergence: Vec<PoolDivergence> = predicted
+            .pool_states
+            .iter()
+            .filter(|(pool, reserves)| mined_states.get(pool) != Some(reserves))
+            .map(|(pool, reserves)| PoolDivergence {
+                pool: *pool,
+                predicted: *reserves,
+                mined: mined_states.get(pool).copied(),
+            })
+            .collect();
+        pool_divergence.sort_by_key(|divergence| divergence.pool);
+
+        Self {
+            missing,
+            unexpected,
+            reordered,
+            pool_divergence,
+            mined_index,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.reordered.is_empty()
+            && self.pool_divergence.is_empty()
+    }
+
+    /// works out why `arb` did or didn't play out as predicted
+    pub fn explain(&self, arb: &PredictedArb) -> MissReason {
+        let victim_index = match arb.victim {
+            Some(victim) => match self.mined_index.get(&victim) {
+                Some(index) => Some(*index),
+                None => return MissReason::VictimDropped,
+            },
+            None => None,
+        };
+        let our_index = self.mined_index.get(&arb.our_tx).copied();
+        let slot_start = victim_index.map(|i| i + 1).unwrap_or(0);
+        let slot_end = our_index.unwrap_or(slot_start + 1);
+
+        // first unpredicted txn that took our place behind the victim
+        if let Some((tx, index)) = self
+            .unexpected
+            .iter()
+            .find(|(_, index)| *index >= slot_start && *index < slot_end)
+        {
+            return MissReason::CompetitorInserted {
+                tx: *tx,
+                index: *index,
+            };
+        }
+
+        let diverged: Vec<Address> = self
+            .pool_divergence
+            .iter()
+            .filter(|divergence| arb.pools.contains(&divergence.pool))
+            .map(|divergence| divergence.pool)
+            .collect();
+        if !diverged.is_empty() {
+            return MissReason::StateDivergence { pools: diverged };
+        }
+        if our_index.is_some() {
+            MissReason::Materialized
+        } else {
+            MissReason::NotIncluded
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_block_diff() {
+        let victim = H256::random();
+        let ours = H256::random();
+        let other = H256::random();
+        let pool = Address::random();
+        let reserves = (U256::from(100), U256::from(200));
+        let predicted = PredictedBlock {
+            transactions: vec![other, victim, ours],
+            pool_states: HashMap::from([(pool, reserves)]),
+        };
+        let arb = PredictedArb {
+            victim: Some(victim),
+            our_tx: ours,
+            pools: vec![pool],
+        };
+        let states = HashMap::from([(pool, reserves)]);
+
+        let diff = BlockDiff::new(&predicted, &[other, victim, ours], &states);
+        assert!(diff.is_empty());
+        assert_eq!(diff.explain(&arb), MissReason::Materialized);
+
+        let diff = BlockDiff::new(&predicted, &[other, ours], &states);
+        assert_eq!(diff.explain(&arb), MissReason::VictimDropped);
+
+        let rival = H256::random();
+        let diff = BlockDiff::new(&predicted, &[victim, rival, other], &states);
+        assert_eq!(
+            diff.explain(&arb),
+            MissReason::CompetitorInserted {
+                tx: rival,
+                index: 1
+            }
+        );
+        assert_eq!(diff.reordered.len(), 2);
+
+        let moved = HashMap::from([(pool, (U256::from(90), U256::from(220)))]);
+        let diff = BlockDiff::new(&predicted, &[other, victim], &moved);
+        assert_eq!(
+            diff.explain(&arb),
+            MissReason::StateDivergence { pools: vec![pool] }
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use ethers::{
+    providers::{Middleware, ProviderError},
+    types::{Bytes, H256, H64, U256},
+    utils::{self, rlp},
+};
+
+use super::{
+    block::{Block, Header, PartialHeader},
+    tracer::{BlockTraceResult, DebugTraceExt, TraceConfig},
+    transaction::{StateSyncTransaction, TypedTransaction},
+};
+
+/// trace of one transaction of a simulated block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatedTransaction {
+    pub hash: H256,
+    pub trace: BlockTraceResult,
+}
+
+/// Assembles a block out of arbitrary transactions and runs it through
+/// `debug_traceBlock`. The block executes on top of its parent's state, so
+/// `from_block` replays an existing block while `next_block` builds the one
+/// after `parent`.
+pub struct BlockSimulator<M> {
+    provider: Arc<M>,
+    header: PartialHeader,
+    transactions: Vec<TypedTransaction>,
+    /// bor's state-sync txn, kept at the end of the block
+    state_sync: Option<StateSyncTransaction>,
+    ommers: Vec<Header>,
+    config: TraceConfig,
+}
+
+impl<M: Middleware> BlockSimulator<M> {
+    /// starts from the contents of block `number`
+    pub async fn from_block(provider: Arc<M>, number: u64) -> Result<Self, ProviderError> {
+        let block = fetch_block(&*provider, number).await?;
+        Ok(Self {
+            provider,
+            header: block.header.into(),
+            transactions: block.transactions,
+            state_sync: block.state_sync,
+            ommers: block.ommers,
+            config: TraceConfig::call_tracer(false),
+        })
+    }
+
+    /// empty block following `parent`
+    pub fn next_block(provider: Arc<M>, parent: Header, base_fee: U256, gas_limit: U256) -> Self {
+        let parent_hash = parent.hash();
+        let parent: PartialHeader = parent.into();
+        // fyi: https://ethereum.stackexchange.com/questions/6400/what-is-the-exact-data-structure-of-each-block
+        let header = PartialHeader {
+            parent_hash,
+            beneficiary: parent.beneficiary,
+            state_root: H256::zero(),
+            receipts_root: H256::zero(),
+            logs_bloom: parent.logs_bloom,
+            difficulty: parent.difficulty,
+            number: parent.number + 1,
+            gas_limit,
+            gas_used: gas_limit,
+            timestamp: parent.timestamp,
+            extra_data: parent.extra_data,
+            mix_hash: H256::zero(),
+            nonce: H64::zero(),
+            base_fee: Some(base_fee),
+        };
+        Self {
+            provider,
+            header,
+            transactions: Vec::new(),
+            state_sync: None,
+            ommers: Vec::new(),
+            config: TraceConfig::call_tracer(false),
+        }
+    }
+
+    /// empty block following block `parent_number`
+    pub async fn after_block(
+        provider: Arc<M>,
+        parent_number: u64,
+        base_fee: U256,
+        gas_limit: U256,
+    ) -> Result<Self, ProviderError> {
+        let parent = fetch_block(&*provider, parent_number).await?;
+        Ok(Self::next_block(
+            provider,
+            parent.header,
+            base_fee,
+            gas_limit,
+        ))
+    }
+
+    /// tracer config used by `simulate`, defaults to the full callTracer
+    pub fn with_config(mut self, config: TraceConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn transactions(&self) -> &[TypedTransaction] {
+        &self.transactions
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn position(&self, hash: H256) -> Option<usize> {
+        self.transactions.iter().position(|tx| tx.hash() == hash)
+    }
+
+    pub fn push(&mut self, tx: TypedTransaction) {
+        self.transactions.push(tx);
+    }
+
+    /// inserts `tx` at `index`, shifting everything after it back
+    pub fn insert(&mut self, index: usize, tx: TypedTransaction) {
+        self.transactions.insert(index, tx);
+    }
+
+    pub fn replace(&mut self, index: usize, tx: TypedTransaction) -> TypedTransaction {
+        std::mem::replace(&mut self.transactions[index], tx)
+    }
+
+    pub fn remove(&mut self, index: usize) -> TypedTransaction {
+        self.transactions.remove(index)
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.transactions.swap(a, b);
+    }
+
+    /// moves the transaction at `from` to `to`
+    pub fn reorder(&mut self, from: usize, to: usize) {
+        let tx = self.transactions.remove(from);
+        self.transactions.insert(to, tx);
+    }
+
+    pub fn clear(&mut self) {
+        self.transactions.clear();
+    }
+
+    pub fn state_sync(&self) -> Option<&StateSyncTransaction> {
+        self.state_sync.as_ref()
+    }
+
+    pub fn block(&self) -> Block {
+        Block::with_state_sync(
+            self.header.clone(),
+            self.transactions.clone(),
+            self.state_sync.clone(),
+            self.ommers.clone(),
+        )
+    }
+
+    /// traces every transaction of the assembled block, in block order. The
+    /// state-sync txn still executes but isn't reported.
+    pub async fn simulate(&self) -> Result<Vec<SimulatedTransaction>, ProviderError> {
+        let block_rlp = Bytes::from(rlp::encode(&self.block()).to_vec());
+        let traces = self
+            .provider
+            .call_trace_block(block_rlp, &self.config)
+            .await?;
+        Ok(self
+            .transactions
+            .iter()
+            .zip(traces)
+            .map(|(tx, trace)| SimulatedTransaction {
+                hash: tx.hash(),
+                trace,
+            })
+            .collect())
+    }
+
+    /// what happens if `tx` is included at position `index`
+    pub async fn simulate_at(
+        &self,
+        index: usize,
+        tx: TypedTransaction,
+    ) -> Result<BlockTraceResult, ProviderError> {
+        let mut transactions = self.transactions[..index].to_vec();
+        transactions.push(tx);
+        let block = Block::new(self.header.clone(), transactions, self.ommers.clone());
+        let block_rlp = Bytes::from(rlp::encode(&block).to_vec());
+        // later transactions can't affect ours, leave them out
+        let mut traces = self
+            .provider
+            .call_trace_block(block_rlp, &self.config)
+            .await?;
+        Ok(traces.pop().unwrap_or_default())
+    }
+}
+
+async fn fetch_block<M: Middleware>(provider: &M, number: u64) -> Result<Block, ProviderError> {
+    let bytes: Bytes = provider
+        .provider()
+        .request("debug_getBlockRlp", [utils::serialize(&number)])
+        .await?;
+    rlp::decode(&bytes).map_err(|e| ProviderError::CustomError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        types::{Address, Signature},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::{
+        batch::fake::FakeTransport,
+        transaction::{LegacyTransaction, TransactionKind},
+    };
+
+    fn tx(nonce: u64) -> TypedTransaction {
+        TypedTransaction::Legacy(LegacyTransaction {
+            nonce: nonce.into(),
+            gas_price: 30_000_000_000u64.into(),
+            gas_limit: 21_000.into(),
+            kind: TransactionKind::Call(Address::repeat_byte(1)),
+            value: U256::zero(),
+            input: Bytes::default(),
+            signature: Signature {
+                r: 1.into(),
+                s: 1.into(),
+                v: 309,
+            },
+        })
+    }
+
+    fn trace(gas_used: u64) -> serde_json::Value {
+        json!({
+            "result": {
+                "from": Address::repeat_byte(2),
+                "to": Address::repeat_byte(1),
+                "gas": U256::from(21_000),
+                "gasUsed": U256::from(gas_used),
+                "input": "0x"
+            }
+        })
+    }
+
+    /// the block a `debug_traceBlock` request asked to trace
+    fn traced_block(params: &serde_json::Value) -> Block {
+        let block_rlp: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+        rlp::decode(&block_rlp).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_block_simulator() {
+        let transport = FakeTransport::new();
+        let provider = Arc::new(Provider::new(transport.clone()));
+        let parent = Block::new(
+            PartialHeader {
+                number: 100.into(),
+                ..Default::default()
+            },
+            vec![tx(0)],
+            vec![],
+        )
+        .header;
+        let mut simulator = BlockSimulator::next_block(
+            provider.clone(),
+            parent.clone(),
+            30.into(),
+            1_000_000.into(),
+        );
+        for nonce in 1..=3 {
+            simulator.push(tx(nonce));
+        }
+        simulator.reorder(2, 0);
+        assert_eq!(simulator.position(tx(3).hash()), Some(0));
+        assert_eq!(simulator.len(), 3);
+
+        transport.push_response("debug_traceBlock", json!([trace(1), trace(2), trace(3)]));
+        let simulated = simulator.simulate().await.unwrap();
+        let hashes: Vec<H256> = simulated.iter().map(|tx| tx.hash).collect();
+        assert_eq!(hashes, vec![tx(3).hash(), tx(1).hash(), tx(2).hash()]);
+        assert_eq!(simulated[2].trace.gas_used, 3.into());
+        let block = traced_block(&transport.requests()[0].1);
+        assert_eq!(block.header.parent_hash, parent.hash());
+        assert_eq!(block.header.number, 101.into());
+        assert_eq!(block.header.base_fee_per_gas, Some(30.into()));
+        assert_eq!(block.transactions, simulator.transactions());
+
+        // only what comes before it is traced with it
+        transport.clear_requests();
+        transport.push_response("debug_traceBlock", json!([trace(1), trace(7)]));
+        let trace = simulator.simulate_at(1, tx(9)).await.unwrap();
+        assert_eq!(trace.gas_used, 7.into());
+        let block = traced_block(&transport.requests()[0].1);
+        assert_eq!(block.transactions, vec![tx(3), tx(9)]);
+
+        // replaying a block starts from its txns
+        let replayed = Block::new(parent.clone().into(), vec![tx(4), tx(5)], vec![]);
+        transport.push_response(
+            "debug_getBlockRlp",
+            Bytes::from(rlp::encode(&replayed).to_vec()),
+        );
+        let simulator = BlockSimulator::from_block(provider, 100).await.unwrap();
+        assert_eq!(simulator.transactions(), replayed.transactions);
+        assert_eq!(simulator.block().header, replayed.header);
+    }
+}
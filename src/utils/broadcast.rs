@@ -0,0 +1,384 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, JsonRpcClient, Provider, ProviderError, Ws},
+    types::{Bytes, H256},
+    utils::keccak256,
+};
+use futures_util::future::join_all;
+use log::{debug, warn};
+use serde::Serialize;
+
+/// anything that takes `eth_sendRawTransaction`
+#[async_trait]
+pub trait RawTransactionSender: Send + Sync {
+    async fn send_raw(&self, raw: &Bytes) -> Result<H256, ProviderError>;
+}
+
+#[async_trait]
+impl<P: JsonRpcClient + 'static> RawTransactionSender for Provider<P> {
+    async fn send_raw(&self, raw: &Bytes) -> Result<H256, ProviderError> {
+        self.request("eth_sendRawTransaction", [raw]).await
+    }
+}
+
+/// errors meaning the endpoint already has the txn, it propagated anyway
+const KNOWN_ERRORS: [&str; 2] = ["already known", "known transaction"];
+
+/// channel name of the local node in `SubmissionStats`
+pub const LOCAL_CHANNEL: &str = "local";
+
+/// txns whose channels are remembered until their outcome is recorded
+const MAX_TRACKED_TXNS: usize = 1024;
+
+fn is_known(error: &str) -> bool {
+    KNOWN_ERRORS.iter().any(|known| error.contains(known))
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastReport {
+    pub hash: H256,
+    /// endpoints that took the txn
+    pub accepted: Vec<String>,
+    /// error message -> endpoints that returned it
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+impl BroadcastReport {
+    pub fn is_accepted(&self) -> bool {
+        !self.accepted.is_empty()
+    }
+}
+
+/// Sends signed txns to every endpoint at once, whichever reaches the
+/// validator first wins the race.
+pub struct Broadcaster {
+    endpoints: Vec<(String, Arc<dyn RawTransactionSender>)>,
+    timeout: Duration,
+}
+
+impl Broadcaster {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            endpoints: Vec::new(),
+            timeout,
+        }
+    }
+
+    /// Connects to every url, `ws(s)://` over websockets, `http(s)://` over
+    /// http and everything else as an ipc path.
+    pub async fn connect(urls: &[&str], timeout: Duration) -> Result<Self, ProviderError> {
+        let mut broadcaster = Self::new(timeout);
+        for url in urls {
+            let sender: Arc<dyn RawTransactionSender> =
+                if url.starts_with("ws://") || url.starts_with("wss://") {
+                    Arc::new(Provider::<Ws>::connect(*url).await?)
+                } else if url.starts_with("http://") || url.starts_with("https://") {
+                    Arc::new(
+                        Provider::<Http>::try_from(*url)
+                            .map_err(|e| ProviderError::CustomError(e.to_string()))?,
+                    )
+                } else {
+                    Arc::new(Provider::connect_ipc(*url).await?)
+                };
+            broadcaster = broadcaster.with_endpoint(*url, sender);
+        }
+        Ok(broadcaster)
+    }
+
+    pub fn with_endpoint(
+        mut self,
+        name: impl ToString,
+        sender: Arc<dyn RawTransactionSender>,
+    ) -> Self {
+        self.endpoints.push((name.to_string(), sender));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// how long each endpoint took to accept `raw`, or why it didn't
+    pub async fn broadcast_timed(&self, raw: &Bytes) -> Vec<(String, Result<Duration, String>)> {
+        let started = Instant::now();
+        join_all(self.endpoints.iter().map(|(name, sender)| async move {
+            let result = match tokio::time::timeout(self.timeout, sender.send_raw(raw)).await {
+                Ok(Ok(_)) => Ok(started.elapsed()),
+                Ok(Err(e)) if is_known(&e.to_string()) => Ok(started.elapsed()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err("timed out".to_string()),
+            };
+            (name.clone(), result)
+        }))
+        .await
+    }
+
+    pub async fn broadcast(&self, raw: &Bytes) -> BroadcastReport {
+        let mut report = BroadcastReport {
+            hash: H256::from(keccak256(raw)),
+            ..Default::default()
+        };
+        for (name, result) in self.broadcast_timed(raw).await {
+            match result {
+                Ok(_) => report.accepted.push(name),
+                Err(e) => report.errors.entry(e).or_default().push(name),
+            }
+        }
+        report
+    }
+
+    /// broadcasts `raws` in order, each to all endpoints at once
+    pub async fn broadcast_all(&self, raws: &[Bytes]) -> Vec<BroadcastReport> {
+        let mut reports = Vec::with_capacity(raws.len());
+        for raw in raws {
+            reports.push(self.broadcast(raw).await);
+        }
+        reports
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ChannelStats {
+    pub channel: String,
+    pub submitted: u64,
+    pub accepted: u64,
+    /// included txns this channel accepted before any other
+    pub wins: u64,
+    pub mean_accept_ms: f64,
+}
+
+#[derive(Default)]
+struct Submissions {
+    channels: BTreeMap<String, ChannelStats>,
+    /// txn -> (channel, time from submission to acceptance) of every
+    /// channel that accepted it
+    accepted: HashMap<H256, Vec<(String, Duration)>>,
+    order: VecDeque<H256>,
+}
+
+/// Which channels accept txns, how fast, and which one got there first for
+/// the txns that were included. The first to accept stands in for the one
+/// the validator heard it from, nothing on chain tells.
+#[derive(Default)]
+pub struct SubmissionStats {
+    submissions: Mutex<Submissions>,
+}
+
+impl SubmissionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `result` is the time from submitting `hash` to `channel` accepting
+    /// it, measured from when the txn was first sent anywhere
+    pub fn record_attempt(&self, hash: H256, channel: &str, result: Result<Duration, String>) {
+        let mut submissions = self.submissions.lock().unwrap();
+        let stats = submissions
+            .channels
+            .entry(channel.to_string())
+            .or_insert_with(|| ChannelStats {
+                channel: channel.to_string(),
+                ..Default::default()
+            });
+        stats.submitted += 1;
+        let latency = match result {
+            Ok(latency) => latency,
+            Err(e) => {
+                debug!("{} rejected {:?}: {}", channel, hash, e);
+                return;
+            }
+        };
+        let ms = latency.as_secs_f64() * 1000.0;
+        stats.mean_accept_ms += (ms - stats.mean_accept_ms) / (stats.accepted + 1) as f64;
+        stats.accepted += 1;
+
+        if !submissions.accepted.contains_key(&hash) {
+            submissions.order.push_back(hash);
+            while submissions.order.len() > MAX_TRACKED_TXNS {
+                let oldest = submissions.order.pop_front().unwrap();
+                submissions.accepted.remove(&oldest);
+            }
+        }
+        submissions
+            .accepted
+            .entry(hash)
+            .or_default()
+            .push((channel.to_string(), latency));
+    }
+
+    /// `hash` was mined (`included`) or dropped, credits the channel that
+    /// accepted it first with a win. Returns that channel.
+    pub fn record_outcome(&self, hash: H256, included: bool) -> Option<String> {
+        let mut submissions = self.submissions.lock().unwrap();
+        let accepted = submissions.accepted.remove(&hash)?;
+        submissions.order.retain(|kept| *kept != hash);
+        if !included {
+            return None;
+        }
+        let (winner, _) = accepted.into_iter().min_by_key(|(_, latency)| *latency)?;
+        if let Some(stats) = submissions.channels.get_mut(&winner) {
+            stats.wins += 1;
+        }
+        Some(winner)
+    }
+
+    pub fn channels(&self) -> Vec<ChannelStats> {
+        let submissions = self.submissions.lock().unwrap();
+        submissions.channels.values().cloned().collect()
+    }
+}
+
+/// Sends signed txns to the local node and returns as soon as it answers,
+/// the relays and public RPCs get the txn in the background. Every
+/// channel's answer is recorded in `stats`.
+pub struct TieredSender {
+    local: Arc<dyn RawTransactionSender>,
+    relays: Arc<Broadcaster>,
+    stats: Arc<SubmissionStats>,
+}
+
+impl TieredSender {
+    pub fn new(
+        local: Arc<dyn RawTransactionSender>,
+        relays: Broadcaster,
+        stats: Arc<SubmissionStats>,
+    ) -> Self {
+        Self {
+            local,
+            relays: Arc::new(relays),
+            stats,
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<SubmissionStats> {
+        &self.stats
+    }
+
+    /// the local node's answer, relays are still being sent to
+    pub async fn send(&self, raw: Bytes) -> Result<H256, ProviderError> {
+        let hash = H256::from(keccak256(&raw));
+        let started = Instant::now();
+        let local = self.local.send_raw(&raw).await;
+        let local_latency = started.elapsed();
+        let result = match &local {
+            Ok(_) => Ok(local_latency),
+            Err(e) if is_known(&e.to_string()) => Ok(local_latency),
+            Err(e) => Err(e.to_string()),
+        };
+        self.stats.record_attempt(hash, LOCAL_CHANNEL, result);
+
+        if !self.relays.is_empty() {
+            let relays = self.relays.clone();
+            let stats = self.stats.clone();
+            tokio::spawn(async move {
+                for (name, result) in relays.broadcast_timed(&raw).await {
+                    if let Err(e) = &result {
+                        warn!("Relay {} rejected {:?}: {}", name, hash, e);
+                    }
+                    // relays only started once the local node answered
+                    stats.record_attempt(
+                        hash,
+                        &name,
+                        result.map(|latency| latency + local_latency),
+                    );
+                }
+            });
+        }
+        local.map(|_| hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Replies(Result<(), &'static str>);
+
+    #[async_trait]
+    impl RawTransactionSender for Replies {
+        async fn send_raw(&self, raw: &Bytes) -> Result<H256, ProviderError> {
+            match self.0 {
+                Ok(()) => Ok(H256::from(keccak256(raw))),
+                Err(e) => Err(ProviderError::CustomError(e.to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_dedups_errors() {
+        let broadcaster = Broadcaster::new(Duration::from_secs(1))
+            .with_endpoint("ipc", Arc::new(Replies(Ok(()))))
+            .with_endpoint("public", Arc::new(Replies(Err("already known"))))
+            .with_endpoint("a", Arc::new(Replies(Err("nonce too low"))))
+            .with_endpoint("b", Arc::new(Replies(Err("nonce too low"))));
+        let report = broadcaster.broadcast(&Bytes::from(vec![1, 2, 3])).await;
+        assert_eq!(report.accepted, vec!["ipc", "public"]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(
+            report.errors.values().next().unwrap(),
+            &vec!["a".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_submission_stats() {
+        let stats = SubmissionStats::new();
+        let (first, second) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        stats.record_attempt(first, LOCAL_CHANNEL, Ok(Duration::from_millis(2)));
+        stats.record_attempt(first, "relay", Ok(Duration::from_millis(30)));
+        stats.record_attempt(second, LOCAL_CHANNEL, Err("txpool is full".into()));
+        stats.record_attempt(second, "relay", Ok(Duration::from_millis(10)));
+
+        assert_eq!(
+            stats.record_outcome(first, true),
+            Some(LOCAL_CHANNEL.to_string())
+        );
+        assert_eq!(
+            stats.record_outcome(second, true),
+            Some("relay".to_string())
+        );
+        // already recorded
+        assert_eq!(stats.record_outcome(second, true), None);
+
+        let channels = stats.channels();
+        let local = &channels[0];
+        assert_eq!(local.channel, LOCAL_CHANNEL);
+        assert_eq!((local.submitted, local.accepted, local.wins), (2, 1, 1));
+        let relay = &channels[1];
+        assert_eq!((relay.submitted, relay.accepted, relay.wins), (2, 2, 1));
+        assert_eq!(relay.mean_accept_ms, 20.0);
+    }
+
+    #[tokio::test]
+    async fn test_tiered_sender() {
+        let stats = Arc::new(SubmissionStats::new());
+        let relays = Broadcaster::new(Duration::from_secs(1))
+            .with_endpoint("relay", Arc::new(Replies(Ok(()))));
+        let sender = TieredSender::new(Arc::new(Replies(Err("txpool is full"))), relays, stats);
+        let raw = Bytes::from(vec![1, 2, 3]);
+        assert!(sender.send(raw.clone()).await.is_err());
+
+        // the relay still got it
+        for _ in 0..100 {
+            if sender.stats().channels().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let hash = H256::from(keccak256(&raw));
+        assert_eq!(
+            sender.stats().record_outcome(hash, true),
+            Some("relay".to_string())
+        );
+    }
+}
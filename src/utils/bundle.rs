@@ -0,0 +1,305 @@
+//! Bundles around someone else's pending txn: our signed txns ahead of it,
+//! after it or both, landing as one unit or not at all. `VictimBundle`
+//! keeps the order and the validity window, and renders them for either
+//! relay API: `eth_sendBundle` (raw txns, one bundle per block, timestamp
+//! bounds) or `mev_sendBundle` (the victim by hash, a block range, no
+//! timestamps).
+
+use ethers::{
+    types::{Address, Bytes, Transaction, H256, U256, U64},
+    utils::keccak256,
+};
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::utils::{
+    mev_share::{BundleItem, BundleRequest, Inclusion},
+    transaction::{decode_raw_transaction, RawTransactionError},
+};
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error(transparent)]
+    RawTransactionError(#[from] RawTransactionError),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("victim {0:?} doesn't encode to its hash, is it signed?")]
+    UnsignedVictim(H256),
+
+    #[error("bundle has none of our txns")]
+    Empty,
+
+    #[error("nonce {nonce} of {sender:?} doesn't follow {previous} in the bundle")]
+    NonceOrder {
+        sender: Address,
+        nonce: U256,
+        previous: U256,
+    },
+
+    #[error("min timestamp {min} is past max timestamp {max}")]
+    Timestamps { min: u64, max: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelayFormat {
+    EthSendBundle,
+    MevSendBundle,
+}
+
+impl RelayFormat {
+    pub fn method(&self) -> &'static str {
+        match self {
+            RelayFormat::EthSendBundle => "eth_sendBundle",
+            RelayFormat::MevSendBundle => "mev_sendBundle",
+        }
+    }
+}
+
+/// `eth_sendBundle` params
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthBundle {
+    pub txs: Vec<Bytes>,
+    pub block_number: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_timestamp: Option<u64>,
+}
+
+/// Our txns around a victim's, for `block` to `max_block`.
+#[derive(Clone, Debug)]
+pub struct VictimBundle {
+    victim: Transaction,
+    victim_raw: Bytes,
+    front: Vec<Bytes>,
+    back: Vec<Bytes>,
+    block: u64,
+    max_block: u64,
+    min_timestamp: Option<u64>,
+    max_timestamp: Option<u64>,
+}
+
+impl VictimBundle {
+    /// `victim` as seen in the mempool, targeting `block` only
+    pub fn new(victim: Transaction, block: u64) -> Result<Self, BundleError> {
+        let victim_raw = victim.rlp();
+        if H256(keccak256(&victim_raw)) != victim.hash {
+            return Err(BundleError::UnsignedVictim(victim.hash));
+        }
+        Ok(Self {
+            victim,
+            victim_raw,
+            front: Vec::new(),
+            back: Vec::new(),
+            block,
+            max_block: block,
+            min_timestamp: None,
+            max_timestamp: None,
+        })
+    }
+
+    /// `tx` signed raw, ahead of the victim after the ones added before
+    pub fn frontrun(mut self, tx: Bytes) -> Self {
+        self.front.push(tx);
+        self
+    }
+
+    /// `tx` signed raw, after the victim and the ones added before
+    pub fn backrun(mut self, tx: Bytes) -> Self {
+        self.back.push(tx);
+        self
+    }
+
+    /// also valid in the blocks up to `max_block`
+    pub fn until_block(mut self, max_block: u64) -> Self {
+        self.max_block = max_block.max(self.block);
+        self
+    }
+
+    /// only valid in blocks with a timestamp within the bounds, in unix
+    /// seconds
+    pub fn between(mut self, min_timestamp: Option<u64>, max_timestamp: Option<u64>) -> Self {
+        self.min_timestamp = min_timestamp;
+        self.max_timestamp = max_timestamp;
+        self
+    }
+
+    pub fn victim(&self) -> &Transaction {
+        &self.victim
+    }
+
+    /// Every txn raw, in the order they land. Fails if the txns of a
+    /// sender, the victim included, don't have consecutive nonces in that
+    /// order, the relay would drop the bundle.
+    pub fn ordered(&self) -> Result<Vec<Bytes>, BundleError> {
+        if self.front.is_empty() && self.back.is_empty() {
+            return Err(BundleError::Empty);
+        }
+        if let (Some(min), Some(max)) = (self.min_timestamp, self.max_timestamp) {
+            if min > max {
+                return Err(BundleError::Timestamps { min, max });
+            }
+        }
+        let mut senders = Vec::new();
+        for tx in &self.front {
+            let (tx, sender) = decode_raw_transaction(tx)?;
+            senders.push((sender, *tx.nonce()));
+        }
+        senders.push((self.victim.from, self.victim.nonce));
+        for tx in &self.back {
+            let (tx, sender) = decode_raw_transaction(tx)?;
+            senders.push((sender, *tx.nonce()));
+        }
+        for (i, (sender, nonce)) in senders.iter().enumerate() {
+            let previous = senders[..i]
+                .iter()
+                .rev()
+                .find(|(earlier, _)| earlier == sender);
+            if let Some((_, previous)) = previous {
+                if *nonce != previous + 1 {
+                    return Err(BundleError::NonceOrder {
+                        sender: *sender,
+                        nonce: *nonce,
+                        previous: *previous,
+                    });
+                }
+            }
+        }
+        let mut txs = self.front.clone();
+        txs.push(self.victim_raw.clone());
+        txs.extend(self.back.iter().cloned());
+        Ok(txs)
+    }
+
+    /// one bundle per block of the range, `eth_sendBundle` takes a single
+    /// block
+    pub fn eth_bundles(&self) -> Result<Vec<EthBundle>, BundleError> {
+        let txs = self.ordered()?;
+        Ok((self.block..=self.max_block)
+            .map(|block| EthBundle {
+                txs: txs.clone(),
+                block_number: block.into(),
+                min_timestamp: self.min_timestamp,
+                max_timestamp: self.max_timestamp,
+            })
+            .collect())
+    }
+
+    /// The victim by hash between our txns. `mev_sendBundle` has no
+    /// timestamp bounds, only the block range applies.
+    pub fn mev_bundle(&self) -> Result<BundleRequest, BundleError> {
+        self.ordered()?;
+        let ours = |tx: &Bytes| BundleItem::Tx {
+            tx: tx.clone(),
+            can_revert: false,
+        };
+        let mut body: Vec<BundleItem> = self.front.iter().map(ours).collect();
+        body.push(BundleItem::Hash {
+            hash: self.victim.hash,
+        });
+        body.extend(self.back.iter().map(ours));
+        Ok(BundleRequest {
+            version: "v0.1",
+            inclusion: Inclusion {
+                block: self.block.into(),
+                max_block: (self.max_block > self.block).then(|| self.max_block.into()),
+            },
+            body,
+        })
+    }
+
+    /// the params of every request to send the bundle through a relay
+    /// speaking `format`
+    pub fn payloads(&self, format: RelayFormat) -> Result<Vec<Value>, BundleError> {
+        match format {
+            RelayFormat::EthSendBundle => self
+                .eth_bundles()?
+                .iter()
+                .map(|bundle| Ok(serde_json::to_value([bundle])?))
+                .collect(),
+            RelayFormat::MevSendBundle => Ok(vec![serde_json::to_value([self.mev_bundle()?])?]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{transaction::eip2718::TypedTransaction, TransactionRequest},
+        utils::rlp::{Decodable, Rlp},
+    };
+
+    use super::*;
+
+    fn signed(wallet: &LocalWallet, nonce: u64) -> Bytes {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(9))
+            .nonce(nonce)
+            .gas(21_000)
+            .gas_price(100)
+            .chain_id(137)
+            .into();
+        let signature = wallet.sign_transaction_sync(&tx);
+        tx.rlp_signed(&signature)
+    }
+
+    #[test]
+    fn test_victim_bundle() {
+        let ours = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64);
+        let theirs = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(137u64);
+        let victim_raw = signed(&theirs, 4);
+        let mut victim = Transaction::decode(&Rlp::new(&victim_raw)).unwrap();
+        // as the node reports it
+        victim.hash = H256(keccak256(&victim_raw));
+        victim.from = theirs.address();
+        let (front, back) = (signed(&ours, 10), signed(&ours, 11));
+
+        let bundle = VictimBundle::new(victim.clone(), 100)
+            .unwrap()
+            .frontrun(front.clone())
+            .backrun(back.clone())
+            .until_block(101)
+            .between(Some(1_700_000_000), None);
+        assert_eq!(
+            bundle.ordered().unwrap(),
+            vec![front.clone(), victim_raw.clone(), back.clone()]
+        );
+
+        let payloads = bundle.payloads(RelayFormat::EthSendBundle).unwrap();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[1][0]["blockNumber"], "0x65");
+        assert_eq!(payloads[1][0]["minTimestamp"], 1_700_000_000);
+        assert!(payloads[1][0].get("maxTimestamp").is_none());
+        let payloads = bundle.payloads(RelayFormat::MevSendBundle).unwrap();
+        assert_eq!(
+            payloads[0][0]["body"][1]["hash"],
+            format!("{:?}", victim.hash)
+        );
+        assert_eq!(payloads[0][0]["inclusion"]["maxBlock"], "0x65");
+
+        // our nonces the wrong way around
+        let swapped = VictimBundle::new(victim.clone(), 100)
+            .unwrap()
+            .frontrun(back)
+            .backrun(front);
+        assert!(matches!(
+            swapped.ordered(),
+            Err(BundleError::NonceOrder { .. })
+        ));
+        let empty = VictimBundle::new(victim.clone(), 100).unwrap();
+        assert!(matches!(empty.ordered(), Err(BundleError::Empty)));
+        victim.r = U256::zero();
+        assert!(matches!(
+            VictimBundle::new(victim, 100),
+            Err(BundleError::UnsignedVictim(_))
+        ));
+    }
+}
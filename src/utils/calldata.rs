@@ -0,0 +1,909 @@
+//! decoding of txn calldata into what the sender is trying to do
+
+use std::{collections::HashMap, sync::Arc};
+
+use ethers::{
+    abi::parse_abi,
+    prelude::BaseContract,
+    types::{Address, Bytes, Transaction, U256},
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{
+    constants::{
+        protocol::{UniswapV2, UNISWAP_V3},
+        token::ERC20Token,
+    },
+    liquidator::AAVE_V3_POOL,
+};
+
+lazy_static! {
+    pub static ref ONE_INCH_V5_ROUTER: Address = "0x1111111254EEB25477B68fb85Ed929f73A960582"
+        .parse::<Address>()
+        .unwrap();
+    pub static ref ZERO_EX_PROXY: Address = "0xDef1C0ded9bec7F1a1670819833240f027b25EfF"
+        .parse::<Address>()
+        .unwrap();
+    /// placeholder aggregators use for the native token
+    pub static ref NATIVE_TOKEN: Address = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE"
+        .parse::<Address>()
+        .unwrap();
+
+    static ref UNISWAP_V2_ROUTER_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline)",
+            "function swapTokensForExactETH(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline)",
+            "function swapExactETHForTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "function swapETHForExactTokens(uint256 amountOut, address[] path, address to, uint256 deadline)",
+        ])
+        .unwrap()
+    );
+
+    static ref UNISWAP_V3_ROUTER_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "struct ExactInputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }",
+            "struct ExactInputParams { bytes path; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; }",
+            "struct ExactOutputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountOut; uint256 amountInMaximum; uint160 sqrtPriceLimitX96; }",
+            "struct ExactOutputParams { bytes path; address recipient; uint256 deadline; uint256 amountOut; uint256 amountInMaximum; }",
+            "function exactInputSingle(ExactInputSingleParams params)",
+            "function exactInput(ExactInputParams params)",
+            "function exactOutputSingle(ExactOutputSingleParams params)",
+            "function exactOutput(ExactOutputParams params)",
+            "function multicall(bytes[] data)",
+        ])
+        .unwrap()
+    );
+
+    static ref AAVE_POOL_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "function liquidationCall(address collateral, address debt, address user, uint256 debtToCover, bool receiveAToken)",
+            "function supply(address asset, uint256 amount, address onBehalfOf, uint16 referralCode)",
+            "function borrow(address asset, uint256 amount, uint256 interestRateMode, uint16 referralCode, address onBehalfOf)",
+            "function repay(address asset, uint256 amount, uint256 interestRateMode, address onBehalfOf)",
+            "function withdraw(address asset, uint256 amount, address to)",
+            "function flashLoanSimple(address receiverAddress, address asset, uint256 amount, bytes params, uint16 referralCode)",
+        ])
+        .unwrap()
+    );
+
+    static ref ONE_INCH_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "struct SwapDescription { address srcToken; address dstToken; address srcReceiver; address dstReceiver; uint256 amount; uint256 minReturnAmount; uint256 flags; }",
+            "function swap(address executor, SwapDescription desc, bytes permit, bytes data)",
+        ])
+        .unwrap()
+    );
+
+    static ref ZERO_EX_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "struct Transformation { uint32 deploymentNonce; bytes data; }",
+            "function transformERC20(address inputToken, address outputToken, uint256 inputTokenAmount, uint256 minOutputTokenAmount, Transformation[] transformations)",
+            "function sellToUniswap(address[] tokens, uint256 sellAmount, uint256 minBuyAmount, bool isSushi)",
+        ])
+        .unwrap()
+    );
+
+    static ref ERC20_ABI: BaseContract = BaseContract::from(
+        parse_abi(&[
+            "function transfer(address to, uint256 amount)",
+            "function transferFrom(address from, address to, uint256 amount)",
+            "function approve(address spender, uint256 amount)",
+        ])
+        .unwrap()
+    );
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum SwapAmount {
+    ExactIn {
+        amount_in: U256,
+        amount_out_min: U256,
+    },
+    ExactOut {
+        amount_out: U256,
+        amount_in_max: U256,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SwapIntent {
+    pub venue: String,
+    /// tokens from input to output, `NATIVE_TOKEN` or WMATIC for native swaps
+    pub path: Vec<Address>,
+    pub amount: SwapAmount,
+    pub recipient: Address,
+}
+
+impl SwapIntent {
+    pub fn token_in(&self) -> Option<Address> {
+        self.path.first().copied()
+    }
+
+    pub fn token_out(&self) -> Option<Address> {
+        self.path.last().copied()
+    }
+}
+
+/// what a call is trying to do, independent of the contract it goes through
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Intent {
+    Swap(SwapIntent),
+    Liquidation {
+        collateral: Address,
+        debt: Address,
+        user: Address,
+        debt_to_cover: U256,
+        receive_a_token: bool,
+    },
+    Supply {
+        asset: Address,
+        amount: U256,
+        on_behalf_of: Address,
+    },
+    Borrow {
+        asset: Address,
+        amount: U256,
+        on_behalf_of: Address,
+    },
+    Repay {
+        asset: Address,
+        amount: U256,
+        on_behalf_of: Address,
+    },
+    Withdraw {
+        asset: Address,
+        amount: U256,
+        to: Address,
+    },
+    FlashLoan {
+        receiver: Address,
+        asset: Address,
+        amount: U256,
+    },
+    Transfer {
+        token: Address,
+        from: Option<Address>,
+        to: Address,
+        amount: U256,
+    },
+    Approve {
+        token: Address,
+        spender: Address,
+        amount: U256,
+    },
+    /// several calls batched into one txn, in order
+    Multicall(Vec<Intent>),
+}
+
+/// Turns calldata of one contract (or one selector, for standards like
+/// ERC20) into an `Intent`.
+pub trait CalldataDecoder: Send + Sync {
+    fn decode(&self, to: Address, input: &[u8], value: U256) -> Option<Intent>;
+}
+
+/// Decoders by contract address, falling back to decoders by selector for
+/// calls to contracts we don't know.
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    contracts: HashMap<Address, Arc<dyn CalldataDecoder>>,
+    selectors: HashMap<[u8; 4], Arc<dyn CalldataDecoder>>,
+}
+
+impl DecoderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// registry with every router, pool and aggregator we know on polygon
+    pub fn polygon() -> Self {
+        let mut registry = Self::new();
+        for protocol in UniswapV2::get_all_protoccols() {
+            registry.register_contract(
+                protocol.get_router_address(),
+                Arc::new(UniswapV2RouterDecoder { protocol }),
+            );
+        }
+        registry.register_contract(UNISWAP_V3.router_address, Arc::new(UniswapV3RouterDecoder));
+        registry.register_contract(*AAVE_V3_POOL, Arc::new(AavePoolDecoder));
+        registry.register_contract(*ONE_INCH_V5_ROUTER, Arc::new(OneInchDecoder));
+        registry.register_contract(*ZERO_EX_PROXY, Arc::new(ZeroExDecoder));
+
+        let erc20: Arc<dyn CalldataDecoder> = Arc::new(Erc20Decoder);
+        for function in ERC20_ABI.abi().functions() {
+            registry.register_selector(function.short_signature(), erc20.clone());
+        }
+        registry
+    }
+
+    pub fn register_contract(&mut self, address: Address, decoder: Arc<dyn CalldataDecoder>) {
+        self.contracts.insert(address, decoder);
+    }
+
+    pub fn register_selector(&mut self, selector: [u8; 4], decoder: Arc<dyn CalldataDecoder>) {
+        self.selectors.insert(selector, decoder);
+    }
+
+    pub fn decode(&self, to: Address, input: &[u8], value: U256) -> Option<Intent> {
+        if let Some(intent) = self
+            .contracts
+            .get(&to)
+            .and_then(|decoder| decoder.decode(to, input, value))
+        {
+            return Some(intent);
+        }
+        let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+        self.selectors.get(&selector)?.decode(to, input, value)
+    }
+
+    pub fn decode_transaction(&self, txn: &Transaction) -> Option<Intent> {
+        self.decode(txn.to?, &txn.input, txn.value)
+    }
+}
+
+/// name of the function `input` calls in `abi`
+fn function_name<'a>(abi: &'a BaseContract, input: &[u8]) -> Option<(&'a str, [u8; 4])> {
+    let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+    abi.abi()
+        .functions()
+        .find(|function| function.short_signature() == selector)
+        .map(|function| (function.name.as_str(), selector))
+}
+
+/// addresses of a uniswap v3 encoded path: token, fee (3 bytes), token, ...
+pub fn decode_v3_path(path: &[u8]) -> Vec<Address> {
+    path.chunks(23)
+        .filter(|chunk| chunk.len() >= 20)
+        .map(|chunk| Address::from_slice(&chunk[..20]))
+        .collect()
+}
+
+/// the native token placeholder of aggregators, swapped for wrapped matic
+fn native(token: Address) -> Address {
+    if token == *NATIVE_TOKEN {
+        ERC20Token::WMATIC.get_address()
+    } else {
+        token
+    }
+}
+
+pub struct UniswapV2RouterDecoder {
+    pub protocol: UniswapV2,
+}
+
+impl CalldataDecoder for UniswapV2RouterDecoder {
+    fn decode(&self, _to: Address, input: &[u8], value: U256) -> Option<Intent> {
+        let abi = &*UNISWAP_V2_ROUTER_ABI;
+        let (name, selector) = function_name(abi, input)?;
+        let (path, amount, recipient) = match name {
+            "swapExactTokensForTokens"
+            | "swapExactTokensForETH"
+            | "swapExactTokensForTokensSupportingFeeOnTransferTokens"
+            | "swapExactTokensForETHSupportingFeeOnTransferTokens" => {
+                let (amount_in, amount_out_min, path, to, _deadline) = abi
+                    .decode_with_selector::<(U256, U256, Vec<Address>, Address, U256), _>(
+                        selector, input,
+                    )
+                    .ok()?;
+                let amount = SwapAmount::ExactIn {
+                    amount_in,
+                    amount_out_min,
+                };
+                (path, amount, to)
+            }
+            "swapTokensForExactTokens" | "swapTokensForExactETH" => {
+                let (amount_out, amount_in_max, path, to, _deadline) = abi
+                    .decode_with_selector::<(U256, U256, Vec<Address>, Address, U256), _>(
+                        selector, input,
+                    )
+                    .ok()?;
+                let amount = SwapAmount::ExactOut {
+                    amount_out,
+                    amount_in_max,
+                };
+                (path, amount, to)
+            }
+            "swapExactETHForTokens" | "swapExactETHForTokensSupportingFeeOnTransferTokens" => {
+                let (amount_out_min, path, to, _deadline) = abi
+                    .decode_with_selector::<(U256, Vec<Address>, Address, U256), _>(selector, input)
+                    .ok()?;
+                let amount = SwapAmount::ExactIn {
+                    amount_in: value,
+                    amount_out_min,
+                };
+                (path, amount, to)
+            }
+            "swapETHForExactTokens" => {
+                let (amount_out, path, to, _deadline) = abi
+                    .decode_with_selector::<(U256, Vec<Address>, Address, U256), _>(selector, input)
+                    .ok()?;
+                // the router refunds whatever isn't needed
+                let amount = SwapAmount::ExactOut {
+                    amount_out,
+                    amount_in_max: value,
+                };
+                (path, amount, to)
+            }
+            _ => return None,
+        };
+        Some(Intent::Swap(SwapIntent {
+            venue: self.protocol.get_name().to_string(),
+            path,
+            amount,
+            recipient,
+        }))
+    }
+}
+
+pub struct UniswapV3RouterDecoder;
+
+impl CalldataDecoder for UniswapV3RouterDecoder {
+    fn decode(&self, _to: Address, input: &[u8], _value: U256) -> Option<Intent> {
+        decode_v3_router_call(input)
+    }
+}
+
+/// the swap router takes native matic through `multicall` + `refundETH`, so
+/// the txn value doesn't matter here
+fn decode_v3_router_call(input: &[u8]) -> Option<Intent> {
+    type SingleParams = (Address, Address, u32, Address, U256, U256, U256, U256);
+    type PathParams = (Bytes, Address, U256, U256, U256);
+
+    let abi = &*UNISWAP_V3_ROUTER_ABI;
+    let (name, selector) = function_name(abi, input)?;
+    let (path, amount, recipient) = match name {
+        "exactInputSingle" => {
+            let (token_in, token_out, _fee, recipient, _deadline, amount_in, amount_out_min, _) =
+                abi.decode_with_selector::<SingleParams, _>(selector, input)
+                    .ok()?;
+            let amount = SwapAmount::ExactIn {
+                amount_in,
+                amount_out_min,
+            };
+            (vec![token_in, token_out], amount, recipient)
+        }
+        "exactOutputSingle" => {
+            let (token_in, token_out, _fee, recipient, _deadline, amount_out, amount_in_max, _) =
+                abi.decode_with_selector::<SingleParams, _>(selector, input)
+                    .ok()?;
+            let amount = SwapAmount::ExactOut {
+                amount_out,
+                amount_in_max,
+            };
+            (vec![token_in, token_out], amount, recipient)
+        }
+        "exactInput" => {
+            let (path, recipient, _deadline, amount_in, amount_out_min) = abi
+                .decode_with_selector::<PathParams, _>(selector, input)
+                .ok()?;
+            let amount = SwapAmount::ExactIn {
+                amount_in,
+                amount_out_min,
+            };
+            (decode_v3_path(&path), amount, recipient)
+        }
+        "exactOutput" => {
+            let (path, recipient, _deadline, amount_out, amount_in_max) = abi
+                .decode_with_selector::<PathParams, _>(selector, input)
+                .ok()?;
+            let amount = SwapAmount::ExactOut {
+                amount_out,
+                amount_in_max,
+            };
+            // exact output paths are encoded from output to input
+            let mut path = decode_v3_path(&path);
+            path.reverse();
+            (path, amount, recipient)
+        }
+        "multicall" => {
+            let calls = abi
+                .decode_with_selector::<Vec<Bytes>, _>(selector, input)
+                .ok()?;
+            let intents: Vec<Intent> = calls
+                .iter()
+                .filter_map(|call| decode_v3_router_call(call))
+                .collect();
+            return Some(Intent::Multicall(intents));
+        }
+        _ => return None,
+    };
+    Some(Intent::Swap(SwapIntent {
+        venue: UNISWAP_V3.name.to_string(),
+        path,
+        amount,
+        recipient,
+    }))
+}
+
+pub struct AavePoolDecoder;
+
+impl CalldataDecoder for AavePoolDecoder {
+    fn decode(&self, _to: Address, input: &[u8], _value: U256) -> Option<Intent> {
+        let abi = &*AAVE_POOL_ABI;
+        let (name, selector) = function_name(abi, input)?;
+        match name {
+            "liquidationCall" => {
+                let (collateral, debt, user, debt_to_cover, receive_a_token) = abi
+                    .decode_with_selector::<(Address, Address, Address, U256, bool), _>(
+                        selector, input,
+                    )
+                    .ok()?;
+                Some(Intent::Liquidation {
+                    collateral,
+                    debt,
+                    user,
+                    debt_to_cover,
+                    receive_a_token,
+                })
+            }
+            "supply" => {
+                let (asset, amount, on_behalf_of, _referral) = abi
+                    .decode_with_selector::<(Address, U256, Address, u16), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Supply {
+                    asset,
+                    amount,
+                    on_behalf_of,
+                })
+            }
+            "borrow" => {
+                let (asset, amount, _rate_mode, _referral, on_behalf_of) = abi
+                    .decode_with_selector::<(Address, U256, U256, u16, Address), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Borrow {
+                    asset,
+                    amount,
+                    on_behalf_of,
+                })
+            }
+            "repay" => {
+                let (asset, amount, _rate_mode, on_behalf_of) = abi
+                    .decode_with_selector::<(Address, U256, U256, Address), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Repay {
+                    asset,
+                    amount,
+                    on_behalf_of,
+                })
+            }
+            "withdraw" => {
+                let (asset, amount, to) = abi
+                    .decode_with_selector::<(Address, U256, Address), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Withdraw { asset, amount, to })
+            }
+            "flashLoanSimple" => {
+                let (receiver, asset, amount, _params, _referral) = abi
+                    .decode_with_selector::<(Address, Address, U256, Bytes, u16), _>(
+                        selector, input,
+                    )
+                    .ok()?;
+                Some(Intent::FlashLoan {
+                    receiver,
+                    asset,
+                    amount,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct OneInchDecoder;
+
+impl CalldataDecoder for OneInchDecoder {
+    fn decode(&self, _to: Address, input: &[u8], _value: U256) -> Option<Intent> {
+        type SwapDescription = (Address, Address, Address, Address, U256, U256, U256);
+
+        let abi = &*ONE_INCH_ABI;
+        let (name, selector) = function_name(abi, input)?;
+        if name != "swap" {
+            return None;
+        }
+        let (_executor, desc, _permit, _data) = abi
+            .decode_with_selector::<(Address, SwapDescription, Bytes, Bytes), _>(selector, input)
+            .ok()?;
+        let (src_token, dst_token, _src_receiver, dst_receiver, amount, min_return, _flags) = desc;
+        Some(Intent::Swap(SwapIntent {
+            venue: "1inch".to_string(),
+            path: vec![native(src_token), native(dst_token)],
+            amount: SwapAmount::ExactIn {
+                amount_in: amount,
+                amount_out_min: min_return,
+            },
+            recipient: dst_receiver,
+        }))
+    }
+}
+
+pub struct ZeroExDecoder;
+
+impl CalldataDecoder for ZeroExDecoder {
+    fn decode(&self, _to: Address, input: &[u8], _value: U256) -> Option<Intent> {
+        let abi = &*ZERO_EX_ABI;
+        let (name, selector) = function_name(abi, input)?;
+        let (path, amount_in, amount_out_min) = match name {
+            "transformERC20" => {
+                let (input_token, output_token, amount_in, amount_out_min, _transformations) = abi
+                    .decode_with_selector::<(Address, Address, U256, U256, Vec<(u32, Bytes)>), _>(
+                        selector, input,
+                    )
+                    .ok()?;
+                (
+                    vec![native(input_token), native(output_token)],
+                    amount_in,
+                    amount_out_min,
+                )
+            }
+            "sellToUniswap" => {
+                let (tokens, amount_in, amount_out_min, _is_sushi) = abi
+                    .decode_with_selector::<(Vec<Address>, U256, U256, bool), _>(selector, input)
+                    .ok()?;
+                (
+                    tokens.into_iter().map(native).collect(),
+                    amount_in,
+                    amount_out_min,
+                )
+            }
+            _ => return None,
+        };
+        Some(Intent::Swap(SwapIntent {
+            venue: "0x".to_string(),
+            path,
+            amount: SwapAmount::ExactIn {
+                amount_in,
+                amount_out_min,
+            },
+            // 0x always pays out to the taker
+            recipient: Address::zero(),
+        }))
+    }
+}
+
+pub struct Erc20Decoder;
+
+impl CalldataDecoder for Erc20Decoder {
+    fn decode(&self, token: Address, input: &[u8], _value: U256) -> Option<Intent> {
+        let abi = &*ERC20_ABI;
+        let (name, selector) = function_name(abi, input)?;
+        match name {
+            "transfer" => {
+                let (to, amount) = abi
+                    .decode_with_selector::<(Address, U256), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Transfer {
+                    token,
+                    from: None,
+                    to,
+                    amount,
+                })
+            }
+            "transferFrom" => {
+                let (from, to, amount) = abi
+                    .decode_with_selector::<(Address, Address, U256), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Transfer {
+                    token,
+                    from: Some(from),
+                    to,
+                    amount,
+                })
+            }
+            "approve" => {
+                let (spender, amount) = abi
+                    .decode_with_selector::<(Address, U256), _>(selector, input)
+                    .ok()?;
+                Some(Intent::Approve {
+                    token,
+                    spender,
+                    amount,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_registry() {
+        let registry = DecoderRegistry::polygon();
+        let router = UniswapV2::QUICKSWAP.get_router_address();
+        let path = vec![Address::random(), Address::random()];
+        let recipient = Address::random();
+
+        let input = UNISWAP_V2_ROUTER_ABI
+            .encode(
+                "swapExactTokensForTokens",
+                (
+                    U256::from(1000),
+                    U256::from(990),
+                    path.clone(),
+                    recipient,
+                    U256::MAX,
+                ),
+            )
+            .unwrap();
+        let intent = registry.decode(router, &input, U256::zero()).unwrap();
+        assert_eq!(
+            intent,
+            Intent::Swap(SwapIntent {
+                venue: "Quickswap".to_string(),
+                path,
+                amount: SwapAmount::ExactIn {
+                    amount_in: U256::from(1000),
+                    amount_out_min: U256::from(990),
+                },
+                recipient,
+            })
+        );
+
+        // unknown contracts still decode through the selector fallback
+        let token = Address::random();
+        let input = ERC20_ABI.encode("approve", (recipient, U256::MAX)).unwrap();
+        assert_eq!(
+            registry.decode(token, &input, U256::zero()),
+            Some(Intent::Approve {
+                token,
+                spender: recipient,
+                amount: U256::MAX,
+            })
+        );
+        assert_eq!(registry.decode(token, &[0xde, 0xad], U256::zero()), None);
+
+        // the human readable abis are only parsed on first use
+        lazy_static::initialize(&UNISWAP_V3_ROUTER_ABI);
+        lazy_static::initialize(&AAVE_POOL_ABI);
+        lazy_static::initialize(&ONE_INCH_ABI);
+        lazy_static::initialize(&ZERO_EX_ABI);
+    }
+
+    /// uniswap v3 path of `tokens` at `fee` between each
+    fn v3_path(tokens: &[Address], fee: u32) -> Bytes {
+        let mut path = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                path.extend_from_slice(&fee.to_be_bytes()[1..]);
+            }
+            path.extend_from_slice(token.as_bytes());
+        }
+        path.into()
+    }
+
+    #[test]
+    fn test_decode_uniswap_v3() {
+        let registry = DecoderRegistry::polygon();
+        let router = UNISWAP_V3.router_address;
+        let (usdc, weth, wbtc) = (Address::random(), Address::random(), Address::random());
+        let recipient = Address::random();
+
+        let single = UNISWAP_V3_ROUTER_ABI
+            .encode(
+                "exactInputSingle",
+                ((
+                    usdc,
+                    weth,
+                    500u32,
+                    recipient,
+                    U256::MAX,
+                    U256::from(1000),
+                    U256::from(990),
+                    U256::zero(),
+                ),),
+            )
+            .unwrap();
+        let single_intent = Intent::Swap(SwapIntent {
+            venue: UNISWAP_V3.name.to_string(),
+            path: vec![usdc, weth],
+            amount: SwapAmount::ExactIn {
+                amount_in: U256::from(1000),
+                amount_out_min: U256::from(990),
+            },
+            recipient,
+        });
+        assert_eq!(
+            registry.decode(router, &single, U256::zero()),
+            Some(single_intent.clone())
+        );
+
+        // encoded from output to input, decoded from input to output
+        let exact_output = UNISWAP_V3_ROUTER_ABI
+            .encode(
+                "exactOutput",
+                ((
+                    v3_path(&[wbtc, weth, usdc], 3000),
+                    recipient,
+                    U256::MAX,
+                    U256::from(5),
+                    U256::from(2000),
+                ),),
+            )
+            .unwrap();
+        let exact_output_intent = Intent::Swap(SwapIntent {
+            venue: UNISWAP_V3.name.to_string(),
+            path: vec![usdc, weth, wbtc],
+            amount: SwapAmount::ExactOut {
+                amount_out: U256::from(5),
+                amount_in_max: U256::from(2000),
+            },
+            recipient,
+        });
+        assert_eq!(
+            registry.decode(router, &exact_output, U256::zero()),
+            Some(exact_output_intent.clone())
+        );
+
+        // calls it can't decode are left out of a multicall
+        let multicall = UNISWAP_V3_ROUTER_ABI
+            .encode(
+                "multicall",
+                vec![
+                    single,
+                    Bytes::from(vec![0x12, 0x21, 0x0e, 0x8a]),
+                    exact_output,
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            registry.decode(router, &multicall, U256::zero()),
+            Some(Intent::Multicall(vec![single_intent, exact_output_intent]))
+        );
+    }
+
+    #[test]
+    fn test_decode_aggregators() {
+        let registry = DecoderRegistry::polygon();
+        let (usdc, weth) = (Address::random(), Address::random());
+        let receiver = Address::random();
+
+        let swap = ONE_INCH_ABI
+            .encode(
+                "swap",
+                (
+                    Address::random(),
+                    (
+                        *NATIVE_TOKEN,
+                        usdc,
+                        Address::random(),
+                        receiver,
+                        U256::from(10),
+                        U256::from(9),
+                        U256::zero(),
+                    ),
+                    Bytes::default(),
+                    Bytes::default(),
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            registry.decode(*ONE_INCH_V5_ROUTER, &swap, U256::from(10)),
+            Some(Intent::Swap(SwapIntent {
+                venue: "1inch".to_string(),
+                path: vec![ERC20Token::WMATIC.get_address(), usdc],
+                amount: SwapAmount::ExactIn {
+                    amount_in: U256::from(10),
+                    amount_out_min: U256::from(9),
+                },
+                recipient: receiver,
+            }))
+        );
+
+        let transform = ZERO_EX_ABI
+            .encode(
+                "transformERC20",
+                (
+                    usdc,
+                    *NATIVE_TOKEN,
+                    U256::from(1000),
+                    U256::from(990),
+                    vec![(7u32, Bytes::from(vec![1u8]))],
+                ),
+            )
+            .unwrap();
+        let sell = ZERO_EX_ABI
+            .encode(
+                "sellToUniswap",
+                (vec![usdc, weth], U256::from(1000), U256::from(990), true),
+            )
+            .unwrap();
+        let zero_ex = |path| {
+            Some(Intent::Swap(SwapIntent {
+                venue: "0x".to_string(),
+                path,
+                amount: SwapAmount::ExactIn {
+                    amount_in: U256::from(1000),
+                    amount_out_min: U256::from(990),
+                },
+                recipient: Address::zero(),
+            }))
+        };
+        assert_eq!(
+            registry.decode(*ZERO_EX_PROXY, &transform, U256::zero()),
+            zero_ex(vec![usdc, ERC20Token::WMATIC.get_address()])
+        );
+        assert_eq!(
+            registry.decode(*ZERO_EX_PROXY, &sell, U256::zero()),
+            zero_ex(vec![usdc, weth])
+        );
+        // only through the proxy
+        assert_eq!(
+            registry.decode(Address::random(), &sell, U256::zero()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_decode_aave() {
+        let registry = DecoderRegistry::polygon();
+        let (collateral, debt, user) = (Address::random(), Address::random(), Address::random());
+
+        let liquidation = AAVE_POOL_ABI
+            .encode(
+                "liquidationCall",
+                (collateral, debt, user, U256::from(42), true),
+            )
+            .unwrap();
+        assert_eq!(
+            registry.decode(*AAVE_V3_POOL, &liquidation, U256::zero()),
+            Some(Intent::Liquidation {
+                collateral,
+                debt,
+                user,
+                debt_to_cover: U256::from(42),
+                receive_a_token: true,
+            })
+        );
+
+        let supply = AAVE_POOL_ABI
+            .encode("supply", (debt, U256::from(7), user, 0u16))
+            .unwrap();
+        assert_eq!(
+            registry.decode(*AAVE_V3_POOL, &supply, U256::zero()),
+            Some(Intent::Supply {
+                asset: debt,
+                amount: U256::from(7),
+                on_behalf_of: user,
+            })
+        );
+
+        let borrow = AAVE_POOL_ABI
+            .encode("borrow", (debt, U256::from(7), U256::from(2), 0u16, user))
+            .unwrap();
+        assert_eq!(
+            registry.decode(*AAVE_V3_POOL, &borrow, U256::zero()),
+            Some(Intent::Borrow {
+                asset: debt,
+                amount: U256::from(7),
+                on_behalf_of: user,
+            })
+        );
+
+        let flash_loan = AAVE_POOL_ABI
+            .encode(
+                "flashLoanSimple",
+                (user, debt, U256::from(1000), Bytes::default(), 0u16),
+            )
+            .unwrap();
+        assert_eq!(
+            registry.decode(*AAVE_V3_POOL, &flash_loan, U256::zero()),
+            Some(Intent::FlashLoan {
+                receiver: user,
+                asset: debt,
+                amount: U256::from(1000),
+            })
+        );
+        // truncated calldata doesn't decode
+        assert_eq!(
+            registry.decode(*AAVE_V3_POOL, &liquidation[..36], U256::zero()),
+            None
+        );
+    }
+}
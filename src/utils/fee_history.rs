@@ -0,0 +1,162 @@
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, FeeHistory as EthersFeeHistory, U256},
+};
+
+/// fees paid in one block of an `eth_feeHistory` range
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockFees {
+    pub number: u64,
+    pub base_fee: U256,
+    pub gas_used_ratio: f64,
+    /// effective priority fee at each requested percentile, all zero for
+    /// empty blocks
+    pub rewards: Vec<U256>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaseFeeTrend {
+    Rising,
+    Falling,
+    Flat,
+}
+
+/// `eth_feeHistory` split up per block
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeHistory {
+    pub percentiles: Vec<f64>,
+    /// oldest block first
+    pub blocks: Vec<BlockFees>,
+    /// base fee of the block after the newest one
+    pub next_base_fee: U256,
+}
+
+/// fee history of the last `block_count` blocks, with priority fees sampled
+/// at `percentiles` (0-100, ascending)
+pub async fn fee_history<M: Middleware>(
+    provider: &M,
+    block_count: u64,
+    percentiles: &[f64],
+) -> Result<FeeHistory, M::Error> {
+    let history = provider
+        .fee_history(block_count, BlockNumber::Latest, percentiles)
+        .await?;
+    Ok(FeeHistory::new(history, percentiles))
+}
+
+impl FeeHistory {
+    pub fn new(history: EthersFeeHistory, percentiles: &[f64]) -> Self {
+        let oldest_block = history.oldest_block.as_u64();
+        // one base fee more than blocks, the last is for the pending block
+        let next_base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+        let blocks = history
+            .gas_used_ratio
+            .iter()
+            .enumerate()
+            .map(|(i, gas_used_ratio)| BlockFees {
+                number: oldest_block + i as u64,
+                base_fee: history.base_fee_per_gas[i],
+                gas_used_ratio: *gas_used_ratio,
+                rewards: history.reward.get(i).cloned().unwrap_or_default(),
+            })
+            .collect();
+        Self {
+            percentiles: percentiles.to_vec(),
+            blocks,
+            next_base_fee,
+        }
+    }
+
+    /// Median priority fee paid at `percentile` over non-empty blocks. `None`
+    /// if the percentile wasn't requested or every block was empty.
+    pub fn suggested_priority_fee(&self, percentile: f64) -> Option<U256> {
+        let idx = self.percentiles.iter().position(|p| *p == percentile)?;
+        let mut rewards: Vec<U256> = self
+            .blocks
+            .iter()
+            .filter(|block| block.gas_used_ratio > 0.0)
+            .filter_map(|block| block.rewards.get(idx).copied())
+            .collect();
+        if rewards.is_empty() {
+            return None;
+        }
+        rewards.sort();
+        Some(rewards[rewards.len() / 2])
+    }
+
+    /// max fee that stays valid if the base fee keeps rising for `blocks`
+    /// blocks, each at most 12.5% over its parent
+    pub fn suggested_max_fee(&self, priority_fee: U256, blocks: u32) -> U256 {
+        let mut base_fee = self.next_base_fee;
+        for _ in 0..blocks {
+            base_fee = base_fee * 9 / 8;
+        }
+        base_fee + priority_fee
+    }
+
+    /// change from the oldest block's base fee to the next one, in bps
+    pub fn base_fee_change_bps(&self) -> i64 {
+        let oldest = match self.blocks.first() {
+            Some(block) if !block.base_fee.is_zero() => block.base_fee,
+            _ => return 0,
+        };
+        let next = self.next_base_fee;
+        if next >= oldest {
+            ((next - oldest) * 10000u64 / oldest).as_u64() as i64
+        } else {
+            -(((oldest - next) * 10000u64 / oldest).as_u64() as i64)
+        }
+    }
+
+    /// average share of the gas limit used over the range
+    pub fn mean_gas_used_ratio(&self) -> f64 {
+        if self.blocks.is_empty() {
+            return 0.0;
+        }
+        self.blocks.iter().map(|b| b.gas_used_ratio).sum::<f64>() / self.blocks.len() as f64
+    }
+
+    /// rising or falling if the base fee moved more than `threshold_bps`
+    pub fn base_fee_trend(&self, threshold_bps: i64) -> BaseFeeTrend {
+        let change = self.base_fee_change_bps();
+        if change > threshold_bps {
+            BaseFeeTrend::Rising
+        } else if change < -threshold_bps {
+            BaseFeeTrend::Falling
+        } else {
+            BaseFeeTrend::Flat
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_history_analytics() {
+        let gwei = U256::exp10(9);
+        let history = EthersFeeHistory {
+            base_fee_per_gas: vec![gwei * 100, gwei * 110, gwei * 120, gwei * 130],
+            gas_used_ratio: vec![0.9, 0.0, 0.8],
+            oldest_block: U256::from(1000),
+            reward: vec![
+                vec![gwei * 30, gwei * 40],
+                vec![U256::zero(), U256::zero()],
+                vec![gwei * 31, gwei * 50],
+            ],
+        };
+        let history = FeeHistory::new(history, &[50.0, 90.0]);
+        assert_eq!(history.blocks.len(), 3);
+        assert_eq!(history.blocks[2].number, 1002);
+        assert_eq!(history.next_base_fee, gwei * 130);
+
+        // the empty block doesn't drag the suggestion down
+        assert_eq!(history.suggested_priority_fee(90.0), Some(gwei * 50));
+        assert_eq!(history.suggested_priority_fee(10.0), None);
+
+        assert_eq!(history.base_fee_change_bps(), 3000);
+        assert_eq!(history.base_fee_trend(500), BaseFeeTrend::Rising);
+        assert_eq!(history.base_fee_trend(5000), BaseFeeTrend::Flat);
+    }
+}
@@ -0,0 +1,132 @@
+//! Overflow-checked fixed-point math on U256, shared by the V2, V3 and
+//! stable pool math. Intermediate products are taken in 512 bits, so
+//! `a * b / c` only fails when the result itself doesn't fit.
+
+use ethers::{abi::ethereum_types::U512, types::U256};
+
+/// 2^96, the unit of Q64.96 numbers
+pub const Q96: U256 = U256([0, 1 << 32, 0, 0]);
+
+/// `floor(a * b / denominator)`, `None` on division by zero or a result
+/// over 256 bits
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    U256::try_from(a.full_mul(b) / U512::from(denominator)).ok()
+}
+
+/// `ceil(a * b / denominator)`, for amounts owed to a pool
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let product = a.full_mul(b);
+    let denominator = U512::from(denominator);
+    let mut result = product / denominator;
+    if !(product % denominator).is_zero() {
+        result += U512::one();
+    }
+    U256::try_from(result).ok()
+}
+
+/// `floor(sqrt(x))`
+pub fn sqrt(x: U256) -> U256 {
+    x.integer_sqrt()
+}
+
+/// 10^decimals, `None` past 10^77
+pub fn pow10(decimals: u8) -> Option<U256> {
+    U256::from(10).checked_pow(U256::from(decimals))
+}
+
+/// `amount` whole tokens in base units of a token with `decimals`, `None`
+/// on overflow
+pub fn whole_units(amount: u64, decimals: u8) -> Option<U256> {
+    U256::from(amount).checked_mul(pow10(decimals)?)
+}
+
+/// `amount` in base units of `from` decimals converted to `to` decimals,
+/// rounding down. `None` on overflow.
+pub fn rescale(amount: U256, from: u8, to: u8) -> Option<U256> {
+    if to >= from {
+        amount.checked_mul(pow10(to - from)?)
+    } else {
+        Some(pow10(from - to).map_or(U256::zero(), |scale| amount / scale))
+    }
+}
+
+/// price of token0 in token1 as Q64.96, from a V3 `sqrtPriceX96`
+pub fn price_x96(sqrt_price_x96: U256) -> Option<U256> {
+    mul_div(sqrt_price_x96, sqrt_price_x96, Q96)
+}
+
+/// Q64.96 square root of the price of token0 in token1 implied by
+/// reserves, what a V3 pool at the same price has as `sqrtPriceX96`
+pub fn sqrt_price_x96(reserve0: U256, reserve1: U256) -> Option<U256> {
+    if reserve0.is_zero() {
+        return None;
+    }
+    let ratio_x192 = (U512::from(reserve1) << 192) / U512::from(reserve0);
+    U256::try_from(ratio_x192.integer_sqrt()).ok()
+}
+
+/// nearest f64, for ratios and statistics, never for amounts sent on chain
+pub fn to_f64(x: U256) -> f64 {
+    x.0.iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_div() {
+        // the product overflows 256 bits, the result doesn't
+        let big = U256::MAX / 2;
+        assert_eq!(mul_div(big, U256::from(6), U256::from(3)), Some(big * 2));
+        assert_eq!(mul_div(U256::MAX, U256::from(2), U256::one()), None);
+        assert_eq!(mul_div(U256::one(), U256::one(), U256::zero()), None);
+
+        assert_eq!(
+            mul_div(U256::from(10), U256::from(10), U256::from(3)),
+            Some(U256::from(33))
+        );
+        assert_eq!(
+            mul_div_rounding_up(U256::from(10), U256::from(10), U256::from(3)),
+            Some(U256::from(34))
+        );
+        assert_eq!(
+            mul_div_rounding_up(U256::from(10), U256::from(9), U256::from(3)),
+            Some(U256::from(30))
+        );
+    }
+
+    #[test]
+    fn test_decimals() {
+        assert_eq!(whole_units(1000, 6), Some(U256::from(1_000_000_000u64)));
+        assert_eq!(whole_units(1000, 76), None);
+        assert_eq!(whole_units(1, 78), None);
+        assert_eq!(
+            rescale(U256::from(1_500_000), 6, 18),
+            Some(U256::from(1_500_000_000_000_000_000u128))
+        );
+        assert_eq!(rescale(U256::from(1_999_999), 6, 0), Some(U256::one()));
+        assert_eq!(rescale(U256::MAX, 0, 18), None);
+        assert_eq!(pow10(78), None);
+    }
+
+    #[test]
+    fn test_q96() {
+        assert_eq!(Q96, U256::one() << 96);
+        // price 4 has a square root of 2
+        let sqrt_price = sqrt_price_x96(U256::from(1_000), U256::from(4_000)).unwrap();
+        assert_eq!(sqrt_price, Q96 * 2);
+        assert_eq!(price_x96(sqrt_price), Some(Q96 * 4));
+        assert_eq!(sqrt(U256::from(17)), U256::from(4));
+        assert_eq!(to_f64(Q96), 2f64.powi(96));
+        assert_eq!(to_f64(U256::from(12345)), 12345.0);
+    }
+}
@@ -0,0 +1,491 @@
+//! Executes transactions with revm against a local cache of chain state, so
+//! simulating an arb, a liquidation or a victim's swap doesn't need a node
+//! round trip once the state it touches is cached. Missing accounts and
+//! slots are fetched in batches with `eth_getProof`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockId, Bytes, Log, NameOrAddress, H256,
+        U256,
+    },
+};
+use futures_util::future::join_all;
+use revm::{
+    db::{CacheDB, DatabaseRef},
+    AccountInfo, BlockEnv, Bytecode, CfgEnv, Env, Return, SpecId, TransactOut, TransactTo, TxEnv,
+    EVM, KECCAK_EMPTY,
+};
+
+pub const CHAIN_ID: u64 = 137;
+/// bor's target block time
+pub const BLOCK_TIME_SECS: u64 = 2;
+/// gas limit of txns that don't set one
+pub const DEFAULT_GAS_LIMIT: u64 = 30_000_000;
+/// fetch and re-run this many times before giving up on a simulation
+pub const MAX_FETCH_ROUNDS: usize = 8;
+
+/// state an execution read that isn't cached yet
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MissingState {
+    pub accounts: HashSet<Address>,
+    pub slots: HashMap<Address, HashSet<U256>>,
+    pub block_hashes: HashSet<u64>,
+}
+
+impl MissingState {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.slots.is_empty() && self.block_hashes.is_empty()
+    }
+}
+
+/// outcome of one txn executed locally
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalExecution {
+    pub exit_reason: Return,
+    pub gas_used: u64,
+    pub output: Bytes,
+    /// only address, topics and data are set
+    pub logs: Vec<Log>,
+}
+
+impl LocalExecution {
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.exit_reason,
+            Return::Continue | Return::Stop | Return::Return | Return::SelfDestruct
+        )
+    }
+}
+
+/// Chain state as of the end of one block. Reads of anything not cached
+/// come back empty and are recorded, an execution is only trusted if it
+/// recorded nothing.
+#[derive(Debug, Default)]
+pub struct StateCache {
+    accounts: HashMap<Address, AccountInfo>,
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    block_hashes: HashMap<u64, H256>,
+    missing: Mutex<MissingState>,
+}
+
+impl StateCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `code` is the account's runtime bytecode, empty for an EOA
+    pub fn insert_account(&mut self, address: Address, balance: U256, nonce: u64, code: Bytes) {
+        let info = if code.is_empty() {
+            AccountInfo::from_balance(balance)
+        } else {
+            AccountInfo::new(balance, nonce, Bytecode::new_raw(code.0))
+        };
+        self.accounts.insert(address, AccountInfo { nonce, ..info });
+    }
+
+    pub fn insert_storage(&mut self, address: Address, slot: U256, value: U256) {
+        self.storage.entry(address).or_default().insert(slot, value);
+    }
+
+    pub fn insert_block_hash(&mut self, number: u64, hash: H256) {
+        self.block_hashes.insert(number, hash);
+    }
+
+    pub fn contains_account(&self, address: Address) -> bool {
+        self.accounts.contains_key(&address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.accounts.clear();
+        self.storage.clear();
+        self.block_hashes.clear();
+    }
+
+    /// Runs `txs` in order on top of the cache, each seeing the changes of
+    /// the ones before it. Nothing is written back to the cache.
+    pub fn execute(
+        &self,
+        env: &Env,
+        txs: &[TypedTransaction],
+    ) -> Result<Vec<LocalExecution>, Box<MissingState>> {
+        std::mem::take(&mut *self.missing.lock().unwrap());
+        let mut evm = EVM::new();
+        evm.env = env.clone();
+        evm.database(CacheDB::new(self));
+        let executions = txs
+            .iter()
+            .map(|tx| {
+                evm.env.tx = tx_env(tx);
+                let result = evm.transact_commit();
+                LocalExecution {
+                    exit_reason: result.exit_reason,
+                    gas_used: result.gas_used,
+                    output: match result.out {
+                        TransactOut::Call(output) => output.into(),
+                        TransactOut::Create(output, _) => output.into(),
+                        TransactOut::None => Bytes::default(),
+                    },
+                    logs: result
+                        .logs
+                        .into_iter()
+                        .map(|log| Log {
+                            address: log.address,
+                            topics: log.topics,
+                            data: log.data.into(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        let missing = std::mem::take(&mut *self.missing.lock().unwrap());
+        if !missing.is_empty() {
+            return Err(Box::new(missing));
+        }
+        Ok(executions)
+    }
+
+    fn record_missing(&self, record: impl FnOnce(&mut MissingState)) {
+        record(&mut self.missing.lock().unwrap());
+    }
+}
+
+impl DatabaseRef for StateCache {
+    type Error = Infallible;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match self.accounts.get(&address) {
+            Some(info) => Ok(Some(info.clone())),
+            None => {
+                self.record_missing(|missing| {
+                    missing.accounts.insert(address);
+                });
+                Ok(Some(AccountInfo::default()))
+            }
+        }
+    }
+
+    fn code_by_hash(&self, _code_hash: H256) -> Result<Bytecode, Self::Error> {
+        // accounts are cached with their code
+        Ok(Bytecode::new())
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        match self.storage.get(&address).and_then(|s| s.get(&index)) {
+            Some(value) => Ok(*value),
+            None => {
+                self.record_missing(|missing| {
+                    missing.slots.entry(address).or_default().insert(index);
+                });
+                Ok(U256::zero())
+            }
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> Result<H256, Self::Error> {
+        let number = number.as_u64();
+        match self.block_hashes.get(&number) {
+            Some(hash) => Ok(*hash),
+            None => {
+                self.record_missing(|missing| {
+                    missing.block_hashes.insert(number);
+                });
+                Ok(H256::zero())
+            }
+        }
+    }
+}
+
+/// revm's view of `tx`, nonces aren't checked so unsigned candidates run too
+pub fn tx_env(tx: &TypedTransaction) -> TxEnv {
+    let gas_priority_fee = match tx {
+        TypedTransaction::Eip1559(tx) => tx.max_priority_fee_per_gas,
+        _ => None,
+    };
+    TxEnv {
+        caller: tx.from().copied().unwrap_or_default(),
+        gas_limit: tx.gas().map_or(DEFAULT_GAS_LIMIT, |gas| gas.as_u64()),
+        gas_price: tx.gas_price().unwrap_or_default(),
+        gas_priority_fee,
+        transact_to: match tx.to() {
+            Some(NameOrAddress::Address(to)) => TransactTo::Call(*to),
+            Some(NameOrAddress::Name(_)) | None => TransactTo::create(),
+        },
+        value: tx.value().copied().unwrap_or_default(),
+        data: tx.data().map(|data| data.0.clone()).unwrap_or_default(),
+        chain_id: None,
+        nonce: None,
+        access_list: tx
+            .access_list()
+            .map(|list| {
+                list.0
+                    .iter()
+                    .map(|item| {
+                        let keys = item
+                            .storage_keys
+                            .iter()
+                            .map(|key| U256::from_big_endian(key.as_bytes()))
+                            .collect();
+                        (item.address, keys)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Simulates on top of block `block_number` with a `StateCache` that's
+/// filled from the node as executions need it.
+pub struct LocalSimulator<M> {
+    provider: Arc<M>,
+    block_number: u64,
+    env: Env,
+    cache: StateCache,
+}
+
+impl<M: Middleware> LocalSimulator<M> {
+    pub fn new(provider: Arc<M>, block_number: u64, block: BlockEnv) -> Self {
+        Self {
+            provider,
+            block_number,
+            env: Env {
+                cfg: CfgEnv {
+                    chain_id: CHAIN_ID.into(),
+                    spec_id: SpecId::LONDON,
+                    ..Default::default()
+                },
+                block,
+                tx: TxEnv::default(),
+            },
+            cache: StateCache::new(),
+        }
+    }
+
+    /// simulates the block after `block_number`, its header guessed from
+    /// that block's
+    pub async fn at_block(provider: Arc<M>, block_number: u64) -> Result<Self, M::Error> {
+        let block = next_block_env(&*provider, block_number).await?;
+        Ok(Self::new(provider, block_number, block))
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn cache(&self) -> &StateCache {
+        &self.cache
+    }
+
+    pub fn cache_mut(&mut self) -> &mut StateCache {
+        &mut self.cache
+    }
+
+    /// moves on to the state after `block_number`, the cache is dropped
+    pub fn advance(&mut self, block_number: u64, block: BlockEnv) {
+        self.block_number = block_number;
+        self.env.block = block;
+        self.cache.clear();
+    }
+
+    /// `txs` in order, fetching whatever they read that isn't cached and
+    /// re-running until nothing is missing
+    pub async fn simulate_bundle(
+        &mut self,
+        txs: &[TypedTransaction],
+    ) -> Result<Option<Vec<LocalExecution>>, M::Error> {
+        for _ in 0..MAX_FETCH_ROUNDS {
+            match self.cache.execute(&self.env, txs) {
+                Ok(executions) => return Ok(Some(executions)),
+                Err(missing) => self.fetch(*missing).await?,
+            }
+        }
+        Ok(None)
+    }
+
+    /// `None` if the state `tx` reads didn't settle within `MAX_FETCH_ROUNDS`
+    pub async fn simulate(
+        &mut self,
+        tx: &TypedTransaction,
+    ) -> Result<Option<LocalExecution>, M::Error> {
+        let executions = self.simulate_bundle(std::slice::from_ref(tx)).await?;
+        Ok(executions.and_then(|mut executions| executions.pop()))
+    }
+
+    /// caches `accounts` and `slots` ahead of the executions that read them
+    pub async fn prefetch(
+        &mut self,
+        accounts: &[Address],
+        slots: &[(Address, U256)],
+    ) -> Result<(), M::Error> {
+        let mut missing = MissingState::default();
+        missing.accounts.extend(accounts);
+        for (address, slot) in slots {
+            missing.slots.entry(*address).or_default().insert(*slot);
+        }
+        self.fetch(missing).await
+    }
+
+    /// one `eth_getProof` per account with all its missing slots, plus
+    /// `eth_getCode` for contracts not cached yet
+    async fn fetch(&mut self, missing: MissingState) -> Result<(), M::Error> {
+        let block = Some(BlockId::Number(self.block_number.into()));
+        let mut slots = missing.slots;
+        for address in missing.accounts {
+            slots.entry(address).or_default();
+        }
+
+        let provider = &self.provider;
+        let cache = &self.cache;
+        let proofs = join_all(slots.into_iter().map(|(address, slots)| async move {
+            let locations = slots
+                .into_iter()
+                .map(|slot| {
+                    let mut key = H256::zero();
+                    slot.to_big_endian(key.as_bytes_mut());
+                    key
+                })
+                .collect();
+            let proof = provider.get_proof(address, locations, block).await?;
+            let code = if cache.contains_account(address)
+                || proof.code_hash == KECCAK_EMPTY
+                || proof.code_hash.is_zero()
+            {
+                None
+            } else {
+                Some(provider.get_code(address, block).await?)
+            };
+            Ok((proof, code))
+        }))
+        .await;
+        for result in proofs {
+            let (proof, code) = result?;
+            if let Some(code) = code {
+                self.cache
+                    .insert_account(proof.address, proof.balance, proof.nonce.as_u64(), code);
+            } else if !self.cache.contains_account(proof.address) {
+                self.cache.insert_account(
+                    proof.address,
+                    proof.balance,
+                    proof.nonce.as_u64(),
+                    Bytes::default(),
+                );
+            }
+            for slot in proof.storage_proof {
+                self.cache.insert_storage(
+                    proof.address,
+                    U256::from_big_endian(slot.key.as_bytes()),
+                    slot.value,
+                );
+            }
+        }
+
+        let hashes = join_all(
+            missing
+                .block_hashes
+                .into_iter()
+                .map(|number| async move { (number, provider.get_block(number).await) }),
+        )
+        .await;
+        for (number, block) in hashes {
+            let hash = block?.and_then(|block| block.hash).unwrap_or_default();
+            self.cache.insert_block_hash(number, hash);
+        }
+        Ok(())
+    }
+}
+
+/// header of the block after `parent_number`, one block time later at the
+/// parent's base fee
+pub async fn next_block_env<M: Middleware>(
+    provider: &M,
+    parent_number: u64,
+) -> Result<BlockEnv, M::Error> {
+    let parent = provider.get_block(parent_number).await?.unwrap_or_default();
+    Ok(BlockEnv {
+        number: (parent_number + 1).into(),
+        coinbase: parent.author.unwrap_or_default(),
+        timestamp: parent.timestamp + BLOCK_TIME_SECS,
+        difficulty: parent.difficulty,
+        basefee: parent.base_fee_per_gas.unwrap_or_default(),
+        gas_limit: parent.gas_limit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::TransactionRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_execute_reports_missing_state() {
+        // returns slot 0
+        let code = Bytes::from(vec![
+            0x60, 0x00, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+        ]);
+        let contract = Address::random();
+        let caller = Address::random();
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(caller)
+            .to(contract)
+            .gas(100_000)
+            .into();
+        let env = Env::default();
+
+        let mut cache = StateCache::new();
+        cache.insert_account(contract, U256::zero(), 1, code);
+        let missing = cache.execute(&env, std::slice::from_ref(&tx)).unwrap_err();
+        assert!(missing.accounts.contains(&caller));
+        assert!(missing.slots[&contract].contains(&U256::zero()));
+
+        cache.insert_account(caller, U256::exp10(18), 0, Bytes::default());
+        // gets paid the fee
+        cache.insert_account(env.block.coinbase, U256::zero(), 0, Bytes::default());
+        cache.insert_storage(contract, U256::zero(), 42.into());
+        let executions = cache.execute(&env, &[tx]).unwrap();
+        assert!(executions[0].is_success());
+        assert_eq!(U256::from_big_endian(&executions[0].output), 42.into());
+    }
+
+    #[test]
+    fn test_execute_stack_ops() {
+        // returns 2 + 2, through a dup and a swap
+        let code = Bytes::from(vec![
+            0x60, 0x02, 0x80, 0x01, 0x60, 0x00, 0x90, 0x81, 0x52, 0x60, 0x20, 0x90, 0xf3,
+        ]);
+        let contract = Address::random();
+        let caller = Address::random();
+        let env = Env::default();
+        let mut cache = StateCache::new();
+        cache.insert_account(contract, U256::zero(), 1, code);
+        cache.insert_account(caller, U256::exp10(18), 0, Bytes::default());
+        cache.insert_account(env.block.coinbase, U256::zero(), 0, Bytes::default());
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(caller)
+            .to(contract)
+            .gas(100_000)
+            .into();
+        let executions = cache.execute(&env, &[tx]).unwrap();
+        assert!(executions[0].is_success());
+        assert_eq!(U256::from_big_endian(&executions[0].output), 4.into());
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use ethers::{
+    prelude::ContractError,
+    providers::Middleware,
+    types::{Address, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::uniswapV2::IUniswapV2Pair;
+
+/// burnt to the zero address on the first mint of every pair
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+
+/// what V2 LP math needs of a pair
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PoolSupply {
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub total_supply: U256,
+    /// `reserve0 * reserve1` at the last mint or burn, zero if the protocol
+    /// fee is off
+    pub k_last: U256,
+}
+
+impl PoolSupply {
+    pub async fn fetch<M: Middleware>(
+        provider: Arc<M>,
+        pair_address: Address,
+    ) -> Result<Self, ContractError<M>> {
+        let pair = IUniswapV2Pair::new(pair_address, provider);
+        let (reserve0, reserve1, _) = pair.get_reserves().call().await?;
+        Ok(Self {
+            reserve0: reserve0.into(),
+            reserve1: reserve1.into(),
+            total_supply: pair.total_supply().call().await?,
+            k_last: pair.k_last().call().await?,
+        })
+    }
+
+    /// LP minted to the fee recipient ahead of any mint or burn, 1/6 of the
+    /// growth in sqrt(k) since `k_last`
+    pub fn protocol_fee_liquidity(&self) -> U256 {
+        if self.k_last.is_zero() {
+            return U256::zero();
+        }
+        let root_k = (self.reserve0 * self.reserve1).integer_sqrt();
+        let root_k_last = self.k_last.integer_sqrt();
+        if root_k <= root_k_last {
+            return U256::zero();
+        }
+        self.total_supply * (root_k - root_k_last) / (root_k * 5u64 + root_k_last)
+    }
+
+    /// LP minted for depositing `amount0` and `amount1`, `None` if that's
+    /// nothing. Whatever goes over the reserve ratio is donated to the pool.
+    pub fn mint_liquidity(&self, amount0: U256, amount1: U256) -> Option<U256> {
+        let total_supply = self.total_supply + self.protocol_fee_liquidity();
+        let liquidity = if total_supply.is_zero() {
+            (amount0 * amount1)
+                .integer_sqrt()
+                .checked_sub(MINIMUM_LIQUIDITY.into())?
+        } else if self.reserve0.is_zero() || self.reserve1.is_zero() {
+            return None;
+        } else {
+            U256::min(
+                amount0 * total_supply / self.reserve0,
+                amount1 * total_supply / self.reserve1,
+            )
+        };
+        if liquidity.is_zero() {
+            return None;
+        }
+        Some(liquidity)
+    }
+
+    /// tokens paid out for burning `liquidity`
+    pub fn burn_amounts(&self, liquidity: U256) -> (U256, U256) {
+        let total_supply = self.total_supply + self.protocol_fee_liquidity();
+        if total_supply.is_zero() {
+            return (U256::zero(), U256::zero());
+        }
+        (
+            liquidity * self.reserve0 / total_supply,
+            liquidity * self.reserve1 / total_supply,
+        )
+    }
+
+    /// pool after a mint of `amount0` and `amount1`
+    pub fn after_mint(&self, amount0: U256, amount1: U256) -> Option<Self> {
+        let liquidity = self.mint_liquidity(amount0, amount1)?;
+        let mut total_supply = self.total_supply + self.protocol_fee_liquidity() + liquidity;
+        if self.total_supply.is_zero() {
+            total_supply += U256::from(MINIMUM_LIQUIDITY);
+        }
+        let reserve0 = self.reserve0 + amount0;
+        let reserve1 = self.reserve1 + amount1;
+        Some(Self {
+            reserve0,
+            reserve1,
+            total_supply,
+            k_last: self.next_k_last(reserve0, reserve1),
+        })
+    }
+
+    /// pool after `liquidity` is burnt
+    pub fn after_burn(&self, liquidity: U256) -> Self {
+        let (amount0, amount1) = self.burn_amounts(liquidity);
+        let reserve0 = self.reserve0 - amount0;
+        let reserve1 = self.reserve1 - amount1;
+        Self {
+            reserve0,
+            reserve1,
+            total_supply: self.total_supply + self.protocol_fee_liquidity() - liquidity,
+            k_last: self.next_k_last(reserve0, reserve1),
+        }
+    }
+
+    fn next_k_last(&self, reserve0: U256, reserve1: U256) -> U256 {
+        if self.k_last.is_zero() {
+            return U256::zero();
+        }
+        reserve0 * reserve1
+    }
+
+    /// Amounts the router's `addLiquidity` deposits for the desired amounts,
+    /// the side over the reserve ratio scaled down.
+    pub fn optimal_deposit(&self, amount0_desired: U256, amount1_desired: U256) -> (U256, U256) {
+        if self.reserve0.is_zero() && self.reserve1.is_zero() {
+            return (amount0_desired, amount1_desired);
+        }
+        let amount1_optimal = amount0_desired * self.reserve1 / self.reserve0;
+        if amount1_optimal <= amount1_desired {
+            return (amount0_desired, amount1_optimal);
+        }
+        (
+            amount1_desired * self.reserve0 / self.reserve1,
+            amount1_desired,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mint_burn_roundtrip() {
+        let empty = PoolSupply::default();
+        assert_eq!(
+            empty.mint_liquidity(4_000.into(), 1_000.into()),
+            Some(1_000.into())
+        );
+        let pool = empty.after_mint(4_000.into(), 1_000.into()).unwrap();
+        assert_eq!(pool.total_supply, 2_000.into());
+
+        // depositing a tenth of the pool at the pool ratio mints a tenth
+        assert_eq!(
+            pool.optimal_deposit(400.into(), 500.into()),
+            (400.into(), 100.into())
+        );
+        let liquidity = pool.mint_liquidity(400.into(), 100.into()).unwrap();
+        assert_eq!(liquidity, 200.into());
+        let pool = pool.after_mint(400.into(), 100.into()).unwrap();
+        assert_eq!(pool.burn_amounts(liquidity), (400.into(), 100.into()));
+        assert_eq!(
+            pool.after_burn(liquidity),
+            PoolSupply {
+                reserve0: 4_000.into(),
+                reserve1: 1_000.into(),
+                total_supply: 2_000.into(),
+                k_last: U256::zero(),
+            }
+        );
+
+        // with the fee on, sqrt(k) growing from 1800 to 2000 mints the fee
+        // recipient 2000 * 200 / (2000 * 5 + 1800)
+        let with_fee = PoolSupply {
+            reserve0: 4_000.into(),
+            reserve1: 1_000.into(),
+            total_supply: 2_000.into(),
+            k_last: (1800u64 * 1800).into(),
+        };
+        assert_eq!(with_fee.protocol_fee_liquidity(), 33.into());
+    }
+}
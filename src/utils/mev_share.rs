@@ -0,0 +1,321 @@
+//! Client for MEV-share style order flow auctions: pending txns are streamed
+//! as hints (hash plus whatever the sender chose to reveal), and a backrun
+//! is submitted as a bundle referencing the hint's hash, so the searcher
+//! never sees the txn itself. Endpoints are configurable for Polygon
+//! auctions that follow the same API.
+//!
+//! The arb backruns hints with `arb::ArbBackrunner` under `--mev-share`.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::{
+    signers::{LocalWallet, Signer, WalletError},
+    types::{Address, Bytes, H256, U256, U64},
+    utils::keccak256,
+};
+use futures_util::{stream::BoxStream, StreamExt};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+pub const FLASHBOTS_MEV_SHARE_STREAM: &str = "https://mev-share.flashbots.net";
+pub const FLASHBOTS_RELAY: &str = "https://relay.flashbots.net";
+
+/// blocks a backrun bundle stays valid for after its target block
+pub const DEFAULT_MAX_BLOCKS: u64 = 3;
+
+// a bundle answered later than this has likely missed its block anyway
+const BUNDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum MevShareError {
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    WalletError(#[from] WalletError),
+
+    #[error("relay rejected bundle: {0}")]
+    RelayError(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HintLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HintTx {
+    pub to: Option<Address>,
+    pub function_selector: Option<Bytes>,
+    pub call_data: Option<Bytes>,
+}
+
+/// A pending txn as revealed by the auction, every field but `hash` is up
+/// to the sender.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hint {
+    pub hash: H256,
+    #[serde(default)]
+    pub logs: Vec<HintLog>,
+    #[serde(default)]
+    pub txs: Vec<HintTx>,
+    pub mev_gas_price: Option<U256>,
+    pub gas_used: Option<U256>,
+}
+
+impl Hint {
+    /// pools that emitted a log, e.g. where a swap happened
+    pub fn log_addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.logs.iter().map(|log| log.address).collect();
+        addresses.dedup();
+        addresses
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inclusion {
+    pub block: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_block: Option<U64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum BundleItem {
+    /// a hinted txn, by hash
+    Hash { hash: H256 },
+    #[serde(rename_all = "camelCase")]
+    Tx { tx: Bytes, can_revert: bool },
+}
+
+/// `mev_sendBundle` params
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BundleRequest {
+    pub version: &'static str,
+    pub inclusion: Inclusion,
+    pub body: Vec<BundleItem>,
+}
+
+impl BundleRequest {
+    /// `backrun` signed raw, landing right after the hinted txn at `block`
+    /// or one of the `max_blocks` after it
+    pub fn backrun(hint: H256, backrun: Bytes, block: u64, max_blocks: u64) -> Self {
+        Self {
+            version: "v0.1",
+            inclusion: Inclusion {
+                block: block.into(),
+                max_block: Some((block + max_blocks).into()),
+            },
+            body: vec![
+                BundleItem::Hash { hash: hint },
+                BundleItem::Tx {
+                    tx: backrun,
+                    can_revert: false,
+                },
+            ],
+        }
+    }
+}
+
+/// a signed backrun and the first block it may land in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backrun {
+    pub tx: Bytes,
+    pub block: u64,
+}
+
+/// Whatever turns hints into backruns, it has to price the pools the hint
+/// touched as the hinted txn leaves them.
+#[async_trait]
+pub trait Backrunner: Send + Sync {
+    async fn backrun(&self, hint: &Hint) -> Option<Backrun>;
+}
+
+/// Splits a server sent event stream into the payloads of its `data:`
+/// lines, events spanning several reads included.
+#[derive(Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut payloads = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                payloads.push(data.trim_start().to_string());
+            }
+        }
+        payloads
+    }
+}
+
+pub struct MevShareClient {
+    http: reqwest::Client,
+    stream_url: String,
+    relay_url: String,
+    /// signs bundle requests, identifies the searcher to the relay but
+    /// holds no funds
+    auth_signer: LocalWallet,
+}
+
+impl MevShareClient {
+    pub fn new(stream_url: &str, relay_url: &str, auth_signer: LocalWallet) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            stream_url: stream_url.to_string(),
+            relay_url: relay_url.to_string(),
+            auth_signer,
+        }
+    }
+
+    pub fn flashbots(auth_signer: LocalWallet) -> Self {
+        Self::new(FLASHBOTS_MEV_SHARE_STREAM, FLASHBOTS_RELAY, auth_signer)
+    }
+
+    /// Hints from now on. Ends when the server closes the stream, events
+    /// that aren't hints (e.g. keepalives) are skipped.
+    pub async fn subscribe_hints(&self) -> Result<BoxStream<'static, Hint>, MevShareError> {
+        let response = self
+            .http
+            .get(&self.stream_url)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut decoder = SseDecoder::default();
+        Ok(response
+            .bytes_stream()
+            .take_while(|chunk| {
+                if let Err(e) = chunk {
+                    warn!("MEV-share stream closed: {}", e);
+                }
+                futures_util::future::ready(chunk.is_ok())
+            })
+            .flat_map(move |chunk| {
+                let hints: Vec<Hint> = decoder
+                    .push(&chunk.unwrap_or_default())
+                    .into_iter()
+                    .filter_map(|data| serde_json::from_str(&data).ok())
+                    .collect();
+                futures_util::stream::iter(hints)
+            })
+            .boxed())
+    }
+
+    /// submits `bundle` through `mev_sendBundle`, returns the bundle hash
+    pub async fn send_bundle(&self, bundle: &BundleRequest) -> Result<H256, MevShareError> {
+        let body = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "mev_sendBundle",
+            "params": [bundle],
+        }))?;
+        let response: serde_json::Value = self
+            .http
+            .post(&self.relay_url)
+            .header("Content-Type", "application/json")
+            .header("X-Flashbots-Signature", self.signature_header(&body).await?)
+            .body(body)
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(MevShareError::RelayError(error.to_string()));
+        }
+        Ok(serde_json::from_value(
+            response["result"]["bundleHash"].clone(),
+        )?)
+    }
+
+    /// `<address>:<signature of the body's keccak as hex>`
+    async fn signature_header(&self, body: &[u8]) -> Result<String, MevShareError> {
+        let digest = format!("{:?}", H256::from(keccak256(body)));
+        let signature = self.auth_signer.sign_message(digest).await?;
+        Ok(format!("{:?}:0x{}", self.auth_signer.address(), signature))
+    }
+
+    /// Backruns every hint `backrunner` finds something for, until the hint
+    /// stream ends. Reconnects are left to the caller.
+    pub async fn run_backruns<B: Backrunner>(
+        &self,
+        backrunner: &B,
+        max_blocks: u64,
+    ) -> Result<(), MevShareError> {
+        let mut hints = self.subscribe_hints().await?;
+        while let Some(hint) = hints.next().await {
+            let backrun = match backrunner.backrun(&hint).await {
+                Some(backrun) => backrun,
+                None => continue,
+            };
+            let bundle = BundleRequest::backrun(hint.hash, backrun.tx, backrun.block, max_blocks);
+            match tokio::time::timeout(BUNDLE_TIMEOUT, self.send_bundle(&bundle)).await {
+                Ok(Ok(bundle_hash)) => {
+                    debug!(
+                        "Backrun of {:?} sent as bundle {:?}",
+                        hint.hash, bundle_hash
+                    )
+                }
+                Ok(Err(e)) => warn!("Backrun of {:?} failed: {}", hint.hash, e),
+                Err(_) => warn!("Backrun of {:?} timed out", hint.hash),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mev_share_hints_and_bundles() {
+        let hash = H256::repeat_byte(1);
+        let event = format!(
+            r#"{{"hash":"{:?}","logs":[{{"address":"0x0000000000000000000000000000000000000002","topics":[],"data":"0x"}}],"mevGasPrice":"0x1","gasUsed":"0x5208"}}"#,
+            hash
+        );
+        let stream = format!(":ping\n\ndata: {}\n\n", event);
+        let mut decoder = SseDecoder::default();
+        let (first, second) = stream.as_bytes().split_at(20);
+        assert!(decoder.push(first).is_empty());
+        let payloads = decoder.push(second);
+        assert_eq!(payloads.len(), 1);
+        let hint: Hint = serde_json::from_str(&payloads[0]).unwrap();
+        assert_eq!(hint.hash, hash);
+        assert_eq!(hint.log_addresses(), vec![Address::from_low_u64_be(2)]);
+
+        let bundle = BundleRequest::backrun(hash, vec![0xab].into(), 100, 2);
+        let bundle = serde_json::to_value(&bundle).unwrap();
+        assert_eq!(bundle["inclusion"]["maxBlock"], "0x66");
+        assert_eq!(bundle["body"][0]["hash"], format!("{:?}", hash));
+        assert_eq!(bundle["body"][1]["canRevert"], false);
+
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let client = MevShareClient::flashbots(wallet.clone());
+        let header = client.signature_header(b"{}").await.unwrap();
+        let (address, signature) = header.split_once(':').unwrap();
+        assert_eq!(address, format!("{:?}", wallet.address()));
+        let signature: ethers::types::Signature = signature[2..].parse().unwrap();
+        let digest = format!("{:?}", H256::from(keccak256(b"{}")));
+        assert_eq!(signature.recover(digest).unwrap(), wallet.address());
+    }
+}
@@ -1,9 +1,33 @@
+#[cfg(any(test, feature = "test-utils"))]
+pub mod anvil_fork;
 pub mod batch;
 pub mod block;
+pub mod block_diff;
 pub mod block_oracle;
+pub mod block_simulator;
+pub mod broadcast;
+pub mod bundle;
+pub mod calldata;
+pub mod fee_history;
+pub mod fixed_point;
+pub mod local_sim;
+pub mod lp;
 pub mod matrix;
+pub mod mev_share;
 pub mod multicall;
+pub mod nonce_guard;
+pub mod permit;
+pub mod poll_schedule;
+pub mod quote_cache;
+pub mod route_health;
 pub mod serialize_structs;
+pub mod sim_cache;
+pub mod sim_check;
+pub mod submitter;
+pub mod tracer;
 pub mod transaction;
 pub mod trie;
+pub mod twap;
+pub mod txpool;
 pub mod txstructs;
+pub mod user_op;
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use ethers::{
     abi::{Detokenize, Function, Token},
-    prelude::{abigen, builders::ContractCall},
+    prelude::{abigen, builders::ContractCall, ContractError},
     providers::Middleware,
     types::{Address, Bytes, NameOrAddress, U256},
 };
@@ -69,9 +69,10 @@ impl<M: Middleware> Multicall<M> {
         contract_call
     }
 
-    pub async fn call_raw(&self) -> Vec<Option<Vec<Token>>> {
+    /// outputs of the calls in order, `None` for the ones that reverted
+    pub async fn call_raw(&self) -> std::result::Result<Vec<Option<Vec<Token>>>, ContractError<M>> {
         let call: ContractCall<M, Vec<Result>> = self.as_aggregate_3();
-        let return_data: Vec<Result> = call.call().await.unwrap();
+        let return_data: Vec<Result> = call.call().await?;
 
         let output = self
             .calls
@@ -92,6 +93,6 @@ impl<M: Middleware> Multicall<M> {
             })
             .collect();
 
-        return output;
+        Ok(output)
     }
 }
@@ -0,0 +1,214 @@
+//! Hands out the hot wallet's nonces locally and watches the mempool and new
+//! blocks for transactions from the wallet the bot didn't send (a manual
+//! transfer from another client). Those take a nonce the local count
+//! doesn't know about, so the count is resynced from the node instead of
+//! sending transactions that fail with "nonce too low".
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, Transaction, H256, U256},
+};
+use log::{error, info};
+
+use crate::bus::{next, Bus};
+
+/// sent hashes remembered to tell own transactions from external ones
+const SENT_HISTORY: usize = 1024;
+
+#[derive(Default)]
+struct State {
+    /// next nonce to send with, `None` until synced with the node
+    next: Option<U256>,
+    /// handed out, not sent or failed yet
+    reserved: HashSet<U256>,
+    sent: HashSet<H256>,
+    sent_order: VecDeque<H256>,
+}
+
+impl State {
+    fn remember(&mut self, hash: H256) {
+        if self.sent.insert(hash) {
+            self.sent_order.push_back(hash);
+        }
+        while self.sent_order.len() > SENT_HISTORY {
+            let oldest = self.sent_order.pop_front().unwrap();
+            self.sent.remove(&oldest);
+        }
+    }
+}
+
+pub struct NonceGuard {
+    wallet: Address,
+    state: Mutex<State>,
+}
+
+impl NonceGuard {
+    pub fn new(wallet: Address) -> Self {
+        Self {
+            wallet,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn wallet(&self) -> Address {
+        self.wallet
+    }
+
+    /// next nonce to send with, `None` while out of sync, in which case the
+    /// middleware should fill it in from the node
+    pub fn reserve(&self) -> Option<U256> {
+        let mut state = self.state.lock().unwrap();
+        let nonce = state.next?;
+        state.next = Some(nonce + 1);
+        state.reserved.insert(nonce);
+        Some(nonce)
+    }
+
+    /// the transaction with `nonce` went out as `hash`
+    pub fn sent(&self, nonce: U256, hash: H256) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved.remove(&nonce);
+        state.remember(hash);
+    }
+
+    /// Sending with `nonce` failed, so the nonces after it are off. Nonces
+    /// come from the node until the next resync.
+    pub fn failed(&self, nonce: U256) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved.remove(&nonce);
+        state.next = None;
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.state.lock().unwrap().next.is_some()
+    }
+
+    /// Whether `tx` is from the wallet but wasn't sent through this guard.
+    /// An external transaction also puts the guard out of sync.
+    pub fn observe(&self, tx: &Transaction) -> bool {
+        if tx.from != self.wallet {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        // ours, or one of ours still on its way back from the node
+        if state.sent.contains(&tx.hash) || state.reserved.contains(&tx.nonce) {
+            return false;
+        }
+        // warned once, not again when it's mined
+        state.remember(tx.hash);
+        state.next = None;
+        true
+    }
+
+    /// takes the next nonce from the node's pending count
+    pub async fn resync<M: Middleware>(&self, provider: &M) -> Result<U256, M::Error> {
+        let nonce = provider
+            .get_transaction_count(self.wallet, Some(BlockNumber::Pending.into()))
+            .await?;
+        let mut state = self.state.lock().unwrap();
+        state.next = Some(nonce);
+        state.reserved.clear();
+        Ok(nonce)
+    }
+
+    async fn resync_logged<M: Middleware>(&self, provider: &M) {
+        match self.resync(provider).await {
+            Ok(nonce) => info!("Wallet {:?} nonce resynced to {}", self.wallet, nonce),
+            Err(e) => error!("Failed to resync wallet {:?} nonce: {:?}", self.wallet, e),
+        }
+    }
+
+    /// Watches pending transactions and the transactions of new blocks on
+    /// `bus` for external use of the wallet, resyncing whenever the guard
+    /// is out of sync. Runs until the bus is dropped.
+    pub async fn watch<M: Middleware>(self: Arc<Self>, provider: Arc<M>, bus: Arc<Bus>) {
+        let mut pending_txs = bus.pending_txs.subscribe();
+        let mut blocks = bus.blocks.subscribe();
+        drop(bus);
+        if !self.is_synced() {
+            self.resync_logged(provider.as_ref()).await;
+        }
+        loop {
+            let external: Vec<Transaction> = tokio::select! {
+                Some(tx) = next(&mut pending_txs, "pending txs") => {
+                    if self.observe(&tx) { vec![tx] } else { vec![] }
+                }
+                Some(block) = next(&mut blocks, "blocks") => {
+                    match provider.get_block_with_txs(block.number).await {
+                        Ok(Some(block)) => block
+                            .transactions
+                            .into_iter()
+                            .filter(|tx| self.observe(tx))
+                            .collect(),
+                        Ok(None) => vec![],
+                        Err(e) => {
+                            error!("Failed to get block {} transactions: {:?}", block.number, e);
+                            vec![]
+                        }
+                    }
+                }
+                else => break,
+            };
+            for tx in &external {
+                error!(
+                    "Wallet {:?} sent {:?} (nonce {}) from outside the bot, resyncing nonce. Don't use the hot wallet elsewhere while the bot runs",
+                    self.wallet, tx.hash, tx.nonce
+                );
+            }
+            if !self.is_synced() {
+                self.resync_logged(provider.as_ref()).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(from: Address, nonce: u64, hash: u64) -> Transaction {
+        Transaction {
+            from,
+            nonce: nonce.into(),
+            hash: H256::from_low_u64_be(hash),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_nonce_guard() {
+        let wallet = Address::from_low_u64_be(1);
+        let guard = NonceGuard::new(wallet);
+        assert_eq!(guard.reserve(), None);
+        guard.state.lock().unwrap().next = Some(5.into());
+
+        // seen in the mempool before the send returned
+        let nonce = guard.reserve().unwrap();
+        assert_eq!(nonce, 5.into());
+        assert!(!guard.observe(&tx(wallet, 5, 100)));
+        guard.sent(nonce, H256::from_low_u64_be(100));
+        // and again once mined
+        assert!(!guard.observe(&tx(wallet, 5, 100)));
+        // someone else's
+        assert!(!guard.observe(&tx(Address::from_low_u64_be(2), 6, 101)));
+        assert_eq!(guard.reserve(), Some(6.into()));
+        guard.sent(6.into(), H256::from_low_u64_be(102));
+
+        // a manual transfer takes nonce 7
+        assert!(guard.observe(&tx(wallet, 7, 103)));
+        assert!(!guard.is_synced());
+        assert_eq!(guard.reserve(), None);
+        // warned about once
+        assert!(!guard.observe(&tx(wallet, 7, 103)));
+
+        guard.state.lock().unwrap().next = Some(8.into());
+        let nonce = guard.reserve().unwrap();
+        guard.failed(nonce);
+        assert_eq!(guard.reserve(), None);
+    }
+}
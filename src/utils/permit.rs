@@ -0,0 +1,309 @@
+//! EIP-2612 `permit` and Permit2 allowance signatures, so a token allowance
+//! can ride along in the same bundle as the swap that spends it instead of
+//! needing its own approval transaction mined first.
+
+use std::sync::Arc;
+
+use ethers::{
+    abi::{encode, Token},
+    prelude::{abigen, k256::ecdsa::SigningKey, ContractError},
+    providers::Middleware,
+    signers::{Signer, Wallet},
+    types::{
+        transaction::{eip2718::TypedTransaction, eip712::EIP712Domain},
+        Address, Signature, H256, U256,
+    },
+    utils::keccak256,
+};
+use lazy_static::lazy_static;
+
+abigen!(
+    IERC20Permit,
+    r#"[
+        function permit(address owner, address spender, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external
+        function nonces(address owner) external view returns (uint256)
+        function DOMAIN_SEPARATOR() external view returns (bytes32)
+    ]"#,
+);
+
+abigen!(
+    IPermit2,
+    r#"[
+        struct PermitDetails { address token; uint160 amount; uint48 expiration; uint48 nonce; }
+        struct PermitSingle { PermitDetails details; address spender; uint256 sigDeadline; }
+        function permit(address owner, PermitSingle permitSingle, bytes signature) external
+        function allowance(address user, address token, address spender) external view returns (uint160 amount, uint48 expiration, uint48 nonce)
+    ]"#,
+);
+
+lazy_static! {
+    /// same address on every chain
+    pub static ref PERMIT2: Address = "0x000000000022D473030F116dDEE9F6B43aC78BA3"
+        .parse::<Address>()
+        .unwrap();
+    static ref PERMIT_TYPEHASH: [u8; 32] = keccak256(
+        "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)"
+    );
+    static ref PERMIT_DETAILS_TYPEHASH: [u8; 32] =
+        keccak256("PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)");
+    static ref PERMIT_SINGLE_TYPEHASH: [u8; 32] = keccak256(
+        "PermitSingle(PermitDetails details,address spender,uint256 sigDeadline)PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)"
+    );
+}
+
+/// `keccak256(0x1901 ++ domain_separator ++ struct_hash)`
+fn typed_data_digest(domain_separator: H256, struct_hash: [u8; 32]) -> H256 {
+    H256::from(keccak256(
+        [&[0x19, 0x01], domain_separator.as_bytes(), &struct_hash[..]].concat(),
+    ))
+}
+
+/// domain separator of an OpenZeppelin style `ERC20Permit` token
+pub fn domain_separator(name: &str, version: &str, chain_id: u64, token: Address) -> H256 {
+    H256::from(
+        EIP712Domain {
+            name: Some(name.to_string()),
+            version: Some(version.to_string()),
+            chain_id: Some(chain_id.into()),
+            verifying_contract: Some(token),
+            salt: None,
+        }
+        .separator(),
+    )
+}
+
+/// domain separator of the canonical Permit2 deployment, which has no version
+pub fn permit2_domain_separator(chain_id: u64) -> H256 {
+    H256::from(
+        EIP712Domain {
+            name: Some("Permit2".to_string()),
+            version: None,
+            chain_id: Some(chain_id.into()),
+            verifying_contract: Some(*PERMIT2),
+            salt: None,
+        }
+        .separator(),
+    )
+}
+
+/// EIP-2612 `permit` message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permit {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+impl Permit {
+    pub fn digest(&self, domain_separator: H256) -> H256 {
+        let struct_hash = keccak256(encode(&[
+            Token::FixedBytes(PERMIT_TYPEHASH.to_vec()),
+            Token::Address(self.owner),
+            Token::Address(self.spender),
+            Token::Uint(self.value),
+            Token::Uint(self.nonce),
+            Token::Uint(self.deadline),
+        ]));
+        typed_data_digest(domain_separator, struct_hash)
+    }
+
+    pub fn sign(&self, wallet: &Wallet<SigningKey>, domain_separator: H256) -> Signature {
+        wallet.sign_hash(self.digest(domain_separator))
+    }
+}
+
+/// Permit2 `PermitSingle` message, an allowance of `token` for `spender`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permit2Single {
+    pub token: Address,
+    /// uint160
+    pub amount: U256,
+    /// uint48 timestamp the allowance lapses at
+    pub expiration: u64,
+    /// uint48
+    pub nonce: u64,
+    pub spender: Address,
+    pub sig_deadline: U256,
+}
+
+impl Permit2Single {
+    pub fn digest(&self, chain_id: u64) -> H256 {
+        let details_hash = keccak256(encode(&[
+            Token::FixedBytes(PERMIT_DETAILS_TYPEHASH.to_vec()),
+            Token::Address(self.token),
+            Token::Uint(self.amount),
+            Token::Uint(self.expiration.into()),
+            Token::Uint(self.nonce.into()),
+        ]));
+        let struct_hash = keccak256(encode(&[
+            Token::FixedBytes(PERMIT_SINGLE_TYPEHASH.to_vec()),
+            Token::FixedBytes(details_hash.to_vec()),
+            Token::Address(self.spender),
+            Token::Uint(self.sig_deadline),
+        ]));
+        typed_data_digest(permit2_domain_separator(chain_id), struct_hash)
+    }
+
+    pub fn sign(&self, wallet: &Wallet<SigningKey>, chain_id: u64) -> Signature {
+        wallet.sign_hash(self.digest(chain_id))
+    }
+
+    fn into_call_params(self) -> PermitSingle {
+        PermitSingle {
+            details: PermitDetails {
+                token: self.token,
+                amount: self.amount,
+                expiration: self.expiration,
+                nonce: self.nonce,
+            },
+            spender: self.spender,
+            sig_deadline: self.sig_deadline,
+        }
+    }
+}
+
+/// Signs permits for `wallet` and builds the txns that submit them. The
+/// permit txn can be sent by anyone, so it goes into the same bundle as the
+/// swap, just ahead of it.
+pub struct PermitSigner<M> {
+    provider: Arc<M>,
+    wallet: Wallet<SigningKey>,
+    chain_id: u64,
+}
+
+impl<M: Middleware> PermitSigner<M> {
+    pub fn new(provider: Arc<M>, wallet: Wallet<SigningKey>, chain_id: u64) -> Self {
+        Self {
+            provider,
+            wallet,
+            chain_id,
+        }
+    }
+
+    /// EIP-2612 `permit` on `token`, nonce and domain separator read from it
+    pub async fn permit_txn(
+        &self,
+        token: Address,
+        spender: Address,
+        value: U256,
+        deadline: U256,
+    ) -> Result<TypedTransaction, ContractError<M>> {
+        let contract = IERC20Permit::new(token, self.provider.clone());
+        let owner = self.wallet.address();
+        let nonce = contract.nonces(owner).call().await?;
+        let domain_separator = H256::from(contract.domain_separator().call().await?);
+        let permit = Permit {
+            owner,
+            spender,
+            value,
+            nonce,
+            deadline,
+        };
+        let signature = permit.sign(&self.wallet, domain_separator);
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        signature.r.to_big_endian(&mut r);
+        signature.s.to_big_endian(&mut s);
+        Ok(contract
+            .permit(owner, spender, value, deadline, signature.v as u8, r, s)
+            .tx)
+    }
+
+    /// Permit2 allowance of `token` for `spender`, nonce read from Permit2.
+    /// The token itself needs a standing approval of the Permit2 contract.
+    pub async fn permit2_txn(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: U256,
+        expiration: u64,
+        sig_deadline: U256,
+    ) -> Result<TypedTransaction, ContractError<M>> {
+        let permit2 = IPermit2::new(*PERMIT2, self.provider.clone());
+        let owner = self.wallet.address();
+        let (_, _, nonce) = permit2.allowance(owner, token, spender).call().await?;
+        let permit = Permit2Single {
+            token,
+            amount,
+            expiration,
+            nonce,
+            spender,
+            sig_deadline,
+        };
+        let signature = permit.sign(&self.wallet, self.chain_id);
+        Ok(permit2
+            .permit(owner, permit.into_call_params(), signature.to_vec().into())
+            .tx)
+    }
+
+    /// `[permit, swap]`, in the order they have to land
+    pub async fn bundle_with_permit(
+        &self,
+        token: Address,
+        spender: Address,
+        value: U256,
+        deadline: U256,
+        swap: TypedTransaction,
+    ) -> Result<Vec<TypedTransaction>, ContractError<M>> {
+        let permit = self.permit_txn(token, spender, value, deadline).await?;
+        Ok(vec![permit, swap])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::LocalWallet;
+
+    use super::*;
+
+    #[test]
+    fn test_permit_signatures_recover() {
+        assert_eq!(
+            H256::from(*PERMIT_TYPEHASH),
+            "0x6e71edae12b1b97f4d1f60370fef10105fa2faae0126114a169c64845d6126c9"
+                .parse::<H256>()
+                .unwrap()
+        );
+        assert_eq!(
+            H256::from(*PERMIT_SINGLE_TYPEHASH),
+            "0xf3841cd1ff0085026a6327b620b67997ce40f282c88a8e905a7a5626e310f3d0"
+                .parse::<H256>()
+                .unwrap()
+        );
+
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let token = Address::random();
+        let permit = Permit {
+            owner: wallet.address(),
+            spender: Address::random(),
+            value: U256::MAX,
+            nonce: 0.into(),
+            deadline: 1_700_000_000.into(),
+        };
+        let separator = domain_separator("USD Coin", "1", 137, token);
+        let signature = permit.sign(&wallet, separator);
+        assert_eq!(
+            signature.recover(permit.digest(separator)).unwrap(),
+            wallet.address()
+        );
+
+        let permit2 = Permit2Single {
+            token,
+            amount: U256::from(1_000_000),
+            expiration: 1_700_000_000,
+            nonce: 3,
+            spender: Address::random(),
+            sig_deadline: 1_700_000_000.into(),
+        };
+        let signature = permit2.sign(&wallet, 137);
+        assert_eq!(
+            signature.recover(permit2.digest(137)).unwrap(),
+            wallet.address()
+        );
+        assert_ne!(permit2.digest(137), permit2.digest(1));
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, hash::Hash};
+
+use ethers::types::U256;
+
+/// How often pools without event subscriptions are requoted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PollConfig {
+    /// quiet pools are refreshed every this many blocks
+    pub quiet_blocks: u64,
+    /// a quote moving at least this much makes its pool hot
+    pub move_bps: u64,
+    /// blocks a pool stays hot after a move or a profitable route through it
+    pub hot_blocks: u64,
+    /// pools refreshed per block at most, hot ones first
+    pub budget: usize,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            quiet_blocks: 5,
+            move_bps: 20,
+            hot_blocks: 10,
+            budget: 50,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Pool {
+    last_refresh: Option<u64>,
+    hot_until: u64,
+    /// (amount in, amount out) of the last quote
+    last_quote: Option<(U256, U256)>,
+}
+
+/// how far `b` is from `a`, in bps of the smaller
+fn move_bps(a: U256, b: U256) -> u64 {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    if low.is_zero() {
+        return if high.is_zero() { 0 } else { u64::MAX };
+    }
+    ((high - low) * U256::from(10_000) / low)
+        .min(U256::from(u64::MAX))
+        .as_u64()
+}
+
+/// Decides which pools get requoted each block: hot pools (recent large
+/// moves, or part of a recently profitable route) every block, quiet ones
+/// every `quiet_blocks`, never more than `budget` at once.
+pub struct PollSchedule<K> {
+    config: PollConfig,
+    pools: HashMap<K, Pool>,
+}
+
+impl<K: Hash + Eq + Clone> PollSchedule<K> {
+    pub fn new(config: PollConfig) -> Self {
+        Self {
+            config,
+            pools: HashMap::new(),
+        }
+    }
+
+    /// Records a fresh quote of `pool`, heating it up when it moved at least
+    /// `move_bps` since the last quote of the same amount.
+    pub fn observe(&mut self, pool: K, block: u64, amount_in: U256, amount_out: U256) {
+        let config = self.config;
+        let state = self.pools.entry(pool).or_default();
+        state.last_refresh = Some(state.last_refresh.map_or(block, |last| last.max(block)));
+        if let Some((last_in, last_out)) = state.last_quote {
+            if last_in == amount_in && move_bps(last_out, amount_out) >= config.move_bps {
+                state.hot_until = state.hot_until.max(block + config.hot_blocks);
+            }
+        }
+        state.last_quote = Some((amount_in, amount_out));
+    }
+
+    /// `pool` was part of a profitable route in `block`
+    pub fn mark_profitable(&mut self, pool: K, block: u64) {
+        let state = self.pools.entry(pool).or_default();
+        state.hot_until = state.hot_until.max(block + self.config.hot_blocks);
+    }
+
+    pub fn is_hot(&self, pool: &K, block: u64) -> bool {
+        self.pools
+            .get(pool)
+            .is_some_and(|state| state.hot_until >= block)
+    }
+
+    /// Pools to refresh in `block`, hot ones first then the longest
+    /// unrefreshed, counted as refreshed from here on.
+    pub fn due(&mut self, block: u64) -> Vec<K> {
+        let quiet_blocks = self.config.quiet_blocks.max(1);
+        let mut due: Vec<(bool, Option<u64>, &K)> = self
+            .pools
+            .iter()
+            .filter_map(|(pool, state)| {
+                let hot = state.hot_until >= block;
+                let interval = if hot { 1 } else { quiet_blocks };
+                match state.last_refresh {
+                    Some(last) if block < last + interval => None,
+                    last => Some((hot, last, pool)),
+                }
+            })
+            .collect();
+        // hot before quiet, never refreshed before refreshed long ago
+        due.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let due: Vec<K> = due
+            .into_iter()
+            .take(self.config.budget)
+            .map(|(_, _, pool)| pool.clone())
+            .collect();
+        for pool in &due {
+            self.pools.get_mut(pool).unwrap().last_refresh = Some(block);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_schedule() {
+        let mut schedule = PollSchedule::new(PollConfig {
+            quiet_blocks: 3,
+            move_bps: 20,
+            hot_blocks: 2,
+            budget: 2,
+        });
+        let amount = U256::from(1_000_000);
+        for pool in 1..=3 {
+            schedule.observe(pool, 10, amount, amount);
+        }
+        assert!(schedule.due(11).is_empty());
+        // moved 0.3%, hot for the next two blocks
+        schedule.observe(1, 11, amount, U256::from(1_003_000));
+        assert!(schedule.is_hot(&1, 12));
+        assert_eq!(schedule.due(12), vec![1]);
+
+        // quiet pools come due, the hot one still goes first within budget
+        schedule.mark_profitable(2, 12);
+        let mut due = schedule.due(13);
+        assert_eq!(due.len(), 2);
+        due.sort();
+        assert_eq!(due, vec![1, 2]);
+        // pool 3 was crowded out and is now the most overdue
+        assert_eq!(schedule.due(14), vec![2, 3]);
+        assert!(!schedule.is_hot(&1, 14));
+    }
+
+    #[test]
+    fn test_small_moves_stay_quiet() {
+        let mut schedule = PollSchedule::new(PollConfig::default());
+        schedule.observe("pool", 1, U256::from(100), U256::from(10_000));
+        schedule.observe("pool", 2, U256::from(100), U256::from(10_010));
+        assert!(!schedule.is_hot(&"pool", 2));
+        // a different amount is not a price move
+        schedule.observe("pool", 3, U256::from(200), U256::from(19_000));
+        assert!(!schedule.is_hot(&"pool", 3));
+    }
+}
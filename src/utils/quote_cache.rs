@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl QuoteCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+struct Entries<K, V> {
+    /// `None` until the first `advance`, nothing is cached before that
+    block_number: Option<u64>,
+    quotes: HashMap<(K, U256), V>,
+}
+
+/// Memoizes quotes of `pool` for an amount within a block. Amounts are
+/// bucketed to their top `precision_bits` bits, so routes that reach a hop
+/// with nearly the same amount share one quote; quotes are always computed
+/// for the bucketed amount. Everything is dropped once a newer block starts.
+pub struct QuoteCache<K, V> {
+    precision_bits: u32,
+    entries: Mutex<Entries<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq, V: Copy> QuoteCache<K, V> {
+    /// `precision_bits` of 0 keys on exact amounts
+    pub fn new(precision_bits: u32) -> Self {
+        Self {
+            precision_bits,
+            entries: Mutex::new(Entries {
+                block_number: None,
+                quotes: HashMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// `amount` with everything below its top `precision_bits` bits cleared
+    pub fn bucket(&self, amount: U256) -> U256 {
+        let bits = amount.bits() as u32;
+        if self.precision_bits == 0 || bits <= self.precision_bits {
+            return amount;
+        }
+        let shift = bits - self.precision_bits;
+        (amount >> shift) << shift
+    }
+
+    /// starts `block_number`, clearing quotes of earlier blocks
+    pub fn advance(&self, block_number: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.block_number < Some(block_number) {
+            entries.block_number = Some(block_number);
+            entries.quotes.clear();
+        }
+    }
+
+    /// like `advance`, but keeps the quotes of pools `keep` returns true
+    /// for, reused as they are in the new block
+    pub fn advance_retaining(&self, block_number: u64, keep: impl Fn(&K) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.block_number < Some(block_number) {
+            entries.block_number = Some(block_number);
+            entries.quotes.retain(|(pool, _), _| keep(pool));
+        }
+    }
+
+    /// drops the quotes of `pool` in the current block, so it's requoted
+    pub fn invalidate(&self, pool: &K) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.quotes.retain(|(cached, _), _| cached != pool);
+    }
+
+    pub fn block_number(&self) -> Option<u64> {
+        self.entries.lock().unwrap().block_number
+    }
+
+    /// quote of `pool` for the bucket of `amount`, if it's cached for the
+    /// current block
+    pub fn get(&self, pool: K, amount: U256) -> Option<V> {
+        let key = (pool, self.bucket(amount));
+        let quote = self.entries.lock().unwrap().quotes.get(&key).copied();
+        match quote {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        quote
+    }
+
+    /// Caches `quote`, computed for `self.bucket(amount)` as of
+    /// `block_number`. Quotes for any block but the current one are dropped.
+    pub fn insert(&self, pool: K, amount: U256, block_number: u64, quote: V) {
+        let key = (pool, self.bucket(amount));
+        let mut entries = self.entries.lock().unwrap();
+        if entries.block_number == Some(block_number) {
+            entries.quotes.insert(key, quote);
+        }
+    }
+
+    /// cached quote, or `quote(bucketed amount)` cached for the current block
+    pub fn get_or_insert_with(&self, pool: K, amount: U256, quote: impl FnOnce(U256) -> V) -> V
+    where
+        K: Clone,
+    {
+        if let Some(cached) = self.get(pool.clone(), amount) {
+            return cached;
+        }
+        let value = quote(self.bucket(amount));
+        if let Some(block_number) = self.block_number() {
+            self.insert(pool, amount, block_number, value);
+        }
+        value
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().quotes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> QuoteCacheStats {
+        QuoteCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// stats since the last reset, e.g. once per block
+    pub fn reset_stats(&self) -> QuoteCacheStats {
+        QuoteCacheStats {
+            hits: self.hits.swap(0, Ordering::Relaxed),
+            misses: self.misses.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_cache_buckets_and_blocks() {
+        let cache: QuoteCache<u8, U256> = QuoteCache::new(8);
+        assert_eq!(cache.bucket(U256::from(0x1234)), U256::from(0x1220));
+        assert_eq!(cache.bucket(U256::from(0xff)), U256::from(0xff));
+
+        cache.advance(10);
+        let quote = cache.get_or_insert_with(1, U256::from(0x1234), |amount| amount * 2);
+        assert_eq!(quote, U256::from(0x2440));
+        // same bucket, served from the cache
+        let quote = cache.get_or_insert_with(1, U256::from(0x123f), |_| unreachable!());
+        assert_eq!(quote, U256::from(0x2440));
+        assert_eq!(cache.stats(), QuoteCacheStats { hits: 1, misses: 1 });
+
+        // late results of an old block are not cached
+        cache.advance(11);
+        assert!(cache.is_empty());
+        cache.insert(1, U256::from(0x1234), 10, U256::zero());
+        assert_eq!(cache.get(1, U256::from(0x1234)), None);
+        assert_eq!(cache.reset_stats().hit_rate(), 1.0 / 3.0);
+
+        cache.insert(1, U256::from(1), 11, U256::one());
+        cache.insert(2, U256::from(1), 11, U256::one());
+        cache.advance_retaining(12, |pool| *pool == 2);
+        assert_eq!(cache.get(1, U256::from(1)), None);
+        assert_eq!(cache.get(2, U256::from(1)), Some(U256::one()));
+        cache.invalidate(&2);
+        assert!(cache.is_empty());
+    }
+}
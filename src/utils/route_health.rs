@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// How quickly reverts are forgiven and when a route is benched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RouteHealthConfig {
+    /// executions this long ago count half
+    pub half_life: Duration,
+    /// (decayed) executions before the revert rate is trusted
+    pub min_attempts: f64,
+    /// revert rate at which a route goes on cooldown
+    pub cooldown_rate: f64,
+    /// first cooldown, doubled every time the route is benched again
+    /// before it has succeeded once more
+    pub cooldown: Duration,
+    pub max_cooldown: Duration,
+}
+
+impl Default for RouteHealthConfig {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(30 * 60),
+            min_attempts: 3.0,
+            cooldown_rate: 0.75,
+            cooldown: Duration::from_secs(10 * 60),
+            max_cooldown: Duration::from_secs(6 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Standing {
+    /// likely to land as often as `success_rate`, 1 for routes without
+    /// enough history
+    Active { success_rate: f64 },
+    /// not to be tried before `until`
+    Cooldown { until: Instant },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Stats {
+    attempts: f64,
+    reverts: f64,
+    updated: Instant,
+    cooldown_until: Option<Instant>,
+    /// cooldowns since the last success
+    strikes: u32,
+}
+
+impl Stats {
+    fn decay(&mut self, half_life: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let factor = 0.5f64.powf(elapsed / half_life.as_secs_f64());
+        self.attempts *= factor;
+        self.reverts *= factor;
+        self.updated = now;
+    }
+}
+
+/// Revert rates of executed routes, decaying over time. Routes that keep
+/// reverting are discounted and eventually benched for a cooldown, after
+/// which they come back with their history mostly forgotten.
+pub struct RouteHealth<K> {
+    config: RouteHealthConfig,
+    stats: HashMap<K, Stats>,
+}
+
+impl<K: Hash + Eq> RouteHealth<K> {
+    pub fn new(config: RouteHealthConfig) -> Self {
+        Self {
+            config,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// an execution of `route` landed (`reverted` false) or reverted
+    pub fn record(&mut self, route: K, reverted: bool, now: Instant) {
+        let config = self.config;
+        let stats = self.stats.entry(route).or_insert(Stats {
+            attempts: 0.0,
+            reverts: 0.0,
+            updated: now,
+            cooldown_until: None,
+            strikes: 0,
+        });
+        stats.decay(config.half_life, now);
+        stats.attempts += 1.0;
+        if !reverted {
+            stats.strikes = 0;
+            return;
+        }
+        stats.reverts += 1.0;
+        // reverts landing during a cooldown were sent before it
+        let benched = matches!(stats.cooldown_until, Some(until) if now < until);
+        if !benched
+            && stats.attempts >= config.min_attempts
+            && stats.reverts / stats.attempts >= config.cooldown_rate
+        {
+            let cooldown = config
+                .cooldown
+                .saturating_mul(1 << stats.strikes.min(16))
+                .min(config.max_cooldown);
+            stats.cooldown_until = Some(now + cooldown);
+            stats.strikes += 1;
+        }
+    }
+
+    pub fn standing(&self, route: &K, now: Instant) -> Standing {
+        let mut stats = match self.stats.get(route) {
+            Some(stats) => *stats,
+            None => return Standing::Active { success_rate: 1.0 },
+        };
+        if let Some(until) = stats.cooldown_until {
+            if now < until {
+                return Standing::Cooldown { until };
+            }
+        }
+        stats.decay(self.config.half_life, now);
+        if stats.attempts < self.config.min_attempts {
+            return Standing::Active { success_rate: 1.0 };
+        }
+        Standing::Active {
+            success_rate: 1.0 - stats.reverts / stats.attempts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_health_cooldown_and_decay() {
+        let config = RouteHealthConfig::default();
+        let mut health = RouteHealth::new(config);
+        let start = Instant::now();
+        let route = "USDC>WETH>USDC Polycat>Sushiswap";
+
+        health.record(route, true, start);
+        health.record(route, false, start);
+        // too little history to judge
+        assert_eq!(
+            health.standing(&route, start),
+            Standing::Active { success_rate: 1.0 }
+        );
+        health.record(route, true, start);
+        match health.standing(&route, start) {
+            Standing::Active { success_rate } => assert!((success_rate - 1.0 / 3.0).abs() < 1e-9),
+            standing => panic!("unexpected {:?}", standing),
+        }
+
+        health.record(route, true, start);
+        health.record(route, true, start);
+        let until = start + config.cooldown;
+        assert_eq!(health.standing(&route, start), Standing::Cooldown { until });
+
+        // history has decayed below `min_attempts` by the time it's back
+        let later = until + config.half_life;
+        assert_eq!(
+            health.standing(&route, later),
+            Standing::Active { success_rate: 1.0 }
+        );
+        // benched again straight away, for twice as long
+        for _ in 0..3 {
+            health.record(route, true, later);
+        }
+        assert_eq!(
+            health.standing(&route, later),
+            Standing::Cooldown {
+                until: later + config.cooldown * 2
+            }
+        );
+    }
+}
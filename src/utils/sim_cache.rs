@@ -0,0 +1,184 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ethers::types::H256;
+use tokio::sync::OnceCell;
+
+use super::quote_cache::QuoteCacheStats;
+
+/// a simulation of transaction `tx` on top of the state `state`, the hash
+/// or state root of the block it ran after
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SimKey {
+    pub tx: H256,
+    pub state: H256,
+}
+
+struct Entries<V> {
+    results: HashMap<SimKey, Arc<OnceCell<V>>>,
+    /// oldest first, for eviction
+    order: VecDeque<SimKey>,
+}
+
+/// Memoizes simulation results (`debug_traceCall` traces, revm executions)
+/// of a transaction against one state, so strategies evaluating the same
+/// pending transaction share one simulation. Callers asking for a
+/// simulation that is still running wait for it instead of starting
+/// another. Holds at most `capacity` results, oldest dropped first.
+pub struct SimCache<V> {
+    capacity: usize,
+    entries: Mutex<Entries<V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<V: Clone> SimCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries {
+                results: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn cell(&self, key: SimKey) -> Arc<OnceCell<V>> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(cell) = entries.results.get(&key) {
+            return cell.clone();
+        }
+        let cell = Arc::new(OnceCell::new());
+        entries.results.insert(key, cell.clone());
+        entries.order.push_back(key);
+        while entries.order.len() > self.capacity {
+            let oldest = entries.order.pop_front().unwrap();
+            entries.results.remove(&oldest);
+        }
+        cell
+    }
+
+    pub fn get(&self, key: SimKey) -> Option<V> {
+        let result = self
+            .entries
+            .lock()
+            .unwrap()
+            .results
+            .get(&key)
+            .and_then(|cell| cell.get().cloned());
+        match result {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    /// Cached result of `key`, or the result of `simulate` cached for it.
+    /// Errors aren't cached, the next caller simulates again.
+    pub async fn get_or_try_simulate<E, F, Fut>(&self, key: SimKey, simulate: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let cell = self.cell(key);
+        if let Some(result) = cell.get() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(result.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        cell.get_or_try_init(simulate).await.cloned()
+    }
+
+    /// drops every result not simulated against `state`, for when the chain
+    /// has moved past the others
+    pub fn retain_state(&self, state: H256) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.results.retain(|key, _| key.state == state);
+        entries.order.retain(|key| key.state == state);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// hits and misses since the last reset, counters are zeroed
+    pub fn reset_stats(&self) -> QuoteCacheStats {
+        QuoteCacheStats {
+            hits: self.hits.swap(0, Ordering::Relaxed),
+            misses: self.misses.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn key(tx: u64, state: u64) -> SimKey {
+        SimKey {
+            tx: H256::from_low_u64_be(tx),
+            state: H256::from_low_u64_be(state),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sim_cache() {
+        let cache = Arc::new(SimCache::new(2));
+        let runs = Arc::new(AtomicU64::new(0));
+        let simulate = |value: u64| {
+            let runs = runs.clone();
+            move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                Ok::<_, ()>(value)
+            }
+        };
+
+        // concurrent callers share one simulation
+        let (a, b) = tokio::join!(
+            cache.get_or_try_simulate(key(1, 1), simulate(10)),
+            cache.get_or_try_simulate(key(1, 1), simulate(11)),
+        );
+        assert_eq!((a, b), (Ok(10), Ok(10)));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(key(1, 1)), Some(10));
+        // same tx, other state
+        assert_eq!(cache.get(key(1, 2)), None);
+
+        // errors are retried
+        let failed = cache
+            .get_or_try_simulate(key(2, 1), || async { Err::<u64, _>("node down") })
+            .await;
+        assert_eq!(failed, Err("node down"));
+        assert_eq!(
+            cache.get_or_try_simulate(key(2, 1), simulate(20)).await,
+            Ok(20)
+        );
+
+        // over capacity, the oldest goes
+        cache
+            .get_or_try_simulate(key(3, 2), simulate(30))
+            .await
+            .unwrap();
+        assert_eq!(cache.get(key(1, 1)), None);
+        assert_eq!(cache.len(), 2);
+
+        cache.retain_state(H256::from_low_u64_be(2));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(key(3, 2)), Some(30));
+    }
+}
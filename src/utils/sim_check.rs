@@ -0,0 +1,295 @@
+//! Cross-checks the local revm simulator against the node. Every
+//! `sample_every`th local simulation is also run through `debug_traceCall`
+//! on the same block, and a local execution that disagrees with the node's
+//! (success, output, or gas past the tolerance) most likely hit a
+//! precompile or a bor quirk revm doesn't have. Each divergence is logged
+//! as an error, and after `divergences_to_fallback` of them the contract
+//! called is only simulated by the node.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, NameOrAddress},
+};
+use log::error;
+
+use crate::{
+    error::TransportError,
+    utils::{
+        local_sim::{LocalExecution, LocalSimulator},
+        serialize_structs::BlockTraceResult,
+        tracer::{DebugTraceExt, TraceConfig},
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimCheckConfig {
+    /// one in this many local simulations is checked, 0 checks none
+    pub sample_every: u64,
+    /// largest difference in gas used that isn't a divergence
+    pub gas_tolerance_bps: u64,
+    /// divergences of a contract before it's simulated by the node
+    pub divergences_to_fallback: u32,
+}
+
+impl Default for SimCheckConfig {
+    fn default() -> Self {
+        Self {
+            sample_every: 20,
+            gas_tolerance_bps: 500,
+            divergences_to_fallback: 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimBackend {
+    Local,
+    Node,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    Success { local: bool, node: bool },
+    Output { local: Bytes, node: Bytes },
+    Gas { local: u64, node: u64 },
+}
+
+/// how `local` differs from the `node` trace of the same txn, outputs only
+/// count when both succeeded
+pub fn compare(
+    local: &LocalExecution,
+    node: &BlockTraceResult,
+    gas_tolerance_bps: u64,
+) -> Option<Divergence> {
+    let node_success = node.error.is_none();
+    if local.is_success() != node_success {
+        return Some(Divergence::Success {
+            local: local.is_success(),
+            node: node_success,
+        });
+    }
+    let node_output = node.output.clone().unwrap_or_default();
+    if node_success && local.output != node_output {
+        return Some(Divergence::Output {
+            local: local.output.clone(),
+            node: node_output,
+        });
+    }
+    let node_gas = node.gas_used.low_u64();
+    if local.gas_used.abs_diff(node_gas) * 10_000 > node_gas * gas_tolerance_bps {
+        return Some(Divergence::Gas {
+            local: local.gas_used,
+            node: node_gas,
+        });
+    }
+    None
+}
+
+/// Divergences per contract and which are simulated by the node.
+#[derive(Debug, Default)]
+pub struct DivergenceTracker {
+    divergences: HashMap<Address, u32>,
+    fallback: HashSet<Address>,
+}
+
+impl DivergenceTracker {
+    /// Counts `divergence` of a txn to `contract`, returning whether the
+    /// contract falls back to the node now.
+    pub fn record(
+        &mut self,
+        contract: Address,
+        divergence: &Divergence,
+        divergences_to_fallback: u32,
+    ) -> bool {
+        let count = self.divergences.entry(contract).or_default();
+        *count += 1;
+        error!(
+            "Local simulation of a call to {:?} diverged from the node ({} so far): {:?}",
+            contract, count, divergence
+        );
+        if *count < divergences_to_fallback || !self.fallback.insert(contract) {
+            return false;
+        }
+        error!(
+            "Simulating calls to {:?} on the node from now on, revm disagrees with it",
+            contract
+        );
+        true
+    }
+
+    pub fn is_fallback(&self, contract: Address) -> bool {
+        self.fallback.contains(&contract)
+    }
+
+    pub fn divergences(&self, contract: Address) -> u32 {
+        self.divergences.get(&contract).copied().unwrap_or_default()
+    }
+}
+
+/// what a simulation came to, on either backend
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimResult {
+    pub backend: SimBackend,
+    pub success: bool,
+    pub gas_used: u64,
+    pub output: Bytes,
+}
+
+impl From<LocalExecution> for SimResult {
+    fn from(execution: LocalExecution) -> Self {
+        Self {
+            backend: SimBackend::Local,
+            success: execution.is_success(),
+            gas_used: execution.gas_used,
+            output: execution.output,
+        }
+    }
+}
+
+impl From<BlockTraceResult> for SimResult {
+    fn from(trace: BlockTraceResult) -> Self {
+        Self {
+            backend: SimBackend::Node,
+            success: trace.error.is_none(),
+            gas_used: trace.gas_used.low_u64(),
+            output: trace.output.unwrap_or_default(),
+        }
+    }
+}
+
+/// Simulates locally, checking a sample against the node and leaving the
+/// contracts revm got wrong to the node.
+pub struct CheckedSimulator<M> {
+    local: LocalSimulator<M>,
+    provider: Arc<M>,
+    config: SimCheckConfig,
+    simulations: u64,
+    tracker: DivergenceTracker,
+}
+
+impl<M: Middleware> CheckedSimulator<M> {
+    pub fn new(local: LocalSimulator<M>, provider: Arc<M>, config: SimCheckConfig) -> Self {
+        Self {
+            local,
+            provider,
+            config,
+            simulations: 0,
+            tracker: DivergenceTracker::default(),
+        }
+    }
+
+    pub fn local(&mut self) -> &mut LocalSimulator<M> {
+        &mut self.local
+    }
+
+    pub fn tracker(&self) -> &DivergenceTracker {
+        &self.tracker
+    }
+
+    async fn trace(&self, tx: &TypedTransaction) -> Result<BlockTraceResult, TransportError> {
+        let block = BlockNumber::Number(self.local.block_number().into());
+        Ok(self
+            .provider
+            .call_trace_call(tx, block, &TraceConfig::call_tracer(true))
+            .await?)
+    }
+
+    /// `tx` on the local simulator's block. The node simulates it when its
+    /// contract fell back or the local state didn't settle, and a sampled
+    /// txn that diverged gets the node's result.
+    pub async fn simulate(&mut self, tx: &TypedTransaction) -> Result<SimResult, TransportError> {
+        let contract = match tx.to() {
+            Some(NameOrAddress::Address(address)) => Some(*address),
+            _ => None,
+        };
+        if contract.is_some_and(|contract| self.tracker.is_fallback(contract)) {
+            return Ok(self.trace(tx).await?.into());
+        }
+        let local = match self
+            .local
+            .simulate(tx)
+            .await
+            .map_err(TransportError::middleware::<M>)?
+        {
+            Some(local) => local,
+            None => return Ok(self.trace(tx).await?.into()),
+        };
+        self.simulations += 1;
+        let sampled = self.config.sample_every > 0
+            && self.simulations.is_multiple_of(self.config.sample_every);
+        if !sampled {
+            return Ok(local.into());
+        }
+        let node = self.trace(tx).await?;
+        match compare(&local, &node, self.config.gas_tolerance_bps) {
+            Some(divergence) => {
+                if let Some(contract) = contract {
+                    self.tracker
+                        .record(contract, &divergence, self.config.divergences_to_fallback);
+                }
+                Ok(node.into())
+            }
+            None => Ok(local.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+    use revm::Return;
+
+    use super::*;
+
+    #[test]
+    fn test_divergence() {
+        let local = LocalExecution {
+            exit_reason: Return::Return,
+            gas_used: 100_000,
+            output: Bytes::from(vec![1]),
+            logs: vec![],
+        };
+        let node = BlockTraceResult {
+            gas_used: U256::from(102_000),
+            output: Some(Bytes::from(vec![1])),
+            ..Default::default()
+        };
+        assert_eq!(compare(&local, &node, 500), None);
+        assert_eq!(
+            compare(&local, &node, 100),
+            Some(Divergence::Gas {
+                local: 100_000,
+                node: 102_000
+            })
+        );
+        // a precompile revm lacks
+        let reverted = BlockTraceResult {
+            error: Some("execution reverted".to_string()),
+            ..node.clone()
+        };
+        assert_eq!(
+            compare(&local, &reverted, 500),
+            Some(Divergence::Success {
+                local: true,
+                node: false
+            })
+        );
+
+        let mut tracker = DivergenceTracker::default();
+        let contract = Address::repeat_byte(1);
+        let divergence = compare(&local, &reverted, 500).unwrap();
+        assert!(!tracker.record(contract, &divergence, 2));
+        assert!(!tracker.is_fallback(contract));
+        assert!(tracker.record(contract, &divergence, 2));
+        assert!(tracker.is_fallback(contract));
+        // reported once
+        assert!(!tracker.record(contract, &divergence, 2));
+        assert_eq!(tracker.divergences(contract), 3);
+        assert!(!tracker.is_fallback(Address::repeat_byte(2)));
+    }
+}
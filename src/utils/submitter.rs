@@ -0,0 +1,281 @@
+//! Cancelling the wallet's txns that got stuck in the mempool: a 0-value
+//! transfer to self with the same nonce, at a fee bumped enough for nodes
+//! to take it as a replacement. Whichever of the two is mined uses the
+//! nonce up; `reconcile` finds out which and keeps the nonce guard in step.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use ethers::{
+    prelude::SignerMiddleware,
+    providers::Middleware,
+    signers::Signer,
+    types::{BlockNumber, TransactionRequest, H256, U256},
+};
+use log::{error, info};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::utils::nonce_guard::NonceGuard;
+
+/// gas of a plain transfer
+const TRANSFER_GAS: u64 = 21_000;
+
+#[derive(Error, Debug)]
+pub enum SubmitError {
+    #[error("failed to send the cancellation of nonce {nonce}: {message}")]
+    Send { nonce: U256, message: String },
+
+    #[error("failed to check the cancellation of nonce {nonce}: {message}")]
+    Check { nonce: U256, message: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelStatus {
+    Pending,
+    /// the cancellation was mined
+    Cancelled,
+    /// the original was mined before the cancellation
+    Landed,
+    /// neither was mined, something else used the nonce
+    Replaced,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Cancellation {
+    pub nonce: U256,
+    /// the stuck txn, if it was tracked
+    pub original: Option<H256>,
+    pub hash: H256,
+    pub gas_price: U256,
+    pub status: CancelStatus,
+}
+
+/// Cancels txns of `client`'s wallet, handing their nonces back to the
+/// guard the wallet's other txns are sent through.
+pub struct Submitter<M, S> {
+    client: Arc<SignerMiddleware<M, S>>,
+    nonces: Arc<NonceGuard>,
+    /// fee increase over the stuck txn, nodes want at least 10%
+    bump_bps: u64,
+    /// stuck txns by nonce, with their gas price
+    stuck: Mutex<HashMap<U256, (H256, U256)>>,
+    cancellations: Mutex<HashMap<U256, Cancellation>>,
+}
+
+impl<M: Middleware, S: Signer> Submitter<M, S> {
+    pub fn new(client: Arc<SignerMiddleware<M, S>>, nonces: Arc<NonceGuard>) -> Self {
+        Self {
+            client,
+            nonces,
+            bump_bps: 1_250,
+            stuck: Mutex::new(HashMap::new()),
+            cancellations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_bump_bps(mut self, bump_bps: u64) -> Self {
+        self.bump_bps = bump_bps;
+        self
+    }
+
+    /// `hash` went out with `nonce` at `gas_price`, a cancellation has to
+    /// outbid it
+    pub fn track(&self, nonce: U256, hash: H256, gas_price: U256) {
+        self.stuck.lock().unwrap().insert(nonce, (hash, gas_price));
+    }
+
+    /// whether a cancellation of `hash` is pending
+    pub fn is_cancelling(&self, hash: H256) -> bool {
+        self.cancellations
+            .lock()
+            .unwrap()
+            .values()
+            .any(|cancellation| cancellation.original == Some(hash))
+    }
+
+    /// cancellations not mined yet
+    pub fn pending(&self) -> Vec<Cancellation> {
+        self.cancellations
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces whatever holds `nonce` with a 0-value transfer to self, at
+    /// the node's gas price or the bumped price of the txn it replaces,
+    /// whichever is higher. Cancelling again outbids the last cancellation.
+    pub async fn cancel(&self, nonce: U256) -> Result<Cancellation, SubmitError> {
+        let error = |message: String| SubmitError::Send { nonce, message };
+        let original = self.stuck.lock().unwrap().get(&nonce).copied();
+        let previous = self
+            .cancellations
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .map(|cancellation| cancellation.gas_price);
+        let outbid = previous.or(original.map(|(_, gas_price)| gas_price));
+        let node_price = self
+            .client
+            .get_gas_price()
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        let gas_price = match outbid {
+            Some(outbid) => node_price.max(outbid * (10_000 + self.bump_bps) / 10_000),
+            None => node_price * (10_000 + self.bump_bps) / 10_000,
+        };
+
+        let wallet = self.nonces.wallet();
+        let tx = TransactionRequest::new()
+            .from(wallet)
+            .to(wallet)
+            .value(0)
+            .nonce(nonce)
+            .gas(TRANSFER_GAS)
+            .gas_price(gas_price);
+        let hash = match self.client.send_transaction(tx, None).await {
+            Ok(pending) => *pending,
+            Err(e) => {
+                // most likely mined already, the count is off either way
+                if let Err(e) = self.nonces.resync(self.client.as_ref()).await {
+                    error!("Failed to resync wallet nonce: {:?}", e);
+                }
+                return Err(error(e.to_string()));
+            }
+        };
+        // ours, not an external use of the wallet
+        self.nonces.sent(nonce, hash);
+        let cancellation = Cancellation {
+            nonce,
+            original: original.map(|(hash, _)| hash),
+            hash,
+            gas_price,
+            status: CancelStatus::Pending,
+        };
+        self.cancellations
+            .lock()
+            .unwrap()
+            .insert(nonce, cancellation.clone());
+        Ok(cancellation)
+    }
+
+    /// Checks the pending cancellations against the chain, returning the
+    /// ones that resolved; those are forgotten. A nonce used by neither
+    /// txn resyncs the guard.
+    pub async fn reconcile(&self) -> Result<Vec<Cancellation>, SubmitError> {
+        let mut resolved = Vec::new();
+        for mut cancellation in self.pending() {
+            let nonce = cancellation.nonce;
+            let error = |e: &dyn std::fmt::Display| SubmitError::Check {
+                nonce,
+                message: e.to_string(),
+            };
+            let mined = |hash| async move {
+                self.client
+                    .get_transaction_receipt(hash)
+                    .await
+                    .map(|receipt| receipt.is_some())
+            };
+            cancellation.status = if mined(cancellation.hash).await.map_err(|e| error(&e))? {
+                CancelStatus::Cancelled
+            } else if let Some(original) = cancellation.original {
+                match mined(original).await.map_err(|e| error(&e))? {
+                    true => CancelStatus::Landed,
+                    false => CancelStatus::Pending,
+                }
+            } else {
+                CancelStatus::Pending
+            };
+            if cancellation.status == CancelStatus::Pending {
+                let mined_count = self
+                    .client
+                    .get_transaction_count(self.nonces.wallet(), Some(BlockNumber::Latest.into()))
+                    .await
+                    .map_err(|e| error(&e))?;
+                if mined_count <= nonce {
+                    continue;
+                }
+                cancellation.status = CancelStatus::Replaced;
+                if let Err(e) = self.nonces.resync(self.client.as_ref()).await {
+                    error!("Failed to resync wallet nonce: {:?}", e);
+                }
+            }
+            info!(
+                "Cancellation {:?} of nonce {} resolved: {:?}",
+                cancellation.hash, nonce, cancellation.status
+            );
+            self.cancellations.lock().unwrap().remove(&nonce);
+            self.stuck.lock().unwrap().remove(&nonce);
+            resolved.push(cancellation);
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        signers::LocalWallet,
+        types::{Address, TransactionReceipt},
+    };
+
+    use super::*;
+    use crate::utils::batch::fake::FakeTransport;
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64);
+        let nonces = Arc::new(NonceGuard::new(wallet.address()));
+        let transport = FakeTransport::new();
+        let client = Arc::new(SignerMiddleware::new(
+            Provider::new(transport.clone()),
+            wallet,
+        ));
+        let submitter = Submitter::new(client, nonces.clone());
+
+        let stuck = H256::repeat_byte(1);
+        submitter.track(7.into(), stuck, 100.into());
+        transport.set_response("eth_gasPrice", "0x64");
+        transport.push_response("eth_sendRawTransaction", H256::repeat_byte(2));
+        let cancellation = submitter.cancel(7.into()).await.unwrap();
+        assert_eq!(cancellation.original, Some(stuck));
+        assert_eq!(cancellation.hash, H256::repeat_byte(2));
+        // 12.5% over the stuck txn
+        assert_eq!(cancellation.gas_price, 112.into());
+        // the cancellation isn't taken for someone else using the wallet
+        assert!(!nonces.observe(&ethers::types::Transaction {
+            from: nonces.wallet(),
+            hash: cancellation.hash,
+            nonce: 7.into(),
+            ..Default::default()
+        }));
+
+        // nothing mined yet
+        transport.push_response("eth_getTransactionReceipt", ());
+        transport.push_response("eth_getTransactionReceipt", ());
+        transport.push_response("eth_getTransactionCount", "0x7");
+        assert!(submitter.reconcile().await.unwrap().is_empty());
+        assert_eq!(submitter.pending().len(), 1);
+
+        transport.push_response(
+            "eth_getTransactionReceipt",
+            TransactionReceipt {
+                transaction_hash: cancellation.hash,
+                from: Address::zero(),
+                ..Default::default()
+            },
+        );
+        let resolved = submitter.reconcile().await.unwrap();
+        assert_eq!(resolved[0].status, CancelStatus::Cancelled);
+        assert!(submitter.pending().is_empty());
+    }
+}
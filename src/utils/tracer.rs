@@ -0,0 +1,488 @@
+//! Typed `debug_trace*` requests using the callTracer and prestateTracer, the
+//! ethers equivalents return untyped json.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Middleware, ProviderError},
+    types::{Address, BlockNumber, Bytes, H256, I256, U256},
+    utils::{self, keccak256},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub use super::serialize_structs::{BlockTraceResult, Res, TraceConfig, TracerConfig};
+
+impl TraceConfig {
+    /// callTracer without storage, stack or memory capture
+    pub fn call_tracer(only_top_call: bool) -> Self {
+        TraceConfig {
+            disable_storage: true,
+            disable_stack: true,
+            enable_memory: false,
+            enable_return_data: false,
+            tracer: "callTracer".to_string(),
+            tracer_config: Some(TracerConfig {
+                only_top_call,
+                with_log: false,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PrestateTraceConfig {
+    pub tracer: String,
+    pub tracer_config: PrestateTracerConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PrestateTracerConfig {
+    pub diff_mode: bool,
+}
+
+impl PrestateTraceConfig {
+    pub fn new(diff_mode: bool) -> Self {
+        PrestateTraceConfig {
+            tracer: "prestateTracer".to_string(),
+            tracer_config: PrestateTracerConfig { diff_mode },
+        }
+    }
+}
+
+/// Account as reported by the prestateTracer, fields the txn doesn't touch
+/// are left out.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct AccountState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<H256, H256>>,
+}
+
+/// Output of the prestateTracer in diff mode. `pre` holds the modified
+/// fields before the txn and `post` after, storage slots missing on one side
+/// are zero on that side.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StateDiff {
+    pub pre: HashMap<Address, AccountState>,
+    pub post: HashMap<Address, AccountState>,
+}
+
+impl StateDiff {
+    /// change of the native balance of `address`
+    pub fn balance_delta(&self, address: Address) -> I256 {
+        let before = self.pre.get(&address).and_then(|a| a.balance);
+        let after = self.post.get(&address).and_then(|a| a.balance);
+        match (before, after) {
+            (Some(before), Some(after)) => I256::from_raw(after) - I256::from_raw(before),
+            (None, Some(after)) => I256::from_raw(after),
+            _ => I256::zero(),
+        }
+    }
+
+    /// nonce after the txn if it changed
+    pub fn nonce_change(&self, address: Address) -> Option<(u64, u64)> {
+        let after = self.post.get(&address)?.nonce?;
+        let before = self.pre.get(&address).and_then(|a| a.nonce).unwrap_or(0);
+        Some((before, after))
+    }
+
+    /// `(slot, before, after)` of every storage slot of `address` the txn wrote
+    pub fn storage_changes(&self, address: Address) -> Vec<(H256, H256, H256)> {
+        let empty = BTreeMap::new();
+        let pre = self
+            .pre
+            .get(&address)
+            .and_then(|a| a.storage.as_ref())
+            .unwrap_or(&empty);
+        let post = self
+            .post
+            .get(&address)
+            .and_then(|a| a.storage.as_ref())
+            .unwrap_or(&empty);
+        let slots: BTreeSet<&H256> = pre.keys().chain(post.keys()).collect();
+        slots
+            .into_iter()
+            .map(|slot| {
+                let before = pre.get(slot).copied().unwrap_or_default();
+                let after = post.get(slot).copied().unwrap_or_default();
+                (*slot, before, after)
+            })
+            .filter(|(_, before, after)| before != after)
+            .collect()
+    }
+
+    /// Change of `holder`'s balance of an erc20 storing balances in a
+    /// `mapping(address => uint256)` at storage slot `balances_slot`.
+    pub fn token_balance_delta(&self, token: Address, holder: Address, balances_slot: u64) -> I256 {
+        let slot = mapping_slot(holder, balances_slot);
+        self.storage_changes(token)
+            .into_iter()
+            .find(|(changed, _, _)| *changed == slot)
+            .map(|(_, before, after)| {
+                I256::from_raw(U256::from(after.as_bytes()))
+                    - I256::from_raw(U256::from(before.as_bytes()))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// storage slot of `key` in a solidity mapping declared at slot `slot`
+pub fn mapping_slot(key: Address, slot: u64) -> H256 {
+    let mut preimage = [0u8; 64];
+    preimage[12..32].copy_from_slice(key.as_bytes());
+    U256::from(slot).to_big_endian(&mut preimage[32..]);
+    H256::from(keccak256(preimage))
+}
+
+/// Custom javascript tracer, `code` is the tracer object geth evaluates for
+/// every step / call frame of the traced txn.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct JsTracer {
+    #[serde(rename = "tracer")]
+    pub code: String,
+    /// eg. "10s", geth defaults to 5s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+}
+
+/// call frame collected by `JsTracer::calls_to`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TracedCall {
+    pub r#type: String,
+    pub from: Address,
+    pub to: Address,
+    pub input: Bytes,
+    pub value: U256,
+}
+
+impl JsTracer {
+    pub fn new(code: impl Into<String>) -> Self {
+        JsTracer {
+            code: code.into(),
+            timeout: None,
+        }
+    }
+
+    /// Collects every call into `target`, including the top level call and
+    /// delegate/static calls. Decodes into `Vec<TracedCall>`.
+    pub fn calls_to(target: Address) -> Self {
+        Self::new(format!(
+            r#"{{
+    target: "{:?}",
+    calls: [],
+    record: function(type, from, to, input, value) {{
+        if (toHex(to) != this.target) return;
+        this.calls.push({{
+            type: type,
+            from: toHex(from),
+            to: toHex(to),
+            input: toHex(input),
+            value: value === undefined ? "0x0" : "0x" + value.toString(16)
+        }});
+    }},
+    enter: function(frame) {{
+        this.record(frame.getType(), frame.getFrom(), frame.getTo(), frame.getInput(), frame.getValue());
+    }},
+    exit: function(res) {{}},
+    fault: function(log, db) {{}},
+    result: function(ctx, db) {{
+        // enter isn't called for the top level call
+        var calls = this.calls;
+        this.calls = [];
+        this.record(ctx.type, ctx.from, ctx.to, ctx.input, ctx.value);
+        return this.calls.concat(calls);
+    }}
+}}"#,
+            target
+        ))
+    }
+
+    /// Captures the value of each of `slots` of `contract` at every SLOAD and
+    /// SSTORE, in execution order. Decodes into `HashMap<H256, Vec<H256>>`.
+    pub fn storage_slots(contract: Address, slots: &[H256]) -> Self {
+        let slots: Vec<String> = slots
+            .iter()
+            .map(|slot| format!("\"{:?}\": []", slot))
+            .collect();
+        Self::new(format!(
+            r#"{{
+    contract: "{:?}",
+    values: {{{}}},
+    step: function(log, db) {{
+        var op = log.op.toString();
+        if (op != "SLOAD" && op != "SSTORE") return;
+        if (toHex(log.contract.getAddress()) != this.contract) return;
+        var key = toWord("0x" + log.stack.peek(0).toString(16));
+        var slot = toHex(key);
+        if (this.values[slot] === undefined) return;
+        if (op == "SSTORE") {{
+            this.values[slot].push(toHex(toWord("0x" + log.stack.peek(1).toString(16))));
+        }} else {{
+            this.values[slot].push(toHex(db.getState(log.contract.getAddress(), key)));
+        }}
+    }},
+    fault: function(log, db) {{}},
+    result: function(ctx, db) {{ return this.values; }}
+}}"#,
+            contract,
+            slots.join(", ")
+        ))
+    }
+}
+
+/// debug_trace* extensions, implemented for every middleware
+#[async_trait]
+pub trait DebugTraceExt: Middleware {
+    /// Traces every transaction of an rlp encoded block, the block doesn't
+    /// have to be canonical so this is how simulated blocks get executed.
+    async fn call_trace_block(
+        &self,
+        block_rlp: Bytes,
+        config: &TraceConfig,
+    ) -> Result<Vec<BlockTraceResult>, ProviderError> {
+        let block_rlp = utils::serialize(&block_rlp);
+        let config = utils::serialize(config);
+        let results: Vec<Res> = self
+            .provider()
+            .request("debug_traceBlock", [block_rlp, config])
+            .await?;
+        Ok(results.into_iter().map(|res| res.result).collect())
+    }
+
+    async fn call_trace_block_by_number(
+        &self,
+        block: BlockNumber,
+        config: &TraceConfig,
+    ) -> Result<Vec<BlockTraceResult>, ProviderError> {
+        let block = utils::serialize(&block);
+        let config = utils::serialize(config);
+        let results: Vec<Res> = self
+            .provider()
+            .request("debug_traceBlockByNumber", [block, config])
+            .await?;
+        Ok(results.into_iter().map(|res| res.result).collect())
+    }
+
+    async fn call_trace_transaction(
+        &self,
+        tx_hash: H256,
+        config: &TraceConfig,
+    ) -> Result<BlockTraceResult, ProviderError> {
+        let tx_hash = utils::serialize(&tx_hash);
+        let config = utils::serialize(config);
+        self.provider()
+            .request("debug_traceTransaction", [tx_hash, config])
+            .await
+    }
+
+    /// Executes `tx` on top of `block` without sending it, `tx` is anything
+    /// serializing to a call object, eg. a `TypedTransaction`.
+    async fn call_trace_call<T: Serialize + Send + Sync>(
+        &self,
+        tx: &T,
+        block: BlockNumber,
+        config: &TraceConfig,
+    ) -> Result<BlockTraceResult, ProviderError> {
+        let tx = utils::serialize(tx);
+        let block = utils::serialize(&block);
+        let config = utils::serialize(config);
+        self.provider()
+            .request("debug_traceCall", [tx, block, config])
+            .await
+    }
+
+    /// state of every account `tx` touches, before it executes
+    async fn prestate_trace_call<T: Serialize + Send + Sync>(
+        &self,
+        tx: &T,
+        block: BlockNumber,
+    ) -> Result<HashMap<Address, AccountState>, ProviderError> {
+        let tx = utils::serialize(tx);
+        let block = utils::serialize(&block);
+        let config = utils::serialize(&PrestateTraceConfig::new(false));
+        self.provider()
+            .request("debug_traceCall", [tx, block, config])
+            .await
+    }
+
+    async fn state_diff_trace_call<T: Serialize + Send + Sync>(
+        &self,
+        tx: &T,
+        block: BlockNumber,
+    ) -> Result<StateDiff, ProviderError> {
+        let tx = utils::serialize(tx);
+        let block = utils::serialize(&block);
+        let config = utils::serialize(&PrestateTraceConfig::new(true));
+        self.provider()
+            .request("debug_traceCall", [tx, block, config])
+            .await
+    }
+
+    /// runs a custom js tracer over `tx`, `R` is whatever its `result` returns
+    async fn js_trace_call<
+        T: Serialize + Send + Sync,
+        R: Serialize + DeserializeOwned + fmt::Debug + Send,
+    >(
+        &self,
+        tx: &T,
+        block: BlockNumber,
+        tracer: &JsTracer,
+    ) -> Result<R, ProviderError> {
+        let tx = utils::serialize(tx);
+        let block = utils::serialize(&block);
+        let tracer = utils::serialize(tracer);
+        self.provider()
+            .request("debug_traceCall", [tx, block, tracer])
+            .await
+    }
+
+    async fn js_trace_transaction<R: Serialize + DeserializeOwned + fmt::Debug + Send>(
+        &self,
+        tx_hash: H256,
+        tracer: &JsTracer,
+    ) -> Result<R, ProviderError> {
+        let tx_hash = utils::serialize(&tx_hash);
+        let tracer = utils::serialize(tracer);
+        self.provider()
+            .request("debug_traceTransaction", [tx_hash, tracer])
+            .await
+    }
+
+    async fn state_diff_trace_transaction(
+        &self,
+        tx_hash: H256,
+    ) -> Result<StateDiff, ProviderError> {
+        let tx_hash = utils::serialize(&tx_hash);
+        let config = utils::serialize(&PrestateTraceConfig::new(true));
+        self.provider()
+            .request("debug_traceTransaction", [tx_hash, config])
+            .await
+    }
+}
+
+impl<M: Middleware> DebugTraceExt for M {}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{providers::Provider, types::TransactionRequest};
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::batch::fake::FakeTransport;
+
+    #[test]
+    fn test_token_balance_delta() {
+        let token = Address::random();
+        let holder = Address::random();
+        let slot = mapping_slot(holder, 0);
+        let other_slot = mapping_slot(Address::random(), 0);
+
+        let diff = StateDiff {
+            pre: HashMap::from([(
+                token,
+                AccountState {
+                    storage: Some(BTreeMap::from([
+                        (slot, H256::from_low_u64_be(100)),
+                        (other_slot, H256::from_low_u64_be(5)),
+                    ])),
+                    ..Default::default()
+                },
+            )]),
+            post: HashMap::from([(
+                token,
+                AccountState {
+                    storage: Some(BTreeMap::from([(slot, H256::from_low_u64_be(40))])),
+                    ..Default::default()
+                },
+            )]),
+        };
+        assert_eq!(diff.token_balance_delta(token, holder, 0), I256::from(-60));
+        // slot missing from post was cleared
+        assert_eq!(diff.storage_changes(token).len(), 2);
+        assert_eq!(diff.balance_delta(holder), I256::zero());
+    }
+
+    #[tokio::test]
+    async fn test_js_tracers() {
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        let target = Address::repeat_byte(0xaa);
+        let tx = TransactionRequest::new().to(target);
+
+        // what geth returns for a call into target and a delegatecall it makes
+        // back into itself
+        transport.push_response(
+            "debug_traceCall",
+            json!([
+                {
+                    "type": "CALL",
+                    "from": format!("{:?}", Address::repeat_byte(1)),
+                    "to": format!("{:?}", target),
+                    "input": "0x12345678",
+                    "value": "0x0"
+                },
+                {
+                    "type": "DELEGATECALL",
+                    "from": format!("{:?}", target),
+                    "to": format!("{:?}", target),
+                    "input": "0x",
+                    "value": "0xde0b6b3a7640000"
+                }
+            ]),
+        );
+        let calls: Vec<TracedCall> = provider
+            .js_trace_call(&tx, BlockNumber::Latest, &JsTracer::calls_to(target))
+            .await
+            .unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].r#type, "CALL");
+        assert_eq!(calls[0].input, Bytes::from(vec![0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(calls[1].from, target);
+        assert_eq!(calls[1].value, U256::exp10(18));
+        let (method, params) = &transport.requests()[0];
+        assert_eq!(method, "debug_traceCall");
+        assert_eq!(params[1], json!("latest"));
+        // toHex is lowercase, so is the target it's compared with
+        let code = params[2]["tracer"].as_str().unwrap();
+        assert!(code.contains(&format!("target: \"{:?}\"", target)));
+        assert!(params[2].get("timeout").is_none());
+
+        let slot = H256::from_low_u64_be(8);
+        let untouched = H256::from_low_u64_be(9);
+        let tracer = JsTracer::storage_slots(target, &[slot, untouched]);
+        assert!(tracer
+            .code
+            .contains(&format!("\"{:?}\": [], \"{:?}\": []", slot, untouched)));
+        transport.push_response(
+            "debug_traceTransaction",
+            json!({
+                format!("{:?}", slot): [
+                    format!("{:?}", H256::from_low_u64_be(1)),
+                    format!("{:?}", H256::from_low_u64_be(2))
+                ],
+                format!("{:?}", untouched): []
+            }),
+        );
+        let values: HashMap<H256, Vec<H256>> = provider
+            .js_trace_transaction(H256::repeat_byte(3), &tracer)
+            .await
+            .unwrap();
+        assert_eq!(
+            values[&slot],
+            vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]
+        );
+        assert!(values[&untouched].is_empty());
+        assert_eq!(transport.requests()[1].1[0], json!(H256::repeat_byte(3)));
+    }
+}
@@ -0,0 +1,128 @@
+//! `eth_createAccessList` helpers, pre-warming the pools and storage slots an
+//! arb touches saves gas on every cold access after the first
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        transaction::{
+            eip2718::TypedTransaction as EthersTypedTransaction, eip2930::AccessListWithGasUsed,
+        },
+        Address, BlockId,
+    },
+};
+
+use super::TypedTransactionRequest;
+
+/// Runs `eth_createAccessList` for `request` sent by `from` and attaches the
+/// resulting list. Legacy requests can't carry one and are left untouched.
+pub async fn attach_access_list<M: Middleware>(
+    provider: &M,
+    request: &mut TypedTransactionRequest,
+    from: Address,
+    block: Option<BlockId>,
+) -> Result<AccessListWithGasUsed, M::Error> {
+    let mut tx: EthersTypedTransaction = request.clone().into();
+    tx.set_from(from);
+    let result = provider.create_access_list(&tx, block).await?;
+    match request {
+        TypedTransactionRequest::Legacy(_) => {}
+        TypedTransactionRequest::EIP2930(request) => {
+            request.access_list = result.access_list.0.clone()
+        }
+        TypedTransactionRequest::EIP1559(request) => {
+            request.access_list = result.access_list.0.clone()
+        }
+    }
+    Ok(result)
+}
+
+/// same as `attach_access_list` for ethers transactions, eg. `ContractCall::tx`
+pub async fn attach_access_list_ethers<M: Middleware>(
+    provider: &M,
+    tx: &mut EthersTypedTransaction,
+    block: Option<BlockId>,
+) -> Result<AccessListWithGasUsed, M::Error> {
+    let result = provider.create_access_list(tx, block).await?;
+    if !matches!(tx, EthersTypedTransaction::Legacy(_)) {
+        tx.set_access_list(result.access_list.clone());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        types::{
+            transaction::eip2930::AccessListItem, Bytes, Eip1559TransactionRequest, H256, U256,
+        },
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::{
+        batch::fake::FakeTransport,
+        transaction::{EIP1559TransactionRequest, LegacyTransactionRequest, TransactionKind},
+    };
+
+    #[tokio::test]
+    async fn test_attach_access_list() {
+        let pool = Address::repeat_byte(0x50);
+        let from = Address::repeat_byte(1);
+        let item = AccessListItem {
+            address: pool,
+            storage_keys: vec![H256::from_low_u64_be(8)],
+        };
+        let transport = FakeTransport::new();
+        transport.set_response(
+            "eth_createAccessList",
+            json!({ "accessList": [item], "gasUsed": "0x1d4c0" }),
+        );
+        let provider = Provider::new(transport.clone());
+
+        let mut request = TypedTransactionRequest::EIP1559(EIP1559TransactionRequest {
+            chain_id: 137,
+            nonce: U256::zero(),
+            max_priority_fee_per_gas: U256::zero(),
+            max_fee_per_gas: U256::zero(),
+            gas_limit: 500_000.into(),
+            kind: TransactionKind::Call(pool),
+            value: U256::zero(),
+            input: Bytes::default(),
+            access_list: Vec::new(),
+        });
+        let result = attach_access_list(&provider, &mut request, from, None)
+            .await
+            .unwrap();
+        assert_eq!(result.gas_used, 120_000.into());
+        match &request {
+            TypedTransactionRequest::EIP1559(request) => {
+                assert_eq!(request.access_list, vec![item.clone()])
+            }
+            _ => unreachable!(),
+        }
+        // asked as sent by `from`
+        assert_eq!(transport.requests()[0].1[0]["from"], json!(from));
+
+        let legacy = LegacyTransactionRequest {
+            nonce: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 500_000.into(),
+            kind: TransactionKind::Call(pool),
+            value: U256::zero(),
+            input: Bytes::default(),
+            chain_id: Some(137),
+        };
+        let mut request = TypedTransactionRequest::Legacy(legacy.clone());
+        attach_access_list(&provider, &mut request, from, None)
+            .await
+            .unwrap();
+        assert_eq!(request, TypedTransactionRequest::Legacy(legacy));
+
+        let mut tx: EthersTypedTransaction = Eip1559TransactionRequest::new().to(pool).into();
+        attach_access_list_ethers(&provider, &mut tx, None)
+            .await
+            .unwrap();
+        assert_eq!(tx.access_list().unwrap().0, vec![item]);
+    }
+}
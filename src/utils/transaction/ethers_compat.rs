@@ -1,7 +1,7 @@
 //! ethers compatibility, this is mainly necessary so we can use all of `ethers` signers
 
 use super::{
-    EIP1559Transaction, EIP1559TransactionRequest, EIP2930TransactionRequest,
+    EIP1559Transaction, EIP1559TransactionRequest, EIP2930Transaction, EIP2930TransactionRequest,
     EthTransactionRequest, LegacyTransaction, LegacyTransactionRequest, TransactionKind,
     TypedTransaction, TypedTransactionRequest,
 };
@@ -101,6 +101,29 @@ impl From<EthersTransaction> for TypedTransaction {
             None => TransactionKind::Create,
         };
 
+        if transaction.transaction_type == Some(U64::one()) {
+            return TypedTransaction::EIP2930(EIP2930Transaction {
+                chain_id: 137,
+                nonce: transaction.nonce,
+                gas_price: transaction.gas_price.unwrap(),
+                gas_limit: transaction.gas,
+                kind,
+                value: transaction.value,
+                input: transaction.input,
+                access_list: transaction.access_list.unwrap_or_default(),
+                odd_y_parity: transaction.v == U64::one(),
+                r: {
+                    let mut rarr = [0u8; 32];
+                    transaction.r.to_big_endian(&mut rarr);
+                    H256::from(rarr)
+                },
+                s: {
+                    let mut sarr = [0u8; 32];
+                    transaction.s.to_big_endian(&mut sarr);
+                    H256::from(sarr)
+                },
+            });
+        }
         if let Some(_) = transaction.max_fee_per_gas {
             let parity = if transaction.v == U64::one() {
                 true
@@ -157,7 +180,7 @@ impl From<TypedTransaction> for EthersTransaction {
                 block_number: None,
                 transaction_index: None,
                 from: Address::default(),
-                to: None,
+                to: t.kind.as_call().copied(),
                 value: t.value,
                 gas_price: Some(t.gas_price),
                 max_fee_per_gas: Some(t.gas_price),
@@ -179,7 +202,7 @@ impl From<TypedTransaction> for EthersTransaction {
                 block_number: None,
                 transaction_index: None,
                 from: Address::default(),
-                to: None,
+                to: t.kind.as_call().copied(),
                 value: t.value,
                 gas_price: Some(t.gas_price),
                 max_fee_per_gas: Some(t.gas_price),
@@ -201,7 +224,7 @@ impl From<TypedTransaction> for EthersTransaction {
                 block_number: None,
                 transaction_index: None,
                 from: Address::default(),
-                to: None,
+                to: t.kind.as_call().copied(),
                 value: t.value,
                 gas_price: None,
                 max_fee_per_gas: Some(t.max_fee_per_gas),
@@ -220,6 +243,41 @@ impl From<TypedTransaction> for EthersTransaction {
     }
 }
 
+impl From<EthersTypedTransactionRequest> for EthTransactionRequest {
+    fn from(tx: EthersTypedTransactionRequest) -> Self {
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas, access_list, transaction_type) =
+            match &tx {
+                EthersTypedTransactionRequest::Legacy(t) => (t.gas_price, None, None, None, 0u64),
+                EthersTypedTransactionRequest::Eip2930(t) => {
+                    (t.tx.gas_price, None, None, Some(t.access_list.0.clone()), 1)
+                }
+                EthersTypedTransactionRequest::Eip1559(t) => (
+                    None,
+                    t.max_fee_per_gas,
+                    t.max_priority_fee_per_gas,
+                    Some(t.access_list.0.clone()),
+                    2,
+                ),
+            };
+        EthTransactionRequest {
+            from: tx.from().copied(),
+            to: tx.to().and_then(|to| match to {
+                NameOrAddress::Name(_) => None,
+                NameOrAddress::Address(to) => Some(*to),
+            }),
+            gas_price,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            gas: tx.gas().copied(),
+            value: tx.value().copied(),
+            data: tx.data().cloned(),
+            nonce: tx.nonce().copied(),
+            access_list,
+            transaction_type: Some(transaction_type.into()),
+        }
+    }
+}
+
 impl From<TransactionRequest> for EthTransactionRequest {
     fn from(req: TransactionRequest) -> Self {
         let TransactionRequest {
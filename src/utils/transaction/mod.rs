@@ -1,8 +1,13 @@
 //! transaction related data
 
 use ethers::{
+    prelude::k256::ecdsa::SigningKey,
+    signers::{Signer, Wallet},
     types::{
-        transaction::eip2930::{AccessList, AccessListItem},
+        transaction::{
+            eip2718::TypedTransaction as EthersTypedTransaction,
+            eip2930::{AccessList, AccessListItem},
+        },
         Address, Bytes, Signature, SignatureError, H256, U256,
     },
     utils::{
@@ -12,8 +17,10 @@ use ethers::{
 };
 use serde::{Deserialize, Serialize};
 
+pub mod access_list;
 /// compatibility with `ethers-rs` types
 mod ethers_compat;
+pub mod offline;
 
 pub fn enveloped<T: Encodable>(id: u8, v: &T, s: &mut RlpStream) {
     let encoded = rlp::encode(v);
@@ -84,61 +91,64 @@ impl EthTransactionRequest {
             value,
             data,
             nonce,
-            mut access_list,
+            access_list,
+            transaction_type,
             ..
         } = self;
-        match (gas_price, max_fee_per_gas, access_list.take()) {
+        let kind = match to {
+            Some(to) => TransactionKind::Call(to),
+            None => TransactionKind::Create,
+        };
+        // an explicit type wins, otherwise infer it from the fee fields
+        let transaction_type = match transaction_type {
+            Some(transaction_type) => transaction_type.as_u64(),
+            None => match (gas_price, max_fee_per_gas, &access_list) {
+                (Some(_), None, None) => 0,
+                (_, None, Some(_)) => 1,
+                // Empty fields fall back to the canonical transaction schema.
+                (None, Some(_), _) | (None, None, None) => 2,
+                _ => return None,
+            },
+        };
+        match transaction_type {
             // legacy transaction
-            (Some(_), None, None) => {
-                Some(TypedTransactionRequest::Legacy(LegacyTransactionRequest {
+            0 => Some(TypedTransactionRequest::Legacy(LegacyTransactionRequest {
+                nonce: nonce.unwrap_or(U256::zero()),
+                gas_price: gas_price.unwrap_or_default(),
+                gas_limit: gas.unwrap_or_default(),
+                value: value.unwrap_or(U256::zero()),
+                input: data.unwrap_or_default(),
+                kind,
+                // replay protected, see EIP-155
+                chain_id: Some(137),
+            })),
+            // EIP2930
+            1 => Some(TypedTransactionRequest::EIP2930(
+                EIP2930TransactionRequest {
                     nonce: nonce.unwrap_or(U256::zero()),
                     gas_price: gas_price.unwrap_or_default(),
                     gas_limit: gas.unwrap_or_default(),
                     value: value.unwrap_or(U256::zero()),
                     input: data.unwrap_or_default(),
-                    kind: match to {
-                        Some(to) => TransactionKind::Call(to),
-                        None => TransactionKind::Create,
-                    },
-                    chain_id: None,
-                }))
-            }
-            // EIP2930
-            (_, None, Some(access_list)) => Some(TypedTransactionRequest::EIP2930(
-                EIP2930TransactionRequest {
+                    kind,
+                    chain_id: 137,
+                    access_list: access_list.unwrap_or_default(),
+                },
+            )),
+            // EIP1559
+            2 => Some(TypedTransactionRequest::EIP1559(
+                EIP1559TransactionRequest {
                     nonce: nonce.unwrap_or(U256::zero()),
-                    gas_price: gas_price.unwrap_or_default(),
+                    max_fee_per_gas: max_fee_per_gas.unwrap_or_default(),
+                    max_priority_fee_per_gas: max_priority_fee_per_gas.unwrap_or(U256::zero()),
                     gas_limit: gas.unwrap_or_default(),
                     value: value.unwrap_or(U256::zero()),
                     input: data.unwrap_or_default(),
-                    kind: match to {
-                        Some(to) => TransactionKind::Call(to),
-                        None => TransactionKind::Create,
-                    },
+                    kind,
                     chain_id: 137,
-                    access_list,
+                    access_list: access_list.unwrap_or_default(),
                 },
             )),
-            // EIP1559
-            (None, Some(_), access_list) | (None, None, access_list @ None) => {
-                // Empty fields fall back to the canonical transaction schema.
-                Some(TypedTransactionRequest::EIP1559(
-                    EIP1559TransactionRequest {
-                        nonce: nonce.unwrap_or(U256::zero()),
-                        max_fee_per_gas: max_fee_per_gas.unwrap_or_default(),
-                        max_priority_fee_per_gas: max_priority_fee_per_gas.unwrap_or(U256::zero()),
-                        gas_limit: gas.unwrap_or_default(),
-                        value: value.unwrap_or(U256::zero()),
-                        input: data.unwrap_or_default(),
-                        kind: match to {
-                            Some(to) => TransactionKind::Call(to),
-                            None => TransactionKind::Create,
-                        },
-                        chain_id: 137,
-                        access_list: access_list.unwrap_or_default(),
-                    },
-                ))
-            }
             _ => None,
         }
     }
@@ -529,6 +539,52 @@ impl TypedTransaction {
     }
 }
 
+impl TypedTransaction {
+    /// Decodes raw transaction bytes as broadcast with `eth_sendRawTransaction`
+    /// or returned by `eth_getRawTransactionByHash`. Unlike in blocks, typed
+    /// transactions aren't wrapped in an rlp string here.
+    pub fn decode_raw(bytes: &[u8]) -> Result<Self, DecoderError> {
+        let first = *bytes.first().ok_or(DecoderError::Custom("empty slice"))?;
+        match first {
+            0x01 => rlp::decode(&bytes[1..]).map(TypedTransaction::EIP2930),
+            0x02 => rlp::decode(&bytes[1..]).map(TypedTransaction::EIP1559),
+            // legacy transactions are plain rlp lists
+            0xc0..=0xff => rlp::decode(bytes).map(TypedTransaction::Legacy),
+            _ => Err(DecoderError::Custom("invalid tx type")),
+        }
+    }
+
+    /// inverse of `decode_raw`
+    pub fn encode_raw(&self) -> Bytes {
+        let (id, encoded) = match self {
+            TypedTransaction::Legacy(tx) => return rlp::encode(tx).freeze().into(),
+            TypedTransaction::EIP2930(tx) => (1, rlp::encode(tx)),
+            TypedTransaction::EIP1559(tx) => (2, rlp::encode(tx)),
+        };
+        let mut out = Vec::with_capacity(1 + encoded.len());
+        out.push(id);
+        out.extend_from_slice(&encoded);
+        out.into()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RawTransactionError {
+    #[error("invalid transaction encoding: {0}")]
+    Decode(#[from] DecoderError),
+    #[error("invalid signature: {0}")]
+    Signature(#[from] SignatureError),
+}
+
+/// decodes a raw transaction and recovers its sender
+pub fn decode_raw_transaction(
+    bytes: &[u8],
+) -> Result<(TypedTransaction, Address), RawTransactionError> {
+    let tx = TypedTransaction::decode_raw(bytes)?;
+    let from = tx.recover()?;
+    Ok((tx, from))
+}
+
 impl Encodable for TypedTransaction {
     fn rlp_append(&self, s: &mut RlpStream) {
         match self {
@@ -814,6 +870,108 @@ impl Decodable for EIP1559Transaction {
     }
 }
 
+/// envelope type bor uses for state-sync transactions once they're part of
+/// the block body
+pub const STATE_SYNC_TX_TYPE: u8 = 0x7f;
+
+/// One state-sync event bridged from L1, see `StateSyncTransaction`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct StateSyncData {
+    pub id: u64,
+    pub contract: Address,
+    pub data: Bytes,
+    /// hash of the L1 txn that emitted the event
+    pub tx_hash: H256,
+}
+
+impl Encodable for StateSyncData {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.id);
+        s.append(&self.contract);
+        s.append(&self.data.as_ref());
+        s.append(&self.tx_hash);
+    }
+}
+
+impl Decodable for StateSyncData {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        Ok(Self {
+            id: rlp.val_at(0)?,
+            contract: rlp.val_at(1)?,
+            data: rlp.val_at::<Vec<u8>>(2)?.into(),
+            tx_hash: rlp.val_at(3)?,
+        })
+    }
+}
+
+/// Bor system transaction committing the state-sync events of a sprint. It
+/// is unsigned, has no sender or gas, and is always the last transaction of
+/// the block. Older bor versions leave it out of the block body entirely and
+/// only report a pseudo transaction over rpc, see
+/// `is_state_sync_transaction`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct StateSyncTransaction {
+    pub state_sync_data: Vec<StateSyncData>,
+}
+
+impl StateSyncTransaction {
+    pub fn hash(&self) -> H256 {
+        let encoded = rlp::encode(self);
+        let mut out = vec![0; 1 + encoded.len()];
+        out[0] = STATE_SYNC_TX_TYPE;
+        out[1..].copy_from_slice(&encoded);
+        H256::from_slice(keccak256(&out).as_slice())
+    }
+}
+
+impl Encodable for StateSyncTransaction {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(1);
+        s.append_list(&self.state_sync_data);
+    }
+}
+
+impl Decodable for StateSyncTransaction {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 1 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        Ok(Self {
+            state_sync_data: rlp.list_at(0)?,
+        })
+    }
+}
+
+/// Hash bor derives for the pseudo state-sync transaction it reports over
+/// rpc for blocks whose body doesn't contain it.
+pub fn bor_state_sync_tx_hash(block_number: u64, block_hash: H256) -> H256 {
+    let mut key = b"matic-bor-receipt-".to_vec();
+    key.extend_from_slice(&block_number.to_be_bytes());
+    key.extend_from_slice(block_hash.as_bytes());
+    H256::from(keccak256(key))
+}
+
+/// Whether an rpc transaction is bor's state-sync pseudo transaction. These
+/// have neither a sender nor a signature and must not be re-encoded into
+/// blocks.
+pub fn is_state_sync_transaction(tx: &ethers::types::Transaction) -> bool {
+    if tx.transaction_type == Some(STATE_SYNC_TX_TYPE.into()) {
+        return true;
+    }
+    if let (Some(number), Some(hash)) = (tx.block_number, tx.block_hash) {
+        if tx.hash == bor_state_sync_tx_hash(number.as_u64(), hash) {
+            return true;
+        }
+    }
+    tx.from == Address::zero() && tx.r.is_zero() && tx.s.is_zero()
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TransactionEssentials {
     pub kind: TransactionKind,
@@ -936,3 +1094,64 @@ pub fn build_typed_transaction(
 
     tx
 }
+
+/// Signs `request` with `wallet` and assembles the signed transaction, works
+/// for every transaction type.
+pub fn sign_typed_request(
+    request: TypedTransactionRequest,
+    wallet: &Wallet<SigningKey>,
+) -> TypedTransaction {
+    let mut ethers_request: EthersTypedTransaction = request.clone().into();
+    ethers_request.set_from(wallet.address());
+    let signature = wallet.sign_transaction_sync(&ethers_request);
+    build_typed_transaction(request, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::LocalWallet;
+
+    use super::*;
+
+    #[test]
+    fn test_sign_and_raw_roundtrip() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64);
+        let base = EthTransactionRequest {
+            to: Some(Address::random()),
+            gas: Some(21_000.into()),
+            value: Some(1.into()),
+            nonce: Some(7.into()),
+            ..Default::default()
+        };
+        let requests = vec![
+            EthTransactionRequest {
+                gas_price: Some(30.into()),
+                ..base.clone()
+            },
+            EthTransactionRequest {
+                gas_price: Some(30.into()),
+                access_list: Some(vec![]),
+                ..base.clone()
+            },
+            EthTransactionRequest {
+                max_fee_per_gas: Some(30.into()),
+                ..base.clone()
+            },
+        ];
+        for request in requests {
+            let request = request.into_typed_request().unwrap();
+            let tx = sign_typed_request(request, &wallet);
+            let decoded: TypedTransaction = rlp::decode(&rlp::encode(&tx)).unwrap();
+            assert_eq!(decoded, tx);
+            assert_eq!(decoded.recover().unwrap(), wallet.address());
+            assert_eq!(decoded.chain_id(), Some(137));
+
+            let (raw_decoded, from) = decode_raw_transaction(&tx.encode_raw()).unwrap();
+            assert_eq!(raw_decoded, tx);
+            assert_eq!(from, wallet.address());
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Building and signing transactions without a provider. Everything a node
+//! would normally fill in (chain id, nonce, fees, gas) is supplied up front,
+//! so a separate signer can hand raw bytes to whatever broadcasts them.
+
+use ethers::{
+    prelude::k256::ecdsa::SigningKey,
+    signers::Wallet,
+    types::{transaction::eip2930::AccessListItem, Address, Bytes, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    sign_typed_request, EIP1559TransactionRequest, EIP2930TransactionRequest,
+    LegacyTransactionRequest, TransactionKind, TypedTransaction, TypedTransactionRequest,
+};
+
+/// fee fields, which also pick the transaction type
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fees {
+    Legacy {
+        gas_price: U256,
+    },
+    EIP2930 {
+        gas_price: U256,
+        access_list: Vec<AccessListItem>,
+    },
+    EIP1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+        access_list: Vec<AccessListItem>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineTransaction {
+    pub chain_id: u64,
+    pub nonce: U256,
+    pub gas_limit: U256,
+    pub kind: TransactionKind,
+    pub value: U256,
+    pub input: Bytes,
+    pub fees: Fees,
+}
+
+impl OfflineTransaction {
+    pub fn call(
+        chain_id: u64,
+        nonce: U256,
+        to: Address,
+        input: Bytes,
+        gas_limit: U256,
+        fees: Fees,
+    ) -> Self {
+        Self {
+            chain_id,
+            nonce,
+            gas_limit,
+            kind: TransactionKind::Call(to),
+            value: U256::zero(),
+            input,
+            fees,
+        }
+    }
+
+    pub fn with_value(mut self, value: U256) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn request(&self) -> TypedTransactionRequest {
+        match &self.fees {
+            Fees::Legacy { gas_price } => {
+                TypedTransactionRequest::Legacy(LegacyTransactionRequest {
+                    nonce: self.nonce,
+                    gas_price: *gas_price,
+                    gas_limit: self.gas_limit,
+                    kind: self.kind,
+                    value: self.value,
+                    input: self.input.clone(),
+                    chain_id: Some(self.chain_id),
+                })
+            }
+            Fees::EIP2930 {
+                gas_price,
+                access_list,
+            } => TypedTransactionRequest::EIP2930(EIP2930TransactionRequest {
+                chain_id: self.chain_id,
+                nonce: self.nonce,
+                gas_price: *gas_price,
+                gas_limit: self.gas_limit,
+                kind: self.kind,
+                value: self.value,
+                input: self.input.clone(),
+                access_list: access_list.clone(),
+            }),
+            Fees::EIP1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                access_list,
+            } => TypedTransactionRequest::EIP1559(EIP1559TransactionRequest {
+                chain_id: self.chain_id,
+                nonce: self.nonce,
+                max_priority_fee_per_gas: *max_priority_fee_per_gas,
+                max_fee_per_gas: *max_fee_per_gas,
+                gas_limit: self.gas_limit,
+                kind: self.kind,
+                value: self.value,
+                input: self.input.clone(),
+                access_list: access_list.clone(),
+            }),
+        }
+    }
+
+    pub fn sign(&self, wallet: &Wallet<SigningKey>) -> TypedTransaction {
+        sign_typed_request(self.request(), wallet)
+    }
+
+    /// signed bytes ready for `eth_sendRawTransaction`
+    pub fn sign_raw(&self, wallet: &Wallet<SigningKey>) -> Bytes {
+        self.sign(wallet).encode_raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+    use crate::utils::transaction::decode_raw_transaction;
+
+    #[test]
+    fn test_offline_sign_raw() {
+        // the wallet's own chain id must not leak into the transaction
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(1u64);
+        let fees = [
+            Fees::Legacy {
+                gas_price: 30.into(),
+            },
+            Fees::EIP1559 {
+                max_fee_per_gas: 40.into(),
+                max_priority_fee_per_gas: 30.into(),
+                access_list: vec![],
+            },
+        ];
+        for fees in fees {
+            let txn = OfflineTransaction::call(
+                80001,
+                3.into(),
+                Address::random(),
+                Bytes::from(vec![1, 2, 3]),
+                50_000.into(),
+                fees,
+            )
+            .with_value(5.into());
+            let raw = txn.sign_raw(&wallet);
+            let (decoded, from) = decode_raw_transaction(&raw).unwrap();
+            assert_eq!(from, wallet.address());
+            assert_eq!(decoded, txn.sign(&wallet));
+        }
+    }
+}
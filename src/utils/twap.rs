@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use ethers::{
+    prelude::ContractError,
+    providers::Middleware,
+    types::{Address, BlockId, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::uniswapV2::IUniswapV2Pair;
+
+/// UQ112x112 fixed point, as the pair contracts store prices
+const Q112: u32 = 112;
+
+/// cumulative prices of a pair as of a block's timestamp
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceObservation {
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub price0_cumulative: U256,
+    pub price1_cumulative: U256,
+}
+
+impl PriceObservation {
+    /// The pair only accumulates on its first interaction in a block, so
+    /// extend `price*CumulativeLast` to `timestamp` at the current reserves,
+    /// like `UniswapV2OracleLibrary.currentCumulativePrices`.
+    pub fn from_pair_state(
+        block_number: u64,
+        timestamp: u64,
+        price0_cumulative_last: U256,
+        price1_cumulative_last: U256,
+        (reserve0, reserve1, block_timestamp_last): (u128, u128, u32),
+    ) -> Self {
+        let mut price0_cumulative = price0_cumulative_last;
+        let mut price1_cumulative = price1_cumulative_last;
+        // timestamps are uint32 on chain and wrap
+        let elapsed = (timestamp as u32).wrapping_sub(block_timestamp_last);
+        if elapsed > 0 && reserve0 != 0 && reserve1 != 0 {
+            let price0 = (U256::from(reserve1) << Q112) / reserve0;
+            let price1 = (U256::from(reserve0) << Q112) / reserve1;
+            price0_cumulative = price0_cumulative.overflowing_add(price0 * elapsed).0;
+            price1_cumulative = price1_cumulative.overflowing_add(price1 * elapsed).0;
+        }
+        Self {
+            block_number,
+            timestamp,
+            price0_cumulative,
+            price1_cumulative,
+        }
+    }
+}
+
+/// time weighted average prices between two observations, UQ112x112 in
+/// base units, `price0` being token1 per token0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Twap {
+    pub price0: U256,
+    pub price1: U256,
+    /// seconds averaged over
+    pub elapsed: u64,
+}
+
+impl Twap {
+    /// `None` if both observations are from the same timestamp
+    pub fn between(start: &PriceObservation, end: &PriceObservation) -> Option<Self> {
+        let elapsed = end.timestamp.checked_sub(start.timestamp)?;
+        if elapsed == 0 {
+            return None;
+        }
+        // cumulative prices overflow by design, differences stay correct
+        let price0 = end
+            .price0_cumulative
+            .overflowing_sub(start.price0_cumulative)
+            .0
+            / elapsed;
+        let price1 = end
+            .price1_cumulative
+            .overflowing_sub(start.price1_cumulative)
+            .0
+            / elapsed;
+        Some(Self {
+            price0,
+            price1,
+            elapsed,
+        })
+    }
+
+    pub fn price0_f64(&self) -> f64 {
+        uq112x112_to_f64(self.price0)
+    }
+
+    pub fn price1_f64(&self) -> f64 {
+        uq112x112_to_f64(self.price1)
+    }
+
+    /// How far the spot price of `reserve0/reserve1` is off the TWAP, in bps.
+    /// A big gap means the pool was just moved and may be manipulated.
+    pub fn spot_deviation_bps(&self, reserve0: U256, reserve1: U256) -> u64 {
+        if reserve0.is_zero() || self.price0.is_zero() {
+            return u64::MAX;
+        }
+        let spot = (reserve1 << Q112) / reserve0;
+        let diff = if spot > self.price0 {
+            spot - self.price0
+        } else {
+            self.price0 - spot
+        };
+        let bps = diff * 10000u64 / self.price0;
+        if bps > U256::from(u64::MAX) {
+            return u64::MAX;
+        }
+        bps.as_u64()
+    }
+}
+
+pub fn uq112x112_to_f64(x: U256) -> f64 {
+    let integer = (x >> Q112).low_u128() as f64;
+    let fraction = (x & ((U256::one() << Q112) - 1)).low_u128() as f64;
+    integer + fraction / 2f64.powi(Q112 as i32)
+}
+
+/// Samples cumulative prices of V2 pairs at past blocks, needs an archive
+/// node for anything older than the node keeps state for.
+pub struct TwapReader<M> {
+    provider: Arc<M>,
+}
+
+impl<M: Middleware> TwapReader<M> {
+    pub fn new(provider: Arc<M>) -> Self {
+        Self { provider }
+    }
+
+    /// `None` if the block doesn't exist yet
+    pub async fn observe(
+        &self,
+        pair_address: Address,
+        block_number: u64,
+    ) -> Result<Option<PriceObservation>, ContractError<M>> {
+        let block = BlockId::Number(block_number.into());
+        let timestamp = match self
+            .provider
+            .get_block(block)
+            .await
+            .map_err(ContractError::MiddlewareError)?
+        {
+            Some(block) => block.timestamp.as_u64(),
+            None => return Ok(None),
+        };
+        let pair = IUniswapV2Pair::new(pair_address, self.provider.clone());
+        let price0_cumulative_last = pair.price_0_cumulative_last().block(block).call().await?;
+        let price1_cumulative_last = pair.price_1_cumulative_last().block(block).call().await?;
+        let reserves = pair.get_reserves().block(block).call().await?;
+        Ok(Some(PriceObservation::from_pair_state(
+            block_number,
+            timestamp,
+            price0_cumulative_last,
+            price1_cumulative_last,
+            reserves,
+        )))
+    }
+
+    /// TWAP of `pair_address` from `from_block` to `to_block`
+    pub async fn twap(
+        &self,
+        pair_address: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Option<Twap>, ContractError<M>> {
+        let start = self.observe(pair_address, from_block).await?;
+        let end = self.observe(pair_address, to_block).await?;
+        match (start, end) {
+            (Some(start), Some(end)) => Ok(Twap::between(&start, &end)),
+            _ => Ok(None),
+        }
+    }
+
+    /// TWAPs of every pair over the same window
+    pub async fn twaps(
+        &self,
+        pair_addresses: &[Address],
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<Option<Twap>>, ContractError<M>> {
+        let mut twaps = Vec::with_capacity(pair_addresses.len());
+        for pair_address in pair_addresses {
+            twaps.push(self.twap(*pair_address, from_block, to_block).await?);
+        }
+        Ok(twaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_twap_from_cumulative_prices() {
+        // 2 token1 per token0 for 100s, then 4 for 100s, pair untouched since
+        let start = PriceObservation::from_pair_state(
+            1,
+            1_000,
+            U256::zero(),
+            U256::zero(),
+            (10, 20, 1_000),
+        );
+        let mid = PriceObservation::from_pair_state(
+            2,
+            1_100,
+            U256::zero(),
+            U256::zero(),
+            (10, 20, 1_000),
+        );
+        let end = PriceObservation::from_pair_state(
+            3,
+            1_200,
+            mid.price0_cumulative,
+            mid.price1_cumulative,
+            (10, 40, 1_100),
+        );
+        let twap = Twap::between(&start, &end).unwrap();
+        assert_eq!(twap.elapsed, 200);
+        assert_eq!(twap.price0_f64(), 3.0);
+        assert_eq!(twap.price1_f64(), 0.375);
+        // spot is 4, a third above the average
+        assert_eq!(twap.spot_deviation_bps(10.into(), 40.into()), 3333);
+        assert_eq!(Twap::between(&start, &start), None);
+    }
+}
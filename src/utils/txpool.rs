@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Middleware, ProviderError},
+    types::{Address, Transaction, H256, U64},
+};
+use serde::{Deserialize, Serialize};
+
+/// txns of one account in the pool, by nonce
+pub type NonceMap = BTreeMap<u64, Transaction>;
+
+/// `txpool_content`. Pending txns are executable, queued ones are waiting on
+/// a nonce gap to close.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TxpoolContent {
+    pub pending: HashMap<Address, NonceMap>,
+    pub queued: HashMap<Address, NonceMap>,
+}
+
+impl TxpoolContent {
+    pub fn pending_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.pending.values().flat_map(|txns| txns.values())
+    }
+
+    pub fn queued_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.queued.values().flat_map(|txns| txns.values())
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending
+            .values()
+            .chain(self.queued.values())
+            .map(|txns| txns.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_pending(&self, hash: H256) -> bool {
+        self.pending_transactions().any(|txn| txn.hash == hash)
+    }
+
+    /// next nonce `account` can use, counting its pending txns
+    pub fn next_nonce(&self, account: Address) -> Option<u64> {
+        let txns = self.pending.get(&account)?;
+        txns.keys().next_back().map(|nonce| nonce + 1)
+    }
+}
+
+/// `txpool_status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TxpoolStatus {
+    pub pending: U64,
+    pub queued: U64,
+}
+
+/// geth's `txpool` namespace
+#[async_trait]
+pub trait TxpoolExt: Middleware {
+    async fn get_txpool_content(&self) -> Result<TxpoolContent, ProviderError> {
+        self.provider().request("txpool_content", ()).await
+    }
+
+    /// pool content of a single sender
+    async fn get_txpool_content_from(
+        &self,
+        account: Address,
+    ) -> Result<(NonceMap, NonceMap), ProviderError> {
+        #[derive(Deserialize, Serialize, Debug)]
+        struct ContentFrom {
+            #[serde(default)]
+            pending: NonceMap,
+            #[serde(default)]
+            queued: NonceMap,
+        }
+        let content: ContentFrom = self
+            .provider()
+            .request("txpool_contentFrom", [account])
+            .await?;
+        Ok((content.pending, content.queued))
+    }
+
+    async fn get_txpool_status(&self) -> Result<TxpoolStatus, ProviderError> {
+        self.provider().request("txpool_status", ()).await
+    }
+}
+
+impl<M: Middleware> TxpoolExt for M {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_txpool_content() {
+        let content: TxpoolContent = serde_json::from_str(
+            r#"{
+                "pending": {
+                    "0x0216d5032f356960cd3749c31ab34eeff21b3395": {
+                        "806": {
+                            "blockHash": null,
+                            "blockNumber": null,
+                            "from": "0x0216d5032f356960cd3749c31ab34eeff21b3395",
+                            "gas": "0x5208",
+                            "gasPrice": "0xba43b7400",
+                            "hash": "0xaf953a2d01f55cfe080c0c94150a60105e8ac3d51153058a1f03dd239dd08586",
+                            "input": "0x",
+                            "nonce": "0x326",
+                            "to": "0x7f69a91a3cf4be60020fb58b893b7cbb65376db8",
+                            "transactionIndex": null,
+                            "value": "0x19a99f0cf456000",
+                            "v": "0x1c",
+                            "r": "0x51b5e4a6a2b6b4bd4c9f7cd6da4c0ad1fcbcc22cd2b9d1a4df2c0a06a4b8d6e1",
+                            "s": "0x1f0b1d4f4d5a52a9c9a0f6c9dd0a4fa2e7d8fa4d8b1a1a3e1c0bf22d7f2fb4a3"
+                        }
+                    }
+                },
+                "queued": {}
+            }"#,
+        )
+        .unwrap();
+        let account: Address = "0x0216d5032f356960cd3749c31ab34eeff21b3395"
+            .parse()
+            .unwrap();
+        assert_eq!(content.len(), 1);
+        assert_eq!(content.next_nonce(account), Some(807));
+        assert!(content.queued_transactions().next().is_none());
+    }
+}
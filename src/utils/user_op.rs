@@ -0,0 +1,282 @@
+//! Submitting the arb/liquidation call as an ERC-4337 UserOperation (entry
+//! point v0.6) through a bundler, gas sponsored by a paymaster if one is
+//! set. The call is made by a smart account owned by our EOA, so it neither
+//! competes on the EOA's nonce nor shows up in the public mempool as ours.
+
+use std::sync::Arc;
+
+use ethers::{
+    abi::{encode, Token},
+    prelude::abigen,
+    providers::{Http, Middleware, Provider, ProviderError},
+    signers::{LocalWallet, Signer, WalletError},
+    types::{Address, Bytes, H256, U256},
+    utils::keccak256,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+abigen!(
+    IEntryPoint,
+    r#"[
+        function getNonce(address sender, uint192 key) external view returns (uint256 nonce)
+    ]"#,
+);
+
+abigen!(
+    ISimpleAccount,
+    r#"[
+        function execute(address dest, uint256 value, bytes func) external
+    ]"#,
+);
+
+lazy_static! {
+    pub static ref ENTRY_POINT_V06: Address = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+        .parse::<Address>()
+        .unwrap();
+}
+
+/// Signature of the right length for gas estimation, accounts only check
+/// it's well formed before the real one is in.
+const DUMMY_SIGNATURE: &str = "0xfffffffffffffffffffffffffffffff0000000000000000000000000000000007aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1c";
+
+#[derive(Error, Debug)]
+pub enum UserOpError {
+    #[error(transparent)]
+    ProviderError(#[from] ProviderError),
+
+    #[error(transparent)]
+    WalletError(#[from] WalletError),
+
+    #[error("failed to read the account nonce: {0}")]
+    NonceError(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    pub init_code: Bytes,
+    pub call_data: Bytes,
+    pub call_gas_limit: U256,
+    pub verification_gas_limit: U256,
+    pub pre_verification_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub paymaster_and_data: Bytes,
+    pub signature: Bytes,
+}
+
+impl UserOperation {
+    /// what the account verifies the signature against
+    pub fn hash(&self, entry_point: Address, chain_id: u64) -> H256 {
+        let packed = encode(&[
+            Token::Address(self.sender),
+            Token::Uint(self.nonce),
+            Token::FixedBytes(keccak256(&self.init_code).to_vec()),
+            Token::FixedBytes(keccak256(&self.call_data).to_vec()),
+            Token::Uint(self.call_gas_limit),
+            Token::Uint(self.verification_gas_limit),
+            Token::Uint(self.pre_verification_gas),
+            Token::Uint(self.max_fee_per_gas),
+            Token::Uint(self.max_priority_fee_per_gas),
+            Token::FixedBytes(keccak256(&self.paymaster_and_data).to_vec()),
+        ]);
+        H256::from(keccak256(encode(&[
+            Token::FixedBytes(keccak256(packed).to_vec()),
+            Token::Address(entry_point),
+            Token::Uint(chain_id.into()),
+        ])))
+    }
+
+    /// signs as the owner of a SimpleAccount style account, EIP-191 over
+    /// the op hash
+    pub async fn sign(
+        &mut self,
+        owner: &LocalWallet,
+        entry_point: Address,
+    ) -> Result<(), WalletError> {
+        let hash = self.hash(entry_point, owner.chain_id());
+        let signature = owner.sign_message(hash.as_bytes()).await?;
+        self.signature = signature.to_vec().into();
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasEstimate {
+    pub pre_verification_gas: U256,
+    pub verification_gas_limit: U256,
+    pub call_gas_limit: U256,
+}
+
+/// What `pm_sponsorUserOperation` answers with. Paymasters that adjust the
+/// gas limits they sign over send them along, they replace the estimate.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sponsorship {
+    pub paymaster_and_data: Bytes,
+    pub pre_verification_gas: Option<U256>,
+    pub verification_gas_limit: Option<U256>,
+    pub call_gas_limit: Option<U256>,
+}
+
+/// Sends calls through `account`, a smart account `owner` controls.
+pub struct UserOpSubmitter<M> {
+    provider: Arc<M>,
+    bundler: Provider<Http>,
+    /// sponsors gas if set, otherwise the account pays from its deposit
+    paymaster: Option<Provider<Http>>,
+    owner: LocalWallet,
+    account: Address,
+    entry_point: Address,
+}
+
+impl<M: Middleware + 'static> UserOpSubmitter<M> {
+    pub fn new(
+        provider: Arc<M>,
+        bundler: Provider<Http>,
+        owner: LocalWallet,
+        account: Address,
+    ) -> Self {
+        Self {
+            provider,
+            bundler,
+            paymaster: None,
+            owner,
+            account,
+            entry_point: *ENTRY_POINT_V06,
+        }
+    }
+
+    pub fn with_paymaster(mut self, paymaster: Provider<Http>) -> Self {
+        self.paymaster = Some(paymaster);
+        self
+    }
+
+    pub fn account(&self) -> Address {
+        self.account
+    }
+
+    /// The op calling `to` with `calldata` from the account, gas estimated
+    /// by the bundler and sponsored if there's a paymaster, signed.
+    pub async fn build(
+        &self,
+        to: Address,
+        calldata: Bytes,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> Result<UserOperation, UserOpError> {
+        let entry_point = IEntryPoint::new(self.entry_point, self.provider.clone());
+        let nonce = entry_point
+            .get_nonce(self.account, U256::zero())
+            .call()
+            .await
+            .map_err(|e| UserOpError::NonceError(e.to_string()))?;
+        let account = ISimpleAccount::new(self.account, self.provider.clone());
+        let call_data = account
+            .execute(to, U256::zero(), calldata)
+            .calldata()
+            .unwrap_or_default();
+        let mut op = UserOperation {
+            sender: self.account,
+            nonce,
+            call_data,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            signature: DUMMY_SIGNATURE.parse().unwrap(),
+            ..Default::default()
+        };
+
+        let estimate: GasEstimate = self
+            .bundler
+            .request("eth_estimateUserOperationGas", (&op, self.entry_point))
+            .await?;
+        op.pre_verification_gas = estimate.pre_verification_gas;
+        op.verification_gas_limit = estimate.verification_gas_limit;
+        op.call_gas_limit = estimate.call_gas_limit;
+
+        // the paymaster signs over the gas limits, so it goes after the estimate
+        if let Some(paymaster) = &self.paymaster {
+            let sponsorship: Sponsorship = paymaster
+                .request("pm_sponsorUserOperation", (&op, self.entry_point))
+                .await?;
+            op.paymaster_and_data = sponsorship.paymaster_and_data;
+            op.pre_verification_gas = sponsorship
+                .pre_verification_gas
+                .unwrap_or(op.pre_verification_gas);
+            op.verification_gas_limit = sponsorship
+                .verification_gas_limit
+                .unwrap_or(op.verification_gas_limit);
+            op.call_gas_limit = sponsorship.call_gas_limit.unwrap_or(op.call_gas_limit);
+        }
+        op.sign(&self.owner, self.entry_point).await?;
+        Ok(op)
+    }
+
+    /// hands `op` to the bundler, returns its hash
+    pub async fn send(&self, op: &UserOperation) -> Result<H256, UserOpError> {
+        Ok(self
+            .bundler
+            .request("eth_sendUserOperation", (op, self.entry_point))
+            .await?)
+    }
+
+    pub async fn submit(
+        &self,
+        to: Address,
+        calldata: Bytes,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> Result<H256, UserOpError> {
+        let op = self
+            .build(to, calldata, max_fee_per_gas, max_priority_fee_per_gas)
+            .await?;
+        self.send(&op).await
+    }
+
+    /// the bundler's receipt once the op is mined, `None` before
+    pub async fn receipt(&self, op_hash: H256) -> Result<Option<serde_json::Value>, UserOpError> {
+        Ok(self
+            .bundler
+            .request("eth_getUserOperationReceipt", [op_hash])
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Signature;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_user_op_signature_recovers() {
+        let owner = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(137u64);
+        let mut op = UserOperation {
+            sender: Address::repeat_byte(1),
+            nonce: 3.into(),
+            call_data: vec![0xb6, 0x1d, 0x27, 0xf6].into(),
+            call_gas_limit: 500_000.into(),
+            ..Default::default()
+        };
+        let hash = op.hash(*ENTRY_POINT_V06, 137);
+        assert_ne!(hash, op.hash(*ENTRY_POINT_V06, 1));
+
+        op.sign(&owner, *ENTRY_POINT_V06).await.unwrap();
+        let signature = Signature::try_from(op.signature.as_ref()).unwrap();
+        assert_eq!(signature.recover(hash.as_bytes()).unwrap(), owner.address());
+
+        let json = serde_json::to_value(&op).unwrap();
+        assert_eq!(json["callGasLimit"], "0x7a120");
+        let dummy: Bytes = DUMMY_SIGNATURE.parse().unwrap();
+        assert_eq!(dummy.len(), 65);
+        assert_eq!(json["paymasterAndData"], "0x");
+    }
+}
@@ -0,0 +1,222 @@
+//! Cold-start warmup. What a route needs besides its quotes is the same
+//! every block, so it's computed once into a `RouteTable` before the first
+//! head, and `WorldState::warm_up` loads the V3 pools and quotes of every
+//! route, so the first blocks after a restart evaluate as fast as the ones
+//! after them.
+
+use std::ops::{Index, IndexMut};
+
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, Address, Bytes, TransactionRequest, U256},
+};
+use thiserror::Error;
+
+use crate::{
+    routes::Route,
+    utils::{fixed_point::pow10, quote_cache::QuoteCacheStats},
+};
+
+/// gas a submission of a route is assumed to use until it's estimated
+pub const DEFAULT_GAS_ESTIMATE: u64 = 500_000;
+
+#[derive(Error, Debug)]
+pub enum WarmupError {
+    #[error("{0} has too many decimals to scale to 18")]
+    Decimals(&'static str),
+}
+
+/// What `WorldState::warm_up` loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WarmupStats {
+    /// V3 pools the routes' hops trade through
+    pub v3_pools: usize,
+    /// misses are the V3 quotes the first block won't ask for
+    pub quotes: QuoteCacheStats,
+}
+
+/// The static data of a route.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteEntry {
+    /// token symbols joined by `>`
+    pub symbols: String,
+    /// 10^(18 - decimals) of the start token
+    pub scale: U256,
+    /// of `calldata` if it estimated, `DEFAULT_GAS_ESTIMATE` otherwise
+    pub gas_estimate: U256,
+    /// V2 pair of every hop of the route best at warmup, `None` for a V3 hop
+    pub pairs: Vec<Option<Address>>,
+    /// calldata of the route best at warmup
+    pub calldata: Option<Bytes>,
+}
+
+impl RouteEntry {
+    pub fn new(route: &Route) -> Result<Self, WarmupError> {
+        let start = route.token_path[0];
+        Ok(Self {
+            symbols: route
+                .token_path
+                .iter()
+                .map(|token| token.get_symbol())
+                .collect::<Vec<_>>()
+                .join(">"),
+            scale: pow10(18u8.saturating_sub(start.get_decimals()))
+                .ok_or(WarmupError::Decimals(start.get_symbol()))?,
+            gas_estimate: U256::from(DEFAULT_GAS_ESTIMATE),
+            pairs: Vec::new(),
+            calldata: None,
+        })
+    }
+
+    /// `amount` of the start token in 18 decimals, `U256::MAX` on overflow
+    pub fn normalize(&self, amount: U256) -> U256 {
+        amount.checked_mul(self.scale).unwrap_or(U256::MAX)
+    }
+}
+
+/// `RouteEntry` of every route, indexed like them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteTable {
+    entries: Vec<RouteEntry>,
+}
+
+impl RouteTable {
+    pub fn new(routes: &[Route]) -> Result<Self, WarmupError> {
+        Ok(Self {
+            entries: routes
+                .iter()
+                .map(RouteEntry::new)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Estimates the gas of every route's calldata sent by `from` to
+    /// `executor`. A route that doesn't estimate, as one unprofitable at
+    /// warmup reverts, keeps its estimate. The number of routes estimated.
+    pub async fn estimate_gas<M: Middleware>(
+        &mut self,
+        provider: &M,
+        from: Address,
+        executor: Address,
+    ) -> usize {
+        let estimates = futures_util::future::join_all(self.entries.iter().map(|entry| async {
+            let calldata = entry.calldata.clone()?;
+            let tx: TypedTransaction = TransactionRequest::new()
+                .from(from)
+                .to(executor)
+                .data(calldata)
+                .into();
+            provider.estimate_gas(&tx, None).await.ok()
+        }))
+        .await;
+        let mut estimated = 0;
+        for (entry, estimate) in self.entries.iter_mut().zip(estimates) {
+            if let Some(gas) = estimate {
+                entry.gas_estimate = gas;
+                estimated += 1;
+            }
+        }
+        estimated
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Index<usize> for RouteTable {
+    type Output = RouteEntry;
+
+    fn index(&self, route: usize) -> &RouteEntry {
+        &self.entries[route]
+    }
+}
+
+impl IndexMut<usize> for RouteTable {
+    fn index_mut(&mut self, route: usize) -> &mut RouteEntry {
+        &mut self.entries[route]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+
+    use super::*;
+    use crate::{
+        constants::token::ERC20Token::{DAI, USDC, WETH},
+        utils::{
+            batch::{common::JsonRpcError, fake::FakeTransport},
+            fixed_point::{rescale, whole_units},
+        },
+    };
+
+    fn routes() -> Vec<Route> {
+        vec![
+            Route {
+                amount_in: whole_units(1000, USDC.get_decimals()).unwrap(),
+                token_path: vec![USDC, WETH, USDC],
+            },
+            Route {
+                amount_in: whole_units(1000, DAI.get_decimals()).unwrap(),
+                token_path: vec![DAI, USDC, WETH, DAI],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_route_table() {
+        let routes = routes();
+        let table = RouteTable::new(&routes).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[0].symbols, "USDC>WETH>USDC");
+        assert_eq!(table[1].symbols, "DAI>USDC>WETH>DAI");
+        for (i, route) in routes.iter().enumerate() {
+            let decimals = route.token_path[0].get_decimals();
+            assert_eq!(
+                table[i].normalize(route.amount_in),
+                rescale(route.amount_in, decimals, 18).unwrap()
+            );
+        }
+        assert_eq!(table[0].normalize(U256::MAX), U256::MAX);
+        assert_eq!(table[0].gas_estimate, U256::from(DEFAULT_GAS_ESTIMATE));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_gas() {
+        let mut table = RouteTable::new(&routes()).unwrap();
+        table[0].calldata = Some(Bytes::from(vec![1, 2, 3]));
+        table[1].calldata = Some(Bytes::from(vec![4, 5, 6]));
+
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        transport.push_response("eth_estimateGas", U256::from(312_000));
+        // unprofitable at warmup
+        transport.push_error(
+            "eth_estimateGas",
+            JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            },
+        );
+
+        let executor = Address::repeat_byte(2);
+        let estimated = table
+            .estimate_gas(&provider, Address::repeat_byte(1), executor)
+            .await;
+        assert_eq!(estimated, 1);
+        assert_eq!(table[0].gas_estimate, U256::from(312_000));
+        assert_eq!(table[1].gas_estimate, U256::from(DEFAULT_GAS_ESTIMATE));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].0, "eth_estimateGas");
+        assert_eq!(requests[0].1[0]["to"], serde_json::json!(executor));
+        assert_eq!(requests[0].1[0]["data"], "0x010203");
+    }
+}
@@ -2,46 +2,230 @@ use ethers::{
     abi::{parse_abi, Address},
     prelude::BaseContract,
     providers::{Middleware, Provider, PubsubClient},
-    types::U256,
+    types::{transaction::eip2718::TypedTransaction, U256},
 };
 use futures_util::StreamExt;
-use log::debug;
-use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+use log::{debug, error, warn};
+use rand::seq::SliceRandom;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, RwLock as StdRwLock},
+    time::Duration,
+};
 use tokio::sync::RwLock;
 
 use crate::{
+    bus::{next, Bus},
     constants::{
         protocol::{UniswapV2, UNISWAPV2_PROTOCOLS},
         token::ERC20Token,
     },
+    error::{Error, StateError, TransportError},
     event_monitor::get_pair_sync_stream,
-    uniswapV2::{UniswapV2Client, UniswapV2Pair},
-    uniswapV3::UniswapV3Client,
-    utils::matrix::Matrix3D,
+    export::ReserveRecord,
+    header_tracker::Reorg,
+    migration::{collapsed, liquidity, Collapse, CollapseConfig, LiquidityPeaks, Replacement},
+    pool_check::verify_protocol,
+    router_probe::{probe_router, ProbeConfig, RouterHealth},
+    snapshot::{SnapshotHistory, StateSnapshot, STATE_HISTORY},
+    uniswapV2::{SwapParams, UniswapV2Client, UniswapV2Pair},
+    uniswapV3::{TickBoundary, UniswapV3Client, FEE_TIERS},
+    utils::{
+        fixed_point::to_f64,
+        matrix::Matrix3D,
+        poll_schedule::{PollConfig, PollSchedule},
+        quote_cache::{QuoteCache, QuoteCacheStats},
+    },
+    warmup::WarmupStats,
 };
 
-#[derive(Debug, Clone, Copy)]
+/// V3 quotes are reused for amounts equal in their top this many bits
+pub const QUOTE_PRECISION_BITS: u32 = 32;
+
+/// chunks `build_best_swap` splits an order into
+pub const SPLIT_PARTS: usize = 10;
+
+/// How often and how strictly `guard_reserves` checks local reserves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaleGuardConfig {
+    pub interval: Duration,
+    /// pairs checked every interval
+    pub sample_size: usize,
+    /// largest move of either reserve tolerated before resyncing
+    pub tolerance_bps: u64,
+    /// wait before rechecking a diverged pair, a Sync event of the latest
+    /// block may not have been applied yet
+    pub confirm_delay: Duration,
+}
+
+impl Default for StaleGuardConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            sample_size: 16,
+            tolerance_bps: 5,
+            confirm_delay: Duration::from_secs(3),
+        }
+    }
+}
+
+/// a pair whose local reserves drifted off the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub pair: Address,
+    pub local: (U256, U256),
+    pub onchain: (U256, U256),
+    pub bps: u64,
+}
+
+/// Largest relative difference of either reserve, in bps of the on-chain
+/// value. A reserve that is zero on one side only counts as 100%.
+pub fn divergence_bps(local: (U256, U256), onchain: (U256, U256)) -> u64 {
+    let bps = |local: U256, onchain: U256| {
+        let diff = if local > onchain {
+            local - onchain
+        } else {
+            onchain - local
+        };
+        if diff.is_zero() {
+            0
+        } else if onchain.is_zero() {
+            10_000
+        } else {
+            (diff.saturating_mul(U256::from(10_000)) / onchain)
+                .min(U256::from(10_000))
+                .as_u64()
+        }
+    };
+    u64::max(bps(local.0, onchain.0), bps(local.1, onchain.1))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Protocol {
     UniswapV2(UniswapV2),
     UniswapV3 { fee: u32 },
 }
 
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::UniswapV2(protocol) => write!(f, "{}", protocol.get_name()),
+            Protocol::UniswapV3 { fee } => write!(f, "UniswapV3 {}", fee),
+        }
+    }
+}
+
 #[inline(always)]
-fn order_tokens(token0: ERC20Token, token1: ERC20Token) -> (ERC20Token, ERC20Token) {
+pub(crate) fn order_tokens(token0: ERC20Token, token1: ERC20Token) -> (ERC20Token, ERC20Token) {
     match token0.get_address().cmp(&token1.get_address()) {
         Ordering::Less => (token0, token1),
         _ => (token1, token0),
     }
 }
 
+/// one router call of a best execution swap
+#[derive(Debug, Clone)]
+pub struct SwapLeg {
+    pub protocol: Protocol,
+    pub amount_in: U256,
+    pub expected_out: U256,
+    /// router call, from/gas/nonce left for the sender to fill in
+    pub tx: TypedTransaction,
+}
+
+#[derive(Debug, Clone)]
+pub struct BestSwap {
+    pub token_in: ERC20Token,
+    pub token_out: ERC20Token,
+    pub amount_in: U256,
+    pub expected_out: U256,
+    pub legs: Vec<SwapLeg>,
+}
+
+/// Splits `amount_in` across `pairs` in `parts` equal chunks, each going to
+/// the pair with the best marginal output given what it already got.
+pub fn split_amount(
+    pairs: &[UniswapV2Pair],
+    token_in: ERC20Token,
+    amount_in: U256,
+    parts: usize,
+) -> Vec<U256> {
+    let mut allocations = vec![U256::zero(); pairs.len()];
+    if pairs.is_empty() || parts == 0 {
+        return allocations;
+    }
+    let chunk = amount_in / parts;
+    for part in 0..parts {
+        // last chunk takes the rounding remainder
+        let size = if part == parts - 1 {
+            amount_in - chunk * (parts - 1)
+        } else {
+            chunk
+        };
+        // a pool the chunk would overflow, or whose output shrinks as it
+        // takes more, isn't a candidate
+        let best = pairs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, pair)| {
+                let allocation = allocations[i].checked_add(size)?;
+                let before = pair.get_amounts_out(allocations[i], token_in);
+                let after = pair.get_amounts_out(allocation, token_in);
+                Some((i, allocation, after.checked_sub(before)?))
+            })
+            .max_by(|(_, _, a), (_, _, b)| a.cmp(b));
+        let Some((best, allocation, _)) = best else {
+            break;
+        };
+        allocations[best] = allocation;
+    }
+    allocations
+}
+
+/// A V3 hop of a route sized past the end of its liquidity range, into a
+/// range too thin to trade through at a sane price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TickConstraint {
+    pub hop: usize,
+    pub fee: u32,
+    pub boundary: TickBoundary,
+    /// what the route put into the hop
+    pub hop_amount_in: U256,
+    /// route amount in that keeps the hop inside its current range,
+    /// scaled down proportionally
+    pub capped_amount_in: U256,
+}
+
 pub struct WorldState<M, P> {
     provider: Arc<M>,
     stream_provider: Provider<P>,
     uniswapV2_markets: RwLock<Matrix3D<UniswapV2Pair>>,
     uniswapV2_pair_lookup: HashMap<Address, (UniswapV2, ERC20Token, ERC20Token)>,
+    /// pair addresses by market index, the pairs that exist
+    v2_pair_index: HashMap<(usize, usize, usize), Address>,
     pub uniswapV2_pair_addresses: Vec<Address>,
+    /// protocols whose configuration didn't match the chain, never quoted
+    disabled_protocols: Vec<UniswapV2>,
+    collapse_config: CollapseConfig,
+    liquidity_peaks: Mutex<LiquidityPeaks>,
+    /// pairs that lost their liquidity with their market index, never
+    /// quoted again
+    collapsed_pairs: StdRwLock<HashMap<Address, (usize, usize, usize)>>,
+    /// protocols whose router failed its latest probes, not quoted until
+    /// one passes
+    router_health: StdRwLock<RouterHealth>,
     uniswapV3_client: UniswapV3Client<M>,
+    /// best V3 (fee, amount out) per (token in, token out)
+    v3_quotes: QuoteCache<(Address, Address), (u32, U256)>,
+    /// which V3 pools are requoted each block, they have no Sync events
+    v3_schedule: Mutex<PollSchedule<(Address, Address)>>,
     pub gas_price: RwLock<U256>,
+    /// where applied Sync events are published
+    bus: Arc<Bus>,
+    /// reserves of the last `STATE_HISTORY` blocks, for comparing runs
+    snapshots: Mutex<SnapshotHistory>,
 }
 
 impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
@@ -50,7 +234,7 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
         stream_provider: Provider<P>,
         mut tokens_list: Vec<ERC20Token>,
         uniswapV2_list: Vec<UniswapV2>,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         // initialize uniswap v2 client to get initial data
         let uniswapV2_client = UniswapV2Client::new(provider.clone()); // initialize interfacer w/ blockchain
 
@@ -71,16 +255,16 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
 
         let pair_addresses = uniswapV2_client
             .get_pair_address_multicall(pair_address_multicall_input)
-            .await;
+            .await?;
 
         let pair_metadatas = uniswapV2_client
             .get_pair_metadata_multicall(&pair_addresses)
-            .await;
+            .await?;
 
         // grab all reserves for pair addresses
         let pair_reserves = uniswapV2_client
             .get_pair_reserves_multicall(&pair_addresses)
-            .await;
+            .await?;
 
         // populate UniswapV2Pair matrix and reverse lookup table
         let mut matrix = Matrix3D::new(
@@ -92,6 +276,7 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
 
         // create pair addresses to information mapping
         let mut pair_lookup: HashMap<Address, (UniswapV2, ERC20Token, ERC20Token)> = HashMap::new();
+        let mut pair_index = HashMap::new();
 
         let mut curr_idx = 0;
         for protocol in &uniswapV2_list {
@@ -107,48 +292,194 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
                     matrix[(*protocol as usize, token0_ord as usize, token1_ord as usize)]
                         .update_reserves(reserve0, reserve1);
                     pair_lookup.insert(pair_addresses[curr_idx], (*protocol, token0, token1));
+                    if !pair_addresses[curr_idx].is_zero() {
+                        pair_index.insert(
+                            (*protocol as usize, token0_ord as usize, token1_ord as usize),
+                            pair_addresses[curr_idx],
+                        );
+                    }
                     curr_idx += 1;
                 }
             }
         }
 
-        WorldState {
+        // one pair per protocol is enough to check its configuration
+        let mut disabled_protocols = Vec::new();
+        for protocol in &uniswapV2_list {
+            let i = match pair_addresses
+                .iter()
+                .position(|address| !address.is_zero() && pair_lookup[address].0 == *protocol)
+            {
+                Some(i) => i,
+                None => continue,
+            };
+            let (token0, token1, fees) = pair_metadatas[i];
+            let mismatches = verify_protocol(
+                provider.clone(),
+                *protocol,
+                pair_addresses[i],
+                token0,
+                token1,
+                fees,
+            )
+            .await;
+            for mismatch in &mismatches {
+                error!("{} misconfigured: {}", protocol.get_name(), mismatch);
+            }
+            if !mismatches.is_empty() {
+                warn!("Not trading through {}", protocol.get_name());
+                disabled_protocols.push(*protocol);
+            }
+        }
+
+        // a pair drained by its first Sync event still has a peak to fall from
+        let mut liquidity_peaks = LiquidityPeaks::default();
+        for (pair_address, reserves) in pair_addresses.iter().zip(&pair_reserves) {
+            if !pair_address.is_zero() {
+                liquidity_peaks.observe(*pair_address, *reserves);
+            }
+        }
+
+        let gas_price = provider
+            .get_gas_price()
+            .await
+            .map_err(TransportError::middleware::<M>)?;
+        Ok(WorldState {
             provider: provider.clone(),
             stream_provider: stream_provider,
             uniswapV2_markets: RwLock::new(matrix),
             uniswapV2_pair_lookup: pair_lookup,
+            v2_pair_index: pair_index,
             uniswapV2_pair_addresses: pair_addresses,
+            disabled_protocols,
+            collapse_config: CollapseConfig::default(),
+            liquidity_peaks: Mutex::new(liquidity_peaks),
+            collapsed_pairs: StdRwLock::new(HashMap::new()),
+            router_health: StdRwLock::new(RouterHealth::default()),
             uniswapV3_client: UniswapV3Client::new(provider.clone()),
-            gas_price: RwLock::new(provider.get_gas_price().await.unwrap()),
+            v3_quotes: QuoteCache::new(QUOTE_PRECISION_BITS),
+            v3_schedule: Mutex::new(PollSchedule::new(PollConfig::default())),
+            gas_price: RwLock::new(gas_price),
+            bus: Arc::new(Bus::default()),
+            snapshots: Mutex::new(SnapshotHistory::new(STATE_HISTORY)),
+        })
+    }
+
+    /// state holding only `pairs` as (protocol, token0, token1, address,
+    /// reserves), nothing asked of the node
+    #[cfg(test)]
+    pub(crate) fn from_pairs(
+        provider: Arc<M>,
+        stream_provider: Provider<P>,
+        pairs: &[(UniswapV2, ERC20Token, ERC20Token, Address, (U256, U256))],
+    ) -> Self {
+        let mut matrix = Matrix3D::new(
+            UNISWAPV2_PROTOCOLS.len(),
+            ERC20Token::WETH as usize + 1,
+            ERC20Token::WETH as usize + 1,
+            UniswapV2Pair::default(),
+        );
+        let mut pair_lookup = HashMap::new();
+        let mut pair_index = HashMap::new();
+        for (protocol, token0, token1, address, (reserve0, reserve1)) in pairs {
+            let index = (*protocol as usize, *token0 as usize, *token1 as usize);
+            matrix[index].update_metadata(*protocol, *token0, *token1, U256::zero());
+            matrix[index].update_reserves(*reserve0, *reserve1);
+            pair_lookup.insert(*address, (*protocol, *token0, *token1));
+            pair_index.insert(index, *address);
         }
+        WorldState {
+            provider: provider.clone(),
+            stream_provider,
+            uniswapV2_markets: RwLock::new(matrix),
+            uniswapV2_pair_lookup: pair_lookup,
+            v2_pair_index: pair_index,
+            uniswapV2_pair_addresses: pairs.iter().map(|pair| pair.3).collect(),
+            disabled_protocols: Vec::new(),
+            collapse_config: CollapseConfig::default(),
+            liquidity_peaks: Mutex::new(LiquidityPeaks::default()),
+            collapsed_pairs: StdRwLock::new(HashMap::new()),
+            router_health: StdRwLock::new(RouterHealth::default()),
+            uniswapV3_client: UniswapV3Client::new(provider),
+            v3_quotes: QuoteCache::new(QUOTE_PRECISION_BITS),
+            v3_schedule: Mutex::new(PollSchedule::new(PollConfig::default())),
+            gas_price: RwLock::new(U256::zero()),
+            bus: Arc::new(Bus::default()),
+            snapshots: Mutex::new(SnapshotHistory::new(STATE_HISTORY)),
+        }
+    }
+
+    /// publishes pool updates to `bus` instead of a bus of its own
+    pub fn with_bus(mut self, bus: Arc<Bus>) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    pub fn bus(&self) -> &Arc<Bus> {
+        &self.bus
+    }
+
+    pub fn with_poll_config(self, config: PollConfig) -> Self {
+        *self.v3_schedule.lock().unwrap() = PollSchedule::new(config);
+        self
+    }
+
+    pub fn with_collapse_config(mut self, config: CollapseConfig) -> Self {
+        self.collapse_config = config;
+        self
     }
 
     pub async fn stream_data(self: Arc<Self>)
     where
         <M as Middleware>::Provider: PubsubClient,
     {
-        let mut pair_stream = get_pair_sync_stream(
+        let mut pair_stream = match get_pair_sync_stream(
             &self.stream_provider,
             self.uniswapV2_pair_addresses.to_vec(),
         )
-        .await;
+        .await
+        {
+            Ok(pair_stream) => pair_stream,
+            Err(e) => {
+                warn!("Failed to subscribe to pair syncs: {}", e);
+                return;
+            }
+        };
         let pair_sync_abi = BaseContract::from(
             parse_abi(&["event Sync(uint112 reserve0, uint112 reserve1)"]).unwrap(),
         );
 
         while let Some(log) = pair_stream.next().await {
-            let (reserve0, reserve1): (U256, U256) = pair_sync_abi
-                .decode_event("Sync", log.topics, log.data)
-                .unwrap();
-            let (protocol, token0, token1) = self.uniswapV2_pair_lookup[&log.address];
+            let (reserve0, reserve1): (U256, U256) =
+                match pair_sync_abi.decode_event("Sync", log.topics, log.data) {
+                    Ok(reserves) => reserves,
+                    Err(e) => {
+                        warn!("Malformed Sync log of {:?}: {}", log.address, e);
+                        continue;
+                    }
+                };
+            let (protocol, pair_token0, pair_token1) = self.uniswapV2_pair_lookup[&log.address];
+            let (token0, token1) = (pair_token0, pair_token1);
             // need to sort tokens here (for proper indexing, since token0<=token1 not guarenteed for Meshswap)
             let (token0, token1) = order_tokens(token0, token1);
             self.uniswapV2_markets.write().await
                 [(protocol as usize, token0 as usize, token1 as usize)]
                 .update_reserves(reserve0, reserve1);
+            let block = log.block_number.unwrap_or_default().as_u64();
+            self.check_collapse(log.address, (reserve0, reserve1), block)
+                .await;
+            self.bus.pool_updates.publish(ReserveRecord {
+                block,
+                pair: log.address,
+                protocol: protocol.get_name().to_string(),
+                token0: pair_token0.get_address(),
+                token1: pair_token1.get_address(),
+                reserve0,
+                reserve1,
+            });
             debug!(
                 "Block#:{}, Pair reserves updated on {:?} protocol, pair {}-{}",
-                log.block_number.unwrap(),
+                block,
                 protocol.get_name(),
                 token0.get_symbol(),
                 token1.get_symbol()
@@ -156,62 +487,816 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
         }
     }
 
+    /// Pulls `pair_address` from routing if `reserves` are a collapse of
+    /// its liquidity, publishing the collapse.
+    async fn check_collapse(&self, pair_address: Address, reserves: (U256, U256), block: u64) {
+        if self
+            .collapsed_pairs
+            .read()
+            .unwrap()
+            .contains_key(&pair_address)
+        {
+            return;
+        }
+        let (peak, now) = self
+            .liquidity_peaks
+            .lock()
+            .unwrap()
+            .observe(pair_address, reserves);
+        if !collapsed(peak, now, self.collapse_config.collapse_bps) {
+            return;
+        }
+        let (protocol, token0, token1) = self.uniswapV2_pair_lookup[&pair_address];
+        let (ordered0, ordered1) = order_tokens(token0, token1);
+        self.collapsed_pairs.write().unwrap().insert(
+            pair_address,
+            (protocol as usize, ordered0 as usize, ordered1 as usize),
+        );
+        self.liquidity_peaks.lock().unwrap().forget(&pair_address);
+        error!(
+            "Liquidity of {} {}-{} ({:?}) collapsed from {} to {}, no longer routing through it",
+            protocol.get_name(),
+            token0.get_symbol(),
+            token1.get_symbol(),
+            pair_address,
+            peak,
+            now
+        );
+        let replacement = match self.collapse_config.scan_replacements {
+            true => self
+                .find_replacement(pair_address, token0, token1)
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to look for a replacement of {:?}: {}",
+                        pair_address, e
+                    );
+                    None
+                }),
+            false => None,
+        };
+        if let Some(replacement) = &replacement {
+            warn!(
+                "{}-{} has {} liquidity on {} ({:?}){}",
+                token0.get_symbol(),
+                token1.get_symbol(),
+                replacement.liquidity,
+                replacement.protocol,
+                replacement.pair,
+                match replacement.tracked {
+                    true => "",
+                    false => ", restart to route through it",
+                }
+            );
+        }
+        self.bus.collapses.publish(Collapse {
+            block,
+            pair: pair_address,
+            protocol: protocol.get_name().to_string(),
+            token0: token0.get_address(),
+            token1: token1.get_address(),
+            peak_liquidity: peak,
+            liquidity: now,
+            replacement,
+        });
+    }
+
+    /// The pair of `token0` and `token1` with the most liquidity on the
+    /// tradable factories other than `pair_address`, asked of the
+    /// factories so pairs created since startup count.
+    async fn find_replacement(
+        &self,
+        pair_address: Address,
+        token0: ERC20Token,
+        token1: ERC20Token,
+    ) -> Result<Option<Replacement>, Error> {
+        let client = UniswapV2Client::new(self.provider.clone());
+        let protocols: Vec<UniswapV2> = UNISWAPV2_PROTOCOLS
+            .iter()
+            .copied()
+            .filter(|protocol| !self.disabled_protocols.contains(protocol))
+            .collect();
+        let candidates: Vec<(UniswapV2, Address)> = {
+            let addresses = client
+                .get_pair_address_multicall(
+                    protocols
+                        .iter()
+                        .map(|protocol| (*protocol, token0, token1))
+                        .collect(),
+                )
+                .await?;
+            let collapsed_pairs = self.collapsed_pairs.read().unwrap();
+            protocols
+                .into_iter()
+                .zip(addresses)
+                .filter(|(_, address)| {
+                    !address.is_zero()
+                        && *address != pair_address
+                        && !collapsed_pairs.contains_key(address)
+                })
+                .collect()
+        };
+        let addresses: Vec<Address> = candidates.iter().map(|(_, address)| *address).collect();
+        let reserves = client.get_reserves_many(&addresses).await?;
+        Ok(candidates
+            .into_iter()
+            .filter_map(|(protocol, address)| {
+                Some(Replacement {
+                    pair: address,
+                    protocol: protocol.get_name().to_string(),
+                    liquidity: liquidity(*reserves.get(&address)?),
+                    tracked: self.uniswapV2_pair_lookup.contains_key(&address),
+                })
+            })
+            .filter(|replacement| !replacement.liquidity.is_zero())
+            .max_by_key(|replacement| replacement.liquidity))
+    }
+
+    /// pairs pulled from routing after losing their liquidity
+    pub fn collapsed_pairs(&self) -> Vec<Address> {
+        self.collapsed_pairs
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Sync events of orphaned blocks were already applied and the new branch
+    /// may not touch the same pairs, so reload all reserves from the node.
+    pub async fn rollback(&self, reorg: &Reorg) {
+        debug!(
+            "Reorg of depth {} past block {:?}, reloading pair reserves",
+            reorg.depth,
+            reorg.common_ancestor()
+        );
+        if let Err(e) = self.resync_reserves().await {
+            error!("Failed to reload reserves after the reorg: {}", e);
+        }
+    }
+
+    /// Rolls back every reorg published on its bus, see `HeaderTracker`.
+    pub async fn follow_reorgs(self: Arc<Self>) {
+        let mut reorgs = self.bus.reorgs.subscribe();
+        while let Some(reorg) = next(&mut reorgs, "reorgs").await {
+            self.rollback(&reorg).await;
+        }
+    }
+
+    /// reloads the reserves of every tracked pair from the node
+    pub async fn resync_reserves(&self) -> Result<(), Error> {
+        self.reload_reserves(&self.uniswapV2_pair_addresses).await
+    }
+
+    /// Reloads the reserves of `pair_addresses` from the node, all of them
+    /// tracked; nothing is applied if one isn't.
+    pub async fn reload_reserves(&self, pair_addresses: &[Address]) -> Result<(), Error> {
+        if let Some(unknown) = pair_addresses
+            .iter()
+            .find(|pair_address| !self.uniswapV2_pair_lookup.contains_key(*pair_address))
+        {
+            return Err(StateError::UnknownPair(*unknown).into());
+        }
+        let pair_reserves = UniswapV2Client::new(self.provider.clone())
+            .get_reserves_many(pair_addresses)
+            .await?;
+        let mut markets = self.uniswapV2_markets.write().await;
+        for (pair_address, (reserve0, reserve1)) in pair_reserves {
+            let (protocol, token0, token1) = self.uniswapV2_pair_lookup[&pair_address];
+            let (token0, token1) = order_tokens(token0, token1);
+            markets[(protocol as usize, token0 as usize, token1 as usize)]
+                .update_reserves(reserve0, reserve1);
+        }
+        Ok(())
+    }
+
+    /// Compares the local reserves of `pair_addresses` against `getReserves`
+    /// on the node, returning the pairs off by more than `tolerance_bps`.
+    pub async fn check_reserves(
+        &self,
+        pair_addresses: &[Address],
+        tolerance_bps: u64,
+    ) -> Result<Vec<Divergence>, Error> {
+        let onchain = UniswapV2Client::new(self.provider.clone())
+            .get_reserves_many(pair_addresses)
+            .await?;
+        let markets = self.uniswapV2_markets.read().await;
+        let mut diverged = Vec::new();
+        for pair_address in pair_addresses {
+            // failed calls are not evidence of anything
+            let onchain = match onchain.get(pair_address) {
+                Some(onchain) => *onchain,
+                None => continue,
+            };
+            let (protocol, token0, token1) = self.uniswapV2_pair_lookup[pair_address];
+            let (token0, token1) = order_tokens(token0, token1);
+            let local = markets[(protocol as usize, token0 as usize, token1 as usize)].reserves();
+            let bps = divergence_bps(local, onchain);
+            if bps > tolerance_bps {
+                diverged.push(Divergence {
+                    pair: *pair_address,
+                    local,
+                    onchain,
+                    bps,
+                });
+            }
+        }
+        Ok(diverged)
+    }
+
+    /// Every `config.interval`, checks a random sample of pairs against the
+    /// node and resyncs all reserves when one is still off after
+    /// `config.confirm_delay`, catching Sync events the stream lost or
+    /// misapplied before they turn into losing trades. Runs forever.
+    pub async fn guard_reserves(self: Arc<Self>, config: StaleGuardConfig) {
+        let mut interval = tokio::time::interval(config.interval);
+        // the first tick completes immediately, reserves were just loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let sample: Vec<Address> = self
+                .uniswapV2_pair_addresses
+                .choose_multiple(&mut rand::thread_rng(), config.sample_size)
+                .copied()
+                .collect();
+            // failed checks are not evidence of anything
+            let suspects = match self.check_reserves(&sample, config.tolerance_bps).await {
+                Ok(suspects) => suspects,
+                Err(e) => {
+                    warn!("Failed to check reserves: {}", e);
+                    continue;
+                }
+            };
+            if suspects.is_empty() {
+                continue;
+            }
+            tokio::time::sleep(config.confirm_delay).await;
+            let suspects: Vec<Address> = suspects.iter().map(|d| d.pair).collect();
+            let diverged = match self.check_reserves(&suspects, config.tolerance_bps).await {
+                Ok(diverged) => diverged,
+                Err(e) => {
+                    warn!("Failed to recheck reserves: {}", e);
+                    continue;
+                }
+            };
+            if diverged.is_empty() {
+                debug!("{} pairs caught up with the node", suspects.len());
+                continue;
+            }
+            for divergence in &diverged {
+                let (protocol, token0, token1) = self.uniswapV2_pair_lookup[&divergence.pair];
+                error!(
+                    "Stale reserves on {} {}-{} ({:?}): local {:?}, on-chain {:?}, off by {} bps",
+                    protocol.get_name(),
+                    token0.get_symbol(),
+                    token1.get_symbol(),
+                    divergence.pair,
+                    divergence.local,
+                    divergence.onchain,
+                    divergence.bps
+                );
+            }
+            warn!(
+                "{} of {} sampled pairs diverged, resyncing all reserves",
+                diverged.len(),
+                sample.len()
+            );
+            if let Err(e) = self.resync_reserves().await {
+                error!("Failed to resync reserves: {}", e);
+            }
+        }
+    }
+
+    /// Every `config.interval`, simulates a tiny swap through the router of
+    /// every protocol with a pair for the probe, excluding the ones that
+    /// keep failing from quoting until they pass. Runs forever.
+    pub async fn probe_routers(self: Arc<Self>, config: ProbeConfig) {
+        let token_in = ERC20Token::WMATIC;
+        let (token0, token1) = order_tokens(token_in, config.token_out);
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            for protocol in UNISWAPV2_PROTOCOLS {
+                if self.disabled_protocols.contains(&protocol) {
+                    continue;
+                }
+                let pair = self.uniswapV2_markets.read().await
+                    [(protocol as usize, token0 as usize, token1 as usize)];
+                if pair.reserves().0.is_zero() {
+                    continue;
+                }
+                let local = pair.get_amounts_out(config.amount_in, token_in);
+                let outcome = probe_router(self.provider.clone(), protocol, &config, local).await;
+                debug!("{} router probe: {:?}", protocol.get_name(), outcome);
+                self.router_health.write().unwrap().record(
+                    protocol,
+                    &outcome,
+                    config.failures_to_exclude,
+                );
+            }
+        }
+    }
+
+    /// protocols excluded after failing their router probes
+    pub fn unhealthy_routers(&self) -> Vec<UniswapV2> {
+        let router_health = self.router_health.read().unwrap();
+        UNISWAPV2_PROTOCOLS
+            .iter()
+            .copied()
+            .filter(|protocol| router_health.is_excluded(*protocol))
+            .collect()
+    }
+
+    /// Second phase of confirming an opportunity: after `delay`, reloads
+    /// the V2 pairs `protocols` take through `token_path` from the node,
+    /// drops the V3 quotes of its hops and requotes it. The amounts out of
+    /// every hop if the best route still goes through `protocols` and
+    /// returns more than `amount_in`, `None` for an opportunity that was
+    /// only there in stale or half-applied state, or if the pairs couldn't
+    /// be reloaded.
+    pub async fn confirm_route(
+        self: Arc<Self>,
+        token_path: &[ERC20Token],
+        amount_in: U256,
+        protocols: &[Protocol],
+        delay: Duration,
+    ) -> Option<Vec<U256>> {
+        tokio::time::sleep(delay).await;
+        for (hop, protocol) in token_path.windows(2).zip(protocols) {
+            if let Protocol::UniswapV3 { .. } = protocol {
+                self.v3_quotes
+                    .invalidate(&(hop[0].get_address(), hop[1].get_address()));
+            }
+        }
+        let pairs: Vec<Address> = self
+            .route_pairs(token_path, protocols)
+            .into_iter()
+            .flatten()
+            .collect();
+        if let Err(e) = self.reload_reserves(&pairs).await {
+            warn!("Failed to reload the pairs of the route: {}", e);
+            return None;
+        }
+        let (amounts_out, requoted) = self
+            .clone()
+            .compute_best_route_hops(token_path.to_vec(), amount_in)
+            .await;
+        let amount_out = amounts_out.last().copied().unwrap_or_default();
+        (requoted == protocols && amount_out > amount_in).then_some(amounts_out)
+    }
+
+    /// The V2 pair of every hop of `token_path` through `protocols`, `None`
+    /// for a V3 hop or a pair that doesn't exist.
+    pub fn route_pairs(
+        &self,
+        token_path: &[ERC20Token],
+        protocols: &[Protocol],
+    ) -> Vec<Option<Address>> {
+        token_path
+            .windows(2)
+            .zip(protocols)
+            .map(|(hop, protocol)| match protocol {
+                Protocol::UniswapV2(protocol) => {
+                    let (token0, token1) = order_tokens(hop[0], hop[1]);
+                    self.v2_pair_index
+                        .get(&(*protocol as usize, token0 as usize, token1 as usize))
+                        .copied()
+                }
+                Protocol::UniswapV3 { .. } => None,
+            })
+            .collect()
+    }
+
     pub async fn compute_best_route(
         self: Arc<Self>,
         token_path: Vec<ERC20Token>,
         amount_in: U256,
     ) -> (U256, Vec<Protocol>) {
-        let mut protocols: Vec<Protocol> = Vec::with_capacity(token_path.len() - 1);
-
-        let mut token_in = token_path[0];
-        let mut token_out;
-        let mut current_amt = amount_in;
-        for i in 1..token_path.len() {
-            token_out = token_path[i];
-            let (best_amount_out_v3, best_pool_fee) =
-                self.best_uniswapV3(token_in, token_out, current_amt).await;
-
-            let (best_amount_out, uniswapV2_protocol) =
-                self.best_uniswapV2(token_in, token_out, current_amt).await;
-
-            if best_amount_out > best_amount_out_v3 {
-                current_amt = best_amount_out;
-                protocols.push(Protocol::UniswapV2(uniswapV2_protocol));
+        let (amounts_out, protocols) = self.compute_best_route_hops(token_path, amount_in).await;
+        (amounts_out.last().copied().unwrap_or(amount_in), protocols)
+    }
+
+    /// like `compute_best_route`, but with the amount out of every hop
+    pub async fn compute_best_route_hops(
+        self: Arc<Self>,
+        token_path: Vec<ERC20Token>,
+        amount_in: U256,
+    ) -> (Vec<U256>, Vec<Protocol>) {
+        self.compute_best_route_ladder(token_path, vec![amount_in])
+            .await
+            .remove(0)
+    }
+
+    /// `compute_best_route_hops` through V2 pairs only, the reserves of the
+    /// pairs in `projected` replaced by the ones given, e.g. as a pending
+    /// txn leaves them. The cached reserves aren't touched.
+    pub async fn compute_projected_route_hops(
+        &self,
+        token_path: &[ERC20Token],
+        amount_in: U256,
+        projected: &HashMap<Address, (U256, U256)>,
+    ) -> (Vec<U256>, Vec<Protocol>) {
+        let markets = self.uniswapV2_markets.read().await;
+        let mut amounts_out = Vec::with_capacity(token_path.len() - 1);
+        let mut protocols = Vec::with_capacity(token_path.len() - 1);
+        let mut amount = amount_in;
+        for hop in token_path.windows(2) {
+            let (token_in, token_out) = (hop[0], hop[1]);
+            let (token0, token1) = order_tokens(token_in, token_out);
+            let mut best_protocol = UNISWAPV2_PROTOCOLS[0];
+            let mut best_amount_out = U256::zero();
+            for protocol in self.tradable_protocols(token0, token1) {
+                let index = (protocol as usize, token0 as usize, token1 as usize);
+                let mut pair = markets[index];
+                if let Some((reserve0, reserve1)) = self
+                    .v2_pair_index
+                    .get(&index)
+                    .and_then(|pair_address| projected.get(pair_address))
+                {
+                    pair.update_reserves(*reserve0, *reserve1);
+                }
+                let amount_out = pair.get_amounts_out(amount, token_in);
+                if amount_out > best_amount_out {
+                    best_protocol = protocol;
+                    best_amount_out = amount_out;
+                }
+            }
+            amount = best_amount_out;
+            amounts_out.push(amount);
+            protocols.push(Protocol::UniswapV2(best_protocol));
+        }
+        (amounts_out, protocols)
+    }
+
+    /// the protocol and tokens of `pair_address` if it's a tracked V2 pair
+    pub fn v2_pair(&self, pair_address: Address) -> Option<(UniswapV2, ERC20Token, ERC20Token)> {
+        self.uniswapV2_pair_lookup.get(&pair_address).copied()
+    }
+
+    /// `compute_best_route_hops` of every amount of `amounts_in` through the
+    /// same path in one pass: each hop reads the V2 reserves once for all
+    /// of them and quotes V3 for all of them at once. Equal amounts are
+    /// quoted once.
+    pub async fn compute_best_route_ladder(
+        self: Arc<Self>,
+        token_path: Vec<ERC20Token>,
+        amounts_in: Vec<U256>,
+    ) -> Vec<(Vec<U256>, Vec<Protocol>)> {
+        let mut distinct = amounts_in.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        let mut hops: Vec<(Vec<U256>, Vec<Protocol>)> = vec![
+            (
+                Vec::with_capacity(token_path.len() - 1),
+                Vec::with_capacity(token_path.len() - 1)
+            );
+            distinct.len()
+        ];
+        let mut current_amts = distinct.clone();
+        for hop in token_path.windows(2) {
+            let (token_in, token_out) = (hop[0], hop[1]);
+            let v3_quotes = futures_util::future::join_all(
+                current_amts
+                    .iter()
+                    .map(|amount| self.best_uniswapV3(token_in, token_out, *amount)),
+            )
+            .await;
+            let v2_quotes = self.best_v2_many(token_in, token_out, &current_amts).await;
+
+            for (rung, ((best_amount_out_v3, best_pool_fee), (best_amount_out, v2_protocol))) in
+                v3_quotes.into_iter().zip(v2_quotes).enumerate()
+            {
+                let (amounts_out, protocols) = &mut hops[rung];
+                if best_amount_out > best_amount_out_v3 {
+                    current_amts[rung] = best_amount_out;
+                    protocols.push(Protocol::UniswapV2(v2_protocol));
+                } else {
+                    current_amts[rung] = best_amount_out_v3;
+                    protocols.push(Protocol::UniswapV3 { fee: best_pool_fee });
+                }
+                amounts_out.push(current_amts[rung]);
+            }
+        }
+        amounts_in
+            .iter()
+            .map(|amount_in| hops[distinct.binary_search(amount_in).unwrap()].clone())
+            .collect()
+    }
+
+    /// Largest amount of `token_path[0]` that moves the deepest tradable V2
+    /// pair of no hop by more than `max_impact_bps`, each hop's limit
+    /// carried back to the first token at the spot prices of the hops
+    /// before it. `None` when a hop has no V2 liquidity to measure, V3 hops
+    /// are capped by `tick_constraint` instead.
+    pub async fn route_depth(
+        &self,
+        token_path: &[ERC20Token],
+        max_impact_bps: u64,
+    ) -> Option<U256> {
+        let impact = max_impact_bps.min(9_999) as f64 / 10_000.0;
+        let markets = self.uniswapV2_markets.read().await;
+        // first token per unit of the hop's token in
+        let mut price = 1.0;
+        let mut depth = f64::INFINITY;
+        for hop in token_path.windows(2) {
+            let (token0, token1) = order_tokens(hop[0], hop[1]);
+            let (reserve_in, reserve_out) = self
+                .tradable_protocols(token0, token1)
+                .into_iter()
+                .map(|protocol| {
+                    let pair = &markets[(protocol as usize, token0 as usize, token1 as usize)];
+                    let (reserve0, reserve1) = pair.reserves();
+                    match pair.tokens().0 == hop[0] {
+                        true => (reserve0, reserve1),
+                        false => (reserve1, reserve0),
+                    }
+                })
+                .max_by_key(|(reserve_in, _)| *reserve_in)?;
+            if reserve_in.is_zero() || reserve_out.is_zero() {
+                return None;
+            }
+            // an amount `dx` in gets `reserve_in / (reserve_in + dx)` of the
+            // spot price
+            let (reserve_in, reserve_out) = (to_f64(reserve_in), to_f64(reserve_out));
+            depth = depth.min(reserve_in * impact / (1.0 - impact) * price);
+            price *= reserve_in / reserve_out;
+        }
+        depth.is_finite().then(|| U256::from(depth as u128))
+    }
+
+    /// The tightest tick constraint of a quoted route: its V3 hops that
+    /// would cross into a range with less than `thin_ratio` of the current
+    /// liquidity, `None` when every hop fits.
+    pub async fn tick_constraint(
+        &self,
+        token_path: &[ERC20Token],
+        amount_in: U256,
+        amounts_out: &[U256],
+        protocols: &[Protocol],
+        thin_ratio: f64,
+    ) -> Option<TickConstraint> {
+        let mut tightest: Option<TickConstraint> = None;
+        for (hop, protocol) in protocols.iter().enumerate() {
+            let fee = match protocol {
+                Protocol::UniswapV3 { fee } => *fee,
+                Protocol::UniswapV2(_) => continue,
+            };
+            let hop_amount_in = if hop == 0 {
+                amount_in
             } else {
-                current_amt = best_amount_out_v3;
-                protocols.push(Protocol::UniswapV3 { fee: best_pool_fee });
+                amounts_out[hop - 1]
+            };
+            let boundary = match self
+                .uniswapV3_client
+                .tick_boundary(token_path[hop], token_path[hop + 1], fee)
+                .await
+            {
+                Some(boundary) => boundary,
+                None => continue,
+            };
+            if hop_amount_in <= boundary.amount_in || !boundary.is_thin(thin_ratio) {
+                continue;
+            }
+            let capped_amount_in = amount_in * boundary.amount_in / hop_amount_in;
+            if tightest.is_none_or(|tightest| capped_amount_in < tightest.capped_amount_in) {
+                tightest = Some(TickConstraint {
+                    hop,
+                    fee,
+                    boundary,
+                    hop_amount_in,
+                    capped_amount_in,
+                });
             }
-            token_in = token_out;
         }
-        (current_amt, protocols)
+        tightest
+    }
+
+    /// V2 protocols quoted for the ordered pair `token0`-`token1`, the
+    /// ones whose configuration checked out, whose router passes its probes
+    /// and whose pair still has its liquidity
+    fn tradable_protocols(&self, token0: ERC20Token, token1: ERC20Token) -> Vec<UniswapV2> {
+        let collapsed_pairs = self.collapsed_pairs.read().unwrap();
+        let router_health = self.router_health.read().unwrap();
+        UNISWAPV2_PROTOCOLS
+            .iter()
+            .copied()
+            .filter(|protocol| !self.disabled_protocols.contains(protocol))
+            .filter(|protocol| !router_health.is_excluded(*protocol))
+            .filter(|protocol| {
+                let index = (*protocol as usize, token0 as usize, token1 as usize);
+                !collapsed_pairs
+                    .values()
+                    .any(|collapsed| *collapsed == index)
+            })
+            .collect()
     }
 
-    async fn best_uniswapV2(
+    /// the best V2 protocol for each of `amounts_in`, from one read of the
+    /// reserves
+    async fn best_v2_many(
+        &self,
+        token_in: ERC20Token,
+        token_out: ERC20Token,
+        amounts_in: &[U256],
+    ) -> Vec<(U256, UniswapV2)> {
+        let (token0, token1) = order_tokens(token_in, token_out);
+
+        let markets = self.uniswapV2_markets.read().await;
+        let protocols = self.tradable_protocols(token0, token1);
+        amounts_in
+            .iter()
+            .map(|amount_in| {
+                let mut best_protocol = UNISWAPV2_PROTOCOLS[0];
+                let mut best_amount_out = U256::zero();
+                for protocol in &protocols {
+                    let amount_out = markets
+                        [(*protocol as usize, token0 as usize, token1 as usize)]
+                        .get_amounts_out(*amount_in, token_in);
+                    if amount_out > best_amount_out {
+                        best_protocol = *protocol;
+                        best_amount_out = amount_out;
+                    }
+                }
+                (best_amount_out, best_protocol)
+            })
+            .collect()
+    }
+
+    /// Best way to swap `amount_in` of `token_in` into `token_out` in one
+    /// hop: the order split across all V2 protocols from local reserves, or
+    /// all of it through the best V3 pool if that pays more. Legs call the
+    /// routers directly, the flashloan executor only runs closed arbs.
+    pub async fn build_best_swap(
         &self,
         token_in: ERC20Token,
         token_out: ERC20Token,
         amount_in: U256,
-    ) -> (U256, UniswapV2) {
+        params: &SwapParams,
+    ) -> BestSwap {
         let (token0, token1) = order_tokens(token_in, token_out);
+        let protocols = self.tradable_protocols(token0, token1);
+        let pairs: Vec<UniswapV2Pair> = {
+            let markets = self.uniswapV2_markets.read().await;
+            protocols
+                .iter()
+                .map(|protocol| markets[(*protocol as usize, token0 as usize, token1 as usize)])
+                .collect()
+        };
+        let allocations = split_amount(&pairs, token_in, amount_in, SPLIT_PARTS);
+        let v2_legs: Vec<(UniswapV2, U256, U256)> = protocols
+            .iter()
+            .zip(&pairs)
+            .zip(allocations)
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|((protocol, pair), amount)| {
+                (*protocol, amount, pair.get_amounts_out(amount, token_in))
+            })
+            .collect();
+        let v2_out = v2_legs
+            .iter()
+            .fold(U256::zero(), |total, (_, _, out)| total + out);
+        let (v3_out, v3_fee) = self.best_uniswapV3(token_in, token_out, amount_in).await;
+
+        let legs = if v3_out > v2_out {
+            let tx = self
+                .uniswapV3_client
+                .get_exact_input_single_txn(token_in, token_out, v3_fee, amount_in, v3_out, params)
+                .tx;
+            vec![SwapLeg {
+                protocol: Protocol::UniswapV3 { fee: v3_fee },
+                amount_in,
+                expected_out: v3_out,
+                tx,
+            }]
+        } else {
+            let v2_client = UniswapV2Client::new(self.provider.clone());
+            v2_legs
+                .into_iter()
+                .map(|(protocol, amount, out)| SwapLeg {
+                    protocol: Protocol::UniswapV2(protocol),
+                    amount_in: amount,
+                    expected_out: out,
+                    tx: v2_client
+                        .get_swap_exact_in_txn(protocol, token_in, token_out, amount, out, params),
+                })
+                .collect()
+        };
+        BestSwap {
+            token_in,
+            token_out,
+            amount_in,
+            expected_out: U256::max(v2_out, v3_out),
+            legs,
+        }
+    }
 
-        let mut best_protocol = UNISWAPV2_PROTOCOLS[0];
-        let mut best_amount_out = self.uniswapV2_markets.read().await
-            [(best_protocol as usize, token0 as usize, token1 as usize)]
-            .get_amounts_out(amount_in, token_in);
+    /// reserves of every V2 pair tracked, as of the block quoting started on
+    pub async fn pools(&self) -> Vec<ReserveRecord> {
+        let block = self.v3_quotes.block_number().unwrap_or_default();
+        let markets = self.uniswapV2_markets.read().await;
+        self.uniswapV2_pair_addresses
+            .iter()
+            .map(|pair_address| {
+                let (protocol, token0, token1) = self.uniswapV2_pair_lookup[pair_address];
+                let (token0, token1) = order_tokens(token0, token1);
+                let pair = &markets[(protocol as usize, token0 as usize, token1 as usize)];
+                let (token0, token1) = pair.tokens();
+                let (reserve0, reserve1) = pair.reserves();
+                ReserveRecord {
+                    block,
+                    pair: *pair_address,
+                    protocol: protocol.get_name().to_string(),
+                    token0: token0.get_address(),
+                    token1: token1.get_address(),
+                    reserve0,
+                    reserve1,
+                }
+            })
+            .collect()
+    }
 
-        for i in 1..UNISWAPV2_PROTOCOLS.len() {
-            let protocol = UNISWAPV2_PROTOCOLS[i];
-            let amount_out = self.uniswapV2_markets.read().await
-                [(protocol as usize, token0 as usize, token1 as usize)]
-                .get_amounts_out(amount_in, token_in);
+    /// Records the reserves as of the block quoting started on, returning
+    /// the snapshot with its state hash.
+    pub async fn snapshot(&self) -> StateSnapshot {
+        let pools = self.pools().await;
+        let block = self.v3_quotes.block_number().unwrap_or_default();
+        let snapshot = StateSnapshot::new(block, pools);
+        self.snapshots.lock().unwrap().push(snapshot.clone());
+        snapshot
+    }
+
+    /// the recorded snapshot of `block`, or the latest one
+    pub fn recorded_snapshot(&self, block: Option<u64>) -> Option<StateSnapshot> {
+        let snapshots = self.snapshots.lock().unwrap();
+        match block {
+            Some(block) => snapshots.get(block).cloned(),
+            None => snapshots.latest().cloned(),
+        }
+    }
 
-            if amount_out > best_amount_out {
-                best_protocol = protocol;
-                best_amount_out = amount_out;
+    /// Starts quoting against `block_number`. V3 quotes of the pools the
+    /// poll schedule has due are dropped, the rest carry over. Returns the
+    /// cache stats of the previous block.
+    pub fn start_block(&self, block_number: u64) -> QuoteCacheStats {
+        let due = self.v3_schedule.lock().unwrap().due(block_number);
+        self.v3_quotes
+            .advance_retaining(block_number, |pool| !due.contains(pool));
+        self.v3_quotes.reset_stats()
+    }
+
+    /// Loads up front what the first quotes of `paths` (each with the
+    /// amounts it's quoted at) would otherwise ask the node for during live
+    /// opportunities: the V3 pools of every hop, and the V3 quotes of every
+    /// amount as of `block_number`, carried into the blocks after like any
+    /// other quote.
+    pub async fn warm_up(
+        self: Arc<Self>,
+        block_number: u64,
+        paths: &[(Vec<ERC20Token>, Vec<U256>)],
+    ) -> WarmupStats {
+        let mut hops: Vec<(ERC20Token, ERC20Token)> = Vec::new();
+        for (token_path, _) in paths {
+            for hop in token_path.windows(2) {
+                let hop = order_tokens(hop[0], hop[1]);
+                if !hops.contains(&hop) {
+                    hops.push(hop);
+                }
             }
         }
+        let pools = futures_util::future::join_all(hops.iter().flat_map(|(token0, token1)| {
+            FEE_TIERS
+                .iter()
+                .map(|fee| self.uniswapV3_client.pool(*token0, *token1, *fee))
+        }))
+        .await;
+
+        self.start_block(block_number);
+        futures_util::future::join_all(paths.iter().map(|(token_path, amounts)| {
+            self.clone()
+                .compute_best_route_ladder(token_path.clone(), amounts.clone())
+        }))
+        .await;
+        WarmupStats {
+            v3_pools: pools.iter().flatten().count(),
+            quotes: self.v3_quotes.reset_stats(),
+        }
+    }
 
-        (best_amount_out, best_protocol)
+    /// Keeps the V3 pools of a route found profitable in the current block
+    /// requoted every block for a while.
+    pub fn mark_profitable(&self, token_path: &[ERC20Token], protocols: &[Protocol]) {
+        let block_number = match self.v3_quotes.block_number() {
+            Some(block_number) => block_number,
+            None => return,
+        };
+        let mut schedule = self.v3_schedule.lock().unwrap();
+        for (hop, protocol) in token_path.windows(2).zip(protocols) {
+            if let Protocol::UniswapV3 { .. } = protocol {
+                schedule
+                    .mark_profitable((hop[0].get_address(), hop[1].get_address()), block_number);
+            }
+        }
     }
 
     async fn best_uniswapV3(
@@ -220,11 +1305,217 @@ impl<M: Middleware + Clone, P: PubsubClient> WorldState<M, P> {
         token_out: ERC20Token,
         amount_in: U256,
     ) -> (U256, u32) {
-        let return_data = self
+        let pool = (token_in.get_address(), token_out.get_address());
+        if let Some((fee, amount_out)) = self.v3_quotes.get(pool, amount_in) {
+            return (amount_out, fee);
+        }
+        let block_number = self.v3_quotes.block_number();
+        let amount_in = self.v3_quotes.bucket(amount_in);
+        // not cached, the next quote asks again
+        let return_data = match self
             .uniswapV3_client
             .quote_multicall(token_in, token_out, amount_in)
-            .await;
+            .await
+        {
+            Ok(return_data) => return_data,
+            Err(e) => {
+                warn!(
+                    "Failed to quote {} {} for {} on UniswapV3: {}",
+                    amount_in,
+                    token_in.get_symbol(),
+                    token_out.get_symbol(),
+                    e
+                );
+                return (U256::zero(), 0);
+            }
+        };
+        if let Some(block_number) = block_number {
+            self.v3_quotes
+                .insert(pool, amount_in, block_number, return_data);
+            self.v3_schedule
+                .lock()
+                .unwrap()
+                .observe(pool, block_number, amount_in, return_data.1);
+        }
 
         (return_data.1, return_data.0)
     }
 }
+
+/// `aggregate3` output of `getReserves` calls answering `reserves`
+#[cfg(test)]
+pub(crate) fn reserves_reply(reserves: &[(U256, U256)]) -> ethers::types::Bytes {
+    use ethers::abi::Token;
+    let results = reserves
+        .iter()
+        .map(|(reserve0, reserve1)| {
+            Token::Tuple(vec![
+                Token::Bool(true),
+                Token::Bytes(ethers::abi::encode(&[
+                    Token::Uint(*reserve0),
+                    Token::Uint(*reserve1),
+                    Token::Uint(U256::zero()),
+                ])),
+            ])
+        })
+        .collect();
+    ethers::abi::encode(&[Token::Array(results)]).into()
+}
+
+/// `aggregate3` output of V3 quotes that all reverted
+#[cfg(test)]
+pub(crate) fn no_v3_quotes() -> ethers::types::Bytes {
+    ethers::abi::encode(&[ethers::abi::Token::Array(Vec::new())]).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{batch::fake::FakeTransport, fixed_point::whole_units};
+
+    #[test]
+    fn test_split_amount() {
+        let mut deep = UniswapV2Pair::default();
+        deep.update_metadata(
+            UniswapV2::QUICKSWAP,
+            ERC20Token::USDC,
+            ERC20Token::USDT,
+            U256::zero(),
+        );
+        deep.update_reserves(U256::from(1_000_000), U256::from(1_000_000));
+        let mut empty = deep;
+        empty.update_reserves(U256::zero(), U256::zero());
+
+        let amount = U256::from(100_003);
+        let allocations = split_amount(&[deep, empty], ERC20Token::USDC, amount, 10);
+        assert_eq!(allocations, vec![amount, U256::zero()]);
+
+        // equal pools split evenly, the remainder goes with the last chunk
+        let allocations = split_amount(&[deep, deep], ERC20Token::USDC, amount, 10);
+        assert_eq!(allocations[0] + allocations[1], amount);
+        assert!(allocations.iter().all(|x| *x >= U256::from(50_000)));
+
+        // a second chunk overflows the quote of the pool that took the first
+        let amount = U256::MAX / 1000 * 2;
+        let allocations = split_amount(&[deep, deep], ERC20Token::USDC, amount, 2);
+        assert_eq!(allocations, vec![amount / 2, amount / 2]);
+    }
+
+    #[test]
+    fn test_divergence_bps() {
+        let onchain = (U256::from(1_000_000), U256::from(2_000_000));
+        assert_eq!(divergence_bps(onchain, onchain), 0);
+        // the worse side counts, either direction
+        assert_eq!(
+            divergence_bps((U256::from(1_000_100), U256::from(1_998_000)), onchain),
+            10
+        );
+        assert_eq!(
+            divergence_bps((U256::from(999_000), U256::from(2_000_000)), onchain),
+            10
+        );
+        assert_eq!(
+            divergence_bps(
+                (U256::from(5), U256::zero()),
+                (U256::from(5), U256::from(1))
+            ),
+            10_000
+        );
+        assert_eq!(
+            divergence_bps(
+                (U256::from(5), U256::from(1)),
+                (U256::from(5), U256::zero())
+            ),
+            10_000
+        );
+        assert_eq!(
+            divergence_bps((U256::MAX, U256::zero()), (U256::from(1), U256::zero())),
+            10_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_confirm_route() {
+        let (usdc, weth) = (ERC20Token::USDC, ERC20Token::WETH);
+        assert_eq!(order_tokens(usdc, weth), (usdc, weth));
+        // WETH at 1000 USDC on Sushiswap, 2000 on Quickswap
+        let sushiswap = Address::repeat_byte(1);
+        let cheap = (
+            whole_units(1_000_000, usdc.get_decimals()).unwrap(),
+            whole_units(1_000, weth.get_decimals()).unwrap(),
+        );
+        let quickswap = Address::repeat_byte(2);
+        let dear = (
+            whole_units(2_000_000, usdc.get_decimals()).unwrap(),
+            whole_units(1_000, weth.get_decimals()).unwrap(),
+        );
+        let transport = FakeTransport::new();
+        let provider = Provider::new(transport.clone());
+        let ws = Arc::new(WorldState::from_pairs(
+            Arc::new(provider.clone()),
+            provider,
+            &[
+                (UniswapV2::SUSHISWAP, usdc, weth, sushiswap, cheap),
+                (UniswapV2::QUICKSWAP, usdc, weth, quickswap, dear),
+            ],
+        ));
+
+        let token_path = [usdc, weth, usdc];
+        let amount_in = whole_units(1_000, usdc.get_decimals()).unwrap();
+        for _ in 0..2 {
+            transport.push_response("eth_call", no_v3_quotes());
+        }
+        let (amounts_out, protocols) = ws
+            .clone()
+            .compute_best_route_hops(token_path.to_vec(), amount_in)
+            .await;
+        assert_eq!(
+            protocols,
+            vec![
+                Protocol::UniswapV2(UniswapV2::SUSHISWAP),
+                Protocol::UniswapV2(UniswapV2::QUICKSWAP)
+            ]
+        );
+        assert_eq!(
+            ws.route_pairs(&token_path, &protocols),
+            vec![Some(sushiswap), Some(quickswap)]
+        );
+
+        // the reserves still hold
+        transport.push_response("eth_call", reserves_reply(&[cheap, dear]));
+        for _ in 0..2 {
+            transport.push_response("eth_call", no_v3_quotes());
+        }
+        let confirmed = ws
+            .clone()
+            .confirm_route(&token_path, amount_in, &protocols, Duration::ZERO)
+            .await;
+        assert_eq!(confirmed, Some(amounts_out));
+
+        // Sushiswap was already arbed back in the latest block
+        transport.push_response("eth_call", reserves_reply(&[dear, dear]));
+        for _ in 0..2 {
+            transport.push_response("eth_call", no_v3_quotes());
+        }
+        let confirmed = ws
+            .clone()
+            .confirm_route(&token_path, amount_in, &protocols, Duration::ZERO)
+            .await;
+        assert_eq!(confirmed, None);
+        let markets = ws.uniswapV2_markets.read().await;
+        assert_eq!(
+            markets[(UniswapV2::SUSHISWAP as usize, usdc as usize, weth as usize)]
+                .get_amounts_out(amount_in, usdc),
+            markets[(UniswapV2::QUICKSWAP as usize, usdc as usize, weth as usize)]
+                .get_amounts_out(amount_in, usdc)
+        );
+        drop(markets);
+
+        // a reload that fails can't confirm anything
+        let confirmed = ws
+            .clone()
+            .confirm_route(&token_path, amount_in, &protocols, Duration::ZERO)
+            .await;
+        assert_eq!(confirmed, None);
+    }
+}